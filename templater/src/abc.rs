@@ -0,0 +1,1122 @@
+// `templater import tune.abc`: converts tunes in ABC notation, the
+// plain text format most folk tune collections are shared in, into
+// song files, like `musicxml.rs` does for MusicXML. every tune in the
+// file (each starts with an `X:` line) is its own song, and its
+// headers go in the front matter:
+//
+// - `T:` is the title, and a second `T:` the subtitle
+// - `C:` is the composer
+// - `Q:` is the bpm, e.g. 112 for `Q:1/4=112`, and its text, e.g.
+//   `Q:"Brisk" 1/4=112`, the meter. without one it's `R:`, e.g. reel
+// - `M:` and `K:` are the melody's `\time` and `\key`, and `L:` the
+//   length of a note without one
+//
+// the chord symbols in quotes, e.g. `"Am7"`, are the `\chordmode`, and
+// the notes of the first voice the melody, in absolute pitch with the
+// key's accidentals written out, one measure per line. `P:` parts are
+// rehearsal marks, and endings, e.g. `[1`, are written above their
+// first note. decorations, lyrics (`w:`), and the other voices aren't
+// imported. `templater export tunes.abc` goes the other way, see
+// `to_abc`.
+
+use crate::absolute::to_absolute;
+use crate::chord::{Chord, Polychord, POLYCHORD};
+use crate::chordnames::ChordSpellings;
+use crate::errors::TemplaterError;
+use crate::harmony::parse_pitch;
+use crate::keys::{key_alterations, Key};
+use crate::models::Song;
+use crate::music::{events, spanned_events, tokenize, Event, Fraction, Token};
+use crate::musicxml::ImportedSong;
+use crate::numbers::quality;
+use crate::pickup::lilypond_duration;
+use crate::range::parse_note;
+use crate::repeats::{bar_marks, BarMark};
+use crate::timing::{measure_length, ChordLine, Clock};
+use crate::transpose::Pitch;
+
+const LETTERS: &str = "CDEFGAB";
+// before a note, e.g. `~` for a roll or `T` for a trill
+const DECORATIONS: &str = ".~HLMOPSTuv";
+
+// every tune in an abc file. anything before the first `X:` is the
+// file's own header
+pub fn import(input: &str) -> Result<Vec<ImportedSong>, TemplaterError> {
+    let mut tunes: Vec<Vec<&str>> = vec![];
+    let has_index = input.lines().any(|line| line.starts_with("X:"));
+    for line in input.lines() {
+        if line.starts_with("X:") || (!has_index && tunes.is_empty()) {
+            tunes.push(vec![]);
+        }
+        if let Some(tune) = tunes.last_mut() {
+            tune.push(line);
+        }
+    }
+
+    let songs = tunes
+        .iter()
+        .filter(|lines| lines.iter().any(|line| !line.trim().is_empty()))
+        .map(|lines| TuneReader::default().read(lines))
+        .collect::<Result<Vec<_>, _>>()?;
+    match songs.is_empty() {
+        true => Err(TemplaterError::from_str("the file has no tunes.")),
+        false => Ok(songs),
+    }
+}
+
+// `4/4`, `C` for common time, or `C|` for cut time. none for `none`
+fn meter(value: &str) -> Option<(u64, u64)> {
+    match value.trim() {
+        "C" => Some((4, 4)),
+        "C|" => Some((2, 2)),
+        value => {
+            let (beats, unit) = value.split_once('/')?;
+            Some((beats.trim().parse().ok()?, unit.trim().parse().ok()?))
+        },
+    }
+}
+
+// (its text, e.g. "Allegro", and the bpm) of e.g. `"Allegro" 1/4=120`.
+// an old style `Q:120` is the bpm too
+fn tempo(value: &str) -> (Option<String>, Option<u32>) {
+    let mut quoted = value.split('"');
+    let text = quoted.nth(1).map(str::trim).filter(|text| !text.is_empty()).map(String::from);
+
+    let number = value.rsplit('=').next().unwrap_or_default().trim();
+    let digits: String = number.chars().take_while(char::is_ascii_digit).collect();
+    let bpm = match value.contains('=') || number == value.trim() {
+        true => digits.parse().ok(),
+        false => None,
+    };
+    (text, bpm)
+}
+
+// e.g. `G`, `Gm`, `F#dor`, or `Bb major`. none for `none`, or a
+// highland pipes key, `HP`
+fn key(value: &str) -> Option<Key> {
+    let mut chars = value.trim().chars().peekable();
+    let step = LETTERS.find(chars.next()?)?;
+    let alteration = match chars.peek() {
+        Some('#') => 1,
+        Some('b') => -1,
+        _ => 0,
+    };
+    if alteration != 0 {
+        chars.next();
+    }
+
+    let rest: String = chars.collect::<String>().trim().to_lowercase();
+    let word = rest.split_whitespace().next().unwrap_or_default();
+    let mode = match word.get(..3).unwrap_or(word) {
+        "m" | "min" => "minor",
+        "ion" => "ionian",
+        "dor" => "dorian",
+        "phr" => "phrygian",
+        "lyd" => "lydian",
+        "mix" => "mixolydian",
+        "aeo" => "aeolian",
+        "loc" => "locrian",
+        _ => "major",
+    };
+
+    Some(Key { tonic: Pitch { step, alteration, octave: 0 }, mode })
+}
+
+// a chord symbol as a chordmode chord without its duration, e.g.
+// ("bes", ":m7.5-") for "Bbm7b5" or ("r", "") for "N.C.". none for
+// an annotation, e.g. "^fine"
+fn chord_symbol(text: &str) -> Option<(String, String)> {
+    let text = text.trim();
+    if text.starts_with(['^', '_', '<', '>', '@']) {
+        return None;
+    }
+    if ["N.C.", "NC", "N.C"].contains(&text) {
+        return Some((String::from("r"), String::new()));
+    }
+
+    let root = |name: &str| -> Option<(Pitch, usize)> {
+        let step = LETTERS.find(name.chars().next()?)?;
+        let (alteration, len) = match name[1..].chars().next() {
+            Some('#' | '♯') => (1, name[1..].chars().next().map_or(1, char::len_utf8) + 1),
+            Some('b' | '♭') => (-1, name[1..].chars().next().map_or(1, char::len_utf8) + 1),
+            _ => (0, 1),
+        };
+        Some((Pitch { step, alteration, octave: 0 }, len))
+    };
+
+    let (pitch, len) = root(text)?;
+    let (quality, bass) = match text[len..].rsplit_once('/') {
+        Some((quality, bass)) if root(bass).is_some_and(|(_, len)| len == bass.len()) => (quality, root(bass)),
+        _ => (&text[len..], None),
+    };
+    let quality: String = quality.chars().filter(|c| !"()".contains(*c)).collect();
+    let quality = ChordSpellings::default().respell(&quality.replace("6/9", "69")).to_string();
+
+    let mut suffix = String::new();
+    if !quality.is_empty() {
+        suffix = format!(":{}", quality);
+    }
+    if let Some((bass, _)) = bass {
+        suffix.push_str(&format!("/{}", bass.lilypond_name()));
+    }
+    Some((pitch.lilypond_name(), suffix))
+}
+
+// e.g. the `3/2` of `A3/2`, `/` is a half and `//` a quarter
+fn length(chars: &[char], i: &mut usize) -> Fraction {
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect::<String>().parse::<u64>().ok()
+    };
+
+    let num = digits(i).unwrap_or(1);
+    let mut den = 1;
+    while chars.get(*i) == Some(&'/') {
+        *i += 1;
+        den *= digits(i).unwrap_or(2);
+    }
+    Fraction::new(num, den)
+}
+
+#[derive(Debug, Default)]
+struct TuneReader {
+    song: ImportedSong,
+    // `L:`
+    unit: Option<Fraction>,
+    // `M:`, 4/4 without one
+    time: Option<(u64, u64)>,
+    // the key signature's alteration of every letter
+    key: [i32; 7],
+    in_body: bool,
+    // the first `V:` in the body, the others are skipped
+    voice: Option<String>,
+    other_voice: bool,
+
+    // the current measure
+    melody: Vec<String>,
+    // (position, root, suffix)
+    chords: Vec<(Fraction, String, String)>,
+    marks: Vec<String>,
+    position: Fraction,
+    // the accidentals so far, by (octave, step)
+    accidentals: Vec<((i32, usize), i32)>,
+    // after a `&`, the rest of the measure is another voice
+    overlay: bool,
+
+    measures: usize,
+    chord_line: ChordLine,
+    // (notes left, what a note's length is played as) in `\tuplet`
+    tuplet: Option<(u64, Fraction)>,
+    // how much longer the next note is, after a `>` or `<`
+    broken: Option<Fraction>,
+    // a `(` before the next note
+    slur: bool,
+    // e.g. `^"1."`, for the next note
+    ending: Option<String>,
+    // the notes of a `{...}`
+    grace: Option<Vec<String>>,
+}
+
+impl TuneReader {
+    fn read(mut self, lines: &[&str]) -> Result<ImportedSong, TemplaterError> {
+        for line in lines {
+            let line = line.trim_end();
+            if line.starts_with('%') || line.is_empty() {
+                continue;
+            }
+
+            let mut chars = line.chars();
+            match (chars.next(), chars.next()) {
+                (Some(name), Some(':')) if name.is_ascii_alphabetic() => self.field(name, line[2..].trim())?,
+                _ if self.in_body && !self.other_voice => self.music(line)?,
+                _ => {},
+            }
+        }
+        self.end_measure();
+
+        if self.song.title.is_empty() {
+            return Err(TemplaterError::from_str("a tune has no T: title."));
+        }
+        Ok(self.song)
+    }
+
+    fn measure(&self) -> Fraction {
+        let (beats, unit) = self.time.unwrap_or((4, 4));
+        Fraction::new(beats, unit)
+    }
+
+    fn field(&mut self, name: char, value: &str) -> Result<(), TemplaterError> {
+        // e.g. `M:3/0`, which isn't a length
+        if matches!(name, 'L' | 'M') && meter(value).is_some_and(|(_, den)| den == 0) {
+            return Err(TemplaterError::from_str(&format!("{}:{} has a zero denominator.", name, value)));
+        }
+
+        match (name, self.in_body) {
+            ('T', false) if self.song.title.is_empty() => self.song.title = value.to_string(),
+            ('T', false) if self.song.subtitle.is_none() => self.song.subtitle = Some(value.to_string()),
+            ('C', false) if self.song.composer.is_none() => self.song.composer = Some(value.to_string()),
+            ('Q', false) => {
+                let (text, bpm) = tempo(value);
+                self.song.meter = text.or(self.song.meter.take());
+                self.song.bpm = bpm.or(self.song.bpm);
+            },
+            ('R', false) if self.song.meter.is_none() => self.song.meter = Some(value.to_string()),
+            ('L', _) => self.unit = meter(value).map(|(num, den)| Fraction::new(num, den)).or(self.unit),
+            ('M', _) => {
+                let Some(time) = meter(value) else { return Ok(()) };
+                self.time = Some(time);
+                if self.in_body {
+                    self.melody.push(format!("\\time {}/{}", time.0, time.1));
+                }
+            },
+            ('K', _) => {
+                let key = key(value);
+                self.key = key_alterations(key.map_or(0, |key| key.fifths()));
+                let key = key.map(|key| format!("\\key {}", key.lilypond_name()));
+                match self.in_body {
+                    true => self.melody.extend(key),
+                    false => {
+                        self.song.header.extend(key);
+                        if let Some((beats, unit)) = self.time {
+                            self.song.header.push(format!("\\time {}/{}", beats, unit));
+                        }
+                        // 1/16 in a meter shorter than 3/4, 1/8 otherwise
+                        let unit = match self.measure() < Fraction::new(3, 4) {
+                            true => Fraction::new(1, 16),
+                            false => Fraction::new(1, 8),
+                        };
+                        self.unit.get_or_insert(unit);
+                        self.in_body = true;
+                    },
+                }
+            },
+            ('P', true) => {
+                self.melody.push(format!("\\boxMark \"{}\"", value.replace('"', "")));
+                self.marks.push(format!("%% \"{}\"", value));
+            },
+            ('V', true) => {
+                let voice = value.split_whitespace().next().unwrap_or_default().to_string();
+                self.other_voice = self.voice.get_or_insert_with(|| voice.clone()) != &voice;
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    // a line of the tune's body
+    fn music(&mut self, line: &str) -> Result<(), TemplaterError> {
+        let line = line.split('%').next().unwrap_or_default();
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while let Some(&c) = chars.get(i) {
+            let next = chars.get(i + 1).copied();
+            i += 1;
+            if self.overlay && !"|:[".contains(c) {
+                continue;
+            }
+
+            match c {
+                '"' => {
+                    let end = chars[i..].iter().position(|c| *c == '"').map_or(chars.len(), |end| i + end);
+                    let text: String = chars[i..end].iter().collect();
+                    i = end + 1;
+                    if let Some((root, suffix)) = chord_symbol(&text) {
+                        self.chords.push((self.position, root, suffix));
+                    }
+                },
+                // `!trill!` and `+trill+`
+                '!' | '+' => {
+                    if let Some(end) = chars[i..].iter().position(|end| *end == c) {
+                        i += end + 1;
+                    }
+                },
+                '{' => {
+                    self.grace = Some(vec![]);
+                    if next == Some('/') {
+                        i += 1;
+                    }
+                },
+                '}' => {
+                    if let Some(notes) = self.grace.take().filter(|notes| !notes.is_empty()) {
+                        match &notes[..] {
+                            [note] => self.melody.push(format!("\\grace {}", note)),
+                            _ => self.melody.push(format!("\\grace {{ {} }}", notes.join(" "))),
+                        }
+                    }
+                },
+                '(' if next.is_some_and(|next| next.is_ascii_digit()) => self.tuplet(&chars, &mut i),
+                '(' => self.slur = true,
+                ')' => self.append(")"),
+                '-' => self.append("~"),
+                '[' if next.is_some_and(|next| next.is_ascii_alphabetic()) && chars.get(i + 1) == Some(&':') => {
+                    let end = chars[i..].iter().position(|c| *c == ']').map_or(chars.len(), |end| i + end);
+                    let field: String = chars[i + 2..end].iter().collect();
+                    self.field(chars[i], field.trim())?;
+                    i = end + 1;
+                },
+                '[' if next.is_some_and(|next| next.is_ascii_digit()) => self.ending(&chars, &mut i),
+                '[' if next != Some('|') => self.chord(&chars, &mut i),
+                '|' | ':' | '[' => {
+                    let start = i - 1;
+                    while chars.get(i).is_some_and(|c| "|:]".contains(*c)) {
+                        i += 1;
+                    }
+                    let bar: String = chars[start..i].iter().collect();
+                    self.bar(&bar);
+                    if chars.get(i).is_some_and(char::is_ascii_digit) {
+                        self.ending(&chars, &mut i);
+                    }
+                },
+                '&' => self.overlay = true,
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    i -= 1;
+                    if let Some(pitch) = self.pitch(&chars, &mut i) {
+                        let length = length(&chars, &mut i);
+                        self.note(&pitch.lilypond_name(), length, &chars, &mut i);
+                    }
+                },
+                'z' | 'x' => {
+                    let length = length(&chars, &mut i);
+                    self.note(if c == 'z' { "r" } else { "s" }, length, &chars, &mut i);
+                },
+                // a rest of whole measures, e.g. `Z4`
+                'Z' | 'X' => {
+                    let measures = length(&chars, &mut i).num;
+                    let rest = format!("R{}*{}", lilypond_duration(self.measure()), measures);
+                    self.melody.push(rest.trim_end_matches("*1").to_string());
+                    self.position = self.position + self.measure() * Fraction::new(measures, 1);
+                },
+                c if DECORATIONS.contains(c) => {},
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    // adds e.g. `~` to the last note
+    fn append(&mut self, s: &str) {
+        if let Some(last) = self.melody.iter_mut().rev().find(|token| !token.starts_with('\\') && token != &"}") {
+            last.push_str(s);
+        }
+    }
+
+    // e.g. `^f'` or `=B,`, with the key's or the measure's accidental
+    fn pitch(&mut self, chars: &[char], i: &mut usize) -> Option<Pitch> {
+        let mut explicit = None;
+        while let Some(c @ ('^' | '_' | '=')) = chars.get(*i) {
+            let alteration = explicit.unwrap_or_default();
+            explicit = Some(match c {
+                '^' => alteration + 1,
+                '_' => alteration - 1,
+                _ => 0,
+            });
+            *i += 1;
+        }
+
+        let letter = chars.get(*i)?;
+        let step = LETTERS.find(letter.to_ascii_uppercase())?;
+        let mut octave = if letter.is_ascii_uppercase() { 1 } else { 2 };
+        *i += 1;
+        while let Some(c @ ('\'' | ',')) = chars.get(*i) {
+            octave += if *c == '\'' { 1 } else { -1 };
+            *i += 1;
+        }
+
+        let at = (octave, step);
+        let alteration = match explicit {
+            Some(alteration) => {
+                self.accidentals.push((at, alteration));
+                alteration
+            },
+            None => self.accidentals.iter().rev().find(|(of, _)| *of == at).map_or(self.key[step], |(_, a)| *a),
+        };
+        Some(Pitch { step, alteration, octave })
+    }
+
+    // a note of `length` units, a rest, or a chord, e.g. `<a' cis''>`.
+    // a `>` or `<` after it makes it longer and the next one shorter
+    fn note(&mut self, pitch: &str, length: Fraction, chars: &[char], i: &mut usize) {
+        let unit = self.unit.unwrap_or(Fraction::new(1, 8));
+        let mut length = unit * length * self.broken.take().unwrap_or(Fraction::ONE);
+
+        if let Some(&c @ ('>' | '<')) = chars.get(*i) {
+            let count = chars[*i..].iter().take_while(|next| **next == c).count() as u32;
+            *i += count as usize;
+            let short = Fraction::new(1, 1 << count);
+            let long = Fraction::new(2, 1) - short;
+            let (this, next) = if c == '>' { (long, short) } else { (short, long) };
+            length = length * this;
+            self.broken = Some(next);
+        }
+
+        let mut token = format!("{}{}", pitch, lilypond_duration(length));
+        if let Some(grace) = &mut self.grace {
+            grace.push(token);
+            return;
+        }
+        if let Some(ending) = self.ending.take() {
+            token.push_str(&ending);
+        }
+        if std::mem::take(&mut self.slur) {
+            token.push('(');
+        }
+        self.melody.push(token);
+
+        let played = self.tuplet.map_or(Fraction::ONE, |(_, played)| played);
+        self.position = self.position + length * played;
+        if let Some((left, played)) = self.tuplet.take() {
+            match left {
+                1 => self.melody.push(String::from("}")),
+                _ => self.tuplet = Some((left - 1, played)),
+            }
+        }
+    }
+
+    // e.g. `[CEG]2`
+    fn chord(&mut self, chars: &[char], i: &mut usize) {
+        let mut pitches = vec![];
+        let mut inner = None;
+        while let Some(&c) = chars.get(*i) {
+            match c {
+                ']' => {
+                    *i += 1;
+                    break;
+                },
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    let Some(pitch) = self.pitch(chars, i) else { break };
+                    pitches.push(pitch.lilypond_name());
+                    let length = length(chars, i);
+                    inner.get_or_insert(length);
+                },
+                _ => *i += 1,
+            }
+        }
+
+        let length = inner.unwrap_or(Fraction::ONE) * length(chars, i);
+        match &pitches[..] {
+            [] => {},
+            [pitch] => self.note(&pitch.clone(), length, chars, i),
+            _ => self.note(&format!("<{}>", pitches.join(" ")), length, chars, i),
+        }
+    }
+
+    // `(3` is 3 notes in the time of 2, or e.g. `(5:4:5` 5 in the time
+    // of 4, for the next 5 notes
+    fn tuplet(&mut self, chars: &[char], i: &mut usize) {
+        let mut numbers = vec![];
+        loop {
+            let start = *i;
+            while chars.get(*i).is_some_and(char::is_ascii_digit) {
+                *i += 1;
+            }
+            numbers.push(chars[start..*i].iter().collect::<String>().parse::<u64>().ok());
+            if chars.get(*i) != Some(&':') || numbers.len() == 3 {
+                break;
+            }
+            *i += 1;
+        }
+
+        let Some(actual) = numbers[0].filter(|actual| *actual > 1) else { return };
+        let compound = self.time.is_some_and(|(beats, _)| beats % 3 == 0 && beats > 3);
+        let normal = numbers.get(1).copied().flatten().unwrap_or(match actual {
+            3 | 6 => 2,
+            2 | 4 | 8 => 3,
+            _ if compound => 3,
+            _ => 2,
+        });
+        let notes = numbers.get(2).copied().flatten().unwrap_or(actual);
+
+        self.melody.push(format!("\\tuplet {}/{} {{", actual, normal));
+        self.tuplet = Some((notes, Fraction::new(normal, actual)));
+    }
+
+    // `[1`, or `2` after a bar line, above the next note
+    fn ending(&mut self, chars: &[char], i: &mut usize) {
+        if chars.get(*i) == Some(&'[') {
+            *i += 1;
+        }
+        let start = *i;
+        while chars.get(*i).is_some_and(|c| c.is_ascii_digit() || *c == ',' || *c == '-') {
+            *i += 1;
+        }
+        let numbers: String = chars[start..*i].iter().collect();
+        self.ending = Some(format!("^\"{}.\"", numbers));
+    }
+
+    // e.g. `|`, `||`, `|]`, `|:`, `:|`, or `::`. a start repeat at the
+    // start of a measure is where the measure starts
+    fn bar(&mut self, bar: &str) {
+        let kind = match (bar.starts_with(':'), bar.ends_with(':')) {
+            (true, true) => Some(":..:"),
+            (true, false) => Some(":|."),
+            (false, true) => Some(".|:"),
+            _ if bar == "||" => Some("||"),
+            _ if bar.ends_with(']') => Some("|."),
+            _ if bar.starts_with('[') => Some(".|"),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            self.melody.push(format!("\\bar \"{}\"", kind));
+        }
+        if self.position > Fraction::ZERO {
+            self.end_measure();
+        }
+    }
+
+    fn end_measure(&mut self) {
+        self.overlay = false;
+        self.accidentals.clear();
+        if self.position == Fraction::ZERO && self.chords.is_empty() {
+            if !self.melody.is_empty() && self.measures > 0 {
+                // e.g. the final bar line after the last measure
+                if let Some(last) = self.song.melody.last_mut() {
+                    let end = last.strip_suffix(" |").unwrap_or(last).to_string();
+                    *last = format!("{} {} |", end, self.melody.join(" "));
+                }
+                self.melody.clear();
+            }
+            return;
+        }
+
+        let (length, pickup) = measure_length(self.position, self.measure(), self.measures == 0);
+        let chords = std::mem::take(&mut self.chords);
+        let line = self.chord_line.measure(chords, length, pickup, lilypond_duration);
+        if pickup {
+            self.song.header.push(format!("\\partial {}", lilypond_duration(length)));
+        }
+
+        self.song.chords.append(&mut self.marks);
+        self.song.chords.push(line);
+        self.melody.push(String::from("|"));
+        self.song.melody.push(std::mem::take(&mut self.melody).join(" "));
+        self.position = Fraction::ZERO;
+        self.measures += 1;
+    }
+}
+
+// `templater export tunes.abc` (or `export --format abc`) writes every
+// song in the book as an abc tune, in concert pitch, the other way
+// around: the front matter is the `T:`, `C:`, and `Q:` headers, the
+// melody's first `\time` and `\key` are `M:` and `K:`, and its notes
+// are written one `L:1/8` at a time, four measures a line. the chords
+// go above the notes they start on, and a note that a chord (or a bar
+// line) starts in the middle of is split into tied notes. repeats,
+// their endings, repeat bar lines, and key and time changes are kept.
+// a song without a melody is its chords above invisible rests.
+
+// `L:`
+const UNIT: Fraction = Fraction { num: 1, den: 8 };
+const MEASURES_PER_LINE: usize = 4;
+
+// what's in the melody between its notes
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mark {
+    Bar(BarMark),
+    Key(Key),
+}
+
+// every repeat, ending, and bar line in `music`, and every key change
+fn marks(music: &str) -> Vec<(usize, Mark)> {
+    let mut marks: Vec<(usize, Mark)> = bar_marks(music).into_iter().map(|(end, mark)| (end, Mark::Bar(mark))).collect();
+
+    let tokens = tokenize(music);
+    let word = |i: usize| match tokens.get(i) {
+        Some((Token::Word(word), ..)) => Some(word.as_str()),
+        _ => None,
+    };
+    for i in 0..tokens.len() {
+        if word(i) != Some("\\key") {
+            continue;
+        }
+        let key = word(i + 1).zip(word(i + 2)).and_then(|(tonic, mode)| format!("{} {}", tonic, mode).parse().ok());
+        if let Some(key) = key {
+            marks.push((tokens[i + 2].2, Mark::Key(key)));
+        }
+    }
+
+    marks.sort_by_key(|(end, _)| *end);
+    marks
+}
+
+// e.g. "Bbm7b5/F" for "bes:m7.5-/f", none if it isn't a chord
+fn abc_chord(token: &str) -> Option<String> {
+    let name = |chord: &Chord| {
+        let mut name = chord.root.display_name() + &quality(&chord.steps).replace('°', "dim").replace('+', "aug");
+        if let Some(bass) = &chord.bass {
+            name.push('/');
+            name.push_str(&bass.pitch.display_name());
+        }
+        name
+    };
+
+    match token.contains(POLYCHORD) {
+        true => {
+            let chord = Polychord::parse(token)?;
+            Some(format!("{}{}{}", name(&chord.upper), POLYCHORD, name(&chord.lower)))
+        },
+        false => Chord::parse(token).map(|chord| name(&chord)),
+    }
+}
+
+// (when, from the start, and name) of every chord of a `\chordmode`
+fn chord_times(chords: &str) -> Vec<(Fraction, String)> {
+    let mut tokens: Vec<(Fraction, String)> = vec![];
+    let mut time = Fraction::ZERO;
+    // whether the last note was a chord, which a modifier is part of
+    let mut chord = false;
+
+    for event in events(chords) {
+        match event {
+            Event::Note { pitch, duration, .. } => {
+                chord = false;
+                if let Some(root) = pitch.filter(|pitch| parse_pitch(pitch).is_some()) {
+                    tokens.push((time, root));
+                    chord = true;
+                }
+                time = time + duration;
+            },
+            Event::Modifier { text, .. } if chord => {
+                if let Some((_, token)) = tokens.last_mut() {
+                    token.push_str(&text);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    tokens.into_iter().filter_map(|(at, token)| abc_chord(&token).map(|name| (at, name))).collect()
+}
+
+// e.g. "Bb", "Cm", or "Ddor"
+fn abc_key(key: &Key) -> String {
+    let mode = match key.mode {
+        "major" => "",
+        "minor" => "m",
+        mode => mode.get(..3).unwrap_or(mode),
+    };
+    format!("{}{}", key.tonic.display_name(), mode)
+}
+
+// e.g. `f'` for f''' or `B,` for b, without its accidental
+fn abc_note(pitch: &Pitch) -> String {
+    let letter = LETTERS.chars().nth(pitch.step).unwrap_or('C');
+    match pitch.octave {
+        octave if octave >= 2 => format!("{}{}", letter.to_ascii_lowercase(), "'".repeat(octave as usize - 2)),
+        octave => format!("{}{}", letter, ",".repeat((1 - octave) as usize)),
+    }
+}
+
+// in `UNIT`s, e.g. `3/2` for a dotted eighth and nothing for an eighth
+fn abc_length(duration: Fraction) -> String {
+    let length = duration * Fraction::new(UNIT.den, UNIT.num);
+    match (length.num, length.den) {
+        (1, 1) => String::new(),
+        (num, 1) => num.to_string(),
+        (1, 2) => String::from("/"),
+        (1, den) => format!("/{}", den),
+        (num, den) => format!("{}/{}", num, den),
+    }
+}
+
+// e.g. `:|` and `|:` are `::`
+fn join_bars(first: &str, then: &str) -> String {
+    match (first, then) {
+        ("|", then) => then.to_string(),
+        (first, "|") => first.to_string(),
+        (":|", "|:") => String::from("::"),
+        (first, _) if first.contains(':') => first.to_string(),
+        (_, then) => then.to_string(),
+    }
+}
+
+#[derive(Debug)]
+struct TuneWriter {
+    lines: Vec<String>,
+    // of the current line
+    tokens: Vec<String>,
+    // whether the last token is a bar line
+    bar_last: bool,
+    // the token the last note is at the end of, for a tie
+    last_note: Option<usize>,
+    // whether the last note can be beamed to the next one
+    beam: bool,
+    measures_on_line: usize,
+    clock: Clock,
+    chords: Vec<(Fraction, String)>,
+    next_chord: usize,
+    key: Option<Key>,
+    alterations: [i32; 7],
+    // the accidentals so far in the measure, by (octave, step)
+    accidentals: Vec<((i32, usize), i32)>,
+    // how many notes of a triplet are written
+    triplets: usize,
+}
+
+impl TuneWriter {
+    fn new(meter: (u64, u64), key: Option<Key>, chords: Vec<(Fraction, String)>) -> Self {
+        TuneWriter {
+            lines: vec![],
+            tokens: vec![],
+            bar_last: false,
+            last_note: None,
+            beam: false,
+            measures_on_line: 0,
+            clock: Clock::new(meter),
+            chords,
+            next_chord: 0,
+            key,
+            alterations: key_alterations(key.map_or(0, |key| key.fifths())),
+            accidentals: vec![],
+            triplets: 0,
+        }
+    }
+
+    fn push(&mut self, token: String) {
+        if self.measures_on_line >= MEASURES_PER_LINE {
+            self.lines.push(std::mem::take(&mut self.tokens).join(" "));
+            self.measures_on_line = 0;
+        }
+        self.tokens.push(token);
+        self.bar_last = false;
+        self.beam = false;
+    }
+
+    fn bar(&mut self, bar: &str) {
+        match self.tokens.last_mut().filter(|_| self.bar_last) {
+            Some(last) => *last = join_bars(last, bar),
+            None => {
+                self.tokens.push(bar.to_string());
+                self.bar_last = true;
+            },
+        }
+        self.beam = false;
+    }
+
+    fn mark(&mut self, mark: Mark) {
+        match mark {
+            Mark::Bar(BarMark::StartRepeat) => self.bar("|:"),
+            Mark::Bar(BarMark::EndRepeat) => self.bar(":|"),
+            Mark::Bar(BarMark::EndStartRepeat) => self.bar("::"),
+            Mark::Bar(BarMark::Double) => self.bar("||"),
+            Mark::Bar(BarMark::Final) => self.bar("|]"),
+            Mark::Bar(BarMark::Ending(number)) => {
+                match self.tokens.last_mut().filter(|_| self.bar_last) {
+                    Some(last) => last.push_str(&format!("[{}", number)),
+                    None => self.push(format!("[{}", number)),
+                }
+                self.bar_last = false;
+            },
+            Mark::Key(key) if self.key != Some(key) => {
+                self.key = Some(key);
+                self.alterations = key_alterations(key.fifths());
+                self.push(format!("[K:{}]", abc_key(&key)));
+            },
+            Mark::Key(_) => {},
+        }
+    }
+
+    fn time_signature(&mut self, beats: u64, unit: u64) {
+        if self.clock.meter != (beats, unit) {
+            self.clock.meter = (beats, unit);
+            self.push(format!("[M:{}/{}]", beats, unit));
+        }
+    }
+
+    fn tie(&mut self) {
+        if let Some(token) = self.last_note.and_then(|i| self.tokens.get_mut(i)) {
+            token.push('-');
+        }
+    }
+
+    // the chords from the last note to `time`, the last one's name is
+    // written above the next note
+    fn chord_until(&mut self, time: Fraction) -> Option<String> {
+        let mut chord = None;
+        while let Some((_, name)) = self.chords.get(self.next_chord).filter(|(at, _)| *at <= time) {
+            chord = Some(format!("\"{}\"", name));
+            self.next_chord += 1;
+        }
+        chord
+    }
+
+    fn accidental(&mut self, pitch: &Pitch) -> &'static str {
+        let at = (pitch.octave, pitch.step);
+        let expected = self.accidentals.iter().rev().find(|(of, _)| *of == at);
+        let expected = expected.map_or(self.alterations[pitch.step], |(_, alteration)| *alteration);
+        if pitch.alteration == expected {
+            return "";
+        }
+        self.accidentals.push((at, pitch.alteration));
+        match pitch.alteration {
+            2 => "^^",
+            1 => "^",
+            0 => "=",
+            -1 => "_",
+            _ => "__",
+        }
+    }
+
+    // a note, or a rest (`z`, or `x` when it's invisible) for none
+    fn note(&mut self, pitch: Option<Pitch>, duration: Fraction, rest: &str) {
+        let in_units = duration * Fraction::new(UNIT.den, UNIT.num);
+        if in_units.den.is_multiple_of(3) {
+            // a triplet's notes are written half again as long
+            let mut token = self.chord_until(self.clock.time).unwrap_or_default();
+            if self.triplets.is_multiple_of(3) {
+                token = format!("(3{}", token);
+            }
+            self.triplets += 1;
+            self.write(token, pitch, duration * Fraction::new(3, 2), rest);
+            self.advance(duration);
+            return;
+        }
+        self.triplets = 0;
+
+        let mut left = duration;
+        while left > Fraction::ZERO {
+            let chord = self.chord_until(self.clock.time).unwrap_or_default();
+            // until the end of the measure or the next chord
+            let mut length = left.min(self.clock.measure() - self.clock.position);
+            if let Some((at, _)) = self.chords.get(self.next_chord) {
+                length = length.min(*at - self.clock.time);
+            }
+            self.write(chord, pitch, length, rest);
+
+            left = left - length;
+            if left > Fraction::ZERO && pitch.is_some() {
+                self.tie();
+            }
+            self.advance(length);
+        }
+    }
+
+    fn write(&mut self, mut token: String, pitch: Option<Pitch>, length: Fraction, rest: &str) {
+        match &pitch {
+            Some(pitch) => {
+                let accidental = self.accidental(pitch);
+                token.push_str(accidental);
+                token.push_str(&abc_note(pitch));
+            },
+            None => token.push_str(rest),
+        }
+        token.push_str(&abc_length(length));
+
+        // eighths and shorter are beamed within a beat
+        let beat = match self.clock.meter {
+            (beats, 8) if beats % 3 == 0 => Fraction::new(3, 8),
+            _ => Fraction::new(1, 4),
+        };
+        let on_beat = (self.clock.position * Fraction::new(beat.den, beat.num)).den == 1;
+        match self.tokens.last_mut().filter(|_| self.beam && !on_beat && pitch.is_some()) {
+            Some(last) => last.push_str(&token),
+            None => self.push(token),
+        }
+        self.beam = pitch.is_some() && length < Fraction::new(1, 4);
+        self.last_note = Some(self.tokens.len() - 1);
+    }
+
+    fn advance(&mut self, length: Fraction) {
+        for pickup in self.clock.advance(length) {
+            self.bar("|");
+            self.accidentals.clear();
+            if !pickup {
+                self.measures_on_line += 1;
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        match self.tokens.last().filter(|_| self.bar_last).map(String::as_str) {
+            Some("|") | None => self.bar("|]"),
+            _ => {},
+        }
+        self.lines.push(self.tokens.join(" "));
+        self.lines
+    }
+}
+
+// a song as an abc tune, in concert pitch
+pub fn to_abc(song: &Song) -> String {
+    let mut lines = vec![format!("X:{}", song.number), format!("T:{}", song.title)];
+    lines.extend(song.subtitle.iter().map(|subtitle| format!("T:{}", subtitle)));
+    lines.push(format!("C:{}", song.composer));
+    match (&song.meter, song.bpm()) {
+        (Some(meter), Some(bpm)) => lines.push(format!("Q:\"{}\" 1/4={}", meter, bpm)),
+        (Some(meter), None) => lines.push(format!("Q:\"{}\"", meter)),
+        (None, Some(bpm)) => lines.push(format!("Q:1/4={}", bpm)),
+        (None, None) => {},
+    }
+
+    let melody = song.voices.iter().find(|voice| voice.name.is_none()).map(|voice| voice.notes.as_str());
+    let melody = melody
+        .filter(|notes| !["<<", "\\fixed", "\\transpose"].iter().any(|command| notes.contains(command)))
+        .and_then(|notes| to_absolute(notes).ok())
+        .filter(|music| events(music).iter().any(|event| matches!(event, Event::Note { .. })));
+    // without one, the chords are over invisible rests
+    let (music, rest) = match &melody {
+        Some(music) => (music.as_str(), "z"),
+        None => (song.chords.as_str(), "x"),
+    };
+
+    let events = spanned_events(music);
+    let marks = marks(music);
+    // before the first note
+    let start = events.iter().find(|(event, _)| matches!(event, Event::Note { .. })).map_or(0, |(_, end)| *end);
+    let meter = events.iter().find_map(|(event, end)| match event {
+        Event::Time { beats, unit, .. } if *end < start => Some((*beats, *unit)),
+        _ => None,
+    });
+    let key = marks.iter().find_map(|(end, mark)| match mark {
+        Mark::Key(key) if *end < start => Some(*key),
+        _ => None,
+    });
+    let key = key.or(song.key);
+    let meter = meter.unwrap_or((4, 4));
+
+    lines.push(format!("M:{}/{}", meter.0, meter.1));
+    lines.push(format!("L:{}/{}", UNIT.num, UNIT.den));
+    lines.push(format!("K:{}", key.map_or(String::from("C"), |key| abc_key(&key))));
+
+    let mut writer = TuneWriter::new(meter, key, chord_times(&song.chords));
+    let mut marks = marks.into_iter().peekable();
+    for (event, end) in events {
+        while let Some((_, mark)) = marks.next_if(|(at, _)| *at < end) {
+            writer.mark(mark);
+        }
+        match event {
+            Event::Note { pitch, duration, .. } if duration > Fraction::ZERO => {
+                let pitch = pitch.as_deref().filter(|_| melody.is_some()).and_then(parse_note);
+                writer.note(pitch, duration, rest);
+            },
+            Event::Time { beats, unit, .. } => writer.time_signature(beats, unit),
+            Event::Partial { duration, .. } => writer.clock.partial(duration),
+            Event::Tie { .. } => writer.tie(),
+            _ => {},
+        }
+    }
+    for (_, mark) in marks {
+        writer.mark(mark);
+    }
+    lines.extend(writer.finish());
+
+    lines.join("\n") + "\n"
+}
+
+// every song, one tune after another
+pub fn export(songs: &[Song]) -> String {
+    songs.iter().map(to_abc).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    const TUNE: &str = "%abc-2.1
+X:1
+T:Test Reel
+T:A Second Title
+C:Trad.
+R:reel
+M:4/4
+L:1/8
+Q:\"Brisk\" 1/4=112
+K:D
+\"D\"FA|:\"G\"d2 B>c (3dcB A2-|A2 =cF \"Em7/B\"[EGB]4:|
+P:B
+w: these words are left out
+z4 e'f' ^g,a|Z2|]
+";
+
+    #[test]
+    fn it_imports_abc() {
+        let songs = import(TUNE).unwrap();
+        let song = &songs[0];
+
+        assert_eq!(song.title, "Test Reel");
+        assert_eq!(song.subtitle.as_deref(), Some("A Second Title"));
+        assert_eq!(song.composer.as_deref(), Some("Trad."));
+        assert_eq!(song.meter.as_deref(), Some("Brisk"));
+        assert_eq!(song.bpm, Some(112));
+        assert_eq!(song.header, vec!["\\key d \\major", "\\time 4/4", "\\partial 4"]);
+        assert_eq!(
+            song.melody,
+            vec![
+                "fis'8 a'8 \\bar \".|:\" |",
+                "d''4 b'8. cis''16 \\tuplet 3/2 { d''8 cis''8 b'8 } a'4~ |",
+                "a'4 c''8 fis'8 <e' g' b'>2 \\bar \":|.\" |",
+                "\\boxMark \"B\" r2 e'''8 fis'''8 gis'8 a''8 |",
+                "R1*2 \\bar \"|.\" |",
+            ]
+        );
+        assert_eq!(
+            song.chords,
+            vec!["\\partial 4 d4 |", "g1 |", "g2 e2:m7/b |", "%% \"B\"", "e1:m7/b |", "e1*2:m7/b |"]
+        );
+    }
+
+    #[test]
+    fn it_reads_abc_headers_and_chord_symbols() {
+        assert_eq!(key("Ador").map(|key| key.lilypond_name()).as_deref(), Some("a \\dorian"));
+        assert_eq!(key("F#m").map(|key| key.lilypond_name()).as_deref(), Some("fis \\minor"));
+        assert_eq!(key("Bb major").map(|key| key.lilypond_name()).as_deref(), Some("bes \\major"));
+        assert_eq!(key("none"), None);
+        assert_eq!(meter("C|"), Some((2, 2)));
+        assert_eq!(tempo("120"), (None, Some(120)));
+        assert_eq!(tempo("\"Slow Air\""), (Some(String::from("Slow Air")), None));
+
+        let chord = |text: &str| chord_symbol(text).map(|(root, suffix)| root + &suffix);
+        assert_eq!(chord("Bbm7b5").as_deref(), Some("bes:m7.5-"));
+        assert_eq!(chord("F#7/A#").as_deref(), Some("fis:7/ais"));
+        assert_eq!(chord("C6/9").as_deref(), Some("c:6.9"));
+        assert_eq!(chord("N.C.").as_deref(), Some("r"));
+        assert_eq!(chord("^fine"), None);
+    }
+
+    #[test]
+    fn it_imports_every_tune_in_a_file() {
+        let file = "A collection\n\nX:1\nT:One\nK:G\nGABc|\n\nX:2\nT:Two\nM:6/8\nK:Em\n\"Em\"E3 F3|]\n";
+        let songs = import(file).unwrap();
+        assert_eq!(songs.iter().map(|song| song.title.as_str()).collect::<Vec<_>>(), ["One", "Two"]);
+        assert_eq!(songs[1].melody, ["e'4. fis'4. \\bar \"|.\" |"]);
+
+        let song = Song::parse(Path::new("two.ly"), &songs[1].to_song_file(), false).unwrap();
+        assert_eq!(song.title, "Two");
+        assert_eq!(song.key.map(|key| key.to_string()).as_deref(), Some("E minor"));
+        assert!(import("X:1\nK:C\nCDEF|\n").is_err());
+        let e = import("X:1\nT:One\nM:3/0\nK:C\nCDE|\n").err().unwrap();
+        assert_eq!(e.to_string(), "M:3/0 has a zero denominator.");
+        assert!(import("X:1\nT:One\nK:C\n[L:1/0] CDE|\n").is_err());
+    }
+
+    #[test]
+    fn it_writes_a_song_as_abc() {
+        let input = "title: Test Tune\nsubtitle: For Testing\ncomposer: Someone\nmeter: Swing\nbpm: 120\n---\n\
+                     \\chordmode { \\partial 4 s4 | \\repeat volta 2 { c1:maj7 | f2:m7 bes2:7 | }\n\
+                     \\alternative { { g1:7 } { c1 } } }\n---\n\
+                     \\key c \\major \\time 4/4 \\numericTimeSignature \\partial 4 g'4 |\n\
+                     \\repeat volta 2 { c''4. d''8 e''2~ | e''1 | }\n\
+                     \\alternative { { d''1 } { c''2 \\key g \\major fis''2 } } \\bar \"|.\"\n";
+        let mut song = Song::parse(Path::new("songs/test-tune.ly"), input, false).unwrap();
+        song.number = 3;
+
+        let abc = to_abc(&song);
+        assert_eq!(
+            abc,
+            "X:3\nT:Test Tune\nT:For Testing\nC:Someone\nQ:\"Swing\" 1/4=120\nM:4/4\nL:1/8\nK:C\n\
+             G2 |: \"Cmaj7\"c3 d e4- | \"Fm7\"e4- \"Bb7\"e4 |[1 \"G7\"d8 :|[2 \"C\"c4 [K:G] f4 |]\n"
+        );
+
+        // and back
+        let tune = &import(&abc).unwrap()[0];
+        assert_eq!((tune.title.as_str(), tune.meter.as_deref(), tune.bpm), ("Test Tune", Some("Swing"), Some(120)));
+        assert_eq!(tune.melody[..2], ["g'4 \\bar \".|:\" |", "c''4. d''8 e''2~ |"]);
+        assert_eq!(tune.chords[2], "f2:m7 bes2:7 |");
+    }
+}
@@ -0,0 +1,465 @@
+// `templater convert-absolute`: rewrites a song's `\relative` blocks
+// in absolute pitch, so every note's octave can be read (and
+// transposed) without following the notes before it.
+//
+// a note in `\relative` mode is placed within a fourth of the note
+// before it, then moved by its own octave marks. the first note of
+// a chord is relative to the note before the chord, the others to
+// the note before them in the chord, and the note after the chord
+// to the chord's first note. the arguments of `\key`, `\markup`, and
+// scheme expressions aren't notes, and music in a nested `\relative`,
+// `\transpose`, or mode like `\chordmode` isn't converted; a song
+// using them is left as it is with an error.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use similar::TextDiff;
+
+use crate::errors::TemplaterError;
+use crate::frontmatter;
+use crate::utils::read_file;
+
+// commands inside `\relative` that change how the music in them is
+// read, so converting around them would change the song
+const UNSUPPORTED: [&str; 10] = [
+    "relative",
+    "transpose",
+    "fixed",
+    "absolute",
+    "chordmode",
+    "chords",
+    "lyricmode",
+    "addlyrics",
+    "lyricsto",
+    "drummode",
+];
+
+// a pitch's octave and letter, counted in steps from the unmarked `c`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position(i32);
+
+impl Position {
+    fn new(step: i32, octave: i32) -> Self {
+        Position(octave * 7 + step)
+    }
+
+    fn step(&self) -> i32 {
+        self.0.rem_euclid(7)
+    }
+
+    // the closest `step` to this one, up to a fourth away
+    fn relative(&self, step: i32, octave_marks: i32) -> Position {
+        let mut interval = (step - self.step()).rem_euclid(7);
+        if interval > 3 {
+            interval -= 7;
+        }
+
+        Position(self.0 + interval + 7 * octave_marks)
+    }
+
+    fn octave_marks(&self) -> String {
+        let octave = self.0.div_euclid(7);
+        match octave < 0 {
+            true => ",".repeat(-octave as usize),
+            false => "'".repeat(octave as usize),
+        }
+    }
+}
+
+// the letter's step if `word` is a note name, e.g. `ees` or `fis`
+fn note_step(word: &str) -> Option<i32> {
+    let mut chars = word.chars();
+    let step = "cdefgab".find(chars.next()?)? as i32;
+
+    let mut rest = chars.as_str();
+    // `as` and `es` are aes and ees
+    if matches!(step, 2 | 5) {
+        rest = rest.strip_prefix('s').unwrap_or(rest);
+    }
+    while !rest.is_empty() {
+        rest = rest.strip_prefix("is").or_else(|| rest.strip_prefix("es"))?;
+    }
+
+    Some(step)
+}
+
+struct Converter<'a> {
+    input: &'a str,
+    pos: usize,
+    out: String,
+}
+
+impl<'a> Converter<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn error(&self, message: &str) -> TemplaterError {
+        let line = self.input[..self.pos].matches('\n').count() + 1;
+        TemplaterError::from_str(&format!("line {}: {}", line, message))
+    }
+
+    // copies `len` bytes unchanged
+    fn copy(&mut self, len: usize) {
+        self.out.push_str(&self.input[self.pos..self.pos + len]);
+        self.pos += len;
+    }
+
+    fn copy_while<F: Fn(char) -> bool>(&mut self, f: F) -> usize {
+        let len = self.rest().find(|c: char| !f(c)).unwrap_or(self.rest().len());
+        self.copy(len);
+        len
+    }
+
+    // a `%` comment, `%{ block comment %}`, or `"string"`. returns
+    // false if there isn't one at `pos`.
+    fn copy_comment_or_string(&mut self) -> Result<bool, TemplaterError> {
+        let rest = self.rest();
+        if rest.starts_with("%{") {
+            let end = rest.find("%}").ok_or_else(|| self.error("unclosed %{ comment"))?;
+            self.copy(end + 2);
+        } else if rest.starts_with('%') {
+            self.copy(rest.find('\n').unwrap_or(rest.len()));
+        } else if rest.starts_with('"') {
+            let mut escaped = false;
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| {
+                    let end = *c == '"' && !escaped;
+                    escaped = *c == '\\' && !escaped;
+                    end
+                })
+                .map(|(i, _)| i)
+                .ok_or_else(|| self.error("unclosed string"))?;
+            self.copy(end + 1);
+        } else {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    // from an opening `open` up to the matching `close`
+    fn copy_balanced(&mut self, open: char, close: char) -> Result<(), TemplaterError> {
+        let mut depth = 0;
+        loop {
+            if self.copy_comment_or_string()? {
+                continue;
+            }
+            match self.peek() {
+                Some(c) if c == open => depth += 1,
+                Some(c) if c == close => depth -= 1,
+                Some(_) => {},
+                None => return Err(self.error(&format!("missing '{}'", close))),
+            }
+            self.copy(self.peek().map_or(0, char::len_utf8));
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    // `#(...)`, `#'sym`, `##t`, `#-3`, ...
+    fn copy_scheme(&mut self) -> Result<(), TemplaterError> {
+        self.copy(1);
+        while matches!(self.peek(), Some('#' | '\'' | '`')) {
+            self.copy(1);
+        }
+
+        match self.peek() {
+            Some('(') => self.copy_balanced('(', ')'),
+            Some('"') => self.copy_comment_or_string().map(|_| ()),
+            _ => {
+                self.copy_while(|c| !c.is_whitespace() && !"{}()<>\"".contains(c));
+                Ok(())
+            },
+        }
+    }
+
+    // `\markup`'s argument: a `{ ... }`, a string, or a command and its argument
+    fn copy_markup(&mut self) -> Result<(), TemplaterError> {
+        self.copy_while(char::is_whitespace);
+        match self.peek() {
+            Some('{') => self.copy_balanced('{', '}'),
+            Some('"') => self.copy_comment_or_string().map(|_| ()),
+            Some('\\') => {
+                self.copy(1);
+                self.copy_while(|c| c.is_ascii_alphabetic() || c == '-');
+                self.copy_markup()
+            },
+            Some('#') => self.copy_scheme(),
+            _ => {
+                self.copy_while(|c| !c.is_whitespace() && c != '}');
+                Ok(())
+            },
+        }
+    }
+
+    // the start pitch after `\relative`, e.g. `c'`
+    fn start_pitch(&mut self) -> Result<Position, TemplaterError> {
+        let rest = self.rest().trim_start();
+        self.pos = self.input.len() - rest.len();
+
+        let word_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        let step = note_step(&rest[..word_len]).ok_or_else(|| {
+            self.error("\\relative without a start pitch can't be converted, e.g. use \\relative c' { ... }")
+        })?;
+        let marks = &rest[word_len..];
+        let marks_len = marks.find(|c: char| c != '\'' && c != ',').unwrap_or(marks.len());
+        let octave = marks[..marks_len].chars().map(|c| if c == '\'' { 1 } else { -1 }).sum();
+
+        self.pos += word_len + marks_len;
+        Ok(Position::new(step, octave))
+    }
+
+    fn convert(mut self) -> Result<String, TemplaterError> {
+        loop {
+            if self.copy_comment_or_string()? {
+                continue;
+            }
+
+            let rest = self.rest();
+            let Some(c) = rest.chars().next() else {
+                return Ok(self.out);
+            };
+
+            let is_relative = rest.starts_with("\\relative")
+                && !rest["\\relative".len()..].starts_with(|c: char| c.is_ascii_alphabetic());
+            match is_relative {
+                true => {
+                    self.pos += "\\relative".len();
+                    let start = self.start_pitch()?;
+                    let whitespace = self.rest().len() - self.rest().trim_start().len();
+                    self.pos += whitespace;
+                    if self.peek() != Some('{') {
+                        return Err(self.error("expected '{' after \\relative's start pitch"));
+                    }
+                    self.relative_block(start)?;
+                },
+                false => self.copy(c.len_utf8()),
+            }
+        }
+    }
+
+    // from `{` to its `}`, rewriting every note
+    fn relative_block(&mut self, mut previous: Position) -> Result<(), TemplaterError> {
+        let mut depth = 0;
+        // the first note of the current chord, and the one before it
+        let mut chord: Option<(Option<Position>, Position)> = None;
+        let mut skip_next_note = false;
+
+        loop {
+            if self.copy_comment_or_string()? {
+                continue;
+            }
+
+            let rest = self.rest();
+            let Some(c) = rest.chars().next() else {
+                return Err(self.error("\\relative block is never closed"));
+            };
+
+            match c {
+                '{' => {
+                    depth += 1;
+                    self.copy(1);
+                },
+                '}' => {
+                    depth -= 1;
+                    self.copy(1);
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                },
+                '#' | '$' => self.copy_scheme()?,
+                '\\' => {
+                    let name_len = rest[1..].find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len() - 1);
+                    let name = &rest[1..1 + name_len];
+                    if UNSUPPORTED.contains(&name) {
+                        return Err(self.error(&format!("\\{} inside \\relative can't be converted", name)));
+                    }
+
+                    // `\<`, `\!`, ... are a single character
+                    let len = match name_len {
+                        0 => rest[1..].chars().next().map_or(0, char::len_utf8),
+                        len => len,
+                    };
+                    self.copy(1 + len);
+                    match name {
+                        "markup" | "markuplist" => self.copy_markup()?,
+                        "key" | "transposition" => skip_next_note = true,
+                        _ => {},
+                    }
+                },
+                // articulations, e.g. `->` and `-^`, aren't chords
+                '-' | '^' | '_' => {
+                    let len = 1 + rest[1..].chars().next().filter(|c| ">^.-+!_|".contains(*c)).map_or(0, char::len_utf8);
+                    self.copy(len);
+                },
+                '<' if rest.starts_with("<<") => self.copy(2),
+                '>' if rest.starts_with(">>") => self.copy(2),
+                '<' => {
+                    chord = Some((None, previous));
+                    self.copy(1);
+                },
+                '>' => {
+                    if let Some((Some(first), _)) = chord.take() {
+                        previous = first;
+                    }
+                    self.copy(1);
+                },
+                c if c.is_ascii_alphabetic() => {
+                    let word_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+                    let word = &rest[..word_len];
+                    // e.g. `Staff.instrumentName` isn't a note
+                    let is_property = rest[word_len..].starts_with('.')
+                        && rest[word_len + 1..].starts_with(|c: char| c.is_ascii_alphabetic());
+
+                    match note_step(word) {
+                        Some(_) if skip_next_note => {
+                            skip_next_note = false;
+                            self.copy(word_len);
+                        },
+                        Some(step) if !is_property => {
+                            let marks = &rest[word_len..];
+                            let marks_len = marks.find(|c: char| c != '\'' && c != ',').unwrap_or(marks.len());
+                            let octave_marks = marks[..marks_len].chars().map(|c| if c == '\'' { 1 } else { -1 }).sum();
+                            if marks[marks_len..].starts_with('=') {
+                                return Err(self.error("octave checks (e.g. c='') can't be converted"));
+                            }
+
+                            let reference = chord.map_or(previous, |(_, before)| before);
+                            let position = reference.relative(step, octave_marks);
+                            match &mut chord {
+                                Some((first, before)) => {
+                                    first.get_or_insert(position);
+                                    *before = position;
+                                },
+                                None => previous = position,
+                            }
+
+                            self.out.push_str(word);
+                            self.out.push_str(&position.octave_marks());
+                            self.pos += word_len + marks_len;
+                        },
+                        _ => {
+                            self.copy(word_len);
+                            if is_property {
+                                self.copy_while(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+                            }
+                        },
+                    }
+                },
+                c => self.copy(c.len_utf8()),
+            }
+        }
+    }
+}
+
+// every `\relative p { ... }` in `input` as a `{ ... }` in absolute pitch
+pub fn to_absolute(input: &str) -> Result<String, TemplaterError> {
+    Converter { input, pos: 0, out: String::with_capacity(input.len()) }.convert()
+}
+
+// a song file's music, leaving its front matter as it is
+pub fn song_to_absolute(song: &str) -> Result<String, TemplaterError> {
+    let (_, document) = frontmatter::split(song);
+    let start = match song.ends_with(document) {
+        true => song.len() - document.len(),
+        false => 0,
+    };
+
+    Ok(format!("{}{}", &song[..start], to_absolute(&song[start..])?))
+}
+
+// a song that was (or would be, with `--dry-run`) rewritten, and
+// the unified diff of the change
+pub struct Conversion {
+    pub path: PathBuf,
+    pub diff: String,
+}
+
+// converts every file in `paths` that has a `\relative` block. files
+// that can't be converted are left as they are, and reported together
+// after the others were converted.
+pub fn convert_songs(paths: &[PathBuf], dry_run: bool) -> Result<Vec<Conversion>, TemplaterError> {
+    let mut conversions = vec![];
+    let mut errors = vec![];
+
+    for path in paths {
+        match convert_song(path, dry_run) {
+            Ok(Some(conversion)) => conversions.push(conversion),
+            Ok(None) => {},
+            Err(e) => errors.push(e),
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(conversions),
+        false => {
+            for conversion in &conversions {
+                log!("[info]: converted {}", conversion.path.display());
+            }
+            Err(TemplaterError::collect(errors))
+        },
+    }
+}
+
+fn convert_song(path: &Path, dry_run: bool) -> Result<Option<Conversion>, TemplaterError> {
+    let song = read_file(path)?;
+    let converted =
+        song_to_absolute(&song).map_err(|e| TemplaterError::from_str(&format!("{}: {}", path.display(), e)))?;
+    if converted == song {
+        return Ok(None);
+    }
+
+    if !dry_run {
+        fs::write(path, &converted).map_err(|e| TemplaterError::file(path, e))?;
+    }
+
+    let name = path.display().to_string();
+    let diff = TextDiff::from_lines(&song, &converted).unified_diff().context_radius(3).header(&name, &name).to_string();
+    Ok(Some(Conversion { path: path.to_path_buf(), diff }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_converts_relative_notes() {
+        assert_eq!(to_absolute("\\relative c' { c d e f g a b c }").unwrap(), "{ c' d' e' f' g' a' b' c'' }");
+        assert_eq!(to_absolute("\\relative c'' { c2.~ c8 bes8 ees8 | f,1 }").unwrap(), "{ c''2.~ c''8 bes'8 ees''8 | f'1 }");
+        assert_eq!(to_absolute("\\relative c' { b' c,, }").unwrap(), "{ b' c }");
+    }
+
+    #[test]
+    fn it_converts_chords_like_lilypond() {
+        assert_eq!(to_absolute("\\relative c' { <c e g>4 <c e g> e }").unwrap(), "{ <c' e' g'>4 <c' e' g'> e' }");
+        assert_eq!(to_absolute("\\relative c' { c4-> d-^ << e >> }").unwrap(), "{ c'4-> d'-^ << e' >> }");
+    }
+
+    #[test]
+    fn it_leaves_commands_alone() {
+        let song = "title: 12\" Mix\n---\n\\chordmode { ees1:maj7 }\n---\n\\relative c' {\n  \\key ees \\major\n  \
+                    \\set Staff.fontSize = #-3\n  bes4^\\markup { \\caps { a fine } } %% c d\n  g'4 \"e\" \n}\n";
+        assert_eq!(
+            song_to_absolute(song).unwrap(),
+            "title: 12\" Mix\n---\n\\chordmode { ees1:maj7 }\n---\n{\n  \\key ees \\major\n  \
+             \\set Staff.fontSize = #-3\n  bes4^\\markup { \\caps { a fine } } %% c d\n  g'4 \"e\" \n}\n"
+        );
+    }
+
+    #[test]
+    fn it_refuses_what_it_cant_convert() {
+        assert!(to_absolute("\\relative { c d }").is_err());
+        assert!(to_absolute("\\relative c' { \\transpose c d { c } }").is_err());
+        assert!(to_absolute("\\relative c' { c='' }").is_err());
+        assert!(to_absolute("\\relative c' { c").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+// a book from more than one songs dir, e.g. `--songs-dir ./songs
+// --songs-dir ../realbook/songs`. every songs dir is a collection,
+// named after the dir, which the ToC shows next to its songs. a title
+// that's in more than one collection is an error, unless `duplicate_titles` says
+// which one the book keeps.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::duplicates::{find_duplicates, Duplicate};
+use crate::errors::TemplaterError;
+use crate::models::Song;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateTitles {
+    #[default]
+    Error,
+    // the song from the songs dir listed first
+    First,
+    Last,
+}
+
+impl FromStr for DuplicateTitles {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "error" => Ok(DuplicateTitles::Error),
+            "first" => Ok(DuplicateTitles::First),
+            "last" => Ok(DuplicateTitles::Last),
+            _ => Err(TemplaterError::from_str(&format!(
+                "Unknown duplicate titles policy '{}'. Expected error, first, or last.",
+                s
+            ))),
+        }
+    }
+}
+
+// e.g. `realbook` for `../realbook/songs`, `jazz` for `./songs/jazz`
+pub fn collection_name(songs_dir: &Path) -> String {
+    let dir = songs_dir.canonicalize().unwrap_or_else(|_| songs_dir.to_path_buf());
+    let name = |path: &Path| path.file_name().map(|name| name.to_string_lossy().into_owned());
+
+    match name(&dir) {
+        Some(songs) if songs == "songs" => dir.parent().and_then(name).unwrap_or(songs),
+        Some(name) => name,
+        None => songs_dir.display().to_string(),
+    }
+}
+
+// `songs` with the index of the songs dir each one is from. a title
+// in more than one of them keeps only one song, unless `policy` is
+// `DuplicateTitles::Error`. duplicates within one songs dir are left
+// for `check_duplicates`.
+pub fn resolve_duplicates(songs: Vec<(usize, Song)>, policy: DuplicateTitles) -> Vec<(usize, Song)> {
+    if policy == DuplicateTitles::Error {
+        return songs;
+    }
+
+    let titles: Vec<&str> = songs.iter().map(|(_, song)| song.title.as_str()).collect();
+    let mut left_out = vec![false; songs.len()];
+    for (i, j, duplicate) in find_duplicates(&titles) {
+        let ((a, song_a), (b, song_b)) = (&songs[i], &songs[j]);
+        if duplicate != Duplicate::Same || a == b {
+            continue;
+        }
+
+        let (kept, dropped) = match (policy == DuplicateTitles::First) == (a < b) {
+            true => (song_a, j),
+            false => (song_b, i),
+        };
+        left_out[dropped] = true;
+        log!(
+            "[info]: left out {}, '{}' is from {}",
+            songs[dropped].1.path.display(),
+            kept.title,
+            kept.path.display()
+        );
+    }
+
+    songs.into_iter().zip(left_out).filter(|(_, left_out)| !left_out).map(|(song, _)| song).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontmatter::Defaults;
+    use crate::transpose::Presets;
+
+    fn song(source: usize, title: &str) -> (usize, Song) {
+        let path = format!("songs{}/{}.ly", source, title);
+        let input = format!("title: {}\ncomposer: Someone\n---\n", title);
+        (source, Song::parse_with(Path::new(&path), &input, false, &Defaults::default(), &Presets::default()).unwrap())
+    }
+
+    fn titles(songs: &[(usize, Song)]) -> Vec<String> {
+        songs.iter().map(|(source, song)| format!("{} {}", source, song.title)).collect()
+    }
+
+    #[test]
+    fn it_keeps_one_song_of_a_duplicate_title() {
+        let songs = vec![song(0, "Misty"), song(0, "Nardis"), song(1, "Misty"), song(1, "Oleo")];
+
+        assert_eq!(titles(&resolve_duplicates(songs.clone(), DuplicateTitles::First)), ["0 Misty", "0 Nardis", "1 Oleo"]);
+        assert_eq!(titles(&resolve_duplicates(songs.clone(), DuplicateTitles::Last)), ["0 Nardis", "1 Misty", "1 Oleo"]);
+        assert_eq!(resolve_duplicates(songs, DuplicateTitles::Error).len(), 4);
+    }
+
+    #[test]
+    fn it_names_a_collection_after_its_dir() {
+        assert_eq!(collection_name(Path::new("/nonexistent/realbook/songs")), "realbook");
+        assert_eq!(collection_name(Path::new("/nonexistent/songs/jazz")), "jazz");
+    }
+}
@@ -0,0 +1,133 @@
+// a recording to play along with, e.g. a reference recording or a
+// backing track, `backing_track` in the front matter:
+//
+//     backing_track:
+//       url: https://www.youtube.com/watch?v=cQ4K1hMtRFA
+//       head: 0:42
+//
+// `head` is where the head (the melody) starts, e.g. after an intro,
+// as `m:ss`, `h:mm:ss`, or seconds. the song's header has a qr code
+// that links to it, see `qr.rs`, with the time under it. a youtube
+// link starts at the head. a link too long for a qr code is only a
+// link, and a warning.
+
+use std::fmt;
+use std::str::FromStr;
+
+use minijinja::{context, Value};
+use serde::de::{Deserializer, Error};
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::frontmatter::{opt_string_like, string_like};
+use crate::qr::QrCode;
+
+// in staff spaces, about 2cm
+const QR_WIDTH: f64 = 11.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timestamp(pub u32);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (hours, minutes, seconds) = (self.0 / 3600, self.0 / 60 % 60, self.0 % 60);
+        match hours {
+            0 => write!(f, "{}:{:02}", minutes, seconds),
+            hours => write!(f, "{}:{:02}:{:02}", hours, minutes, seconds),
+        }
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = TemplaterError;
+
+    // e.g. 1:05, 1:02:03, or 65
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || TemplaterError::from_str(&format!("'{}' isn't a time, e.g. 1:05 or 65", s));
+        let parts: Vec<u32> =
+            s.trim().split(':').map(|part| part.parse().map_err(|_| error())).collect::<Result<_, _>>()?;
+        if parts.len() > 3 || parts.iter().skip(1).any(|part| *part >= 60) {
+            return Err(error());
+        }
+        Ok(Timestamp(parts.iter().fold(0, |seconds, part| seconds * 60 + part)))
+    }
+}
+
+fn opt_timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Timestamp>, D::Error> {
+    match opt_string_like(deserializer)? {
+        Some(head) => head.parse().map(Some).map_err(D::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackingTrack {
+    #[serde(deserialize_with = "string_like")]
+    pub url: String,
+    #[serde(default, deserialize_with = "opt_timestamp")]
+    pub head: Option<Timestamp>,
+}
+
+impl BackingTrack {
+    // the url, at the head if it's a youtube link
+    pub fn link(&self) -> String {
+        let youtube = ["youtube.com/", "youtu.be/"].iter().any(|host| self.url.contains(host));
+        match self.head {
+            Some(Timestamp(seconds)) if youtube && !self.url.contains("t=") => {
+                let separator = if self.url.contains('?') { '&' } else { '?' };
+                format!("{}{}t={}s", self.url, separator, seconds)
+            },
+            _ => self.url.clone(),
+        }
+    }
+
+    // why the link can't be a qr code, if it can't, e.g. it's too long
+    pub fn verify(&self) -> Option<String> {
+        QrCode::encode(self.link().as_bytes()).err().map(|e| format!("backing_track has no qr code: {}", e))
+    }
+
+    // the qr code is none if the link is too long for one, see `verify`
+    pub fn context(&self) -> Value {
+        let link = self.link();
+        context! {
+            url => self.url.as_str(),
+            head => self.head.map(|head| head.to_string()).unwrap_or_default(),
+            qr => QrCode::encode(link.as_bytes()).ok().map(|code| code.to_markup(QR_WIDTH)),
+            link,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_links_to_the_head() {
+        let yaml = "url: https://www.youtube.com/watch?v=abc\nhead: 1:05\n";
+        let track: BackingTrack = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(track.head, Some(Timestamp(65)));
+        assert_eq!(track.link(), "https://www.youtube.com/watch?v=abc&t=65s");
+
+        let track: BackingTrack = serde_yaml::from_str("url: https://example.com/track.mp3\nhead: 3723\n").unwrap();
+        assert_eq!(track.head.map(|head| head.to_string()).as_deref(), Some("1:02:03"));
+        assert_eq!(track.link(), "https://example.com/track.mp3");
+
+        assert!(serde_yaml::from_str::<BackingTrack>("url: https://youtu.be/abc\nhead: 1:75\n").is_err());
+        assert!(serde_yaml::from_str::<BackingTrack>("head: 0:42\n").is_err());
+    }
+
+    #[test]
+    fn it_warns_about_a_link_too_long_for_a_qr_code() {
+        let track = BackingTrack { url: String::from("https://youtu.be/cQ4K1hMtRFA"), head: Some(Timestamp(42)) };
+        assert_eq!(track.verify(), None);
+
+        let track = BackingTrack { url: format!("https://example.com/{}.mp3", "a".repeat(200)), head: None };
+        assert_eq!(
+            track.verify().as_deref(),
+            Some("backing_track has no qr code: 224 bytes is too long for a qr code, the most is 213")
+        );
+        assert_eq!(track.context().get_attr("qr").unwrap(), Value::from(()));
+    }
+}
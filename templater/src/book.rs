@@ -0,0 +1,1152 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use rayon::iter::Either;
+use similar::TextDiff;
+use rayon::prelude::*;
+
+use crate::anthology::{collection_name, resolve_duplicates, DuplicateTitles};
+use crate::backing::BackingTrack;
+use crate::braille::BrailleBook;
+use crate::cache::BookpartCache;
+use crate::changelog::{dates_added, Changes};
+use crate::chordnames::ChordSpellings;
+use crate::check::Severity;
+use crate::composers::ComposerAliases;
+use crate::duplicates::{compare_titles, find_duplicates, Duplicate};
+use crate::errors::TemplaterError;
+use crate::filter::{matches_title, SongFilter};
+use crate::form::{form_summary, verify_form};
+use crate::frontmatter::{self, Defaults};
+use crate::guitar::Voicings;
+use crate::harmony::verify_song;
+use crate::lilypond::SourceMap;
+use crate::license::is_public_domain;
+use crate::lint::{LintIssue, Linter};
+use crate::html::HtmlIndex;
+use crate::lyrics::{LyricsBook, LyricsFormat};
+use crate::melisma::verify_lyrics;
+use crate::metadata::BookMetadata;
+use crate::pickup::verify_pickup;
+use crate::range::verify_range;
+use crate::repeats::verify_repeats;
+use crate::models::*;
+use crate::order::{sort_songs, SortOrder};
+use crate::profiles::LayoutProfile;
+use crate::renderer::{LilyPondBook, LilypondOutput, MidiBook, Outputs, Renderer, SongSheets};
+use crate::setlist::Setlist;
+use crate::songids::{song_key, SongIds};
+use crate::spelling::Spelling;
+use crate::templates::Templates;
+use crate::toc::IndexKind;
+use crate::transpose::Presets;
+use crate::volumes::split_volumes;
+use crate::warnings::{Warning, WarningKind, Warnings};
+use crate::written::WrittenFiles;
+use crate::utils::*;
+
+// builds a `Book` from a songs directory, e.g.
+//
+//     let book = BookBuilder::new()
+//         .songs_dir("./songs/jazz")
+//         .transpose("bb")
+//         .build()?;
+//     book.write()?;
+#[derive(Debug, Clone, Default)]
+pub struct BookBuilder {
+    config: Config,
+    song_names: Option<Vec<String>>,
+    composers: Option<Vec<String>>,
+    filters: Vec<SongFilter>,
+    dry_run: bool,
+}
+
+impl BookBuilder {
+    pub fn new() -> Self {
+        BookBuilder::default()
+    }
+
+    pub fn from_config(config: Config) -> Self {
+        BookBuilder { config, ..Default::default() }
+    }
+
+    // a comma delimited list renders one book per transposition,
+    // e.g. "c,bb,eb"
+    pub fn transpose<S: Into<String>>(mut self, transpose: S) -> Self {
+        self.config.transpose = transpose.into();
+        self
+    }
+
+    // how a transposed song's key is spelled, see `spelling.rs`
+    pub fn spelling(mut self, spelling: Spelling) -> Self {
+        self.config.spelling = spelling;
+        self
+    }
+
+    pub fn songs_dir<P: Into<PathBuf>>(mut self, songs_dir: P) -> Self {
+        self.config.songs_dir = songs_dir.into();
+        self
+    }
+
+    // merged into the book, see `anthology.rs`
+    pub fn more_songs_dir<P: Into<PathBuf>>(mut self, songs_dir: P) -> Self {
+        self.config.more_songs_dirs.push(songs_dir.into());
+        self
+    }
+
+    pub fn duplicate_titles(mut self, duplicate_titles: DuplicateTitles) -> Self {
+        self.config.duplicate_titles = duplicate_titles;
+        self
+    }
+
+    pub fn templates_dir<P: Into<PathBuf>>(mut self, templates_dir: P) -> Self {
+        self.config.templates_dir = templates_dir.into();
+        self
+    }
+
+    // where the books are written, default the current directory
+    pub fn out_dir<P: Into<PathBuf>>(mut self, out_dir: P) -> Self {
+        self.config.out_dir = out_dir.into();
+        self
+    }
+
+    // a filename, `{key}` and `{date}` are filled in
+    pub fn output<P: Into<PathBuf>>(mut self, output: P) -> Self {
+        self.config.output = Some(output.into());
+        self
+    }
+
+    pub fn lyrics(mut self, lyrics: bool) -> Self {
+        self.config.lyrics = lyrics;
+        self
+    }
+
+    // songs with `status: draft` are left out otherwise
+    pub fn include_drafts(mut self, include_drafts: bool) -> Self {
+        self.config.include_drafts = include_drafts;
+        self
+    }
+
+    // leaves out every song that isn't in the public domain this year
+    pub fn public_domain_only(mut self, public_domain_only: bool) -> Self {
+        self.config.public_domain_only = public_domain_only;
+        self
+    }
+
+    // tag every song with the folders it's in below the songs directory
+    pub fn folder_tags(mut self, folder_tags: bool) -> Self {
+        self.config.folder_tags = folder_tags;
+        self
+    }
+
+    // where the stable song numbers are kept, default ./songids.toml
+    pub fn song_ids<P: Into<PathBuf>>(mut self, song_ids: P) -> Self {
+        self.config.song_ids = song_ids.into();
+        self
+    }
+
+    // check every song's lilypond before rendering it
+    pub fn lint_ly(mut self, lint_ly: bool) -> Self {
+        self.config.lint_ly = lint_ly;
+        self
+    }
+
+    // respell every chord in the house style, see `chordnames.rs`
+    pub fn normalize_chords(mut self, normalize_chords: bool) -> Self {
+        self.config.normalize_chords = normalize_chords;
+        self
+    }
+
+    // preferred fret diagrams for a guitar book, default ./voicings.toml
+    pub fn voicings<P: Into<PathBuf>>(mut self, voicings: P) -> Self {
+        self.config.voicings = voicings.into();
+        self
+    }
+
+    // alternate spellings of composers, default ./composers.toml
+    pub fn composer_aliases<P: Into<PathBuf>>(mut self, composer_aliases: P) -> Self {
+        self.config.composer_aliases = composer_aliases.into();
+        self
+    }
+
+    // transposition presets, default ./transpositions.toml
+    pub fn transpositions<P: Into<PathBuf>>(mut self, transpositions: P) -> Self {
+        self.config.transpositions = transpositions.into();
+        self
+    }
+
+    // the title page, see `metadata.rs`
+    pub fn metadata(mut self, metadata: BookMetadata) -> Self {
+        self.config.metadata = metadata;
+        self
+    }
+
+    pub fn indexes(mut self, indexes: Vec<IndexKind>) -> Self {
+        self.config.indexes = indexes;
+        self
+    }
+
+    // e.g. only the chords, see `BookMode`
+    pub fn mode(mut self, mode: BookMode) -> Self {
+        self.config.mode = mode;
+        self
+    }
+
+    // only songs with one of these titles (case insensitive, `*`
+    // and `?` globs allowed) are kept
+    pub fn song_names(mut self, song_names: Vec<String>) -> Self {
+        self.song_names = Some(song_names);
+        self
+    }
+
+    // only songs by one of these composers (case insensitive, or
+    // one of their aliases) are kept
+    pub fn composers(mut self, composers: Vec<String>) -> Self {
+        self.composers = Some(composers);
+        self
+    }
+
+    // every filter has to match for a song to be kept
+    pub fn filter(mut self, filter: SongFilter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    // about the same number of songs per volume
+    pub fn split_volumes(mut self, count: usize) -> Self {
+        self.config.split_volumes = count;
+        self
+    }
+
+    // pages of blank staves at the end of the book
+    pub fn manuscript_pages(mut self, pages: usize) -> Self {
+        self.config.manuscript_pages = pages;
+        self
+    }
+
+    pub fn chord_grid_pages(mut self, pages: usize) -> Self {
+        self.config.chord_grid_pages = pages;
+        self
+    }
+
+    // a volume starts at every one of these letters
+    pub fn split_at(mut self, letters: Vec<String>) -> Self {
+        self.config.split_at = letters;
+        self
+    }
+
+    // also write every song as its own lead sheet, see `SongSheets`
+    pub fn per_song(mut self, per_song: bool) -> Self {
+        self.config.per_song = per_song;
+        self
+    }
+
+    // the order of the songs, see `order.rs`
+    pub fn sort(mut self, sort: SortOrder) -> Self {
+        self.config.sort = sort;
+        self
+    }
+
+    // the paper and staff size, see `profiles.rs`
+    pub fn layout(mut self, layout: LayoutProfile) -> Self {
+        self.config.layout = Some(layout);
+        self
+    }
+
+    // a file with a song title on every line, see `setlist.rs`
+    pub fn setlist<P: Into<PathBuf>>(mut self, setlist: P) -> Self {
+        self.config.setlist = Some(setlist.into());
+        self
+    }
+
+    // a git ref or date, see `changelog.rs`
+    pub fn changes_since<S: Into<String>>(mut self, since: S) -> Self {
+        self.config.changes_since = Some(since.into());
+        self
+    }
+
+    // don't write anything while building, e.g. new song numbers
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn build(self) -> Result<Book, TemplaterError> {
+        let presets = Presets::load(&self.config.transpositions)?;
+        let confs = self
+            .config
+            .transpose
+            .split(',')
+            .map(|t| {
+                let spelling = self.config.spelling_for(t);
+                let transpose_text = TransposeText { spelling, ..presets.transpose_text(t.trim())? };
+                Ok(TemplaterConfig { transpose_text, layout: self.config.layout })
+            })
+            .collect::<Result<Vec<_>, TemplaterError>>()?;
+
+        // only one book fits on stdout
+        if self.config.writes_to_stdout() {
+            let conflicts = [
+                (confs.len() > 1, "more than one transposition"),
+                (self.config.midi, "--midi"),
+                (self.config.html_index, "--html-index"),
+                (self.config.per_song, "--per-song"),
+                (self.config.pdf, "--pdf, try piping it into `lilypond -`"),
+            ];
+            if let Some((_, conflict)) = conflicts.iter().find(|(conflicts, _)| *conflicts) {
+                return Err(TemplaterError::from_str(&format!(
+                    "--output - writes one book to stdout, so it can't be used with {}.",
+                    conflict
+                )));
+            }
+        }
+        if confs.len() > 1 && !self.config.output_has_key() {
+            return Err(TemplaterError::from_str(
+                "An output filename without {key} can't be used with multiple transpositions.",
+            ));
+        }
+
+        // checked before the songs are read
+        let lyrics_format = match self.config.mode {
+            BookMode::Lyrics => {
+                Some(LyricsFormat::from_path(&self.config.output_path(&confs[0].transpose_text, None))?)
+            },
+            _ => None,
+        };
+        if self.config.per_song && lyrics_format.is_some() {
+            return Err(TemplaterError::from_str(
+                "--per-song writes lead sheets, so it can't be used with --mode lyrics.",
+            ));
+        }
+
+        let voicings = match self.config.mode {
+            BookMode::Guitar => Some(Voicings::load(&self.config.voicings)?),
+            _ => None,
+        };
+
+        let setlist = self.config.setlist.as_ref().map(Setlist::load).transpose()?;
+        if setlist.is_some() && (self.config.split_volumes > 1 || !self.config.split_at.is_empty()) {
+            return Err(TemplaterError::from_str("A setlist book can't be split into volumes."));
+        }
+        if self.config.sort != SortOrder::Title && (self.config.split_volumes > 1 || !self.config.split_at.is_empty()) {
+            return Err(TemplaterError::from_str(&format!(
+                "Volumes are split by letter, so a book sorted by {} can't be split into them.",
+                self.config.sort
+            )));
+        }
+
+        let templates = Templates::load(&self.config)?;
+
+        // every broken song is reported, not just the first one. each
+        // songs dir has its own defaults, and the index of the dir is
+        // kept with every song in it
+        let songs_dirs = self.config.songs_dirs();
+        let mut paths = vec![];
+        let mut defaults = vec![];
+        // e.g. broken links, which aren't of any song
+        let mut warnings = Warnings::default();
+        for (source, songs_dir) in songs_dirs.iter().enumerate() {
+            let mut dir_paths = get_files_by_ext(songs_dir, "ly", &mut warnings)?;
+            dir_paths.sort();
+            paths.extend(dir_paths.into_iter().map(|path| (source, path)));
+            defaults.push(Defaults::load(songs_dir)?);
+        }
+        let linter = match self.config.lint_ly {
+            true => Some(Linter::new(&self.config.includes_dir, &mut warnings)?),
+            false => None,
+        };
+        let (songs, errors): (Vec<Vec<(usize, ReadSong)>>, Vec<TemplaterError>) = paths
+            .par_iter()
+            .map(|(source, path)| {
+                let include_lyrics = self.config.lyrics || lyrics_format.is_some();
+                let (include_drafts, defaults) = (self.config.include_drafts, &defaults[*source]);
+                let songs = read_songs(path, include_lyrics, include_drafts, defaults, &presets, linter.as_ref())?;
+                Ok(songs.into_iter().map(|song| (*source, song)).collect())
+            })
+            .partition_map(|result| match result {
+                Ok(songs) => Either::Left(songs),
+                Err(e) => Either::Right(e),
+            });
+
+        if !errors.is_empty() {
+            return Err(TemplaterError::collect(errors));
+        }
+        let songs: Vec<(usize, ReadSong)> = songs.into_iter().flatten().collect();
+        let drafts = songs.iter().filter(|(_, song)| song.is_none()).count();
+        let songs: Vec<(usize, (Song, Vec<LintIssue>))> =
+            songs.into_iter().filter_map(|(source, song)| Some((source, song?))).collect();
+        log!("[info]: total songs found: {}", songs.len());
+        if drafts > 0 {
+            log!("[info]: left out {} drafts, --include-drafts adds them", drafts);
+        }
+
+        // of every song, even the ones the filters leave out later
+        let mut song_issues = Warnings::default();
+        let songs: Vec<(usize, Song)> = songs
+            .into_iter()
+            .map(|(source, (song, lint_warnings))| {
+                song_issues.extend(lint_warnings.into_iter().map(|issue| Warning {
+                    line: Some(issue.line),
+                    ..Warning::song(WarningKind::Lint, &song.path, issue.message)
+                }));
+                (source, song)
+            })
+            .collect();
+
+        let songs = resolve_duplicates(songs, self.config.duplicate_titles);
+        let collections: Vec<String> = songs_dirs.iter().map(|dir| collection_name(dir)).collect();
+        let mut songs: Vec<Song> = songs
+            .into_iter()
+            .map(|(source, mut song)| {
+                // e.g. `songs/bossa/` is the songs tagged bossa
+                if self.config.folder_tags {
+                    for tag in folder_tags(songs_dirs[source], &song.path) {
+                        if !song.tags.iter().any(|existing| existing.eq_ignore_ascii_case(&tag)) {
+                            song.tags.push(tag);
+                        }
+                    }
+                }
+                if songs_dirs.len() > 1 {
+                    song.collection = Some(collections[source].clone());
+                }
+                song
+            })
+            .collect();
+
+        check_duplicates(&songs, &mut song_issues)?;
+
+        let composer_aliases = ComposerAliases::load(&self.config.composer_aliases)?;
+        for song in &mut songs {
+            composer_aliases.apply(song);
+        }
+        if self.config.normalize_chords {
+            let spellings = ChordSpellings::new(&self.config.chord_spellings);
+            for song in &mut songs {
+                let respellings = spellings.apply_to(song);
+                if !respellings.is_empty() {
+                    log!("[info]: {}: respelled {}", song.path.display(), respellings);
+                }
+            }
+        }
+
+        if self.config.form_summary {
+            for song in &mut songs {
+                song.form_summary = form_summary(song);
+            }
+        }
+
+        // the songs the filters below leave in the book
+        let composers: Option<Vec<String>> = self
+            .composers
+            .as_ref()
+            .map(|composers| composers.iter().map(|s| composer_aliases.canonical(s).to_lowercase()).collect());
+        let selected = |song: &Song| {
+            self.song_names.as_ref().is_none_or(|names| names.iter().any(|name| matches_title(name, &song.title)))
+                && composers.as_ref().is_none_or(|composers| composers.contains(&song.composer.to_lowercase()))
+                && self.filters.iter().all(|filter| filter.matches(song))
+        };
+        let year = this_year();
+        let in_book = |song: &Song| selected(song) && (!self.config.public_domain_only || is_public_domain(song, year));
+
+        // only for the songs in the book, so `--strict` doesn't fail on
+        // the ones that are left out
+        let paths: Vec<&Path> = songs.iter().filter(|song| in_book(song)).map(|song| song.path.as_path()).collect();
+        let in_book_path = |warning: &&Warning| warning.path.as_deref().is_some_and(|path| paths.contains(&path));
+        warnings.extend(song_issues.iter().filter(in_book_path).cloned());
+        for song in songs.iter().filter(|song| in_book(song)) {
+            warnings.extend(song_warnings(song));
+        }
+        // the notes every book's instrument can't play
+        for (transpose, conf) in self.config.transpose.split(',').zip(&confs) {
+            let Some(range) = self.config.range_for(transpose) else { continue };
+            for song in songs.iter().filter(|song| in_book(song)) {
+                warnings.extend(
+                    verify_range(song, &conf.transpose_text, &range)
+                        .into_iter()
+                        .map(|message| Warning::song(WarningKind::Range, &song.path, message)),
+                );
+            }
+        }
+        // before anything is written, e.g. new song numbers
+        let warnings = warnings.check(self.config.strict)?;
+
+        // numbered before filtering, so a smaller book keeps the
+        // same numbers
+        songs.sort_by(|a, b| a.cmp_book_order(b));
+        number_songs(&mut songs, &self.config.song_ids, !self.dry_run)?;
+
+        songs.retain(|song| selected(song));
+
+        if self.config.public_domain_only {
+            let before = songs.len();
+            songs.retain(|song| is_public_domain(song, year));
+            log!("[info]: left out {} songs that aren't in the public domain", before - songs.len());
+        }
+
+        if self.config.sort == SortOrder::DateAdded {
+            let mut dates = BTreeMap::new();
+            for songs_dir in &songs_dirs {
+                dates.extend(dates_added(songs_dir)?);
+            }
+            for song in &mut songs {
+                song.added = dates.get(&song.path).cloned();
+            }
+        }
+        sort_songs(&mut songs, self.config.sort);
+
+        // in the order they're played, not alphabetical
+        if let Some(setlist) = &setlist {
+            let order = setlist.select(&songs)?;
+            let mut selected: Vec<Option<Song>> = songs.into_iter().map(Some).collect();
+            songs = order.into_iter().filter_map(|i| selected[i].take()).collect();
+        }
+
+        if songs.is_empty() {
+            return Err(TemplaterError::from_str(
+                "0 songs were selected. Check your filters (--only | --song-names | --composers | --filter).",
+            ));
+        }
+
+        log!("[info]: songs remaining after filtering: {}", songs.len());
+
+        let volumes = split_volumes(&songs, self.config.split_volumes, &self.config.split_at)?;
+        if volumes.len() > 1 && self.config.writes_to_stdout() {
+            let message = "--output - writes one book to stdout, so it can't be split into volumes.";
+            return Err(TemplaterError::from_str(message));
+        }
+        if volumes.len() > 1 {
+            log!("[info]: splitting the book into {} volumes", volumes.len());
+        }
+
+        let changes = match &self.config.changes_since {
+            Some(since) => {
+                let changes = Changes::from_dirs(&songs_dirs, since)?;
+                let (added, modified) = changes.songs(&songs);
+                log!("[info]: {} songs were added and {} changed since {}", added.len(), modified.len(), since);
+                Some(changes)
+            },
+            None => None,
+        };
+
+        let cache = self.config.cache.then(|| BookpartCache::new(&self.config.cache_dir, !self.dry_run));
+
+        Ok(Book {
+            config: self.config,
+            confs,
+            templates,
+            presets,
+            songs,
+            volumes,
+            cache,
+            warnings,
+            setlist,
+            changes,
+            voicings,
+        })
+    }
+}
+
+// a song and its lint warnings, none for a draft that's left out
+type ReadSong = Option<(Song, Vec<LintIssue>)>;
+
+// every song in the file, and its lint warnings. lint errors fail
+// the file. none for a draft, unless `include_drafts`. drafts
+// aren't linted
+fn read_songs(
+    path: &Path,
+    include_lyrics: bool,
+    include_drafts: bool,
+    defaults: &Defaults,
+    presets: &Presets,
+    linter: Option<&Linter>,
+) -> Result<Vec<ReadSong>, TemplaterError> {
+    log!("Handling {}", path.display());
+    let input = normalize_line_endings(read_file(path)?);
+    let songs = Song::parse_file(path, &input, include_lyrics, defaults, presets)?;
+
+    let mut read = vec![];
+    let mut errors = vec![];
+    for (song, (first_line, input)) in songs.into_iter().zip(frontmatter::split_songs(&input)) {
+        if song.draft && !include_drafts {
+            read.push(None);
+            continue;
+        }
+
+        let (lint_errors, warnings): (Vec<LintIssue>, Vec<LintIssue>) = linter
+            .map(|linter| linter.lint(input))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|issue| LintIssue { line: issue.line + first_line - 1, ..issue })
+            .partition(|issue| issue.severity == Severity::Error);
+        errors.extend(
+            lint_errors
+                .into_iter()
+                .map(|issue| TemplaterError::Lint { path: path.to_path_buf(), line: issue.line, message: issue.message }),
+        );
+        errors.extend(
+            verify_repeats(&song)
+                .errors
+                .into_iter()
+                .map(|message| TemplaterError::from_str(&format!("{}: {}", path.display(), message))),
+        );
+        read.push(Some((song, warnings)));
+    }
+
+    match errors.is_empty() {
+        true => Ok(read),
+        false => Err(TemplaterError::collect(errors)),
+    }
+}
+
+// what's accepted, but probably a mistake
+fn song_warnings(song: &Song) -> Vec<Warning> {
+    let mut warnings: Vec<Warning> = song
+        .unknown_keys
+        .iter()
+        .map(|key| Warning::song(WarningKind::UnknownKey, &song.path, format!("unknown frontmatter key '{}'", key)))
+        .collect();
+
+    if song.tempo.is_none() {
+        warnings.push(Warning::song(WarningKind::MissingBpm, &song.path, "missing bpm"));
+    }
+
+    warnings.extend(verify_song(song).into_iter().map(|message| Warning::song(WarningKind::Harmony, &song.path, message)));
+    warnings.extend(verify_form(song).map(|message| Warning::song(WarningKind::Form, &song.path, message)));
+    warnings.extend(verify_pickup(song).into_iter().map(|message| Warning::song(WarningKind::Pickup, &song.path, message)));
+    let repeats = verify_repeats(song).warnings;
+    warnings.extend(repeats.into_iter().map(|message| Warning::song(WarningKind::Repeat, &song.path, message)));
+    warnings.extend(verify_lyrics(song).into_iter().map(|message| Warning::song(WarningKind::Lyrics, &song.path, message)));
+    let backing_track = song.backing_track.as_ref().and_then(BackingTrack::verify);
+    warnings.extend(backing_track.map(|message| Warning::song(WarningKind::BackingTrack, &song.path, message)));
+    warnings
+}
+
+// the same title twice fails the build, similar ones are a warning
+fn check_duplicates(songs: &[Song], warnings: &mut Warnings) -> Result<(), TemplaterError> {
+    let titles: Vec<&str> = songs.iter().map(|song| song.title.as_str()).collect();
+    let mut errors = vec![];
+
+    for (i, j, duplicate) in find_duplicates(&titles) {
+        let (a, b) = (&songs[i], &songs[j]);
+        match duplicate {
+            Duplicate::Same => errors.push(TemplaterError::from_str(&format!(
+                "{}: duplicate title '{}', also used by {}",
+                a.path.display(),
+                a.title,
+                b.path.display()
+            ))),
+            Duplicate::Similar => warnings.push(Warning::song(
+                WarningKind::SimilarTitle,
+                &a.path,
+                format!("title '{}' is very similar to '{}' in {}", a.title, b.title, b.path.display()),
+            )),
+        }
+    }
+
+    // e.g. "Café" and "Cafe", which aren't the same title but would
+    // both be `\label #'song-cafe`, so the indices couldn't tell them apart
+    let mut labels: BTreeMap<String, &Song> = BTreeMap::new();
+    for song in songs {
+        match labels.get(&song.label()) {
+            Some(other) if compare_titles(&other.title, &song.title) != Some(Duplicate::Same) => {
+                errors.push(TemplaterError::from_str(&format!(
+                    "{}: '{}' would have the same label as '{}' in {}, rename one of them",
+                    song.path.display(),
+                    song.title,
+                    other.title,
+                    other.path.display()
+                )));
+            },
+            Some(_) => {},
+            None => {
+                labels.insert(song.label(), song);
+            },
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(TemplaterError::collect(errors)),
+    }
+}
+
+fn number_songs(songs: &mut [Song], path: &Path, save: bool) -> Result<(), TemplaterError> {
+    let mut song_ids = SongIds::load(path)?;
+
+    let keys: Vec<String> = songs.iter().map(song_key).collect();
+    if song_ids.assign(keys.iter().map(String::as_str)) {
+        match save {
+            true => {
+                log!("[info]: numbering new songs in {}", path.display());
+                song_ids.save(path)?;
+            },
+            false => log!("[info]: new songs would be numbered in {}", path.display()),
+        }
+    }
+
+    for (song, key) in songs.iter_mut().zip(&keys) {
+        song.number = song_ids.get(key).unwrap_or_default();
+    }
+
+    Ok(())
+}
+
+// songs are parsed once and then rendered for every transposition
+pub struct Book {
+    pub config: Config,
+    pub confs: Vec<TemplaterConfig>,
+    // shared by every transposition, see `templates.rs`
+    pub templates: Templates,
+    // `transpositions.toml`, for `--transpose` and `transpose_override`
+    pub presets: Presets,
+    pub songs: Vec<Song>,
+    // ranges of `songs`, more than one if the book is split
+    pub volumes: Vec<Range<usize>>,
+    pub cache: Option<BookpartCache>,
+    // printed once the book is written, see `warnings.rs`
+    pub warnings: Warnings,
+    // the songs are in its order, see `setlist.rs`
+    pub setlist: Option<Setlist>,
+    // for the "Recent Additions" page, see `changelog.rs`
+    pub changes: Option<Changes>,
+    // only in a guitar book, see `guitar.rs`
+    pub voicings: Option<Voicings>,
+}
+
+impl Book {
+    // `volume` is numbered from 1, and only given for a split book
+    pub fn output_path(&self, conf: &TemplaterConfig, volume: Option<usize>) -> PathBuf {
+        self.config.output_path(&conf.transpose_text, volume)
+    }
+
+    // the book's own renderer, see `BookMode`
+    pub fn renderer(&self) -> Box<dyn Renderer> {
+        match self.config.mode {
+            BookMode::Full | BookMode::ChordsOnly | BookMode::Guitar | BookMode::Numbers => Box::new(LilyPondBook),
+            BookMode::Lyrics => Box::new(LyricsBook),
+            BookMode::Braille => Box::new(BrailleBook),
+        }
+    }
+
+    // the book, and every extra output that's enabled
+    pub fn renderers(&self) -> Vec<Box<dyn Renderer>> {
+        let mut renderers = vec![self.renderer()];
+        if self.config.midi {
+            renderers.push(Box::new(MidiBook));
+        }
+        if self.config.html_index {
+            renderers.push(Box::new(HtmlIndex));
+        }
+        if self.config.per_song {
+            renderers.push(Box::new(SongSheets));
+        }
+
+        renderers
+    }
+
+    // every file `renderer` writes, see `Outputs`
+    pub fn outputs(&self, renderer: &dyn Renderer) -> Vec<Output<'_>> {
+        let volumes: Vec<Option<usize>> = match self.volumes.len() {
+            1 => vec![None],
+            n => (1..=n).map(Some).collect(),
+        };
+        let output = |conf, volume, song| Output { conf, volume, song };
+
+        match renderer.outputs() {
+            Outputs::PerTransposition => self
+                .confs
+                .iter()
+                .flat_map(|conf| volumes.iter().map(move |volume| output(conf, *volume, None)))
+                .collect(),
+            Outputs::PerVolume => volumes.iter().map(|volume| output(&self.confs[0], *volume, None)).collect(),
+            Outputs::Single => vec![output(&self.confs[0], None, None)],
+            Outputs::PerSong => self
+                .confs
+                .iter()
+                .flat_map(|conf| (0..self.songs.len()).map(move |song| output(conf, None, Some(song))))
+                .collect(),
+        }
+    }
+
+    // where `output` is written
+    pub fn output_file(&self, renderer: &dyn Renderer, output: &Output) -> Result<PathBuf, TemplaterError> {
+        let path = renderer.output_path(&self.config, output.conf, output.volume)?;
+        Ok(match output.song {
+            Some(i) => renderer.song_path(path, &self.songs[i]),
+            None => path,
+        })
+    }
+
+    // the whole book. returns which lines of the output came from which song
+    pub fn render<W: Write>(&self, out: &mut W, conf: &TemplaterConfig) -> Result<SourceMap, TemplaterError> {
+        self.render_with(self.renderer().as_ref(), out, &Output { conf, volume: None, song: None })
+    }
+
+    // one volume of a split book, with its own intro and ToC
+    pub fn render_volume<W: Write>(
+        &self,
+        out: &mut W,
+        conf: &TemplaterConfig,
+        volume: usize,
+    ) -> Result<SourceMap, TemplaterError> {
+        self.render_with(self.renderer().as_ref(), out, &Output { conf, volume: Some(volume), song: None })
+    }
+
+    // the midi book, see `MidiBook`
+    pub fn render_midi<W: Write>(&self, out: &mut W) -> Result<SourceMap, TemplaterError> {
+        self.render_with(&MidiBook, out, &Output { conf: &self.confs[0], volume: None, song: None })
+    }
+
+    // one song's lead sheet, see `SongSheets`
+    pub fn render_sheet<W: Write>(
+        &self,
+        out: &mut W,
+        conf: &TemplaterConfig,
+        song: usize,
+    ) -> Result<SourceMap, TemplaterError> {
+        self.render_with(&SongSheets, out, &Output { conf, volume: None, song: Some(song) })
+    }
+
+    fn render_with(
+        &self,
+        renderer: &dyn Renderer,
+        out: &mut dyn Write,
+        output: &Output,
+    ) -> Result<SourceMap, TemplaterError> {
+        let songs = match (output.song, output.volume) {
+            (Some(song), _) => self.songs.get(song..=song).ok_or_else(|| {
+                TemplaterError::from_str(&format!("The book only has {} songs.", self.songs.len()))
+            })?,
+            (None, Some(volume)) => {
+                let songs = self.volumes.get(volume.wrapping_sub(1)).ok_or_else(|| {
+                    TemplaterError::from_str(&format!("The book only has {} volumes.", self.volumes.len()))
+                })?;
+                &self.songs[songs.clone()]
+            },
+            (None, None) => &self.songs[..],
+        };
+
+        renderer.render(self, out, output.conf, songs, output.volume)
+    }
+
+    // writes every renderer's files, returning what was written.
+    // none of them are replaced if any of them can't be written.
+    pub fn write(&self) -> Result<Vec<BookOutput>, TemplaterError> {
+        if self.config.writes_to_stdout() {
+            return self.write_to_stdout().map(|output| vec![output]);
+        }
+
+        let renderers = self.renderers();
+        let mut planned = vec![];
+        for renderer in &renderers {
+            for output in self.outputs(renderer.as_ref()) {
+                planned.push((renderer.as_ref(), self.output_file(renderer.as_ref(), &output)?, output));
+            }
+        }
+
+        self.write_planned(planned)
+    }
+
+    // one song's lead sheet in every transposition, like `--per-song`
+    // writes them, e.g. for `templater browse`
+    pub fn write_song(&self, song: usize) -> Result<Vec<BookOutput>, TemplaterError> {
+        if song >= self.songs.len() {
+            return Err(TemplaterError::from_str(&format!("The book only has {} songs.", self.songs.len())));
+        }
+
+        let mut planned: Vec<(&dyn Renderer, PathBuf, Output)> = vec![];
+        for conf in &self.confs {
+            let output = Output { conf, volume: None, song: Some(song) };
+            planned.push((&SongSheets, self.output_file(&SongSheets, &output)?, output));
+        }
+
+        self.write_planned(planned)
+    }
+
+    fn write_planned(&self, planned: Vec<(&dyn Renderer, PathBuf, Output)>) -> Result<Vec<BookOutput>, TemplaterError> {
+        let mut written = WrittenFiles::load(&self.config.cache_dir)?;
+        let paths: Vec<&Path> = planned.iter().map(|(_, path, _)| path.as_path()).collect();
+        check_outputs(&paths, &written, self.config.force)?;
+
+        let lilypond_outputs: Vec<_> = planned.iter().map(|(renderer, _, _)| renderer.lilypond_output()).collect();
+        let files = planned
+            .iter()
+            .map(|(renderer, path, output)| (path.clone(), move |out: &mut BufWriter<File>| {
+                self.render_with(*renderer, out, output)
+            }))
+            .collect();
+        let source_maps = write_outputs(files, |path| {
+            // saved after every file, so a failed build still knows
+            // which ones it wrote
+            let contents = fs::read(path).map_err(|e| TemplaterError::file(path, e))?;
+            written.record(path, &contents);
+            written.save(&self.config.cache_dir)
+        })?;
+
+        let outputs = planned
+            .into_iter()
+            .zip(source_maps)
+            .zip(lilypond_outputs)
+            .map(|(((_, path, _), source_map), lilypond_output)| BookOutput { path, source_map, lilypond_output })
+            .collect();
+        Ok(outputs)
+    }
+}
+
+impl Book {
+    // `--output -`, the only output. what was written to stdout
+    // isn't known afterwards, so it isn't in `written.toml`
+    fn write_to_stdout(&self) -> Result<BookOutput, TemplaterError> {
+        let renderer = self.renderer();
+        let output = Output { conf: &self.confs[0], volume: None, song: None };
+
+        let mut out = BufWriter::new(io::stdout().lock());
+        let source_map = match self.render_with(renderer.as_ref(), &mut out, &output).and_then(|source_map| {
+            out.flush()?;
+            Ok(source_map)
+        }) {
+            // e.g. piped into `head`, which doesn't want the rest
+            Err(TemplaterError::IO(e)) if e.kind() == io::ErrorKind::BrokenPipe => SourceMap::default(),
+            result => result?,
+        };
+
+        Ok(BookOutput { path: PathBuf::from(STDOUT), source_map, lilypond_output: renderer.lilypond_output() })
+    }
+}
+
+// two outputs can't have the same path, and a book that was edited
+// by hand is only overwritten with `--force`
+fn check_outputs(paths: &[&Path], written: &WrittenFiles, force: bool) -> Result<(), TemplaterError> {
+    let mut errors = vec![];
+
+    for (i, path) in paths.iter().enumerate() {
+        // reported once, at the second one
+        let earlier = paths[..i].iter().filter(|p| *p == path).count();
+        if earlier == 1 {
+            errors.push(TemplaterError::from_str(&format!(
+                "{} would be written more than once. Check --output, e.g. for {{key}}.",
+                path.display()
+            )));
+        }
+        if earlier > 0 {
+            continue;
+        }
+
+        if !force && written.edited(path)? {
+            errors.push(TemplaterError::from_str(&format!(
+                "{} was changed since it was written. --force overwrites it.",
+                path.display()
+            )));
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(TemplaterError::collect(errors)),
+    }
+}
+
+// one file a renderer writes, see `Outputs`
+#[derive(Debug, Clone, Copy)]
+pub struct Output<'a> {
+    pub conf: &'a TemplaterConfig,
+    // only for a volume of a split book
+    pub volume: Option<usize>,
+    // only for `Outputs::PerSong`, the song's index in `songs`
+    pub song: Option<usize>,
+}
+
+// a written file, and what lilypond makes of it
+#[derive(Debug)]
+pub struct BookOutput {
+    pub path: PathBuf,
+    pub source_map: SourceMap,
+    pub lilypond_output: Option<LilypondOutput>,
+}
+
+// every file is rendered to a temporary file next to it, and they're
+// only renamed into place once all of them were written, so a failed
+// build (e.g. a full disk) leaves the old book as it was. `renamed`
+// is called after each rename.
+fn write_outputs<F, R>(files: Vec<(PathBuf, F)>, mut renamed: R) -> Result<Vec<SourceMap>, TemplaterError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<SourceMap, TemplaterError>,
+    R: FnMut(&Path) -> Result<(), TemplaterError>,
+{
+    let mut staged = vec![];
+    for (path, render) in files {
+        match stage_output(&path, render) {
+            Ok((tmp, source_map)) => staged.push((tmp, path, source_map)),
+            Err(e) => {
+                for (tmp, _, _) in &staged {
+                    let _ = fs::remove_file(tmp);
+                }
+                return Err(e);
+            },
+        }
+    }
+
+    let mut source_maps = vec![];
+    let mut renames = staged.into_iter();
+    while let Some((tmp, path, source_map)) = renames.next() {
+        let result = fs::rename(&tmp, &path).map_err(|e| TemplaterError::file(&path, e));
+        if let Err(e) = result.and_then(|_| renamed(&path)) {
+            let _ = fs::remove_file(&tmp);
+            for (tmp, _, _) in renames {
+                let _ = fs::remove_file(tmp);
+            }
+            return Err(e);
+        }
+        source_maps.push(source_map);
+    }
+
+    Ok(source_maps)
+}
+
+// renders `path` to its temporary file, see `write_outputs`. it's
+// buffered, so a song isn't many small writes.
+fn stage_output<F>(path: &Path, render: F) -> Result<(PathBuf, SourceMap), TemplaterError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<SourceMap, TemplaterError>,
+{
+    log!("[info]: writing {}", path.display());
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| TemplaterError::file(dir, e))?;
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    let mut out = BufWriter::new(File::create(&tmp).map_err(|e| TemplaterError::file(&tmp, e))?);
+    let rendered = render(&mut out).and_then(|source_map| {
+        // the rest of the buffer, which can fail like any other write
+        let outfile = out.into_inner().map_err(|e| e.into_error())?;
+        outfile.sync_all()?;
+        Ok(source_map)
+    });
+
+    match rendered {
+        Ok(source_map) => Ok((tmp, source_map)),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(match e {
+                TemplaterError::IO(e) => TemplaterError::file(path, e),
+                e => e,
+            })
+        },
+    }
+}
+
+impl Book {
+    // renders every book to memory, returning a unified diff
+    // against the file on disk. the diff is empty if nothing changed.
+    pub fn diff(&self) -> Result<Vec<(PathBuf, String)>, TemplaterError> {
+        let mut diffs = vec![];
+
+        for renderer in self.renderers() {
+            for output in self.outputs(renderer.as_ref()) {
+                let path = self.output_file(renderer.as_ref(), &output)?;
+                let mut rendered = vec![];
+                self.render_with(renderer.as_ref(), &mut rendered, &output)?;
+                let rendered = String::from_utf8_lossy(&rendered);
+
+                let existing = match fs::read_to_string(&path) {
+                    Ok(existing) => existing,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                    Err(e) => return Err(TemplaterError::file(path, e)),
+                };
+
+                let name = path.display().to_string();
+                let diff = TextDiff::from_lines(&existing, &rendered)
+                    .unified_diff()
+                    .context_radius(3)
+                    .header(&name, &name)
+                    .to_string();
+                diffs.push((path, diff));
+            }
+        }
+
+        Ok(diffs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_refuses_to_write_a_file_twice() {
+        let written = WrittenFiles::default();
+        let paths = [Path::new("./index.html"), Path::new("./openbook-Bb.ly"), Path::new("./index.html")];
+
+        let error = check_outputs(&paths, &written, false).unwrap_err().to_string();
+        assert!(error.contains("./index.html would be written more than once"));
+        assert!(check_outputs(&paths[..2], &written, false).is_ok());
+    }
+
+    #[test]
+    fn it_keeps_the_old_file_when_a_write_fails() {
+        let dir = std::env::temp_dir().join(format!("templater-write-{}", std::process::id()));
+        let path = dir.join("openbook-Bb.ly");
+        let write = |out: &mut BufWriter<File>| {
+            write!(out, "\\version \"2.24.0\"")?;
+            Ok(SourceMap::default())
+        };
+        write_outputs(vec![(path.clone(), write)], |_| Ok(())).unwrap();
+
+        // e.g. the disk filling up partway through the book
+        let fail = |out: &mut BufWriter<File>| {
+            write!(out, "\\bookpart {{")?;
+            Err(io::Error::new(io::ErrorKind::StorageFull, "no space left on device").into())
+        };
+        let error = write_outputs(vec![(path.clone(), fail)], |_| Ok(())).unwrap_err();
+        assert!(matches!(&error, TemplaterError::File { path: failed, .. } if *failed == path), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\\version \"2.24.0\"");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_keeps_every_old_file_when_one_write_fails() {
+        let dir = std::env::temp_dir().join(format!("templater-write-all-{}", std::process::id()));
+        let paths = [dir.join("openbook-C.ly"), dir.join("openbook-Bb.ly")];
+        let write = |text: &'static str| {
+            move |out: &mut BufWriter<File>| {
+                write!(out, "{}", text)?;
+                Ok(SourceMap::default())
+            }
+        };
+        let files = paths.iter().map(|path| (path.clone(), write("old"))).collect();
+        write_outputs(files, |_| Ok(())).unwrap();
+
+        // the first one is written fine, the second one isn't
+        let files = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| (path.clone(), move |out: &mut BufWriter<File>| {
+                write!(out, "new")?;
+                match i {
+                    0 => Ok(SourceMap::default()),
+                    _ => Err(io::Error::new(io::ErrorKind::StorageFull, "no space left on device").into()),
+                }
+            }))
+            .collect();
+        let mut renamed = vec![];
+        let error = write_outputs(files, |path| {
+            renamed.push(path.to_path_buf());
+            Ok(())
+        })
+        .unwrap_err();
+        assert!(matches!(&error, TemplaterError::File { path: failed, .. } if *failed == paths[1]), "{}", error);
+        assert!(renamed.is_empty());
+        for path in &paths {
+            assert_eq!(fs::read_to_string(path).unwrap(), "old");
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,486 @@
+// `templater diff v1.0` (or `diff old.ly new.ly`): what changed
+// between two books song by song, for release notes. either side is
+// a book that was built, songs dirs, or a git revision of the songs
+// dirs, and the new side is the songs dirs if there's none. songs are
+// the same song if they're at the same path (or have the same label
+// in a book), then if they have the same title, then if their music
+// is the same, so a song that was moved and retitled is still found.
+// a song that doesn't parse (e.g. at an old revision) is a warning,
+// and the rest are still diffed.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::changelog::git;
+use crate::errors::TemplaterError;
+use crate::frontmatter::{Defaults, DEFAULTS_FILE};
+use crate::models::Song;
+use crate::music::{tokenize, Token};
+use crate::transpose::Presets;
+use crate::utils::{get_files_by_ext, is_editor_file, read_file, slugify, IgnoreFile, IGNORE_FILE};
+use crate::warnings::{Warning, WarningKind, Warnings};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Side {
+    Book(PathBuf),
+    Dirs(Vec<PathBuf>),
+    Revision(String),
+}
+
+impl Side {
+    // a file is a book and a folder is a songs dir
+    pub fn parse(arg: &str) -> Self {
+        let path = PathBuf::from(arg);
+        if path.is_file() {
+            Side::Book(path)
+        } else if path.is_dir() {
+            Side::Dirs(vec![path])
+        } else {
+            Side::Revision(arg.to_string())
+        }
+    }
+
+    // a revision is of every one of `songs_dirs`. `presets` are for the
+    // songs' `transpose_override`s
+    pub fn songs<P: AsRef<Path>>(
+        &self,
+        songs_dirs: &[P],
+        presets: &Presets,
+        warnings: &mut Warnings,
+    ) -> Result<Vec<SongText>, TemplaterError> {
+        let mut songs = vec![];
+        match self {
+            Side::Book(path) => songs = book_songs(&read_file(path)?),
+            Side::Dirs(dirs) => {
+                for dir in dirs {
+                    songs.extend(dir_songs(dir, dirs.len() > 1, presets, warnings)?);
+                }
+            },
+            Side::Revision(revision) => {
+                for songs_dir in songs_dirs {
+                    let songs_dir = songs_dir.as_ref();
+                    songs.extend(revision_songs(songs_dir, songs_dirs.len() > 1, revision, presets, warnings)?);
+                }
+            },
+        }
+
+        Ok(songs)
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Book(path) => write!(f, "{}", path.display()),
+            Side::Dirs(dirs) => {
+                write!(f, "{}", dirs.iter().map(|dir| dir.display().to_string()).collect::<Vec<_>>().join(", "))
+            },
+            Side::Revision(revision) => write!(f, "{}", revision),
+        }
+    }
+}
+
+// what's compared of a song, with the music's comments and spacing
+// left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongText {
+    // its path below the songs dir (with the dir, if there are more),
+    // or its label in a book
+    pub key: String,
+    pub title: String,
+    pub composer: String,
+    pub chords: String,
+    pub melody: String,
+    // none in a book without lyrics, so they aren't compared
+    pub lyrics: Option<String>,
+}
+
+impl SongText {
+    fn from_song(key: String, song: &Song) -> Self {
+        let verses = song.lyrics.iter().map(|verse| match &verse.stanza {
+            Some(stanza) => format!("\\set stanza = \"{}\" {}", stanza, verse.text),
+            None => verse.text.clone(),
+        });
+
+        SongText {
+            key,
+            title: song.title.clone(),
+            composer: song.composer.clone(),
+            chords: normalize(&song.chords),
+            melody: normalize(&song.voices.iter().map(|voice| voice.notes.as_str()).collect::<Vec<_>>().join(" ")),
+            lyrics: Some(normalize(&verses.collect::<Vec<_>>().join(" "))),
+        }
+    }
+}
+
+fn normalize(music: &str) -> String {
+    let lines = music.lines().map(|line| line.split('%').next().unwrap_or_default());
+    lines.flat_map(str::split_whitespace).collect::<Vec<_>>().join(" ")
+}
+
+// what's between the braces after `start`, e.g. the music of a
+// `\transpose c d { ... }`
+fn braced<'a>(text: &'a str, start: &str) -> Option<&'a str> {
+    let rest = &text[text.find(start)? + start.len()..];
+    let (mut depth, mut open) = (0, 0);
+    for (token, _, end) in tokenize(rest) {
+        match token {
+            Token::Open => {
+                if depth == 0 {
+                    open = end;
+                }
+                depth += 1;
+            },
+            Token::Close if depth == 0 => return None,
+            Token::Close if depth == 1 => return Some(&rest[open..end - 1]),
+            Token::Close => depth -= 1,
+            _ => {},
+        }
+    }
+
+    None
+}
+
+// every block that starts with `start`
+fn blocks<'a>(text: &'a str, start: &str) -> Vec<&'a str> {
+    text.match_indices(start).filter_map(|(i, _)| braced(&text[i..], start)).collect()
+}
+
+// the songs of a book, from their `\bookpart`s. the ToC entry is
+// "<number>. <title> - <composer>".
+pub fn book_songs(book: &str) -> Vec<SongText> {
+    let has_lyrics = book.contains("\\new Lyrics=");
+    let mut songs = vec![];
+    for part in book.split("\\bookpart {") {
+        let Some(entry) = part.lines().find_map(|line| line.trim().strip_prefix("\\tocItem \\markup \"")) else {
+            continue;
+        };
+        let entry = entry.strip_suffix('"').unwrap_or(entry);
+        let entry = entry.split_once(". ").map_or(entry, |(_, entry)| entry);
+        let (title, composer) = entry.rsplit_once(" - ").unwrap_or((entry, ""));
+        let key = part.lines().find_map(|line| line.trim().strip_prefix("\\label #'")).unwrap_or(title);
+
+        // the music is in the templates' `\transpose`, a voice's after
+        // its settings
+        let music = |text: &str| braced(text, "\\transpose").map(normalize).unwrap_or_default();
+        let chords = part.find("\\new ChordNames=\"Chords\"").map(|i| music(&part[i..])).unwrap_or_default();
+        let melody: Vec<String> = part
+            .match_indices("\\new Voice=")
+            .map(|(i, _)| {
+                let voice = music(&part[i..]);
+                let settings = "\\set Staff.ottavationMarkups = #ottavation-ordinals ";
+                let voice = voice.strip_prefix(settings).unwrap_or(&voice);
+                match voice.strip_prefix("\\clef ") {
+                    Some(clef) => clef.split_once(' ').map_or("", |(_, voice)| voice).to_string(),
+                    None => voice.to_string(),
+                }
+            })
+            .collect();
+        let lyrics = blocks(part, "\\new Lyrics=").join(" ");
+
+        songs.push(SongText {
+            key: key.to_string(),
+            title: title.to_string(),
+            composer: composer.to_string(),
+            chords,
+            melody: melody.join(" "),
+            lyrics: has_lyrics.then(|| normalize(&lyrics)),
+        });
+    }
+
+    songs
+}
+
+// `with_dir` keys the songs by their path with `songs_dir`, to tell
+// the same path in different songs dirs apart
+fn dir_songs(
+    songs_dir: &Path,
+    with_dir: bool,
+    presets: &Presets,
+    warnings: &mut Warnings,
+) -> Result<Vec<SongText>, TemplaterError> {
+    let mut paths = get_files_by_ext(&songs_dir, "ly", warnings)?;
+    paths.sort();
+    let defaults = Defaults::load(songs_dir)?;
+
+    let mut songs = vec![];
+    for path in paths {
+        let key = match with_dir {
+            true => path.display().to_string(),
+            false => path.strip_prefix(songs_dir).unwrap_or(&path).display().to_string(),
+        };
+        songs.extend(file_songs(key, &path, &read_file(&path)?, &defaults, presets, warnings));
+    }
+
+    Ok(songs)
+}
+
+// the songs dir at `revision`, read with `git show`, e.g. `v1.0`
+fn revision_songs(
+    songs_dir: &Path,
+    with_dir: bool,
+    revision: &str,
+    presets: &Presets,
+    warnings: &mut Warnings,
+) -> Result<Vec<SongText>, TemplaterError> {
+    let what = format!("at '{}'", revision);
+    let files = git(songs_dir, &["ls-tree", "-r", "-z", "--name-only", revision, "--", "."], &what)?;
+    let files: Vec<&str> = files.split('\0').filter(|file| !file.is_empty()).collect();
+    let show = |file: &str| git(songs_dir, &["show", &format!("{}:./{}", revision, file)], &what);
+
+    let ignore = match files.contains(&IGNORE_FILE) {
+        true => IgnoreFile::parse(&show(IGNORE_FILE)?),
+        false => IgnoreFile::default(),
+    };
+    let defaults = match files.contains(&DEFAULTS_FILE) {
+        true => Defaults::parse(Path::new(DEFAULTS_FILE), &show(DEFAULTS_FILE)?)?,
+        false => Defaults::default(),
+    };
+
+    let mut songs = vec![];
+    for file in files {
+        let path = Path::new(file);
+        // like `get_files_by_ext`, with the file's folders too
+        let skipped = path.ancestors().filter(|ancestor| !ancestor.as_os_str().is_empty()).any(|ancestor| {
+            is_editor_file(ancestor) || ignore.ignores(ancestor, ancestor != path)
+        });
+        if skipped || path.extension().is_none_or(|ext| ext != "ly") {
+            continue;
+        }
+
+        let display = PathBuf::from(format!("{}:{}", revision, file));
+        let key = match with_dir {
+            true => songs_dir.join(file).display().to_string(),
+            false => file.to_string(),
+        };
+        songs.extend(file_songs(key, &display, &show(file)?, &defaults, presets, warnings));
+    }
+
+    Ok(songs)
+}
+
+// the songs of a file after its first are keyed by their index too.
+// drafts aren't in a book, so they're left out, and so is a file that
+// doesn't parse, with a warning
+fn file_songs(
+    key: String,
+    path: &Path,
+    input: &str,
+    defaults: &Defaults,
+    presets: &Presets,
+    warnings: &mut Warnings,
+) -> Vec<SongText> {
+    let songs = match Song::parse_file(path, input, true, defaults, presets) {
+        Ok(songs) => songs,
+        Err(e) => {
+            warnings.push(Warning::new(WarningKind::Unparsable, format!("{}, so it isn't diffed", e)));
+            return vec![];
+        },
+    };
+    songs
+        .iter()
+        .enumerate()
+        .filter(|(_, song)| !song.draft)
+        .map(|(i, song)| match i {
+            0 => SongText::from_song(key.clone(), song),
+            i => SongText::from_song(format!("{}#{}", key, i + 1), song),
+        })
+        .collect()
+}
+
+fn same_key(old: &SongText, new: &SongText) -> bool {
+    old.key == new.key
+}
+
+fn same_title(old: &SongText, new: &SongText) -> bool {
+    slugify(&old.title) == slugify(&new.title)
+}
+
+fn same_music(old: &SongText, new: &SongText) -> bool {
+    !(old.chords.is_empty() && old.melody.is_empty()) && old.chords == new.chords && old.melody == new.melody
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BookDiff {
+    pub old: String,
+    pub new: String,
+    pub added: Vec<SongText>,
+    pub removed: Vec<SongText>,
+    // (old title, new title)
+    pub retitled: Vec<(String, String)>,
+    // (title, what changed), e.g. ("Misty", ["chords", "melody"])
+    pub changed: Vec<(String, Vec<&'static str>)>,
+}
+
+impl BookDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.retitled.is_empty() && self.changed.is_empty()
+    }
+}
+
+// markdown, e.g. for a release's notes
+impl fmt::Display for BookDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Changes from {} to {}", self.old, self.new)?;
+        if self.is_empty() {
+            return writeln!(f, "\nNo songs changed.");
+        }
+
+        for (heading, songs) in [("Added", &self.added), ("Removed", &self.removed)] {
+            if !songs.is_empty() {
+                writeln!(f, "\n## {}", heading)?;
+                for song in songs {
+                    match song.composer.is_empty() {
+                        true => writeln!(f, "- {}", song.title)?,
+                        false => writeln!(f, "- {} - {}", song.title, song.composer)?,
+                    }
+                }
+            }
+        }
+        if !self.retitled.is_empty() {
+            writeln!(f, "\n## Retitled")?;
+            for (old, new) in &self.retitled {
+                writeln!(f, "- {} is now {}", old, new)?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "\n## Changed")?;
+            for (title, changes) in &self.changed {
+                writeln!(f, "- {}: {}", title, changes.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn diff_songs(old: &[SongText], new: &[SongText]) -> BookDiff {
+    let mut old_left: Vec<usize> = (0..old.len()).collect();
+    let mut new_left: Vec<usize> = (0..new.len()).collect();
+    let mut pairs = vec![];
+    let rules: [fn(&SongText, &SongText) -> bool; 3] = [same_key, same_title, same_music];
+    for rule in rules {
+        old_left.retain(|&i| match new_left.iter().position(|&j| rule(&old[i], &new[j])) {
+            Some(position) => {
+                pairs.push((&old[i], &new[new_left.remove(position)]));
+                false
+            },
+            None => true,
+        });
+    }
+
+    let mut diff = BookDiff {
+        added: new_left.into_iter().map(|i| new[i].clone()).collect(),
+        removed: old_left.into_iter().map(|i| old[i].clone()).collect(),
+        ..BookDiff::default()
+    };
+    for (old, new) in pairs {
+        if old.title != new.title {
+            diff.retitled.push((old.title.clone(), new.title.clone()));
+        }
+
+        let mut changes = vec![];
+        if old.composer != new.composer {
+            changes.push("composer");
+        }
+        if old.chords != new.chords {
+            changes.push("chords");
+        }
+        if old.melody != new.melody {
+            changes.push("melody");
+        }
+        if old.lyrics.is_some() && new.lyrics.is_some() && old.lyrics != new.lyrics {
+            changes.push("lyrics");
+        }
+        if !changes.is_empty() {
+            diff.changed.push((new.title.clone(), changes));
+        }
+    }
+
+    diff.added.sort_by_key(|song| slugify(&song.title));
+    diff.removed.sort_by_key(|song| slugify(&song.title));
+    diff.retitled.sort_by_key(|(_, new)| slugify(new));
+    diff.changed.sort_by_key(|(title, _)| slugify(title));
+    diff
+}
+
+pub fn diff_books<P: AsRef<Path>>(
+    old: &Side,
+    new: &Side,
+    songs_dirs: &[P],
+    presets: &Presets,
+    warnings: &mut Warnings,
+) -> Result<BookDiff, TemplaterError> {
+    let (old_songs, new_songs) = (old.songs(songs_dirs, presets, warnings)?, new.songs(songs_dirs, presets, warnings)?);
+    Ok(BookDiff { old: old.to_string(), new: new.to_string(), ..diff_songs(&old_songs, &new_songs) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn song(key: &str, title: &str, chords: &str, melody: &str) -> SongText {
+        SongText {
+            key: key.to_string(),
+            title: title.to_string(),
+            composer: String::from("Miles Davis"),
+            chords: normalize(chords),
+            melody: normalize(melody),
+            lyrics: None,
+        }
+    }
+
+    #[test]
+    fn it_reads_the_songs_of_a_book() {
+        let book = [
+            "\\bookpart {\n  \\markup \\null\n}",
+            "\\bookpart {",
+            "  \\tocItem \\markup \"1. So What - Miles Davis\"",
+            "  \\label #'song-so-what",
+            "  \\new ChordNames=\"Chords\" \\with { \\consists #percent-repeated-chords } {",
+            "    \\transpose c d {\n      \\chordmode { d1:m7 % dorian\n }\n    }\n  }",
+            "  \\new Staff {\n    \\new Voice=\"Voice\" {\n      \\transpose c d {",
+            "        \\set Staff.ottavationMarkups = #ottavation-ordinals\n        \\clef bass",
+            "        \\relative c' { d4 a' }\n      }\n    }\n  }\n}",
+        ]
+        .join("\n");
+        assert_eq!(
+            book_songs(&book),
+            vec![SongText {
+                key: String::from("song-so-what"),
+                title: String::from("So What"),
+                composer: String::from("Miles Davis"),
+                chords: String::from("\\chordmode { d1:m7 }"),
+                melody: String::from("\\relative c' { d4 a' }"),
+                lyrics: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn it_diffs_songs() {
+        let old = [
+            song("milestones.ly", "Milestones", "g1:m7", "g4 a"),
+            song("nardis.ly", "Nardis", "e1:m", "e4 f"),
+            song("sowhat.ly", "So What", "d1:m7", "d4 a'"),
+            song("solar.ly", "Solar", "c1:m", "g4 c"),
+        ];
+        let new = [
+            song("milestones.ly", "Milestones", "g1:m7", "g4 a"),
+            song("jazz/nardis.ly", "Nardis", "e1:m", "e4 f g"),
+            song("so-what.ly", "So What?", "d1:m7", "d4 a'"),
+            song("four.ly", "Four", "ees1:maj7", "g4 bes"),
+        ];
+
+        let diff = BookDiff { old: String::from("v1.0"), new: String::from("songs"), ..diff_songs(&old, &new) };
+        assert_eq!(diff.added.iter().map(|song| song.title.as_str()).collect::<Vec<_>>(), ["Four"]);
+        assert_eq!(diff.removed.iter().map(|song| song.title.as_str()).collect::<Vec<_>>(), ["Solar"]);
+        assert_eq!(diff.retitled, [(String::from("So What"), String::from("So What?"))]);
+        assert_eq!(diff.changed, [(String::from("Nardis"), vec!["melody"])]);
+        assert_eq!(
+            diff.to_string(),
+            "# Changes from v1.0 to songs\n\n## Added\n- Four - Miles Davis\n\n## Removed\n- Solar - Miles Davis\n\n\
+             ## Retitled\n- So What is now So What?\n\n## Changed\n- Nardis: melody\n"
+        );
+    }
+}
@@ -0,0 +1,573 @@
+// `--mode braille` (an experiment): the book in braille music, for
+// blind musicians, as a braille ascii file (`.brf`, 40 cells a line)
+// that an embosser or a braille display reads, e.g. `openbook-Bb.brf`.
+// every song starts a new page with its title and composer, then its
+// chords, a measure at a time, and its melody, written in the book's
+// key:
+//
+//     ,MISTY
+//     ,ERROL ,GARNER
+//
+//     ,CHORDS3
+//     ,FMAJ#G ,CM#G-,F#G ,B<MAJ#G ,B<M#G-,E<#G
+//     ...
+//
+//     ,MELODY3
+//     <#D4
+//     .DI P'DE <F.EEEEDIG O2X_IJ2EGI DDDJ?'J
+//     ...
+//
+// a measure's chords are joined by `-`, and `7` is a measure with no
+// new chord. the melody gets its key and time signature, octave marks,
+// accidentals, dots, ties, and triplets. lyrics, the other voices,
+// slurs, and marks aren't transcribed, a volta is written once, and a
+// duration braille can't write is the next shorter one.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::absolute::to_absolute;
+use crate::book::Book;
+use crate::chord::{Bass, Chord, Polychord, POLYCHORD};
+use crate::errors::TemplaterError;
+use crate::harmony::parse_pitch;
+use crate::keys::key_alterations;
+use crate::lilypond::SourceMap;
+use crate::models::{Config, Song, TemplaterConfig};
+use crate::music::{events, Event, Fraction};
+use crate::numbers::quality;
+use crate::range::parse_note;
+use crate::renderer::{Outputs, Renderer};
+use crate::transpose::Pitch;
+use crate::utils::fold_accents;
+
+// cells on a line
+const LINE_WIDTH: usize = 40;
+// before every song but the first
+const PAGE_BREAK: &str = "\x0c";
+
+// c to b as an eighth, a quarter, a half, and a whole note. a 128th,
+// 64th, 32nd, and 16th are written the same
+const NOTES: [[char; 7]; 4] = [
+    ['D', 'E', 'F', 'G', 'H', 'I', 'J'],
+    ['?', ':', '$', ']', '\\', '[', 'W'],
+    ['N', 'O', 'P', 'Q', 'R', 'S', 'T'],
+    ['Y', 'Z', '&', '=', '(', '!', ')'],
+];
+const RESTS: [char; 4] = ['X', 'V', 'U', 'M'];
+// the first to the seventh octave, middle c starts the fourth
+const OCTAVES: [char; 7] = ['@', '^', '_', '"', '.', ';', ','];
+// 1 to 9 and 0 after a number sign
+const DIGITS: [char; 10] = ['J', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I'];
+
+const NUMBER: char = '#';
+const CAPITAL: char = ',';
+const SHARP: char = '%';
+const FLAT: char = '<';
+const NATURAL: char = '*';
+const DOT: char = '\'';
+const TIE: &str = "@C";
+const TRIPLET: char = '2';
+const MEASURE_REPEAT: &str = "7";
+const FINAL_BAR: &str = "<K";
+
+// a number in the upper cells, e.g. "#D" for 4
+fn number(n: u64) -> String {
+    let digits: String = n.to_string().chars().filter_map(|c| c.to_digit(10)).map(|d| DIGITS[d as usize]).collect();
+    format!("{}{}", NUMBER, digits)
+}
+
+// e.g. "#D4" for 4/4, the unit in the lower cells, which are the
+// digits in braille ascii
+fn time_signature(beats: u64, unit: u64) -> String {
+    format!("{}{}", number(beats), unit)
+}
+
+// e.g. "%%" for D major, "#D<" for Ab major
+fn key_signature(fifths: i32) -> String {
+    let sign = if fifths > 0 { SHARP } else { FLAT };
+    match fifths.unsigned_abs() {
+        0 => String::new(),
+        count @ 1..=3 => sign.to_string().repeat(count as usize),
+        count => format!("{}{}", number(count as u64), sign),
+    }
+}
+
+fn accidental(alteration: i32) -> String {
+    match alteration {
+        0 => NATURAL.to_string(),
+        _ if alteration < 0 => FLAT.to_string().repeat(alteration.unsigned_abs() as usize),
+        _ => SHARP.to_string().repeat(alteration as usize),
+    }
+}
+
+// (value, dots, whether it's a triplet) of a duration, the value's
+// row of `NOTES`. none for a grace note
+fn note_value(duration: Fraction) -> Option<(usize, usize, bool)> {
+    if duration == Fraction::ZERO {
+        return None;
+    }
+    let value = |base: Fraction| {
+        let written = base.num == 1 && base.den.is_power_of_two() && base.den <= 128;
+        written.then(|| 3 - base.den.trailing_zeros() as usize % 4)
+    };
+
+    // e.g. a dotted quarter is 3/8, a quarter and its half
+    for dots in 0..3 {
+        if let Some(value) = value(duration * Fraction::new(1 << dots, (2 << dots) - 1)) {
+            return Some((value, dots, false));
+        }
+    }
+    if let Some(value) = value(duration * Fraction::new(3, 2)) {
+        return Some((value, 0, true));
+    }
+
+    let mut den = 1;
+    while Fraction::new(1, den) > duration && den < 128 {
+        den *= 2;
+    }
+    value(Fraction::new(1, den)).map(|value| (value, 0, false))
+}
+
+// a line of literary braille, uncontracted, e.g. ",ERROL ,GARNER". a
+// word in capitals gets a double capital sign instead, e.g. ",,ABC"
+fn literary(text: &str) -> String {
+    let text = fold_accents(text);
+    let words: Vec<String> = text.split(' ').map(literary_word).collect();
+    words.join(" ")
+}
+
+fn literary_word(word: &str) -> String {
+    let letters = word.chars().filter(char::is_ascii_alphabetic).count();
+    let capitals = letters > 1 && word.chars().all(|c| !c.is_ascii_lowercase());
+    let mut out = match capitals {
+        true => CAPITAL.to_string().repeat(2),
+        false => String::new(),
+    };
+    let mut in_number = false;
+    for c in word.chars() {
+        match c.to_digit(10) {
+            Some(digit) => {
+                if !in_number {
+                    out.push(NUMBER);
+                }
+                out.push(DIGITS[digit as usize]);
+                in_number = true;
+                continue;
+            },
+            None => in_number = false,
+        }
+        match c {
+            c if c.is_ascii_uppercase() && !capitals => {
+                out.push(CAPITAL);
+                out.push(c);
+            },
+            c if c.is_ascii_uppercase() => out.push(c),
+            c if c.is_ascii_lowercase() => out.push(c.to_ascii_uppercase()),
+            '.' => out.push('4'),
+            ',' => out.push('1'),
+            '?' => out.push('8'),
+            '!' => out.push('6'),
+            ':' => out.push('3'),
+            ';' => out.push('2'),
+            '(' | ')' => out.push('7'),
+            '-' | '\'' | '/' => out.push(c),
+            _ => {},
+        }
+    }
+    out
+}
+
+// `words` with spaces between them, on lines of up to `LINE_WIDTH`
+// cells. a longer word gets a line of its own
+fn wrap(words: &[String]) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for word in words {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= LINE_WIDTH => {
+                line.push(' ');
+                line.push_str(word);
+            },
+            _ => lines.push(word.clone()),
+        }
+    }
+    lines
+}
+
+// e.g. ",B<" for bes
+fn pitch_name(pitch: &Pitch) -> String {
+    let letter = pitch.display_name().chars().next().unwrap_or('C');
+    match pitch.alteration {
+        0 => format!("{}{}", CAPITAL, letter),
+        alteration => format!("{}{}{}", CAPITAL, letter, accidental(alteration)),
+    }
+}
+
+// a chord name, e.g. ",B<M#G/,F" for bes:m7/f. digits get a number
+// sign, and flats and sharps are the music signs
+fn chord_name(chord: &Chord) -> String {
+    let mut name = pitch_name(&chord.root);
+    let quality = quality(&chord.steps).replace('°', "dim").replace('+', "aug");
+    let mut chars = quality.chars().peekable();
+    let mut in_number = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '0'..='9' => {
+                if !in_number {
+                    name.push(NUMBER);
+                }
+                name.push(DIGITS[c.to_digit(10).unwrap_or_default() as usize]);
+                in_number = true;
+                continue;
+            },
+            'b' if chars.peek().is_some_and(char::is_ascii_digit) => name.push(FLAT),
+            '#' => name.push(SHARP),
+            '(' | ')' => {},
+            c => name.push(c.to_ascii_uppercase()),
+        }
+        in_number = false;
+    }
+    if let Some(Bass { pitch, .. }) = &chord.bass {
+        name.push('/');
+        name.push_str(&pitch_name(pitch));
+    }
+    name
+}
+
+// a chord of `\chordmode`, e.g. "bes2.:m7/f" or "d//c:7", named in
+// the key of `\transpose c <to>`. none if it isn't a chord
+fn chord_token(token: &str, to: &Pitch) -> Option<String> {
+    match token.contains(POLYCHORD) {
+        true => {
+            let polychord = Polychord::parse(token)?;
+            let (upper, lower) = (polychord.upper.transpose(to), polychord.lower.transpose(to));
+            Some(format!("{}{}{}", chord_name(&upper), POLYCHORD, chord_name(&lower)))
+        },
+        false => Chord::parse(token).map(|chord| chord_name(&chord.transpose(to))),
+    }
+}
+
+// every measure of a `\chordmode`, its chords joined by `-`
+fn chord_measures(chords: &str, to: &Pitch) -> Vec<String> {
+    // the chords of every measure, as written
+    let mut measures: Vec<Vec<String>> = vec![vec![]];
+    let mut measure = Fraction::new(4, 4);
+    let mut position = Fraction::ZERO;
+
+    for event in events(chords) {
+        match event {
+            Event::Time { beats, unit, .. } => measure = Fraction::new(beats, unit),
+            Event::Partial { duration, .. } => position = measure - duration,
+            Event::Note { pitch, duration, .. } if measure.num > 0 => {
+                if let Some(root) = pitch.filter(|pitch| parse_pitch(pitch).is_some()) {
+                    if let Some(chords) = measures.last_mut() {
+                        chords.push(root);
+                    }
+                }
+
+                position = position + duration;
+                while position >= measure {
+                    position = position - measure;
+                    measures.push(vec![]);
+                }
+            },
+            Event::Modifier { text, .. } => {
+                // it's after the chord, which may have ended the measure
+                if let Some(chord) = measures.iter_mut().rev().find_map(|chords| chords.last_mut()) {
+                    chord.push_str(&text);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // the measure after the last chord hasn't started
+    if position == Fraction::ZERO && measures.len() > 1 {
+        measures.pop();
+    }
+
+    let mut seen_chord = false;
+    measures
+        .iter()
+        .filter_map(|chords| {
+            let names: Vec<String> = chords.iter().filter_map(|chord| chord_token(chord, to)).collect();
+            seen_chord |= !names.is_empty();
+            match names.is_empty() {
+                true if seen_chord => Some(String::from(MEASURE_REPEAT)),
+                true => None,
+                false => Some(names.join("-")),
+            }
+        })
+        .collect()
+}
+
+// what's in a measure of the melody
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    // none for a rest, in the written pitch
+    Note { pitch: Option<Pitch>, duration: Fraction, tied: bool },
+    Time { beats: u64, unit: u64 },
+}
+
+// every measure of `music`, in absolute pitch, written for
+// `\transpose c <target>`
+fn melody_measures(music: &str, target: &Pitch) -> Vec<Vec<Item>> {
+    let mut measures: Vec<Vec<Item>> = vec![vec![]];
+    let mut measure = Fraction::new(4, 4);
+    let mut position = Fraction::ZERO;
+
+    for event in events(music) {
+        match event {
+            Event::Time { beats, unit, .. } => {
+                measure = Fraction::new(beats, unit);
+                if let Some(items) = measures.last_mut() {
+                    items.push(Item::Time { beats, unit });
+                }
+            },
+            Event::Partial { duration, .. } => position = measure - duration,
+            Event::Note { pitch, duration, .. } if measure.num > 0 && duration > Fraction::ZERO => {
+                let pitch = pitch.as_deref().and_then(parse_note).map(|pitch| {
+                    let semitones = pitch.semitones() + target.semitones();
+                    let spelled = pitch.transpose(target);
+                    Pitch { octave: (semitones - spelled.semitones()).div_euclid(12), ..spelled }
+                });
+                if let Some(items) = measures.last_mut() {
+                    items.push(Item::Note { pitch, duration, tied: false });
+                }
+
+                position = position + duration;
+                while position >= measure {
+                    position = position - measure;
+                    measures.push(vec![]);
+                }
+            },
+            Event::Tie { .. } => {
+                let last = measures.iter_mut().rev().find_map(|items| {
+                    items.iter_mut().rev().find(|item| matches!(item, Item::Note { .. }))
+                });
+                if let Some(Item::Note { tied, .. }) = last {
+                    *tied = true;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    measures.retain(|items| !items.is_empty());
+    measures
+}
+
+// whether a note needs an octave mark after `previous`, by the
+// interval between them: never within a third, always from a sixth,
+// and a fourth or a fifth when it's in another octave
+fn needs_octave(pitch: &Pitch, previous: Option<&Pitch>) -> bool {
+    let Some(previous) = previous else { return true };
+    let steps = |pitch: &Pitch| pitch.octave * 7 + pitch.step as i32;
+    match (steps(pitch) - steps(previous)).abs() {
+        0..=2 => false,
+        3 | 4 => pitch.octave != previous.octave,
+        _ => true,
+    }
+}
+
+// a measure of the melody, and its last note. `previous` is the note
+// before it, none at the start of a line
+fn melody_measure(items: &[Item], key: &[i32; 7], mut previous: Option<Pitch>) -> (String, Option<Pitch>) {
+    let mut out = String::new();
+    // the accidentals so far, by (octave, letter)
+    let mut altered: Vec<((i32, usize), i32)> = vec![];
+    let mut triplets = 0;
+
+    for item in items {
+        let (pitch, duration, tied) = match item {
+            Item::Time { beats, unit } => {
+                if !out.is_empty() {
+                    out.push(' ');
+                }
+                out.push_str(&time_signature(*beats, *unit));
+                out.push(' ');
+                previous = None;
+                continue;
+            },
+            Item::Note { pitch, duration, tied } => (pitch, *duration, *tied),
+        };
+        let Some((value, dots, triplet)) = note_value(duration) else { continue };
+
+        match triplet {
+            true if triplets % 3 == 0 => {
+                out.push(TRIPLET);
+                triplets += 1;
+            },
+            true => triplets += 1,
+            false => triplets = 0,
+        }
+
+        match pitch {
+            Some(pitch) => {
+                let at = (pitch.octave, pitch.step);
+                let expected = altered.iter().rev().find(|(of, _)| *of == at).map_or(key[pitch.step], |(_, a)| *a);
+                if pitch.alteration != expected {
+                    out.push_str(&accidental(pitch.alteration));
+                    altered.push((at, pitch.alteration));
+                }
+                if needs_octave(pitch, previous.as_ref()) {
+                    out.push(OCTAVES[(pitch.octave + 3).clamp(1, 7) as usize - 1]);
+                }
+                out.push(NOTES[value][pitch.step]);
+                previous = Some(*pitch);
+            },
+            None => out.push(RESTS[value]),
+        }
+        out.extend(std::iter::repeat_n(DOT, dots));
+        if tied {
+            out.push_str(TIE);
+        }
+    }
+
+    (out.trim_end().to_string(), previous)
+}
+
+// the melody, on lines of up to `LINE_WIDTH` cells. the first note of
+// every line gets an octave mark
+fn melody_lines(measures: &[Vec<Item>], fifths: i32) -> Vec<String> {
+    let key = key_alterations(fifths);
+    let mut items = measures.to_vec();
+
+    // the key and time signature start the melody
+    let time = match items.first().and_then(|items| items.first()) {
+        Some(Item::Time { beats, unit }) => time_signature(*beats, *unit),
+        _ => time_signature(4, 4),
+    };
+    if let Some(first) = items.first_mut().filter(|items| matches!(items.first(), Some(Item::Time { .. }))) {
+        first.remove(0);
+    }
+    let mut lines = vec![format!("{}{}", key_signature(fifths), time)];
+
+    let mut previous = None;
+    let mut line = String::new();
+    let count = items.len();
+    for (i, measure) in items.iter().enumerate() {
+        // the last one ends with the final bar line
+        let end = if i + 1 == count { FINAL_BAR } else { "" };
+        let (mut cells, mut last) = melody_measure(measure, &key, previous);
+        if !line.is_empty() && line.len() + 1 + cells.len() + end.len() > LINE_WIDTH {
+            lines.push(std::mem::take(&mut line));
+            (cells, last) = melody_measure(measure, &key, None);
+        }
+        cells.push_str(end);
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&cells);
+        previous = last;
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+// a song's page: its title and composer, its chords, and its melody
+pub fn render_song(song: &Song, conf: &TemplaterConfig) -> String {
+    let transposition = song.transposition(&conf.transpose_text);
+    let target = transposition.lilypond_text.split_whitespace().nth(1).and_then(parse_note);
+    let target = target.unwrap_or(Pitch { step: 0, alteration: 0, octave: 0 });
+    let fifths = song.key.map_or(0, |key| key.transposed(&transposition).fifths());
+
+    let words = |text: &str| -> Vec<String> { text.split_whitespace().map(literary).collect() };
+    let mut lines = wrap(&words(&song.title));
+    lines.extend(wrap(&words(&song.composer)));
+
+    let chords = chord_measures(&song.chords, &target);
+    if !chords.is_empty() {
+        lines.push(String::new());
+        lines.push(literary("Chords:"));
+        lines.extend(wrap(&chords));
+    }
+
+    let melody = song.voices.iter().find(|voice| voice.name.is_none()).map(|voice| voice.notes.as_str());
+    let melody = melody
+        .filter(|notes| !["<<", "\\fixed", "\\transpose"].iter().any(|command| notes.contains(command)))
+        .and_then(|notes| to_absolute(notes).ok())
+        .map(|music| melody_measures(&music, &target))
+        .filter(|measures| !measures.is_empty());
+    if let Some(measures) = melody {
+        lines.push(String::new());
+        lines.push(literary("Melody:"));
+        lines.extend(melody_lines(&measures, fifths));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+// the melodies and chords are read in the key of the book, so it's
+// written for every transposition
+pub struct BrailleBook;
+
+impl Renderer for BrailleBook {
+    fn outputs(&self) -> Outputs {
+        Outputs::PerTransposition
+    }
+
+    fn output_path(
+        &self,
+        config: &Config,
+        conf: &TemplaterConfig,
+        volume: Option<usize>,
+    ) -> Result<PathBuf, TemplaterError> {
+        Ok(config.output_path(&conf.transpose_text, volume))
+    }
+
+    fn render(
+        &self,
+        _book: &Book,
+        out: &mut dyn Write,
+        conf: &TemplaterConfig,
+        songs: &[Song],
+        _volume: Option<usize>,
+    ) -> Result<SourceMap, TemplaterError> {
+        let pages: Vec<String> = songs.iter().map(|song| render_song(song, conf)).collect();
+        write!(out, "{}", pages.join(PAGE_BREAK))?;
+
+        // not lilypond, so there's nothing to map
+        Ok(SourceMap::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn target(name: &str) -> Pitch {
+        parse_note(name).unwrap()
+    }
+
+    #[test]
+    fn it_writes_a_melody_in_braille() {
+        let music = "\\time 3/4 c'4 d' e' | f'2. | \\tuplet 3/2 { g'8 a' b' } c''4~ c''8. r16 | fis'2 f'4 | c1";
+        let measures = melody_measures(music, &target("c"));
+        assert_eq!(
+            melody_lines(&measures, -1),
+            ["<#C4", "\"?:$ Q' 2HI*J?@CD'M %\"Q*] _Y<K"]
+        );
+
+        // up a major second, in D, with the octave marked on every line
+        let measures = melody_measures(&"c'4 e' g' c'' | ".repeat(8), &target("d"));
+        let lines = melody_lines(&measures, 2);
+        assert_eq!(lines[0], "%%#D4");
+        assert_eq!(lines[1], ["\":][.:"; 5].join(" "));
+        assert_eq!(lines[2], ["\":][.:"; 3].join(" ") + "<K");
+    }
+
+    #[test]
+    fn it_names_chords_in_braille() {
+        let chords = "\\partial 4 g4:7 | ees1:maj7 | bes2:m7/f d//c:7 | s1 | f:7.9- |";
+        assert_eq!(chord_measures(chords, &target("c")), [",G#G", ",E<MAJ#G", ",B<M#G/,F-,D//,C#G", "7", ",F#G<#I"]);
+        assert_eq!(chord_measures("c1:dim7 | d:6.9", &target("d")), [",DDIM#G", ",E#F/#I"]);
+        assert_eq!(literary("Take 5 (Live)"), ",TAKE #E 7,LIVE7");
+        assert_eq!(literary("UNKNOWN COMPOSER, Jr."), ",,UNKNOWN ,,COMPOSER1 ,JR4");
+        assert_eq!(key_signature(-4), "#D<");
+        assert_eq!(time_signature(6, 8), "#F8");
+    }
+}
@@ -0,0 +1,618 @@
+// `templater browse`: the songs in the terminal, to find one by
+// typing part of its title, composer, or tags, see its front matter
+// and the bookpart it renders to, and write its lead sheet (enter)
+// or open it in $EDITOR (ctrl-o). it's drawn with plain ansi
+// escapes, with the terminal in raw mode from `stty` while it's
+// open, so it needs a unix terminal. it's drawn again when SIGWINCH
+// says the terminal was resized. the book is built again after
+// every edit, so the preview is always the song as it's saved.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::book::BookBuilder;
+use crate::errors::TemplaterError;
+use crate::lilypond::run_lilypond;
+use crate::models::Song;
+use crate::session::BookSession;
+use crate::utils::fold_accents;
+use crate::warnings::Warnings;
+use crate::set_quiet;
+
+const HELP: &str = "type to search  ↑↓ select  PgUp/PgDn scroll  enter: write its lead sheet  ctrl-o: edit  esc: quit";
+
+// set by SIGWINCH, when the terminal is resized
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_resize(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    // e.g. 'o' for ctrl-o
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Esc,
+}
+
+// every key in what was read from the terminal at once. escape
+// sequences for the keys it doesn't use are left out
+pub fn parse_keys(input: &[u8]) -> Vec<Key> {
+    let input = String::from_utf8_lossy(input);
+    let mut chars = input.chars().peekable();
+    let mut keys = vec![];
+
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' if matches!(chars.peek(), Some('[' | 'O')) => {
+                chars.next();
+                // e.g. "A" for up, or "5~" for page up
+                let mut sequence = String::new();
+                for c in chars.by_ref() {
+                    sequence.push(c);
+                    if c.is_ascii_alphabetic() || c == '~' {
+                        break;
+                    }
+                }
+                match sequence.as_str() {
+                    "A" => Key::Up,
+                    "B" => Key::Down,
+                    "5~" => Key::PageUp,
+                    "6~" => Key::PageDown,
+                    _ => continue,
+                }
+            },
+            '\x1b' => Key::Esc,
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            // ctrl-a is 1
+            c if ('\x01'..'\x1b').contains(&c) => Key::Ctrl((b'a' + c as u8 - 1) as char),
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+
+    keys
+}
+
+// how much of `input` is whole keys, i.e. without an escape sequence
+// or a character that's cut off at its end
+fn whole_keys(input: &[u8]) -> usize {
+    let end = match std::str::from_utf8(input) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => input.len(),
+    };
+
+    if let Some(start) = input[..end].iter().rposition(|b| *b == 0x1b) {
+        let sequence = &input[start + 1..end];
+        let ended = match sequence.first() {
+            None => false,
+            Some(b'[' | b'O') => sequence[1..].iter().any(|b| b.is_ascii_alphabetic() || *b == b'~'),
+            Some(_) => true,
+        };
+        if !ended {
+            return start;
+        }
+    }
+    end
+}
+
+// the keys of one read after another, since a key's escape sequence
+// can be split between two of them
+#[derive(Debug, Default)]
+pub struct KeyReader {
+    // the start of a key that's only partly read
+    pending: Vec<u8>,
+}
+
+impl KeyReader {
+    // every whole key so far. a read without anything, a tenth of a
+    // second later, ends what's left, e.g. esc on its own
+    pub fn read(&mut self, input: &[u8]) -> Vec<Key> {
+        if input.is_empty() {
+            return parse_keys(&std::mem::take(&mut self.pending));
+        }
+
+        self.pending.extend_from_slice(input);
+        let rest = self.pending.split_off(whole_keys(&self.pending));
+        parse_keys(&std::mem::replace(&mut self.pending, rest))
+    }
+}
+
+// how well `query` matches `text`, none unless all of its letters are
+// in it, in order. a letter right after the one before it, or at the
+// start of a word, counts for more, so "bb" is "Blue Bossa" first
+pub fn fuzzy_score(query: &str, text: &str) -> Option<u32> {
+    let text: Vec<char> = fold_accents(text).to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut from = 0;
+    let mut previous: Option<usize> = None;
+
+    for c in fold_accents(query).to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let i = from + text[from..].iter().position(|t| *t == c)?;
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == i) {
+            score += 2;
+        }
+        if i == 0 || !text[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        previous = Some(i);
+        from = i + 1;
+    }
+
+    Some(score)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    // the index of the song in the book
+    Build(usize),
+    Edit(usize),
+}
+
+// what's on the screen, as the keys change it
+#[derive(Debug, Default)]
+pub struct Browser {
+    // what's searched of every song, in book order
+    haystacks: Vec<String>,
+    query: String,
+    // the songs that match it, best first
+    matches: Vec<usize>,
+    // index into `matches`
+    selected: usize,
+    // lines of the preview scrolled past
+    scroll: usize,
+    // the result of the last action, instead of the help
+    pub status: String,
+}
+
+impl Browser {
+    pub fn new(songs: &[Song]) -> Self {
+        let mut browser = Browser::default();
+        browser.reload(songs, None);
+        browser
+    }
+
+    // after the book is built again, with the same song selected
+    pub fn reload(&mut self, songs: &[Song], selected: Option<&Path>) {
+        self.haystacks = songs
+            .iter()
+            .map(|song| format!("{} {} {}", song.title, song.composer, song.tags.join(" ")))
+            .collect();
+        self.search();
+        if let Some(path) = selected {
+            let selected = self.matches.iter().position(|&song| songs[song].path == path);
+            self.selected = selected.unwrap_or_default();
+        }
+    }
+
+    fn search(&mut self) {
+        let mut matches: Vec<(u32, usize)> = self
+            .haystacks
+            .iter()
+            .enumerate()
+            .filter_map(|(song, haystack)| Some((fuzzy_score(&self.query, haystack)?, song)))
+            .collect();
+        // ties stay in book order
+        matches.sort_by_key(|(score, song)| (std::cmp::Reverse(*score), *song));
+
+        self.matches = matches.into_iter().map(|(_, song)| song).collect();
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    // the selected song's index in the book
+    pub fn song(&self) -> Option<usize> {
+        self.matches.get(self.selected).copied()
+    }
+
+    // `page` is how far page up and down scroll the preview
+    pub fn handle(&mut self, key: Key, page: usize) -> Option<Action> {
+        self.status.clear();
+        match key {
+            Key::Esc | Key::Ctrl('c') => return Some(Action::Quit),
+            Key::Enter => return self.song().map(Action::Build),
+            Key::Ctrl('o') => return self.song().map(Action::Edit),
+            Key::Up | Key::Ctrl('p') => {
+                self.selected = self.selected.saturating_sub(1);
+                self.scroll = 0;
+            },
+            Key::Down | Key::Ctrl('n') => {
+                self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1));
+                self.scroll = 0;
+            },
+            Key::PageUp => self.scroll = self.scroll.saturating_sub(page),
+            Key::PageDown => self.scroll += page,
+            Key::Backspace => {
+                self.query.pop();
+                self.search();
+            },
+            Key::Ctrl('u') => {
+                self.query.clear();
+                self.search();
+            },
+            Key::Char(c) => {
+                self.query.push(c);
+                self.search();
+            },
+            Key::Ctrl(_) => {},
+        }
+
+        None
+    }
+
+    // the whole screen: the search, the songs on the left and the
+    // selected one on the right, and the help or the status below
+    pub fn draw(&self, songs: &[Song], preview: &str, width: usize, height: usize) -> String {
+        let list_width = (width * 2 / 5).max(20).min(width);
+        let detail_width = width.saturating_sub(list_width + 3);
+        let rows = height.saturating_sub(2);
+
+        let count = format!("{}/{}", self.matches.len(), songs.len());
+        let search = format!("> {}", self.query);
+        let mut lines = vec![format!("{}{:>pad$}", search, count, pad = width.saturating_sub(search.chars().count()))];
+
+        let first = self.selected.saturating_sub(rows.saturating_sub(1));
+        let list: Vec<String> = self.matches[first.min(self.matches.len())..]
+            .iter()
+            .take(rows)
+            .enumerate()
+            .map(|(i, &song)| {
+                let entry = fit(&format!("{} - {}", songs[song].title, songs[song].composer), list_width);
+                match first + i == self.selected {
+                    true => format!("\x1b[7m{}\x1b[0m", entry),
+                    false => entry,
+                }
+            })
+            .collect();
+
+        let mut details = self.song().map(|song| details(&songs[song])).unwrap_or_default();
+        details.push(String::new());
+        details.extend(preview.lines().skip(self.scroll).map(|line| line.replace('\t', "  ")));
+
+        for row in 0..rows {
+            let entry = list.get(row).cloned().unwrap_or_else(|| fit("", list_width));
+            let detail = details.get(row).map_or(String::new(), |detail| fit(detail, detail_width));
+            lines.push(format!("{} │ {}", entry, detail.trim_end()));
+        }
+
+        let status = match self.status.is_empty() {
+            true => HELP,
+            false => &self.status,
+        };
+        lines.push(format!("\x1b[2m{}\x1b[0m", fit(status, width).trim_end()));
+
+        // every line is cleared to its end, and drawn from the top
+        let lines: Vec<String> = lines.into_iter().map(|line| format!("{}\x1b[K", line)).collect();
+        format!("\x1b[H{}", lines.join("\r\n"))
+    }
+}
+
+// cut or padded to `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let text: String = text.chars().take(width).collect();
+    format!("{:width$}", text, width = width)
+}
+
+// the front matter the preview starts with
+fn details(song: &Song) -> Vec<String> {
+    let times: Vec<String> = song.time_signatures.iter().map(ToString::to_string).collect();
+    let fields = [
+        ("composer", Some(song.composer.clone()).filter(|composer| !composer.is_empty())),
+        ("poet", song.poet.clone()),
+        ("arranger", song.arranger.clone()),
+        ("year", song.year.map(|year| year.to_string())),
+        ("meter", song.meter.clone()),
+        ("bpm", song.tempo.as_ref().map(ToString::to_string)),
+        ("key", song.key.map(|key| key.to_string())),
+        ("time", Some(times.join(", ")).filter(|times| !times.is_empty())),
+        ("form", song.form.as_ref().map(ToString::to_string)),
+        ("measures", song.measures.map(|measures| measures.to_string())),
+        ("tags", Some(song.tags.join(", ")).filter(|tags| !tags.is_empty())),
+        ("license", song.license.clone()),
+    ];
+
+    let mut lines = vec![format!("{} ({})", song.title, song.path.display())];
+    lines.extend(fields.into_iter().filter_map(|(name, value)| Some(format!("{}: {}", name, value?))));
+    lines
+}
+
+// the terminal, in raw mode and on the alternate screen until it's
+// dropped, which is also while unwinding from a panic
+struct Terminal {
+    tty: File,
+    // `stty -g`, to put it back
+    saved: String,
+    keys: KeyReader,
+    // SIGWINCH's handler before `on_resize`
+    on_resize: libc::sigaction,
+}
+
+fn stty(tty: &File, args: &[&str]) -> Result<String, TemplaterError> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(tty.try_clone()?)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| TemplaterError::from_str(&format!("Unable to run 'stty': {}", e)))?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => Err(TemplaterError::from_str("templater browse needs a terminal.")),
+    }
+}
+
+impl Terminal {
+    fn open() -> Result<Self, TemplaterError> {
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(|e| TemplaterError::from_str(&format!("templater browse needs a terminal: {}", e)))?;
+        let saved = stty(&tty, &["-g"])?;
+
+        // without SA_RESTART, so a read returns as soon as it's resized
+        let on_resize = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_resize as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGWINCH, &action, &mut previous);
+            previous
+        };
+
+        let mut terminal = Terminal { tty, saved, keys: KeyReader::default(), on_resize };
+        terminal.enter()?;
+        Ok(terminal)
+    }
+
+    // reads return after a tenth of a second without a key
+    fn enter(&mut self) -> Result<(), TemplaterError> {
+        stty(&self.tty, &["raw", "-echo", "min", "0", "time", "1"])?;
+        write!(self.tty, "\x1b[?1049h\x1b[?25l")?;
+        Ok(())
+    }
+
+    fn leave(&mut self) -> Result<(), TemplaterError> {
+        write!(self.tty, "\x1b[?25h\x1b[?1049l")?;
+        stty(&self.tty, &[&self.saved]).map(|_| ())
+    }
+
+    // (rows, columns)
+    fn size(&self) -> (usize, usize) {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        match unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_row > 0 && size.ws_col > 0 => (size.ws_row as usize, size.ws_col as usize),
+            _ => (24, 80),
+        }
+    }
+
+    fn read_keys(&mut self) -> Result<Vec<Key>, TemplaterError> {
+        let mut buffer = [0; 64];
+        match self.tty.read(&mut buffer) {
+            Ok(read) => Ok(self.keys.read(&buffer[..read])),
+            // by SIGWINCH
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.leave();
+        unsafe {
+            libc::sigaction(libc::SIGWINCH, &self.on_resize, std::ptr::null_mut());
+        }
+    }
+}
+
+// the song's bookpart in the book's first transposition
+fn preview(session: &BookSession, song: usize) -> String {
+    let book = session.book();
+    book.songs[song].render(&book.templates, &book.confs[0]).unwrap_or_else(|e| format!("error: {}", e))
+}
+
+// its lead sheet in every transposition, run through lilypond with `--pdf`
+fn build_song(session: &BookSession, song: usize) -> Result<String, TemplaterError> {
+    let book = session.book();
+    let mut written = vec![];
+    for output in book.write_song(song)? {
+        match book.config.pdf {
+            true => written.push(run_lilypond(
+                &book.config.lilypond,
+                &book.config.lilypond_flags,
+                &output.path,
+                &output.source_map,
+            )?),
+            false => written.push(output.path),
+        }
+    }
+
+    let written: Vec<String> = written.iter().map(|path| path.display().to_string()).collect();
+    Ok(format!("wrote {}", written.join(", ")))
+}
+
+// $VISUAL or $EDITOR, e.g. "code --wait", or vi
+fn edit(terminal: &mut Terminal, path: &Path) -> Result<(), TemplaterError> {
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| String::from("vi"));
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+
+    terminal.leave()?;
+    let status = Command::new(program).args(words).arg(path).status();
+    terminal.enter()?;
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(TemplaterError::from_str(&format!("{} failed ({})", editor, status))),
+        Err(e) => Err(TemplaterError::from_str(&format!("Unable to run '{}': {}", editor, e))),
+    }
+}
+
+// blocks until it's closed. returns the last book's warnings
+pub fn browse(builder: BookBuilder) -> Result<Warnings, TemplaterError> {
+    set_quiet(true);
+
+    // a panic's message would be printed on the alternate screen, so
+    // it's kept until the terminal is put back
+    let message = Arc::new(Mutex::new(None));
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new({
+        let message = message.clone();
+        move |info| *message.lock().unwrap_or_else(|e| e.into_inner()) = Some(info.to_string())
+    }));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run(builder)));
+    panic::set_hook(hook);
+
+    set_quiet(false);
+    match result {
+        Ok(result) => result,
+        Err(panic) => {
+            if let Some(message) = message.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                eprintln!("{}", message);
+            }
+            panic::resume_unwind(panic)
+        },
+    }
+}
+
+fn run(builder: BookBuilder) -> Result<Warnings, TemplaterError> {
+    let mut session = BookSession::new(builder.clone())?;
+    let mut terminal = Terminal::open()?;
+    let mut browser = Browser::new(session.songs());
+    let mut previews: HashMap<usize, String> = HashMap::new();
+
+    let mut size = terminal.size();
+    let mut redraw = true;
+    loop {
+        if redraw {
+            let preview = match browser.song() {
+                Some(song) => previews.entry(song).or_insert_with(|| preview(&session, song)).as_str(),
+                None => "",
+            };
+            write!(terminal.tty, "{}", browser.draw(session.songs(), preview, size.1, size.0))?;
+            terminal.tty.flush()?;
+        }
+
+        let keys = terminal.read_keys()?;
+        redraw = !keys.is_empty();
+        if RESIZED.swap(false, Ordering::Relaxed) {
+            size = terminal.size();
+            redraw = true;
+        }
+
+        for key in keys {
+            match browser.handle(key, size.0 / 2) {
+                None => {},
+                Some(Action::Quit) => return Ok(session.book().warnings.clone()),
+                Some(Action::Build(song)) => {
+                    browser.status = build_song(&session, song).unwrap_or_else(|e| first_line(&e.to_string()));
+                },
+                Some(Action::Edit(song)) => {
+                    let path: PathBuf = session.songs()[song].path.clone();
+                    if let Err(e) = edit(&mut terminal, &path) {
+                        browser.status = first_line(&e.to_string());
+                        continue;
+                    }
+
+                    // a song that doesn't parse anymore keeps the old book
+                    match BookSession::new(builder.clone()) {
+                        Ok(rebuilt) => {
+                            session = rebuilt;
+                            previews.clear();
+                            browser.reload(session.songs(), Some(&path));
+                            browser.status = format!("reloaded {}", path.display());
+                        },
+                        Err(e) => browser.status = first_line(&e.to_string()),
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn first_line(message: &str) -> String {
+    message.lines().next().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontmatter::Defaults;
+    use crate::transpose::Presets;
+
+    fn song(title: &str, composer: &str) -> Song {
+        let input = format!("title: {}\ncomposer: {}\n---\n", title, composer);
+        let path = format!("{}.ly", title.to_lowercase());
+        Song::parse_with(Path::new(&path), &input, false, &Defaults::default(), &Presets::default()).unwrap()
+    }
+
+    #[test]
+    fn it_reads_keys_from_the_terminal() {
+        assert_eq!(
+            parse_keys(b"m\x1b[Bi\x7f\r\x0f\x1b[5~\x1b"),
+            [Key::Char('m'), Key::Down, Key::Char('i'), Key::Backspace, Key::Enter, Key::Ctrl('o'), Key::PageUp, Key::Esc]
+        );
+        assert_eq!(parse_keys("\x1b[Cé".as_bytes()), [Key::Char('é')]);
+    }
+
+    #[test]
+    fn it_reads_keys_split_between_reads() {
+        let mut keys = KeyReader::default();
+        assert_eq!(keys.read(b"m\x1b["), [Key::Char('m')]);
+        assert_eq!(keys.read(b"5"), []);
+        assert_eq!(keys.read(b"~i"), [Key::PageUp, Key::Char('i')]);
+        assert_eq!(keys.read(&"é".as_bytes()[..1]), []);
+        assert_eq!(keys.read(&"é".as_bytes()[1..]), [Key::Char('é')]);
+
+        // esc on its own, once nothing else came
+        assert_eq!(keys.read(b"\x1b"), []);
+        assert_eq!(keys.read(b""), [Key::Esc]);
+        assert_eq!(keys.read(b""), []);
+    }
+
+    #[test]
+    fn it_finds_songs_by_fuzzy_search() {
+        assert!(fuzzy_score("bb", "Blue Bossa") > fuzzy_score("bb", "Bebop"));
+        assert!(fuzzy_score("msty", "Misty") > fuzzy_score("msty", "My Funny Valentine"));
+        assert_eq!(fuzzy_score("água", "Agua de Beber"), fuzzy_score("agua", "Água de Beber"));
+        assert_eq!(fuzzy_score("xyz", "Misty"), None);
+        assert_eq!(fuzzy_score("", "Misty"), Some(0));
+
+        let songs = [song("Blue Bossa", "Kenny Dorham"), song("Misty", "Erroll Garner"), song("Solar", "Miles Davis")];
+        let mut browser = Browser::new(&songs);
+        assert_eq!(browser.song(), Some(0));
+        for c in "mi".chars() {
+            browser.handle(Key::Char(c), 10);
+        }
+        // "Misty" before "Solar Miles Davis"
+        assert_eq!(browser.matches, [1, 2]);
+        assert_eq!(browser.handle(Key::Down, 10), None);
+        assert_eq!(browser.handle(Key::Ctrl('o'), 10), Some(Action::Edit(2)));
+        browser.handle(Key::Char('q'), 10);
+        assert_eq!(browser.handle(Key::Enter, 10), None);
+
+        let screen = browser.draw(&songs, "", 80, 10);
+        assert!(screen.starts_with("\x1b[H> miq"));
+        assert!(screen.contains("0/3"));
+    }
+}
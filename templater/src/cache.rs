@@ -0,0 +1,119 @@
+// every song's rendered bookpart, so rebuilding a big book after
+// editing one song only renders that song again. entries are keyed
+// by a hash of everything a bookpart is rendered from: the parsed
+// song (including its number), the templates, the transposition, and
+// the layout profile.
+// nothing is ever removed, `rm -rf .cache` is always safe.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::PathBuf;
+
+use crate::errors::TemplaterError;
+use crate::models::{BookMode, Song, TemplaterConfig};
+
+pub const DEFAULT_CACHE_DIR: &str = ".cache";
+
+#[derive(Debug)]
+pub struct BookpartCache {
+    dir: PathBuf,
+    // a dry run reads the cache, but doesn't add to it
+    write: bool,
+}
+
+impl BookpartCache {
+    pub fn new<P: Into<PathBuf>>(dir: P, write: bool) -> Self {
+        BookpartCache { dir: dir.into(), write }
+    }
+
+    // `DefaultHasher` can change between rust versions, which only
+    // means a full rebuild. the version covers changes to the
+    // templater itself.
+    pub fn key(song: &Song, templates_hash: u64, conf: &TemplaterConfig, mode: BookMode) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        templates_hash.hash(&mut hasher);
+        conf.transpose_text.hash(&mut hasher);
+        conf.layout.hash(&mut hasher);
+        mode.hash(&mut hasher);
+        song.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.ly", key))
+    }
+
+    pub fn get(&self, key: u64) -> Result<Option<String>, TemplaterError> {
+        let path = self.path(key);
+
+        match fs::read_to_string(&path) {
+            Ok(bookpart) => Ok(Some(bookpart)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(TemplaterError::file(path, e)),
+        }
+    }
+
+    // written to a temporary file first, so an interrupted build
+    // can't leave half a bookpart behind
+    pub fn put(&self, key: u64, bookpart: &str) -> Result<(), TemplaterError> {
+        if !self.write {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir).map_err(|e| TemplaterError::file(&self.dir, e))?;
+
+        let path = self.path(key);
+        let tmp = path.with_extension("ly.tmp");
+        fs::write(&tmp, bookpart).map_err(|e| TemplaterError::file(&tmp, e))?;
+        fs::rename(&tmp, &path).map_err(|e| TemplaterError::file(&path, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::profiles::LayoutProfile;
+    use crate::transpose::transpose_text;
+    use std::path::Path;
+
+    fn song(input: &str) -> Song {
+        Song::parse(Path::new("test.ly"), input, false).unwrap()
+    }
+
+    #[test]
+    fn it_keys_by_song_templates_transposition_and_mode() {
+        let concert = TemplaterConfig { transpose_text: transpose_text("c").unwrap(), layout: None };
+        let bb = TemplaterConfig { transpose_text: transpose_text("bb").unwrap(), layout: None };
+        let large_print = TemplaterConfig { layout: Some(LayoutProfile::LargePrint), ..concert.clone() };
+        let misty = song("title: Misty\n---\n\\chordmode { ees1 }\n");
+        let full = BookMode::Full;
+        let key = BookpartCache::key(&misty, 1, &concert, full);
+
+        assert_eq!(key, BookpartCache::key(&song("title: Misty\n---\n\\chordmode { ees1 }\n"), 1, &concert, full));
+        assert_ne!(key, BookpartCache::key(&song("title: Misty\n---\n\\chordmode { ees1:maj7 }\n"), 1, &concert, full));
+        assert_ne!(key, BookpartCache::key(&misty, 2, &concert, full));
+        assert_ne!(key, BookpartCache::key(&misty, 1, &bb, full));
+        assert_ne!(key, BookpartCache::key(&misty, 1, &concert, BookMode::ChordsOnly));
+        assert_ne!(key, BookpartCache::key(&misty, 1, &large_print, full));
+    }
+
+    #[test]
+    fn it_reads_back_bookparts() {
+        let dir = std::env::temp_dir().join(format!("templater-cache-{}", std::process::id()));
+        let cache = BookpartCache::new(&dir, true);
+
+        assert_eq!(cache.get(42).unwrap(), None);
+        cache.put(42, "\\bookpart {}\n").unwrap();
+        assert_eq!(cache.get(42).unwrap().as_deref(), Some("\\bookpart {}\n"));
+
+        // a dry run doesn't write
+        BookpartCache::new(&dir, false).put(43, "").unwrap();
+        assert_eq!(cache.get(43).unwrap(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
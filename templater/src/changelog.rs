@@ -0,0 +1,212 @@
+// `--changes-since v1.2` (or a date, e.g. 2024-03-01): a "Recent
+// Additions" page after the ToC, with the songs that were added or
+// changed since then. it's whatever `git log` says about the songs
+// directory, so only committed changes are listed.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use minijinja::{context, Value};
+
+use crate::errors::TemplaterError;
+use crate::models::Song;
+use crate::templates::Templates;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Changes {
+    // the ref or date they're since
+    pub since: String,
+    // song files, as they're found in the songs directory
+    pub added: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+}
+
+// e.g. 2024-03-01, anything else is a ref
+pub(crate) fn is_date(since: &str) -> bool {
+    let parts: Vec<&str> = since.split('-').collect();
+    parts.len() == 3
+        && parts.iter().zip([4, 2, 2]).all(|(part, len)| part.len() == len && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+// `git log` of the songs directory. `--relative` makes the paths
+// relative to it. `what` is for the error, e.g. "since 'v1.0'".
+fn git_log(songs_dir: &Path, args: &[&str], what: &str) -> Result<String, TemplaterError> {
+    git(songs_dir, &[&["log", "--relative"], args, &["--", "."]].concat(), what)
+}
+
+// `git -C <songs dir>`, e.g. `git show` for `templater diff`
+pub(crate) fn git(songs_dir: &Path, args: &[&str], what: &str) -> Result<String, TemplaterError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(songs_dir)
+        .args(args)
+        .output()
+        .map_err(|e| TemplaterError::from_str(&format!("Unable to run 'git': {}", e)))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(TemplaterError::from_str(&format!(
+            "git {} of {} {} failed: {}",
+            args.first().unwrap_or(&""),
+            songs_dir.display(),
+            what,
+            stderr.trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// the date every song file was first committed, e.g. "2024-03-01",
+// for `--sort date-added`
+pub fn dates_added<P: AsRef<Path>>(songs_dir: P) -> Result<BTreeMap<PathBuf, String>, TemplaterError> {
+    let songs_dir = songs_dir.as_ref();
+    let log = git_log(songs_dir, &["--reverse", "--diff-filter=A", "--name-only", "--format=date %cs"], "for dates")?;
+    Ok(parse_dates_added(songs_dir, &log))
+}
+
+// oldest first, so a file that was deleted and added again keeps
+// the first date
+fn parse_dates_added(songs_dir: &Path, log: &str) -> BTreeMap<PathBuf, String> {
+    let mut dates = BTreeMap::new();
+    let mut date = "";
+
+    for line in log.lines() {
+        match line.strip_prefix("date ") {
+            Some(commit_date) => date = commit_date,
+            None if line.ends_with(".ly") => {
+                dates.entry(songs_dir.join(line)).or_insert_with(|| date.to_string());
+            },
+            None => {},
+        }
+    }
+
+    dates
+}
+
+impl Changes {
+    // every songs dir's, e.g. with `more_songs_dirs`
+    pub fn from_dirs<P: AsRef<Path>>(songs_dirs: &[P], since: &str) -> Result<Self, TemplaterError> {
+        let mut changes = Changes { since: since.to_string(), ..Default::default() };
+        for songs_dir in songs_dirs {
+            let dir_changes = Changes::from_git(songs_dir, since)?;
+            changes.added.extend(dir_changes.added);
+            changes.modified.extend(dir_changes.modified);
+        }
+        Ok(changes)
+    }
+
+    pub fn from_git<P: AsRef<Path>>(songs_dir: P, since: &str) -> Result<Self, TemplaterError> {
+        let songs_dir = songs_dir.as_ref();
+        let range = match is_date(since) {
+            true => format!("--since={}", since),
+            false => format!("{}..HEAD", since),
+        };
+
+        let log = git_log(songs_dir, &["--name-status", "--format=", &range], &format!("since '{}'", since))?;
+        Ok(Changes::parse(songs_dir, since, &log))
+    }
+
+    // `git log --name-status` lists the newest commit first, so it's
+    // read backwards. a song that was added and then changed is added.
+    pub fn parse(songs_dir: &Path, since: &str, log: &str) -> Self {
+        let mut changes = Changes { since: since.to_string(), ..Default::default() };
+
+        for line in log.lines().rev() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (status, path) = match fields.as_slice() {
+                [status, path] => (*status, *path),
+                // a rename or copy, (status, from, to)
+                [status, _, path] => (*status, *path),
+                _ => continue,
+            };
+            if !path.ends_with(".ly") {
+                continue;
+            }
+
+            let path = songs_dir.join(path);
+            match status.chars().next() {
+                Some('A') | Some('C') => {
+                    changes.modified.retain(|p| *p != path);
+                    if !changes.added.contains(&path) {
+                        changes.added.push(path);
+                    }
+                },
+                Some('M') | Some('R') if !changes.added.contains(&path) && !changes.modified.contains(&path) => {
+                    changes.modified.push(path);
+                },
+                Some('D') => {
+                    changes.added.retain(|p| *p != path);
+                    changes.modified.retain(|p| *p != path);
+                },
+                _ => {},
+            }
+        }
+
+        changes
+    }
+
+    // (added, modified) of `songs`, in book order
+    pub fn songs<'a>(&self, songs: &'a [Song]) -> (Vec<&'a Song>, Vec<&'a Song>) {
+        let added = songs.iter().filter(|song| self.added.contains(&song.path)).collect();
+        let modified = songs.iter().filter(|song| self.modified.contains(&song.path)).collect();
+        (added, modified)
+    }
+
+    // none if none of `songs` changed
+    pub fn render(&self, templates: &Templates, songs: &[Song]) -> Result<Option<String>, TemplaterError> {
+        let (added, modified) = self.songs(songs);
+        if added.is_empty() && modified.is_empty() {
+            return Ok(None);
+        }
+
+        let values = |songs: Vec<&Song>| -> Vec<Value> {
+            songs
+                .iter()
+                .map(|song| {
+                    context! {
+                        title => song.title.as_str(),
+                        composer => song.composer.as_str(),
+                        number => song.number,
+                        label => song.label(),
+                    }
+                })
+                .collect()
+        };
+
+        let page = templates.render(
+            "changes",
+            context! { since => self.since.as_str(), added => values(added), modified => values(modified) },
+        )?;
+        Ok(Some(page))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_reads_what_git_log_lists() {
+        // newest first
+        let log = "M\tmisty.ly\nD\toleo.ly\n\nA\tjazz/nardis.ly\nM\tREADME.md\n\nA\tmisty.ly\nM\toleo.ly\nR087\tstella.ly\tstella-by-starlight.ly\n";
+        let changes = Changes::parse(Path::new("./songs"), "v1.0", log);
+
+        assert_eq!(changes.added, vec![Path::new("./songs/misty.ly"), Path::new("./songs/jazz/nardis.ly")]);
+        assert_eq!(changes.modified, vec![Path::new("./songs/stella-by-starlight.ly")]);
+        assert!(is_date("2024-03-01"));
+        assert!(!is_date("v1.0") && !is_date("HEAD~3"));
+    }
+
+    #[test]
+    fn it_reads_when_every_song_was_added() {
+        // oldest first
+        let log = "date 2021-05-02\n\nmisty.ly\nREADME.md\ndate 2023-01-10\n\njazz/nardis.ly\nmisty.ly\n";
+        let dates = parse_dates_added(Path::new("./songs"), log);
+
+        assert_eq!(dates.len(), 2);
+        assert_eq!(dates[Path::new("./songs/misty.ly")], "2021-05-02");
+        assert_eq!(dates[Path::new("./songs/jazz/nardis.ly")], "2023-01-10");
+    }
+}
@@ -0,0 +1,348 @@
+// `templater check`: validates song files without generating a book.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::backing::BackingTrack;
+use crate::clef::Clef;
+use crate::duplicates::{find_duplicates, Duplicate};
+use crate::errors::TemplaterError;
+use crate::form::{verify_form, Form};
+use crate::frontmatter::{self, Defaults, Frontmatter};
+use crate::harmony::verify_song;
+use crate::keys::Key;
+use crate::license::is_public_domain_license;
+use crate::lint::Linter;
+use crate::melisma::verify_lyrics;
+use crate::models::Song;
+use crate::pickup::verify_pickup;
+use crate::repeats::verify_repeats;
+use crate::style::check_style;
+use crate::transpose::Presets;
+use crate::utils::{get_files_by_ext, this_year};
+use crate::warnings::Warnings;
+
+// anything outside of this is almost certainly a typo
+const BPM_RANGE: std::ops::RangeInclusive<u32> = 20..=400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Issue {
+    fn error<S: Into<String>>(message: S) -> Self {
+        Issue { severity: Severity::Error, message: message.into() }
+    }
+
+    fn warning<S: Into<String>>(message: S) -> Self {
+        Issue { severity: Severity::Warning, message: message.into() }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub songs_checked: usize,
+    // only files with at least one issue, sorted by path
+    pub files: Vec<(PathBuf, Vec<Issue>)>,
+}
+
+impl CheckReport {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.files
+            .iter()
+            .flat_map(|(_, issues)| issues)
+            .filter(|issue| issue.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, issues) in &self.files {
+            writeln!(f, "{}", path.display())?;
+            for issue in issues {
+                writeln!(f, "  [{}] {}", issue.severity, issue.message)?;
+            }
+        }
+
+        write!(
+            f,
+            "checked {} songs: {} errors, {} warnings",
+            self.songs_checked,
+            self.count(Severity::Error),
+            self.count(Severity::Warning),
+        )
+    }
+}
+
+// checks a single song file's contents, with `defaults` under its
+// frontmatter. returns the parsed frontmatter too, if it was valid.
+pub fn check_source(
+    path: &Path,
+    input: &str,
+    defaults: &Defaults,
+    presets: &Presets,
+) -> (Option<Frontmatter>, Vec<Issue>) {
+    let (front_matter, _document) = frontmatter::split(input);
+
+    let front_matter = match Frontmatter::parse_with(path, &front_matter, defaults) {
+        Ok(fm) => fm,
+        Err(TemplaterError::Frontmatter { source, .. }) => {
+            return (None, vec![Issue::error(format!("invalid frontmatter: {}", source))])
+        },
+        Err(e) => return (None, vec![Issue::error(e.to_string())]),
+    };
+
+    let mut issues = vec![];
+
+    if front_matter.title.trim().is_empty() {
+        issues.push(Issue::error("empty title"));
+    }
+
+    if front_matter.composer.as_deref().is_none_or(|c| c.trim().is_empty()) {
+        issues.push(Issue::error("missing composer"));
+    }
+
+    match front_matter.meter.as_deref() {
+        None => issues.push(Issue::warning("missing meter")),
+        Some(meter) if meter != meter.trim() => {
+            issues.push(Issue::warning(format!("meter '{}' has surrounding whitespace", meter)))
+        },
+        Some(_) => {},
+    }
+
+    match &front_matter.bpm {
+        None => issues.push(Issue::warning("missing bpm")),
+        Some(tempo) if !BPM_RANGE.contains(&tempo.slowest) || !BPM_RANGE.contains(&tempo.fastest) => {
+            issues.push(Issue::error(format!(
+                "bpm {} is outside of {}-{}",
+                tempo.mark(),
+                BPM_RANGE.start(),
+                BPM_RANGE.end()
+            )))
+        },
+        Some(_) => {},
+    }
+
+    if let Err(e) = front_matter.is_draft() {
+        issues.push(Issue::error(e));
+    }
+
+    if front_matter.pages == Some(0) {
+        issues.push(Issue::error("pages has to be at least 1"));
+    }
+
+    if let Some(key) = &front_matter.key {
+        if let Err(e) = key.parse::<Key>() {
+            issues.push(Issue::error(format!("invalid key: {}", e)));
+        }
+    }
+
+    if let Some(clef) = &front_matter.clef {
+        if let Err(e) = clef.parse::<Clef>() {
+            issues.push(Issue::error(format!("invalid clef: {}", e)));
+        }
+    }
+
+    if let Some(form) = &front_matter.form {
+        if let Err(e) = form.parse::<Form>() {
+            issues.push(Issue::error(format!("invalid form: {}", e)));
+        }
+    }
+
+    if let Some(key) = &front_matter.transpose_override {
+        if let Err(e) = presets.transpose_text(key) {
+            issues.push(Issue::error(format!("invalid transpose_override: {}", e)));
+        }
+    }
+
+    if let (Some(license), Some(pd_year)) = (&front_matter.license, front_matter.pd_year) {
+        if is_public_domain_license(license) && pd_year > this_year() {
+            issues.push(Issue::warning(format!("license is {}, but pd_year is {}", license, pd_year)));
+        }
+    }
+
+    for key in front_matter.extra.keys() {
+        issues.push(Issue::warning(format!("unknown key '{}'", key)));
+    }
+
+    if let Ok(song) = Song::parse_with(path, input, true, defaults, presets) {
+        let repeats = verify_repeats(&song);
+        issues.extend(repeats.errors.into_iter().map(Issue::error));
+        issues.extend(repeats.warnings.into_iter().map(Issue::warning));
+        issues.extend(verify_song(&song).into_iter().map(Issue::warning));
+        issues.extend(verify_form(&song).map(Issue::warning));
+        issues.extend(verify_pickup(&song).into_iter().map(Issue::warning));
+        issues.extend(verify_lyrics(&song).into_iter().map(Issue::warning));
+        issues.extend(song.backing_track.as_ref().and_then(BackingTrack::verify).map(Issue::warning));
+    }
+
+    (Some(front_matter), issues)
+}
+
+// `linter` also checks every song's lilypond, see `lint.rs`
+// checks every song in a file's contents, and returns the titles of
+// the ones with valid frontmatter, e.g. to look for duplicates
+pub fn check_file(
+    path: &Path,
+    input: &str,
+    defaults: &Defaults,
+    presets: &Presets,
+    linter: Option<&Linter>,
+) -> (Vec<Issue>, Vec<String>) {
+    let songs = frontmatter::split_songs(input);
+    let mut file_issues = vec![];
+    let mut titles = vec![];
+    for (n, (first_line, input)) in songs.iter().enumerate() {
+        let (front_matter, mut issues) = check_source(path, input, defaults, presets);
+        if let Some(linter) = linter {
+            issues.extend(linter.lint(input).into_iter().map(|issue| Issue {
+                severity: issue.severity,
+                message: format!("line {}: {}", issue.line + first_line - 1, issue.message),
+            }));
+        }
+
+        // which one, in a file with more than one song
+        if songs.len() > 1 {
+            let song = front_matter.as_ref().map(|fm| fm.title.clone()).unwrap_or_else(|| format!("song {}", n + 1));
+            for issue in &mut issues {
+                issue.message = format!("{}: {}", song, issue.message);
+            }
+        }
+
+        if let Some(front_matter) = front_matter {
+            titles.push(front_matter.title);
+        }
+        file_issues.extend(issues);
+    }
+
+    file_issues.extend(check_style(input).0.into_iter().map(|issue| {
+        let autofix = if issue.fixable { " (--autofix)" } else { "" };
+        Issue::warning(format!("line {}: {}{}", issue.line, issue.message, autofix))
+    }));
+
+    (file_issues, titles)
+}
+
+pub fn check_songs<P: AsRef<Path>>(
+    songs_dir: P,
+    presets: &Presets,
+    linter: Option<&Linter>,
+    warnings: &mut Warnings,
+) -> Result<CheckReport, TemplaterError> {
+    let mut paths = get_files_by_ext(&songs_dir, "ly", warnings)?;
+    paths.sort();
+    let defaults = Defaults::load(&songs_dir)?;
+
+    let mut issues_by_path: Vec<(PathBuf, Vec<Issue>)> = vec![];
+    // (index into `issues_by_path`, title)
+    let mut titles: Vec<(usize, String)> = vec![];
+
+    let mut songs_checked = 0;
+    for path in paths {
+        let input = match fs::read_to_string(&path) {
+            Ok(input) => input,
+            Err(e) => {
+                songs_checked += 1;
+                issues_by_path.push((path, vec![Issue::error(format!("unable to read file: {}", e))]));
+                continue;
+            },
+        };
+
+        let (file_issues, file_titles) = check_file(&path, &input, &defaults, presets, linter);
+        titles.extend(file_titles.into_iter().map(|title| (issues_by_path.len(), title)));
+
+        songs_checked += frontmatter::split_songs(&input).len();
+        issues_by_path.push((path, file_issues));
+    }
+
+    let title_strs: Vec<&str> = titles.iter().map(|(_, title)| title.as_str()).collect();
+    for (a, b, duplicate) in find_duplicates(&title_strs) {
+        let (i, j) = (titles[a].0, titles[b].0);
+        let issue = |other: usize, other_title: &str| match duplicate {
+            Duplicate::Same => Issue::error(format!("duplicate title, also used by {}", issues_by_path[other].0.display())),
+            Duplicate::Similar => Issue::warning(format!(
+                "title is very similar to '{}' in {}",
+                other_title,
+                issues_by_path[other].0.display()
+            )),
+        };
+        let (issue_i, issue_j) = (issue(j, &titles[b].1), issue(i, &titles[a].1));
+        issues_by_path[i].1.push(issue_i);
+        issues_by_path[j].1.push(issue_j);
+    }
+
+    Ok(CheckReport {
+        songs_checked,
+        files: issues_by_path
+            .into_iter()
+            .filter(|(_, issues)| !issues.is_empty())
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(input: &str) -> Vec<Issue> {
+        check_source(Path::new("test.ly"), input, &Defaults::default(), &Presets::default()).1
+    }
+
+    #[test]
+    fn it_accepts_complete_frontmatter() {
+        assert_eq!(check("title: Oleo\ncomposer: Sonny Rollins\nmeter: Fast\nbpm: 240\n---\n"), vec![]);
+    }
+
+    #[test]
+    fn it_reports_incomplete_frontmatter() {
+        let issues = check("title: Oleo\nbpm: 4000\nyear: 1954\n---\n");
+
+        assert_eq!(
+            issues,
+            vec![
+                Issue::error("missing composer"),
+                Issue::warning("missing meter"),
+                Issue::error("bpm 4000 is outside of 20-400"),
+                Issue::warning("unknown key 'year'"),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_a_public_domain_song_that_isnt_yet() {
+        let issues = check("title: Oleo\ncomposer: Sonny Rollins\nmeter: Fast\nbpm: 240\nlicense: PD\npd_year: 9999\n---\n");
+
+        assert_eq!(issues, vec![Issue::warning("license is PD, but pd_year is 9999")]);
+    }
+
+    #[test]
+    fn it_reports_invalid_frontmatter() {
+        let issues = check("composer: Sonny Rollins\n---\n");
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Error);
+    }
+}
@@ -0,0 +1,258 @@
+// slash chords and polychords. a slash chord, e.g. `g:7/b`, or `c/+e`
+// to add the bass note instead of inverting the chord, is lilypond's
+// own and is transposed with the rest of the chords. a polychord is
+// the upper chord over the lower one, e.g. `d2//c:7` for D over C7,
+// with the duration after the first root like any other chord:
+//
+//     \chordmode { c1:maj7 | d2//c:7 g:7.9-/b }
+//
+// lilypond doesn't have those, so before a song is rendered it's
+// written as the lower chord, which is what's played, with both
+// names stacked over a line by `\polychord` in `includes/chords.ily`.
+// lilypond can't transpose a name, so the templater names both
+// chords in the book's key itself.
+
+use std::fmt;
+
+use crate::harmony::parse_pitch;
+use crate::numbers::quality;
+use crate::transpose::{parse_lilypond_pitch, Pitch};
+
+// between a polychord's upper and lower chords
+pub const POLYCHORD: &str = "//";
+
+// what ends a chord, besides whitespace
+const DELIMITERS: &str = "{}|()<>~\"%";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    pub root: Pitch,
+    // as written, e.g. "2." or "1*2"
+    pub duration: String,
+    // after the `:`, e.g. "7.9-"
+    pub steps: String,
+    pub bass: Option<Bass>,
+}
+
+// after the `/`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bass {
+    pub pitch: Pitch,
+    // `/+`, added below the chord instead of inverting it
+    pub added: bool,
+}
+
+impl Chord {
+    // e.g. "bes2.:m7/f" or "c/+e", none if it isn't a chord
+    pub fn parse(token: &str) -> Option<Chord> {
+        let root = parse_pitch(&token[..root_len(token)])?;
+        let rest = &token[root_len(token)..];
+        let (duration, rest) = rest.split_at(rest.find([':', '/']).unwrap_or(rest.len()));
+        if !duration.chars().all(|c| c.is_ascii_digit() || c == '.' || c == '*') {
+            return None;
+        }
+
+        let (steps, bass) = match rest.split_once('/') {
+            Some((steps, bass)) => (steps, Some(bass)),
+            None => (rest, None),
+        };
+        let bass = match bass {
+            Some(bass) => {
+                let pitch = bass.trim_start_matches('+');
+                if root_len(pitch) != pitch.len() {
+                    return None;
+                }
+                Some(Bass { pitch: parse_pitch(pitch)?, added: bass.starts_with('+') })
+            },
+            None => None,
+        };
+
+        Some(Chord { root, duration: duration.to_string(), steps: steps.trim_start_matches(':').to_string(), bass })
+    }
+
+    // moved up by the interval from `c` to `to`, the root and the bass
+    // spelled the way lilypond's `\transpose c <to>` would
+    pub fn transpose(&self, to: &Pitch) -> Chord {
+        Chord {
+            root: self.root.transpose(to),
+            bass: self.bass.map(|bass| Bass { pitch: bass.pitch.transpose(to), ..bass }),
+            ..self.clone()
+        }
+    }
+
+    // the name as markup, e.g. `\concat { "B" \flat "m7/F" }`
+    pub fn markup(&self) -> String {
+        let mut markup = vec![pitch_markup(&self.root)];
+        let mut text = quality(&self.steps);
+        if let Some(bass) = &self.bass {
+            markup.push(format!("\"{}/\"", text));
+            text = String::new();
+            markup.push(pitch_markup(&bass.pitch));
+        }
+        if !text.is_empty() {
+            markup.push(format!("\"{}\"", text));
+        }
+
+        format!("\\concat {{ {} }}", markup.join(" "))
+    }
+}
+
+// e.g. "bes2.:m7/f"
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.root.lilypond_name(), self.duration)?;
+        if !self.steps.is_empty() {
+            write!(f, ":{}", self.steps)?;
+        }
+        if let Some(bass) = &self.bass {
+            write!(f, "/{}{}", if bass.added { "+" } else { "" }, bass.pitch.lilypond_name())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Polychord {
+    pub upper: Chord,
+    // with the duration
+    pub lower: Chord,
+}
+
+impl Polychord {
+    // e.g. "d2//c:7"
+    pub fn parse(token: &str) -> Option<Polychord> {
+        let (upper, lower) = token.split_once(POLYCHORD)?;
+        let (mut upper, mut lower) = (Chord::parse(upper)?, Chord::parse(lower)?);
+        if lower.duration.is_empty() {
+            lower.duration = std::mem::take(&mut upper.duration);
+        }
+        upper.duration.clear();
+
+        Some(Polychord { upper, lower })
+    }
+
+    // what lilypond reads instead, named in the key of `\transpose c <to>`
+    pub fn lilypond(&self, to: &Pitch) -> String {
+        format!(
+            "\\polychord \\markup {} \\markup {} {}",
+            self.upper.transpose(to).markup(),
+            self.lower.transpose(to).markup(),
+            self.lower
+        )
+    }
+}
+
+// how long the pitch at the start of `token` is, e.g. 3 for "bes2"
+fn root_len(token: &str) -> usize {
+    token.find(|c: char| !c.is_ascii_lowercase()).unwrap_or(token.len())
+}
+
+// e.g. `"B" \flat`
+fn pitch_markup(pitch: &Pitch) -> String {
+    let name = pitch.display_name();
+    let accidental = match pitch.alteration {
+        -2 => " \\doubleflat",
+        -1 => " \\flat",
+        1 => " \\sharp",
+        2 => " \\doublesharp",
+        _ => "",
+    };
+    format!("\"{}\"{}", &name[..1], accidental)
+}
+
+// `chords` with every polychord written for lilypond, for a book that's
+// `\transpose`d by `transpose`, e.g. "c d"
+pub fn polychords(chords: &str, transpose: &str) -> String {
+    let to = transpose.split_whitespace().nth(1).and_then(|to| parse_lilypond_pitch(to).ok());
+    let Some(to) = to.filter(|_| chords.contains(POLYCHORD)) else {
+        return chords.to_string();
+    };
+
+    replace_chords(chords, |token| Polychord::parse(token).map(|polychord| polychord.lilypond(&to)))
+}
+
+// `chords` with every token `replace` returns something for replaced.
+// strings, e.g. a `\boxMark`, and comments are kept as they are
+pub(crate) fn replace_chords(chords: &str, mut replace: impl FnMut(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(chords.len());
+    let mut i = 0;
+    while let Some(c) = chords[i..].chars().next() {
+        let rest = &chords[i..];
+        let len = match c {
+            '"' => quoted_len(rest),
+            '%' => comment_len(rest),
+            c if c.is_whitespace() || DELIMITERS.contains(c) => c.len_utf8(),
+            c => {
+                let word = &rest[c.len_utf8()..];
+                let end = word.find(|c: char| c.is_whitespace() || DELIMITERS.contains(c));
+                c.len_utf8() + end.unwrap_or(word.len())
+            },
+        };
+
+        let token = &rest[..len];
+        let replaced = match c {
+            '"' | '%' => None,
+            _ => replace(token),
+        };
+        output.push_str(replaced.as_deref().unwrap_or(token));
+        i += len;
+    }
+
+    output
+}
+
+// up to and with the closing quote
+fn quoted_len(rest: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return i + 1,
+            _ => escaped = false,
+        }
+    }
+
+    rest.len()
+}
+
+// a `%` line comment, or a `%{ %}` block comment
+fn comment_len(rest: &str) -> usize {
+    match rest.starts_with("%{") {
+        true => rest.find("%}").map_or(rest.len(), |end| end + 2),
+        false => rest.find('\n').unwrap_or(rest.len()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transposed(chord: &str, to: &str) -> String {
+        Chord::parse(chord).unwrap().transpose(&to.parse().unwrap()).to_string()
+    }
+
+    #[test]
+    fn it_transposes_altered_and_slash_chords() {
+        assert_eq!(transposed("g2.:7.9-/b", "d"), "a2.:7.9-/cis");
+        assert_eq!(transposed("ees:m7.5-/+ges", "a"), "c:m7.5-/+ees");
+        assert_eq!(transposed("bes1*2:7.9+.13-/aes", "d"), "c1*2:7.9+.13-/bes");
+        assert_eq!(transposed("fis:dim7/c", "ees"), "a:dim7/ees");
+        assert_eq!(transposed("c:7^5", "c"), "c:7^5");
+        assert_eq!(Chord::parse("r1"), None);
+        assert_eq!(Chord::parse("c:7/bass"), None);
+    }
+
+    #[test]
+    fn it_names_polychords_in_the_books_key() {
+        let chords = "c1:maj7 | d2//c:7 \"d//c\" ees:m7//f/+a % d//c\n";
+        assert_eq!(polychords(chords, "c c"), polychords(chords, "c c'"));
+        assert_eq!(
+            polychords(chords, "c d"),
+            "c1:maj7 | \\polychord \\markup \\concat { \"E\" } \\markup \\concat { \"D\" \"7\" } c2:7 \"d//c\" \
+             \\polychord \\markup \\concat { \"F\" \"m7\" } \\markup \\concat { \"G\" \"/\" \"B\" } f/+a % d//c\n"
+        );
+        let polychord = Polychord::parse("bes//aes:7").unwrap();
+        assert!(polychord.lilypond(&"c".parse().unwrap()).ends_with(" aes:7"));
+        assert_eq!(polychords("c1 | g:7/b", "c a"), "c1 | g:7/b");
+    }
+}
@@ -0,0 +1,187 @@
+// `--normalize-chords`: every chord is respelled in the book's house
+// style before it's rendered, so the same chord is named the same way
+// in every song, whoever entered it. e.g. `c:7+`, `c:maj`, and `c:M7`
+// are all `c:maj7`, the spellings `musicxml::CHORD_KINDS` uses.
+// `chord_spellings` in `templater.toml` picks others, or respells
+// anything else:
+//
+//     normalize_chords = true
+//
+//     [chord_spellings]
+//     "maj7" = "7+"
+//     "sus4" = "sus"
+//
+// only the quality after the `:` changes, not the root, the duration,
+// or a `/` bass note, in both chords of a polychord. the song files
+// stay as they are, and the log says what was respelled in each one.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::chord::{replace_chords, POLYCHORD};
+use crate::harmony::parse_pitch;
+use crate::models::Song;
+
+// (spelling, the built in one)
+const SPELLINGS: [(&str, &str); 30] = [
+    ("maj", "maj7"),
+    ("7+", "maj7"),
+    ("M7", "maj7"),
+    ("Maj7", "maj7"),
+    ("ma7", "maj7"),
+    ("min", "m"),
+    ("mi", "m"),
+    ("-", "m"),
+    ("min7", "m7"),
+    ("mi7", "m7"),
+    ("-7", "m7"),
+    ("m7b5", "m7.5-"),
+    ("ø", "m7.5-"),
+    ("ø7", "m7.5-"),
+    ("mmaj7", "m7+"),
+    ("mM7", "m7+"),
+    ("o", "dim"),
+    ("o7", "dim7"),
+    ("+", "aug"),
+    ("5+", "aug"),
+    ("sus", "sus4"),
+    ("69", "6.9"),
+    ("7b5", "7.5-"),
+    ("7#5", "7.5+"),
+    ("7b9", "7.9-"),
+    ("7#9", "7.9+"),
+    ("7#11", "7.11+"),
+    ("9-", "7.9-"),
+    ("9+", "7.9+"),
+    ("7sus", "7sus4"),
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct ChordSpellings {
+    house: BTreeMap<String, String>,
+}
+
+// how often each quality was respelled, e.g. ("7+", "maj7") -> 2
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Respellings(pub BTreeMap<(String, String), usize>);
+
+impl Respellings {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// e.g. "7+ as maj7 (2), M7 as maj7"
+impl fmt::Display for Respellings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let respellings: Vec<String> = self
+            .0
+            .iter()
+            .map(|((from, to), count)| match count {
+                1 => format!("{} as {}", from, to),
+                _ => format!("{} as {} ({})", from, to, count),
+            })
+            .collect();
+        write!(f, "{}", respellings.join(", "))
+    }
+}
+
+impl ChordSpellings {
+    // `chord_spellings`, which win over the built in ones
+    pub fn new(house: &BTreeMap<String, String>) -> Self {
+        ChordSpellings { house: house.clone() }
+    }
+
+    // e.g. "7+" -> "maj7". a house spelling of the built in one is
+    // used for all of its spellings
+    pub fn respell<'a>(&'a self, quality: &'a str) -> &'a str {
+        let builtin = SPELLINGS.iter().find(|(spelling, _)| *spelling == quality);
+        let builtin = builtin.map_or(quality, |(_, builtin)| builtin);
+        self.house.get(builtin).or_else(|| self.house.get(quality)).map_or(builtin, String::as_str)
+    }
+
+    // "ees2.:7+/g" -> "ees2.:maj7/g", none if it's not a chord or its
+    // spelling doesn't change
+    fn respell_chord(&self, token: &str, respellings: &mut Respellings) -> Option<String> {
+        let (root, rest) = token.split_once(':')?;
+        parse_pitch(root)?;
+        let (quality, bass) = match rest.split_once('/') {
+            Some((quality, bass)) => (quality, Some(bass)),
+            None => (rest, None),
+        };
+
+        let respelled = self.respell(quality);
+        if respelled == quality {
+            return None;
+        }
+        *respellings.0.entry((quality.to_string(), respelled.to_string())).or_default() += 1;
+        let bass = bass.map(|bass| format!("/{}", bass)).unwrap_or_default();
+        Some(format!("{}:{}{}", root, respelled, bass))
+    }
+
+    // `chords` with every chord respelled, both of a polychord's.
+    // strings, e.g. a `\boxMark`, and comments are kept as they are
+    pub fn apply(&self, chords: &str, respellings: &mut Respellings) -> String {
+        replace_chords(chords, |token| match token.split_once(POLYCHORD) {
+            Some((upper, lower)) => {
+                let (respelled_upper, respelled_lower) =
+                    (self.respell_chord(upper, respellings), self.respell_chord(lower, respellings));
+                if respelled_upper.is_none() && respelled_lower.is_none() {
+                    return None;
+                }
+                let upper = respelled_upper.as_deref().unwrap_or(upper);
+                let lower = respelled_lower.as_deref().unwrap_or(lower);
+                Some(format!("{}{}{}", upper, POLYCHORD, lower))
+            },
+            None => self.respell_chord(token, respellings),
+        })
+    }
+
+    // respells the song's chords and chord variants
+    pub fn apply_to(&self, song: &mut Song) -> Respellings {
+        let mut respellings = Respellings::default();
+        song.chords = self.apply(&song.chords, &mut respellings);
+        for variant in &mut song.variants {
+            variant.chords = self.apply(&variant.chords, &mut respellings);
+        }
+
+        respellings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_respells_chords_in_the_house_style() {
+        let builtin = ChordSpellings::default();
+        let mut respellings = Respellings::default();
+        let chords = "\\boxMark \"c:7+\" ees1:7+ | bes2.:M7/f as:m7 % c:7+\n  f1*2:maj | g:-7 }";
+
+        assert_eq!(
+            builtin.apply(chords, &mut respellings),
+            "\\boxMark \"c:7+\" ees1:maj7 | bes2.:maj7/f as:m7 % c:7+\n  f1*2:maj7 | g:m7 }"
+        );
+        assert_eq!(respellings.to_string(), "-7 as m7, 7+ as maj7, M7 as maj7, maj as maj7");
+
+        let house: BTreeMap<String, String> = [("maj7", "7+"), ("sus4", "sus")]
+            .into_iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect();
+        let house = ChordSpellings::new(&house);
+        assert_eq!(house.respell("maj7"), "7+");
+        assert_eq!(house.respell("M7"), "7+");
+        assert_eq!(house.respell("7+"), "7+");
+        assert_eq!(house.respell("sus"), "sus");
+        assert_eq!(house.respell("m7"), "m7");
+    }
+
+    #[test]
+    fn it_counts_what_was_respelled() {
+        let mut respellings = Respellings::default();
+        let chords = ChordSpellings::default().apply("c1:7+ f:7+ bes:sus s1 r d2//c:M7 e:-//d", &mut respellings);
+        assert_eq!(chords, "c1:maj7 f:maj7 bes:sus4 s1 r d2//c:maj7 e:m//d");
+        assert_eq!(respellings.to_string(), "- as m, 7+ as maj7 (2), M7 as maj7, sus as sus4");
+    }
+}
@@ -0,0 +1,76 @@
+// a song's `clef` in the front matter, e.g. `clef: bass` for a
+// trombone tune or `clef: treble_8` for a tenor's, which every voice
+// starts in instead of treble. a transposition with a clef of its
+// own, e.g. `bass`, is for an instrument that reads in it, so the
+// book's clef wins.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::TemplaterError;
+
+// lilypond's, see "Clef styles" in its notation reference
+const CLEFS: [&str; 17] = [
+    "treble", "violin", "G", "G2", "french", "soprano", "mezzosoprano", "alto", "C", "tenor", "baritone", "varbaritone",
+    "bass", "F", "subbass", "percussion", "tab",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Clef {
+    pub name: String,
+}
+
+// `\clef`'s argument. a transposed clef, e.g. `treble_8`, has to be
+// quoted or the `_8` is read as a fingering
+pub fn lilypond_clef(name: &str) -> String {
+    match name.chars().all(char::is_alphanumeric) {
+        true => name.to_string(),
+        false => format!("\"{}\"", name),
+    }
+}
+
+impl FromStr for Clef {
+    type Err = TemplaterError;
+
+    // e.g. "bass", "treble_8", or "treble^15"
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim().trim_matches('"');
+        let (clef, octave) = match input.find(['_', '^']) {
+            Some(i) => input.split_at(i),
+            None => (input, ""),
+        };
+
+        if !CLEFS.contains(&clef) || !matches!(octave.get(1..), None | Some("8" | "15")) {
+            return Err(TemplaterError::from_str(&format!(
+                "'{}' is not a clef lilypond knows, e.g. treble, bass, alto, or treble_8.",
+                input
+            )));
+        }
+
+        Ok(Clef { name: input.to_string() })
+    }
+}
+
+impl fmt::Display for Clef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_reads_lilypond_clefs() {
+        assert_eq!("bass".parse::<Clef>().unwrap().name, "bass");
+        assert_eq!("\"treble_8\"".parse::<Clef>().unwrap().name, "treble_8");
+        assert!("tenor^15".parse::<Clef>().is_ok());
+        assert!("Bass".parse::<Clef>().is_err());
+        assert!("treble_7".parse::<Clef>().is_err());
+        assert!("treble_".parse::<Clef>().is_err());
+
+        assert_eq!(lilypond_clef("bass"), "bass");
+        assert_eq!(lilypond_clef("treble_8"), "\"treble_8\"");
+    }
+}
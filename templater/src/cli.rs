@@ -0,0 +1,421 @@
+// the `templater` subcommands and the flags each one takes, which
+// is also where `--help` comes from. `main.rs` only parses the flags
+// of the subcommand that's run, so any other flag is an unused
+// argument (an error with `--strict`).
+
+use crate::errors::TemplaterError;
+
+#[derive(Debug)]
+pub struct Flag {
+    pub name: &'static str,
+    // e.g. "key", none for a switch
+    pub value: Option<&'static str>,
+    // continued on the next line after a \n
+    pub help: &'static str,
+}
+
+const fn flag(name: &'static str, value: &'static str, help: &'static str) -> Flag {
+    Flag { name, value: Some(value), help }
+}
+
+const fn switch(name: &'static str, help: &'static str) -> Flag {
+    Flag { name, value: None, help }
+}
+
+// flags that go together, e.g. every flag that selects songs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagGroup {
+    // every subcommand
+    Common,
+    // which songs, and how they're read
+    Songs,
+    // the book that's written
+    Book,
+    Check,
+    // print what would change instead of writing it
+    DryRun,
+    New,
+    Export,
+    Import,
+    Web,
+    Serve,
+}
+
+impl FlagGroup {
+    pub fn flags(&self) -> &'static [Flag] {
+        match self {
+            FlagGroup::Common => COMMON_FLAGS,
+            FlagGroup::Songs => SONG_FLAGS,
+            FlagGroup::Book => BOOK_FLAGS,
+            FlagGroup::Check => CHECK_FLAGS,
+            FlagGroup::DryRun => DRY_RUN_FLAGS,
+            FlagGroup::New => NEW_FLAGS,
+            FlagGroup::Export => EXPORT_FLAGS,
+            FlagGroup::Import => IMPORT_FLAGS,
+            FlagGroup::Web => WEB_FLAGS,
+            FlagGroup::Serve => SERVE_FLAGS,
+        }
+    }
+}
+
+const COMMON_FLAGS: &[Flag] = &[
+    flag("--config", "file", "path to a config file (default ./templater.toml)"),
+    flag("--songs-dir", "dir", "directory to search for songs (default ./songs). can be repeated to merge them"),
+    flag("--templates-dir", "dir", "directory containing the templates (default ./templates)"),
+    flag("--transpositions", "file", "file with more --transpose presets by name (default ./transpositions.toml)"),
+    switch("--strict", "fail on any warning, e.g. an unknown frontmatter key, a missing bpm, or an unused argument"),
+];
+
+const SONG_FLAGS: &[Flag] = &[
+    flag("--only", "titles", "quoted, comma delimited list of song titles to include. * and ? globs allowed"),
+    flag("--song-names", "titles", "same as --only"),
+    flag("--composers", "names", "quoted, comma delimited list of composers to filter by"),
+    flag(
+        "--filter",
+        "field=pattern",
+        "e.g. composer=Ellington or title=\"All the*\". can be repeated\nfields: title, composer, poet, arranger, meter, tag",
+    ),
+    flag("--setlist", "file", "file with one song title per line. a book of only those songs, in that order"),
+    flag("--sort", "order", "the order of the songs and the ToC: title (the default), composer, key, bpm, or date-added"),
+    switch("--include-drafts", "include the songs with status: draft"),
+    switch("--public-domain-only", "leave out every song that isn't in the public domain (license or pd_year) this year"),
+    switch("--folder-tags", "tag every song with the folders it's in, e.g. bossa for songs/bossa/"),
+    flag(
+        "--duplicate-titles",
+        "keep",
+        "which song a title in more than one --songs-dir keeps: error (the default), first, or last",
+    ),
+    switch("--lyrics", "include lyrics"),
+    flag("--song-ids", "file", "file with the stable song numbers (default ./songids.toml)"),
+    flag("--composer-aliases", "file", "file with alternate spellings of composers (default ./composers.toml)"),
+    switch("--lint-ly", "check every song's braces, durations, and commands before rendering"),
+    switch("--normalize-chords", "respell every chord in the house style (chord_spellings), e.g. c:7+ as c:maj7"),
+];
+
+const BOOK_FLAGS: &[Flag] = &[
+    flag(
+        "--transpose",
+        "key",
+        "instrument key, e.g. c, bb, eb, f, or bass. append +8va/-8vb to shift octaves\n\
+         or a preset from transpositions.toml. a comma delimited list (c,bb,eb) generates one book per key",
+    ),
+    flag(
+        "--spelling",
+        "policy",
+        "how a transposed song's key is spelled: simplest (the default), the key with fewer accidentals\n\
+         sharps or flats, the same but F# or Gb for a tie, or as-written, lilypond's by letter",
+    ),
+    flag(
+        "--mode",
+        "mode",
+        "full (default) or chords-only, a book of only every song's chord grid\n\
+         or lyrics, a book of only the words. --output's extension picks .md (default), .txt, or .tex\n\
+         or guitar, the lead sheets with a fret diagram above every chord\n\
+         or numbers, every song's changes as roman numerals of its key\n\
+         or braille, every song's melody and chords in braille music, as a .brf file",
+    ),
+    switch("--guitar", "same as --mode guitar"),
+    flag("--voicings", "file", "file with the fret diagrams to use for a guitar book (default ./voicings.toml)"),
+    flag(
+        "--layout",
+        "profile",
+        "the paper and staff size: a4, letter, tablet (landscape, for a screen), or large-print\n\
+         adds its name to the book's, e.g. openbook-Bb-large-print.ly",
+    ),
+    switch("--large-print", "same as --layout large-print: bigger staves, chord names, and lyrics, with more space"),
+    flag("--indexes", "kinds", "comma delimited list of extra indices (composer,meter,bpm,key,tag,time). empty for none"),
+    flag("--out-dir", "dir", "directory the book(s) are written to, created if missing (default .)"),
+    flag(
+        "--output",
+        "file",
+        "output filename, {key} and {date} are filled in (default openbook-{key}.ly). - writes to stdout",
+    ),
+    flag(
+        "--metadata",
+        "field=value",
+        "for the title page, e.g. title=\"The Gig Book\". can be repeated\n\
+         fields: title, subtitle, edition, date, editor, copyright",
+    ),
+    flag("--split-volumes", "count", "split the book into this many volumes, e.g. 2 for A-L and M-Z"),
+    flag("--split-at", "letters", "comma delimited letters each volume starts at, e.g. M or H,P"),
+    flag("--manuscript-pages", "pages", "add this many pages of blank staves at the end of the book"),
+    flag("--chord-grid-pages", "pages", "add this many pages of blank chord grids at the end of the book"),
+    switch("--pdf", "run lilypond on the generated book(s)"),
+    flag("--lilypond-flags", "flags", "quoted, space delimited flags passed to lilypond"),
+    switch("--midi", "also write a concert pitch book of one midi file per song, tempo from bpm"),
+    switch("--html-index", "also write an index.html of the songs, linking to every book's pdf"),
+    switch("--per-song", "also write every song as its own lead sheet, e.g. openbook-Bb-songs/042-misty.ly"),
+    flag(
+        "--changes-since",
+        "ref",
+        "git ref or date (e.g. v1.0 or 2024-03-01). adds a page of the songs added or changed since",
+    ),
+    switch("--no-dividers", "don't add a page with the letter before each group of songs"),
+    switch("--toc-form", "add every song's form (e.g. 32-bar AABA) or number of measures to the ToC"),
+    switch("--form-summary", "add every song's form and jumps (e.g. AABA with D.S. al Coda) under its title"),
+    switch("--no-cache", "render every song again instead of reusing ./.cache"),
+    switch("--force", "overwrite a book even if it was edited since it was written"),
+];
+
+const CHECK_FLAGS: &[Flag] = &[
+    switch("--autofix", "fix the front matter's style, e.g. a lowercase word in a title, in place"),
+    switch("--lint-ly", "also check every song's braces, durations, and commands"),
+];
+
+const DRY_RUN_FLAGS: &[Flag] = &[switch("--dry-run", "print what would change instead of writing anything")];
+
+const NEW_FLAGS: &[Flag] = &[switch("-i/--interactive", "ask for every field that wasn't given")];
+
+const EXPORT_FLAGS: &[Flag] = &[flag("--format", "format", "json, csv, abc, or ireal (default: from the extension)")];
+
+const IMPORT_FLAGS: &[Flag] = &[flag("--format", "format", "musicxml or abc (default: from the file's extension)")];
+
+const WEB_FLAGS: &[Flag] = &[switch("--snippets", "add every song's lead sheet to its page, rendered by lilypond as an svg")];
+
+const SERVE_FLAGS: &[Flag] = &[
+    flag("--port", "port", "answer requests POSTed to http://127.0.0.1:<port> instead of stdin"),
+    flag("--allow-origin", "origin", "answer a web page's requests from the origin, e.g. http://localhost:5173"),
+];
+
+#[derive(Debug)]
+pub struct Subcommand {
+    pub name: &'static str,
+    // what comes after the flags, e.g. "<file>"
+    pub args: &'static str,
+    pub about: &'static str,
+    pub groups: &'static [FlagGroup],
+}
+
+impl Subcommand {
+    pub fn takes(&self, group: FlagGroup) -> bool {
+        self.groups.contains(&group)
+    }
+}
+
+// `build` is what runs without a subcommand
+pub const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "build",
+        args: "",
+        about: "write the book(s), the default",
+        groups: &[FlagGroup::Common, FlagGroup::Songs, FlagGroup::Book, FlagGroup::DryRun],
+    },
+    Subcommand {
+        name: "watch",
+        args: "",
+        about: "build, then rebuild whenever a song or template changes",
+        groups: &[FlagGroup::Common, FlagGroup::Songs, FlagGroup::Book],
+    },
+    Subcommand {
+        name: "check",
+        args: "",
+        about: "validate every song file without generating a book",
+        groups: &[FlagGroup::Common, FlagGroup::Check],
+    },
+    Subcommand {
+        name: "new",
+        args: "<title> [field=value ...]",
+        about: "create a song file in the songs dir, e.g. new \"Blue Bossa\" composer=\"Kenny Dorham\" key=cm\n\
+                fields: composer, meter, bpm, key",
+        groups: &[FlagGroup::Common, FlagGroup::New],
+    },
+    Subcommand {
+        name: "export",
+        args: "<file>",
+        about: "write every selected song's metadata to a .json or .csv file, or the songs as abc or iReal Pro charts",
+        groups: &[FlagGroup::Common, FlagGroup::Songs, FlagGroup::Export],
+    },
+    Subcommand {
+        name: "release",
+        args: "<version>",
+        about: "build the pdfs of every transposition, named with the version, in dist/<version>, with\n\
+                the changes since the last release and a SHA256SUMS file",
+        groups: &[FlagGroup::Common, FlagGroup::Songs, FlagGroup::Book],
+    },
+    Subcommand {
+        name: "stats",
+        args: "[file]",
+        about: "print how many selected songs there are by composer, decade, meter, and key,\n\
+                and their average length in measures. also written to the .json file if given",
+        groups: &[FlagGroup::Common, FlagGroup::Songs],
+    },
+    Subcommand {
+        name: "web",
+        args: "[dir]",
+        about: "write a static site to the dir (default ./web in the out dir): an index.html that filters the\n\
+                selected songs by title, composer, or tag as you type, and a page for every song",
+        groups: &[FlagGroup::Common, FlagGroup::Songs, FlagGroup::Web],
+    },
+    Subcommand {
+        name: "browse",
+        args: "",
+        about: "search the selected songs in the terminal, with a preview of each one's front matter and\n\
+                bookpart. enter writes its lead sheet, ctrl-o opens it in $EDITOR",
+        groups: &[FlagGroup::Common, FlagGroup::Songs, FlagGroup::Book],
+    },
+    Subcommand {
+        name: "serve",
+        args: "",
+        about: "read the songs once, then answer json-rpc requests from an editor or a web frontend, one per\n\
+                line on stdin: songs, parse, check, render, and reload",
+        groups: &[FlagGroup::Common, FlagGroup::Songs, FlagGroup::Book, FlagGroup::Serve],
+    },
+    Subcommand {
+        name: "lsp",
+        args: "",
+        about: "a language server for song files, over stdin and stdout: diagnostics, completion of\n\
+                frontmatter keys and chords, and a song's stats on hover",
+        groups: &[FlagGroup::Common],
+    },
+    Subcommand {
+        name: "diff",
+        args: "<old> [new]",
+        about: "print the songs that were added, removed, retitled, or changed between two books, songs dirs,\n\
+                or git revisions of the songs dir (new is the songs dir if there's none), for release notes",
+        groups: &[FlagGroup::Common],
+    },
+    Subcommand {
+        name: "drafts",
+        args: "",
+        about: "list the songs with status: draft",
+        groups: &[FlagGroup::Common],
+    },
+    Subcommand {
+        name: "vars",
+        args: "[template]",
+        about: "list the variables every template (or the given one) gets",
+        groups: &[FlagGroup::Common],
+    },
+    Subcommand {
+        name: "migrate",
+        args: "",
+        about: "upgrade every song's front matter to the current keys and order",
+        groups: &[FlagGroup::Common, FlagGroup::DryRun],
+    },
+    Subcommand {
+        name: "import",
+        args: "<file>",
+        about: "write a song file from a musicxml file, or one for every tune in an abc file",
+        groups: &[FlagGroup::Common, FlagGroup::Import, FlagGroup::DryRun],
+    },
+    Subcommand {
+        name: "convert-absolute",
+        args: "[song.ly ...]",
+        about: "rewrite the \\relative blocks of the given songs (or every song) in absolute pitch",
+        groups: &[FlagGroup::Common, FlagGroup::DryRun],
+    },
+    Subcommand {
+        name: "normalize",
+        args: "[song.ly ...]",
+        about: "rewrite the bar checks of the given songs (or every song) to one at every bar line",
+        groups: &[FlagGroup::Common, FlagGroup::DryRun],
+    },
+];
+
+pub fn subcommand(name: Option<&str>) -> Result<&'static Subcommand, TemplaterError> {
+    let name = name.unwrap_or("build");
+    SUBCOMMANDS.iter().find(|subcommand| subcommand.name == name).ok_or_else(|| {
+        let names: Vec<&str> = SUBCOMMANDS.iter().map(|subcommand| subcommand.name).collect();
+        TemplaterError::from_str(&format!("Unknown subcommand '{}'. Expected one of {}.", name, names.join(", ")))
+    })
+}
+
+// continuation lines are indented under the first one
+fn push_wrapped(out: &mut String, indent: usize, text: &str) {
+    for (i, line) in text.lines().enumerate() {
+        match i {
+            0 => out.push_str(&format!("{}\n", line)),
+            _ => out.push_str(&format!("{:indent$}{}\n", "", line.trim_start(), indent = indent)),
+        }
+    }
+}
+
+// `templater --help`: every subcommand
+pub fn help() -> String {
+    let mut out = String::from("usage: templater [subcommand] [flags]\n\nsubcommands:\n");
+    let width = SUBCOMMANDS.iter().map(|subcommand| subcommand.name.len()).max().unwrap_or(0);
+    for subcommand in SUBCOMMANDS {
+        out.push_str(&format!("    {:width$}  ", subcommand.name, width = width));
+        push_wrapped(&mut out, width + 6, subcommand.about);
+    }
+    out.push_str("\n`templater <subcommand> --help` lists its flags.\n");
+
+    out
+}
+
+// `templater <subcommand> --help`
+pub fn subcommand_help(subcommand: &Subcommand) -> String {
+    let args = match subcommand.args.is_empty() {
+        true => String::new(),
+        false => format!(" {}", subcommand.args),
+    };
+    let mut out = format!("usage: templater {} [flags]{}\n\n", subcommand.name, args);
+    push_wrapped(&mut out, 0, subcommand.about);
+
+    let flags: Vec<&Flag> = subcommand.groups.iter().flat_map(|group| group.flags()).collect();
+    let names: Vec<String> = flags
+        .iter()
+        .map(|flag| match flag.value {
+            Some(value) => format!("{} <{}>", flag.name, value),
+            None => flag.name.to_string(),
+        })
+        .collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+
+    out.push_str("\nflags:\n");
+    for (flag, name) in flags.iter().zip(&names) {
+        out.push_str(&format!("    {:width$}  ", name, width = width));
+        push_wrapped(&mut out, width + 6, flag.help);
+    }
+    out.push_str(&format!("    {:width$}  print this\n", "-h/--help", width = width));
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_lists_every_subcommands_flags() {
+        assert_eq!(subcommand(None).unwrap().name, "build");
+        assert!(subcommand(Some("bulid")).unwrap_err().to_string().contains("Expected one of build, watch, check"));
+
+        let check = subcommand_help(subcommand(Some("check")).unwrap());
+        assert!(check.starts_with("usage: templater check [flags]\n"));
+        assert!(check.contains("    --autofix  "));
+        assert!(!check.contains("--transpose <key>"));
+
+        let build = subcommand_help(subcommand(Some("build")).unwrap());
+        assert!(build.contains("    --transpose <key>  "));
+        let preset = build.lines().find(|line| line.trim_start().starts_with("or a preset")).unwrap();
+        assert!(preset.starts_with(&" ".repeat(31)));
+        assert!(help().contains("    convert-absolute  rewrite"));
+    }
+
+    // they're written out, since the help is const
+    #[test]
+    fn it_lists_every_field() {
+        let listed: [(&[&str], &str); 2] =
+            [(&crate::scaffold::STUB_FIELDS, "new"), (&crate::metadata::METADATA_FIELDS, "build")];
+        for (fields, name) in listed {
+            let help = subcommand_help(subcommand(Some(name)).unwrap());
+            assert!(help.contains(&format!("fields: {}\n", fields.join(", "))), "{} doesn't list every field", name);
+        }
+
+        let build = subcommand_help(subcommand(None).unwrap());
+        assert!(build.contains(&format!("(default ./{})", crate::models::DEFAULT_CONFIG_FILE)));
+        assert!(build.contains(&format!("(default ./{})", crate::transpose::DEFAULT_TRANSPOSITIONS_FILE)));
+    }
+
+    #[test]
+    fn it_doesnt_repeat_a_flag() {
+        for subcommand in SUBCOMMANDS {
+            let mut names: Vec<&str> = subcommand.groups.iter().flat_map(|group| group.flags()).map(|f| f.name).collect();
+            names.sort();
+            let count = names.len();
+            names.dedup();
+            assert_eq!(names.len(), count, "{} repeats a flag", subcommand.name);
+        }
+    }
+}
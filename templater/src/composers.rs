@@ -0,0 +1,146 @@
+// `composers.toml`: the same composer is often spelled differently
+// across songs, e.g. "Duke Ellington", "Ellington, Duke", and
+// "D. Ellington". every alias is replaced by its canonical name
+// when the songs are read, so they're grouped (and filtered) as one.
+//
+//     [aliases]
+//     "Ellington, Duke" = "Duke Ellington"
+//     "D. Ellington" = "Duke Ellington"
+//
+//     # when the surname isn't the last word
+//     [sort_as]
+//     "Sun Ra" = "Sun Ra"
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::models::{Song, UNKNOWN_COMPOSER};
+
+pub const DEFAULT_COMPOSERS_FILE: &str = "composers.toml";
+
+// skipped when looking for a surname
+const NAME_SUFFIXES: [&str; 4] = ["jr", "jr.", "sr", "sr."];
+// part of the surname, e.g. "Jimmy Van Heusen" -> "van heusen"
+const NAME_PARTICLES: [&str; 9] = ["da", "de", "del", "della", "di", "du", "la", "van", "von"];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComposerAliases {
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    sort_as: BTreeMap<String, String>,
+}
+
+// "Hirokazu  Tanaka " -> "Hirokazu Tanaka"
+fn normalize(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// sorts by the first composer's surname, e.g. "Dizzy Gillespie &
+// Frank Paparelli" -> "gillespie, dizzy & frank paparelli".
+// nicknames and notes in parentheses are ignored.
+pub fn sort_key(composer: &str) -> String {
+    let without_parens: String = composer
+        .split('(')
+        .enumerate()
+        .map(|(i, part)| match i {
+            0 => part,
+            _ => part.split_once(')').map_or("", |(_, rest)| rest),
+        })
+        .collect();
+
+    let first = without_parens.split([',', '&']).next().unwrap_or_default();
+    let words: Vec<&str> = first.split_whitespace().collect();
+    let is_in = |list: &[&str], word: &str| list.contains(&word.to_lowercase().as_str());
+    let Some(mut start) = words.iter().rposition(|word| !is_in(&NAME_SUFFIXES, word)) else {
+        return normalize(composer).to_lowercase();
+    };
+    let end = start + 1;
+    while start > 1 && is_in(&NAME_PARTICLES, words[start - 1]) {
+        start -= 1;
+    }
+    if composer == UNKNOWN_COMPOSER || start == 0 {
+        return normalize(composer).to_lowercase();
+    }
+
+    // the last match in the first composer's name, which is the
+    // surname even when it's also part of the first name
+    let surname = words[start..end].join(" ");
+    let first_len = composer.find([',', '&']).unwrap_or(composer.len());
+    let key = match composer[..first_len].rfind(&surname) {
+        Some(i) => format!("{}, {}{}", surname, &composer[..i], &composer[i + surname.len()..]),
+        None => composer.to_string(),
+    };
+    normalize(&key).to_lowercase()
+}
+
+impl ComposerAliases {
+    // a missing file has no aliases
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TemplaterError> {
+        let path = path.as_ref();
+
+        match fs::read_to_string(path) {
+            Ok(input) => toml::from_str(&input)
+                .map_err(|e| TemplaterError::from_str(&format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ComposerAliases::default()),
+            Err(e) => Err(TemplaterError::file(path, e)),
+        }
+    }
+
+    // aliases match regardless of case and spacing
+    pub fn canonical(&self, composer: &str) -> String {
+        let composer = normalize(composer);
+
+        self.aliases
+            .iter()
+            .find(|(alias, _)| normalize(alias).eq_ignore_ascii_case(&composer))
+            .map_or(composer, |(_, canonical)| normalize(canonical))
+    }
+
+    pub fn sort_key(&self, composer: &str) -> String {
+        match self.sort_as.iter().find(|(name, _)| normalize(name).eq_ignore_ascii_case(composer)) {
+            Some((_, sort_as)) => normalize(sort_as).to_lowercase(),
+            None => sort_key(composer),
+        }
+    }
+
+    pub fn apply(&self, song: &mut Song) {
+        song.composer = self.canonical(&song.composer);
+        song.composer_sort = self.sort_key(&song.composer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_sorts_by_surname() {
+        assert_eq!(sort_key("Charlie Parker"), "parker, charlie");
+        assert_eq!(sort_key("John L. (Wes) Montgomery"), "montgomery, john l. (wes)");
+        assert_eq!(sort_key("John 'Dizzy' Gillespie & Frank Paparelli"), "gillespie, john 'dizzy' & frank paparelli");
+        assert_eq!(sort_key("Harry Connick Jr."), "connick, harry jr.");
+        assert_eq!(sort_key("Jimmy Van Heusen"), "van heusen, jimmy");
+        assert_eq!(sort_key("Traditional"), "traditional");
+    }
+
+    #[test]
+    fn it_replaces_aliases() {
+        let aliases: ComposerAliases = toml::from_str(
+            "[aliases]\n\"Ellington, Duke\" = \"Duke Ellington\"\n\"D. Ellington\" = \"Duke Ellington\"\n\
+             [sort_as]\n\"Sun Ra\" = \"Sun Ra\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(aliases.canonical("ellington,  duke "), "Duke Ellington");
+        assert_eq!(aliases.canonical("D. Ellington"), "Duke Ellington");
+        assert_eq!(aliases.canonical("Hirokazu Tanaka "), "Hirokazu Tanaka");
+        assert_eq!(aliases.sort_key("Sun Ra"), "sun ra");
+    }
+}
@@ -0,0 +1,71 @@
+// `templater drafts`: the songs with `status: draft`, which are
+// only in the book with `--include-drafts`. only the front matter
+// is read, so a draft's body can still be broken.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::errors::TemplaterError;
+use crate::frontmatter::{self, Defaults, Frontmatter};
+use crate::models::UNKNOWN_COMPOSER;
+use crate::utils::{get_files_by_ext, read_file};
+use crate::warnings::Warnings;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Draft {
+    pub path: PathBuf,
+    pub title: String,
+    pub composer: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DraftsReport {
+    pub drafts: Vec<Draft>,
+    pub songs_checked: usize,
+}
+
+impl fmt::Display for DraftsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for draft in &self.drafts {
+            writeln!(f, "{}: {} - {}", draft.path.display(), draft.title, draft.composer)?;
+        }
+        write!(f, "{} of {} songs are drafts", self.drafts.len(), self.songs_checked)
+    }
+}
+
+pub fn find_drafts<P: AsRef<Path>>(songs_dir: P, warnings: &mut Warnings) -> Result<DraftsReport, TemplaterError> {
+    let mut paths = get_files_by_ext(&songs_dir, "ly", warnings)?;
+    paths.sort();
+    let defaults = Defaults::load(&songs_dir)?;
+
+    let mut report = DraftsReport::default();
+    let mut errors = vec![];
+    for path in paths {
+        let input = read_file(&path)?;
+        for (_, song) in frontmatter::split_songs(&input) {
+            report.songs_checked += 1;
+            let front_matter = match Frontmatter::parse_with(&path, &frontmatter::split(song).0, &defaults) {
+                Ok(front_matter) => front_matter,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                },
+            };
+
+            match front_matter.is_draft() {
+                Ok(true) => report.drafts.push(Draft {
+                    title: front_matter.title,
+                    composer: front_matter.composer.unwrap_or_else(|| String::from(UNKNOWN_COMPOSER)),
+                    path: path.clone(),
+                }),
+                Ok(false) => {},
+                Err(e) => errors.push(TemplaterError::from_str(&format!("{}: {}", path.display(), e))),
+            }
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(report),
+        false => Err(TemplaterError::collect(errors)),
+    }
+}
@@ -0,0 +1,116 @@
+// two contributors adding "Blue Bossa" and "Blue bossa " would
+// otherwise put the same song in the book twice. titles that only
+// differ in case, spacing, or punctuation are duplicates, and ones
+// that are a typo or a leading "The" apart (e.g. "Blue Bosa") are
+// probably duplicates.
+
+use crate::utils::{title_sort_key, unicode_slugify};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplicate {
+    // only case, spacing, or punctuation differ
+    Same,
+    // a short edit distance apart, ignoring leading articles
+    Similar,
+}
+
+// characters that have to be inserted, removed, or replaced
+// to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+// longer titles allow more typos; very short ones, e.g. "Oleo"
+// and "Olea", are too likely to be different songs
+fn max_distance(len: usize) -> usize {
+    match len {
+        0..=5 => 0,
+        6..=15 => 1,
+        _ => 2,
+    }
+}
+
+fn digits(s: &str) -> String {
+    s.chars().filter(char::is_ascii_digit).collect()
+}
+
+// "Now's the Time" -> "nowsthetime"
+fn alphanumerics(title: &str) -> String {
+    title.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+pub fn compare_titles(a: &str, b: &str) -> Option<Duplicate> {
+    if alphanumerics(a) == alphanumerics(b) {
+        return Some(Duplicate::Same);
+    }
+
+    // every alphabet's letters, so two titles without latin ones aren't
+    // both empty, and the same
+    let (a, b) = (unicode_slugify(&title_sort_key(a)), unicode_slugify(&title_sort_key(b)));
+
+    // "Suite No. 1" and "Suite No. 2" are different songs
+    if digits(&a) != digits(&b) {
+        return None;
+    }
+
+    let (len_a, len_b) = (a.chars().count(), b.chars().count());
+    let max = max_distance(len_a.min(len_b));
+    match len_a.abs_diff(len_b) <= max && edit_distance(&a, &b) <= max {
+        true => Some(Duplicate::Similar),
+        false => None,
+    }
+}
+
+// every pair of (possibly) duplicate titles, as indices into `titles`
+pub fn find_duplicates(titles: &[&str]) -> Vec<(usize, usize, Duplicate)> {
+    let mut duplicates = vec![];
+
+    for (i, a) in titles.iter().enumerate() {
+        for (j, b) in titles.iter().enumerate().skip(i + 1) {
+            if let Some(duplicate) = compare_titles(a, b) {
+                duplicates.push((i, j, duplicate));
+            }
+        }
+    }
+
+    duplicates
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_measures_edit_distance() {
+        assert_eq!(edit_distance("blue-bossa", "blue-bossa"), 0);
+        assert_eq!(edit_distance("blue-bosa", "blue-bossa"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn it_finds_duplicate_titles() {
+        assert_eq!(compare_titles("Blue Bossa", "Blue bossa "), Some(Duplicate::Same));
+        assert_eq!(compare_titles("Now's the Time", "Nows The Time"), Some(Duplicate::Same));
+        assert_eq!(compare_titles("Blue Bossa", "Blue Bosa"), Some(Duplicate::Similar));
+        assert_eq!(compare_titles("The Days Of Wine And Roses", "Days of Wine and Roses"), Some(Duplicate::Similar));
+        assert_eq!(compare_titles("All the Things You Are", "All The Thing You Are"), Some(Duplicate::Similar));
+        assert_eq!(compare_titles("Oleo", "Olea"), None);
+        assert_eq!(compare_titles("Blue Monk", "Blue Bossa"), None);
+        assert_eq!(compare_titles("Suite No. 1", "Suite No. 2"), None);
+        assert_eq!(compare_titles("Катюша", "Калинка"), None);
+        assert_eq!(compare_titles("Подмосковные вечера", "Подмосковные вечер"), Some(Duplicate::Similar));
+    }
+}
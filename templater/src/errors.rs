@@ -1,35 +1,91 @@
 use std::fmt::{self, Formatter};
+use std::path::PathBuf;
 
-#[derive(Debug)]
+use thiserror::Error;
+
+use crate::warnings::Warning;
+
+// errors display without a prefix, so they can be used in other
+// messages. `report` adds the "[Error]: " for the user.
+#[derive(Debug, Error)]
 pub enum TemplaterError {
+    #[error("{0}")]
     Templater(TemplaterErrorKind),
 
-    IO(std::io::Error),
-    PicoArgs(pico_args::Error),
+    // an io error on a known file
+    #[error("{}: {source}", path.display())]
+    File { path: PathBuf, source: std::io::Error },
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+    #[error("{0}")]
+    PicoArgs(#[from] pico_args::Error),
+    #[error("config: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("{}: invalid frontmatter: {source}", path.display())]
+    Frontmatter { path: PathBuf, source: serde_yaml::Error },
+    #[error("template '{name}': {message}")]
+    Template { name: String, message: String },
+    #[error("{0}")]
+    Transposition(String),
+    // lilypond rejected a generated book
+    #[error("{}: {message}", path.display())]
+    Lilypond { path: PathBuf, message: String },
+    // found by `--lint-ly` before lilypond ever sees the song
+    #[error("{}:{line}: {message}", path.display())]
+    Lint { path: PathBuf, line: usize, message: String },
+    // a warning, failing the build because of `--strict`
+    #[error("{0} (--strict)")]
+    Strict(Warning),
+    #[error("watch: {0}")]
+    Notify(#[from] notify::Error),
+
+    // e.g. every song that failed to parse, not just the first
+    #[error("{}", join_messages(.0))]
+    Multiple(Vec<TemplaterError>),
 }
 
 impl TemplaterError {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(msg: &str) -> TemplaterError {
         TemplaterError::Templater(TemplaterErrorKind::General(msg.into()))
     }
-}
 
-impl fmt::Display for TemplaterError { 
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    pub fn file<P: Into<PathBuf>>(path: P, source: std::io::Error) -> TemplaterError {
+        TemplaterError::File { path: path.into(), source }
+    }
+
+    // one error, or all of them if there's more than one
+    pub fn collect(mut errors: Vec<TemplaterError>) -> TemplaterError {
+        match errors.len() {
+            1 => errors.remove(0),
+            _ => TemplaterError::Multiple(errors),
+        }
+    }
+
+    // every error on its own line, e.g.
+    //
+    //     [Error]: songs/misty.ly: invalid frontmatter: missing field `title`
+    //     [Error]: songs/oleo.ly: invalid transpose_override: ...
+    pub fn report(&self) -> String {
         match self {
-            TemplaterError::Templater(e) => write!(f, "[Error]: {}", e),
-            TemplaterError::IO(e) => write!(f, "[Error]: {}", e),
-            TemplaterError::PicoArgs(e) => write!(f, "[Error]: {}", e),
+            TemplaterError::Multiple(errors) => {
+                errors.iter().map(TemplaterError::report).collect::<Vec<_>>().join("\n")
+            },
+            e => format!("[Error]: {}", e),
         }
     }
 }
 
+fn join_messages(errors: &[TemplaterError]) -> String {
+    errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")
+}
+
 #[derive(Debug)]
 pub enum TemplaterErrorKind {
     General(String),
 }
 
-impl fmt::Display for TemplaterErrorKind { 
+impl fmt::Display for TemplaterErrorKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             TemplaterErrorKind::General(s) => write!(f, "{}", s),
@@ -43,14 +99,17 @@ impl From<String> for TemplaterError {
     }
 }
 
-impl From<std::io::Error> for TemplaterError {
-    fn from(err: std::io::Error) -> TemplaterError {
-        TemplaterError::IO(err)
-    }
-}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_reports_every_error() {
+        let err = TemplaterError::collect(vec![
+            TemplaterError::from_str("first"),
+            TemplaterError::file("songs/misty.ly", std::io::Error::other("unreadable")),
+        ]);
 
-impl From<pico_args::Error> for TemplaterError {
-    fn from(err: pico_args::Error) -> TemplaterError {
-        TemplaterError::PicoArgs(err)
+        assert_eq!(err.report(), "[Error]: first\n[Error]: songs/misty.ly: unreadable");
     }
 }
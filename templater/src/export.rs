@@ -0,0 +1,160 @@
+// `templater export songs.json`: the metadata of every song in the
+// book, e.g. for a website index or a spreadsheet. page numbers
+// only exist once lilypond has laid out the book, so songs are
+// listed with their number and the `\label` the ToC links to.
+// `templater export tunes.abc` writes the songs themselves instead,
+// see `abc.rs`, and `templater export charts.html --format ireal`
+// their chords as iReal Pro charts, see `ireal.rs`.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::abc;
+use crate::errors::TemplaterError;
+use crate::ireal;
+use crate::keys::describe_keys;
+use crate::models::Song;
+
+const CSV_HEADER: [&str; 10] = ["number", "title", "composer", "poet", "meter", "bpm", "time", "key", "label", "path"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Abc,
+    Ireal,
+}
+
+impl ExportFormat {
+    // from the output file's extension
+    pub fn from_path(path: &Path) -> Result<Self, TemplaterError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(ExportFormat::Json),
+            Some("csv") => Ok(ExportFormat::Csv),
+            Some("abc") => Ok(ExportFormat::Abc),
+            _ => Err(TemplaterError::from_str(&format!(
+                "{}: unknown export format. Expected a .json, .csv, or .abc file, or a --format.",
+                path.display()
+            ))),
+        }
+    }
+}
+
+// `--format`
+impl FromStr for ExportFormat {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "abc" => Ok(ExportFormat::Abc),
+            "ireal" | "irealpro" | "ireal-pro" => Ok(ExportFormat::Ireal),
+            _ => Err(TemplaterError::from_str(&format!(
+                "Unknown export format '{}'. Expected json, csv, abc, or ireal.",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SongMetadata {
+    pub number: u32,
+    pub title: String,
+    pub composer: String,
+    pub poet: Option<String>,
+    pub meter: Option<String>,
+    pub bpm: Option<u32>,
+    // every time signature, e.g. ["3/4", "4/4"], the one most of the
+    // song is in first
+    pub time: Vec<String>,
+    // in concert pitch, e.g. "Bb major → D major" if it modulates,
+    // see `keys.rs`
+    pub key: Option<String>,
+    pub label: String,
+    pub path: String,
+}
+
+impl SongMetadata {
+    pub fn new(song: &Song) -> Self {
+        SongMetadata {
+            number: song.number,
+            title: song.title.clone(),
+            composer: song.composer.clone(),
+            poet: song.poet.clone(),
+            meter: song.meter.clone(),
+            bpm: song.bpm(),
+            time: song.time_signatures.iter().map(|time| time.to_string()).collect(),
+            key: Some(describe_keys(&song.keys())).filter(|keys| !keys.is_empty()),
+            label: song.label(),
+            path: song.path.display().to_string(),
+        }
+    }
+
+    fn csv_row(&self) -> [String; 10] {
+        let field = |value: &Option<String>| value.clone().unwrap_or_default();
+
+        [
+            self.number.to_string(),
+            self.title.clone(),
+            self.composer.clone(),
+            field(&self.poet),
+            field(&self.meter),
+            self.bpm.map(|bpm| bpm.to_string()).unwrap_or_default(),
+            self.time.join(" "),
+            field(&self.key),
+            self.label.clone(),
+            self.path.clone(),
+        ]
+    }
+}
+
+// quoted only if needed, with quotes doubled
+fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
+
+pub fn export(songs: &[Song], format: ExportFormat) -> Result<String, TemplaterError> {
+    let metadata = || songs.iter().map(SongMetadata::new).collect::<Vec<_>>();
+
+    match format {
+        ExportFormat::Json => serde_json::to_string_pretty(&metadata())
+            .map(|json| json + "\n")
+            .map_err(|e| TemplaterError::from_str(&e.to_string())),
+        ExportFormat::Csv => {
+            let mut output = CSV_HEADER.join(",") + "\n";
+            for song in &metadata() {
+                let row: Vec<String> = song.csv_row().iter().map(|s| csv_field(s)).collect();
+                output.push_str(&row.join(","));
+                output.push('\n');
+            }
+            Ok(output)
+        },
+        ExportFormat::Abc => Ok(abc::export(songs)),
+        ExportFormat::Ireal => Ok(ireal::export(songs)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_exports_csv() {
+        let input = "title: \"Zelda: Majora's Mask\"\ncomposer: Koji Kondo, Toru Minegishi\nbpm: 120\n---\n\\relative c' { \\key d \\minor \\numericTimeSignature d1 \\time 3/4 d2. d }\n";
+        let mut song = Song::parse(Path::new("songs/zelda.ly"), input, false).unwrap();
+        song.number = 7;
+
+        assert_eq!(
+            export(&[song], ExportFormat::Csv).unwrap(),
+            "number,title,composer,poet,meter,bpm,time,key,label,path\n\
+             7,Zelda: Majora's Mask,\"Koji Kondo, Toru Minegishi\",,,120,3/4 4/4,D minor,song-zelda-majora-s-mask,songs/zelda.ly\n"
+        );
+    }
+}
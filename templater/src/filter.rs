@@ -0,0 +1,146 @@
+// `--filter field=pattern`: selects songs by a frontmatter field.
+// patterns with `*` or `?` are globs over the whole value, anything
+// else matches if the value contains it. both are case insensitive.
+// tags are short, so a tag is only matched as a whole.
+
+use std::str::FromStr;
+
+use crate::errors::TemplaterError;
+use crate::models::Song;
+use crate::utils::glob_match;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterField {
+    Title,
+    Composer,
+    Poet,
+    Arranger,
+    Meter,
+    Tag,
+}
+
+impl FilterField {
+    fn value<'a>(&self, song: &'a Song) -> Option<&'a str> {
+        match self {
+            FilterField::Title => Some(&song.title),
+            FilterField::Composer => Some(&song.composer),
+            FilterField::Poet => song.poet.as_deref(),
+            FilterField::Arranger => song.arranger.as_deref(),
+            FilterField::Meter => song.meter.as_deref(),
+            FilterField::Tag => None,
+        }
+    }
+}
+
+impl FromStr for FilterField {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "title" => Ok(FilterField::Title),
+            "composer" => Ok(FilterField::Composer),
+            "poet" => Ok(FilterField::Poet),
+            "arranger" => Ok(FilterField::Arranger),
+            "meter" => Ok(FilterField::Meter),
+            "tag" | "tags" => Ok(FilterField::Tag),
+            _ => Err(TemplaterError::from_str(&format!(
+                "Unknown filter field '{}'. Expected title, composer, poet, arranger, meter, or tag.",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongFilter {
+    pub field: FilterField,
+    pub pattern: String,
+}
+
+impl SongFilter {
+    pub fn matches(&self, song: &Song) -> bool {
+        match self.field {
+            FilterField::Tag => song.tags.iter().any(|tag| matches_title(&self.pattern, tag)),
+            field => field.value(song).is_some_and(|value| matches_pattern(&self.pattern, value)),
+        }
+    }
+}
+
+impl FromStr for SongFilter {
+    type Err = TemplaterError;
+
+    // e.g. `composer=Ellington` or `title="All the*"`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, pattern) = s.split_once('=').ok_or_else(|| {
+            TemplaterError::from_str(&format!("Invalid filter '{}'. Expected field=pattern.", s))
+        })?;
+
+        Ok(SongFilter {
+            field: field.parse()?,
+            pattern: pattern.trim().trim_matches('"').to_string(),
+        })
+    }
+}
+
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let value = value.to_lowercase();
+
+    if pattern.contains(['*', '?']) {
+        glob_match(&pattern, &value)
+    } else {
+        value.contains(&pattern)
+    }
+}
+
+// `--only`/`--song-names` entries are matched against the whole
+// title, so "Four" doesn't also select "Four on Six".
+pub fn matches_title(pattern: &str, title: &str) -> bool {
+    let pattern = pattern.trim().to_lowercase();
+    let title = title.to_lowercase();
+
+    if pattern.contains(['*', '?']) {
+        glob_match(&pattern, &title)
+    } else {
+        pattern == title
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_filters() {
+        let filter: SongFilter = "composer=\"Ellington\"".parse().unwrap();
+        assert_eq!(filter, SongFilter { field: FilterField::Composer, pattern: "Ellington".into() });
+
+        assert!("composer".parse::<SongFilter>().is_err());
+        assert!("year=1954".parse::<SongFilter>().is_err());
+    }
+
+    #[test]
+    fn it_matches_substrings_and_globs() {
+        assert!(matches_pattern("ellington", "Duke Ellington"));
+        assert!(matches_pattern("duke*", "Duke Ellington"));
+        assert!(!matches_pattern("ellington*", "Duke Ellington"));
+    }
+
+    #[test]
+    fn it_matches_whole_tags() {
+        let song = Song::parse(std::path::Path::new("test.ly"), "title: Ceora\ntags: [Bossa, ballad]", false).unwrap();
+        let matches = |filter: &str| filter.parse::<SongFilter>().unwrap().matches(&song);
+
+        assert!(matches("tag=bossa"));
+        assert!(matches("tag=ball*"));
+        assert!(!matches("tag=boss"));
+        assert!(!matches("tag=blues"));
+    }
+
+    #[test]
+    fn it_matches_whole_titles() {
+        assert!(matches_title("all of me", "All of Me"));
+        assert!(!matches_title("four", "Four on Six"));
+        assert!(matches_title("four*", "Four on Six"));
+    }
+}
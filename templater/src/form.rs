@@ -0,0 +1,228 @@
+// a song's form, e.g. `form: AABA` or `form: 12-bar blues` in the
+// front matter. without one it's guessed from the chord roots on
+// every downbeat: 12 bars with the IV chord in bar 5 are a blues,
+// and 32 bars are split into four 8-bar sections, where a section
+// with mostly the same roots as an earlier one gets its letter.
+// bars are counted as written, without the pickup, so a repeat
+// counts once.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::TemplaterError;
+use crate::harmony::parse_pitch;
+use crate::models::Song;
+use crate::music::{count_bars, downbeats, time_signature};
+use crate::repeats::jumps;
+
+const BLUES_BARS: u64 = 12;
+const SONG_FORM_BARS: u64 = 32;
+const SECTION_BARS: usize = 8;
+
+// how many of a section's downbeats have to match an earlier one's
+// for it to be the same section, e.g. an A with another turnaround
+const SAME_SECTION: f64 = 0.75;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Form {
+    Blues { bars: u64 },
+    // e.g. "AABA", and the bars of a chorus if they're known
+    Sections { letters: String, bars: Option<u64> },
+}
+
+impl Form {
+    // a warning if `bars` aren't a whole number of choruses
+    pub fn check(&self, bars: u64) -> Option<String> {
+        match self {
+            Form::Blues { bars: chorus } | Form::Sections { bars: Some(chorus), .. }
+                if !bars.is_multiple_of(*chorus) =>
+            {
+                Some(format!("{} bars aren't a whole number of {} choruses", bars, self))
+            },
+            Form::Sections { letters, bars: None } if !bars.is_multiple_of(letters.len() as u64) => {
+                Some(format!("{} bars don't split into the {} sections of {}", bars, letters.len(), letters))
+            },
+            _ => None,
+        }
+    }
+
+    // from the chords, none if it isn't a common form
+    pub fn detect(chords: &str, melody: &str) -> Option<Form> {
+        let bars = count_bars(melody);
+        let roots: Vec<Option<i32>> = downbeats(chords, time_signature(melody))
+            .iter()
+            .map(|pitch| pitch.as_deref().and_then(parse_pitch).map(|pitch| pitch.pitch_class()))
+            .collect();
+
+        let root = |bar: usize| roots.get(bar).copied().flatten();
+        match bars {
+            BLUES_BARS => match (root(0), root(4)) {
+                (Some(one), Some(four)) if (four - one).rem_euclid(12) == 5 => Some(Form::Blues { bars }),
+                _ => None,
+            },
+            SONG_FORM_BARS if roots.len() >= SONG_FORM_BARS as usize => {
+                let sections: Vec<&[Option<i32>]> = roots.chunks(SECTION_BARS).take(4).collect();
+                let same = |a: &[Option<i32>], b: &[Option<i32>]| {
+                    let matching = a.iter().zip(b).filter(|(a, b)| a.is_some() && a == b).count();
+                    matching as f64 / SECTION_BARS as f64 >= SAME_SECTION
+                };
+
+                let mut letters = String::new();
+                for (i, section) in sections.iter().enumerate() {
+                    let letter = match (0..i).find(|j| same(sections[*j], section)) {
+                        Some(j) => letters.as_bytes()[j] as char,
+                        None => (b'A' + letters.chars().max().map_or(0, |c| c as u8 - b'A' + 1)) as char,
+                    };
+                    letters.push(letter);
+                }
+
+                // four different sections isn't much of a form
+                (letters != "ABCD").then_some(Form::Sections { letters, bars: Some(bars) })
+            },
+            _ => None,
+        }
+    }
+}
+
+// e.g. "12-bar blues", "32-bar AABA", or "ABAC"
+impl fmt::Display for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Form::Blues { bars } => write!(f, "{}-bar blues", bars),
+            Form::Sections { letters, bars: Some(bars) } => write!(f, "{}-bar {}", bars, letters),
+            Form::Sections { letters, bars: None } => write!(f, "{}", letters),
+        }
+    }
+}
+
+impl FromStr for Form {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            let message = format!("Unknown form '{}'. Expected e.g. AABA, 32-bar AABA, or 12-bar blues.", s);
+            TemplaterError::from_str(&message)
+        };
+
+        // e.g. "32-bar " or "12 bar "
+        let input = s.trim().to_lowercase();
+        let digits = input.len() - input.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (bars, rest) = match input[digits..].trim_start_matches(['-', ' ']).strip_prefix("bar") {
+            Some(rest) if digits > 0 => {
+                let rest = rest.strip_prefix('s').unwrap_or(rest).trim();
+                (Some(input[..digits].parse().map_err(|_| error())?), rest)
+            },
+            _ => (None, input.as_str()),
+        };
+        if bars == Some(0) {
+            return Err(error());
+        }
+
+        // e.g. the `'` of "AABA'" or `*`
+        let letters: String = rest.chars().filter(|c| !"'*".contains(*c)).collect::<String>().to_uppercase();
+        match letters.as_str() {
+            "BLUES" => Ok(Form::Blues { bars: bars.unwrap_or(BLUES_BARS) }),
+            "" => Err(error()),
+            _ if letters.chars().all(|c| c.is_ascii_uppercase()) => Ok(Form::Sections { letters, bars }),
+            _ => Err(error()),
+        }
+    }
+}
+
+// the declared form, or else a guess
+pub fn song_form(song: &Song) -> Option<Form> {
+    song.form.clone().or_else(|| {
+        let melody = song.voices.iter().find(|voice| voice.name.is_none())?;
+        Form::detect(&song.chords, &melody.notes)
+    })
+}
+
+// the form and the jumps, e.g. "AABA with D.S. al Coda", "32-bar
+// AABA", or "D.C. al Fine". none if neither is known
+pub fn form_summary(song: &Song) -> Option<String> {
+    let music = [song.chords.as_str()].into_iter().chain(song.voices.iter().map(|voice| voice.notes.as_str()));
+    let mut found: Vec<String> = vec![];
+    for jump in music.flat_map(jumps) {
+        if !found.contains(&jump) {
+            found.push(jump);
+        }
+    }
+
+    let jumps = found.join(" and ");
+    match song_form(song) {
+        Some(form) if jumps.is_empty() => Some(form.to_string()),
+        Some(form) => Some(format!("{} with {}", form, jumps)),
+        None => Some(jumps).filter(|jumps| !jumps.is_empty()),
+    }
+}
+
+// a warning if the melody doesn't fit the declared form
+pub fn verify_form(song: &Song) -> Option<String> {
+    let (form, measures) = (song.form.as_ref()?, song.measures?);
+    form.check(measures).map(|warning| format!("form: {}", warning))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontmatter::Defaults;
+    use crate::transpose::Presets;
+    use std::path::Path;
+
+    #[test]
+    fn it_parses_forms() {
+        assert_eq!("AABA".parse::<Form>().unwrap(), Form::Sections { letters: String::from("AABA"), bars: None });
+        assert_eq!("32-bar aaba*".parse::<Form>().unwrap().to_string(), "32-bar AABA");
+        assert_eq!("blues".parse::<Form>().unwrap(), Form::Blues { bars: 12 });
+        assert_eq!("16 bar blues".parse::<Form>().unwrap().to_string(), "16-bar blues");
+        assert!("rondo 2".parse::<Form>().is_err());
+        assert!("0-bar AB".parse::<Form>().is_err());
+    }
+
+    #[test]
+    fn it_checks_the_bars_of_a_form() {
+        assert_eq!("AABA".parse::<Form>().unwrap().check(32), None);
+        assert_eq!("blues".parse::<Form>().unwrap().check(24), None);
+        assert_eq!(
+            "AABA".parse::<Form>().unwrap().check(33).unwrap(),
+            "33 bars don't split into the 4 sections of AABA"
+        );
+        assert_eq!(
+            "12-bar blues".parse::<Form>().unwrap().check(13).unwrap(),
+            "13 bars aren't a whole number of 12-bar blues choruses"
+        );
+    }
+
+    #[test]
+    fn it_detects_common_forms() {
+        let melody = |bars: usize| format!("\\time 4/4 \\partial 4 c4 | {}", "c1 | ".repeat(bars));
+
+        let blues = "\\chordmode { \\partial 4 s4 f1:7 | bes:7 | f:7 | f:7 | bes:7 | bes:7 | f:7 | d:7 |\
+                     g:m7 | c:7 | f:7 | c:7 }";
+        assert_eq!(Form::detect(blues, &melody(12)), Some(Form::Blues { bars: 12 }));
+        assert_eq!(Form::detect(blues, &melody(16)), None);
+
+        let a = "c1:maj7 | a:m7 | d:m7 | g:7 | c:maj7 | a:m7 | d:m7 | g:7 |";
+        let b = "e1:m7 | a:7 | d:m7 | g:7 | e:m7 | a:7 | d2:m7 g:7 | c1 |";
+        let last_a = "c1:maj7 | a:m7 | d:m7 | g:7 | c:maj7 | a:m7 | d2:m7 g:7 | c1 |";
+        let rhythm_changes = format!("\\chordmode {{ \\partial 4 s4 {} {} {} {} }}", a, a, b, last_a);
+        assert_eq!(
+            Form::detect(&rhythm_changes, &melody(32)),
+            Some(Form::Sections { letters: String::from("AABA"), bars: Some(32) })
+        );
+    }
+
+    #[test]
+    fn it_summarizes_the_form_and_the_jumps() {
+        let song = |form: &str, melody: &str| {
+            let input = format!("title: Test\n{}---\n{{ \\numericTimeSignature {} }}\n", form, melody);
+            Song::parse_with(Path::new("test.ly"), &input, false, &Defaults::default(), &Presets::default()).unwrap()
+        };
+
+        let coda = "c1 | d1 \\mark \\markup { \\italic \"D.S. al Coda\" } |";
+        assert_eq!(form_summary(&song("form: AABA\n", coda)).as_deref(), Some("AABA with D.S. al Coda"));
+        assert_eq!(form_summary(&song("", coda)).as_deref(), Some("D.S. al Coda"));
+        assert_eq!(form_summary(&song("form: 12-bar blues\n", "c1")).as_deref(), Some("12-bar blues"));
+        assert_eq!(form_summary(&song("", "c1")), None);
+    }
+}
@@ -0,0 +1,439 @@
+// the yaml block at the top of each song file, before the first `---`
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use extract_frontmatter::{Extractor,config::Splitter};
+use serde::de::{Deserializer, Error};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::backing::BackingTrack;
+use crate::errors::TemplaterError;
+use crate::provenance::Provenance;
+use crate::tempo::Tempo;
+
+// every key a song's frontmatter is read for, which is what
+// `templater lsp` completes
+pub const KEYS: [&str; 31] = [
+    "title",
+    "subtitle",
+    "subsubtitle",
+    "sort_title",
+    "composer",
+    "poet",
+    "arranger",
+    "meter",
+    "bpm",
+    "key",
+    "form",
+    "year",
+    "tags",
+    "variants",
+    "status",
+    "license",
+    "pd_year",
+    "source",
+    "backing_track",
+    "copyright",
+    "dedication",
+    "footer",
+    "tagline",
+    "instrument",
+    "clef",
+    "pianostaff",
+    "transpose_override",
+    "pages",
+    "break_before",
+    "compress",
+    "ragged",
+];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Frontmatter {
+    #[serde(deserialize_with = "string_like")]
+    pub title: String,
+
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub composer: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub arranger: Option<String>,
+    // e.g. 132, 120-140, or Medium Swing, see `tempo.rs`
+    #[serde(default, deserialize_with = "opt_tempo")]
+    pub bpm: Option<Tempo>,
+    // every voice's, e.g. `bass` or `treble_8`, see `clef.rs`
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub clef: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub copyright: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub dedication: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub footer: Option<String>,
+    // e.g. "AABA" or "12-bar blues", see `form.rs`
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub form: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub instrument: Option<String>,
+    // e.g. "Eb" or "c minor", see `keys.rs`
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub key: Option<String>,
+    // e.g. "CC BY-SA 4.0" or "public domain", see `license.rs`
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub license: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub meter: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub pianostaff: Option<String>,
+    // layout hints for awkward page turns, see `LayoutHints`
+    #[serde(default)]
+    pub pages: Option<u32>,
+    #[serde(default)]
+    pub break_before: bool,
+    #[serde(default)]
+    pub compress: bool,
+    #[serde(default)]
+    pub ragged: bool,
+    // the year it enters the public domain
+    #[serde(default)]
+    pub pd_year: Option<u32>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub poet: Option<String>,
+    // e.g. "Girl from Ipanema", see `utils::title_sort_key`
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub sort_title: Option<String>,
+    // the fakebook, transcriber, and recording, see `provenance.rs`
+    #[serde(default)]
+    pub source: Option<Provenance>,
+    // a recording to play along with, see `backing.rs`
+    #[serde(default)]
+    pub backing_track: Option<BackingTrack>,
+    // `draft` leaves the song out of the book, see `is_draft`
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub status: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub subsubtitle: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub subtitle: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub tagline: Option<String>,
+    // e.g. `[bossa, ballad]` or `bossa, ballad`
+    #[serde(default, deserialize_with = "string_list")]
+    pub tags: Vec<String>,
+    // e.g. `bass` or `eb`. used instead of the book's transposition
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub transpose_override: Option<String>,
+    // e.g. `[Coltrane changes]`, each one a `--- chords: Coltrane
+    // changes ---` section
+    #[serde(default, deserialize_with = "string_list")]
+    pub variants: Vec<String>,
+
+    // keys the templater doesn't use (yet), e.g. year, style
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+// a file can hold more than one song, e.g. a medley, each one
+// after a line with only this and starting with its own frontmatter
+pub const SONG_SEPARATOR: &str = "===";
+
+// (first line, song) for every song in a file
+pub fn split_songs(input: &str) -> Vec<(usize, &str)> {
+    let mut songs = vec![];
+    let (mut start, mut first_line, mut offset) = (0, 1, 0);
+
+    for (i, line) in input.split_inclusive('\n').enumerate() {
+        if line.trim() == SONG_SEPARATOR {
+            songs.push((first_line, &input[start..offset]));
+            start = offset + line.len();
+            first_line = i + 2;
+        }
+        offset += line.len();
+    }
+    songs.push((first_line, &input[start..]));
+
+    songs
+}
+
+// (frontmatter, document)
+pub fn split(input: &str) -> (Cow<'_, str>, &str) {
+    let extractor = Extractor::new(Splitter::DelimiterLine("---"));
+    let (front_matter, document) = extractor.extract(input);
+
+    (Cow::Owned(dedent(&front_matter)), document)
+}
+
+// the indent every line shares and trailing whitespace are removed,
+// but not the rest of the indent, which nests e.g. `source`'s keys
+fn dedent(front_matter: &str) -> String {
+    let indent = front_matter
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    front_matter.lines().map(|line| line.get(indent..).unwrap_or_default().trim_end()).collect::<Vec<_>>().join("\n")
+}
+
+// frontmatter every song in `--songs-dir` starts with, e.g. a
+// copyright line or `meter: Swing`. a song's own keys win, and
+// `copyright: ~` leaves a default out. a default like `source`
+// is merged with the song's key by key.
+pub const DEFAULTS_FILE: &str = "_defaults.yml";
+
+#[derive(Debug, Clone, Default)]
+pub struct Defaults(Mapping);
+
+impl Defaults {
+    // a missing file has no defaults
+    pub fn load<P: AsRef<Path>>(songs_dir: P) -> Result<Self, TemplaterError> {
+        let path = songs_dir.as_ref().join(DEFAULTS_FILE);
+        match fs::read_to_string(&path) {
+            Ok(input) => Defaults::parse(&path, &input),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Defaults::default()),
+            Err(e) => Err(TemplaterError::file(path, e)),
+        }
+    }
+
+    pub fn parse(path: &Path, input: &str) -> Result<Self, TemplaterError> {
+        let error = |message: String| TemplaterError::from_str(&format!("{}: {}", path.display(), message));
+        let defaults = match serde_yaml::from_str(input) {
+            Ok(Value::Mapping(defaults)) => defaults,
+            Ok(Value::Null) => Mapping::new(),
+            Ok(_) => return Err(error(String::from("expected keys and values, e.g. `meter: Swing`"))),
+            Err(source) => return Err(TemplaterError::Frontmatter { path: path.to_path_buf(), source }),
+        };
+
+        // every default is checked here, so a song that doesn't parse
+        // is the song's fault
+        for (key, value) in &defaults {
+            let name = value_to_string::<serde_yaml::Error>(key.clone()).ok().flatten().unwrap_or_default();
+            if name == "title" {
+                return Err(error(String::from("every song needs its own title")));
+            }
+
+            let mut song = Mapping::new();
+            song.insert(Value::from("title"), Value::from(""));
+            song.insert(key.clone(), value.clone());
+            if let Err(e) = serde_yaml::from_value::<Frontmatter>(Value::Mapping(song)) {
+                return Err(error(format!("{}: {}", name, e)));
+            }
+        }
+
+        Ok(Defaults(defaults))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Frontmatter {
+    pub fn parse(path: &Path, input: &str) -> Result<Self, TemplaterError> {
+        Frontmatter::parse_with(path, input, &Defaults::default())
+    }
+
+    // with the `defaults` under the song's own keys
+    pub fn parse_with(path: &Path, input: &str, defaults: &Defaults) -> Result<Self, TemplaterError> {
+        let error = |source| TemplaterError::Frontmatter { path: path.to_path_buf(), source };
+        if defaults.is_empty() {
+            return serde_yaml::from_str(input).map_err(error);
+        }
+
+        let mut front_matter = match serde_yaml::from_str(input).map_err(error)? {
+            Value::Mapping(front_matter) => front_matter,
+            _ => Mapping::new(),
+        };
+        for (key, value) in &defaults.0 {
+            match (front_matter.get_mut(key), value) {
+                (Some(Value::Mapping(own)), Value::Mapping(default)) => {
+                    for (key, value) in default {
+                        if !own.contains_key(key) {
+                            own.insert(key.clone(), value.clone());
+                        }
+                    }
+                },
+                (Some(_), _) => {},
+                (None, _) => {
+                    front_matter.insert(key.clone(), value.clone());
+                },
+            }
+        }
+
+        // the song's own yaml says where it went wrong
+        serde_yaml::from_value(Value::Mapping(front_matter)).map_err(|e| match serde_yaml::from_str::<Frontmatter>(input) {
+            Err(located) => error(located),
+            Ok(_) => error(e),
+        })
+    }
+
+    // a work in progress, only in the book with `--include-drafts`
+    pub fn is_draft(&self) -> Result<bool, String> {
+        match self.status.as_deref().map(|status| status.trim().to_lowercase()).as_deref() {
+            None | Some("published") => Ok(false),
+            Some("draft") => Ok(true),
+            Some(other) => Err(format!("unknown status '{}'. Expected draft or published.", other)),
+        }
+    }
+
+    // when the song was written: `year: 1954` if it's there, or the
+    // first year in the subsubtitle, e.g. "From Pal Joey (1940)", or
+    // in the copyright
+    pub fn year(&self) -> Option<u32> {
+        let year = self.extra.get("year").and_then(|year| match year {
+            Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+            Value::String(s) => find_year(s),
+            _ => None,
+        });
+
+        year.or_else(|| self.subsubtitle.as_deref().and_then(find_year))
+            .or_else(|| self.copyright.as_deref().and_then(find_year))
+    }
+}
+
+// the first four digits on their own between 1800 and 2099
+fn find_year(s: &str) -> Option<u32> {
+    s.split(|c: char| !c.is_ascii_digit())
+        .filter(|digits| digits.len() == 4)
+        .filter_map(|digits| digits.parse().ok())
+        .find(|year| (1800..2100).contains(year))
+}
+
+// `subsubtitle: 1959` is a number as far as yaml is concerned,
+// but every value in the header is a string to lilypond.
+fn value_to_string<E: Error>(value: Value) -> Result<Option<String>, E> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s)),
+        Value::Number(n) => Ok(Some(n.to_string())),
+        Value::Bool(b) => Ok(Some(b.to_string())),
+        _ => Err(E::custom("expected a string")),
+    }
+}
+
+pub(crate) fn string_like<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    value_to_string(Value::deserialize(deserializer)?)?
+        .ok_or_else(|| D::Error::custom("expected a string, found nothing"))
+}
+
+pub(crate) fn opt_string_like<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    value_to_string(Value::deserialize(deserializer)?)
+}
+
+fn opt_tempo<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Tempo>, D::Error> {
+    match value_to_string(Value::deserialize(deserializer)?)? {
+        Some(tempo) => tempo.parse().map(Some).map_err(D::Error::custom),
+        None => Ok(None),
+    }
+}
+
+fn string_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let values = match Value::deserialize(deserializer)? {
+        Value::Sequence(values) => values,
+        value => match value_to_string(value)? {
+            Some(s) => s.split(',').map(|item| Value::String(item.to_string())).collect(),
+            None => vec![],
+        },
+    };
+
+    let mut list = vec![];
+    for value in values {
+        match value_to_string(value)? {
+            Some(item) if !item.trim().is_empty() => list.push(item.trim().to_string()),
+            _ => {},
+        }
+    }
+
+    Ok(list)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(input: &str) -> Result<Frontmatter, TemplaterError> {
+        Frontmatter::parse(Path::new("test.ly"), input)
+    }
+
+    #[test]
+    fn it_splits_songs() {
+        let input = "title: Body and Soul\n---\nc1\n===\ntitle: Misty\n---\nd1\n";
+
+        assert_eq!(split_songs(input), vec![(1, "title: Body and Soul\n---\nc1\n"), (5, "title: Misty\n---\nd1\n")]);
+        assert_eq!(split_songs("title: Oleo\n---\n"), vec![(1, "title: Oleo\n---\n")]);
+    }
+
+    #[test]
+    fn it_keeps_the_indent_of_nested_keys() {
+        let (front_matter, document) = split("  title: Oleo  \n  source:\n    page: 326\n---\nc1\n");
+
+        assert_eq!(front_matter, "title: Oleo\nsource:\n  page: 326");
+        assert_eq!(document, "c1\n");
+    }
+
+    #[test]
+    fn it_parses_frontmatter() {
+        let fm = parse("title: Oleo\ncomposer: Sonny Rollins\nsubsubtitle: 1954\nbpm: 240\nyear: 1954\nmeter:").unwrap();
+
+        assert_eq!(fm.title, "Oleo");
+        assert_eq!(fm.composer.as_deref(), Some("Sonny Rollins"));
+        assert_eq!(fm.subsubtitle.as_deref(), Some("1954"));
+        assert_eq!(fm.bpm, Some(Tempo::from(240)));
+        assert_eq!(fm.meter, None);
+        assert!(fm.extra.contains_key("year"));
+    }
+
+    #[test]
+    fn it_finds_the_year() {
+        assert_eq!(parse("title: Oleo\nyear: 1954\nsubsubtitle: 1960").unwrap().year(), Some(1954));
+        assert_eq!(parse("title: Oleo\nsubsubtitle: From Pal Joey (1940)").unwrap().year(), Some(1940));
+        assert_eq!(parse("title: Oleo\ncopyright: 1955 by Prestige, 10000 copies").unwrap().year(), Some(1955));
+        assert_eq!(parse("title: Oleo\nsubsubtitle: Opus 12345").unwrap().year(), None);
+    }
+
+    #[test]
+    fn it_parses_tag_lists() {
+        assert_eq!(parse("title: Ceora\ntags: [bossa, ballad]").unwrap().tags, vec!["bossa", "ballad"]);
+        assert_eq!(parse("title: Ceora\ntags: bossa, ballad").unwrap().tags, vec!["bossa", "ballad"]);
+        assert!(parse("title: Ceora").unwrap().tags.is_empty());
+        assert!(parse("title: Ceora\ntags: [[bossa]]").is_err());
+    }
+
+    #[test]
+    fn it_merges_defaults_under_the_frontmatter() {
+        let defaults = "copyright: Public domain\nmeter: Swing\ntags: [standard]\n";
+        let defaults = Defaults::parse(Path::new(DEFAULTS_FILE), defaults).unwrap();
+        let parse = |input: &str| Frontmatter::parse_with(Path::new("test.ly"), input, &defaults).unwrap();
+
+        let fm = parse("title: Oleo\nmeter: Fast\ncopyright: ~");
+        assert_eq!(fm.meter.as_deref(), Some("Fast"));
+        assert_eq!(fm.copyright, None);
+        assert_eq!(fm.tags, vec!["standard"]);
+        assert_eq!(parse("title: Oleo").copyright.as_deref(), Some("Public domain"));
+
+        let defaults = Defaults::parse(Path::new(DEFAULTS_FILE), "source:\n  book: The Real Book\n  page: 1\n").unwrap();
+        let source = Frontmatter::parse_with(Path::new("test.ly"), "title: Oleo\nsource:\n  page: 298", &defaults).unwrap();
+        assert_eq!(source.source.unwrap().citation(), "The Real Book, p. 298");
+
+        let err = Frontmatter::parse_with(Path::new("test.ly"), "title: Oleo\nbpm: brisk", &defaults).unwrap_err();
+        assert!(err.to_string().contains("bpm"), "{}", err);
+        assert!(Defaults::parse(Path::new(DEFAULTS_FILE), "title: Untitled").is_err());
+        let err = Defaults::parse(Path::new(DEFAULTS_FILE), "bpm: brisk").unwrap_err().to_string();
+        assert!(err.starts_with("_defaults.yml: bpm: "), "{}", err);
+    }
+
+    #[test]
+    fn it_names_the_file_and_field_on_errors() {
+        let err = parse("composer: Sonny Rollins").unwrap_err().to_string();
+        assert!(err.contains("test.ly"), "{}", err);
+        assert!(err.contains("title"), "{}", err);
+
+        let err = parse("title: Oleo\nbpm: brisk").unwrap_err().to_string();
+        assert!(err.contains("test.ly"), "{}", err);
+        assert!(err.contains("bpm"), "{}", err);
+    }
+}
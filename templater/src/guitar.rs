@@ -0,0 +1,108 @@
+// `voicings.toml`: the fret diagrams a `--guitar` book prints for a
+// chord instead of LilyPond's own (`predefined-guitar-fretboards.ly`).
+// chords are written like in `\chordmode`, in the book's key, since
+// that's what's read. frets go from the low E string up, `x` for a
+// muted string, `o` for an open one, and e.g. `3-2` for the third
+// fret with the second finger.
+//
+//     "c:maj7" = "x;3;2;o;o;o;"
+//     "bes:7" = "x;1;3;1;3;1;"
+//
+// a chord without a voicing, and without a predefined one, gets a
+// diagram LilyPond works out itself.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use minijinja::{context, Value};
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::harmony::parse_pitch;
+
+pub const DEFAULT_VOICINGS_FILE: &str = "voicings.toml";
+
+const GUITAR_STRINGS: usize = 6;
+
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Voicings(BTreeMap<String, String>);
+
+// e.g. "3", "3-2", "x", or "o"
+fn is_fret(fret: &str) -> bool {
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    match fret.split_once('-') {
+        Some((fret, finger)) => is_number(fret) && is_number(finger),
+        None => fret == "x" || fret == "o" || is_number(fret),
+    }
+}
+
+// what's wrong with a voicing, if anything
+fn check(chord: &str, frets: &str) -> Option<String> {
+    let root = chord.split([':', '/']).next().unwrap_or_default();
+    if parse_pitch(root).is_none() {
+        return Some(format!("'{}' isn't a chord, e.g. c:maj7 or bes:7", chord));
+    }
+
+    let frets: Vec<&str> = frets.trim_end_matches(';').split(';').map(str::trim).collect();
+    if frets.len() != GUITAR_STRINGS {
+        return Some(format!("{}: {} strings instead of {}", chord, frets.len(), GUITAR_STRINGS));
+    }
+    frets
+        .iter()
+        .find(|fret| !is_fret(fret))
+        .map(|fret| format!("{}: '{}' isn't a fret, e.g. 3, 3-2, x, or o", chord, fret))
+}
+
+impl Voicings {
+    // a missing file has no voicings
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TemplaterError> {
+        let path = path.as_ref();
+        let error = |message: String| TemplaterError::from_str(&format!("{}: {}", path.display(), message));
+
+        let voicings: Voicings = match fs::read_to_string(path) {
+            Ok(input) => toml::from_str(&input).map_err(|e| error(e.to_string()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Voicings::default()),
+            Err(e) => return Err(TemplaterError::file(path, e)),
+        };
+
+        match voicings.0.iter().find_map(|(chord, frets)| check(chord, frets)) {
+            Some(message) => Err(error(message)),
+            None => Ok(voicings),
+        }
+    }
+
+    // for the `fretboards-header` template, every voicing's chord and frets
+    pub fn context(&self) -> Vec<Value> {
+        self.0
+            .iter()
+            .map(|(chord, frets)| {
+                let frets: Vec<&str> = frets.split(';').map(str::trim).filter(|fret| !fret.is_empty()).collect();
+                let frets = format!("{};", frets.join(";"));
+                context! { chord => chord.trim(), frets }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_checks_every_voicing() {
+        assert_eq!(check("c:maj7", "x;3;2;o;o;o;"), None);
+        assert_eq!(check("bes:m7.5-", "x;1-1;2-3;1-2;2-4;x"), None);
+        assert_eq!(check("h:7", "x;2;1;2;o;2;").unwrap(), "'h:7' isn't a chord, e.g. c:maj7 or bes:7");
+        assert_eq!(check("c:7", "x;3;2;3;1;").unwrap(), "c:7: 5 strings instead of 6");
+        assert_eq!(check("c:7", "x;3;2;3;1;0b;").unwrap(), "c:7: '0b' isn't a fret, e.g. 3, 3-2, x, or o");
+    }
+
+    #[test]
+    fn it_ends_every_fret_with_a_semicolon() {
+        let voicings: Voicings = toml::from_str("\"c:maj7\" = \"x; 3; 2; o; o; o\"\n").unwrap();
+        assert_eq!(voicings.context(), vec![context! { chord => "c:maj7", frets => "x;3;2;o;o;o;" }]);
+    }
+}
@@ -0,0 +1,262 @@
+// sanity checks between a song's chords and its voices. both are
+// transposed by the same `\transpose` in the templates, so they
+// only end up in different keys if the song file itself disagrees,
+// e.g. chords copied from a Bb part, or a voice with its own
+// `\transpose`.
+
+use crate::keys::Key;
+use crate::models::Song;
+use crate::transpose::Pitch;
+
+pub(crate) const MAJOR_SCALE: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+// a mismatch is only reported if less than half of the chord roots
+// are in the melody's key, and another key fits them much better.
+// jazz tunes borrow plenty of chords from outside their key, and
+// blues and modal tunes don't fit a major scale very well at all.
+const MIN_KEY_FIT: f64 = 0.5;
+const KEY_MARGIN: f64 = 0.25;
+
+// without `%` line and `%{ %}` block comments
+fn strip_comments(input: &str) -> String {
+    let mut output = String::new();
+    let mut rest = input;
+
+    while let Some(i) = rest.find('%') {
+        output.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let end = match rest.strip_prefix("%{") {
+            Some(block) => block.find("%}").map(|j| j + 4),
+            None => rest.find('\n'),
+        };
+        match end {
+            Some(end) => rest = &rest[end..],
+            None => rest = "",
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+// "ees1:maj7" -> ees, "as2" -> aes, "f:m7/c" -> f. rests,
+// spacers, and anything else that isn't a pitch are `None`.
+pub(crate) fn parse_pitch(token: &str) -> Option<Pitch> {
+    let mut chars = token.chars();
+    let letter = chars.next()?;
+    if !('a'..='g').contains(&letter) {
+        return None;
+    }
+
+    let mut rest = chars.as_str();
+    let mut alteration = 0;
+    loop {
+        if let Some(r) = rest.strip_prefix("is") {
+            alteration += 1;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("es") {
+            alteration -= 1;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('s').filter(|_| letter == 'a' || letter == 'e') {
+            // dutch "as" and "es"
+            alteration -= 1;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+
+    // e.g. "break" or "bass" in a markup
+    if rest.starts_with(|c: char| c.is_alphabetic()) {
+        return None;
+    }
+
+    let step = "cdefgab".find(letter)?;
+    Some(Pitch { step, alteration, octave: 0 })
+}
+
+// every token outside of quotes that isn't a command
+fn tokens(input: &str) -> Vec<&str> {
+    input
+        .split('"')
+        .step_by(2)
+        .flat_map(|s| s.split(|c: char| c.is_whitespace() || "{}|<>()".contains(c)))
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// every pitch in a block, ignoring commands and anything quoted
+fn pitches(block: &str) -> Vec<Pitch> {
+    let block = strip_comments(block);
+
+    tokens(&block)
+        .into_iter()
+        .filter(|token| !token.starts_with('\\'))
+        .filter_map(parse_pitch)
+        .collect()
+}
+
+pub fn chord_roots(chords: &str) -> Vec<Pitch> {
+    match chords.split_once("chordmode") {
+        Some((_, chords)) => pitches(chords),
+        None => pitches(chords),
+    }
+}
+
+// the `\transpose` pitches a block wraps its own notes in
+fn own_transpositions(block: &str) -> Vec<String> {
+    let block = strip_comments(block);
+    let tokens = tokens(&block);
+
+    tokens
+        .windows(3)
+        .filter(|w| w[0] == "\\transpose")
+        .map(|w| format!("{} {}", w[1], w[2]))
+        .collect()
+}
+
+// the fraction of pitches in the major scale of `tonic`
+fn key_fit(pitches: &[Pitch], tonic: i32) -> f64 {
+    let fits = pitches
+        .iter()
+        .filter(|pitch| MAJOR_SCALE.contains(&(pitch.pitch_class() - tonic).rem_euclid(12)))
+        .count();
+
+    fits as f64 / pitches.len() as f64
+}
+
+// the major key (or its modes) that fits the most pitches
+fn best_key(pitches: &[Pitch]) -> (i32, f64) {
+    (0..12)
+        .map(|tonic| (tonic, key_fit(pitches, tonic)))
+        .fold((0, 0.0), |best, candidate| if candidate.1 > best.1 { candidate } else { best })
+}
+
+// the first `\key` of a voice, e.g. Eb major
+pub fn written_key(notes: &str) -> Option<Key> {
+    written_keys(notes).into_iter().next()
+}
+
+// every `\key` of a voice in order, the same one twice in a row once
+pub fn written_keys(notes: &str) -> Vec<Key> {
+    let notes = strip_comments(notes);
+    let tokens = tokens(&notes);
+
+    let mut keys: Vec<Key> = vec![];
+    for key in tokens.windows(3).filter(|w| w[0] == "\\key").filter_map(|w| {
+        let tonic = parse_pitch(w[1])?;
+        let mode: Key = format!("c {}", w[2]).parse().ok()?;
+        Some(Key { tonic, mode: mode.mode })
+    }) {
+        if keys.last() != Some(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+fn key_name(tonic: i32) -> &'static str {
+    ["C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B"][tonic.rem_euclid(12) as usize]
+}
+
+// warnings about chords and voices that won't end up in the same key
+pub fn verify_song(song: &Song) -> Vec<String> {
+    let mut warnings = vec![];
+    if song.chords.is_empty() {
+        return warnings;
+    }
+
+    let chords_transposed = own_transpositions(&song.chords);
+    for voice in &song.voices {
+        let voice_transposed = own_transpositions(&voice.notes);
+        if voice_transposed != chords_transposed {
+            let describe = |t: &[String]| match t.is_empty() {
+                true => String::from("not transposed"),
+                false => format!("\\transpose {}", t.join(", \\transpose ")),
+            };
+            warnings.push(format!(
+                "the chords are {} but a voice is {}, so they'd end up in different keys",
+                describe(&chords_transposed),
+                describe(&voice_transposed),
+            ));
+        }
+    }
+
+    // the melody's notes are compared instead of its `\key`, since
+    // modal tunes are often written without a key signature
+    let roots = chord_roots(&song.chords);
+    let melody: Vec<Pitch> = song.voices.first().map(|voice| pitches(&voice.notes)).unwrap_or_default();
+    if roots.is_empty() || melody.is_empty() {
+        return warnings;
+    }
+
+    let (melody_tonic, _) = best_key(&melody);
+    let (chords_tonic, chords_fit) = best_key(&roots);
+    let fit = key_fit(&roots, melody_tonic);
+
+    if fit < MIN_KEY_FIT && chords_fit - fit >= KEY_MARGIN {
+        warnings.push(format!(
+            "the melody fits {} major, but the chords fit {} major ({:.0}% vs {:.0}% of chord roots in key)",
+            key_name(melody_tonic),
+            key_name(chords_tonic),
+            chords_fit * 100.0,
+            fit * 100.0,
+        ));
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_chord_roots() {
+        let roots: Vec<String> = chord_roots("\\chordmode {\n  \\boxMark \"A\"\n  ees1:maj7 | as2:m7 s4 r % bes:7\n  f:m7/c\n}")
+            .iter()
+            .map(|p| p.display_name())
+            .collect();
+
+        assert_eq!(roots, vec!["Eb", "Ab", "F"]);
+    }
+
+    #[test]
+    fn it_finds_the_written_key() {
+        let notes = "\\relative c' {\n  % \\key c \\major\n  \\key ees \\major\n  \\key c \\minor\n}";
+
+        assert_eq!(written_key(notes).map(|key| key.to_string()).as_deref(), Some("Eb major"));
+        assert_eq!(written_key("\\relative c' { c4 }"), None);
+
+        let keys: Vec<String> = written_keys("\\key bes \\major c1 \\key bes \\major \\key d \\major c1 \\key bes \\major")
+            .iter()
+            .map(|key| key.to_string())
+            .collect();
+        assert_eq!(keys, vec!["Bb major", "D major", "Bb major"]);
+    }
+
+    fn song(chords: &str, voice: &str) -> Song {
+        let input = format!("title: Test\n---\n{}\n---\n{}", chords, voice);
+        Song::parse(std::path::Path::new("test.ly"), &input, false).unwrap()
+    }
+
+    #[test]
+    fn it_warns_about_mismatched_keys() {
+        let voice = "\\relative c' { \\key ees \\major \\numericTimeSignature bes'4 g f ees c bes aes' g }";
+
+        assert!(verify_song(&song("\\chordmode { ees1:maj7 f:m7 bes:7 ees:maj7 c:m7 }", voice)).is_empty());
+        // the same changes in A: copied from another part
+        assert_eq!(verify_song(&song("\\chordmode { a1:maj7 b:m7 e:7 a:maj7 fis:m7 }", voice)).len(), 1);
+    }
+
+    #[test]
+    fn it_warns_about_mismatched_transpositions() {
+        let warnings = verify_song(&song(
+            "\\chordmode { c1 }",
+            "\\transpose bes c \\relative c' { \\numericTimeSignature c d e }",
+        ));
+
+        assert_eq!(warnings, vec!["the chords are not transposed but a voice is \\transpose bes c, so they'd end up in different keys"]);
+    }
+}
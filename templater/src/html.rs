@@ -0,0 +1,106 @@
+// `--html-index`: an `index.html` next to the books, e.g. for the
+// website. it lists every song in the book, with links to the pdf
+// of every transposition.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use minijinja::{context, Value};
+
+use crate::book::Book;
+use crate::errors::TemplaterError;
+use crate::export::SongMetadata;
+use crate::lilypond::SourceMap;
+use crate::models::{Config, Song, TemplaterConfig};
+use crate::renderer::{LilypondOutput, Outputs, Renderer};
+
+pub const HTML_INDEX_FILE: &str = "index.html";
+
+pub struct HtmlIndex;
+
+impl Renderer for HtmlIndex {
+    fn outputs(&self) -> Outputs {
+        Outputs::Single
+    }
+
+    fn output_path(
+        &self,
+        config: &Config,
+        _conf: &TemplaterConfig,
+        _volume: Option<usize>,
+    ) -> Result<PathBuf, TemplaterError> {
+        Ok(config.out_dir.join(HTML_INDEX_FILE))
+    }
+
+    fn render(
+        &self,
+        book: &Book,
+        out: &mut dyn Write,
+        _conf: &TemplaterConfig,
+        songs: &[Song],
+        _volume: Option<usize>,
+    ) -> Result<SourceMap, TemplaterError> {
+        // what the book's main renderer writes, or the pdf made from it
+        let renderer = book.renderer();
+        let mut books = vec![];
+        for output in book.outputs(renderer.as_ref()) {
+            let (conf, volume) = (output.conf, output.volume);
+            let mut path = book.output_file(renderer.as_ref(), &output)?;
+            if renderer.lilypond_output() == Some(LilypondOutput::Pdf) {
+                path.set_extension("pdf");
+            }
+
+            books.push(context! {
+                key => conf.transpose_text.display_text.as_str(),
+                volume,
+                file => link(&book.config.out_dir, &path),
+            });
+        }
+
+        let songs: Vec<Value> = songs
+            .iter()
+            .map(|song| {
+                let song_metadata = SongMetadata::new(song);
+                context! {
+                    number => song_metadata.number,
+                    title => song_metadata.title,
+                    composer => song_metadata.composer,
+                    poet => song_metadata.poet,
+                    meter => song_metadata.meter,
+                    bpm => song_metadata.bpm,
+                    time => song_metadata.time,
+                    key => song_metadata.key,
+                    label => song_metadata.label,
+                    tags => song.tags.clone(),
+                }
+            })
+            .collect();
+
+        let index = book.templates.render("html-index", context! {
+            metadata => book.config.metadata.context(),
+            num_tunes => songs.len(),
+            books,
+            songs,
+        })?;
+        write!(out, "{}", index)?;
+
+        Ok(SourceMap::default())
+    }
+}
+
+// relative to the index, with forward slashes
+fn link(out_dir: &Path, path: &Path) -> String {
+    let path = path.strip_prefix(out_dir).unwrap_or(path);
+    path.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_links_relative_to_the_index() {
+        assert_eq!(link(Path::new("build"), Path::new("build/vol1/openbook-Bb.pdf")), "vol1/openbook-Bb.pdf");
+        assert_eq!(link(Path::new("."), Path::new("./openbook-Concert.pdf")), "openbook-Concert.pdf");
+    }
+}
@@ -0,0 +1,527 @@
+// `templater export charts.html --format ireal`: every song's chords as
+// an iReal Pro chart, so the book's changes can be played along with in
+// the app. a chart is an `irealbook://` link, with its title, composer,
+// style (from its meter, e.g. Up Tempo Swing for "Bright", or Medium
+// Swing), key, and progression, four cells (beats) a bar:
+//
+// - `[` and `]` around every section, from the chords' `\boxMark`s,
+//   e.g. `*A` for "A2" or `*i` for "Intro", or without any, from the
+//   song's form if the chords are a single chorus of it
+// - `{` and `}` around repeats, and `N1`, `N2`, ... for their endings
+// - `T44` for the time signature, at the start and wherever it changes
+// - `S` and `Q` for `\segnoMark` and `\codaMark`
+// - `x` for a bar with the same chords as the last one
+//
+// e.g. `^7` is maj7, `-` minor, `h7` half-diminished, and `o`
+// diminished. the charts are in concert pitch, the app transposes
+// them itself. the page has a link for every song and one for the
+// whole book, which the app imports as a playlist.
+
+use crate::chord::{Chord, Polychord, POLYCHORD};
+use crate::form::{song_form, Form};
+use crate::harmony::parse_pitch;
+use crate::models::Song;
+use crate::music::{events, spanned_events, tokenize, Event, Fraction, Token};
+use crate::numbers::quality;
+use crate::repeats::{bar_marks, literal, BarMark};
+use crate::timing::Clock;
+
+// every bar is this many cells, whatever its time signature
+const CELLS: u64 = 4;
+// (a word in the meter, the app's style), the first match wins
+const STYLES: [(&str, &str); 12] = [
+    ("bossa", "Bossa Nova"),
+    ("samba", "Samba"),
+    ("latin", "Latin"),
+    ("waltz", "Jazz Waltz"),
+    ("ballad", "Ballad"),
+    ("funk", "Funk"),
+    ("rock", "Rock"),
+    ("medium up", "Medium Up Swing"),
+    ("up", "Up Tempo Swing"),
+    ("bright", "Up Tempo Swing"),
+    ("fast", "Up Tempo Swing"),
+    ("slow", "Slow Swing"),
+];
+const DEFAULT_STYLE: &str = "Medium Swing";
+
+// what's in the chords between them
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mark {
+    Bar(BarMark),
+    // e.g. "*A"
+    Section(String),
+    Segno,
+    Coda,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Bar {
+    // the chords, by the cell they start in
+    cells: Vec<Option<String>>,
+    // e.g. (3, 4) when it's in a new time signature
+    time: Option<(u64, u64)>,
+    section: Option<String>,
+    ending: Option<usize>,
+    segno: bool,
+    coda: bool,
+    start_repeat: bool,
+    end_repeat: bool,
+    // a double or final bar line after it
+    double: bool,
+    pickup: bool,
+}
+
+impl Bar {
+    fn new() -> Self {
+        Bar { cells: vec![None; CELLS as usize], ..Bar::default() }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cells.iter().all(|cell| cell.is_none())
+    }
+
+    // whether anything but its chords is marked at its start
+    fn is_marked(&self) -> bool {
+        self.time.is_some() || self.section.is_some() || self.ending.is_some() || self.segno || self.coda
+    }
+}
+
+// e.g. "h7" for "m7b5", "^9" for "maj7(9)", or "13b9" for "7b9(13)"
+fn ireal_quality(quality: &str) -> String {
+    // the steps in parentheses extend a seventh, or are added
+    let mut base = String::new();
+    let mut extensions: Vec<u32> = vec![];
+    for (i, part) in quality.split(['(', ')']).enumerate() {
+        match i.is_multiple_of(2) {
+            true => base.push_str(part),
+            false => extensions.extend(part.parse::<u32>().ok()),
+        }
+    }
+    let quality = match extensions.iter().max() {
+        Some(step) if base.contains('7') => base.replacen('7', &step.to_string(), 1),
+        Some(step) => format!("{}add{}", base, step),
+        None => base,
+    };
+    let quality = quality.replace("6/9", "69").replace("sus4", "sus").replace('°', "o").replace("min", "m");
+    if let Some(rest) = quality.strip_prefix("m7b5") {
+        return format!("h7{}", rest);
+    }
+    if let Some(rest) = quality.strip_prefix("m7+") {
+        return format!("-^7{}", rest);
+    }
+    match (quality.strip_prefix("maj"), quality.strip_prefix('m')) {
+        (Some(rest), _) => format!("^{}", rest),
+        (None, Some(rest)) => format!("-{}", rest),
+        (None, None) => quality,
+    }
+}
+
+// e.g. "Bb-7/F" for "bes:m7/f", none if it isn't a chord. a polychord
+// is its upper chord over the lower one's root
+fn ireal_chord(token: &str) -> Option<String> {
+    let name = |chord: &Chord, bass: Option<String>| {
+        let mut name = chord.root.display_name() + &ireal_quality(&quality(&chord.steps));
+        if let Some(bass) = bass.or_else(|| chord.bass.as_ref().map(|bass| bass.pitch.display_name())) {
+            name.push('/');
+            name.push_str(&bass);
+        }
+        name
+    };
+
+    match token.contains(POLYCHORD) {
+        true => {
+            let chord = Polychord::parse(token)?;
+            Some(name(&chord.upper, Some(chord.lower.root.display_name())))
+        },
+        false => Chord::parse(token).map(|chord| name(&chord, None)),
+    }
+}
+
+// e.g. "*A" for "A2", "*i" for "Intro", none for a mark the app
+// doesn't have, e.g. "Solos"
+fn section(mark: &str) -> Option<String> {
+    let mark = mark.trim();
+    match mark.chars().next()? {
+        letter @ 'A'..='D' if mark.len() == 1 || !mark.chars().nth(1)?.is_alphabetic() => Some(format!("*{}", letter)),
+        _ if mark.eq_ignore_ascii_case("intro") => Some(String::from("*i")),
+        _ if mark.eq_ignore_ascii_case("verse") => Some(String::from("*V")),
+        _ => None,
+    }
+}
+
+// every repeat, bar line, section, segno, and coda in `chords`
+fn marks(chords: &str) -> Vec<(usize, Mark)> {
+    let mut marks: Vec<(usize, Mark)> =
+        bar_marks(chords).into_iter().map(|(end, mark)| (end, Mark::Bar(mark))).collect();
+
+    let tokens = tokenize(chords);
+    for (i, (token, _, end)) in tokens.iter().enumerate() {
+        match token {
+            Token::Word(word) if word == "\\segnoMark" => marks.push((*end, Mark::Segno)),
+            Token::Word(word) if word == "\\codaMark" => marks.push((*end, Mark::Coda)),
+            Token::Word(word) if word == "\\boxMark" || word == "\\mark" => {
+                let Some((Token::Literal, _, end)) = tokens.get(i + 1) else { continue };
+                if let Some(name) = section(literal(chords, *end)) {
+                    marks.push((*end, Mark::Section(name)));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    marks.sort_by_key(|(end, _)| *end);
+    marks
+}
+
+// (when, from the start, and the new time signature) of every `\time`
+fn time_changes(music: &str) -> Vec<(Fraction, (u64, u64))> {
+    let mut changes = vec![];
+    let mut time = Fraction::ZERO;
+    for event in events(music) {
+        match event {
+            Event::Time { beats, unit, .. } => changes.push((time, (beats, unit))),
+            Event::Note { duration, .. } => time = time + duration,
+            _ => {},
+        }
+    }
+    changes
+}
+
+#[derive(Debug)]
+struct ChartWriter {
+    bars: Vec<Bar>,
+    // the one being written
+    bar: Bar,
+    clock: Clock,
+    // the melody's, since the chords don't usually have any
+    time_changes: Vec<(Fraction, (u64, u64))>,
+    // whether the last note was a chord, which a modifier is part of
+    chord: bool,
+}
+
+impl ChartWriter {
+    fn new(time_changes: Vec<(Fraction, (u64, u64))>) -> Self {
+        let meter = time_changes.first().filter(|(at, _)| *at == Fraction::ZERO).map_or((4, 4), |(_, meter)| *meter);
+        let mut bar = Bar::new();
+        bar.time = Some(meter);
+        ChartWriter {
+            bars: vec![],
+            bar,
+            clock: Clock::new(meter),
+            time_changes,
+            chord: false,
+        }
+    }
+
+    // a mark at the end of a bar is on the last one
+    fn ending_bar(&mut self) -> &mut Bar {
+        match (self.clock.position == Fraction::ZERO, self.bars.last_mut()) {
+            (true, Some(bar)) => bar,
+            _ => &mut self.bar,
+        }
+    }
+
+    fn mark(&mut self, mark: Mark) {
+        match mark {
+            Mark::Bar(BarMark::StartRepeat) => self.bar.start_repeat = true,
+            Mark::Bar(BarMark::EndRepeat) => self.ending_bar().end_repeat = true,
+            Mark::Bar(BarMark::EndStartRepeat) => {
+                self.ending_bar().end_repeat = true;
+                self.bar.start_repeat = true;
+            },
+            Mark::Bar(BarMark::Ending(ending)) => self.bar.ending = Some(ending),
+            Mark::Bar(BarMark::Double | BarMark::Final) => self.ending_bar().double = true,
+            Mark::Section(name) => self.bar.section = Some(name),
+            Mark::Segno => self.bar.segno = true,
+            Mark::Coda => self.bar.coda = true,
+        }
+    }
+
+    fn time_signature(&mut self, beats: u64, unit: u64) {
+        if (beats, unit) != self.clock.meter {
+            self.clock.meter = (beats, unit);
+            self.bar.time = Some((beats, unit));
+        }
+    }
+
+    fn partial(&mut self, duration: Fraction) {
+        self.clock.partial(duration);
+        self.bar.pickup = self.clock.pickup;
+    }
+
+    fn note(&mut self, chord: Option<String>, duration: Fraction) {
+        if self.clock.position == Fraction::ZERO {
+            let change = self.time_changes.iter().rev().find(|(at, _)| *at <= self.clock.time).map(|(_, meter)| *meter);
+            if let Some((beats, unit)) = change {
+                self.time_signature(beats, unit);
+            }
+        }
+
+        self.chord = chord.is_some();
+        if let Some(chord) = chord {
+            let measure = self.clock.measure();
+            let cell = self.clock.position * Fraction::new(CELLS * measure.den, measure.num);
+            let cell = ((cell.num / cell.den) as usize).min(CELLS as usize - 1);
+            self.bar.cells[cell] = Some(chord);
+        }
+
+        for _ in self.clock.advance(duration) {
+            self.next_bar();
+        }
+    }
+
+    // e.g. the `:m7` after `f1`
+    fn modifier(&mut self, text: &str) {
+        if !self.chord {
+            return;
+        }
+        let bars = self.bars.iter_mut().rev().flat_map(|bar| bar.cells.iter_mut().rev());
+        if let Some(chord) = self.bar.cells.iter_mut().rev().chain(bars).flatten().next() {
+            chord.push_str(text);
+        }
+    }
+
+    fn next_bar(&mut self) {
+        let bar = std::mem::replace(&mut self.bar, Bar::new());
+        // an empty pickup isn't a bar in the app
+        if bar.pickup && bar.is_empty() {
+            self.bar = Bar { cells: self.bar.cells.clone(), pickup: false, ..bar };
+            return;
+        }
+        self.bars.push(bar);
+    }
+
+    fn finish(mut self) -> Vec<Bar> {
+        if self.clock.position > Fraction::ZERO || !self.bar.is_empty() {
+            self.next_bar();
+        }
+        for bar in &mut self.bars {
+            for cell in bar.cells.iter_mut() {
+                *cell = cell.take().and_then(|token| ireal_chord(&token));
+            }
+        }
+        self.bars
+    }
+}
+
+// the bars of the song's chords
+fn chart_bars(song: &Song) -> Vec<Bar> {
+    let melody = song.voices.iter().find(|voice| voice.name.is_none()).map_or("", |voice| voice.notes.as_str());
+    let mut writer = ChartWriter::new(time_changes(melody));
+
+    let mut marks = marks(&song.chords).into_iter().peekable();
+    for (event, end) in spanned_events(&song.chords) {
+        while let Some((_, mark)) = marks.next_if(|(at, _)| *at < end) {
+            writer.mark(mark);
+        }
+        match event {
+            Event::Note { pitch, duration, .. } => {
+                writer.note(pitch.filter(|pitch| parse_pitch(pitch).is_some()), duration);
+            },
+            Event::Modifier { text, .. } => writer.modifier(&text),
+            Event::Time { beats, unit, .. } => writer.time_signature(beats, unit),
+            Event::Partial { duration, .. } => writer.partial(duration),
+            _ => {},
+        }
+    }
+    for (_, mark) in marks {
+        writer.mark(mark);
+    }
+    let mut bars = writer.finish();
+
+    // without any sections, the form's, if the chords are one chorus of it
+    if bars.iter().all(|bar| bar.section.is_none()) {
+        if let Some(Form::Sections { letters, bars: Some(chorus) }) = song_form(song) {
+            let length = chorus as usize / letters.len().max(1);
+            let start = bars.iter().take_while(|bar| bar.pickup).count();
+            if chorus as usize == bars.len() - start && length > 0 && (chorus as usize).is_multiple_of(letters.len()) {
+                for (i, letter) in letters.chars().enumerate() {
+                    bars[start + i * length].section = section(&letter.to_string());
+                }
+            }
+        }
+    }
+
+    bars
+}
+
+// e.g. "T44", or "T12" for 12/8
+fn ireal_time((beats, unit): (u64, u64)) -> String {
+    match (beats, unit) {
+        (12, 8) => String::from("T12"),
+        (beats, unit) => format!("T{}{}", beats, unit),
+    }
+}
+
+// the chords of every cell, a space where there isn't one and a comma
+// between chords in cells next to each other
+fn ireal_cells(cells: &[Option<String>]) -> String {
+    let mut out = String::new();
+    for (i, cell) in cells.iter().enumerate() {
+        match cell {
+            Some(chord) => {
+                if i > 0 && cells[i - 1].is_some() {
+                    out.push(',');
+                }
+                out.push_str(chord);
+            },
+            None => out.push(' '),
+        }
+    }
+    out
+}
+
+// the bars as an iReal Pro progression, e.g. `{*AT44C^7 |x }`
+fn progression(bars: &[Bar]) -> String {
+    let mut out = String::new();
+    // the chords of the last bar and the one it ends on
+    let mut last: Option<&[Option<String>]> = None;
+    let mut last_chord: Option<&str> = None;
+
+    for (i, bar) in bars.iter().enumerate() {
+        let opens = |bar: &Bar, i: usize| i == 0 || bar.start_repeat || bar.section.is_some() || bars[i - 1].double;
+        if bar.start_repeat {
+            out.push('{');
+        } else if opens(bar, i) {
+            out.push('[');
+        }
+        out.push_str(bar.section.as_deref().unwrap_or_default());
+        out.extend(bar.time.map(ireal_time));
+        out.extend(bar.ending.map(|ending| format!("N{}", ending)));
+        if bar.segno {
+            out.push('S');
+        }
+        if bar.coda {
+            out.push('Q');
+        }
+
+        // an empty bar is the last one again if it had a single chord,
+        // or else its last chord held
+        let single = last.is_some_and(|cells| cells.iter().flatten().count() == 1);
+        let repeated = last == Some(bar.cells.as_slice()) || (bar.is_empty() && single);
+        let cells = match (last_chord, bar.is_empty()) {
+            _ if repeated && !bar.is_marked() => String::from(" x  "),
+            (Some(chord), true) => ireal_cells(&[Some(chord.to_string()), None, None, None]),
+            _ => ireal_cells(&bar.cells),
+        };
+        out.push_str(&cells);
+        if !bar.is_empty() {
+            last = Some(&bar.cells);
+            last_chord = bar.cells.iter().flatten().last().map(|chord| chord.as_str());
+        }
+
+        let next = bars.get(i + 1);
+        match next {
+            _ if bar.end_repeat => out.push('}'),
+            None => out.push('Z'),
+            Some(next) if opens(next, i + 1) => out.push(']'),
+            Some(_) => out.push('|'),
+        }
+    }
+
+    out
+}
+
+// e.g. "Up Tempo Swing" for "Bright", or Medium Swing
+fn style(meter: Option<&str>) -> &'static str {
+    let meter = meter.unwrap_or_default().to_lowercase();
+    STYLES.iter().find(|(word, _)| meter.contains(word)).map_or(DEFAULT_STYLE, |(_, style)| style)
+}
+
+// e.g. "Eb", or "C-" for C minor
+fn ireal_key(song: &Song) -> String {
+    match song.key {
+        Some(key) if key.mode == "minor" => format!("{}-", key.tonic.display_name()),
+        Some(key) => key.tonic.display_name(),
+        None => String::from("C"),
+    }
+}
+
+// title=composer=style=key=n=progression, whose fields can't have an `=`
+pub fn chart(song: &Song) -> String {
+    let field = |s: &str| s.replace('=', "-");
+    [
+        field(&song.title),
+        field(&song.composer),
+        style(song.meter.as_deref()).to_string(),
+        ireal_key(song),
+        String::from("n"),
+        progression(&chart_bars(song)),
+    ]
+    .join("=")
+}
+
+// `irealbook://` and the charts, percent-encoded
+pub fn link(charts: &[String]) -> String {
+    let mut link = String::from("irealbook://");
+    for byte in charts.join("=").bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => link.push(byte as char),
+            byte => link.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    link
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// a page with a link to every song's chart, and one to all of them
+pub fn export(songs: &[Song]) -> String {
+    let charts: Vec<String> = songs.iter().map(chart).collect();
+
+    let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>iReal Pro charts</title>\n</head>\n<body>\n");
+    out.push_str(&format!("<p><a href=\"{}\">All {} songs</a></p>\n<ol>\n", link(&charts), songs.len()));
+    for (song, chart) in songs.iter().zip(&charts) {
+        out.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            link(std::slice::from_ref(chart)),
+            escape_html(&song.title)
+        ));
+    }
+    out.push_str("</ol>\n</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn it_writes_ireal_chord_names() {
+        assert_eq!(ireal_chord("bes:m7/f").as_deref(), Some("Bb-7/F"));
+        assert_eq!(ireal_chord("c:m7.5-").as_deref(), Some("Ch7"));
+        assert_eq!(ireal_chord("f:maj7.9").as_deref(), Some("F^9"));
+        assert_eq!(ireal_chord("g:7.9-.13").as_deref(), Some("G13b9"));
+        assert_eq!(ireal_chord("d:dim7").as_deref(), Some("Do7"));
+        assert_eq!(ireal_chord("e:sus4").as_deref(), Some("Esus"));
+        assert_eq!(ireal_chord("ees:6.9").as_deref(), Some("Eb69"));
+        assert_eq!(section("A2").as_deref(), Some("*A"));
+        assert_eq!(section("Intro").as_deref(), Some("*i"));
+        assert_eq!(section("Solos"), None);
+        assert_eq!(style(Some("Bright Swing")), "Up Tempo Swing");
+        assert_eq!(style(None), "Medium Swing");
+    }
+
+    #[test]
+    fn it_writes_a_song_as_an_ireal_chart() {
+        let input = "title: Test = Tune\ncomposer: Someone\nmeter: Medium Up\n---\n\
+                     \\chordmode { \\partial 4 s4 | \\boxMark \"A\" \\repeat volta 2 { c1:maj7 | c1:maj7 | d2:m7 g2:7 | }\n\
+                     \\alternative { { e1:m7 } { f2 g2:7 } } \\boxMark \"B\" f1:maj7 | s2. | }\n---\n\
+                     \\key c \\minor \\time 4/4 \\numericTimeSignature \\partial 4 g'4 |\n\
+                     c''1 | c''1 | c''1 | c''1 | c''1 | c''1 | \\time 3/4 c''2. |\n";
+        let song = Song::parse(Path::new("songs/test-tune.ly"), input, false).unwrap();
+
+        assert_eq!(
+            chart(&song),
+            "Test - Tune=Someone=Medium Up Swing=C-=n=\
+             {*AT44C^7   | x  |D-7 G7 |N1E-7   }N2F G7 ][*BF^7   |T34F^7   Z"
+        );
+        assert_eq!(link(&[String::from("A=B c")]), "irealbook://A%3DB%20c");
+        assert!(export(&[song]).contains("<a href=\"irealbook://Test%20-%20Tune%3DSomeone"));
+    }
+}
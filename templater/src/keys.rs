@@ -0,0 +1,151 @@
+// a song's key, for the key index and `templater export`. it's
+// `key:` in the front matter if the song sets it, otherwise the
+// melody's first `\key`. every other key the melody's `\key`s
+// change to is a modulation, e.g. "Bb major → D major". keys are
+// always in concert pitch, and transposed along with the book.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::TemplaterError;
+use crate::models::TransposeText;
+use crate::transpose::Pitch;
+
+// lilypond's `\key` modes
+const MODES: [&str; 9] = ["major", "minor", "ionian", "dorian", "phrygian", "lydian", "mixolydian", "aeolian", "locrian"];
+// fifths from the major key on the same tonic, by mode
+const MODE_FIFTHS: [i32; 9] = [0, -3, 0, -2, -4, 1, -1, -3, -5];
+// fifths from c, by letter
+const LETTER_FIFTHS: [i32; 7] = [0, 2, 4, -1, 1, 3, 5];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub tonic: Pitch,
+    // one of `MODES`
+    pub mode: &'static str,
+}
+
+impl Key {
+    // e.g. `ees \major`
+    pub fn lilypond_name(&self) -> String {
+        format!("{} \\{}", self.tonic.lilypond_name(), self.mode)
+    }
+
+    // the key a book's instrument reads it in
+    pub fn transposed(&self, transpose_text: &TransposeText) -> Key {
+        match transpose_target(transpose_text) {
+            Some(target) => Key { tonic: self.tonic.transpose(&target), mode: self.mode },
+            None => *self,
+        }
+    }
+
+    // sharps in the key signature, negative for flats, e.g. -3 for
+    // `ees \major`. more than 7 either way has double accidentals
+    pub fn fifths(&self) -> i32 {
+        let mode = MODES.iter().position(|mode| *mode == self.mode).unwrap_or_default();
+        LETTER_FIFTHS[self.tonic.step] + self.tonic.alteration * 7 + MODE_FIFTHS[mode]
+    }
+
+    // circle of fifths order would be nicer for practicing, but
+    // by tonic is easier to look something up in
+    pub fn sort_key(&self) -> String {
+        let mode = MODES.iter().position(|mode| *mode == self.mode).unwrap_or_default();
+        format!("{:02}-{}-{}", self.tonic.pitch_class(), mode, self.tonic.display_name())
+    }
+}
+
+// e.g. "Bb major → D major" for a song that modulates
+pub fn describe_keys(keys: &[Key]) -> String {
+    keys.iter().map(|key| key.to_string()).collect::<Vec<_>>().join(" → ")
+}
+
+// the order sharps are added to a key signature in, flats the other way
+const SHARPS: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+
+// the alteration of every letter in the key signature with `fifths`
+// sharps (or flats, if it's negative), by step
+pub fn key_alterations(fifths: i32) -> [i32; 7] {
+    let mut alterations = [0; 7];
+    for i in 0..fifths.unsigned_abs() as usize {
+        match fifths > 0 {
+            true => alterations[SHARPS[i % 7]] += 1,
+            false => alterations[SHARPS[6 - i % 7]] -= 1,
+        }
+    }
+    alterations
+}
+
+// "c d'" -> d
+fn transpose_target(transpose_text: &TransposeText) -> Option<Pitch> {
+    let target = transpose_text.lilypond_text.split_whitespace().nth(1)?;
+    target.trim_end_matches(['\'', ',']).parse().ok()
+}
+
+impl fmt::Display for Key {
+    // e.g. "Eb major"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.tonic.display_name(), self.mode)
+    }
+}
+
+impl FromStr for Key {
+    type Err = TemplaterError;
+
+    // accepts "Eb", "ebm", "c minor", and lilypond style "ees \minor"
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let err = || TemplaterError::from_str(&format!("'{}' is not a valid key, e.g. Eb or \"c minor\".", input));
+
+        let lower = input.trim().to_lowercase();
+        let lower = lower.strip_prefix("\\key").unwrap_or(&lower);
+        let mut words = lower.split_whitespace();
+        let tonic = words.next().ok_or_else(err)?;
+        let (tonic, mut mode) = match tonic.strip_suffix('m') {
+            Some(tonic) => (tonic, "minor"),
+            None => (tonic, "major"),
+        };
+        if let Some(word) = words.next() {
+            mode = MODES.iter().find(|mode| **mode == word.trim_start_matches('\\')).ok_or_else(err)?;
+        }
+        if words.next().is_some() {
+            return Err(err());
+        }
+
+        let tonic = Pitch::from_str(tonic).map_err(|_| err())?;
+        Ok(Key { tonic, mode })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transpose::transpose_text;
+
+    fn key(input: &str) -> Key {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn it_parses_keys() {
+        assert_eq!(key("Eb").to_string(), "Eb major");
+        assert_eq!(key("ebm").to_string(), "Eb minor");
+        assert_eq!(key("F# minor").lilypond_name(), "fis \\minor");
+        assert_eq!(key("\\key d \\dorian").to_string(), "D dorian");
+        assert!("h".parse::<Key>().is_err());
+        assert!("c blues".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn it_counts_accidentals() {
+        assert_eq!(key("Eb").fifths(), -3);
+        assert_eq!(key("c# minor").fifths(), 4);
+        assert_eq!(key("\\key d \\dorian").fifths(), 0);
+        assert_eq!(key("A#").fifths(), 10);
+    }
+
+    #[test]
+    fn it_transposes_keys() {
+        assert_eq!(key("Eb").transposed(&transpose_text("bb").unwrap()).to_string(), "F major");
+        assert_eq!(key("c minor").transposed(&transpose_text("eb+8va").unwrap()).to_string(), "A minor");
+        assert_eq!(key("Eb").transposed(&transpose_text("bass").unwrap()).to_string(), "Eb major");
+    }
+}
@@ -0,0 +1,129 @@
+// goals of this project
+// * automatically parse frontmatter in the songfiles
+// * generate a `book.ly` from templates
+//   -> book cover / title page
+//   -> alphabetical (by song name) table of contents
+//   -> extra ToCs (by composer name, meter, bpm)
+// * setup each song from template
+//   -> name, composer, etc
+//   -> transposing_instrument (default to c)
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// a book written to stdout (`--output -`) can't share it with the
+// templater's own messages, so they go to stderr instead
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+pub fn log_to_stderr() {
+    LOG_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn logs_to_stderr() -> bool {
+    LOG_TO_STDERR.load(Ordering::Relaxed)
+}
+
+// `templater browse` draws over the whole terminal, so nothing is
+// logged while it's open
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+// `println!` for the templater's messages, e.g. "[info]: ...". it's
+// before the modules so they can all use it
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        match ($crate::is_quiet(), $crate::logs_to_stderr()) {
+            (true, _) => {},
+            (false, true) => eprintln!($($arg)*),
+            (false, false) => println!($($arg)*),
+        }
+    };
+}
+
+pub mod abc;
+pub mod absolute;
+pub mod anthology;
+pub mod backing;
+pub mod book;
+pub mod bookdiff;
+pub mod braille;
+pub mod browse;
+pub mod cache;
+pub mod changelog;
+pub mod check;
+pub mod chord;
+pub mod chordnames;
+pub mod clef;
+pub mod cli;
+pub mod composers;
+pub mod drafts;
+pub mod duplicates;
+pub mod errors;
+pub mod export;
+pub mod filter;
+pub mod form;
+pub mod frontmatter;
+pub mod guitar;
+pub mod harmony;
+pub mod html;
+pub mod ireal;
+pub mod keys;
+pub mod license;
+pub mod lilypond;
+pub mod lint;
+pub mod lsp;
+pub mod lyrics;
+pub mod manuscript;
+pub mod melisma;
+pub mod metadata;
+pub mod migrate;
+pub mod models;
+pub mod music;
+pub mod normalize;
+pub mod musicxml;
+pub mod numbers;
+pub mod order;
+pub mod pickup;
+pub mod profiles;
+pub mod provenance;
+pub mod qr;
+pub mod range;
+pub mod release;
+pub mod repeats;
+pub mod renderer;
+pub mod scaffold;
+pub mod sections;
+pub mod serve;
+pub mod session;
+pub mod setlist;
+pub mod songids;
+pub mod spelling;
+pub mod stats;
+pub mod style;
+pub mod tempo;
+pub mod templates;
+pub mod timing;
+pub mod toc;
+pub mod transpose;
+pub mod utils;
+pub mod vars;
+pub mod volumes;
+pub mod warnings;
+pub mod watch;
+pub mod web;
+pub mod written;
+pub mod xml;
+
+pub use crate::book::{Book, BookBuilder};
+pub use crate::session::BookSession;
+pub use crate::errors::TemplaterError;
+pub use crate::models::{Config, ConfigOverrides, Song};
+
+use crate::utils::*;
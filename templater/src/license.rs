@@ -0,0 +1,87 @@
+// a song's `license` in the front matter, e.g. `license: CC BY-SA
+// 4.0` or `license: public domain`, and `pd_year`, the year it enters
+// the public domain, e.g. `pd_year: 2051`. `--public-domain-only`
+// leaves out every song that isn't, or doesn't say, so a book made
+// from a mixed collection can be handed out. the "Licenses" page at
+// the end of the book lists every song under its license.
+
+use std::collections::BTreeMap;
+
+use minijinja::{context, Value};
+
+use crate::errors::TemplaterError;
+use crate::models::Song;
+use crate::templates::Templates;
+
+const PUBLIC_DOMAIN: &str = "Public Domain";
+
+// e.g. "public domain", "PD", or "CC0 1.0"
+pub fn is_public_domain_license(license: &str) -> bool {
+    let license = license.trim().to_lowercase().replace(['-', '_'], " ");
+    matches!(license.as_str(), "public domain" | "pd") || license.starts_with("cc0")
+}
+
+// in `year`, e.g. the year the book is built
+pub fn is_public_domain(song: &Song, year: u32) -> bool {
+    song.license.as_deref().is_some_and(is_public_domain_license) || song.pd_year.is_some_and(|pd_year| pd_year <= year)
+}
+
+// what the "Licenses" page lists the song under
+fn license_name(song: &Song, year: u32) -> String {
+    match (is_public_domain(song, year), &song.license) {
+        (true, _) => PUBLIC_DOMAIN.to_string(),
+        (false, Some(license)) => license.clone(),
+        (false, None) => String::from("Not Stated"),
+    }
+}
+
+// the "Licenses" page, none if no song has a license or pd_year
+pub fn render_licenses(templates: &Templates, songs: &[Song], year: u32) -> Result<Option<String>, TemplaterError> {
+    if songs.iter().all(|song| song.license.is_none() && song.pd_year.is_none()) {
+        return Ok(None);
+    }
+
+    let mut groups: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    for song in songs {
+        groups.entry(license_name(song, year)).or_default().push(context! {
+            number => song.number,
+            title => song.title.as_str(),
+            label => song.label(),
+            // only while it isn't yet
+            pd_year => song.pd_year.filter(|pd_year| *pd_year > year),
+        });
+    }
+
+    // the public domain first, then by name
+    let mut groups: Vec<(String, Vec<Value>)> = groups.into_iter().collect();
+    groups.sort_by_key(|(name, _)| name != PUBLIC_DOMAIN);
+    let groups: Vec<Value> = groups.into_iter().map(|(name, songs)| context! { name, songs }).collect();
+
+    templates.render("licenses", context! { groups, year }).map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontmatter::Defaults;
+    use crate::transpose::Presets;
+    use std::path::Path;
+
+    fn song(front_matter: &str) -> Song {
+        let input = format!("title: Oleo\ncomposer: Sonny Rollins\n{}\n---\n", front_matter);
+        Song::parse_with(Path::new("oleo.ly"), &input, false, &Defaults::default(), &Presets::default()).unwrap()
+    }
+
+    #[test]
+    fn it_knows_what_is_in_the_public_domain() {
+        assert!(is_public_domain(&song("license: Public Domain"), 2026));
+        assert!(is_public_domain(&song("license: CC0-1.0"), 2026));
+        assert!(is_public_domain(&song("pd_year: 2026"), 2026));
+        assert!(!is_public_domain(&song("pd_year: 2051"), 2026));
+        assert!(!is_public_domain(&song("license: CC BY-SA 4.0"), 2026));
+        assert!(!is_public_domain(&song(""), 2026));
+
+        assert_eq!(license_name(&song("license: All Rights Reserved\npd_year: 2020"), 2026), PUBLIC_DOMAIN);
+        assert_eq!(license_name(&song("license: CC BY 4.0"), 2026), "CC BY 4.0");
+    }
+}
@@ -0,0 +1,170 @@
+// runs lilypond on a generated book and maps the line numbers
+// in its errors back to the song files they came from.
+
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::errors::TemplaterError;
+use crate::utils::read_file;
+
+// which lines of a generated book belong to which song file
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+    // 1-based, end exclusive
+    spans: Vec<(Range<usize>, PathBuf)>,
+}
+
+impl SourceMap {
+    pub fn push(&mut self, lines: Range<usize>, path: PathBuf) {
+        self.spans.push((lines, path));
+    }
+
+    // (song file, line within the song's bookpart)
+    pub fn lookup(&self, line: usize) -> Option<(&Path, usize)> {
+        self.spans
+            .iter()
+            .find(|(lines, _)| lines.contains(&line))
+            .map(|(lines, path)| (path.as_path(), line - lines.start + 1))
+    }
+}
+
+// e.g. "openbook-Concert.ly:1234:5: error: unknown escaped string"
+fn parse_location(line: &str) -> Option<(&str, usize)> {
+    let mut parts = line.splitn(4, ':');
+    let file = parts.next()?;
+    let line_number = parts.next()?.trim().parse().ok()?;
+    let _column: usize = parts.next()?.trim().parse().ok()?;
+
+    Some((file, line_number))
+}
+
+// the generated line usually appears verbatim in the song file
+fn find_source_line(source: &str, generated_line: &str) -> Option<usize> {
+    let needle = generated_line.trim();
+    if needle.is_empty() {
+        return None;
+    }
+
+    source
+        .lines()
+        .position(|line| line.trim() == needle)
+        .map(|i| i + 1)
+}
+
+// appends "-> song.ly:line" to every diagnostic that points
+// into the generated book
+pub fn annotate_output(output: &str, book_path: &Path, book: &str, source_map: &SourceMap) -> String {
+    let book_name = book_path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let book_lines: Vec<&str> = book.lines().collect();
+
+    let mut annotated = String::new();
+    for line in output.lines() {
+        annotated.push_str(line);
+        annotated.push('\n');
+
+        let Some((file, line_number)) = parse_location(line) else {
+            continue;
+        };
+        if !file.ends_with(book_name) {
+            continue;
+        }
+        let Some((song_path, bookpart_line)) = source_map.lookup(line_number) else {
+            continue;
+        };
+
+        let source_line = book_lines
+            .get(line_number - 1)
+            .zip(fs::read_to_string(song_path).ok())
+            .and_then(|(generated, source)| find_source_line(&source, generated));
+
+        match source_line {
+            Some(n) => annotated.push_str(&format!("  -> {}:{}\n", song_path.display(), n)),
+            None => annotated.push_str(&format!(
+                "  -> {} (line {} of its bookpart)\n",
+                song_path.display(),
+                bookpart_line
+            )),
+        }
+    }
+
+    annotated
+}
+
+// runs `lilypond` next to the generated book, so the pdf ends up
+// beside it. lilypond's output is printed with song locations.
+pub fn run_lilypond(
+    command: &str,
+    flags: &[String],
+    book_path: &Path,
+    source_map: &SourceMap,
+) -> Result<PathBuf, TemplaterError> {
+    let output_stem = book_path.with_extension("");
+
+    log!("[info]: running {} on {}", command, book_path.display());
+    let output = Command::new(command)
+        .args(flags)
+        .arg("-o")
+        .arg(&output_stem)
+        .arg(book_path)
+        .output()
+        .map_err(|e| TemplaterError::from_str(&format!("Unable to run '{}': {}", command, e)))?;
+
+    let book = read_file(book_path)?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    eprint!("{}", annotate_output(&stderr, book_path, &book, source_map));
+
+    if !output.status.success() {
+        return Err(TemplaterError::Lilypond {
+            path: book_path.to_path_buf(),
+            message: format!("{} failed ({})", command, output.status),
+        });
+    }
+
+    Ok(output_stem.with_extension("pdf"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_looks_up_song_lines() {
+        let mut source_map = SourceMap::default();
+        source_map.push(10..20, PathBuf::from("a.ly"));
+        source_map.push(20..25, PathBuf::from("b.ly"));
+
+        assert_eq!(source_map.lookup(5), None);
+        assert_eq!(source_map.lookup(10), Some((Path::new("a.ly"), 1)));
+        assert_eq!(source_map.lookup(21), Some((Path::new("b.ly"), 2)));
+        assert_eq!(source_map.lookup(25), None);
+    }
+
+    #[test]
+    fn it_parses_lilypond_locations() {
+        assert_eq!(
+            parse_location("openbook-Concert.ly:1234:5: error: unknown escaped string"),
+            Some(("openbook-Concert.ly", 1234))
+        );
+        assert_eq!(parse_location("Processing `openbook-Concert.ly'"), None);
+    }
+
+    #[test]
+    fn it_annotates_errors_in_unknown_songs_with_the_bookpart_line() {
+        let mut source_map = SourceMap::default();
+        source_map.push(2..4, PathBuf::from("missing.ly"));
+
+        let annotated = annotate_output(
+            "openbook-Concert.ly:3:1: error: syntax error\n",
+            Path::new("./openbook-Concert.ly"),
+            "intro\n\\bookpart {\n  c4 d e\n}\n",
+            &source_map,
+        );
+
+        assert_eq!(
+            annotated,
+            "openbook-Concert.ly:3:1: error: syntax error\n  -> missing.ly (line 2 of its bookpart)\n"
+        );
+    }
+}
@@ -0,0 +1,352 @@
+// `--lint-ly`: a quick look at every song's lilypond before the
+// book is rendered, so a typo is reported with its song and line
+// instead of as a lilypond error 40 pages into the book. it's not a
+// parser: it checks that braces are balanced within each section,
+// that note durations are powers of two, and that every command is
+// either built into lilypond or defined in `includes/` or the song.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::check::Severity;
+use crate::errors::TemplaterError;
+use crate::utils::get_files_by_ext;
+use crate::warnings::Warnings;
+
+// the lilypond commands the songs use, plus common ones. anything
+// else is only a warning, since this can't be a complete list.
+const BUILTIN_COMMANDS: &[&str] = &[
+    "accent", "acciaccatura", "addlyrics", "aeolian", "afterGrace", "alternative", "appoggiatura",
+    "arpeggio", "autoBeamOff", "autoBeamOn", "bar", "bold", "book", "bookpart", "box", "break",
+    "breathe", "breve", "caesura", "caps", "center-align", "center-column", "char", "chordmode",
+    "chords", "clef", "coda", "column", "compressEmptyMeasures", "compressFullBarRests", "consists",
+    "context", "cresc", "cueDuring", "default", "decresc", "dim", "dorian", "dotsDown", "dotsUp",
+    "downbow", "dynamicDown", "dynamicUp", "epsfile", "espressivo", "f", "fermata", "ff", "fill-line",
+    "flageolet", "fontsize", "general-align", "glissando", "grace", "halign", "harmonic", "header",
+    "hide", "hspace", "huge", "improvisationOff", "improvisationOn", "include", "instrumentSwitch",
+    "ionian", "italic", "key", "label", "language", "large", "layout", "left-align", "line", "locrian",
+    "longa", "lydian", "lyricmode", "lyrics", "lyricsto", "major", "marcato", "mark", "markup",
+    "markuplist", "midi", "minor", "mixolydian", "mf", "mordent", "mp", "musicglyph", "new",
+    "noBeam", "noBreak", "normalsize", "null", "numericTimeSignature", "omit", "once", "oneVoice",
+    "ottava", "override", "p", "pageBreak", "paper", "parenthesize", "partial", "phrygian", "pp",
+    "portato", "prall", "raise", "relative", "remove", "repeat", "repeatTie", "rest", "revert",
+    "right-align", "rightHandFinger", "score", "segno", "sf", "sfz", "set", "shape", "skip", "slashed",
+    "small", "smaller", "staccatissimo", "staccato", "startTrillSpan", "stemDown", "stemNeutral",
+    "stemUp", "stopTrillSpan", "super", "sustainOff", "sustainOn", "tempo", "tenuto", "tied",
+    "time", "times", "tiny", "tocItem", "transpose", "trill", "tuplet", "turn", "tweak", "underline",
+    "undo", "unset", "upbow", "user", "version", "voiceFour", "voiceOne", "voiceThree", "voiceTwo",
+    "vspace", "with", "with-color", "with-dimensions", "with-link", "with-url", "wordwrap",
+];
+
+// commands whose next `{` isn't music
+const LYRICS_COMMANDS: [&str; 4] = ["addlyrics", "lyricmode", "lyrics", "lyricsto"];
+const MARKUP_COMMANDS: [&str; 2] = ["markup", "markuplist"];
+
+const DURATIONS: [u32; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintIssue {
+    // in the song file, starting at 1
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Music,
+    Lyrics,
+    Markup,
+}
+
+// an open `{` or `<<`
+struct Frame {
+    closer: &'static str,
+    mode: Mode,
+    line: usize,
+}
+
+#[derive(Debug)]
+pub struct Linter {
+    known_commands: HashSet<String>,
+}
+
+// `name = ...` at the start of a line
+fn definitions(input: &str) -> impl Iterator<Item = &str> {
+    input.lines().filter_map(|line| {
+        let (name, _) = line.split_once('=')?;
+        let name = name.trim_end();
+        match !name.is_empty() && name.chars().all(|c| c.is_ascii_alphabetic()) {
+            true => Some(name),
+            false => None,
+        }
+    })
+}
+
+impl Linter {
+    // songs can also use anything defined in the includes
+    pub fn new<P: AsRef<Path>>(includes_dir: P, warnings: &mut Warnings) -> Result<Self, TemplaterError> {
+        let mut known_commands: HashSet<String> = BUILTIN_COMMANDS.iter().map(|s| s.to_string()).collect();
+
+        if includes_dir.as_ref().is_dir() {
+            for path in get_files_by_ext(&includes_dir, "ily", warnings)? {
+                let input = fs::read_to_string(&path).map_err(|e| TemplaterError::file(&path, e))?;
+                known_commands.extend(definitions(&input).map(String::from));
+            }
+        }
+
+        Ok(Linter { known_commands })
+    }
+
+    // lints everything after the front matter
+    pub fn lint(&self, input: &str) -> Vec<LintIssue> {
+        let lines: Vec<&str> = input.lines().collect();
+        let Some(front_matter_end) = lines.iter().position(|line| line.trim() == "---") else {
+            return vec![];
+        };
+
+        let known_commands: HashSet<&str> = definitions(input).collect();
+        let mut issues = vec![];
+
+        // braces have to be balanced within every `---` section, since
+        // they end up in different places of the templates
+        let mut start = front_matter_end + 1;
+        for (i, line) in lines.iter().enumerate().skip(start) {
+            if line.trim_start().starts_with("---") {
+                self.lint_section(&lines[start..i].join("\n"), start + 1, &known_commands, &mut issues);
+                start = i + 1;
+            }
+        }
+        if start < lines.len() {
+            self.lint_section(&lines[start..].join("\n"), start + 1, &known_commands, &mut issues);
+        }
+
+        issues.sort_by_key(|issue| issue.line);
+        issues
+    }
+
+    fn lint_section(&self, section: &str, first_line: usize, local: &HashSet<&str>, issues: &mut Vec<LintIssue>) {
+        let chars: Vec<char> = section.chars().collect();
+        let at = |i: usize| chars.get(i).copied().unwrap_or('\0');
+
+        let mut line = first_line;
+        let mut stack: Vec<Frame> = vec![];
+        // the mode of the next `{`, e.g. after `\lyricmode`
+        let mut pending: Option<Mode> = None;
+        let mut error = |line: usize, message: String| issues.push(LintIssue { line, severity: Severity::Error, message });
+        let mut warnings = vec![];
+
+        let mut i = 0;
+        while i < chars.len() {
+            let mode = stack.last().map_or(Mode::Music, |frame| frame.mode);
+
+            match (at(i), at(i + 1)) {
+                ('\n', _) => {
+                    line += 1;
+                    i += 1;
+                },
+                (c, _) if c.is_whitespace() => i += 1,
+                ('%', '{') => {
+                    i += 2;
+                    while i < chars.len() && !(at(i) == '%' && at(i + 1) == '}') {
+                        line += (at(i) == '\n') as usize;
+                        i += 1;
+                    }
+                    i += 2;
+                },
+                ('%', _) => {
+                    while i < chars.len() && at(i) != '\n' {
+                        i += 1;
+                    }
+                },
+                ('"', _) => {
+                    i += 1;
+                    while i < chars.len() && at(i) != '"' {
+                        line += (at(i) == '\n') as usize;
+                        i += if at(i) == '\\' { 2 } else { 1 };
+                    }
+                    i += 1;
+                    if pending == Some(Mode::Markup) {
+                        pending = None;
+                    }
+                },
+                ('#', '{') => {
+                    while i < chars.len() && !(at(i) == '#' && at(i + 1) == '}') {
+                        line += (at(i) == '\n') as usize;
+                        i += 1;
+                    }
+                    i += 2;
+                },
+                ('#', _) => {
+                    // scheme, e.g. `#'(1 . 2)`, `##f`, or `#LEFT`
+                    let mut depth = 0;
+                    i += 1;
+                    while i < chars.len() {
+                        match at(i) {
+                            '(' => depth += 1,
+                            ')' if depth > 0 => depth -= 1,
+                            '\n' if depth > 0 => line += 1,
+                            '"' if depth > 0 => {
+                                i += 1;
+                                while i < chars.len() && at(i) != '"' {
+                                    i += 1;
+                                }
+                            },
+                            c if depth == 0 && (c.is_whitespace() || "{}".contains(c)) => break,
+                            _ => {},
+                        }
+                        i += 1;
+                        if depth == 0 && at(i - 1) == ')' {
+                            break;
+                        }
+                    }
+                },
+                ('{', _) => {
+                    stack.push(Frame { closer: "}", mode: pending.take().unwrap_or(mode), line });
+                    i += 1;
+                },
+                ('<', '<') => {
+                    stack.push(Frame { closer: ">>", mode, line });
+                    i += 2;
+                },
+                (c @ '}', _) | (c @ '>', '>') => {
+                    let closer = if c == '}' { "}" } else { ">>" };
+                    match stack.pop() {
+                        Some(frame) if frame.closer == closer => {},
+                        Some(frame) => error(
+                            line,
+                            format!("'{}' doesn't match the '{}' from line {}", closer, opener(frame.closer), frame.line),
+                        ),
+                        None => error(line, format!("'{}' without an opening '{}'", closer, opener(closer))),
+                    }
+                    i += closer.len();
+                },
+                ('\\', _) => {
+                    let start = i + 1;
+                    i = start;
+                    while at(i).is_ascii_alphabetic() || (at(i) == '-' && i > start) {
+                        i += 1;
+                    }
+                    if i == start {
+                        // e.g. `\(`, `\!`, `\<`
+                        i += 1;
+                        continue;
+                    }
+
+                    let name: String = chars[start..i].iter().collect();
+                    if LYRICS_COMMANDS.contains(&name.as_str()) {
+                        pending = Some(Mode::Lyrics);
+                    } else if MARKUP_COMMANDS.contains(&name.as_str()) {
+                        pending = Some(Mode::Markup);
+                    }
+
+                    if !self.known_commands.contains(&name) && !local.contains(name.as_str()) {
+                        let message = format!("unknown command \\{}", name);
+                        warnings.push(LintIssue { line, severity: Severity::Warning, message });
+                    }
+                },
+                _ => {
+                    let start = i;
+                    while i < chars.len()
+                        && !(at(i).is_whitespace() || "{}\"%#\\".contains(at(i)))
+                        && !(at(i) == '>' && at(i + 1) == '>')
+                        && !(at(i) == '<' && at(i + 1) == '<')
+                    {
+                        i += 1;
+                    }
+                    if pending == Some(Mode::Markup) {
+                        pending = None;
+                    }
+
+                    let token: String = chars[start..i].iter().collect();
+                    if mode == Mode::Music {
+                        if let Some(duration) = invalid_duration(&token) {
+                            error(line, format!("invalid duration {} in '{}'", duration, token));
+                        }
+                    }
+                },
+            }
+        }
+
+        for frame in stack {
+            error(frame.line, format!("'{}' is never closed", opener(frame.closer)));
+        }
+        issues.extend(warnings);
+    }
+}
+
+fn opener(closer: &str) -> &'static str {
+    match closer {
+        ">>" => "<<",
+        _ => "{",
+    }
+}
+
+// the duration of a note, rest, or chord if it isn't a power of
+// two, e.g. 3 for "c'3" or 6 for "r6". other tokens are ignored.
+fn invalid_duration(token: &str) -> Option<u32> {
+    let token = token.trim_start_matches(['<', '(', '[', '~', '|']);
+
+    let rest = match token.chars().next()? {
+        'a'..='g' => {
+            let mut rest = &token[1..];
+            while let Some(r) = rest.strip_prefix("is").or_else(|| rest.strip_prefix("es")).or_else(|| rest.strip_prefix('s')) {
+                rest = r;
+            }
+            rest
+        },
+        'r' | 'R' | 's' => &token[1..],
+        _ => return None,
+    };
+
+    let rest = rest.trim_start_matches(['\'', ',', '!', '?']);
+    let digits = &rest[..rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len())];
+
+    // e.g. "break": "b" is a pitch, but this isn't a note
+    if digits.is_empty() || rest[digits.len()..].starts_with(|c: char| c.is_alphabetic()) {
+        return None;
+    }
+
+    let duration: u32 = digits.parse().ok()?;
+    match DURATIONS.contains(&duration) {
+        true => None,
+        false => Some(duration),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lint(body: &str) -> Vec<(usize, String)> {
+        let linter = Linter { known_commands: BUILTIN_COMMANDS.iter().map(|s| s.to_string()).collect() };
+        let input = format!("title: Test\n---\n{}", body);
+
+        linter.lint(&input).into_iter().map(|issue| (issue.line, issue.message)).collect()
+    }
+
+    #[test]
+    fn it_accepts_valid_songs() {
+        let body = "\\chordmode {\n  \\partial 4 s4\n  ees1:maj7 | bes2.:m7 aes1*2:7.9-\n}\n---\n\
+                    \\relative c' {\n  \\time 3/4 \\tuplet 3/2 { c8 d e } <c e g>4 r2. | R1*4\n  \\markup { \\bold \"Fine\" c3 }\n}\n\
+                    --- verse: 1 ---\n\\lyricmode { one4 two3 }\n";
+
+        assert_eq!(lint(body), vec![]);
+    }
+
+    #[test]
+    fn it_reports_broken_songs() {
+        let body = "\\chordmode {\n  ees1:maj7 | bes2:m7 }\n}\n---\n\\relative c' {\n  c4 d3 \\unknownThing\n  << { c1 } >>\n";
+
+        assert_eq!(
+            lint(body),
+            vec![
+                (5, String::from("'}' without an opening '{'")),
+                (7, String::from("'{' is never closed")),
+                (8, String::from("invalid duration 3 in 'd3'")),
+                (8, String::from("unknown command \\unknownThing")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,449 @@
+// `templater lsp`: a language server for song files, for any editor
+// with an lsp client, e.g. neovim's
+//
+//     vim.lsp.start { name = "templater", cmd = { "templater", "lsp" } }
+//
+// it talks lsp over stdin and stdout, and offers:
+//
+// * diagnostics as a song is typed: what `templater check` says about
+//   its frontmatter and style, and what `--lint-ly` says about its
+//   lilypond, on the line they're about
+// * completion of frontmatter keys, and of chord roots and qualities
+//   (the part after the `:`) in a `\chordmode` section
+// * hover on the frontmatter: the song's key, time, tempo, form, and
+//   how many measures it has
+//
+// the editor sends whole documents, since a song file is short. the
+// json-rpc parts are shared with `templater serve`, see `serve.rs`.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::check::{check_source, Severity};
+use crate::errors::TemplaterError;
+use crate::form::song_form;
+use crate::frontmatter::{split_songs, Defaults, KEYS};
+use crate::keys::describe_keys;
+use crate::lint::Linter;
+use crate::log_to_stderr;
+use crate::models::{Config, Song};
+use crate::musicxml::CHORD_KINDS;
+use crate::serve::{response, RpcError, METHOD_NOT_FOUND, PARSE_ERROR};
+use crate::style::check_style;
+use crate::transpose::Presets;
+use crate::warnings::Warnings;
+
+const ROOTS: [&str; 17] =
+    ["c", "cis", "des", "d", "dis", "ees", "e", "f", "fis", "ges", "g", "gis", "aes", "a", "ais", "bes", "b"];
+
+// lsp's `CompletionItemKind`s
+const PROPERTY: u32 = 10;
+const VALUE: u32 = 12;
+
+pub struct LanguageServer {
+    config: Config,
+    linter: Linter,
+    presets: Presets,
+    // by uri, as the editor has them
+    documents: HashMap<String, String>,
+}
+
+impl LanguageServer {
+    pub fn new(config: Config, warnings: &mut Warnings) -> Result<Self, TemplaterError> {
+        let linter = Linter::new(&config.includes_dir, warnings)?;
+        let presets = Presets::load(&config.transpositions)?;
+        Ok(LanguageServer { config, linter, presets, documents: HashMap::new() })
+    }
+
+    // what to send back: a response to a request, and the diagnostics
+    // of a document that changed
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        if !message.is_object() {
+            return vec![response(Value::Null, Err(RpcError::new(PARSE_ERROR, "Expected a json object.")))];
+        }
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+
+        let result = match message["method"].as_str() {
+            Some("initialize") => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": { "triggerCharacters": [":"] },
+                    "hoverProvider": true,
+                },
+                "serverInfo": { "name": "templater", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            Some("shutdown") => Value::Null,
+            Some("textDocument/didOpen") => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+                return vec![self.publish_diagnostics(&uri)];
+            },
+            Some("textDocument/didChange") => {
+                // the last change is the whole document
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|changes| changes.last()?["text"].as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                return vec![self.publish_diagnostics(&uri)];
+            },
+            Some("textDocument/didClose") => {
+                self.documents.remove(&uri);
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })];
+            },
+            Some("textDocument/completion") => {
+                let (line, character) = position(params);
+                let text = self.documents.get(&uri).map_or("", String::as_str);
+                Value::Array(completions(text, line, character))
+            },
+            Some("textDocument/hover") => {
+                let (line, _) = position(params);
+                let text = self.documents.get(&uri).map_or("", String::as_str);
+                self.hover(&uri_path(&uri), text, line).unwrap_or(Value::Null)
+            },
+            Some(method) if message.get("id").is_some() => {
+                let error = RpcError::new(METHOD_NOT_FOUND, format!("templater lsp doesn't support {}.", method));
+                return vec![response(message["id"].clone(), Err(error))];
+            },
+            // notifications it doesn't need, e.g. `initialized`, and
+            // the editor's responses
+            _ => return vec![],
+        };
+
+        match message.get("id") {
+            Some(id) => vec![response(id.clone(), Ok(result))],
+            None => vec![],
+        }
+    }
+
+    // the defaults of the songs dir `path` is in, or the first one's
+    fn defaults(&self, path: &Path) -> Result<Defaults, TemplaterError> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let songs_dirs = self.config.songs_dirs();
+        let songs_dir = songs_dirs.iter().find(|dir| dir.canonicalize().is_ok_and(|dir| path.starts_with(dir)));
+        Defaults::load(songs_dir.copied().unwrap_or(&self.config.songs_dir))
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": self.diagnostics(&uri_path(uri), text) },
+        })
+    }
+
+    fn diagnostics(&self, path: &Path, text: &str) -> Vec<Value> {
+        let lines: Vec<&str> = text.lines().collect();
+        let diagnostic = |line: usize, severity: Severity, message: &str| {
+            let end = lines.get(line).map_or(0, |line| line.encode_utf16().count());
+            let severity = match severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            };
+            json!({
+                "range": { "start": { "line": line, "character": 0 }, "end": { "line": line, "character": end } },
+                "severity": severity,
+                "source": "templater",
+                "message": message,
+            })
+        };
+
+        let defaults = match self.defaults(path) {
+            Ok(defaults) => defaults,
+            Err(e) => return vec![diagnostic(0, Severity::Error, &e.to_string())],
+        };
+
+        let mut diagnostics = vec![];
+        for (first_line, song) in split_songs(text) {
+            let start = first_line - 1;
+            let (_, issues) = check_source(path, song, &defaults, &self.presets);
+            for issue in issues {
+                let line = start + frontmatter_line(song, &issue.message);
+                diagnostics.push(diagnostic(line, issue.severity, &issue.message));
+            }
+            for issue in self.linter.lint(song) {
+                diagnostics.push(diagnostic(start + issue.line - 1, issue.severity, &issue.message));
+            }
+        }
+        for issue in check_style(text).0 {
+            let message = match issue.fixable {
+                true => format!("{} (templater check --autofix)", issue.message),
+                false => issue.message,
+            };
+            diagnostics.push(diagnostic(issue.line - 1, Severity::Warning, &message));
+        }
+
+        diagnostics
+    }
+
+    fn hover(&self, path: &Path, text: &str, line: usize) -> Option<Value> {
+        let (start, song) = song_at(text, line);
+        if line - start >= frontmatter_end(song) {
+            return None;
+        }
+
+        let song = Song::parse_with(path, song, false, &self.defaults(path).ok()?, &self.presets).ok()?;
+        Some(json!({ "contents": { "kind": "markdown", "value": summary(&song) } }))
+    }
+}
+
+// (line, character) of a request's `position`
+fn position(params: &Value) -> (usize, usize) {
+    let number = |name: &str| params["position"][name].as_u64().unwrap_or_default() as usize;
+    (number("line"), number("character"))
+}
+
+// "file:///home/me/songs/a%20train.ly" -> /home/me/songs/a train.ly
+fn uri_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri).as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < path.len() {
+        let hex = path.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (path[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+// (its first line, from 0, and its text) of the song `line` is in
+fn song_at(text: &str, line: usize) -> (usize, &str) {
+    let songs = split_songs(text);
+    let (first_line, song) = songs.iter().rev().find(|(first_line, _)| first_line - 1 <= line).unwrap_or(&songs[0]);
+    (first_line - 1, song)
+}
+
+// the number of frontmatter lines, before the first `---`
+fn frontmatter_end(song: &str) -> usize {
+    song.lines().position(|line| line.trim() == "---").unwrap_or(usize::MAX)
+}
+
+// the frontmatter line an issue is about, e.g. "bpm: 500" for "bpm
+// 500 is outside of 20-400", or else the first one
+fn frontmatter_line(song: &str, message: &str) -> usize {
+    let mut words = message.split(|c: char| !c.is_alphanumeric() && c != '_');
+    let keys: Vec<&str> = song
+        .lines()
+        .take(frontmatter_end(song))
+        .map(|line| match line.starts_with(char::is_whitespace) {
+            true => "",
+            false => line.split_once(':').map_or("", |(key, _)| key.trim()),
+        })
+        .collect();
+
+    words.find_map(|word| keys.iter().position(|key| !key.is_empty() && *key == word)).unwrap_or(0)
+}
+
+fn completions(text: &str, line: usize, character: usize) -> Vec<Value> {
+    let current = text.lines().nth(line).unwrap_or_default();
+    let before: String = current.chars().take(character).collect();
+    let (start, song) = song_at(text, line);
+    let song_lines: Vec<&str> = song.lines().collect();
+    let offset = (line - start).min(song_lines.len());
+
+    // a key, at the start of a frontmatter line
+    let end = frontmatter_end(song).min(song_lines.len());
+    if offset < end {
+        if before.contains(':') || before.starts_with(char::is_whitespace) {
+            return vec![];
+        }
+        let used: Vec<&str> =
+            song_lines[..end].iter().filter_map(|line| Some(line.split_once(':')?.0.trim())).collect();
+        return KEYS
+            .iter()
+            .filter(|key| !used.contains(key))
+            .map(|key| json!({ "label": key, "kind": PROPERTY, "insertText": format!("{}: ", key) }))
+            .collect();
+    }
+
+    let section_start = song_lines[..offset].iter().rposition(|line| line.trim() == "---").map_or(0, |i| i + 1);
+    let section_end =
+        song_lines[offset..].iter().position(|line| line.trim() == "---").map_or(song_lines.len(), |i| offset + i);
+    if !song_lines[section_start..section_end].iter().any(|line| line.contains("chordmode")) {
+        return vec![];
+    }
+
+    // replaces what's typed of the root or quality
+    let word = before.rsplit(|c: char| c.is_whitespace() || "{}|()".contains(c)).next().unwrap_or_default();
+    let item = |label: &str, detail: String, typed: &str| {
+        let start = json!({ "line": line, "character": character - typed.chars().count() });
+        let end = json!({ "line": line, "character": character });
+        json!({
+            "label": label,
+            "kind": VALUE,
+            "detail": detail,
+            "textEdit": { "range": { "start": start, "end": end }, "newText": label },
+        })
+    };
+    match word.split_once(':') {
+        Some((_, typed)) => CHORD_KINDS
+            .iter()
+            .filter(|(_, quality)| !quality.is_empty())
+            .map(|(kind, quality)| item(quality, kind.replace('-', " "), typed))
+            .collect(),
+        None if word.chars().all(|c| c.is_ascii_lowercase()) => {
+            ROOTS.iter().map(|root| item(root, String::from("chord root"), word)).collect()
+        },
+        None => vec![],
+    }
+}
+
+// e.g. "**Misty**, Erroll Garner\n\nEb major · 4/4 · 60 bpm · 32 measures · AABA"
+fn summary(song: &Song) -> String {
+    let mut facts = vec![];
+    if !song.keys().is_empty() {
+        facts.push(describe_keys(&song.keys()));
+    }
+    if !song.time_signatures.is_empty() {
+        facts.push(song.time_signatures.iter().map(|time| time.to_string()).collect::<Vec<_>>().join(", "));
+    }
+    if let Some(tempo) = &song.tempo {
+        facts.push(format!("{} bpm", tempo.mark()));
+    }
+    if let Some(measures) = song.measures {
+        facts.push(format!("{} measures", measures));
+    }
+    if let Some(form) = song_form(song) {
+        facts.push(form.to_string());
+    }
+
+    format!("**{}**, {}\n\n{}", song.title, song.composer, facts.join(" · "))
+}
+
+fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Value>, TemplaterError> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| TemplaterError::from_str("An lsp message has no Content-Length header."))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body).unwrap_or(Value::Null)))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// until the editor sends `exit`, or closes stdin
+pub fn serve_lsp<R: BufRead, W: Write>(
+    server: &mut LanguageServer,
+    mut input: R,
+    output: &mut W,
+) -> Result<(), TemplaterError> {
+    while let Some(message) = read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(output, &reply)?;
+        }
+    }
+
+    Ok(())
+}
+
+// the warnings are printed once the editor is closed
+pub fn lsp(config: Config) -> Result<Warnings, TemplaterError> {
+    log_to_stderr();
+    let mut warnings = Warnings::default();
+    let mut server = LanguageServer::new(config, &mut warnings)?;
+    serve_lsp(&mut server, io::stdin().lock(), &mut io::stdout().lock())?;
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SONG: &str = "title: Blue Bossa\ncomposer:\nbpm: 500\n---\n\\chordmode {\n  c1:m7 f:\n}\n";
+
+    fn server() -> LanguageServer {
+        let config = Config { songs_dir: "tests/fixtures/songs".into(), ..Default::default() };
+        let mut server = LanguageServer::new(config, &mut Warnings::default()).unwrap();
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///songs/blue%20bossa.ly", "text": SONG } },
+        });
+        server.handle(&open);
+        server
+    }
+
+    fn request(server: &mut LanguageServer, method: &str, line: usize, character: usize) -> Value {
+        let params = json!({
+            "textDocument": { "uri": "file:///songs/blue%20bossa.ly" },
+            "position": { "line": line, "character": character },
+        });
+        let replies = server.handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }));
+        replies[0]["result"].clone()
+    }
+
+    #[test]
+    fn it_reports_issues_on_their_line() {
+        let server = server();
+        let diagnostics = server.diagnostics(Path::new("/songs/blue bossa.ly"), SONG);
+        let lines: Vec<(u64, &str)> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                (diagnostic["range"]["start"]["line"].as_u64().unwrap(), diagnostic["message"].as_str().unwrap())
+            })
+            .collect();
+
+        assert!(lines.contains(&(1, "missing composer")));
+        assert!(lines.contains(&(2, "bpm 500 is outside of 20-400")));
+        assert_eq!(uri_path("file:///songs/blue%20bossa.ly"), PathBuf::from("/songs/blue bossa.ly"));
+    }
+
+    #[test]
+    fn it_completes_keys_and_chords() {
+        let mut server = server();
+        let labels = |result: Value| -> Vec<String> {
+            result.as_array().unwrap().iter().map(|item| item["label"].as_str().unwrap().to_string()).collect()
+        };
+
+        let keys = labels(request(&mut server, "textDocument/completion", 1, 0));
+        assert!(keys.contains(&String::from("meter")) && !keys.contains(&String::from("title")));
+        let qualities = labels(request(&mut server, "textDocument/completion", 5, 10));
+        assert!(qualities.contains(&String::from("maj7")));
+        let roots = labels(request(&mut server, "textDocument/completion", 5, 2));
+        assert_eq!(roots.len(), ROOTS.len());
+        assert!(labels(request(&mut server, "textDocument/completion", 2, 5)).is_empty());
+
+        let hover = request(&mut server, "textDocument/hover", 0, 3);
+        assert!(hover["contents"]["value"].as_str().unwrap().starts_with("**Blue Bossa**"));
+        assert_eq!(request(&mut server, "textDocument/hover", 5, 3), Value::Null);
+    }
+}
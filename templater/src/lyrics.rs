@@ -0,0 +1,372 @@
+// `--mode lyrics`: a book of only the words, for singers. the
+// `\lyricmode` blocks are turned back into plain text, e.g.
+// `hap -- py __` becomes "happy", and rendered as text, Markdown,
+// or LaTeX depending on the output file's extension.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use minijinja::{context, Value};
+
+use crate::book::Book;
+use crate::errors::TemplaterError;
+use crate::lilypond::SourceMap;
+use crate::metadata::BookMetadata;
+use crate::models::{Config, Song, TemplaterConfig, STDOUT};
+use crate::templates::Templates;
+use crate::renderer::{Outputs, Renderer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LyricsFormat {
+    Text,
+    Markdown,
+    Latex,
+}
+
+impl LyricsFormat {
+    // from the output file's extension
+    // stdout gets markdown
+    pub fn from_path(path: &Path) -> Result<Self, TemplaterError> {
+        if path == Path::new(STDOUT) {
+            return Ok(LyricsFormat::Markdown);
+        }
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("txt") => Ok(LyricsFormat::Text),
+            Some("md") => Ok(LyricsFormat::Markdown),
+            Some("tex") => Ok(LyricsFormat::Latex),
+            _ => Err(TemplaterError::from_str(&format!(
+                "{}: unknown lyrics format. Expected a .txt, .md, or .tex file.",
+                path.display()
+            ))),
+        }
+    }
+
+    fn template(&self) -> &'static str {
+        match self {
+            LyricsFormat::Text => "lyrics-text",
+            LyricsFormat::Markdown => "lyrics-markdown",
+            LyricsFormat::Latex => "lyrics-latex",
+        }
+    }
+}
+
+// the words are the same in every key, so a lyrics book is
+// only written for the first one
+pub struct LyricsBook;
+
+impl Renderer for LyricsBook {
+    fn outputs(&self) -> Outputs {
+        Outputs::PerVolume
+    }
+
+    fn output_path(
+        &self,
+        config: &Config,
+        conf: &TemplaterConfig,
+        volume: Option<usize>,
+    ) -> Result<PathBuf, TemplaterError> {
+        Ok(config.output_path(&conf.transpose_text, volume))
+    }
+
+    fn render(
+        &self,
+        book: &Book,
+        out: &mut dyn Write,
+        conf: &TemplaterConfig,
+        songs: &[Song],
+        volume: Option<usize>,
+    ) -> Result<SourceMap, TemplaterError> {
+        let format = LyricsFormat::from_path(&self.output_path(&book.config, conf, volume)?)?;
+        write!(out, "{}", render_lyrics_book(&book.templates, songs, format, &book.config.metadata)?)?;
+
+        // not lilypond, so there's nothing to map
+        Ok(SourceMap::default())
+    }
+}
+
+// every song with lyrics, the rest are left out
+pub fn render_lyrics_book(
+    templates: &Templates,
+    songs: &[Song],
+    format: LyricsFormat,
+    metadata: &BookMetadata,
+) -> Result<String, TemplaterError> {
+    let songs: Vec<Value> = songs
+        .iter()
+        .filter(|song| !song.lyrics.is_empty())
+        .map(|song| {
+            let verses: Vec<Value> = song
+                .lyrics
+                .iter()
+                .map(|verse| {
+                    context! {
+                        stanza => verse.stanza.clone().unwrap_or_default(),
+                        lines => lyric_lines(&verse.text),
+                    }
+                })
+                .collect();
+
+            context! {
+                title => song.title.as_str(),
+                composer => song.composer.as_str(),
+                poet => song.poet.clone().unwrap_or_default(),
+                number => song.number,
+                label => song.label(),
+                verses,
+            }
+        })
+        .collect();
+
+    templates.render(format.template(), context! { metadata => metadata.context(), num_tunes => songs.len(), songs })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+    Newline,
+}
+
+// `%` comments are dropped, `%{ ... %}` too
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => tokens.push(Token::Newline),
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '%' if chars.peek() == Some(&'{') => {
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '%' && c == '}' {
+                        break;
+                    }
+                    previous = c;
+                }
+            },
+            '%' => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            },
+            '"' => {
+                let mut s = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => s.extend(chars.next()),
+                        c => s.push(c),
+                    }
+                }
+                tokens.push(Token::Quoted(s));
+            },
+            c if c.is_whitespace() => {},
+            c => {
+                let mut s = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !matches!(c, '{' | '}' | '"' | '%')) {
+                    s.push(c);
+                }
+                tokens.push(Token::Word(s));
+            },
+        }
+    }
+
+    tokens
+}
+
+// the index after the value starting at `i`: a `{ block }`, a
+// `\markup` and its argument, or one word
+fn skip_value(tokens: &[Token], mut i: usize) -> usize {
+    while tokens.get(i) == Some(&Token::Newline) {
+        i += 1;
+    }
+
+    match tokens.get(i) {
+        Some(Token::Open) => {
+            let mut depth = 0;
+            while let Some(token) = tokens.get(i) {
+                i += 1;
+                match token {
+                    Token::Open => depth += 1,
+                    Token::Close if depth == 1 => break,
+                    Token::Close => depth -= 1,
+                    _ => {},
+                }
+            }
+            i
+        },
+        Some(Token::Word(word)) if word == "\\markup" => skip_value(tokens, i + 1),
+        Some(_) => i + 1,
+        None => i,
+    }
+}
+
+// one string per line of lyrics as they're written in the song,
+// with an empty string between stanzas
+pub fn lyric_lines(input: &str) -> Vec<String> {
+    let tokens = tokenize(input);
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+    let mut newlines = 0;
+    // after `--`, the next syllable is part of the same word
+    let mut hyphen = false;
+
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        i += 1;
+
+        let syllable = match token {
+            Token::Newline => {
+                newlines += 1;
+                continue;
+            },
+            Token::Open | Token::Close => continue,
+            Token::Quoted(s) => s.clone(),
+            Token::Word(word) if word == "--" => {
+                hyphen = true;
+                continue;
+            },
+            // extenders and skipped notes
+            Token::Word(word) if word == "__" || word == "_" => continue,
+            Token::Word(word) if word.starts_with('#') => continue,
+            Token::Word(word) if word.starts_with('\\') => {
+                i = match word.as_str() {
+                    // `\set stanza = "2."`
+                    "\\set" | "\\override" => (0..3).fold(i, |i, _| skip_value(&tokens, i)),
+                    "\\markup" => skip_value(&tokens, i),
+                    // `\skip 1`, `\lyricsto "Voice"`
+                    "\\skip" | "\\lyricsto" => skip_value(&tokens, i),
+                    // `\repeat volta 2 { ... }` keeps the words
+                    "\\repeat" => (0..2).fold(i, |i, _| skip_value(&tokens, i)),
+                    _ => i,
+                };
+                continue;
+            },
+            // `~` and `_` join two syllables sung on one note
+            Token::Word(word) => word.replace(['~', '_'], " "),
+        };
+
+        if hyphen && !line.is_empty() {
+            line.push_str(&syllable);
+        } else {
+            if newlines > 0 && !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+                if newlines > 1 {
+                    lines.push(String::new());
+                }
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&syllable);
+            newlines = 0;
+        }
+        hyphen = false;
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines.iter().map(|line| line.split_whitespace().collect::<Vec<_>>().join(" ")).collect()
+}
+
+// every syllable `\lyricsto` puts on its own note, in order: the
+// words and `_`, `\skip`s, and "quoted" and `\markup` syllables.
+// `--` and `__` aren't, and `~` joins two syllables on one note.
+pub fn syllables(input: &str) -> Vec<String> {
+    let tokens = tokenize(input);
+    let mut i = 0;
+    let mut syllables = vec![];
+    block_syllables(&tokens, &mut i, &mut syllables);
+    syllables
+}
+
+// until the `}` of the block `i` is in
+fn block_syllables(tokens: &[Token], i: &mut usize, syllables: &mut Vec<String>) {
+    while let Some(token) = tokens.get(*i) {
+        *i += 1;
+
+        match token {
+            Token::Close => return,
+            Token::Open => block_syllables(tokens, i, syllables),
+            Token::Newline => {},
+            Token::Quoted(s) => syllables.push(s.clone()),
+            Token::Word(word) if word == "--" || word == "__" || word.starts_with('#') => {},
+            Token::Word(word) if word.starts_with('\\') => match word.as_str() {
+                "\\set" | "\\override" => *i = (0..3).fold(*i, |i, _| skip_value(tokens, i)),
+                "\\lyricsto" => *i = skip_value(tokens, *i),
+                "\\skip" => {
+                    *i = skip_value(tokens, *i);
+                    syllables.push(String::from("\\skip"));
+                },
+                "\\markup" => {
+                    *i = skip_value(tokens, *i);
+                    syllables.push(String::from("\\markup"));
+                },
+                // `\repeat unfold 3 { \skip 1 }` is 3 of them, a volta
+                // only once
+                "\\repeat" => {
+                    let kind = tokens.get(*i);
+                    let times = match tokens.get(*i + 1) {
+                        Some(Token::Word(times)) => times.parse::<usize>().unwrap_or(1),
+                        _ => 1,
+                    };
+                    *i = (0..2).fold(*i, |i, _| skip_value(tokens, i));
+                    while tokens.get(*i) == Some(&Token::Newline) {
+                        *i += 1;
+                    }
+                    if tokens.get(*i) == Some(&Token::Open) {
+                        *i += 1;
+                        let mut repeated = vec![];
+                        block_syllables(tokens, i, &mut repeated);
+                        let times = if kind == Some(&Token::Word(String::from("unfold"))) { times } else { 1 };
+                        for _ in 0..times {
+                            syllables.extend(repeated.iter().cloned());
+                        }
+                    }
+                },
+                _ => {},
+            },
+            Token::Word(word) => syllables.push(word.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_turns_lyricmode_into_text() {
+        let lyrics = "\\lyricmode {\n  \\repeat unfold 3 { \\skip 1 }\n  Just friends, __ lo -- vers no more, __\n\
+                      ev -- 'ry -- thing __ be --\n  fore. % not sung\n\n  \\set stanza = \\markup { \\column { \"Duet \" } }\n\
+                      on_this earth \\skip 1 to me. \"Yeah!\"\n}\n";
+
+        assert_eq!(
+            lyric_lines(lyrics),
+            vec!["Just friends, lovers no more,", "ev'rything before.", "", "on this earth to me. Yeah!"]
+        );
+    }
+
+    #[test]
+    fn it_counts_the_syllables_of_every_note() {
+        let lyrics = "\\lyricmode {\n  \\set stanza = \"2.\" \\repeat unfold 2 { \\skip 1 }\n\
+                      Just friends, __ lo -- vers_no more, _ \"Yeah!\" % not sung\n}\n";
+
+        assert_eq!(
+            syllables(lyrics),
+            vec!["\\skip", "\\skip", "Just", "friends,", "lo", "vers_no", "more,", "_", "Yeah!"]
+        );
+    }
+
+    #[test]
+    fn it_picks_the_format_from_the_extension() {
+        assert_eq!(LyricsFormat::from_path(Path::new("lyrics.md")).unwrap(), LyricsFormat::Markdown);
+        assert_eq!(LyricsFormat::from_path(Path::new("out/lyrics.tex")).unwrap(), LyricsFormat::Latex);
+        assert!(LyricsFormat::from_path(Path::new("lyrics.ly")).is_err());
+    }
+}
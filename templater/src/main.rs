@@ -1,211 +1,580 @@
-// goals of this project
-// * automatically parse frontmatter in the songfiles
-// * generate a `book.ly` from templates
-//   -> book cover / title page
-//   -> alphabetical (by song name) table of contents
-//   -> extra ToCs (by composer name, meter, bpm)
-// * setup each song from template
-//   -> name, composer, etc
-//   -> transposing_instrument (default to c)
-
-use std::fs::{self, File};
-use std::io::Write;
+use std::fs;
+use std::io;
 use std::path::PathBuf;
-
-
-use extract_frontmatter::{Extractor,config::{Modifier,Splitter}};
-use once_cell::sync::OnceCell;
-
-mod errors;
-mod models;
-mod utils;
-
-use crate::errors::TemplaterError;
-use crate::models::*;
-use crate::utils::*;
-
-static INTRO_TEMPLATE: OnceCell<String> = OnceCell::new();
-static BOOKPART_TEMPLATE: OnceCell<String> = OnceCell::new();
-static SONG_BODY_TEMPLATE: OnceCell<String> = OnceCell::new();
-static SONG_HEADER_TEMPLATE: OnceCell<String> = OnceCell::new();
-static CHORDS_TEMPLATE: OnceCell<String> = OnceCell::new();
-static VOICE_TEMPLATE: OnceCell<String> = OnceCell::new();
-static LYRICS_TEMPLATE: OnceCell<String> = OnceCell::new();
+use std::process::Command;
+
+use openbook_templater::absolute::convert_songs;
+use openbook_templater::bookdiff::{diff_books, Side};
+use openbook_templater::browse::browse;
+use openbook_templater::check::{check_songs, CheckReport, Severity};
+use openbook_templater::cli::{self, FlagGroup};
+use openbook_templater::drafts::{find_drafts, DraftsReport};
+use openbook_templater::export::{export, ExportFormat};
+use openbook_templater::filter::SongFilter;
+use openbook_templater::lilypond::run_lilypond;
+use openbook_templater::lint::Linter;
+use openbook_templater::lsp::lsp;
+use openbook_templater::migrate::migrate_songs;
+use openbook_templater::models::BookMode;
+use openbook_templater::musicxml::{import_file, import_song, ImportFormat};
+use openbook_templater::normalize::normalize_songs;
+use openbook_templater::profiles::LayoutProfile;
+use openbook_templater::release::Release;
+use openbook_templater::renderer::LilypondOutput;
+use openbook_templater::scaffold::{new_song, SongStub};
+use openbook_templater::serve::serve;
+use openbook_templater::stats::Stats;
+use openbook_templater::style::autofix_songs;
+use openbook_templater::toc::parse_index_kinds;
+use openbook_templater::transpose::Presets;
+use openbook_templater::utils::get_files_by_ext;
+use openbook_templater::vars::describe_vars;
+use openbook_templater::warnings::{Warning, WarningKind, Warnings};
+use openbook_templater::watch::watch;
+use openbook_templater::web::{write_site, DEFAULT_WEB_DIR};
+use openbook_templater::{log, log_to_stderr, BookBuilder, Config, ConfigOverrides, TemplaterError};
 
 #[derive(Debug)]
 struct AppArgs {
-    transpose: String,
-    songs_dir: String,
+    // see `cli.rs`
+    subcommand: &'static str,
+    config: Option<PathBuf>,
+    overrides: ConfigOverrides,
     song_names: Option<String>,
     composers: Option<String>,
-    lyrics: bool,
+    filters: Vec<SongFilter>,
+    dry_run: bool,
+    // `export <file> [--format abc|ireal]`
+    export_path: Option<PathBuf>,
+    export_format: Option<ExportFormat>,
+    // `stats [file]`
+    stats_path: Option<PathBuf>,
+    // `web [dir] [--snippets]`
+    web_dir: Option<PathBuf>,
+    snippets: bool,
+    // `serve [--port <port>] [--allow-origin <origin>]`
+    port: Option<u16>,
+    allow_origin: Option<String>,
+    // `vars [template]`
+    vars_template: Option<String>,
+    // `import <file> [--format abc]`
+    import_path: Option<PathBuf>,
+    import_format: Option<ImportFormat>,
+    // `release <version>`
+    release_version: Option<String>,
+    // `diff <old> [new]`
+    diff_old: Option<String>,
+    diff_new: Option<String>,
+    // `convert-absolute [song.ly ...]` and `normalize [song.ly ...]`
+    song_paths: Vec<PathBuf>,
+    // `new "Song Title" [field=value ...]`
+    new_args: Vec<String>,
+    interactive: bool,
+    // `check --autofix`
+    autofix: bool,
+    // anything that wasn't a known flag
+    unused: Vec<String>,
 }
 
 fn parse_args() -> Result<AppArgs, TemplaterError> {
     let mut pargs = pico_args::Arguments::from_env();
-    let args = AppArgs {
-        transpose: pargs.opt_value_from_str("--transpose")?.unwrap_or("c".into()),
-        songs_dir: pargs.opt_value_from_str("--songs-dir")?.unwrap_or(String::from("./songs")),
-        song_names: pargs.opt_value_from_str("--song-names")?,
-        composers: pargs.opt_value_from_str("--composers")?,
-        lyrics: pargs.contains("--lyrics"),
-    };
+    let name: Option<String> = pargs.subcommand()?;
 
     // Help has a higher priority and should be handled separately.
     if pargs.contains(["-h", "--help"]) {
-        println!("args:\n    --transpose: pass in bb/eb");
-        println!("    --lyrics: include lyrics");
-        println!("    --composers: quoted, comma delimited list of composers to filter by");
-        println!("    --song-names: quoted, comma delimited list of song names to filter by");
-        println!("    --song-dir");
+        match name {
+            Some(name) => print!("{}", cli::subcommand_help(cli::subcommand(Some(&name))?)),
+            None => print!("{}", cli::help()),
+        }
         std::process::exit(0);
     }
+    let subcommand = cli::subcommand(name.as_deref())?;
+    if pargs.contains("--watch") {
+        return Err(TemplaterError::from_str("--watch is a subcommand now, e.g. `templater watch --transpose bb`."));
+    }
 
-    let remaining = pargs.finish();
-    if !remaining.is_empty() {
-        println!("Warning: unused arguments left: {:?}.", remaining);
+    // only the subcommand's own flags are parsed, the rest are unused
+    let mut args = AppArgs {
+        subcommand: subcommand.name,
+        config: None,
+        overrides: ConfigOverrides::default(),
+        song_names: None,
+        composers: None,
+        filters: vec![],
+        dry_run: false,
+        export_path: None,
+        export_format: None,
+        stats_path: None,
+        web_dir: None,
+        snippets: false,
+        port: None,
+        allow_origin: None,
+        vars_template: None,
+        import_path: None,
+        import_format: None,
+        release_version: None,
+        diff_old: None,
+        diff_new: None,
+        song_paths: vec![],
+        new_args: vec![],
+        interactive: false,
+        autofix: false,
+        unused: vec![],
+    };
+    let overrides = &mut args.overrides;
+
+    if subcommand.takes(FlagGroup::Common) {
+        args.config = pargs.opt_value_from_str("--config")?;
+        let mut songs_dirs = pargs.values_from_str::<_, PathBuf>("--songs-dir")?.into_iter();
+        overrides.songs_dir = songs_dirs.next();
+        overrides.more_songs_dirs = songs_dirs.collect();
+        overrides.templates_dir = pargs.opt_value_from_str("--templates-dir")?;
+        overrides.transpositions = pargs.opt_value_from_str("--transpositions")?;
+        overrides.strict = pargs.contains("--strict");
+    }
+    if subcommand.takes(FlagGroup::Songs) {
+        args.song_names = match pargs.opt_value_from_str("--only")? {
+            Some(only) => Some(only),
+            None => pargs.opt_value_from_str("--song-names")?,
+        };
+        args.composers = pargs.opt_value_from_str("--composers")?;
+        overrides.duplicate_titles = pargs.opt_value_from_str("--duplicate-titles")?;
+        args.filters = pargs.values_from_str("--filter")?;
+        overrides.setlist = pargs.opt_value_from_str("--setlist")?;
+        overrides.sort = pargs.opt_value_from_str("--sort")?;
+        overrides.include_drafts = pargs.contains("--include-drafts");
+        overrides.public_domain_only = pargs.contains("--public-domain-only");
+        overrides.folder_tags = pargs.contains("--folder-tags");
+        overrides.lyrics = pargs.contains("--lyrics");
+        overrides.song_ids = pargs.opt_value_from_str("--song-ids")?;
+        overrides.composer_aliases = pargs.opt_value_from_str("--composer-aliases")?;
+        overrides.lint_ly = pargs.contains("--lint-ly");
+        overrides.normalize_chords = pargs.contains("--normalize-chords");
+    }
+    if subcommand.takes(FlagGroup::Book) {
+        overrides.transpose = pargs.opt_value_from_str("--transpose")?;
+        overrides.spelling = pargs.opt_value_from_str("--spelling")?;
+        overrides.mode = pargs.opt_value_from_str("--mode")?;
+        if pargs.contains("--guitar") {
+            overrides.mode = match overrides.mode {
+                None | Some(BookMode::Guitar) => Some(BookMode::Guitar),
+                Some(_) => {
+                    let message = "--guitar is --mode guitar, so it can't be used with another --mode.";
+                    return Err(TemplaterError::from_str(message));
+                },
+            };
+        }
+        overrides.voicings = pargs.opt_value_from_str("--voicings")?;
+        overrides.layout = pargs.opt_value_from_str("--layout")?;
+        if pargs.contains("--large-print") {
+            overrides.layout = match overrides.layout {
+                None | Some(LayoutProfile::LargePrint) => Some(LayoutProfile::LargePrint),
+                Some(_) => {
+                    let message = "--large-print is --layout large-print, so it can't be used with another --layout.";
+                    return Err(TemplaterError::from_str(message));
+                },
+            };
+        }
+        overrides.indexes = pargs.opt_value_from_fn("--indexes", parse_index_kinds)?;
+        overrides.out_dir = pargs.opt_value_from_str("--out-dir")?;
+        overrides.output = pargs.opt_value_from_str("--output")?;
+        overrides.metadata = pargs.values_from_str("--metadata")?;
+        overrides.split_volumes = pargs.opt_value_from_str("--split-volumes")?;
+        overrides.split_at = pargs.opt_value_from_fn("--split-at", |letters: &str| {
+            Ok::<_, TemplaterError>(split_list(letters))
+        })?;
+        overrides.manuscript_pages = pargs.opt_value_from_str("--manuscript-pages")?;
+        overrides.chord_grid_pages = pargs.opt_value_from_str("--chord-grid-pages")?;
+        overrides.pdf = pargs.contains("--pdf");
+        overrides.lilypond_flags = pargs.opt_value_from_fn("--lilypond-flags", |flags: &str| {
+            Ok::<_, TemplaterError>(flags.split_whitespace().map(String::from).collect())
+        })?;
+        overrides.midi = pargs.contains("--midi");
+        overrides.html_index = pargs.contains("--html-index");
+        overrides.per_song = pargs.contains("--per-song");
+        overrides.changes_since = pargs.opt_value_from_str("--changes-since")?;
+        overrides.no_dividers = pargs.contains("--no-dividers");
+        overrides.toc_form = pargs.contains("--toc-form");
+        overrides.form_summary = pargs.contains("--form-summary");
+        overrides.no_cache = pargs.contains("--no-cache");
+        overrides.force = pargs.contains("--force");
+    }
+    if subcommand.takes(FlagGroup::Check) {
+        args.autofix = pargs.contains("--autofix");
+        overrides.lint_ly = pargs.contains("--lint-ly");
+    }
+    if subcommand.takes(FlagGroup::DryRun) {
+        args.dry_run = pargs.contains("--dry-run");
+    }
+    if subcommand.takes(FlagGroup::New) {
+        args.interactive = pargs.contains(["-i", "--interactive"]);
+    }
+    if subcommand.takes(FlagGroup::Export) {
+        args.export_format = pargs.opt_value_from_str("--format")?;
+    }
+    if subcommand.takes(FlagGroup::Import) {
+        args.import_format = pargs.opt_value_from_str("--format")?;
+    }
+    if subcommand.takes(FlagGroup::Web) {
+        args.snippets = pargs.contains("--snippets");
+    }
+    if subcommand.takes(FlagGroup::Serve) {
+        args.port = pargs.opt_value_from_str("--port")?;
+        args.allow_origin = pargs.opt_value_from_str("--allow-origin")?;
+    }
+
+    // free arguments come after every flag
+    match subcommand.name {
+        "export" => args.export_path = pargs.opt_free_from_str()?,
+        "stats" => args.stats_path = pargs.opt_free_from_str()?,
+        "web" => args.web_dir = pargs.opt_free_from_str()?,
+        "vars" => args.vars_template = pargs.opt_free_from_str()?,
+        "import" => args.import_path = pargs.opt_free_from_str()?,
+        "release" => args.release_version = pargs.opt_free_from_str()?,
+        "diff" => {
+            args.diff_old = pargs.opt_free_from_str()?;
+            args.diff_new = pargs.opt_free_from_str()?;
+        },
+        "convert-absolute" | "normalize" => {
+            while let Some(path) = pargs.opt_free_from_str()? {
+                args.song_paths.push(path);
+            }
+        },
+        "new" => {
+            while let Some(arg) = pargs.opt_free_from_str()? {
+                args.new_args.push(arg);
+            }
+        },
+        _ => {},
     }
 
+    args.unused = pargs.finish().iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+
     Ok(args)
 }
 
-fn main() -> Result<(), TemplaterError> {
-    let args = parse_args()?;
-    let transpose_text = transpose_text(&args.transpose)?;
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').map(String::from).collect()
+}
 
-    let conf = TemplaterConfig { transpose_text, };
+// warnings are printed at the end, even if the build failed
+fn main() {
+    let mut warnings = Warnings::default();
+    let result = run(&mut warnings);
+    warnings.print();
 
-    let mut songs: Vec<Song> = get_files_by_ext(&PathBuf::from(&args.songs_dir), "ly")
-        .iter_mut()
-        .map(|path| {
-            println!("Handling {}", &path.display());
-            let input = fs::read_to_string(path).unwrap();
+    if let Err(e) = result {
+        eprintln!("{}", e.report());
+        std::process::exit(1);
+    }
+}
 
-            let mut extractor = Extractor::new(Splitter::DelimiterLine("---"));
-            extractor.with_modifier(Modifier::TrimWhitespace);
+fn run(warnings: &mut Warnings) -> Result<(), TemplaterError> {
+    let args = parse_args()?;
 
-            let (front_matter, document) = extractor.extract(&input);
-            let front_matter = front_matter.into_owned();
-            let front_matter = front_matter.split("\n").collect::<Vec<&str>>();
+    let transposed = args.overrides.transpose.is_some();
+    let config = Config::load(args.config.as_deref())?.merge(args.overrides);
+    if config.writes_to_stdout() {
+        log_to_stderr();
+    }
 
-            Song::new(front_matter, document, conf.transpose_text.clone(), args.lyrics)
-        })
-        .collect();
-    println!("[info]: total songs found: {}", songs.len());
+    // a release's book has its own names and transpositions
+    let release = match args.subcommand {
+        "release" => {
+            let usage = || TemplaterError::from_str("usage: templater release <version>");
+            Some(Release::new(&config, &args.release_version.ok_or_else(usage)?)?)
+        },
+        _ => None,
+    };
+    let config = match &release {
+        Some(release) => release.config(config, transposed),
+        None => config,
+    };
 
-    if let Some(song_names) = args.song_names {
-        let song_names: Vec<&str> = song_names.split(",").collect();
+    let mut arg_warnings = Warnings::default();
+    for arg in &args.unused {
+        arg_warnings.push(Warning::new(WarningKind::UnusedArgument, format!("unused argument '{}'", arg)));
+    }
+    warnings.extend(arg_warnings.check(config.strict)?);
+
+    match args.subcommand {
+        "check" => {
+            let linter = match config.lint_ly {
+                true => Some(Linter::new(&config.includes_dir, warnings)?),
+                false => None,
+            };
+            let presets = Presets::load(&config.transpositions)?;
+            // every songs dir is checked on its own
+            let mut report = CheckReport::default();
+            for songs_dir in config.songs_dirs() {
+                if args.autofix {
+                    for (path, fixes) in autofix_songs(songs_dir, warnings)? {
+                        log!("[info]: fixed {} style issues in {}", fixes, path.display());
+                    }
+                }
+                let dir_report = check_songs(songs_dir, &presets, linter.as_ref(), warnings)?;
+                report.songs_checked += dir_report.songs_checked;
+                report.files.extend(dir_report.files);
+            }
+            println!("{}", report);
+            if report.has_errors() || (config.strict && (report.count(Severity::Warning) > 0 || !warnings.is_empty())) {
+                warnings.print();
+                std::process::exit(1);
+            }
+            return Ok(());
+        },
+        "drafts" => {
+            let mut report = DraftsReport::default();
+            for songs_dir in config.songs_dirs() {
+                let dir_report = find_drafts(songs_dir, warnings)?;
+                report.songs_checked += dir_report.songs_checked;
+                report.drafts.extend(dir_report.drafts);
+            }
+            println!("{}", report);
+            return Ok(());
+        },
+        "migrate" => {
+            let paths = song_files(&config, warnings)?;
+            let migrations = migrate_songs(&paths, args.dry_run)?;
+            for migration in &migrations {
+                match args.dry_run {
+                    true => print!("{}", migration.diff),
+                    false => log!("[info]: migrated {}: {}", migration.path.display(), migration.changes.join(", ")),
+                }
+            }
+            log!("[info]: {} of {} songs needed migrating", migrations.len(), paths.len());
+            return Ok(());
+        },
+        "lsp" => {
+            warnings.extend(lsp(config)?);
+            return Ok(());
+        },
+        "vars" => {
+            print!("{}", describe_vars(args.vars_template.as_deref())?);
+            return Ok(());
+        },
+        "convert-absolute" => {
+            let paths = match args.song_paths.is_empty() {
+                true => song_files(&config, warnings)?,
+                false => args.song_paths,
+            };
+
+            let conversions = convert_songs(&paths, args.dry_run)?;
+            for conversion in &conversions {
+                match args.dry_run {
+                    true => print!("{}", conversion.diff),
+                    false => log!("[info]: converted {}", conversion.path.display()),
+                }
+            }
+            log!("[info]: {} of {} songs had \\relative blocks", conversions.len(), paths.len());
+            return Ok(());
+        },
+        "normalize" => {
+            let paths = match args.song_paths.is_empty() {
+                true => song_files(&config, warnings)?,
+                false => args.song_paths,
+            };
+
+            let normalizations = normalize_songs(&paths, args.dry_run)?;
+            for normalization in &normalizations {
+                match args.dry_run {
+                    true => print!("{}", normalization.diff),
+                    false => log!(
+                        "[info]: normalized {}: {} bar checks added, {} removed",
+                        normalization.path.display(),
+                        normalization.changes.added,
+                        normalization.changes.removed
+                    ),
+                }
+            }
+            log!("[info]: {} of {} songs needed normalizing", normalizations.len(), paths.len());
+            return Ok(());
+        },
+        "new" => {
+            let mut new_args = args.new_args.iter();
+            let mut stub = SongStub::new(new_args.next().cloned().unwrap_or_default());
+            for arg in new_args {
+                stub.set_arg(arg)?;
+            }
+            if args.interactive {
+                stub.prompt(&mut io::stdin().lock(), &mut io::stdout())?;
+            }
+
+            let path = new_song(&config, &stub)?;
+            log!("[info]: created {}", path.display());
+            return Ok(());
+        },
+        "import" => {
+            let path = args
+                .import_path
+                .ok_or_else(|| TemplaterError::from_str("usage: templater import <file> [--format musicxml|abc]"))?;
+            if args.dry_run {
+                let songs = import_file(&path, args.import_format)?;
+                print!("{}", songs.iter().map(|song| song.to_song_file()).collect::<Vec<_>>().join("\n"));
+                return Ok(());
+            }
+
+            for song_path in import_song(&config, &path, args.import_format)? {
+                log!("[info]: imported {} to {}", path.display(), song_path.display());
+            }
+            return Ok(());
+        },
+        "diff" => {
+            let old = args.diff_old.ok_or_else(|| TemplaterError::from_str("usage: templater diff <old> [new]"))?;
+            let new = match args.diff_new {
+                Some(new) => Side::parse(&new),
+                None => Side::Dirs(config.songs_dirs().into_iter().map(PathBuf::from).collect()),
+            };
+            let presets = Presets::load(&config.transpositions)?;
+            let mut diff_warnings = Warnings::default();
+            let diff = diff_books(&Side::parse(&old), &new, &config.songs_dirs(), &presets, &mut diff_warnings)?;
+            warnings.extend(diff_warnings.check(config.strict)?);
+            print!("{}", diff);
+            return Ok(());
+        },
+        // build, watch, browse, serve, export, stats, and web build a book
+        _ => {},
+    }
 
-        songs = songs
+    if args.subcommand == "watch" {
+        // the templates dir only holds overrides, so it may not exist
+        let dirs = config
+            .songs_dirs()
             .into_iter()
-            .filter(|song| song_names.contains(&song.title.to_lowercase().as_str()))
+            .chain([config.templates_dir.as_path()])
+            .filter(|dir| dir.is_dir())
+            .map(PathBuf::from)
             .collect();
+        return watch_and_rebuild(dirs);
     }
 
-    if let Some(composers) = args.composers {
-        let composers: Vec<&str> = composers.split(",").collect();
+    let mut builder = BookBuilder::from_config(config).dry_run(args.dry_run);
+    for filter in args.filters {
+        builder = builder.filter(filter);
+    }
 
-        songs = songs
-            .into_iter()
-            .filter(|song| composers.contains(&song.composer.to_lowercase().as_str()))
-            .collect();
+    if let Some(song_names) = args.song_names {
+        builder = builder.song_names(split_list(&song_names));
     }
 
-    if songs.is_empty() {
-        println!("0 songs were selected. Check your filters (--song-names | --composers).");
-        std::process::exit(1);
+    if let Some(composers) = args.composers {
+        builder = builder.composers(split_list(&composers));
     }
 
-    println!("[info]: songs remaining after filtering: {}", songs.len());
-    songs.sort_by(|a, b| a.title.cmp(&b.title));
+    if args.subcommand == "browse" {
+        warnings.extend(browse(builder)?);
+        return Ok(());
+    }
+    if args.subcommand == "serve" {
+        warnings.extend(serve(builder, args.port, args.allow_origin.as_deref())?);
+        return Ok(());
+    }
 
-    init_static(&conf, songs.len())?;
+    // checked before the songs are read
+    let export_to = match args.subcommand {
+        "export" => {
+            let usage = "usage: templater export <file.json | file.csv | file.abc> [--format ireal]";
+            let path = args.export_path.ok_or_else(|| TemplaterError::from_str(usage))?;
+            let format = match args.export_format {
+                Some(format) => format,
+                None => ExportFormat::from_path(&path)?,
+            };
+            Some((path, format))
+        },
+        _ => None,
+    };
+    if let Some(path) = &args.stats_path {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            let message = format!("{}: stats are written as json. Expected a .json file.", path.display());
+            return Err(TemplaterError::from_str(&message));
+        }
+    }
 
-    let final_filename = format!("openbook-{}.ly", &conf.transpose_text.display_text);
-    let mut outfile = File::create(final_filename).expect("Unable to create output file");
+    let book = builder.build()?;
+    warnings.extend(book.warnings.iter().cloned());
+    if let Some(release) = &release {
+        let outputs = book.write()?;
+        warnings.extend(release.package(&book, &outputs)?.check(book.config.strict)?);
+        log!("[info]: released {} to {}", release.version, release.dir.display());
+        return Ok(());
+    }
+    if let Some((path, format)) = export_to {
+        let output = export(&book.songs, format)?;
+        fs::write(&path, output).map_err(|e| TemplaterError::file(&path, e))?;
 
-    write!(outfile, "{}", INTRO_TEMPLATE.get().unwrap()).unwrap();
+        log!("[info]: exported {} songs to {}", book.songs.len(), path.display());
+        return Ok(());
+    }
 
-    //let songs_by_meter: Vec<_> = songs
-    //    .clone()
-    //    .into_iter()
-    //    .into_group_map_by(|song| song.meter.clone())
-    //    .into_iter()
-    //    .filter(|(k, _v)| Option::is_some(&k))
-    //    .map(|(k, v)| (k, v))
-    //    .sorted_by(|a, b| Ord::cmp(&a.0, &b.0))
-    //    .collect();
+    if args.subcommand == "stats" {
+        let stats = Stats::new(&book.songs);
+        print!("{}", stats);
+        if let Some(path) = &args.stats_path {
+            fs::write(path, stats.to_json()?).map_err(|e| TemplaterError::file(path, e))?;
+            log!("[info]: wrote the stats to {}", path.display());
+        }
+        return Ok(());
+    }
 
-    //for (k, v) in songs_by_meter {
-    //    println!("k: {:?}", k);
-    //    println!("  -> v: {:?}", v.into_iter().map(|s| s.title).collect::<Vec<String>>());
-    //}
+    if args.subcommand == "web" {
+        let dir = args.web_dir.unwrap_or_else(|| book.config.out_dir.join(DEFAULT_WEB_DIR));
+        let pages = write_site(&book, &dir, args.snippets)?;
+        log!("[info]: wrote {} pages to {}", pages.len(), dir.display());
+        return Ok(());
+    }
 
-    //fs::create_dir("./.cache").expect("Unable to create .cache dir! Check your permissions.");
-    for song in songs {
-        println!("Handling {}", song.title);
-        song.write(&mut outfile);
-    //    let song_filename = format!("./.cache/{}-{}.ly", song.title, &conf.transpose_text.display_text);
-    //    let song_file = File::create(song_filename).expect("Unable to create songfile!"); 
+    if args.dry_run {
+        for (path, diff) in book.diff()? {
+            match diff.is_empty() {
+                true => log!("[info]: {} is unchanged", path.display()),
+                false => print!("{}", diff),
+            }
+        }
+        return Ok(());
     }
 
-    // }} escapes } apparently
-    writeln!(outfile, "}}").unwrap();
+    for output in book.write()? {
+        if !book.config.pdf {
+            continue;
+        }
+
+        // e.g. an html index or a lyrics book isn't lilypond
+        let Some(lilypond_output) = output.lilypond_output else { continue };
+        let pdf = run_lilypond(&book.config.lilypond, &book.config.lilypond_flags, &output.path, &output.source_map)?;
+        match lilypond_output {
+            LilypondOutput::Pdf => log!("[info]: wrote {}", pdf.display()),
+            // the midi book only has `\midi` blocks, so no pdf
+            LilypondOutput::Midi => log!("[info]: wrote {} midi files", book.songs.len()),
+        }
+    }
 
     Ok(())
 }
 
-fn transpose_text(input: &str) -> Result<TransposeText, TemplaterError> {
-    match input {
-        "c" => Ok(TransposeText {
-            display_text: "Concert".into(),
-            lilypond_text: "c c".into(),
-        }),
-        "bb" => Ok(TransposeText {
-            display_text: "Bb".into(),
-            lilypond_text: "c d".into(),
-        }),
-        "eb" => Ok(TransposeText {
-            display_text: "Eb".into(),
-            lilypond_text: "c a".into(),
-        }),
-        "testing-f" => Ok(TransposeText {
-            display_text: "Testing".into(),
-            lilypond_text: "c g".into(),
-        }),
-        _ => Err(TemplaterError::from_str(&format!("Transposing '{}' not supported.", input))),
+// every song file in every songs dir
+fn song_files(config: &Config, warnings: &mut Warnings) -> Result<Vec<PathBuf>, TemplaterError> {
+    let mut paths = vec![];
+    for songs_dir in config.songs_dirs() {
+        paths.extend(get_files_by_ext(&songs_dir, "ly", warnings)?);
     }
+    Ok(paths)
 }
 
-// set templates in memory
-fn init_static(conf: &TemplaterConfig, num_songs: usize) -> Result<(), TemplaterError> {
-    let intro_template = fs::read_to_string("./templates/intro")?
-        .replace(
-            "%%TRANSPOSE%%",
-            &capitalize_first_letter_ascii(&conf.transpose_text.display_text),
-        )
-        .replace("%%NUM_TUNES%%", &format!("{}", num_songs));
-
-    INTRO_TEMPLATE.set(intro_template)?;
-
-    let bookpart_template = fs::read_to_string("./templates/bookpart")?;
-    BOOKPART_TEMPLATE.set(bookpart_template)?;
-
-    let song_body_template = fs::read_to_string("./templates/song-body")?;
-    SONG_BODY_TEMPLATE.set(song_body_template)?;
-
-    let song_header_template = fs::read_to_string("./templates/song-header")?;
-    SONG_HEADER_TEMPLATE.set(song_header_template)?;
-
-    let voice_template = fs::read_to_string("./templates/voice")?
-        .replace("%%TRANSPOSE%%", &conf.transpose_text.lilypond_text);
-    VOICE_TEMPLATE.set(voice_template)?;
-
-    let lyrics_template = fs::read_to_string("./templates/lyrics")?;
-    LYRICS_TEMPLATE.set(lyrics_template)?;
-
-    let chords_template = fs::read_to_string("./templates/chords")?;
-    CHORDS_TEMPLATE.set(chords_template)?;
+// every rebuild runs the binary again with the same args, as `build`,
+// so the config is read again along with the songs and templates.
+fn watch_and_rebuild(dirs: Vec<PathBuf>) -> Result<(), TemplaterError> {
+    let exe = std::env::current_exe()?;
+    // the first one is `watch`
+    let rebuild_args: Vec<String> = std::env::args().skip(2).collect();
+
+    let rebuild = |_changed: &[PathBuf]| -> Result<(), TemplaterError> {
+        let status = Command::new(&exe).args(&rebuild_args).status()?;
+        if !status.success() {
+            eprintln!("[Error]: rebuild failed, waiting for changes.");
+        }
+        Ok(())
+    };
 
-    Ok(())
+    rebuild(&[])?;
+    watch(&dirs, rebuild)
 }
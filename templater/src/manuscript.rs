@@ -0,0 +1,24 @@
+// `--manuscript-pages 4` and `--chord-grid-pages 2`: blank pages at
+// the end of the book, like the ones at the back of a fakebook, to
+// write a tune down on. manuscript pages are empty staves, from
+// `templates/manuscript`, and chord grid pages are lines of empty
+// bars for a tune's changes, from `templates/chord-grids`. how many
+// staves or bars a page has is up to the template.
+
+use minijinja::context;
+
+use crate::errors::TemplaterError;
+use crate::models::Config;
+use crate::templates::Templates;
+
+// none if the book doesn't ask for any
+pub fn render_blank_pages(templates: &Templates, config: &Config) -> Result<Option<String>, TemplaterError> {
+    let mut pages = String::new();
+    for (template, count) in [("manuscript", config.manuscript_pages), ("chord-grids", config.chord_grid_pages)] {
+        if count > 0 {
+            pages.push_str(&templates.render(template, context! { pages => count })?);
+        }
+    }
+
+    Ok(Some(pages).filter(|pages| !pages.is_empty()))
+}
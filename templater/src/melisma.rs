@@ -0,0 +1,126 @@
+// checks that every verse of a song's lyrics has a syllable for each
+// note of the melody it's sung to, since `\lyricsto` just puts them
+// on the notes in order: a missing `--` or an extra `_` moves every
+// word after it. tied notes and the rest of a slur or `\melisma`
+// are sung on one syllable, rests aren't sung at all.
+
+use std::cmp::Ordering;
+
+use crate::lyrics::syllables;
+use crate::models::{Song, Verse};
+use crate::music::{sung_notes, SungNote};
+
+// e.g. "verse 2." or "the lyrics"
+fn describe(verse: &Verse, n: usize, of: usize) -> String {
+    match &verse.stanza {
+        Some(stanza) => format!("verse {}", stanza.trim_end_matches('.')),
+        None if of > 1 => format!("lyrics {}", n + 1),
+        None => String::from("the lyrics"),
+    }
+}
+
+pub fn verify_lyrics(song: &Song) -> Vec<String> {
+    let mut warnings = vec![];
+    if song.lyrics.is_empty() {
+        return warnings;
+    }
+
+    // `\lyricsto "Voice"` is the melody
+    let Some(melody) = song.voices.iter().find(|voice| voice.name.is_none()) else {
+        return vec![String::from("there are lyrics but no melody to sing them to")];
+    };
+    let notes: Vec<SungNote> = sung_notes(&melody.notes);
+    let Some(last) = notes.last() else {
+        return warnings;
+    };
+
+    let unnamed = song.lyrics.iter().filter(|verse| verse.stanza.is_none()).count();
+    for (n, verse) in song.lyrics.iter().enumerate() {
+        let syllables = syllables(&verse.text);
+        let name = describe(verse, n, unnamed);
+
+        // e.g. `mes - sage` instead of `mes -- sage`
+        for (syllable, note) in syllables.iter().zip(&notes) {
+            let punctuation =
+                !syllable.chars().any(char::is_alphanumeric) && syllable != "_" && !syllable.starts_with('\\');
+            match syllable.as_str() {
+                "-" => warnings.push(format!(
+                    "{}: '-' in measure {} is sung as a syllable, '--' is the hyphen between two",
+                    name, note.measure
+                )),
+                _ if punctuation => {
+                    warnings.push(format!("{}: '{}' in measure {} is sung as a syllable", name, syllable, note.measure))
+                },
+                _ => {},
+            }
+        }
+
+        // a second verse is often only the words of a repeat, but
+        // there isn't a note for any syllable after the last one
+        match syllables.len().cmp(&notes.len()) {
+            Ordering::Greater => {
+                let extra = syllables.len() - notes.len();
+                warnings.push(format!(
+                    "{}: {} more syllable{} than the melody has notes, from '{}' on, after the last note in measure {}",
+                    name,
+                    extra,
+                    if extra == 1 { "" } else { "s" },
+                    syllables[notes.len()],
+                    last.measure
+                ));
+            },
+            Ordering::Less if song.lyrics.len() == 1 => {
+                let end = syllables.len().checked_sub(1).map_or(notes[0].measure, |i| notes[i].measure);
+                warnings.push(format!(
+                    "{}: the words end in measure {}, {} notes before the melody does in measure {}",
+                    name,
+                    end,
+                    notes.len() - syllables.len(),
+                    last.measure
+                ));
+            },
+            _ => {},
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn song(melody: &str, lyrics: &[&str]) -> Song {
+        let mut input = format!("title: Test\n---\n\\relative c' {{ \\numericTimeSignature {} }}\n", melody);
+        for (i, verse) in lyrics.iter().enumerate() {
+            input.push_str(&format!("--- verse: {} ---\n\\lyricmode {{ {} }}\n", i + 1, verse));
+        }
+        Song::parse(Path::new("test.ly"), &input, true).unwrap()
+    }
+
+    #[test]
+    fn it_accepts_a_syllable_for_every_note() {
+        let melody = "c4 d e( f) | g2~ g4 r | c1";
+        assert!(verify_lyrics(&song(melody, &["la -- la la __ la la"])).is_empty());
+        // the second verse is only the start
+        assert!(verify_lyrics(&song(melody, &["la -- la la __ la la", "la la"])).is_empty());
+    }
+
+    #[test]
+    fn it_reports_syllables_without_a_note() {
+        let melody = "c4 d e( f) | g2~ g4 r | c1";
+
+        assert_eq!(
+            verify_lyrics(&song(melody, &["la - la la la la"])),
+            vec![
+                "verse 1: '-' in measure 1 is sung as a syllable, '--' is the hyphen between two",
+                "verse 1: 1 more syllable than the melody has notes, from 'la' on, after the last note in measure 3",
+            ]
+        );
+        assert_eq!(
+            verify_lyrics(&song(melody, &["la la la"])),
+            vec!["verse 1: the words end in measure 1, 2 notes before the melody does in measure 3"]
+        );
+    }
+}
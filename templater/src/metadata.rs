@@ -0,0 +1,119 @@
+// the book's title page and meta information, so a fork of the
+// book doesn't need its own `intro` template. set in the config
+// file, or with `--metadata field=value`:
+//
+//     [metadata]
+//     title = "The Gig Book"
+//     editor = "Jane Doe"
+//
+// every template that has a title gets them as `metadata`.
+
+use std::str::FromStr;
+
+use minijinja::{context, Value};
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+
+pub const METADATA_FIELDS: [&str; 6] = ["title", "subtitle", "edition", "date", "editor", "copyright"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BookMetadata {
+    pub title: String,
+    pub subtitle: String,
+    // e.g. "Version 0.8.0"
+    pub edition: String,
+    // e.g. "Spring 2024". the build time is always printed too
+    pub date: Option<String>,
+    pub editor: Option<String>,
+    // replaces the note about the songs' copyrights
+    pub copyright: Option<String>,
+}
+
+impl Default for BookMetadata {
+    fn default() -> Self {
+        BookMetadata {
+            title: String::from("The Openbook"),
+            subtitle: String::from("An open-source, libre songbook."),
+            edition: String::from("Version 0.8.0"),
+            date: None,
+            editor: None,
+            copyright: None,
+        }
+    }
+}
+
+impl BookMetadata {
+    pub fn set(&mut self, value: &MetadataValue) {
+        let text = value.value.clone();
+        let optional = Some(text.clone()).filter(|text| !text.is_empty());
+
+        match value.field.as_str() {
+            "title" => self.title = text,
+            "subtitle" => self.subtitle = text,
+            "edition" => self.edition = text,
+            "date" => self.date = optional,
+            "editor" => self.editor = optional,
+            "copyright" => self.copyright = optional,
+            _ => {},
+        }
+    }
+
+    pub fn context(&self) -> Value {
+        context! {
+            title => self.title.as_str(),
+            subtitle => self.subtitle.as_str(),
+            edition => self.edition.as_str(),
+            date => self.date.clone().unwrap_or_default(),
+            editor => self.editor.clone().unwrap_or_default(),
+            copyright => self.copyright.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// `--metadata field=value`. an empty value clears an optional field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataValue {
+    pub field: String,
+    pub value: String,
+}
+
+impl FromStr for MetadataValue {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s
+            .split_once('=')
+            .ok_or_else(|| TemplaterError::from_str("Expected field=value, e.g. title=\"The Gig Book\"."))?;
+        let field = field.trim().to_lowercase();
+        if !METADATA_FIELDS.contains(&field.as_str()) {
+            return Err(TemplaterError::from_str(&format!(
+                "Unknown metadata field '{}'. Expected {}.",
+                field,
+                METADATA_FIELDS.join(", ")
+            )));
+        }
+
+        Ok(MetadataValue { field, value: value.trim().to_string() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_sets_metadata_from_the_command_line() {
+        let mut metadata = BookMetadata::default();
+        metadata.set(&"title=The Gig Book".parse().unwrap());
+        metadata.set(&"Editor = Jane Doe".parse().unwrap());
+        metadata.set(&"copyright=".parse().unwrap());
+
+        assert_eq!(metadata.title, "The Gig Book");
+        assert_eq!(metadata.editor.as_deref(), Some("Jane Doe"));
+        assert_eq!(metadata.copyright, None);
+        assert!("publisher=Me".parse::<MetadataValue>().is_err());
+        assert!("title".parse::<MetadataValue>().is_err());
+    }
+}
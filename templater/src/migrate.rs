@@ -0,0 +1,317 @@
+// `templater migrate`: upgrades every song's front matter to the
+// current keys, e.g. `lyricist` to `poet` or `type: blues` to
+// `tags: [blues]`, puts the keys in the usual order, and adds the
+// ones `templater new` writes. values are kept as they're written.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::Value;
+use similar::TextDiff;
+
+use crate::errors::TemplaterError;
+use crate::frontmatter;
+use crate::utils::{read_file, yaml_string};
+
+// (old, new)
+const RENAMED_KEYS: [(&str, &str); 8] = [
+    ("lyricist", "poet"),
+    ("lyrics_by", "poet"),
+    ("tempo", "bpm"),
+    ("arrangement", "arranger"),
+    ("transpose", "transpose_override"),
+    ("piano_staff", "pianostaff"),
+    ("sorttitle", "sort_title"),
+    ("structure", "form"),
+];
+
+// old keys for what are tags now
+const TAG_KEYS: [&str; 3] = ["type", "category", "genre"];
+
+// written by `templater new`, empty if there's no value yet
+const DEFAULT_KEYS: [&str; 3] = ["composer", "meter", "bpm"];
+
+// every other key comes after these, in the order they were in
+const KEY_ORDER: [&str; 26] = [
+    "title",
+    "subtitle",
+    "subsubtitle",
+    "sort_title",
+    "composer",
+    "poet",
+    "arranger",
+    "meter",
+    "bpm",
+    "key",
+    "form",
+    "year",
+    "tags",
+    "variants",
+    "status",
+    "copyright",
+    "dedication",
+    "footer",
+    "tagline",
+    "instrument",
+    "pianostaff",
+    "transpose_override",
+    "pages",
+    "break_before",
+    "compress",
+    "ragged",
+];
+
+// a key and its lines, e.g. the items of a list, and any comments
+// right above it
+#[derive(Debug, Clone)]
+struct Entry {
+    key: String,
+    lines: Vec<String>,
+}
+
+impl Entry {
+    fn new(key: &str, value: &str) -> Self {
+        let line = match value.is_empty() {
+            true => format!("{}:", key),
+            false => format!("{}: {}", key, value),
+        };
+        Entry { key: key.to_string(), lines: vec![line] }
+    }
+
+    fn value(&self) -> Value {
+        let yaml = self.lines.join("\n");
+        serde_yaml::from_str::<BTreeMap<String, Value>>(&yaml)
+            .ok()
+            .and_then(|mut map| map.remove(&self.key))
+            .unwrap_or(Value::Null)
+    }
+
+    fn rename(&mut self, key: &str) {
+        for line in &mut self.lines {
+            if let Some(rest) = line.strip_prefix(&format!("{}:", self.key)) {
+                *line = format!("{}:{}", key, rest);
+                break;
+            }
+        }
+        self.key = key.to_string();
+    }
+}
+
+fn top_level_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once(':')?;
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(key)
+}
+
+// e.g. "blues" or `[bossa, ballad]`
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Sequence(values) => values.iter().flat_map(strings).collect(),
+        Value::String(s) => s.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+        Value::Number(n) => vec![n.to_string()],
+        _ => vec![],
+    }
+}
+
+// (migrated front matter, what changed)
+fn migrate_front_matter(lines: &[&str]) -> (Vec<String>, Vec<String>) {
+    let mut changes = vec![];
+
+    // comments above the first key stay at the top
+    let mut header = vec![];
+    let mut entries: Vec<Entry> = vec![];
+    let mut comments = vec![];
+    for line in lines {
+        match top_level_key(line) {
+            Some(key) => {
+                let mut lines = std::mem::take(&mut comments);
+                lines.push(line.to_string());
+                entries.push(Entry { key: key.to_string(), lines });
+            },
+            None if line.trim_start().starts_with('#') && entries.is_empty() => header.push(line.to_string()),
+            None if line.trim_start().starts_with('#') => comments.push(line.to_string()),
+            None => match entries.last_mut() {
+                Some(entry) => entry.lines.push(line.to_string()),
+                None => header.push(line.to_string()),
+            },
+        }
+    }
+    let trailing_comments = comments;
+
+    let has = |entries: &[Entry], key: &str| entries.iter().any(|entry| entry.key == key);
+
+    for (old, new) in RENAMED_KEYS {
+        let Some(i) = entries.iter().position(|entry| entry.key == old) else { continue };
+        match has(&entries, new) {
+            true => changes.push(format!("kept {}, since {} is set too", old, new)),
+            false => {
+                entries[i].rename(new);
+                changes.push(format!("renamed {} to {}", old, new));
+            },
+        }
+    }
+
+    if let Some(i) = entries.iter().position(|entry| entry.key == "draft") {
+        let entry = entries.remove(i);
+        if entry.value() == Value::Bool(true) && !has(&entries, "status") {
+            entries.push(Entry::new("status", "draft"));
+            changes.push(String::from("replaced draft: true with status: draft"));
+        } else {
+            changes.push(String::from("removed draft"));
+        }
+    }
+
+    // the style was the meter, before there were tags
+    if let Some(i) = entries.iter().position(|entry| entry.key == "style") {
+        if !has(&entries, "meter") {
+            entries[i].rename("meter");
+            changes.push(String::from("renamed style to meter"));
+        }
+    }
+
+    let mut tags = vec![];
+    let mut tag_keys = vec![];
+    for key in TAG_KEYS.iter().copied().chain(["style"]) {
+        if let Some(i) = entries.iter().position(|entry| entry.key == key) {
+            tags.extend(strings(&entries.remove(i).value()));
+            tag_keys.push(key);
+        }
+    }
+    if !tag_keys.is_empty() {
+        let mut all_tags = entries.iter().find(|entry| entry.key == "tags").map_or(vec![], |entry| strings(&entry.value()));
+        for tag in tags {
+            if !all_tags.iter().any(|t| t.to_lowercase() == tag.to_lowercase()) {
+                all_tags.push(tag);
+            }
+        }
+
+        let list = format!("[{}]", all_tags.iter().map(|tag| yaml_string(tag)).collect::<Vec<_>>().join(", "));
+        entries.retain(|entry| entry.key != "tags");
+        entries.push(Entry::new("tags", &list));
+        changes.push(format!("moved {} to tags", tag_keys.join(", ")));
+    }
+
+    for key in DEFAULT_KEYS {
+        if !has(&entries, key) {
+            entries.push(Entry::new(key, ""));
+            changes.push(format!("added {}", key));
+        }
+    }
+
+    let order = |entry: &Entry| KEY_ORDER.iter().position(|key| *key == entry.key).unwrap_or(KEY_ORDER.len());
+    let before: Vec<String> = entries.iter().map(|entry| entry.key.clone()).collect();
+    entries.sort_by_key(order);
+    if changes.is_empty() && entries.iter().map(|entry| &entry.key).ne(before.iter()) {
+        changes.push(String::from("reordered the keys"));
+    }
+
+    let mut lines = header;
+    lines.extend(entries.into_iter().flat_map(|entry| entry.lines));
+    lines.extend(trailing_comments);
+    (lines, changes)
+}
+
+// one song's text, with its front matter migrated
+fn migrate_song(song: &str) -> (String, Vec<String>) {
+    let newline = if song.contains("\r\n") { "\r\n" } else { "\n" };
+    let lines: Vec<&str> = song.split_inclusive('\n').collect();
+    let end = lines.iter().position(|line| line.trim() == "---").unwrap_or(lines.len());
+
+    let front_matter: Vec<&str> =
+        lines[..end].iter().map(|line| line.trim_end_matches(['\n', '\r'])).filter(|line| !line.trim().is_empty()).collect();
+    let (front_matter, changes) = migrate_front_matter(&front_matter);
+    if changes.is_empty() {
+        return (song.to_string(), changes);
+    }
+
+    let mut migrated: String = front_matter.iter().map(|line| format!("{}{}", line, newline)).collect();
+    if end == lines.len() && !song.ends_with('\n') {
+        migrated.truncate(migrated.len() - newline.len());
+    }
+    migrated.extend(lines[end..].iter().copied());
+
+    (migrated, changes)
+}
+
+// every song in a file. (migrated file, what changed)
+pub fn migrate_source(input: &str) -> (String, Vec<String>) {
+    let mut migrated = String::with_capacity(input.len());
+    let mut all_changes = vec![];
+
+    let mut end = 0;
+    for (_, song) in frontmatter::split_songs(input) {
+        // the separator between them, if any
+        let start = song.as_ptr() as usize - input.as_ptr() as usize;
+        migrated.push_str(&input[end..start]);
+        end = start + song.len();
+
+        let (song, changes) = migrate_song(song);
+        migrated.push_str(&song);
+        all_changes.extend(changes);
+    }
+
+    (migrated, all_changes)
+}
+
+// a song that was (or would be, with `--dry-run`) migrated
+pub struct Migration {
+    pub path: PathBuf,
+    pub changes: Vec<String>,
+    pub diff: String,
+}
+
+pub fn migrate_songs(paths: &[PathBuf], dry_run: bool) -> Result<Vec<Migration>, TemplaterError> {
+    let mut migrations = vec![];
+
+    for path in paths {
+        if let Some(migration) = migrate_song_file(path, dry_run)? {
+            migrations.push(migration);
+        }
+    }
+
+    Ok(migrations)
+}
+
+fn migrate_song_file(path: &Path, dry_run: bool) -> Result<Option<Migration>, TemplaterError> {
+    let song = read_file(path)?;
+    let (migrated, changes) = migrate_source(&song);
+    if migrated == song {
+        return Ok(None);
+    }
+
+    if !dry_run {
+        fs::write(path, &migrated).map_err(|e| TemplaterError::file(path, e))?;
+    }
+
+    let name = path.display().to_string();
+    let diff = TextDiff::from_lines(&song, &migrated).unified_diff().context_radius(3).header(&name, &name).to_string();
+    Ok(Some(Migration { path: path.to_path_buf(), changes, diff }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_migrates_old_front_matter() {
+        let input = "title: Blue 'N Boogie\nyear: 1944\nlyricist: Nobody\n# the tempo from the record\ntempo: 240\ntype: blues\nstyle: Bop\n---\nc1\n";
+        let (migrated, changes) = migrate_source(input);
+
+        assert_eq!(
+            migrated,
+            "title: Blue 'N Boogie\ncomposer:\npoet: Nobody\nmeter: Bop\n# the tempo from the record\nbpm: 240\nyear: 1944\ntags: [blues]\n---\nc1\n"
+        );
+        assert_eq!(changes, vec!["renamed lyricist to poet", "renamed tempo to bpm", "renamed style to meter", "moved type to tags", "added composer"]);
+    }
+
+    #[test]
+    fn it_leaves_current_front_matter_alone() {
+        let input = "title: Misty\ncomposer: Errol Garner\nmeter: Ballad\nbpm:\ntags:\n  - ballad\n---\n===\ntitle: Oleo\ncomposer: Sonny Rollins\nmeter: Fast\nbpm: 240\n---\n";
+        assert_eq!(migrate_source(input), (input.to_string(), vec![]));
+
+        let (migrated, _) = migrate_source("title: Oleo\nmeter: Fast\nbpm: 240\ncomposer: Sonny Rollins\nstyle: Bop\ntags: bebop");
+        assert_eq!(migrated, "title: Oleo\ncomposer: Sonny Rollins\nmeter: Fast\nbpm: 240\ntags: [bebop, Bop]");
+    }
+}
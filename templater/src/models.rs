@@ -1,39 +1,623 @@
-use std::collections::HashMap;
-use std::fs::File;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use crate::capitalize_first_letter_ascii;
 
+use minijinja::{context, Value};
+use serde::Deserialize;
+
+use crate::anthology::DuplicateTitles;
+use crate::backing::BackingTrack;
+use crate::cache::DEFAULT_CACHE_DIR;
+use crate::chord::polychords;
+use crate::capitalize_first_letter;
+use crate::clef::{lilypond_clef, Clef};
+use crate::composers::{self, DEFAULT_COMPOSERS_FILE};
+use crate::errors::TemplaterError;
+use crate::form::Form;
+use crate::frontmatter::{self, Defaults, Frontmatter};
+use crate::guitar::DEFAULT_VOICINGS_FILE;
+use crate::harmony::written_keys;
+use crate::keys::Key;
+use crate::metadata::{BookMetadata, MetadataValue};
+use crate::music::{count_bars, time_signature, time_signatures, Fraction, TimeSignature};
+use crate::numbers::{numbers_chart, BARS_PER_LINE};
+use crate::sections::expand_markers;
+use crate::songids::{song_key, DEFAULT_SONG_IDS_FILE};
+use crate::order::SortOrder;
+use crate::profiles::{template_for, LayoutProfile};
+use crate::spelling::{spell_for, Spelling};
+use crate::provenance::Provenance;
+use crate::range::Range;
+use crate::tempo::Tempo;
+use crate::templates::Templates;
+use crate::toc::IndexKind;
+use crate::transpose::{transpose_text, Presets, DEFAULT_TRANSPOSITIONS_FILE};
+use crate::utils::{add_to_file_stem, read_file, title_sort_key, today};
+
+pub const DEFAULT_CONFIG_FILE: &str = "templater.toml";
+pub const DEFAULT_OUTPUT: &str = "openbook-{key}.ly";
+// `--output -`
+pub const STDOUT: &str = "-";
+pub const UNKNOWN_COMPOSER: &str = "UNKNOWN COMPOSER";
+
+// what every song in the book looks like
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BookMode {
+    // the lead sheets
+    #[default]
+    Full,
+    // a cheat sheet for comping: only every song's chord grid,
+    // without staves or dividers, written to e.g. `openbook-Bb-chords.ly`
+    ChordsOnly,
+    // only the words, as text, Markdown, or LaTeX, see `lyrics.rs`
+    Lyrics,
+    // the lead sheets with a fret diagram above every chord, see
+    // `guitar.rs`, written to e.g. `openbook-Concert-guitar.ly`
+    Guitar,
+    // every song's changes as roman numerals of its key, see
+    // `numbers.rs`, written to e.g. `openbook-Concert-numbers.ly`
+    Numbers,
+    // every song's melody and chords in braille music, see
+    // `braille.rs`, written to e.g. `openbook-Concert.brf`
+    Braille,
+}
+
+impl FromStr for BookMode {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "full" => Ok(BookMode::Full),
+            "chords-only" | "chords" => Ok(BookMode::ChordsOnly),
+            "lyrics" => Ok(BookMode::Lyrics),
+            "guitar" => Ok(BookMode::Guitar),
+            "numbers" | "roman" => Ok(BookMode::Numbers),
+            "braille" | "brf" => Ok(BookMode::Braille),
+            _ => Err(TemplaterError::from_str(&format!(
+                "Unknown mode '{}'. Expected full, chords-only, lyrics, guitar, numbers, or braille.",
+                s
+            ))),
+        }
+    }
+}
+
+// values read from `templater.toml`. anything passed on
+// the command line takes precedence over the file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub transpose: String,
+    // how a transposed song's key is spelled, see `spelling.rs`
+    pub spelling: Spelling,
+    // by transposition, e.g. `eb = "flats"`
+    pub spellings: BTreeMap<String, Spelling>,
+    // the instrument's written range by transposition, see `range.rs`
+    pub ranges: BTreeMap<String, Range>,
+    pub songs_dir: PathBuf,
+    // merged into the book, e.g. another collection's songs, see `anthology.rs`
+    pub more_songs_dirs: Vec<PathBuf>,
+    // which song a title in more than one songs dir keeps
+    pub duplicate_titles: DuplicateTitles,
+    pub templates_dir: PathBuf,
+    // the books are written here, created if it doesn't exist
+    pub out_dir: PathBuf,
+    // a filename pattern, see `output_path`
+    pub output: Option<PathBuf>,
+    // one book per range of letters, see `volumes.rs`
+    pub split_volumes: usize,
+    pub split_at: Vec<String>,
+    // blank pages at the end of the book, see `manuscript.rs`
+    pub manuscript_pages: usize,
+    pub chord_grid_pages: usize,
+    pub lyrics: bool,
+    // songs with `status: draft`
+    pub include_drafts: bool,
+    // only songs in the public domain this year, see `license.rs`
+    pub public_domain_only: bool,
+    // every song is tagged with the folders it's in, e.g. `songs/bossa/`
+    pub folder_tags: bool,
+    pub mode: BookMode,
+    // the order of the songs, see `order.rs`
+    pub sort: SortOrder,
+    // the paper and staff size, see `profiles.rs`
+    pub layout: Option<LayoutProfile>,
+    pub indexes: Vec<IndexKind>,
+    // run lilypond on every generated book
+    pub pdf: bool,
+    // also write a book of midi files, see `MidiBook`
+    pub midi: bool,
+    // also write an `index.html` of the songs, see `html.rs`
+    pub html_index: bool,
+    // also write every song as its own lead sheet, see `SongSheets`
+    pub per_song: bool,
+    // only these songs, in this order, see `setlist.rs`
+    pub setlist: Option<PathBuf>,
+    // a git ref or date for the "Recent Additions" page, see `changelog.rs`
+    pub changes_since: Option<String>,
+    pub lilypond: String,
+    pub lilypond_flags: Vec<String>,
+    // stable song numbers, see `songids.rs`
+    pub song_ids: PathBuf,
+    // check every song's lilypond before rendering, see `lint.rs`
+    pub lint_ly: bool,
+    // respell every chord in the house style, see `chordnames.rs`
+    pub normalize_chords: bool,
+    pub chord_spellings: BTreeMap<String, String>,
+    pub includes_dir: PathBuf,
+    // every warning is an error, see `warnings.rs`
+    pub strict: bool,
+    // overwrite books that were edited by hand, see `written.rs`
+    pub force: bool,
+    // a page with the letter before the songs starting with it
+    pub dividers: bool,
+    // every song's form or measures in the ToC, see `form.rs`
+    pub toc_form: bool,
+    // every song's form and jumps under its title, see `form.rs`
+    pub form_summary: bool,
+    // composer aliases, see `composers.rs`
+    pub composer_aliases: PathBuf,
+    // fret diagrams for a guitar book, see `guitar.rs`
+    pub voicings: PathBuf,
+    // more transpositions by name, see `transpose.rs`
+    pub transpositions: PathBuf,
+    // the title page, see `metadata.rs`
+    pub metadata: BookMetadata,
+    // rendered bookparts, see `cache.rs`
+    pub cache: bool,
+    pub cache_dir: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            transpose: String::from("c"),
+            spelling: Spelling::Simplest,
+            spellings: BTreeMap::new(),
+            ranges: BTreeMap::new(),
+            songs_dir: PathBuf::from("./songs"),
+            more_songs_dirs: vec![],
+            duplicate_titles: DuplicateTitles::Error,
+            templates_dir: PathBuf::from("./templates"),
+            out_dir: PathBuf::from("."),
+            output: None,
+            split_volumes: 1,
+            split_at: vec![],
+            manuscript_pages: 0,
+            chord_grid_pages: 0,
+            lyrics: false,
+            include_drafts: false,
+            public_domain_only: false,
+            folder_tags: false,
+            mode: BookMode::Full,
+            sort: SortOrder::Title,
+            layout: None,
+            indexes: IndexKind::all(),
+            pdf: false,
+            midi: false,
+            html_index: false,
+            per_song: false,
+            setlist: None,
+            changes_since: None,
+            lilypond: String::from("lilypond"),
+            lilypond_flags: vec![],
+            song_ids: PathBuf::from(DEFAULT_SONG_IDS_FILE),
+            lint_ly: false,
+            normalize_chords: false,
+            chord_spellings: BTreeMap::new(),
+            includes_dir: PathBuf::from("./includes"),
+            strict: false,
+            force: false,
+            dividers: true,
+            toc_form: false,
+            form_summary: false,
+            composer_aliases: PathBuf::from(DEFAULT_COMPOSERS_FILE),
+            voicings: PathBuf::from(DEFAULT_VOICINGS_FILE),
+            transpositions: PathBuf::from(DEFAULT_TRANSPOSITIONS_FILE),
+            metadata: BookMetadata::default(),
+            cache: true,
+            cache_dir: PathBuf::from(DEFAULT_CACHE_DIR),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, TemplaterError> {
+        let input = read_file(path)?;
+        Ok(toml::from_str(&input)?)
+    }
+
+    // an explicit `--config` has to exist, but the default
+    // `templater.toml` is optional.
+    pub fn load(path: Option<&Path>) -> Result<Self, TemplaterError> {
+        match path {
+            Some(path) => Config::from_file(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::from_file(DEFAULT_CONFIG_FILE),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn merge(mut self, overrides: ConfigOverrides) -> Self {
+        if let Some(transpose) = overrides.transpose {
+            self.transpose = transpose;
+        }
+        // for every transposition, even one in `spellings`
+        if let Some(spelling) = overrides.spelling {
+            self.spelling = spelling;
+            self.spellings.clear();
+        }
+        // the songs dirs given replace every one in the file
+        if let Some(songs_dir) = overrides.songs_dir {
+            self.songs_dir = songs_dir;
+            self.more_songs_dirs = overrides.more_songs_dirs;
+        }
+        if let Some(duplicate_titles) = overrides.duplicate_titles {
+            self.duplicate_titles = duplicate_titles;
+        }
+        if let Some(templates_dir) = overrides.templates_dir {
+            self.templates_dir = templates_dir;
+        }
+        if let Some(out_dir) = overrides.out_dir {
+            self.out_dir = out_dir;
+        }
+        if overrides.output.is_some() {
+            self.output = overrides.output;
+        }
+        if let Some(split_volumes) = overrides.split_volumes {
+            self.split_volumes = split_volumes;
+        }
+        if let Some(split_at) = overrides.split_at {
+            self.split_at = split_at;
+        }
+        if let Some(manuscript_pages) = overrides.manuscript_pages {
+            self.manuscript_pages = manuscript_pages;
+        }
+        if let Some(chord_grid_pages) = overrides.chord_grid_pages {
+            self.chord_grid_pages = chord_grid_pages;
+        }
+        self.lyrics |= overrides.lyrics;
+        self.include_drafts |= overrides.include_drafts;
+        self.public_domain_only |= overrides.public_domain_only;
+        self.folder_tags |= overrides.folder_tags;
+        if let Some(mode) = overrides.mode {
+            self.mode = mode;
+        }
+        if let Some(sort) = overrides.sort {
+            self.sort = sort;
+        }
+        if overrides.layout.is_some() {
+            self.layout = overrides.layout;
+        }
+        if let Some(indexes) = overrides.indexes {
+            self.indexes = indexes;
+        }
+        self.pdf |= overrides.pdf;
+        self.midi |= overrides.midi;
+        self.html_index |= overrides.html_index;
+        self.per_song |= overrides.per_song;
+        if overrides.setlist.is_some() {
+            self.setlist = overrides.setlist;
+        }
+        if overrides.changes_since.is_some() {
+            self.changes_since = overrides.changes_since;
+        }
+        if let Some(lilypond_flags) = overrides.lilypond_flags {
+            self.lilypond_flags = lilypond_flags;
+        }
+        if let Some(song_ids) = overrides.song_ids {
+            self.song_ids = song_ids;
+        }
+        if let Some(composer_aliases) = overrides.composer_aliases {
+            self.composer_aliases = composer_aliases;
+        }
+        if let Some(voicings) = overrides.voicings {
+            self.voicings = voicings;
+        }
+        if let Some(transpositions) = overrides.transpositions {
+            self.transpositions = transpositions;
+        }
+        for value in &overrides.metadata {
+            self.metadata.set(value);
+        }
+        self.lint_ly |= overrides.lint_ly;
+        self.normalize_chords |= overrides.normalize_chords;
+        self.strict |= overrides.strict;
+        self.force |= overrides.force;
+        self.dividers &= !overrides.no_dividers;
+        self.toc_form |= overrides.toc_form;
+        self.form_summary |= overrides.form_summary;
+        self.cache &= !overrides.no_cache;
+
+        self
+    }
+
+    // `songs_dir` first
+    pub fn songs_dirs(&self) -> Vec<&Path> {
+        std::iter::once(&self.songs_dir).chain(&self.more_songs_dirs).map(PathBuf::as_path).collect()
+    }
+
+    pub fn template_path(&self, name: &str) -> PathBuf {
+        self.templates_dir.join(name)
+    }
+
+    // `{key}` in the filename is replaced by the transposition, e.g.
+    // `Bb`, and `{date}` by today's date, e.g. `2024-03-01`. a volume
+    // of a split book replaces `{volume}`, or gets e.g. `-vol2` added.
+    // a chords-only book gets `-chords` added, a guitar book `-guitar`,
+    // a numbers chart `-numbers`, a lyrics book `-lyrics` and a `.md`
+    // extension instead of `.ly`, and a braille book a `.brf` one. a
+    // setlist book gets the setlist's name added, e.g. `-friday-gig`,
+    // and a layout profile its name, e.g. `-large-print`. stdout is `-`
+    // as it is.
+    pub fn output_path(&self, transpose_text: &TransposeText, volume: Option<usize>) -> PathBuf {
+        if self.writes_to_stdout() {
+            return PathBuf::from(STDOUT);
+        }
+
+        let pattern = match &self.output {
+            Some(output) => output.to_string_lossy(),
+            None => DEFAULT_OUTPUT.into(),
+        };
+        let mut filename = pattern
+            .replace("{key}", &transpose_text.display_text.replace(' ', "-"))
+            .replace("{date}", &today());
+        match self.mode {
+            BookMode::Full => {},
+            BookMode::ChordsOnly => {
+                filename = add_to_file_stem(Path::new(&filename), "-chords").to_string_lossy().into_owned();
+            },
+            BookMode::Guitar => {
+                filename = add_to_file_stem(Path::new(&filename), "-guitar").to_string_lossy().into_owned();
+            },
+            BookMode::Numbers => {
+                filename = add_to_file_stem(Path::new(&filename), "-numbers").to_string_lossy().into_owned();
+            },
+            BookMode::Lyrics => {
+                let mut path = add_to_file_stem(Path::new(&filename), "-lyrics");
+                if path.extension().is_some_and(|ext| ext == "ly") {
+                    path.set_extension("md");
+                }
+                filename = path.to_string_lossy().into_owned();
+            },
+            BookMode::Braille => {
+                let mut path = PathBuf::from(&filename);
+                if path.extension().is_some_and(|ext| ext == "ly") {
+                    path.set_extension("brf");
+                }
+                filename = path.to_string_lossy().into_owned();
+            },
+        }
+
+        if let Some(name) = self.setlist.as_ref().and_then(|setlist| setlist.file_stem()) {
+            let suffix = format!("-{}", name.to_string_lossy());
+            filename = add_to_file_stem(Path::new(&filename), &suffix).to_string_lossy().into_owned();
+        }
+        if let Some(layout) = self.layout {
+            let suffix = format!("-{}", layout);
+            filename = add_to_file_stem(Path::new(&filename), &suffix).to_string_lossy().into_owned();
+        }
+
+        // a book that isn't split is its only volume
+        let path = match volume {
+            Some(volume) if !filename.contains("{volume}") => {
+                add_to_file_stem(Path::new(&filename), &format!("-vol{}", volume))
+            },
+            _ => PathBuf::from(filename.replace("{volume}", &volume.unwrap_or(1).to_string())),
+        };
+
+        self.out_dir.join(path)
+    }
+
+    // next to the concert book, e.g. `openbook-Concert-midi.ly`.
+    // always lilypond, even next to a lyrics book.
+    pub fn midi_path(&self) -> Result<PathBuf, TemplaterError> {
+        Ok(add_to_file_stem(&self.output_path(&transpose_text("c")?, None), "-midi").with_extension("ly"))
+    }
+
+    // a folder next to the book, e.g. `openbook-Bb-songs/`
+    pub fn sheets_dir(&self, transpose_text: &TransposeText) -> PathBuf {
+        add_to_file_stem(&self.output_path(transpose_text, None), "-songs").with_extension("")
+    }
+
+    // a fixed filename would be overwritten by every transposition
+    pub fn output_has_key(&self) -> bool {
+        self.output.as_ref().is_none_or(|output| output.to_string_lossy().contains("{key}"))
+    }
+
+    // e.g. `spellings.eb` for `--transpose eb`
+    pub fn spelling_for(&self, transpose: &str) -> Spelling {
+        let transpose = transpose.trim();
+        self.spellings
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(transpose))
+            .map_or(self.spelling, |(_, spelling)| *spelling)
+    }
+
+    // e.g. `ranges.eb`, or the built in one for `--transpose eb`
+    pub fn range_for(&self, transpose: &str) -> Option<Range> {
+        let transpose = transpose.trim();
+        self.ranges
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(transpose))
+            .map(|(_, range)| *range)
+            .or_else(|| Range::default_for(transpose))
+    }
+
+    pub fn writes_to_stdout(&self) -> bool {
+        self.output.as_deref() == Some(Path::new(STDOUT))
+    }
+}
+
+// config values given on the command line
+#[derive(Debug, Default)]
+pub struct ConfigOverrides {
+    pub transpose: Option<String>,
+    pub spelling: Option<Spelling>,
+    pub songs_dir: Option<PathBuf>,
+    // every --songs-dir after the first
+    pub more_songs_dirs: Vec<PathBuf>,
+    pub duplicate_titles: Option<DuplicateTitles>,
+    pub templates_dir: Option<PathBuf>,
+    pub out_dir: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub split_volumes: Option<usize>,
+    pub split_at: Option<Vec<String>>,
+    pub manuscript_pages: Option<usize>,
+    pub chord_grid_pages: Option<usize>,
+    pub lyrics: bool,
+    pub include_drafts: bool,
+    pub public_domain_only: bool,
+    pub folder_tags: bool,
+    pub mode: Option<BookMode>,
+    pub sort: Option<SortOrder>,
+    pub layout: Option<LayoutProfile>,
+    pub indexes: Option<Vec<IndexKind>>,
+    pub pdf: bool,
+    pub midi: bool,
+    pub html_index: bool,
+    pub per_song: bool,
+    pub setlist: Option<PathBuf>,
+    pub changes_since: Option<String>,
+    pub lilypond_flags: Option<Vec<String>>,
+    pub song_ids: Option<PathBuf>,
+    pub composer_aliases: Option<PathBuf>,
+    pub voicings: Option<PathBuf>,
+    pub transpositions: Option<PathBuf>,
+    pub metadata: Vec<MetadataValue>,
+    pub lint_ly: bool,
+    pub normalize_chords: bool,
+    pub strict: bool,
+    pub force: bool,
+    pub no_dividers: bool,
+    pub toc_form: bool,
+    pub form_summary: bool,
+    pub no_cache: bool,
+}
+
+// everything that differs between the books rendered
+// from the same set of songs
+#[derive(Debug, Clone)]
 pub struct TemplaterConfig {
     // e.g. If someone enters 'Bb', lilypond expects
     // 'd'. In the intro page I want to display the
     // user-specified key (e.g. "for Bb instruments")
     pub transpose_text: TransposeText,
+    // the same for every book, see `profiles.rs`
+    pub layout: Option<LayoutProfile>,
+}
+
+impl TemplaterConfig {
+    // bigger chords, lyrics, and spacing in every bookpart
+    pub fn large_print(&self) -> bool {
+        self.layout == Some(LayoutProfile::LargePrint)
+    }
 }
 
 // lilypond expects this format: "c c"
 // but we also want to show the key in the pdf
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash)]
 pub struct TransposeText {
     pub display_text: String,
     pub lilypond_text: String,
+    // e.g. "bass" for a bass clef book
+    pub clef: Option<String>,
+    // how a song's key is spelled in it, see `spelling.rs`
+    pub spelling: Spelling,
+}
+
+// one staff of notes. voices from a `--- voice: harmony ---`
+// section are named, the rest are the melody.
+#[derive(Clone, Debug, Hash)]
+pub struct Voice {
+    pub name: Option<String>,
+    pub notes: String,
+}
+
+// other changes for the same melody, from a `--- chords: Coltrane
+// changes ---` section. they're on their own chord staff, above
+// the song's chords.
+#[derive(Clone, Debug, Hash)]
+pub struct ChordVariant {
+    pub name: String,
+    pub chords: String,
+}
+
+// a block of lyrics. verses from a `--- verse: 2 ---` section
+// get a stanza number, the rest (e.g. the words for a repeat)
+// don't.
+#[derive(Clone, Debug, Hash)]
+pub struct Verse {
+    pub stanza: Option<String>,
+    pub text: String,
+}
+
+// `--- voice: harmony ---` splits into an extra part, " voice: harmony ",
+// that labels the part after it. returns (kind, name).
+pub(crate) fn section_label(part: &str) -> Option<(&str, &str)> {
+    if part.contains('\n') {
+        return None;
+    }
+
+    let (kind, name) = part.trim().split_once(':')?;
+    Some((kind.trim(), name.trim()))
 }
 
-#[derive(Clone,Debug)]
+// e.g. "1." for `verse: 1`, or "Chorus" for `verse: Chorus`
+fn stanza(name: &str) -> String {
+    match name.parse::<u32>() {
+        Ok(number) => format!("{}.", number),
+        Err(_) => name.to_string(),
+    }
+}
+
+// page layout from the front matter, to fix awkward page turns.
+// every song is its own `\bookpart`, so it starts on a new page.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LayoutHints {
+    // spread over exactly this many pages
+    pub pages: Option<u32>,
+    // a blank page before the song, e.g. so a two page song
+    // starts on a left page
+    pub break_before: bool,
+    // less space between systems, to fit on fewer pages
+    pub compress: bool,
+    // the systems aren't spread out to the bottom of the page
+    pub ragged: bool,
+}
+
+// a song in a file with more than one, e.g. a medley. they're
+// kept together in the book, where the first one would be.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FilePart {
+    pub first_title: String,
+    pub first_sort_title: String,
+    // 0 for the first song in the file
+    pub index: usize,
+}
+
+#[derive(Clone, Debug, Hash)]
 pub struct Song {
-    pub transpose_text: TransposeText,
+    pub path: PathBuf,
 
     // body
     pub chords: String,
-    pub voices: Vec<String>,
+    pub variants: Vec<ChordVariant>,
+    pub voices: Vec<Voice>,
     pub pre_staves: String,
-    pub lyrics: Vec<String>,
+    pub lyrics: Vec<Verse>,
     pub pre_section: String,
     pub post_section: String,
 
     // header
     pub arranger: Option<String>,
+    pub tempo: Option<Tempo>,
     pub composer: String,
+    // e.g. "parker, charlie", see `composers.rs`
+    pub composer_sort: String,
     //pub copyright: Option<String>,
     pub dedication: Option<String>,
     pub footer: Option<String>,
@@ -45,28 +629,245 @@ pub struct Song {
     pub subtitle: Option<String>,
     pub tagline: Option<String>,
     pub title: String,
+    // e.g. for `templater stats`, see `Frontmatter::year`
+    pub year: Option<u32>,
+    // what the songs are sorted by
+    pub sort_title: String,
+    // none if it's the only song in its file
+    pub file_part: Option<FilePart>,
+    // in concert pitch, see `keys.rs`
+    pub key: Option<Key>,
+    // the other keys the melody modulates to, in order
+    pub key_changes: Vec<Key>,
+    // e.g. bossa, ballad
+    pub tags: Vec<String>,
+    // `source` in the front matter, see `provenance.rs`
+    pub provenance: Option<Provenance>,
+    // `backing_track` in the front matter, see `backing.rs`
+    pub backing_track: Option<BackingTrack>,
+    // see `license.rs`
+    pub license: Option<String>,
+    pub pd_year: Option<u32>,
+    // as declared, see `form::song_form` for a guess too
+    pub form: Option<Form>,
+    // under the title with `--form-summary`, e.g. "AABA with D.S. al
+    // Coda", see `form::form_summary`
+    pub form_summary: Option<String>,
+    // of the melody, without the pickup. none without a melody
+    pub measures: Option<u64>,
+    // of the melody, the one most of it is in first, see
+    // `music::time_signatures`. empty without a melody
+    pub time_signatures: Vec<TimeSignature>,
+
+    pub transpose_override: Option<TransposeText>,
+    // every voice's, unless the transposition has one, see `clef.rs`
+    pub clef: Option<Clef>,
+    pub layout: LayoutHints,
+    // `status: draft`, see `Frontmatter::is_draft`
+    pub draft: bool,
+    // frontmatter keys the templater doesn't use, e.g. year
+    pub unknown_keys: Vec<String>,
+    // the songs dir it's from, if the book has more than one, see
+    // `anthology.rs`
+    pub collection: Option<String>,
+    // from songids.toml, assigned when the book is built
+    pub number: u32,
+    // when its file was first committed, e.g. "2024-03-01", only
+    // for `--sort date-added`, see `order.rs`
+    pub added: Option<String>,
 }
 
 impl Song {
-    pub fn new(front_matter: Vec<&str>, document: &str, transpose_text: TransposeText, include_lyrics: bool) -> Self {
-        let mut metadata = Song::parse_frontmatter(front_matter);
+    // e.g. within an index
+    pub fn cmp_title(&self, other: &Song) -> Ordering {
+        self.sort_title.cmp(&other.sort_title).then_with(|| self.title.cmp(&other.title))
+    }
+
+    // the order of the ToC and the bookparts: by title, but the
+    // songs of one file stay together, in the file's order
+    pub fn cmp_book_order(&self, other: &Song) -> Ordering {
+        let key = |song: &Song| match &song.file_part {
+            Some(part) => (part.first_sort_title.clone(), part.first_title.clone(), part.index),
+            None => (song.sort_title.clone(), song.title.clone(), 0),
+        };
+
+        key(self).cmp(&key(other)).then_with(|| self.path.cmp(&other.path))
+    }
+
+    // where it is in the book, the first song's in its file
+    pub fn book_sort_title(&self) -> &str {
+        match &self.file_part {
+            Some(part) => &part.first_sort_title,
+            None => &self.sort_title,
+        }
+    }
+
+    // its key and then every key it modulates to, none without a key
+    pub fn keys(&self) -> Vec<Key> {
+        self.key.into_iter().chain(self.key_changes.iter().copied()).collect()
+    }
+
+    // the middle of its tempo range, see `Tempo::bpm`
+    pub fn bpm(&self) -> Option<u32> {
+        self.tempo.as_ref().map(Tempo::bpm)
+    }
+
+    // the letter it's listed under, e.g. "G" for "The Girl from
+    // Ipanema". titles starting with a number are under "#".
+    pub fn letter(&self) -> String {
+        match self.book_sort_title().chars().next() {
+            Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+            _ => String::from("#"),
+        }
+    }
+
+    // parses a song file's contents. the path is only used for errors.
+    pub fn parse(path: &Path, input: &str, include_lyrics: bool) -> Result<Self, TemplaterError> {
+        Song::parse_with(path, input, include_lyrics, &Defaults::default(), &Presets::default())
+    }
+
+    // with `_defaults.yml` under the frontmatter, see `Defaults`, and
+    // the book's `transpose_override` presets
+    pub fn parse_with(
+        path: &Path,
+        input: &str,
+        include_lyrics: bool,
+        defaults: &Defaults,
+        presets: &Presets,
+    ) -> Result<Self, TemplaterError> {
+        let (front_matter, document) = frontmatter::split(input);
+        let front_matter = Frontmatter::parse_with(path, &front_matter, defaults)?;
+
+        Song::new(path, front_matter, document, include_lyrics, presets)
+    }
+
+    // every song in a file, see `frontmatter::split_songs`
+    pub fn parse_file(
+        path: &Path,
+        input: &str,
+        include_lyrics: bool,
+        defaults: &Defaults,
+        presets: &Presets,
+    ) -> Result<Vec<Self>, TemplaterError> {
+        let mut songs = frontmatter::split_songs(input)
+            .into_iter()
+            .map(|(_, input)| Song::parse_with(path, input, include_lyrics, defaults, presets))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if songs.len() > 1 {
+            let (first_title, first_sort_title) = (songs[0].title.clone(), songs[0].sort_title.clone());
+            for (index, song) in songs.iter_mut().enumerate() {
+                let (first_title, first_sort_title) = (first_title.clone(), first_sort_title.clone());
+                song.file_part = Some(FilePart { first_title, first_sort_title, index });
+            }
+        }
+
+        Ok(songs)
+    }
+
+    pub fn new(
+        path: &Path,
+        front_matter: Frontmatter,
+        document: &str,
+        include_lyrics: bool,
+        presets: &Presets,
+    ) -> Result<Self, TemplaterError> {
+        let transpose_override = match &front_matter.transpose_override {
+            Some(key) => Some(presets.transpose_text(key).map_err(|e| {
+                TemplaterError::Transposition(format!("{}: invalid transpose_override: {}", path.display(), e))
+            })?),
+            None => None,
+        };
+
+        let key = match &front_matter.key {
+            Some(key) => Some(
+                key.parse::<Key>()
+                    .map_err(|e| TemplaterError::from_str(&format!("{}: invalid key: {}", path.display(), e)))?,
+            ),
+            None => None,
+        };
+
+        let clef = match &front_matter.clef {
+            Some(clef) => Some(
+                clef.parse::<Clef>()
+                    .map_err(|e| TemplaterError::from_str(&format!("{}: invalid clef: {}", path.display(), e)))?,
+            ),
+            None => None,
+        };
+
+        let form = match &front_matter.form {
+            Some(form) => Some(
+                form.parse::<Form>()
+                    .map_err(|e| TemplaterError::from_str(&format!("{}: invalid form: {}", path.display(), e)))?,
+            ),
+            None => None,
+        };
+
+        let year = front_matter.year();
+        let draft = front_matter
+            .is_draft()
+            .map_err(|e| TemplaterError::from_str(&format!("{}: {}", path.display(), e)))?;
+        let composer = front_matter.composer.unwrap_or_else(|| String::from(UNKNOWN_COMPOSER));
         let parts = document.split("---").collect::<Vec<&str>>();
 
         let mut chords = String::new();
+        let mut variants = vec![];
         let mut voices = vec![];
         let mut lyrics = vec![];
         let mut pre_staves = String::new();
         let mut pre_section = String::new();
         let mut post_section = String::new();
 
+        let error = |message: &str| TemplaterError::from_str(&format!("{}: {}", path.display(), message));
+        let mut label = None;
         for part in parts {
+            if let Some((kind, name)) = section_label(part) {
+                match kind {
+                    "voice" | "verse" | "chords" if name.is_empty() => {
+                        return Err(error(&format!("a {} section needs a name", kind)))
+                    },
+                    "voice" if voices.iter().any(|v: &Voice| v.name.as_deref() == Some(name)) => {
+                        return Err(error(&format!("there's more than one voice named '{}'", name)))
+                    },
+                    "chords" if variants.iter().any(|v: &ChordVariant| v.name == name) => {
+                        return Err(error(&format!("there's more than one chords section named '{}'", name)))
+                    },
+                    "chords" if !front_matter.variants.iter().any(|variant| variant == name) => {
+                        return Err(error(&format!("the chords section '{}' isn't one of the song's variants", name)))
+                    },
+                    "voice" | "verse" | "chords" => {},
+                    _ => return Err(error(&format!("unknown section '{}'. Expected voice, verse, or chords.", kind))),
+                }
+
+                label = Some((kind, name));
+                continue;
+            }
+
+            match label.take() {
+                Some(("voice", name)) => {
+                    voices.push(Voice { name: Some(name.to_string()), notes: expand_markers(part) });
+                    continue;
+                },
+                Some(("chords", name)) => {
+                    variants.push(ChordVariant { name: name.to_string(), chords: expand_markers(part) });
+                    continue;
+                },
+                Some((_, name)) => {
+                    if include_lyrics {
+                        lyrics.push(Verse { stanza: Some(stanza(name)), text: part.to_string() });
+                    }
+                    continue;
+                },
+                None => {},
+            }
+
             if part.contains("chordmode") {
-                chords = part.to_string();
+                chords = expand_markers(part);
                 continue;
             }
 
             if part.contains("numericTimeSignature") {
-                voices.push(part.to_string());
+                voices.push(Voice { name: None, notes: expand_markers(part) });
                 continue;
             }
 
@@ -76,7 +877,7 @@ impl Song {
             }
 
             if part.contains("lyricmode") && include_lyrics {
-                lyrics.push(part.to_string());
+                lyrics.push(Verse { stanza: None, text: part.to_string() });
                 continue;
             }
 
@@ -91,134 +892,382 @@ impl Song {
             }
         }
 
-        Song {
-            transpose_text,
+        if let Some(variant) = front_matter.variants.iter().find(|name| !variants.iter().any(|v| &&v.name == name)) {
+            return Err(error(&format!("the variant '{}' needs a `--- chords: {} ---` section", variant, variant)));
+        }
+
+        let measures = voices.iter().find(|voice| voice.name.is_none()).map(|voice| count_bars(&voice.notes));
+        let time_signatures =
+            voices.iter().find(|voice| voice.name.is_none()).map(|voice| time_signatures(&voice.notes)).unwrap_or_default();
+
+        // the melody's `\key`, if the front matter doesn't say, and
+        // the other keys it changes to
+        let written_keys =
+            voices.iter().find(|voice| voice.name.is_none()).map(|voice| written_keys(&voice.notes)).unwrap_or_default();
+        let key = key.or_else(|| written_keys.first().copied());
+        let mut key_changes: Vec<Key> = vec![];
+        for written in written_keys {
+            if Some(written) != key && !key_changes.contains(&written) {
+                key_changes.push(written);
+            }
+        }
+
+        Ok(Song {
+            path: path.to_path_buf(),
 
             chords,
+            variants,
             voices,
             lyrics,
             pre_staves,
             pre_section,
             post_section,
 
-            title: metadata
-                .remove("title")
-                .unwrap_or_else(|| String::from("UNKNOWN TITLE")),
-            composer: metadata
-                .remove("composer")
-                .unwrap_or_else(|| String::from("UNKNOWN COMPOSER")),
-            arranger: metadata.remove("arranger"),
-            //copyright: metadata.remove("copyright"),
-            dedication: metadata.remove("dedication"),
-            footer: metadata.remove("footer"),
-            instrument: metadata.remove("instrument"),
-            meter: metadata.remove("meter"),
-            poet: metadata.remove("poet"),
-            subsubtitle: metadata.remove("subsubtitle"),
-            subtitle: metadata.remove("subtitle"),
-            tagline: metadata.remove("tagline"),
-            is_piano_staff: metadata.remove("pianostaff"),
-        }
-    }
-
-    fn parse_frontmatter(front_matter: Vec<&str>) -> HashMap<String, String> {
-        front_matter
-            .into_iter()
-            .map(|e| e.split(':').collect::<Vec<&str>>())
-            .fold(HashMap::new(), |mut acc, vec| {
-                acc.insert(vec[0].to_string(), vec[1].trim_start().to_string());
-                acc
-            })
+            sort_title: title_sort_key(front_matter.sort_title.as_deref().unwrap_or(&front_matter.title)),
+            title: front_matter.title,
+            file_part: None,
+            year,
+            composer_sort: composers::sort_key(&composer),
+            composer,
+            arranger: front_matter.arranger,
+            tempo: front_matter.bpm,
+            //copyright: front_matter.copyright,
+            dedication: front_matter.dedication,
+            footer: front_matter.footer,
+            instrument: front_matter.instrument,
+            meter: front_matter.meter,
+            poet: front_matter.poet,
+            subsubtitle: front_matter.subsubtitle,
+            subtitle: front_matter.subtitle,
+            tagline: front_matter.tagline,
+            tags: front_matter.tags,
+            provenance: front_matter.source.filter(|source| !source.is_empty()),
+            backing_track: front_matter.backing_track,
+            license: front_matter.license,
+            pd_year: front_matter.pd_year,
+            form,
+            form_summary: None,
+            measures,
+            time_signatures,
+            is_piano_staff: front_matter.pianostaff,
+            layout: LayoutHints {
+                pages: front_matter.pages,
+                break_before: front_matter.break_before,
+                compress: front_matter.compress,
+                ragged: front_matter.ragged,
+            },
+            draft,
+            unknown_keys: front_matter.extra.into_keys().collect(),
+
+            key,
+            key_changes,
+            transpose_override,
+            clef,
+            collection: None,
+            number: 0,
+            added: None,
+        })
     }
 
-    pub fn write(self, file: &mut File) {
-        let chords = crate::CHORDS_TEMPLATE
-            .get()
-            .unwrap()
-            .replace("%%TRANSPOSE%%", &self.transpose_text.lilypond_text)
-            .replace("%%CHORDS%%", &self.chords);
+    // used by `\label` so the indices can reference the song's page.
+    // it's the song's key, see `songids.rs`, with the letters of other
+    // alphabets as their code points, e.g. `u43a` for к, so it's an
+    // ascii scheme symbol
+    pub fn label(&self) -> String {
+        let key: String = song_key(self)
+            .chars()
+            .map(|c| match c.is_ascii() {
+                true => c.to_string(),
+                false => format!("u{:x}", c as u32),
+            })
+            .collect();
+        format!("song-{}", key)
+    }
 
-        let mut voices = String::new();
-        for voicepart in &self.voices {
-            let formatted_voice = crate::VOICE_TEMPLATE
-                .get()
-                .unwrap()
-                .replace("%%TRANSPOSE%%", &self.transpose_text.lilypond_text)
-                .replace("%%NOTES%%", voicepart);
-            voices.push_str(&formatted_voice);
+    // the book's transposition spelled for the song's key, see
+    // `spelling.rs`, unless the song has its own
+    pub fn transposition<'a>(&'a self, transpose_text: &'a TransposeText) -> Cow<'a, TransposeText> {
+        match (&self.transpose_override, &self.key) {
+            (Some(transpose_override), _) => Cow::Borrowed(transpose_override),
+            (None, Some(key)) => spell_for(transpose_text, key),
+            (None, None) => Cow::Borrowed(transpose_text),
         }
+    }
 
-        let mut lyrics = String::new();
-        for lyricspart in &self.lyrics {
-            let formatted_lyrics = crate::LYRICS_TEMPLATE
-                .get()
-                .unwrap()
-                .replace("%%LYRICS%%", lyricspart);
-            lyrics.push_str(&formatted_lyrics);
-        }
+    pub fn write<W: Write>(
+        &self,
+        out: &mut W,
+        templates: &Templates,
+        conf: &TemplaterConfig,
+    ) -> Result<(), TemplaterError> {
+        write!(out, "{}", self.render(templates, conf)?)?;
+        Ok(())
+    }
 
-        let poet = match self.poet {
-            Some(p) => String::from(p),
-            None => String::new(),
+    // the song's complete bookpart. `bookpart` includes the
+    // other song templates, so they all share these variables.
+    pub fn render(&self, templates: &Templates, conf: &TemplaterConfig) -> Result<String, TemplaterError> {
+        let transpose_text = &self.transposition(&conf.transpose_text);
+        let bookpart = template_for(templates, conf.layout, "bookpart");
+        let large_print = conf.large_print();
+        templates.render(&bookpart, context! { large_print, ..self.context(transpose_text) })
+    }
+
+    // the song's chord grid, for a chords-only book
+    pub fn render_chord_sheet(&self, templates: &Templates, conf: &TemplaterConfig) -> Result<String, TemplaterError> {
+        let transpose_text = &self.transposition(&conf.transpose_text);
+        templates.render("chord-sheet", self.context(transpose_text))
+    }
+
+    // the lead sheet with a fret diagram above every chord, for a guitar book
+    pub fn render_guitar(&self, templates: &Templates, conf: &TemplaterConfig) -> Result<String, TemplaterError> {
+        let transpose_text = &self.transposition(&conf.transpose_text);
+        let bookpart = template_for(templates, conf.layout, "bookpart");
+        let large_print = conf.large_print();
+        templates.render(&bookpart, context! { fretboards => true, large_print, ..self.context(transpose_text) })
+    }
+
+    // the song's changes as roman numerals, for a numbers chart. a
+    // song without a key gets its chord grid instead
+    pub fn render_numbers(&self, templates: &Templates, conf: &TemplaterConfig) -> Result<String, TemplaterError> {
+        let transpose_text = &self.transposition(&conf.transpose_text);
+        let Some(key) = self.key else {
+            return self.render_chord_sheet(templates, conf);
         };
 
-        let composerpoet = match &self.composer == &poet {
-            true => {
-                format!("composer = \"Music & Lyrics by {}\"", &self.composer)
+        let time = self.voices.iter().find(|voice| voice.name.is_none()).map_or(Fraction::ONE, |melody| {
+            time_signature(&melody.notes)
+        });
+        let (pickup, bars) = numbers_chart(&self.chords, time, &key.tonic);
+        let lines: Vec<Vec<String>> = bars.chunks(BARS_PER_LINE).map(<[String]>::to_vec).collect();
+
+        templates.render(
+            "numbers-sheet",
+            context! {
+                numbers_key => key.transposed(transpose_text).to_string(),
+                numbers_pickup => pickup.unwrap_or_default(),
+                numbers => lines,
+                bars_per_line => BARS_PER_LINE,
+                ..self.context(transpose_text)
             },
-            false => {
-                match &poet.is_empty() {
-                    true => {
-                        format!("composer = \"Music by {}\"\n", &self.composer)
-                    },
-                    false => {
-                        format!("composer = \"Music by {}\"\npoet = \"Lyrics by {}\"", &self.composer, &poet)
-                    }
-                }
-            }
+        )
+    }
+
+    // a `\book` that only has a `\midi` block, always in concert
+    // pitch so it can be played along to with any instrument
+    pub fn render_midi(&self, templates: &Templates) -> Result<String, TemplaterError> {
+        templates.render("midi", self.context(&transpose_text("c")?))
+    }
+
+    // e.g. `042-misty`, so the midi files sort like the book
+    pub fn midi_name(&self) -> String {
+        format!("{:03}-{}", self.number, song_key(self))
+    }
+
+    fn context(&self, transpose_text: &TransposeText) -> Value {
+        let field = |value: &Option<String>| value.clone().unwrap_or_default();
+
+        context! {
+            title => self.title.as_str(),
+            label => self.label(),
+            song_number => self.number,
+            composer => self.composer.as_str(),
+            poet => field(&self.poet),
+            arranger => field(&self.arranger),
+            dedication => field(&self.dedication),
+            footer => field(&self.footer),
+            instrument => field(&self.instrument),
+            meter => field(&self.meter),
+            subsubtitle => field(&self.subsubtitle),
+            form_summary => field(&self.form_summary),
+            subtitle => field(&self.subtitle),
+            tagline => field(&self.tagline),
+            bpm => self.bpm(),
+            tempo => self.tempo.as_ref().map(Tempo::context),
+            source => self.provenance.as_ref().map(Provenance::context),
+            backing_track => self.backing_track.as_ref().map(BackingTrack::context),
+            midi_name => self.midi_name(),
+
+            transpose => transpose_text.lilypond_text.as_str(),
+            transpose_display => capitalize_first_letter(&transpose_text.display_text),
+            clef => transpose_text
+                .clef
+                .as_deref()
+                .or(self.clef.as_ref().map(|clef| clef.name.as_str()))
+                .map(lilypond_clef)
+                .unwrap_or_default(),
+
+            pianostaff => self.is_piano_staff.is_some(),
+            layout => context! {
+                pages => self.layout.pages,
+                break_before => self.layout.break_before,
+                compress => self.layout.compress,
+                ragged => self.layout.ragged,
+            },
+            chords => polychords(&self.chords, &transpose_text.lilypond_text),
+            // only in a guitar book, see `render_guitar`
+            fretboards => false,
+            // only in a large-print book, see `render`
+            large_print => false,
+            variants => self
+                .variants
+                .iter()
+                .map(|variant| {
+                    let chords = polychords(&variant.chords, &transpose_text.lilypond_text);
+                    context! { name => variant.name.as_str(), chords }
+                })
+                .collect::<Vec<_>>(),
+            voices => self
+                .voices
+                .iter()
+                .map(|voice| context! { name => voice.name.clone().unwrap_or_default(), notes => voice.notes.as_str() })
+                .collect::<Vec<_>>(),
+            lyrics => self
+                .lyrics
+                .iter()
+                .map(|verse| context! { stanza => verse.stanza.clone().unwrap_or_default(), text => verse.text.as_str() })
+                .collect::<Vec<_>>(),
+            pre_staves => self.pre_staves.as_str(),
+            pre_section => self.pre_section.as_str(),
+            post_section => self.post_section.as_str(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_merges_args_over_config_file() {
+        let config: Config = toml::from_str("transpose = \"bb\"\nsongs_dir = \"./songs/jazz\"\n").unwrap();
+        let overrides = ConfigOverrides {
+            transpose: Some(String::from("eb")),
+            lyrics: true,
+            ..Default::default()
         };
+        let config = config.merge(overrides);
+
+        assert_eq!(config.transpose, "eb");
+        assert_eq!(config.songs_dir, PathBuf::from("./songs/jazz"));
+        assert_eq!(config.templates_dir, PathBuf::from("./templates"));
+        assert!(config.lyrics);
+    }
+
+    #[test]
+    fn it_spells_each_transposition_by_name() {
+        let config: Config = toml::from_str("spelling = \"sharps\"\n[spellings]\nEb = \"flats\"\n").unwrap();
+        assert_eq!(config.spelling_for("eb"), Spelling::Flats);
+        assert_eq!(config.spelling_for("bb"), Spelling::Sharps);
 
-        let song_header = crate::SONG_HEADER_TEMPLATE
-            .get()
-            .unwrap()
-            .replace("%%TITLE%%", &self.title)
-            .replace("%%COMPOSERPOET%%", &composerpoet)
-            .replace("%%ARRANGER%%", &self.arranger.unwrap_or_default())
-            .replace(
-                "%%COPYRIGHT%%",
-                &capitalize_first_letter_ascii(&self.transpose_text.display_text),
-            )
-            .replace("%%DEDICATION%%", &self.dedication.unwrap_or_default())
-            .replace("%%INSTRUMENT%%", &self.instrument.unwrap_or_default())
-            .replace("%%METER%%", &self.meter.unwrap_or_default())
-            .replace("%%SUBSUBTITLE%%", &self.subsubtitle.unwrap_or_default())
-            .replace("%%SUBTITLE%%", &self.subtitle.unwrap_or_default())
-            .replace("%%TAGLINE%%", &self.tagline.unwrap_or_default());
-
-        let pianostaff = match &self.is_piano_staff {
-            Some(_) => String::from("\\new PianoStaff"),
-            None => String::new(),
+        let config = config.merge(ConfigOverrides { spelling: Some(Spelling::AsWritten), ..Default::default() });
+        assert_eq!(config.spelling_for("eb"), Spelling::AsWritten);
+    }
+
+    #[test]
+    fn it_reads_each_transpositions_range() {
+        let config: Config = toml::from_str("[ranges]\nbb = \"fis c'''\"\n").unwrap();
+        assert_eq!(config.range_for("bb").map(|range| range.to_string()).as_deref(), Some("fis to c'''"));
+        assert_eq!(config.range_for("eb"), Range::default_for("eb"));
+        assert_eq!(config.range_for("c"), None);
+        assert!(toml::from_str::<Config>("[ranges]\nbb = \"c'''\"\n").is_err());
+    }
+
+    #[test]
+    fn it_fills_in_the_output_pattern() {
+        let bb = transpose_text("bb").unwrap();
+        let config = Config { out_dir: PathBuf::from("build"), ..Default::default() };
+        assert_eq!(config.output_path(&bb, None), PathBuf::from("build/openbook-Bb.ly"));
+        assert_eq!(config.output_path(&bb, Some(2)), PathBuf::from("build/openbook-Bb-vol2.ly"));
+        assert!(config.output_has_key());
+
+        let config = Config { output: Some(PathBuf::from("book-{key}-{date}.ly")), ..Default::default() };
+        assert_eq!(config.output_path(&bb, None), PathBuf::from(format!("./book-Bb-{}.ly", today())));
+
+        let config = Config { output: Some(PathBuf::from("vol{volume}/book-{key}.ly")), ..Default::default() };
+        assert_eq!(config.output_path(&bb, Some(1)), PathBuf::from("./vol1/book-Bb.ly"));
+
+        let config = Config { output: Some(PathBuf::from("book.ly")), ..Default::default() };
+        assert!(!config.output_has_key());
+
+        let config = Config { mode: BookMode::Lyrics, ..Default::default() };
+        assert_eq!(config.output_path(&bb, None), PathBuf::from("./openbook-Bb-lyrics.md"));
+        let config = Config { mode: BookMode::Lyrics, output: Some(PathBuf::from("words.tex")), ..Default::default() };
+        assert_eq!(config.output_path(&bb, None), PathBuf::from("./words-lyrics.tex"));
+        let config = Config { mode: BookMode::Braille, ..Default::default() };
+        assert_eq!(config.output_path(&bb, Some(2)), PathBuf::from("./openbook-Bb-vol2.brf"));
+
+        let config = Config { mode: BookMode::Guitar, layout: Some(LayoutProfile::LargePrint), ..Default::default() };
+        assert_eq!(config.output_path(&bb, Some(2)), PathBuf::from("./openbook-Bb-guitar-large-print-vol2.ly"));
+
+        let config = Config { mode: BookMode::ChordsOnly, output: Some(PathBuf::from("-")), ..Default::default() };
+        assert!(config.writes_to_stdout());
+        assert_eq!(config.output_path(&bb, Some(2)), PathBuf::from("-"));
+    }
+
+    #[test]
+    fn it_lists_songs_under_their_sort_letter() {
+        let letter = |input: &str| Song::parse(Path::new("test.ly"), input, false).unwrap().letter();
+
+        assert_eq!(letter("title: The Girl from Ipanema"), "G");
+        assert_eq!(letter("title: 2 AM"), "#");
+        assert_eq!(letter("title: (Meet) The Flintstones"), "M");
+        assert_eq!(letter("title: Água de Beber"), "A");
+        assert_eq!(letter("title: Ölüdeniz"), "O");
+    }
+
+    #[test]
+    fn it_keeps_the_songs_of_one_file_together() {
+        let parse = |path: &str, input: &str| {
+            Song::parse_file(Path::new(path), input, false, &Defaults::default(), &Presets::default()).unwrap()
         };
 
-        let song_body = crate::SONG_BODY_TEMPLATE
-            .get()
-            .unwrap()
-            .replace("%%CHORDS%%", &chords)
-            .replace("%%VOICES%%", &voices)
-            .replace("%%PIANOSTAFF%%", &pianostaff)
-            .replace("%%LYRICS%%", &lyrics)
-            .replace("%%PRE_STAVES%%", &self.pre_staves)
-            .replace("%%PRE_SECTION%%", &self.pre_section)
-            .replace("%%POST_SECTION%%", &self.post_section);
-
-        let toc_title = format!("{} - {}", self.title, self.composer);
-        let bookpart = crate::BOOKPART_TEMPLATE
-            .get()
-            .unwrap()
-            .replace("%%TITLE%%", &toc_title)
-            .replace("%%SONG_HEADER%%", &song_header)
-            .replace("%%SONG_BODY%%", &song_body);
-
-        write!(file, "{}", bookpart).unwrap();
+        let mut songs = parse("medley.ly", "title: Misty\n---\n===\ntitle: Body and Soul\n---\n");
+        songs.extend(parse("autumn-leaves.ly", "title: Autumn Leaves"));
+        songs.extend(parse("naima.ly", "title: Naima"));
+        songs.sort_by(|a, b| a.cmp_book_order(b));
+
+        let titles: Vec<&str> = songs.iter().map(|song| song.title.as_str()).collect();
+        assert_eq!(titles, vec!["Autumn Leaves", "Misty", "Body and Soul", "Naima"]);
+        assert_eq!(songs[2].letter(), "M");
+        assert_eq!(songs[0].file_part, None);
+    }
+
+    #[test]
+    fn it_reads_chord_variants() {
+        let input = "title: Giant Steps\nvariants: [Coltrane changes]\n---\n\\chordmode { c1 }\n--- chords: Coltrane changes ---\n\\chordmode { c2 ees:7 }\n";
+        let song = Song::parse(Path::new("test.ly"), input, false).unwrap();
+
+        assert_eq!(song.variants.len(), 1);
+        assert_eq!(song.variants[0].name, "Coltrane changes");
+        assert!(song.variants[0].chords.contains("ees:7"));
+        assert!(song.chords.contains("c1"));
+
+        assert!(Song::parse(Path::new("test.ly"), &input.replace("variants: [Coltrane changes]\n", ""), false).is_err());
+        assert!(Song::parse(Path::new("test.ly"), "title: Giant Steps\nvariants: Tritone subs\n---\n", false).is_err());
+    }
+
+    #[test]
+    fn it_parses_named_voices() {
+        let input = "title: Duet\n---\n\\relative c' { \\numericTimeSignature c4 }\n--- voice: harmony ---\n\\relative c' { a4 }\n";
+        let song = Song::parse(Path::new("duet.ly"), input, false).unwrap();
+
+        let names: Vec<Option<&str>> = song.voices.iter().map(|v| v.name.as_deref()).collect();
+        assert_eq!(names, vec![None, Some("harmony")]);
+        assert!(song.voices[1].notes.contains("a4"));
+
+        let input = format!("{}--- voice: harmony ---\n{{ b4 }}\n", input);
+        assert!(Song::parse(Path::new("duet.ly"), &input, false).is_err());
+    }
+
+    #[test]
+    fn it_numbers_labeled_verses() {
+        let input = "title: Test\n---\n\\lyricmode { a b }\n--- verse: 2 ---\n\\lyricmode { c d }\n--- verse: Chorus ---\n{ e }\n";
+        let song = Song::parse(Path::new("test.ly"), input, true).unwrap();
+
+        let stanzas: Vec<Option<&str>> = song.lyrics.iter().map(|v| v.stanza.as_deref()).collect();
+        assert_eq!(stanzas, vec![None, Some("2."), Some("Chorus")]);
+
+        assert!(Song::parse(Path::new("test.ly"), input, false).unwrap().lyrics.is_empty());
     }
 }
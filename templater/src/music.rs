@@ -0,0 +1,779 @@
+// a rough reader for a voice's lilypond: the notes and rests with
+// their durations, time signatures, pickups, and bar checks. it
+// doesn't know every command, just enough to count measures, e.g.
+// for `templater stats`. pitches are kept as they're written.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::{Add, Mul, Sub};
+use std::str::CharIndices;
+
+// a duration in whole notes, e.g. 3/8 for a dotted quarter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fraction {
+    pub num: u64,
+    pub den: u64,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    match b {
+        0 => a.max(1),
+        _ => gcd(b, a % b),
+    }
+}
+
+impl Fraction {
+    pub const ZERO: Fraction = Fraction { num: 0, den: 1 };
+    pub const ONE: Fraction = Fraction { num: 1, den: 1 };
+
+    pub fn new(num: u64, den: u64) -> Self {
+        let divisor = gcd(num, den);
+        Fraction { num: num / divisor, den: den.max(1) / divisor }
+    }
+}
+
+impl Default for Fraction {
+    fn default() -> Self {
+        Fraction::ZERO
+    }
+}
+
+impl Add for Fraction {
+    type Output = Fraction;
+
+    fn add(self, other: Fraction) -> Fraction {
+        Fraction::new(self.num * other.den + other.num * self.den, self.den * other.den)
+    }
+}
+
+// zero if `other` is longer
+impl Sub for Fraction {
+    type Output = Fraction;
+
+    fn sub(self, other: Fraction) -> Fraction {
+        Fraction::new((self.num * other.den).saturating_sub(other.num * self.den), self.den * other.den)
+    }
+}
+
+impl Mul for Fraction {
+    type Output = Fraction;
+
+    fn mul(self, other: Fraction) -> Fraction {
+        Fraction::new(self.num * other.num, self.den * other.den)
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Fraction) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Fraction) -> Ordering {
+        (self.num * other.den).cmp(&(other.num * self.den))
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    // a note, a chord, a rest, or a skip. `pitch` is `None` for rests.
+    Note { pitch: Option<String>, duration: Fraction, line: usize },
+    // `\time 3/4`
+    Time { beats: u64, unit: u64, line: usize },
+    // `\partial 4`
+    Partial { duration: Fraction, line: usize },
+    // `|`
+    BarCheck { line: usize },
+    // `~`, after the note it ties to the next one
+    Tie { line: usize },
+    // `(` and `)`, after the note they start or end on. `\melisma`
+    // and `\melismaEnd` are the same, for lyrics.
+    Slur { start: bool, line: usize },
+    // e.g. ":m7/c" of "f2:m7/c", after the chord it's part of
+    Modifier { text: String, line: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    Word(String),
+    Open,
+    Close,
+    ChordOpen,
+    ChordClose,
+    BarCheck,
+    // a string or a scheme value
+    Literal,
+}
+
+// (token, line, end), where `end` is the byte just after the token.
+// comments are left out.
+pub(crate) fn tokenize(input: &str) -> Vec<(Token, usize, usize)> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+    let mut line = 1;
+    let mut in_chord = false;
+
+    let end = |chars: &mut Peekable<CharIndices>| chars.peek().map_or(input.len(), |(i, _)| *i);
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {},
+            '%' if matches!(chars.peek(), Some((_, '{'))) => {
+                let mut previous = ' ';
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if previous == '%' && c == '}' {
+                        break;
+                    }
+                    previous = c;
+                }
+            },
+            '%' => while chars.next_if(|(_, c)| *c != '\n').is_some() {},
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            chars.next();
+                        },
+                        '\n' => line += 1,
+                        _ => {},
+                    }
+                }
+                tokens.push((Token::Literal, line, end(&mut chars)));
+            },
+            // `#'(a b)`, `#(...)`, `#.33`, `#t`
+            '#' => {
+                let mut depth = 0;
+                while let Some((_, c)) = chars.next_if(|(_, c)| depth > 0 || !c.is_whitespace() && !"{}".contains(*c)) {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        '\n' => line += 1,
+                        _ => {},
+                    }
+                }
+                tokens.push((Token::Literal, line, end(&mut chars)));
+            },
+            '{' => tokens.push((Token::Open, line, end(&mut chars))),
+            '}' => tokens.push((Token::Close, line, end(&mut chars))),
+            '|' => tokens.push((Token::BarCheck, line, end(&mut chars))),
+            // `<<` and `>>` are simultaneous music, not chords
+            '<' if chars.next_if(|(_, c)| *c == '<').is_some() => {},
+            '>' if chars.next_if(|(_, c)| *c == '>').is_some() => {},
+            '<' => {
+                in_chord = true;
+                tokens.push((Token::ChordOpen, line, end(&mut chars)));
+            },
+            '>' if in_chord => {
+                in_chord = false;
+                tokens.push((Token::ChordClose, line, end(&mut chars)));
+            },
+            c => {
+                let mut word = String::from(c);
+                loop {
+                    match chars.peek().map(|(_, c)| c) {
+                        Some(c) if c.is_whitespace() || "{}|\"%<".contains(*c) => break,
+                        // the next note of a tie, e.g. `b8.~b8.`
+                        Some(c) if word.len() > 1 && word.ends_with('~') && c.is_ascii_alphabetic() => break,
+                        // the end of a chord, unless it's an accent, e.g. `c->`
+                        Some('>') if in_chord && !word.ends_with(['-', '^', '_', '\\']) => break,
+                        Some(c) => {
+                            word.push(*c);
+                            chars.next();
+                        },
+                        None => break,
+                    }
+                }
+                tokens.push((Token::Word(word), line, end(&mut chars)));
+            },
+        }
+    }
+
+    tokens
+}
+
+// `\repeat unfold 4 { c1:7 }` is printed 4 times, so it's the same
+// as writing it out. a volta is only written once.
+fn unfold(tokens: Vec<(Token, usize, usize)>) -> Vec<(Token, usize, usize)> {
+    let mut unfolded = vec![];
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let times = match tokens[i..].get(..4) {
+            Some([(Token::Word(repeat), ..), (Token::Word(kind), ..), (Token::Word(times), ..), (Token::Open, ..)])
+                if repeat == "\\repeat" && kind == "unfold" =>
+            {
+                times.parse::<usize>().ok()
+            },
+            _ => None,
+        };
+        let Some(times) = times else {
+            unfolded.push(tokens[i].clone());
+            i += 1;
+            continue;
+        };
+
+        // the block, with its braces
+        let start = i + 3;
+        let mut depth = 0;
+        let mut end = start;
+        while let Some((token, ..)) = tokens.get(end) {
+            end += 1;
+            match token {
+                Token::Open => depth += 1,
+                Token::Close if depth == 1 => break,
+                Token::Close => depth -= 1,
+                _ => {},
+            }
+        }
+
+        let block = unfold(tokens[start..end].to_vec());
+        for _ in 0..times {
+            unfolded.extend(block.iter().cloned());
+        }
+        i = end;
+    }
+
+    unfolded
+}
+
+// e.g. "4", "8.", "2*3", "1*3/4". `None` if it's not a duration.
+pub fn parse_duration(s: &str) -> Option<Fraction> {
+    let (base, multiplier) = match s.split_once('*') {
+        Some((base, multiplier)) => (base, Some(multiplier)),
+        None => (s, None),
+    };
+    let dots = base.len() - base.trim_end_matches('.').len();
+    let base: u64 = base.trim_end_matches('.').parse().ok()?;
+    if base == 0 || !base.is_power_of_two() {
+        return None;
+    }
+
+    // each dot adds half of the previous value
+    let mut duration = Fraction::new(1, base);
+    let mut dot = duration;
+    for _ in 0..dots {
+        dot = dot * Fraction::new(1, 2);
+        duration = duration + dot;
+    }
+
+    if let Some(multiplier) = multiplier {
+        let (num, den) = multiplier.split_once('/').unwrap_or((multiplier, "1"));
+        duration = duration * Fraction::new(num.parse().ok()?, den.parse().ok()?);
+    }
+
+    Some(duration)
+}
+
+// e.g. "bes'4.->" is ("bes'", Some("4.")), "r8" is ("r", Some("8")),
+// "c" is ("c", None). `None` if the word isn't a note or rest.
+fn split_note(word: &str) -> Option<(&str, Option<&str>)> {
+    let first = word.chars().next()?;
+    let mut end = match first {
+        'a'..='g' => {
+            let mut rest = &word[1..];
+            loop {
+                if let Some(r) = rest.strip_prefix("is").or_else(|| rest.strip_prefix("es")) {
+                    rest = r;
+                } else if let Some(r) = rest.strip_prefix('s').filter(|_| first == 'a' || first == 'e') {
+                    rest = r;
+                } else {
+                    break;
+                }
+            }
+            word.len() - rest.len()
+        },
+        'r' | 'R' | 's' | 'q' => 1,
+        _ => return None,
+    };
+
+    // e.g. "break" or "bass"
+    if word[end..].starts_with(|c: char| c.is_alphabetic()) {
+        return None;
+    }
+
+    end += word[end..].len() - word[end..].trim_start_matches(['\'', ',', '!', '?', '=']).len();
+    let pitch = &word[..end];
+
+    // a `/` is only part of a duration after a `*`, e.g. "1*3/4", and
+    // otherwise a chord's bass note, e.g. "g2/b"
+    let rest = &word[end..];
+    let digits = |s: &str, other: char| s.find(|c: char| !c.is_ascii_digit() && c != other).unwrap_or(s.len());
+    let mut len = digits(rest, '.');
+    if rest[len..].starts_with('*') {
+        len += 1 + digits(&rest[len + 1..], '/');
+    }
+    let duration = Some(&rest[..len]).filter(|d| !d.is_empty());
+
+    Some((pitch, duration))
+}
+
+fn is_pitch(pitch: &str) -> bool {
+    pitch.starts_with(|c: char| ('a'..='g').contains(&c))
+}
+
+// the ties and slurs after a note, e.g. "~" of "b1~" or "(" of
+// "c4->(". `\(` is a phrasing slur, which isn't a melisma.
+fn marks(rest: &str, line: usize, events: &mut Vec<Event>) {
+    let mut previous = ' ';
+    for c in rest.chars() {
+        match c {
+            '~' => events.push(Event::Tie { line }),
+            '(' if previous != '\\' => events.push(Event::Slur { start: true, line }),
+            ')' if previous != '\\' => events.push(Event::Slur { start: false, line }),
+            _ => {},
+        }
+        previous = c;
+    }
+}
+
+// every note, rest, and time signature, in order. repeats are only
+// counted as they're written (an unfold is written out), and grace
+// notes not at all.
+pub fn events(input: &str) -> Vec<Event> {
+    spanned_events(input).into_iter().map(|(event, _)| event).collect()
+}
+
+// (event, end) for every event, where `end` is the byte in `input`
+// just after what it was read from, e.g. after the "~" of "c4~". an
+// unfold's events are there once for every time it's played.
+pub fn spanned_events(input: &str) -> Vec<(Event, usize)> {
+    let tokens = unfold(tokenize(input));
+    let mut events = vec![];
+    let mut ends = vec![];
+
+    let mut last_duration = Fraction::new(1, 4);
+    // the tuplet or grace scale of every open `{`
+    let mut scales = vec![Fraction::ONE];
+    // for the next `{` or note, e.g. after `\tuplet 3/2`
+    let mut pending_scale: Option<Fraction> = None;
+
+    let word = |i: usize| match tokens.get(i) {
+        Some((Token::Word(word), ..)) => Some(word.as_str()),
+        _ => None,
+    };
+
+    // every event so far was read from the tokens before `i`
+    let end = |i: usize| i.checked_sub(1).map_or(0, |i| tokens[i].2);
+    let mut i = 0;
+    while let Some((token, line, _)) = tokens.get(i) {
+        ends.resize(events.len(), end(i));
+        let line = *line;
+        i += 1;
+        let scale = *scales.last().unwrap_or(&Fraction::ONE);
+
+        match token {
+            Token::Open => scales.push(pending_scale.take().map_or(scale, |pending| scale * pending)),
+            Token::Close => {
+                if scales.len() > 1 {
+                    scales.pop();
+                }
+            },
+            Token::BarCheck => events.push(Event::BarCheck { line }),
+            Token::Literal | Token::ChordClose => {},
+            Token::ChordOpen => {
+                let mut pitch = None;
+                while let Some((token, ..)) = tokens.get(i) {
+                    i += 1;
+                    match token {
+                        Token::ChordClose => break,
+                        Token::Word(word) if pitch.is_none() => {
+                            pitch = split_note(word).map(|(pitch, _)| pitch.to_string());
+                        },
+                        _ => {},
+                    }
+                }
+
+                // e.g. "4~" after `<c e>`, or "~" for the last duration
+                let after = word(i).and_then(|word| {
+                    let len = word.find(|c: char| !c.is_ascii_digit() && !"./*".contains(c)).unwrap_or(word.len());
+                    let duration = parse_duration(&word[..len]);
+                    let marked = len == 0 && word.starts_with(['~', '(', ')']);
+                    (duration.is_some() || marked).then(|| (duration, &word[len..]))
+                });
+                if let Some((duration, _)) = after {
+                    if let Some(duration) = duration {
+                        last_duration = duration;
+                    }
+                    i += 1;
+                }
+
+                let scale = pending_scale.take().map_or(scale, |pending| scale * pending);
+                events.push(Event::Note { pitch, duration: last_duration * scale, line });
+                if let Some((_, rest)) = after {
+                    marks(rest, line, &mut events);
+                }
+            },
+            Token::Word(command) if command.starts_with('\\') => match command.as_str() {
+                "\\time" => {
+                    if let Some((beats, unit)) = word(i).and_then(|word| word.split_once('/')) {
+                        if let (Ok(beats), Ok(unit)) = (beats.parse(), unit.parse()) {
+                            events.push(Event::Time { beats, unit, line });
+                        }
+                        i += 1;
+                    }
+                },
+                "\\partial" => {
+                    if let Some(duration) = word(i).and_then(parse_duration) {
+                        events.push(Event::Partial { duration, line });
+                        i += 1;
+                    }
+                },
+                // `\tuplet 3/2 { }` is 2/3 of the written durations,
+                // `\times 2/3 { }` the same
+                "\\tuplet" | "\\times" => {
+                    if let Some((num, den)) = word(i).and_then(|word| word.split_once('/')) {
+                        if let (Ok(num), Ok(den)) = (num.parse::<u64>(), den.parse::<u64>()) {
+                            pending_scale = Some(match command.as_str() {
+                                "\\tuplet" => Fraction::new(den, num),
+                                _ => Fraction::new(num, den),
+                            });
+                        }
+                        i += 1;
+                        // `\tuplet 3/2 4 { }` groups the tuplets by quarters
+                        if command == "\\tuplet" && word(i).and_then(parse_duration).is_some() {
+                            i += 1;
+                        }
+                    }
+                },
+                "\\melisma" => events.push(Event::Slur { start: true, line }),
+                "\\melismaEnd" => events.push(Event::Slur { start: false, line }),
+                "\\grace" | "\\acciaccatura" | "\\appoggiatura" | "\\slashedGrace" => {
+                    pending_scale = Some(Fraction::ZERO);
+                },
+                // a skip is as long as its duration
+                "\\skip" => {
+                    if let Some(duration) = word(i).and_then(parse_duration) {
+                        last_duration = duration;
+                        events.push(Event::Note { pitch: None, duration: duration * scale, line });
+                        i += 1;
+                    }
+                },
+                "\\key" | "\\transpose" | "\\repeat" => i += 2,
+                "\\clef" | "\\bar" | "\\mark" | "\\boxMark" => i += 1,
+                "\\relative" | "\\fixed" if word(i).is_some() => i += 1,
+                // `\tempo 4 = 120`, `\tempo "Slowly" 4 = 60`
+                "\\tempo" => {
+                    if let Some((Token::Literal, ..)) = tokens.get(i) {
+                        i += 1;
+                    }
+                    if word(i).and_then(parse_duration).is_some() {
+                        i += 3;
+                    }
+                },
+                // `\set Score.x = y`, `\override Staff.x = #y`
+                "\\set" | "\\override" => {
+                    while let Some((Token::Word(_) | Token::Literal, ..)) = tokens.get(i) {
+                        i += 1;
+                        if word(i - 1).is_some_and(|word| word.ends_with('=')) {
+                            break;
+                        }
+                    }
+                    if let Some((Token::Word(_) | Token::Literal, ..)) = tokens.get(i) {
+                        i += 1;
+                    }
+                },
+                // a markup's braces aren't music
+                "\\markup" => {
+                    if let Some((Token::Open, ..)) = tokens.get(i) {
+                        let mut depth = 0;
+                        while let Some((token, ..)) = tokens.get(i) {
+                            i += 1;
+                            match token {
+                                Token::Open => depth += 1,
+                                Token::Close if depth == 1 => break,
+                                Token::Close => depth -= 1,
+                                _ => {},
+                            }
+                        }
+                    } else {
+                        i += 1;
+                    }
+                },
+                _ => {},
+            },
+            Token::Word(word) => {
+                // e.g. the "~" of "c4 ~ c4" or "c4 ~c4"
+                let note = word.trim_start_matches(['~', '(', ')']);
+                marks(&word[..word.len() - note.len()], line, &mut events);
+                let word = note;
+
+                let Some((pitch, duration)) = split_note(word) else { continue };
+                if let Some(duration) = duration.and_then(parse_duration) {
+                    last_duration = duration;
+                }
+
+                let scale = pending_scale.take().map_or(scale, |pending| scale * pending);
+                let rest = &word[pitch.len() + duration.map_or(0, str::len)..];
+                let pitch = is_pitch(pitch).then(|| pitch.to_string());
+                events.push(Event::Note { pitch, duration: last_duration * scale, line });
+                let modifier = &rest[..rest.find(['~', '(', ')']).unwrap_or(rest.len())];
+                if modifier.starts_with([':', '/']) {
+                    events.push(Event::Modifier { text: modifier.to_string(), line });
+                }
+                marks(rest, line, &mut events);
+            },
+        }
+    }
+    ends.resize(events.len(), end(i));
+
+    events.into_iter().zip(ends).collect()
+}
+
+// how many measures the voice is long, as written. a pickup
+// counts as a measure of its own, and so does a short last one.
+pub fn count_measures(input: &str) -> u64 {
+    let mut measure = Fraction::ONE;
+    // how far into the current measure
+    let mut position = Fraction::ZERO;
+    let mut measures = 0;
+
+    for event in events(input) {
+        match event {
+            Event::Time { beats, unit, .. } => measure = Fraction::new(beats, unit),
+            // the pickup is the end of a measure
+            Event::Partial { duration, .. } => position = measure - duration,
+            Event::Note { duration, .. } => {
+                position = position + duration;
+                while measure.num > 0 && position >= measure {
+                    position = position - measure;
+                    measures += 1;
+                }
+            },
+            Event::BarCheck { .. } | Event::Tie { .. } | Event::Slur { .. } | Event::Modifier { .. } => {},
+        }
+    }
+
+    match position {
+        Fraction::ZERO => measures,
+        _ => measures + 1,
+    }
+}
+
+// the bars lilypond numbers, i.e. not counting a pickup
+pub fn count_bars(input: &str) -> u64 {
+    let pickup = events(input)
+        .iter()
+        .find(|event| matches!(event, Event::Note { .. } | Event::Partial { .. }))
+        .is_some_and(|event| matches!(event, Event::Partial { .. }));
+
+    count_measures(input).saturating_sub(pickup as u64)
+}
+
+// the pitch sounding at the start of every bar after the pickup,
+// e.g. the roots of a `\chordmode`, `None` for a rest. `time` is
+// the time signature until there's a `\time`, e.g. the melody's
+// for the chords.
+pub fn downbeats(input: &str, time: Fraction) -> Vec<Option<String>> {
+    let mut downbeats = vec![];
+    let mut measure = time;
+    let mut position = Fraction::ZERO;
+
+    for event in events(input) {
+        match event {
+            Event::Time { beats, unit, .. } => measure = Fraction::new(beats, unit),
+            Event::Partial { duration, .. } => position = measure - duration,
+            Event::Note { pitch, duration, .. } if measure.num > 0 => {
+                // a note can be held over more than one bar
+                let mut left = duration;
+                while left > Fraction::ZERO {
+                    if position == Fraction::ZERO {
+                        downbeats.push(pitch.clone());
+                    }
+
+                    let rest_of_bar = measure - position;
+                    if left < rest_of_bar {
+                        position = position + left;
+                        break;
+                    }
+                    left = left - rest_of_bar;
+                    position = Fraction::ZERO;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    downbeats
+}
+
+// the first `\time`, 4/4 if there isn't one
+pub fn time_signature(input: &str) -> Fraction {
+    events(input)
+        .into_iter()
+        .find_map(|event| match event {
+            Event::Time { beats, unit, .. } => Some(Fraction::new(beats, unit)),
+            _ => None,
+        })
+        .unwrap_or(Fraction::ONE)
+}
+
+// a `\time` the way it's written, since 4/4 isn't the same meter as 2/2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimeSignature {
+    pub beats: u64,
+    pub unit: u64,
+}
+
+impl TimeSignature {
+    // lilypond's, until there's a `\time`
+    pub const COMMON: TimeSignature = TimeSignature { beats: 4, unit: 4 };
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.beats, self.unit)
+    }
+}
+
+// every time signature the voice changes to, the one most of its
+// measures are in first, and then the others in the order they
+// come in. a voice without a `\time` is in 4/4.
+pub fn time_signatures(input: &str) -> Vec<TimeSignature> {
+    // (time signature, measures in it)
+    let mut counts: Vec<(TimeSignature, u64)> = vec![];
+    let mut time = TimeSignature::COMMON;
+    let mut measure = Fraction::ONE;
+    let mut position = Fraction::ZERO;
+
+    let count = |counts: &mut Vec<(TimeSignature, u64)>, time: TimeSignature| {
+        match counts.iter_mut().find(|(counted, _)| *counted == time) {
+            Some((_, measures)) => *measures += 1,
+            None => counts.push((time, 1)),
+        }
+    };
+    for event in events(input) {
+        match event {
+            Event::Time { beats, unit, .. } => {
+                time = TimeSignature { beats, unit };
+                measure = Fraction::new(beats, unit);
+            },
+            Event::Partial { duration, .. } => position = measure - duration,
+            Event::Note { duration, .. } => {
+                position = position + duration;
+                while measure.num > 0 && position >= measure {
+                    position = position - measure;
+                    count(&mut counts, time);
+                }
+            },
+            _ => {},
+        }
+    }
+    if position > Fraction::ZERO {
+        count(&mut counts, time);
+    }
+
+    // a stable sort keeps the order they come in
+    counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+    counts.into_iter().map(|(time, _)| time).collect()
+}
+
+// a note that's sung on a new syllable of `\lyricsto`, i.e. not a
+// rest, a grace note, or the rest of a tie or slur
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SungNote {
+    // lilypond's bar number, a pickup is measure 0
+    pub measure: u64,
+    pub line: usize,
+}
+
+pub fn sung_notes(input: &str) -> Vec<SungNote> {
+    let mut notes = vec![];
+    let mut measure = Fraction::ONE;
+    let mut position = Fraction::ZERO;
+    let mut number = 1;
+    let mut tied = false;
+    // open slurs and `\melisma`s
+    let mut slurs = 0_usize;
+
+    for event in events(input) {
+        match event {
+            Event::Time { beats, unit, .. } => measure = Fraction::new(beats, unit),
+            Event::Partial { duration, .. } => {
+                if number == 1 && position == Fraction::ZERO && notes.is_empty() {
+                    number = 0;
+                }
+                position = measure - duration;
+            },
+            Event::Note { pitch, duration, line } => {
+                if duration == Fraction::ZERO {
+                    continue;
+                }
+                if pitch.is_some() && !tied && slurs == 0 {
+                    notes.push(SungNote { measure: number, line });
+                }
+                tied = false;
+
+                position = position + duration;
+                while measure.num > 0 && position >= measure {
+                    position = position - measure;
+                    number += 1;
+                }
+            },
+            Event::Tie { .. } => tied = true,
+            Event::Slur { start: true, .. } => slurs += 1,
+            Event::Slur { start: false, .. } => slurs = slurs.saturating_sub(1),
+            Event::BarCheck { .. } | Event::Modifier { .. } => {},
+        }
+    }
+
+    notes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_durations() {
+        assert_eq!(parse_duration("4."), Some(Fraction::new(3, 8)));
+        assert_eq!(parse_duration("1*3/4"), Some(Fraction::new(3, 4)));
+        assert_eq!(parse_duration("2*3"), Some(Fraction::new(3, 2)));
+        assert_eq!(parse_duration("3"), None);
+    }
+
+    #[test]
+    fn it_counts_measures() {
+        let melody = "\\key a \\major\n\\time 4/4\n\\partial 2 r8 ais'8 b'8 cis''8\n\\boxMark \"A\"\n\
+                      d''4. eis'8~ eis'4 cis''4~ | % a comment 1 1 1\n\
+                      cis''8 d''16 cis''16 ais'8 b'8 \\tuplet 3/2 { d''8 cis''8 b'8 } ais'8 b'8 |\n\
+                      \\grace { c16 d } <e' g'>1-> | \\time 3/4 R2. | \\set Score.x = #'(1 2) s2. \\bar \"|.\"";
+
+        assert_eq!(count_measures(melody), 6);
+        assert_eq!(count_measures("c4 d e"), 1);
+        assert_eq!(count_measures("b2.~b2 c2"), 2);
+        assert_eq!(count_measures(""), 0);
+        // a slash chord's or polychord's duration is before the `/`
+        assert_eq!(count_measures("c2/e f2:7/+a | g1*2:7/b | d2//c:7 c1*1/2"), 4);
+    }
+
+    #[test]
+    fn it_finds_every_time_signature() {
+        let time = |beats, unit| TimeSignature { beats, unit };
+        let melody = "\\time 3/4 \\partial 4 c4 | c2. | c2. | \\time 4/4 c1 | \\time 3/4 c2. | \\time 6/8 c4. c4.";
+
+        assert_eq!(time_signatures(melody), vec![time(3, 4), time(4, 4), time(6, 8)]);
+        assert_eq!(time_signatures("c1 | \\time 2/2 c1 | c1"), vec![time(2, 2), time(4, 4)]);
+        assert!(time_signatures("").is_empty());
+    }
+
+    #[test]
+    fn it_finds_the_notes_lyrics_are_sung_on() {
+        let melody = "\\time 3/4 \\partial 4 d'4 | g'2.~ | g'4 a'8( b'8 a'4) |\
+                      r4 \\grace c''8 b'4 \\melisma c''4 \\melismaEnd | <d' g'>2. ~<d' g'>2.";
+        let measures: Vec<u64> = sung_notes(melody).iter().map(|note| note.measure).collect();
+
+        assert_eq!(measures, vec![0, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,671 @@
+// `templater import song.musicxml`: converts a MusicXML file, e.g.
+// exported from MuseScore, into a song file. the title, composer,
+// lyricist, tempo, and tempo text go in the front matter, the first
+// part's harmonies in the `\chordmode` section, and its first voice
+// in absolute pitch as the melody. lyrics, dynamics, and articulations
+// aren't imported, and neither are compressed `.mxl` files. see
+// `abc.rs` for `templater import tune.abc`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::abc;
+use crate::errors::TemplaterError;
+use crate::keys::Key;
+use crate::models::Config;
+use crate::scaffold::create_song_file;
+use crate::timing::{self, ChordLine};
+use crate::transpose::Pitch;
+use crate::utils::{read_file, slugify, yaml_string};
+use crate::xml::{self, Element};
+
+const STEPS: [&str; 7] = ["C", "D", "E", "F", "G", "A", "B"];
+
+// `import --format abc`, from the file's extension without one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    MusicXml,
+    Abc,
+}
+
+impl ImportFormat {
+    pub fn from_path(path: &Path) -> ImportFormat {
+        match path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("abc")) {
+            true => ImportFormat::Abc,
+            false => ImportFormat::MusicXml,
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "musicxml" | "xml" => Ok(ImportFormat::MusicXml),
+            "abc" => Ok(ImportFormat::Abc),
+            _ => Err(TemplaterError::from_str(&format!("Unknown import format '{}'. Expected musicxml or abc.", s))),
+        }
+    }
+}
+
+// a song converted from MusicXML or ABC, see `to_song_file`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportedSong {
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub composer: Option<String>,
+    pub poet: Option<String>,
+    // the first tempo text, e.g. "Medium Swing"
+    pub meter: Option<String>,
+    pub bpm: Option<u32>,
+    pub chords: Vec<String>,
+    pub melody: Vec<String>,
+    // `\key`, `\time`, and `\partial` before the first measure
+    pub header: Vec<String>,
+}
+
+impl ImportedSong {
+    // the frontmatter, then the chords and melody one measure per line
+    pub fn to_song_file(&self) -> String {
+        let mut front_matter = vec![format!("title: {}", yaml_string(&self.title))];
+        let fields = [
+            ("subtitle", &self.subtitle),
+            ("composer", &self.composer),
+            ("poet", &self.poet),
+            ("meter", &self.meter),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                front_matter.push(format!("{}: {}", field, yaml_string(value)));
+            }
+        }
+        if let Some(bpm) = self.bpm {
+            front_matter.push(format!("bpm: {}", bpm));
+        }
+
+        let indent = |lines: &[String]| lines.iter().map(|line| format!("  {}\n", line)).collect::<String>();
+
+        format!(
+            "{}\n---\n\\chordmode {{\n{}}}\n---\n{}\n\\numericTimeSignature\n\n{}",
+            front_matter.join("\n"),
+            indent(&self.chords),
+            self.header.join("\n"),
+            self.melody.iter().map(|line| format!("{}\n", line)).collect::<String>(),
+        )
+    }
+}
+
+// e.g. `songs/blue-bossa.ly`, never overwriting an existing song.
+// returns the new files' paths, one for every tune in an abc file.
+// every path is checked before any is written, so a tune that can't
+// be imported doesn't leave the ones before it behind
+pub fn import_song(
+    config: &Config,
+    path: &Path,
+    format: Option<ImportFormat>,
+) -> Result<Vec<PathBuf>, TemplaterError> {
+    let songs = import_file(path, format)?;
+
+    let mut paths: Vec<PathBuf> = vec![];
+    for song in &songs {
+        let slug = slugify(&song.title);
+        if slug.is_empty() {
+            return Err(TemplaterError::from_str(&format!(
+                "{}: '{}' can't be a filename.",
+                path.display(),
+                song.title
+            )));
+        }
+
+        let song_path = config.songs_dir.join(format!("{}.ly", slug));
+        if paths.contains(&song_path) {
+            return Err(TemplaterError::from_str(&format!(
+                "{}: more than one tune would be imported to {}, retitle one of them.",
+                path.display(),
+                song_path.display()
+            )));
+        }
+        if song_path.exists() {
+            return Err(TemplaterError::from_str(&format!("{} already exists.", song_path.display())));
+        }
+        paths.push(song_path);
+    }
+
+    // e.g. the first song of a new book
+    let songs_dir = &config.songs_dir;
+    fs::create_dir_all(songs_dir).map_err(|e| TemplaterError::file(songs_dir, e))?;
+    for (song, song_path) in songs.iter().zip(&paths) {
+        create_song_file(song_path, &song.to_song_file())?;
+    }
+
+    Ok(paths)
+}
+
+pub fn import_file(path: &Path, format: Option<ImportFormat>) -> Result<Vec<ImportedSong>, TemplaterError> {
+    let format = format.unwrap_or_else(|| ImportFormat::from_path(path));
+    if format == ImportFormat::MusicXml && path.extension().is_some_and(|ext| ext == "mxl") {
+        return Err(TemplaterError::from_str(&format!(
+            "{}: compressed .mxl files aren't supported, export an uncompressed .musicxml file instead.",
+            path.display()
+        )));
+    }
+
+    let input = read_file(path)?;
+    let songs = match format {
+        ImportFormat::MusicXml => import(&input).map(|song| vec![song]),
+        ImportFormat::Abc => abc::import(&input),
+    };
+    songs.map_err(|e| TemplaterError::from_str(&format!("{}: {}", path.display(), e)))
+}
+
+pub fn import(input: &str) -> Result<ImportedSong, TemplaterError> {
+    let root = xml::parse(input)?;
+    if root.name != "score-partwise" {
+        return Err(TemplaterError::from_str(&format!(
+            "expected a <score-partwise> MusicXML file, got <{}>.",
+            root.name
+        )));
+    }
+
+    let creator = |kind: &str| {
+        root.find("identification")
+            .and_then(|identification| identification.children("creator").find(|c| c.attr("type") == Some(kind)))
+            .map(Element::text)
+            .filter(|name| !name.is_empty())
+    };
+
+    let title = root
+        .text_at("work/work-title")
+        .or_else(|| root.text_at("movement-title"))
+        .ok_or_else(|| TemplaterError::from_str("the file has no <work-title> or <movement-title>."))?;
+    let part = root.child("part").ok_or_else(|| TemplaterError::from_str("the file has no <part>."))?;
+
+    let mut song = ImportedSong {
+        title,
+        composer: creator("composer"),
+        poet: creator("lyricist").or_else(|| creator("poet")),
+        ..Default::default()
+    };
+    PartReader::default().read(part, &mut song)?;
+
+    Ok(song)
+}
+
+// a note's or chord's length, in `divisions` per quarter note
+struct Length {
+    divisions: u32,
+    length: u32,
+}
+
+impl Length {
+    // e.g. `4.`, or `4*5/2` if no note value fits
+    fn lilypond(&self) -> String {
+        let whole = 4 * self.divisions;
+        for den in [1, 2, 4, 8, 16, 32, 64] {
+            for dots in 0..=2 {
+                // whole/den * (2 - 1/2^dots)
+                if self.length * den * (1 << dots) == whole * ((1 << (dots + 1)) - 1) {
+                    return format!("{}{}", den, ".".repeat(dots as usize));
+                }
+            }
+        }
+
+        let (mut num, mut den) = (self.length, self.divisions);
+        let gcd = gcd(num, den);
+        num /= gcd;
+        den /= gcd;
+        match den {
+            1 => format!("4*{}", num),
+            _ => format!("4*{}/{}", num, den),
+        }
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    match b {
+        0 => a.max(1),
+        _ => gcd(b, a % b),
+    }
+}
+
+// `<type>quarter</type>` and its dots
+fn note_type(note: &Element) -> Option<String> {
+    let value = match note.text_at("type")?.as_str() {
+        "breve" => "\\breve",
+        "whole" => "1",
+        "half" => "2",
+        "quarter" => "4",
+        "eighth" => "8",
+        "16th" => "16",
+        "32nd" => "32",
+        "64th" => "64",
+        "128th" => "128",
+        _ => return None,
+    };
+
+    Some(format!("{}{}", value, ".".repeat(note.children("dot").count())))
+}
+
+fn number<T: std::str::FromStr>(element: &Element, path: &str) -> Option<T> {
+    element.text_at(path)?.parse().ok()
+}
+
+// `<step>`, `<alter>`, and `<octave>`, MusicXML's octave 4 is `c'`
+fn pitch(element: &Element, prefix: &str) -> Option<Pitch> {
+    let step = STEPS.iter().position(|step| Some(*step) == element.text_at(&format!("{}step", prefix)).as_deref())?;
+    let alteration = number::<f32>(element, &format!("{}alter", prefix)).unwrap_or_default().round() as i32;
+    let octave = number::<i32>(element, &format!("{}octave", prefix)).map_or(0, |octave| octave - 3);
+
+    Some(Pitch { step, alteration, octave })
+}
+
+// `<fifths>-3</fifths><mode>minor</mode>` is c minor
+fn key(element: &Element) -> Option<Key> {
+    let fifths: i32 = number(element, "fifths")?;
+    let mode = element.text_at("mode").unwrap_or_else(|| String::from("major"));
+    // the tonic's distance in fifths from a major key's tonic
+    let offset = match mode.as_str() {
+        "major" | "ionian" => 0,
+        "minor" | "aeolian" => 3,
+        "dorian" => 2,
+        "phrygian" => 4,
+        "lydian" => -1,
+        "mixolydian" => 1,
+        "locrian" => 5,
+        _ => return None,
+    };
+
+    // f c g d a e b, then sharps or flats
+    let n = fifths + offset + 1;
+    let step = [3, 0, 4, 1, 5, 2, 6][n.rem_euclid(7) as usize];
+    let tonic = Pitch { step, alteration: n.div_euclid(7), octave: 0 };
+    format!("{} {}", tonic.lilypond_name(), mode).parse().ok()
+}
+
+// `<sound tempo="112.5"/>`
+fn tempo(sound: &Element) -> Option<u32> {
+    let tempo: f32 = sound.attr("tempo")?.parse().ok()?;
+    Some(tempo.round() as u32)
+}
+
+// (musicxml's `<kind>`, the chordmode quality), e.g. a minor seventh
+// is `:m7`. also what `templater lsp` completes a chord with
+pub const CHORD_KINDS: [(&str, &str); 25] = [
+    ("major", ""),
+    ("minor", "m"),
+    ("augmented", "aug"),
+    ("diminished", "dim"),
+    ("dominant", "7"),
+    ("major-seventh", "maj7"),
+    ("minor-seventh", "m7"),
+    ("diminished-seventh", "dim7"),
+    ("augmented-seventh", "aug7"),
+    ("half-diminished", "m7.5-"),
+    ("major-minor", "m7+"),
+    ("major-sixth", "6"),
+    ("minor-sixth", "m6"),
+    ("dominant-ninth", "9"),
+    ("major-ninth", "maj9"),
+    ("minor-ninth", "m9"),
+    ("dominant-11th", "11"),
+    ("major-11th", "maj11"),
+    ("minor-11th", "m11"),
+    ("dominant-13th", "13"),
+    ("major-13th", "maj13"),
+    ("minor-13th", "m13"),
+    ("suspended-second", "sus2"),
+    ("suspended-fourth", "sus4"),
+    ("power", "5"),
+];
+
+// `<harmony>` as a chordmode chord without its duration, e.g.
+// ("bes", ":m7/f")
+fn harmony(element: &Element) -> Option<(String, String)> {
+    let root = pitch(element, "root/root-")?;
+    let kind = element.text_at("kind").unwrap_or_else(|| String::from("major"));
+    // "none" is N.C.
+    let (_, quality) = CHORD_KINDS.iter().find(|(name, _)| *name == kind)?;
+    let mut quality = quality.to_string();
+
+    // e.g. a dominant with an added flat 9 is `:7.9-`
+    for degree in element.children("degree") {
+        let (Some(value), Some(kind)) = (degree.text_at("degree-value"), degree.text_at("degree-type")) else {
+            continue;
+        };
+        let alter = number::<i32>(degree, "degree-alter").unwrap_or_default();
+        let accidental = match alter {
+            a if a < 0 => "-",
+            a if a > 0 => "+",
+            _ => "",
+        };
+        let separator = if quality.is_empty() { "" } else { "." };
+        match kind.as_str() {
+            "subtract" => quality.push_str(&format!("^{}", value)),
+            _ => quality.push_str(&format!("{}{}{}", separator, value, accidental)),
+        }
+    }
+
+    let mut suffix = match quality.is_empty() {
+        true => String::new(),
+        false => format!(":{}", quality),
+    };
+    if let Some(bass) = pitch(element, "bass/bass-") {
+        suffix.push_str(&format!("/{}", Pitch { octave: 0, ..bass }.lilypond_name()));
+    }
+
+    Some((Pitch { octave: 0, ..root }.lilypond_name(), suffix))
+}
+
+// the state carried from one measure to the next
+#[derive(Default)]
+struct PartReader {
+    divisions: u32,
+    // e.g. (3, 4) for 3/4
+    time: (u32, u32),
+    // the melody, the first voice seen
+    voice: Option<String>,
+    chord_line: ChordLine,
+}
+
+impl PartReader {
+    fn read(&mut self, part: &Element, song: &mut ImportedSong) -> Result<(), TemplaterError> {
+        self.divisions = 1;
+        self.time = (4, 4);
+
+        for (i, measure) in part.children("measure").enumerate() {
+            let mut melody = vec![];
+            // (offset, root, suffix)
+            let mut chords = vec![];
+            let mut cursor: u32 = 0;
+            let mut measure_length: u32 = 0;
+            let mut in_tuplet = false;
+            // e.g. `%% "A"`, on their own line above the chords
+            let mut marks = vec![];
+
+            for element in measure.elements() {
+                match element.name.as_str() {
+                    "attributes" => {
+                        if let Some(divisions) = number(element, "divisions") {
+                            self.divisions = divisions;
+                        }
+                        let key = element.child("key").and_then(key);
+                        let key = key.map(|key| format!("\\key {}", key.lilypond_name()));
+                        let time = element
+                            .child("time")
+                            .and_then(|time| Some((number(time, "beats")?, number(time, "beat-type")?)));
+                        if let Some(time) = time {
+                            self.time = time;
+                        }
+                        let time = time.map(|(beats, beat_type)| format!("\\time {}/{}", beats, beat_type));
+
+                        let changes = key.into_iter().chain(time);
+                        match i == 0 && cursor == 0 {
+                            true => song.header.extend(changes),
+                            false => melody.extend(changes),
+                        }
+                    },
+                    "direction" => {
+                        if let Some(mark) = element.text_at("direction-type/rehearsal") {
+                            melody.push(format!("\\boxMark \"{}\"", mark.replace('"', "")));
+                            marks.push(format!("%% \"{}\"", mark));
+                        }
+                        if song.meter.is_none() && i == 0 {
+                            song.meter = element.text_at("direction-type/words");
+                        }
+                        if song.bpm.is_none() {
+                            song.bpm = element.child("sound").and_then(tempo);
+                        }
+                    },
+                    "sound" if song.bpm.is_none() => song.bpm = tempo(element),
+                    "harmony" => {
+                        let offset = number::<i32>(element, "offset").unwrap_or_default();
+                        let at = (cursor as i32 + offset).max(0) as u32;
+                        match harmony(element) {
+                            Some((root, suffix)) => chords.push((at, root, suffix)),
+                            None => chords.push((at, String::from("r"), String::new())),
+                        }
+                    },
+                    "backup" => cursor = cursor.saturating_sub(number(element, "duration").unwrap_or_default()),
+                    "forward" => {
+                        cursor += number::<u32>(element, "duration").unwrap_or_default();
+                        measure_length = measure_length.max(cursor);
+                    },
+                    "note" => self.note(element, &mut melody, &mut cursor, &mut in_tuplet),
+                    "barline" => {
+                        let repeat = element.child("repeat").and_then(|repeat| repeat.attr("direction"));
+                        let bar = match (repeat, element.text_at("bar-style").as_deref()) {
+                            (Some("forward"), _) => Some(".|:"),
+                            (Some("backward"), _) => Some(":|."),
+                            (_, Some("light-heavy")) => Some("|."),
+                            (_, Some("light-light")) => Some("||"),
+                            _ => None,
+                        };
+                        if let Some(bar) = bar {
+                            melody.push(format!("\\bar \"{}\"", bar));
+                        }
+                    },
+                    _ => {},
+                }
+                measure_length = measure_length.max(cursor);
+            }
+
+            let full_measure = 4 * self.divisions * self.time.0 / self.time.1.max(1);
+            let implicit = measure.attr("implicit") == Some("yes");
+            let (measure_length, pickup) = timing::measure_length(measure_length, full_measure, implicit);
+            let divisions = self.divisions;
+            let duration = |length| Length { divisions, length }.lilypond();
+            if pickup {
+                let partial = format!("\\partial {}", duration(measure_length));
+                match i {
+                    0 => song.header.push(partial),
+                    _ => melody.insert(0, partial),
+                }
+            }
+
+            song.chords.extend(marks);
+            song.chords.push(self.chord_line.measure(chords, measure_length, pickup, duration));
+            melody.push(String::from("|"));
+            song.melody.push(melody.join(" "));
+        }
+
+        Ok(())
+    }
+
+    fn note(&mut self, note: &Element, melody: &mut Vec<String>, cursor: &mut u32, in_tuplet: &mut bool) {
+        let is_chord_tone = note.has("chord");
+        let is_grace = note.has("grace");
+        let duration: u32 = number(note, "duration").unwrap_or_default();
+
+        if !is_chord_tone && !is_grace {
+            *cursor += duration;
+        }
+
+        let voice = note.text_at("voice").unwrap_or_else(|| String::from("1"));
+        if self.voice.get_or_insert_with(|| voice.clone()) != &voice {
+            return;
+        }
+
+        let pitch = match note.has("rest") {
+            true => None,
+            false => pitch(note, "pitch/"),
+        };
+        let pitch_name = pitch.map(|pitch| pitch.lilypond_name());
+
+        // `<c' e'>4`, the chord tones join the note before them
+        if is_chord_tone {
+            if let (Some(last), Some(pitch_name)) = (melody.last_mut(), pitch_name) {
+                let (grace, note) = match last.strip_prefix("\\grace ") {
+                    Some(note) => ("\\grace ", note),
+                    None => ("", last.as_str()),
+                };
+                if let Some(i) = note.find(|c: char| c.is_ascii_digit()) {
+                    let (pitches, rest) = note.split_at(i);
+                    let pitches = pitches.trim_start_matches('<').trim_end_matches('>');
+                    *last = format!("{}<{} {}>{}", grace, pitches, pitch_name, rest);
+                }
+            }
+            return;
+        }
+
+        let length = match note_type(note) {
+            Some(length) => length,
+            None if note.has("rest") && note.find("rest").and_then(|rest| rest.attr("measure")) == Some("yes") => {
+                let (beats, beat_type) = self.time;
+                let rest = match beats == beat_type {
+                    true => String::from("R1"),
+                    false => format!("R1*{}/{}", beats, beat_type),
+                };
+                melody.push(rest);
+                return;
+            },
+            None => Length { divisions: self.divisions, length: duration }.lilypond(),
+        };
+
+        let notations = note.child("notations");
+        let tuplet = |kind: &str| {
+            notations.is_some_and(|notations| notations.children("tuplet").any(|t| t.attr("type") == Some(kind)))
+        };
+        if tuplet("start") && !*in_tuplet {
+            let actual: u32 = number(note, "time-modification/actual-notes").unwrap_or(3);
+            let normal: u32 = number(note, "time-modification/normal-notes").unwrap_or(2);
+            melody.push(format!("\\tuplet {}/{} {{", actual, normal));
+            *in_tuplet = true;
+        }
+
+        let mut token = match pitch_name {
+            Some(pitch_name) => format!("{}{}", pitch_name, length),
+            None => format!("r{}", length),
+        };
+        if is_grace {
+            token = format!("\\grace {}", token);
+        }
+        if note.children("tie").any(|tie| tie.attr("type") == Some("start")) {
+            token.push('~');
+        }
+        melody.push(token);
+
+        if tuplet("stop") && *in_tuplet {
+            melody.push(String::from("}"));
+            *in_tuplet = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Song;
+
+    const SONG: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+<score-partwise version="4.0">
+  <work><work-title>Blue Bossa</work-title></work>
+  <identification><creator type="composer">Kenny Dorham</creator></identification>
+  <part-list><score-part id="P1"><part-name>Melody</part-name></score-part></part-list>
+  <part id="P1">
+    <measure number="0" implicit="yes">
+      <attributes>
+        <divisions>2</divisions>
+        <key><fifths>-3</fifths><mode>minor</mode></key>
+        <time><beats>4</beats><beat-type>4</beat-type></time>
+      </attributes>
+      <direction><direction-type><words>Bossa Nova</words></direction-type><sound tempo="150"/></direction>
+      <note><pitch><step>G</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>quarter</type></note>
+    </measure>
+    <measure number="1">
+      <direction><direction-type><rehearsal>A</rehearsal></direction-type></direction>
+      <harmony><root><root-step>C</root-step></root><kind>minor-seventh</kind></harmony>
+      <note><pitch><step>G</step><octave>5</octave></pitch><duration>3</duration><voice>1</voice><type>quarter</type><dot/></note>
+      <note><pitch><step>F</step><octave>5</octave></pitch><duration>1</duration><voice>1</voice><type>eighth</type></note>
+      <note><pitch><step>E</step><alter>-1</alter><octave>5</octave></pitch><duration>2</duration><voice>1</voice><type>quarter</type></note>
+      <note><pitch><step>D</step><octave>5</octave></pitch><duration>2</duration><voice>1</voice><type>quarter</type><notations><tied type="start"/></notations><tie type="start"/></note>
+      <backup><duration>8</duration></backup>
+      <note><pitch><step>C</step><octave>3</octave></pitch><duration>8</duration><voice>2</voice><type>whole</type></note>
+    </measure>
+    <measure number="2">
+      <harmony><root><root-step>F</root-step></root><kind>minor-seventh</kind></harmony>
+      <note><pitch><step>D</step><octave>5</octave></pitch><duration>4</duration><voice>1</voice><type>half</type><tie type="stop"/></note>
+      <harmony><root><root-step>G</root-step></root><kind>dominant</kind><degree><degree-value>9</degree-value><degree-alter>-1</degree-alter><degree-type>add</degree-type></degree></harmony>
+      <note><rest/><duration>2</duration><voice>1</voice><type>quarter</type></note>
+      <note><pitch><step>B</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>quarter</type></note>
+      <note><chord/><pitch><step>D</step><octave>5</octave></pitch><duration>2</duration><voice>1</voice><type>quarter</type></note>
+    </measure>
+    <measure number="3">
+      <note><rest measure="yes"/><duration>8</duration><voice>1</voice></note>
+      <barline location="right"><bar-style>light-heavy</bar-style></barline>
+    </measure>
+  </part>
+</score-partwise>
+"#;
+
+    #[test]
+    fn it_converts_lengths() {
+        assert_eq!(Length { divisions: 2, length: 3 }.lilypond(), "4.");
+        assert_eq!(Length { divisions: 4, length: 16 }.lilypond(), "1");
+        assert_eq!(Length { divisions: 1, length: 5 }.lilypond(), "4*5");
+        assert_eq!(Length { divisions: 4, length: 5 }.lilypond(), "4*5/4");
+    }
+
+    #[test]
+    fn it_imports_musicxml() {
+        let song = import(SONG).unwrap();
+
+        assert_eq!(song.title, "Blue Bossa");
+        assert_eq!(song.composer.as_deref(), Some("Kenny Dorham"));
+        assert_eq!(song.meter.as_deref(), Some("Bossa Nova"));
+        assert_eq!(song.bpm, Some(150));
+        assert_eq!(song.header, vec!["\\key c \\minor", "\\time 4/4", "\\partial 4"]);
+        assert_eq!(
+            song.melody,
+            vec![
+                "g'4 |",
+                "\\boxMark \"A\" g''4. f''8 ees''4 d''4~ |",
+                "d''2 r4 <b' d''>4 |",
+                "R1 \\bar \"|.\" |",
+            ]
+        );
+        assert_eq!(
+            song.chords,
+            vec!["\\partial 4 s4 |", "%% \"A\"", "c1:m7 |", "f2:m7 g2:7.9- |", "g1:7.9- |"]
+        );
+    }
+
+    #[test]
+    fn it_writes_a_song_file() {
+        let file = import(SONG).unwrap().to_song_file();
+        let song = Song::parse(Path::new("blue-bossa.ly"), &file, false).unwrap();
+
+        assert_eq!(song.title, "Blue Bossa");
+        assert_eq!(song.bpm(), Some(150));
+        assert_eq!(song.key.map(|key| key.to_string()).as_deref(), Some("C minor"));
+        assert!(song.chords.contains("c1:m7 |"));
+        assert_eq!(song.voices.len(), 1);
+    }
+
+    #[test]
+    fn it_imports_every_tune_or_none() {
+        let dir = std::env::temp_dir().join(format!("templater-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config { songs_dir: dir.join("songs"), ..Config::default() };
+        let abc = |name: &str, titles: &[&str]| {
+            let tunes: Vec<String> = titles.iter().map(|title| format!("X:1\nT:{}\nK:C\nCDEF|\n", title)).collect();
+            fs::write(dir.join(name), tunes.join("\n")).unwrap();
+            dir.join(name)
+        };
+
+        // the songs dir doesn't exist yet
+        let paths = import_song(&config, &abc("one.abc", &["One"]), None).unwrap();
+        assert_eq!(paths, [config.songs_dir.join("one.ly")]);
+
+        let e = import_song(&config, &abc("two.abc", &["Two", "One"]), None).err().unwrap();
+        assert_eq!(e.to_string(), format!("{} already exists.", config.songs_dir.join("one.ly").display()));
+        let e = import_song(&config, &abc("three.abc", &["Three", "Three!"]), None).err().unwrap();
+        assert!(e.to_string().contains("more than one tune would be imported to"), "{}", e);
+        assert!(!config.songs_dir.join("two.ly").exists());
+        assert!(!config.songs_dir.join("three.ly").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
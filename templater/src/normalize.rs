@@ -0,0 +1,388 @@
+// `templater normalize`: rewrites the bar checks of every song's
+// chords and voices from their durations, so every contributed tune
+// has a `|` at every bar line and nowhere else, and a diff between
+// two versions of it lines up by measure.
+//
+// a `|` that isn't at a bar line is removed, and so is a second one
+// at the same bar line. a bar line without one gets one after the
+// note (and its tie) that ends the measure, or after the `}`s that
+// close there, e.g. `\tuplet 3/2 { c8 d e } | f1`. the last bar line,
+// and one that's followed by `\alternative` or another block, are
+// left as they are. music with `<< >>` isn't normalized, since its
+// parts are counted one after the other, and neither is a voice whose
+// first bar check is off because it's missing its `\partial`, or a
+// `\repeat unfold` that's counted differently each time it's played.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use similar::TextDiff;
+
+use crate::errors::TemplaterError;
+use crate::frontmatter;
+use crate::models::section_label;
+use crate::music::{spanned_events, Event, Fraction, TimeSignature};
+use crate::pickup::lilypond_duration;
+use crate::utils::read_file;
+
+// what's done at a byte of the music, the same every time it's played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    // not a bar line, or the last one
+    Keep,
+    // a bar line with a `|` after it
+    Checked,
+    // a `|` after the bar line that ends here
+    Insert,
+    // the `|` that ends here
+    Remove,
+}
+
+// how many bar checks were added and removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl Changes {
+    fn add(&mut self, other: Changes) {
+        self.added += other.added;
+        self.removed += other.removed;
+    }
+}
+
+// where the `|` after a bar line at `at` goes, none if it can't
+fn bar_check_at(music: &str, at: usize) -> Option<usize> {
+    let mut end = at;
+    for (i, c) in music[at..].char_indices() {
+        match c {
+            '}' => end = at + i + 1,
+            c if c.is_whitespace() => {},
+            _ => break,
+        }
+    }
+
+    // the last bar line is left as it is
+    let next = music[end..].trim_start();
+    match next.is_empty() || end > at && (next.starts_with('{') || next.starts_with("\\alternative")) {
+        true => None,
+        false => Some(end),
+    }
+}
+
+// the bytes of the `|` ending at `at` and the spaces around it, and
+// what they're replaced with
+fn bar_check_span(music: &str, at: usize) -> (usize, usize, &'static str) {
+    let bar = at - 1;
+    let start = music[..bar].trim_end_matches([' ', '\t']).len();
+    let end = music.len() - music[at..].trim_start_matches([' ', '\t']).len();
+    let line_start = start == 0 || music[..start].ends_with('\n');
+    let line_end = end == music.len() || music[end..].starts_with(['\n', '\r', '%']);
+
+    match (line_start, line_end) {
+        // the indent is kept
+        (true, _) => (bar, end, ""),
+        (false, true) => (start, end, if music[end..].starts_with('%') { " " } else { "" }),
+        (false, false) => (start, end, " "),
+    }
+}
+
+// the music with a `|` at every bar line. `time` is the one it starts
+// in, e.g. the melody's for the chords.
+pub fn normalize_music(music: &str, time: TimeSignature) -> Result<(String, Changes), String> {
+    if music.contains("<<") {
+        return Ok((music.to_string(), Changes::default()));
+    }
+
+    let events = spanned_events(music);
+    let mut edits: BTreeMap<usize, Edit> = BTreeMap::new();
+    let mut conflict = false;
+    let mut edit = |at: usize, edit: Edit| match edits.entry(at) {
+        Entry::Vacant(entry) => {
+            entry.insert(edit);
+        },
+        // e.g. the `|` after an unfold, which is only there the last time it's played
+        Entry::Occupied(mut entry) => match (*entry.get(), edit) {
+            (Edit::Checked, Edit::Insert) => {},
+            (Edit::Insert, Edit::Checked) => {
+                entry.insert(Edit::Checked);
+            },
+            (old, edit) => conflict |= old != edit,
+        },
+    };
+
+    let mut measure = Fraction::new(time.beats, time.unit);
+    let mut position = Fraction::ZERO;
+    let mut pickup = false;
+    let mut started = false;
+    // past the first bar line
+    let mut first_measure = true;
+    // the bar line a note just ended on, and whether it has a `|` yet
+    let mut bar_line: Option<usize> = None;
+    let mut checked = false;
+
+    for (i, (event, end)) in events.iter().enumerate() {
+        match event {
+            Event::Time { beats, unit, .. } => measure = Fraction::new(*beats, *unit),
+            Event::Partial { duration, .. } => {
+                pickup |= !started;
+                position = measure - *duration;
+            },
+            Event::Note { duration, .. } if *duration > Fraction::ZERO => {
+                if let Some(at) = bar_line.take() {
+                    edit(at, if checked { Edit::Checked } else { Edit::Insert });
+                }
+                started = true;
+                checked = false;
+
+                position = position + *duration;
+                let mut crossed = false;
+                while measure.num > 0 && position >= measure {
+                    position = position - measure;
+                    crossed = true;
+                }
+                first_measure &= !crossed;
+
+                // its tie, slurs, and chord modifier are part of it
+                let at = events[i + 1..]
+                    .iter()
+                    .take_while(|(event, _)| matches!(event, Event::Tie { .. } | Event::Slur { .. } | Event::Modifier { .. }))
+                    .last()
+                    .map_or(*end, |(_, end)| *end);
+                match crossed && position == Fraction::ZERO {
+                    true => bar_line = Some(at),
+                    false => edit(at, Edit::Keep),
+                }
+            },
+            Event::BarCheck { .. } if position != Fraction::ZERO => {
+                if first_measure && !pickup {
+                    return Err(format!(
+                        "the bar check in measure 1 is {} into it, it needs a `\\partial {}` first",
+                        position,
+                        lilypond_duration(position)
+                    ));
+                }
+                edit(*end, Edit::Remove);
+            },
+            Event::BarCheck { .. } => {
+                edit(*end, if checked { Edit::Remove } else { Edit::Keep });
+                checked = true;
+            },
+            _ => {},
+        }
+    }
+    if conflict {
+        return Err(String::from("a `\\repeat unfold` in it doesn't start at the same beat every time"));
+    }
+
+    let mut normalized = String::with_capacity(music.len());
+    let mut changes = Changes::default();
+    let mut copied = 0;
+    for (at, edit) in edits {
+        match edit {
+            Edit::Keep | Edit::Checked => {},
+            Edit::Insert => {
+                let Some(at) = bar_check_at(music, at) else { continue };
+                normalized.push_str(&music[copied..at]);
+                normalized.push_str(" |");
+                if !music[at..].starts_with(char::is_whitespace) {
+                    normalized.push(' ');
+                }
+                copied = at;
+                changes.added += 1;
+            },
+            Edit::Remove => {
+                let (start, end, replacement) = bar_check_span(music, at);
+                normalized.push_str(&music[copied..start]);
+                normalized.push_str(replacement);
+                copied = end;
+                changes.removed += 1;
+            },
+        }
+    }
+    normalized.push_str(&music[copied..]);
+
+    Ok((normalized, changes))
+}
+
+// the first `\time` of the music
+fn first_time(music: &str) -> Option<TimeSignature> {
+    spanned_events(music).into_iter().find_map(|(event, _)| match event {
+        Event::Time { beats, unit, .. } => Some(TimeSignature { beats, unit }),
+        _ => None,
+    })
+}
+
+// one song with its chords and voices normalized, the sections the
+// same way `Song::parse_with` tells them apart
+fn normalize_song(song: &str) -> Result<(String, Changes), TemplaterError> {
+    let (_, document) = frontmatter::split(song);
+    let start = match song.ends_with(document) {
+        true => song.len() - document.len(),
+        false => 0,
+    };
+
+    let parts: Vec<&str> = song[start..].split("---").collect();
+    // lilypond's timing is the whole score's, so it's the melody's `\time`
+    let melody_time = parts
+        .iter()
+        .find(|part| part.contains("numericTimeSignature"))
+        .and_then(|melody| first_time(melody))
+        .unwrap_or(TimeSignature::COMMON);
+
+    let mut normalized = vec![];
+    let mut changes = Changes::default();
+    let mut label = None;
+    for part in parts {
+        if let Some(section) = section_label(part) {
+            label = Some(section);
+            normalized.push(part.to_string());
+            continue;
+        }
+
+        let name = match label.take() {
+            Some(("voice", name)) => format!("voice {}", name),
+            Some(("chords", name)) => format!("chords {}", name),
+            Some(_) => String::new(),
+            None if part.contains("chordmode") => String::from("chords"),
+            None if part.contains("numericTimeSignature") => String::from("melody"),
+            None => String::new(),
+        };
+        if name.is_empty() {
+            normalized.push(part.to_string());
+            continue;
+        }
+
+        let time = first_time(part).unwrap_or(melody_time);
+        let (part, part_changes) =
+            normalize_music(part, time).map_err(|e| TemplaterError::from_str(&format!("{}: {}", name, e)))?;
+        normalized.push(part);
+        changes.add(part_changes);
+    }
+
+    Ok((format!("{}{}", &song[..start], normalized.join("---")), changes))
+}
+
+// every song in a file
+pub fn normalize_source(input: &str) -> Result<(String, Changes), TemplaterError> {
+    let mut normalized = String::with_capacity(input.len());
+    let mut changes = Changes::default();
+
+    let mut end = 0;
+    for (_, song) in frontmatter::split_songs(input) {
+        // the separator between them, if any
+        let start = song.as_ptr() as usize - input.as_ptr() as usize;
+        normalized.push_str(&input[end..start]);
+        end = start + song.len();
+
+        let (song, song_changes) = normalize_song(song)?;
+        normalized.push_str(&song);
+        changes.add(song_changes);
+    }
+
+    Ok((normalized, changes))
+}
+
+// a song that was (or would be, with `--dry-run`) normalized, and
+// the unified diff of the change
+pub struct Normalization {
+    pub path: PathBuf,
+    pub changes: Changes,
+    pub diff: String,
+}
+
+// normalizes every file in `paths`. files that can't be normalized
+// are left as they are, and reported together after the others were
+// normalized.
+pub fn normalize_songs(paths: &[PathBuf], dry_run: bool) -> Result<Vec<Normalization>, TemplaterError> {
+    let mut normalizations = vec![];
+    let mut errors = vec![];
+
+    for path in paths {
+        match normalize_song_file(path, dry_run) {
+            Ok(Some(normalization)) => normalizations.push(normalization),
+            Ok(None) => {},
+            Err(e) => errors.push(e),
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(normalizations),
+        false => {
+            for normalization in &normalizations {
+                log!("[info]: normalized {}", normalization.path.display());
+            }
+            Err(TemplaterError::collect(errors))
+        },
+    }
+}
+
+fn normalize_song_file(path: &Path, dry_run: bool) -> Result<Option<Normalization>, TemplaterError> {
+    let song = read_file(path)?;
+    let (normalized, changes) =
+        normalize_source(&song).map_err(|e| TemplaterError::from_str(&format!("{}: {}", path.display(), e)))?;
+    if normalized == song {
+        return Ok(None);
+    }
+
+    if !dry_run {
+        fs::write(path, &normalized).map_err(|e| TemplaterError::file(path, e))?;
+    }
+
+    let name = path.display().to_string();
+    let diff = TextDiff::from_lines(&song, &normalized).unified_diff().context_radius(3).header(&name, &name).to_string();
+    Ok(Some(Normalization { path: path.to_path_buf(), changes, diff }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn normalize(music: &str) -> String {
+        normalize_music(music, TimeSignature::COMMON).unwrap().0
+    }
+
+    #[test]
+    fn it_puts_a_bar_check_at_every_bar_line() {
+        assert_eq!(normalize("c2 d | e1 f2 g2 a1"), "c2 d | e1 | f2 g2 | a1");
+        assert_eq!(normalize("\\partial 4 g4 c1\n  d2.~ d4 ~ e1"), "\\partial 4 g4 | c1 |\n  d2.~ d4 ~ | e1");
+        assert_eq!(normalize("\\time 3/4 c2. | | d2. e2."), "\\time 3/4 c2. | d2. | e2.");
+        assert_eq!(normalize("c1 % A\n| d1 |\n  |e1"), "c1 % A\n| d1 |\n  e1");
+        assert_eq!(normalize("c4 d e f | g1 |"), "c4 d e f | g1 |");
+    }
+
+    #[test]
+    fn it_puts_bar_checks_around_blocks() {
+        assert_eq!(normalize("c2 \\tuplet 3/2 { c4 d e } f1"), "c2 \\tuplet 3/2 { c4 d e } | f1");
+        assert_eq!(
+            normalize("\\repeat volta 2 { c1 d1 } \\alternative { { e1 } { f1 } } g1"),
+            "\\repeat volta 2 { c1 | d1 } \\alternative { { e1 } { f1 } } | g1"
+        );
+        assert_eq!(normalize("\\repeat unfold 2 { c1 } d1"), "\\repeat unfold 2 { c1 } | d1");
+        assert_eq!(normalize("\\chordmode { c1:m7 f2:7 bes }"), "\\chordmode { c1:m7 | f2:7 bes }");
+    }
+
+    #[test]
+    fn it_leaves_what_it_cant_count_alone() {
+        let time = TimeSignature::COMMON;
+        assert_eq!(normalize("<< { c1 d1 } { e1 f1 } >>"), "<< { c1 d1 } { e1 f1 } >>");
+        assert!(normalize_music("g4 | c1 | d1", time).is_err());
+        assert!(normalize_music("\\repeat unfold 2 { c2 } d1", time).is_err());
+    }
+
+    #[test]
+    fn it_normalizes_every_section_of_a_song() {
+        let song = "title: Oleo\n---\n\\chordmode {\n  bes2. g:m7 | c:m7\n}\n---\n{\n  \\time 3/4\n  \
+                    \\numericTimeSignature\n  d2. | c4 | d2\n}\n--- verse: 1 ---\nla la | la\n";
+        let (normalized, changes) = normalize_source(song).unwrap();
+        assert_eq!(
+            normalized,
+            "title: Oleo\n---\n\\chordmode {\n  bes2. | g:m7 | c:m7\n}\n---\n{\n  \\time 3/4\n  \
+             \\numericTimeSignature\n  d2. | c4 d2\n}\n--- verse: 1 ---\nla la | la\n"
+        );
+        assert_eq!(changes, Changes { added: 1, removed: 1 });
+    }
+}
@@ -0,0 +1,189 @@
+// `--mode numbers`: every song's changes as roman numerals of its
+// key, e.g. `IIm7 V7 Imaj7`, so the chart reads the same in every
+// key. numerals are counted on the major scale of the tonic, in a
+// minor key too, so C minor's Eb is bIII. a bar without a new chord
+// is `%`, and repeats are only written once, like in the chords.
+
+use crate::chord::{Chord, POLYCHORD};
+use crate::harmony::{parse_pitch, MAJOR_SCALE};
+use crate::music::{events, Event, Fraction};
+use crate::transpose::Pitch;
+
+const NUMERALS: [&str; 7] = ["I", "II", "III", "IV", "V", "VI", "VII"];
+
+pub const BARS_PER_LINE: usize = 4;
+
+// e.g. bes in f is IV, ees in c is bIII, fis in c is #IV
+pub fn numeral(pitch: &Pitch, tonic: &Pitch) -> String {
+    let step = (pitch.step + 7 - tonic.step) % 7;
+    let semitones = (pitch.pitch_class() - tonic.pitch_class()).rem_euclid(12);
+    let mut alteration = semitones - MAJOR_SCALE[step];
+    if alteration > 6 {
+        alteration -= 12;
+    } else if alteration < -6 {
+        alteration += 12;
+    }
+
+    let accidental = if alteration < 0 { "b" } else { "#" };
+    format!("{}{}", accidental.repeat(alteration.unsigned_abs() as usize), NUMERALS[step])
+}
+
+// the `\chordmode` steps as they're usually written, e.g. "maj7",
+// "m7b5" for "m7.5-", or "6/9" for "6.9". removed steps are left out.
+pub fn quality(steps: &str) -> String {
+    let steps = steps.split('^').next().unwrap_or_default();
+    let mut parts = steps.split('.');
+    let mut quality = match parts.next().unwrap_or_default() {
+        "maj" => String::from("maj7"),
+        "dim" => String::from("°"),
+        "dim7" => String::from("°7"),
+        "aug" => String::from("+"),
+        first => first.to_string(),
+    };
+
+    for part in parts {
+        match (part.strip_suffix('-'), part.strip_suffix('+')) {
+            (Some(step), _) => quality.push_str(&format!("b{}", step)),
+            (_, Some(step)) => quality.push_str(&format!("#{}", step)),
+            _ if quality.ends_with('6') => quality.push_str(&format!("/{}", part)),
+            _ => quality.push_str(&format!("({})", part)),
+        }
+    }
+    quality
+}
+
+// one chord of a bar, e.g. "IIm7", "V7/II", or "II|I7" for a polychord
+fn chord(root: &Pitch, modifier: &str, tonic: &Pitch) -> String {
+    if let Some((modifier, lower)) = modifier.split_once(POLYCHORD) {
+        let upper = chord(root, modifier, tonic);
+        return match Chord::parse(lower) {
+            Some(below) => {
+                let modifier = &lower[lower.find([':', '/']).unwrap_or(lower.len())..];
+                format!("{}|{}", upper, chord(&below.root, modifier, tonic))
+            },
+            None => upper,
+        };
+    }
+
+    let (steps, bass) = match modifier.split_once('/') {
+        Some((steps, bass)) => (steps, Some(bass.trim_start_matches('+'))),
+        None => (modifier, None),
+    };
+
+    let mut chord = format!("{}{}", numeral(root, tonic), quality(steps.trim_start_matches(':')));
+    if let Some(bass) = bass.and_then(parse_pitch) {
+        chord.push_str(&format!("/{}", numeral(&bass, tonic)));
+    }
+    chord
+}
+
+// every bar of a `\chordmode` as numerals of `tonic`, e.g. "IIm7 V7"
+// or "%", and the pickup's chords if there are any. `time` is the
+// time signature until there's a `\time`, e.g. the melody's.
+pub fn numbers_chart(chords: &str, time: Fraction, tonic: &Pitch) -> (Option<String>, Vec<String>) {
+    // (root, modifier) of every chord in every bar, the pickup first
+    let mut bars: Vec<Vec<(Pitch, String)>> = vec![vec![]];
+    let mut pickup = false;
+    let mut measure = time;
+    let mut position = Fraction::ZERO;
+
+    for event in events(chords) {
+        match event {
+            Event::Time { beats, unit, .. } => measure = Fraction::new(beats, unit),
+            Event::Partial { duration, .. } => {
+                pickup |= bars.len() == 1 && bars[0].is_empty();
+                position = measure - duration;
+            },
+            Event::Note { pitch, duration, .. } if measure.num > 0 => {
+                if let Some(root) = pitch.as_deref().and_then(parse_pitch) {
+                    if let Some(bar) = bars.last_mut() {
+                        bar.push((root, String::new()));
+                    }
+                }
+
+                position = position + duration;
+                while position >= measure {
+                    position = position - measure;
+                    bars.push(vec![]);
+                }
+            },
+            Event::Modifier { text, .. } => {
+                // it's after the note, which may have ended the bar
+                if let Some((_, modifier)) = bars.iter_mut().rev().find_map(|bar| bar.last_mut()) {
+                    *modifier = text;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // the bar after the last note hasn't started
+    if position == Fraction::ZERO && bars.len() > 1 {
+        bars.pop();
+    }
+
+    let mut seen_chord = false;
+    let mut numbered: Vec<String> = bars
+        .iter()
+        .map(|bar| {
+            let chords: Vec<String> = bar.iter().map(|(root, modifier)| chord(root, modifier, tonic)).collect();
+            seen_chord |= !chords.is_empty();
+            match chords.is_empty() {
+                true if seen_chord => String::from("%"),
+                _ => chords.join(" "),
+            }
+        })
+        .collect();
+
+    let pickup = match pickup {
+        true => Some(numbered.remove(0)).filter(|chords| !chords.is_empty()),
+        false => None,
+    };
+    (pickup, numbered)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pitch(name: &str) -> Pitch {
+        parse_pitch(name).unwrap()
+    }
+
+    #[test]
+    fn it_numbers_chords_in_a_key() {
+        assert_eq!(numeral(&pitch("bes"), &pitch("f")), "IV");
+        assert_eq!(numeral(&pitch("ees"), &pitch("c")), "bIII");
+        assert_eq!(numeral(&pitch("fis"), &pitch("c")), "#IV");
+        assert_eq!(numeral(&pitch("c"), &pitch("ees")), "VI");
+        assert_eq!(numeral(&pitch("b"), &pitch("c")), "VII");
+
+        assert_eq!(quality("m7.5-"), "m7b5");
+        assert_eq!(quality("6.9"), "6/9");
+        assert_eq!(quality("7.9+"), "7#9");
+        assert_eq!(quality("dim7"), "°7");
+        assert_eq!(quality("7.13^9"), "7(13)");
+        assert_eq!(chord(&pitch("d"), ":7/fis", &pitch("g")), "V7/VII");
+        assert_eq!(chord(&pitch("a"), ":m//g:7/+b", &pitch("g")), "IIm|I7/III");
+    }
+
+    #[test]
+    fn it_numbers_every_bar() {
+        let chords = "\\partial 4 d4:7 | g1:maj7 | e2:m7 a:7 | d1:7 | s1 | g1:6.9 |";
+        assert_eq!(
+            numbers_chart(chords, Fraction::ONE, &pitch("g")),
+            (
+                Some(String::from("V7")),
+                vec![
+                    String::from("Imaj7"),
+                    String::from("VIm7 II7"),
+                    String::from("V7"),
+                    String::from("%"),
+                    String::from("I6/9")
+                ]
+            )
+        );
+        assert_eq!(numbers_chart("\\partial 4 s4 c1 | f", Fraction::ONE, &pitch("c")).0, None);
+        assert_eq!(numbers_chart("d2//c:7 g:7", Fraction::ONE, &pitch("c")).1, ["II|I7 V7"]);
+    }
+}
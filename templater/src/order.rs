@@ -0,0 +1,154 @@
+// `--sort composer` (or `sort = "composer"`): the order of the book's
+// songs, e.g. for an edition grouped by composer. the ToC lists them
+// in that order, with a heading for every composer, key, tempo, or
+// year they were added in, and the songs of one file stay together
+// under its first song. songs without a key or bpm come last, and so
+// do the songs that aren't committed yet for `date-added`, which is
+// when git first saw a song's file, see `changelog.rs`. ties stay in
+// title order. a setlist's order wins over it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::models::{Song, TransposeText};
+use crate::toc::bpm_range;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    #[default]
+    Title,
+    Composer,
+    // by tonic, in concert pitch
+    Key,
+    Bpm,
+    DateAdded,
+}
+
+impl SortOrder {
+    // none comes last
+    fn sort_key(&self, song: &Song) -> Option<String> {
+        match self {
+            SortOrder::Title => Some(String::new()),
+            SortOrder::Composer => Some(song.composer_sort.clone()),
+            SortOrder::Key => song.key.map(|key| key.sort_key()),
+            SortOrder::Bpm => song.bpm().map(|bpm| format!("{:04}", bpm)),
+            SortOrder::DateAdded => song.added.clone(),
+        }
+    }
+
+    // what the ToC lists it under, none in title order. keys are the
+    // ones the book's instrument reads.
+    pub fn heading(&self, song: &Song, transpose_text: &TransposeText) -> Option<String> {
+        let heading = match self {
+            SortOrder::Title => return None,
+            SortOrder::Composer => Some(song.composer.clone()),
+            SortOrder::Key => song.key.map(|key| key.transposed(&song.transposition(transpose_text)).to_string()),
+            SortOrder::Bpm => song.bpm().map(|bpm| bpm_range(bpm).1),
+            // e.g. "2024"
+            SortOrder::DateAdded => song.added.as_ref().and_then(|added| added.get(..4)).map(String::from),
+        };
+
+        Some(heading.unwrap_or_else(|| String::from("Other")))
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortOrder::Title => write!(f, "title"),
+            SortOrder::Composer => write!(f, "composer"),
+            SortOrder::Key => write!(f, "key"),
+            SortOrder::Bpm => write!(f, "bpm"),
+            SortOrder::DateAdded => write!(f, "date-added"),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "title" => Ok(SortOrder::Title),
+            "composer" => Ok(SortOrder::Composer),
+            "key" => Ok(SortOrder::Key),
+            "bpm" | "tempo" => Ok(SortOrder::Bpm),
+            "date-added" | "date_added" | "added" => Ok(SortOrder::DateAdded),
+            _ => Err(TemplaterError::from_str(&format!(
+                "Unknown sort order '{}'. Expected title, composer, key, bpm, or date-added.",
+                s
+            ))),
+        }
+    }
+}
+
+// `songs` are in book order, and stay in it within every group
+pub fn sort_songs(songs: &mut [Song], order: SortOrder) {
+    if order == SortOrder::Title {
+        return;
+    }
+
+    // the songs of a file go where its first one does
+    let mut keys: BTreeMap<PathBuf, Option<String>> = BTreeMap::new();
+    for song in songs.iter() {
+        keys.entry(song.path.clone()).or_insert_with(|| order.sort_key(song));
+    }
+    songs.sort_by_cached_key(|song| {
+        let key = keys.get(&song.path).cloned().flatten();
+        (key.is_none(), key)
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontmatter::Defaults;
+    use crate::transpose::Presets;
+    use std::path::Path;
+
+    fn song(title: &str, front_matter: &str) -> Song {
+        let input = format!("title: {}\n{}\n---\n", title, front_matter);
+        let path = format!("{}.ly", title.to_lowercase());
+        Song::parse_with(Path::new(&path), &input, false, &Defaults::default(), &Presets::default()).unwrap()
+    }
+
+    fn sorted(songs: &[Song], order: SortOrder) -> Vec<String> {
+        let mut songs = songs.to_vec();
+        sort_songs(&mut songs, order);
+        songs.into_iter().map(|song| song.title).collect()
+    }
+
+    #[test]
+    fn it_sorts_the_book_by_a_field() {
+        let songs = [
+            song("Caravan", "composer: Duke Ellington\nkey: C\nbpm: 200"),
+            song("Misty", "composer: Erroll Garner\nkey: Eb\nbpm: 60"),
+            song("Nardis", "composer: Miles Davis"),
+            song("Solitude", "composer: Duke Ellington\nkey: Db"),
+        ];
+
+        assert_eq!(sorted(&songs, SortOrder::Title), ["Caravan", "Misty", "Nardis", "Solitude"]);
+        assert_eq!(sorted(&songs, SortOrder::Composer), ["Nardis", "Caravan", "Solitude", "Misty"]);
+        assert_eq!(sorted(&songs, SortOrder::Key), ["Caravan", "Solitude", "Misty", "Nardis"]);
+        assert_eq!(sorted(&songs, SortOrder::Bpm), ["Misty", "Caravan", "Nardis", "Solitude"]);
+    }
+
+    #[test]
+    fn it_heads_every_group_in_the_toc() {
+        let bb = crate::transpose::transpose_text("bb").unwrap();
+        let misty = song("Misty", "composer: Erroll Garner\nkey: Eb\nbpm: 60");
+
+        assert_eq!(SortOrder::Title.heading(&misty, &bb), None);
+        assert_eq!(SortOrder::Composer.heading(&misty, &bb).as_deref(), Some("Erroll Garner"));
+        assert_eq!(SortOrder::Key.heading(&misty, &bb).as_deref(), Some("F major"));
+        assert_eq!(SortOrder::Bpm.heading(&misty, &bb).as_deref(), Some("60-79 bpm"));
+        assert_eq!(SortOrder::DateAdded.heading(&misty, &bb).as_deref(), Some("Other"));
+        assert_eq!("Date-Added".parse::<SortOrder>().unwrap(), SortOrder::DateAdded);
+    }
+}
@@ -0,0 +1,162 @@
+// checks a song's pickup against its bar checks. lilypond counts a
+// `\partial 4` as the end of a measure, so a melody that starts on
+// a pickup without one has every bar line a quarter off, and it
+// warns about every `|` after it. the chords and every voice are
+// checked on their own, since each one has to have the same pickup.
+// a song that starts on a pickup usually ends on a short measure
+// too, which together make up a whole one. music with `<< >>` isn't
+// checked, since its parts are counted one after the other.
+
+use crate::models::Song;
+use crate::music::{events, Event, Fraction, TimeSignature};
+
+// e.g. "4", "4.", or "8*5"
+pub(crate) fn lilypond_duration(duration: Fraction) -> String {
+    match (duration.num, duration.den) {
+        (1, den) => den.to_string(),
+        (3, den) if den % 2 == 0 => format!("{}.", den / 2),
+        (num, den) => format!("{}*{}", den, num),
+    }
+}
+
+// warnings for one voice, e.g. "melody: ...". `time` is the one it
+// starts in, e.g. the melody's for the chords, which don't have one
+fn verify_music(name: &str, music: &str, mut time: TimeSignature) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut measure = Fraction::new(time.beats, time.unit);
+    let mut position = Fraction::ZERO;
+    // lilypond's bar number, a pickup is measure 0
+    let mut number = 1;
+    let mut pickup = None;
+    let mut started = false;
+    // (measure, how far into it) of every `|` that isn't at a bar line
+    let mut failed: Vec<(u64, Fraction)> = vec![];
+
+    for event in events(music) {
+        match event {
+            Event::Time { beats, unit, .. } => {
+                time = TimeSignature { beats, unit };
+                measure = Fraction::new(beats, unit);
+            },
+            Event::Partial { duration, .. } => {
+                match started {
+                    false => {
+                        pickup = Some(duration);
+                        number = 0;
+                    },
+                    true if position != Fraction::ZERO => warnings.push(format!(
+                        "{}: `\\partial {}` in measure {} isn't at a bar line",
+                        name,
+                        lilypond_duration(duration),
+                        number
+                    )),
+                    true => {},
+                }
+                position = measure - duration;
+            },
+            Event::Note { duration, .. } => {
+                started |= duration > Fraction::ZERO;
+                position = position + duration;
+                while measure.num > 0 && position >= measure {
+                    position = position - measure;
+                    number += 1;
+                }
+            },
+            Event::BarCheck { .. } if position != Fraction::ZERO => failed.push((number, position)),
+            Event::BarCheck { .. } | Event::Tie { .. } | Event::Slur { .. } | Event::Modifier { .. } => {},
+        }
+    }
+
+    // every one after the first is usually off because of it
+    if let Some(&(number, position)) = failed.first() {
+        let mut warning = match (pickup, number) {
+            (None, 1) => format!(
+                "{}: the bar check in measure 1 is {} into it, a pickup needs `\\partial {}`",
+                name,
+                position,
+                lilypond_duration(position)
+            ),
+            _ => format!("{}: the bar check in measure {} is {} into it", name, number, position),
+        };
+        if failed.len() > 1 {
+            warning.push_str(&format!(", and {} more after it", failed.len() - 1));
+        }
+        warnings.push(warning);
+    }
+
+    if let Some(pickup) = pickup.filter(|_| position != Fraction::ZERO) {
+        if pickup + position != measure {
+            warnings.push(format!(
+                "{}: the pickup ({}) and the last measure ({}) add up to {}, not a measure of {}",
+                name,
+                pickup,
+                position,
+                pickup + position,
+                time
+            ));
+        }
+    }
+
+    warnings
+}
+
+pub fn verify_pickup(song: &Song) -> Vec<String> {
+    let mut warnings = vec![];
+    let first_time = |music: &str| {
+        events(music).into_iter().find_map(|event| match event {
+            Event::Time { beats, unit, .. } => Some(TimeSignature { beats, unit }),
+            _ => None,
+        })
+    };
+    // lilypond's timing is the whole score's, so it's the melody's `\time`
+    let melody = song.voices.iter().find(|voice| voice.name.is_none());
+    let time = melody.and_then(|melody| first_time(&melody.notes)).unwrap_or(TimeSignature::COMMON);
+
+    let music = song.voices.iter().map(|voice| {
+        (voice.name.as_ref().map_or(String::from("melody"), |name| format!("voice {}", name)), voice.notes.as_str())
+    });
+    for (name, music) in [(String::from("chords"), song.chords.as_str())].into_iter().chain(music) {
+        if music.trim().is_empty() || music.contains("<<") {
+            continue;
+        }
+        warnings.extend(verify_music(&name, music, first_time(music).unwrap_or(time)));
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn verify(name: &str, music: &str) -> Vec<String> {
+        verify_music(name, music, TimeSignature::COMMON)
+    }
+
+    #[test]
+    fn it_accepts_a_pickup_that_completes_the_last_measure() {
+        assert!(verify("melody", "\\time 4/4 \\partial 4 g4 | c1 | d2.").is_empty());
+        assert!(verify("melody", "\\partial 8*3 g8 a b | c1 | d1").is_empty());
+        assert!(verify("melody", "c1 | d2 e | \\time 3/4 f2. |").is_empty());
+    }
+
+    #[test]
+    fn it_reports_where_the_bar_checks_fail() {
+        assert_eq!(
+            verify("melody", "g4 | c1 | d1 |"),
+            ["melody: the bar check in measure 1 is 1/4 into it, a pickup needs `\\partial 4`, and 2 more after it"]
+        );
+        assert_eq!(
+            verify("chords", "\\partial 4 s4 | c1 | d2. | e1"),
+            ["chords: the bar check in measure 2 is 3/4 into it"]
+        );
+        assert_eq!(
+            verify("melody", "\\partial 4. g4 a8 | c1 | d2"),
+            ["melody: the pickup (3/8) and the last measure (1/2) add up to 7/8, not a measure of 4/4"]
+        );
+        assert_eq!(
+            verify("melody", "c1 | d2 \\partial 4 e4 | f1 |"),
+            ["melody: `\\partial 4` in measure 2 isn't at a bar line"]
+        );
+    }
+}
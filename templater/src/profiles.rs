@@ -0,0 +1,149 @@
+// `--layout large-print` (or `layout = "large-print"`): the pages the
+// book is printed on, for another edition of the same songs. every
+// profile sets lilypond's paper size, margins, and staff size with the
+// `paper` template, after the includes, and adds its name to the
+// book's, e.g. `openbook-Bb-large-print.ly`:
+//
+// - a4, 210 x 297 mm
+// - letter, 8.5 x 11 in
+// - tablet, a landscape page that fits a tablet's screen
+// - large-print, letter with bigger staves, for reading at a distance,
+//   and bigger chord names and lyrics in every song, see `--large-print`
+//
+// without one the paper is `includes/globals.ily`'s. a profile can
+// also have its own intro and bookpart, e.g. `templates/intro-tablet`
+// is used instead of `templates/intro` in a tablet book.
+
+use std::fmt;
+use std::str::FromStr;
+
+use minijinja::{context, Value};
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::templates::Templates;
+
+// the templates a profile can have its own of
+pub const VARIANT_TEMPLATES: [&str; 2] = ["intro", "bookpart"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayoutProfile {
+    A4,
+    Letter,
+    Tablet,
+    LargePrint,
+}
+
+impl LayoutProfile {
+    pub const ALL: [LayoutProfile; 4] =
+        [LayoutProfile::A4, LayoutProfile::Letter, LayoutProfile::Tablet, LayoutProfile::LargePrint];
+
+    // (width, height) in lilypond's units
+    fn paper(&self) -> (&'static str, &'static str) {
+        match self {
+            LayoutProfile::A4 => ("210\\mm", "297\\mm"),
+            LayoutProfile::Letter | LayoutProfile::LargePrint => ("8.5\\in", "11\\in"),
+            LayoutProfile::Tablet => ("8\\in", "6\\in"),
+        }
+    }
+
+    // globals.ily's is 19
+    fn staff_size(&self) -> u32 {
+        match self {
+            LayoutProfile::A4 | LayoutProfile::Letter => 19,
+            LayoutProfile::Tablet => 17,
+            LayoutProfile::LargePrint => 26,
+        }
+    }
+
+    // on every side of the page
+    fn margin(&self) -> &'static str {
+        match self {
+            LayoutProfile::A4 | LayoutProfile::Letter => "12\\mm",
+            LayoutProfile::Tablet => "6\\mm",
+            LayoutProfile::LargePrint => "10\\mm",
+        }
+    }
+
+    // for the `paper` template
+    pub fn context(&self) -> Value {
+        let (paper_width, paper_height) = self.paper();
+        context! {
+            name => self.to_string(),
+            paper_width,
+            paper_height,
+            staff_size => self.staff_size(),
+            margin => self.margin(),
+        }
+    }
+
+    // e.g. `intro-tablet`
+    pub fn variant_name(&self, template: &str) -> String {
+        format!("{}-{}", template, self)
+    }
+}
+
+impl fmt::Display for LayoutProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutProfile::A4 => write!(f, "a4"),
+            LayoutProfile::Letter => write!(f, "letter"),
+            LayoutProfile::Tablet => write!(f, "tablet"),
+            LayoutProfile::LargePrint => write!(f, "large-print"),
+        }
+    }
+}
+
+impl FromStr for LayoutProfile {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "a4" => Ok(LayoutProfile::A4),
+            "letter" | "us-letter" => Ok(LayoutProfile::Letter),
+            "tablet" | "landscape" => Ok(LayoutProfile::Tablet),
+            "large-print" | "large_print" | "large" => Ok(LayoutProfile::LargePrint),
+            _ => Err(TemplaterError::from_str(&format!(
+                "Unknown layout '{}'. Expected a4, letter, tablet, or large-print.",
+                s
+            ))),
+        }
+    }
+}
+
+// the template to render for `template`, the profile's own if the
+// templates dir has one
+pub fn template_for(templates: &Templates, layout: Option<LayoutProfile>, template: &str) -> String {
+    match layout.map(|layout| layout.variant_name(template)) {
+        Some(variant) if templates.source(&variant).is_some() => variant,
+        _ => template.to_string(),
+    }
+}
+
+// e.g. `intro` for `intro-tablet`, none if it isn't a profile's template
+pub fn variant_of(name: &str) -> Option<&'static str> {
+    VARIANT_TEMPLATES.into_iter().find(|template| {
+        LayoutProfile::ALL.iter().any(|layout| layout.variant_name(template) == name)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_the_profiles_and_their_templates() {
+        for layout in LayoutProfile::ALL {
+            assert_eq!(layout.to_string().parse::<LayoutProfile>().unwrap(), layout);
+        }
+        assert_eq!("Landscape".parse::<LayoutProfile>().unwrap(), LayoutProfile::Tablet);
+        assert!("a5".parse::<LayoutProfile>().is_err());
+
+        assert_eq!(LayoutProfile::LargePrint.variant_name("bookpart"), "bookpart-large-print");
+        assert_eq!(variant_of("intro-tablet"), Some("intro"));
+        assert_eq!(variant_of("bookpart-large-print"), Some("bookpart"));
+        assert_eq!(variant_of("intro-a5"), None);
+        assert_eq!(variant_of("toc-tablet"), None);
+    }
+}
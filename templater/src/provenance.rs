@@ -0,0 +1,148 @@
+// where a lead sheet comes from, `source` in the front matter:
+//
+//     source:
+//       book: The Real Book, Vol. 1
+//       page: 257
+//       transcriber: Jane Doe
+//       recording:
+//         artist: Miles Davis
+//         album: Kind of Blue
+//         year: 1959
+//
+// every field is optional. the header cites it at the foot of the
+// song's first page, and the "Sources" page at the end of the book
+// lists every song's. `source` in `_defaults.yml`, e.g. the book
+// every song was transcribed from, is merged with a song's own.
+
+use minijinja::{context, Value};
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::frontmatter::opt_string_like;
+use crate::models::Song;
+use crate::templates::Templates;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Provenance {
+    // e.g. a fakebook
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub book: Option<String>,
+    // e.g. 257 or 257-258
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub page: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub transcriber: Option<String>,
+    // the one the lead sheet follows
+    #[serde(default)]
+    pub recording: Option<Recording>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Recording {
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub artist: Option<String>,
+    #[serde(default, deserialize_with = "opt_string_like")]
+    pub album: Option<String>,
+    #[serde(default)]
+    pub year: Option<u32>,
+}
+
+impl Recording {
+    // e.g. "Miles Davis, Kind of Blue (1959)"
+    fn citation(&self) -> Option<String> {
+        let names: Vec<&str> = [&self.artist, &self.album].into_iter().flatten().map(String::as_str).collect();
+        let mut citation = names.join(", ");
+        if let Some(year) = self.year {
+            citation = format!("{} ({})", citation, year).trim().to_string();
+        }
+
+        (!citation.is_empty()).then_some(citation)
+    }
+}
+
+impl Provenance {
+    pub fn is_empty(&self) -> bool {
+        self.citation().is_empty()
+    }
+
+    // e.g. "The Real Book, Vol. 1, p. 257 · Transcribed by Jane Doe ·
+    // Recording: Miles Davis, Kind of Blue (1959)"
+    pub fn citation(&self) -> String {
+        let book = match (&self.book, &self.page) {
+            (Some(book), Some(page)) => Some(format!("{}, p. {}", book, page)),
+            (Some(book), None) => Some(book.clone()),
+            (None, Some(page)) => Some(format!("p. {}", page)),
+            (None, None) => None,
+        };
+        let transcriber = self.transcriber.as_ref().map(|transcriber| format!("Transcribed by {}", transcriber));
+        let recording = self.recording.as_ref().and_then(Recording::citation).map(|recording| {
+            format!("Recording: {}", recording)
+        });
+
+        [book, transcriber, recording].into_iter().flatten().collect::<Vec<_>>().join(" · ")
+    }
+
+    pub fn context(&self) -> Value {
+        let recording = self.recording.as_ref().map(|recording| {
+            context! {
+                artist => recording.artist.as_deref(),
+                album => recording.album.as_deref(),
+                year => recording.year,
+            }
+        });
+
+        context! {
+            book => self.book.as_deref(),
+            page => self.page.as_deref(),
+            transcriber => self.transcriber.as_deref(),
+            recording,
+            citation => self.citation(),
+        }
+    }
+}
+
+// the "Sources" page, none if no song has a source
+pub fn render_sources(templates: &Templates, songs: &[Song]) -> Result<Option<String>, TemplaterError> {
+    let songs: Vec<Value> = songs
+        .iter()
+        .filter_map(|song| {
+            let provenance = song.provenance.as_ref()?;
+            Some(context! {
+                number => song.number,
+                title => song.title.as_str(),
+                label => song.label(),
+                source => provenance.context(),
+            })
+        })
+        .collect();
+
+    match songs.is_empty() {
+        true => Ok(None),
+        false => templates.render("sources", context! { songs }).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn provenance(yaml: &str) -> Provenance {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn it_cites_every_field_it_has() {
+        let full = "book: The Real Book, Vol. 1\npage: 257\ntranscriber: Jane Doe\n\
+                    recording:\n  artist: Miles Davis\n  album: Kind of Blue\n  year: 1959\n";
+        assert_eq!(
+            provenance(full).citation(),
+            "The Real Book, Vol. 1, p. 257 · Transcribed by Jane Doe · Recording: Miles Davis, Kind of Blue (1959)"
+        );
+        assert_eq!(provenance("recording:\n  year: 1959\n").citation(), "Recording: (1959)");
+        assert_eq!(provenance("page: 12-13\n").citation(), "p. 12-13");
+        assert!(provenance("recording: {}\n").is_empty());
+        assert!(serde_yaml::from_str::<Provenance>("transcribed: Jane Doe\n").is_err());
+    }
+}
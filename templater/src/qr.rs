@@ -0,0 +1,437 @@
+// qr codes, e.g. for the link to a song's backing track in its header,
+// see `backing.rs`. a link is written in byte mode with medium (15%)
+// error correction, in the smallest of versions 1 to 10 it fits in,
+// which is up to 213 bytes. the code is drawn as a lilypond markup, a
+// filled `\path` with a rectangle for every run of dark modules.
+
+use crate::errors::TemplaterError;
+
+const VERSIONS: usize = 10;
+// for every version, (error correction codewords per block, and its
+// two groups of blocks, each (blocks, data codewords per block))
+const BLOCKS: [(usize, [(usize, usize); 2]); VERSIONS] = [
+    (10, [(1, 16), (0, 0)]),
+    (16, [(1, 28), (0, 0)]),
+    (26, [(1, 44), (0, 0)]),
+    (18, [(2, 32), (0, 0)]),
+    (24, [(2, 43), (0, 0)]),
+    (16, [(4, 27), (0, 0)]),
+    (18, [(4, 31), (0, 0)]),
+    (22, [(2, 38), (2, 39)]),
+    (22, [(3, 36), (2, 37)]),
+    (26, [(4, 43), (1, 44)]),
+];
+// the rows (and columns) of every version's alignment patterns
+const ALIGNMENTS: [&[usize]; VERSIONS] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+// the format bits of medium error correction
+const LEVEL_M: u32 = 0;
+// modules of white space around the code
+const QUIET_ZONE: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    pub version: usize,
+    // by row, then column, true for dark
+    pub modules: Vec<Vec<bool>>,
+}
+
+// in GF(256), modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u8 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1d);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
+
+// the generator polynomial's coefficients, without its leading 1
+fn generator(degree: usize) -> Vec<u8> {
+    let mut coefficients = vec![0; degree];
+    coefficients[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            coefficients[j] = gf_multiply(coefficients[j], root);
+            if j + 1 < degree {
+                coefficients[j] ^= coefficients[j + 1];
+            }
+        }
+        root = gf_multiply(root, 2);
+    }
+    coefficients
+}
+
+// the reed-solomon error correction codewords of a block
+fn error_correction(data: &[u8], degree: usize) -> Vec<u8> {
+    let generator = generator(degree);
+    let mut remainder = vec![0; degree];
+    for byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (r, g) in remainder.iter_mut().zip(&generator) {
+            *r ^= gf_multiply(*g, factor);
+        }
+    }
+    remainder
+}
+
+fn data_codewords(version: usize) -> usize {
+    BLOCKS[version - 1].1.iter().map(|(blocks, codewords)| blocks * codewords).sum()
+}
+
+// bits of the byte count
+fn count_bits(version: usize) -> usize {
+    match version {
+        1..=9 => 8,
+        _ => 16,
+    }
+}
+
+// the data codewords: the mode, the byte count, the bytes, and padding
+fn encode_data(data: &[u8], version: usize) -> Vec<u8> {
+    let mut bits: Vec<bool> = vec![];
+    let mut push = |value: usize, length: usize| bits.extend((0..length).rev().map(|i| (value >> i) & 1 == 1));
+    push(0b0100, 4);
+    push(data.len(), count_bits(version));
+    for byte in data {
+        push(*byte as usize, 8);
+    }
+
+    let capacity = data_codewords(version) * 8;
+    let terminator = (capacity - bits.len()).min(4);
+    bits.extend(std::iter::repeat_n(false, terminator));
+    while !bits.len().is_multiple_of(8) {
+        bits.push(false);
+    }
+
+    let mut codewords: Vec<u8> =
+        bits.chunks(8).map(|byte| byte.iter().fold(0, |b, bit| (b << 1) | *bit as u8)).collect();
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if codewords.len() >= capacity / 8 {
+            break;
+        }
+        codewords.push(pad);
+    }
+    codewords
+}
+
+// the data and error correction codewords, interleaved block by block
+fn codewords(data: &[u8], version: usize) -> Vec<u8> {
+    let (degree, groups) = BLOCKS[version - 1];
+    let data = encode_data(data, version);
+
+    let mut blocks: Vec<&[u8]> = vec![];
+    let mut start = 0;
+    for (count, length) in groups {
+        for _ in 0..count {
+            blocks.push(&data[start..start + length]);
+            start += length;
+        }
+    }
+    let corrections: Vec<Vec<u8>> = blocks.iter().map(|block| error_correction(block, degree)).collect();
+
+    let mut codewords = vec![];
+    let longest = blocks.iter().map(|block| block.len()).max().unwrap_or(0);
+    for i in 0..longest {
+        codewords.extend(blocks.iter().filter_map(|block| block.get(i)));
+    }
+    for i in 0..degree {
+        codewords.extend(corrections.iter().map(|correction| correction[i]));
+    }
+    codewords
+}
+
+// whether a mask inverts the module at (row, col)
+fn masked(mask: u32, row: usize, col: usize) -> bool {
+    let product = row * col;
+    match mask {
+        0 => (row + col).is_multiple_of(2),
+        1 => row.is_multiple_of(2),
+        2 => col.is_multiple_of(3),
+        3 => (row + col).is_multiple_of(3),
+        4 => (row / 2 + col / 3).is_multiple_of(2),
+        5 => product % 2 + product % 3 == 0,
+        6 => (product % 2 + product % 3).is_multiple_of(2),
+        _ => ((row + col) % 2 + product % 3).is_multiple_of(2),
+    }
+}
+
+// `value` with its bch code, the remainder of the polynomial
+// `generator` of degree `degree`, after it
+fn bch(value: u32, generator: u32, degree: u32) -> u32 {
+    let mut remainder = value;
+    for _ in 0..degree {
+        remainder = (remainder << 1) ^ ((remainder >> (degree - 1)) * generator);
+    }
+    (value << degree) | remainder
+}
+
+#[derive(Clone)]
+struct Matrix {
+    size: usize,
+    modules: Vec<Vec<bool>>,
+    // whether it's part of a pattern, not data
+    reserved: Vec<Vec<bool>>,
+}
+
+impl Matrix {
+    fn new(version: usize) -> Self {
+        let size = 17 + 4 * version;
+        let blank = vec![vec![false; size]; size];
+        let mut matrix = Matrix { size, modules: blank.clone(), reserved: blank };
+
+        for i in 0..size {
+            matrix.set(6, i, i % 2 == 0);
+            matrix.set(i, 6, i % 2 == 0);
+        }
+        for (row, col) in [(3, 3), (3, size - 4), (size - 4, 3)] {
+            matrix.finder(row, col);
+        }
+        let alignments = ALIGNMENTS[version - 1];
+        for (i, row) in alignments.iter().enumerate() {
+            for (j, col) in alignments.iter().enumerate() {
+                let corner = |k: usize| k == 0 || k == alignments.len() - 1;
+                if !(i == 0 && corner(j) || j == 0 && corner(i)) {
+                    matrix.alignment(*row, *col);
+                }
+            }
+        }
+        // reserved for now, see `format`
+        matrix.format(0);
+        if version >= 7 {
+            let bits = bch(version as u32, 0x1f25, 12);
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                matrix.set(i / 3, size - 11 + i % 3, dark);
+                matrix.set(size - 11 + i % 3, i / 3, dark);
+            }
+        }
+        matrix
+    }
+
+    fn set(&mut self, row: usize, col: usize, dark: bool) {
+        self.modules[row][col] = dark;
+        self.reserved[row][col] = true;
+    }
+
+    // with its separator, around (row, col)
+    fn finder(&mut self, row: usize, col: usize) {
+        for dr in -4i32..=4 {
+            for dc in -4i32..=4 {
+                let (r, c) = (row as i32 + dr, col as i32 + dc);
+                if (0..self.size as i32).contains(&r) && (0..self.size as i32).contains(&c) {
+                    let distance = dr.abs().max(dc.abs());
+                    self.set(r as usize, c as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn alignment(&mut self, row: usize, col: usize) {
+        for dr in -2i32..=2 {
+            for dc in -2i32..=2 {
+                let (r, c) = ((row as i32 + dr) as usize, (col as i32 + dc) as usize);
+                self.set(r, c, dr.abs().max(dc.abs()) != 1);
+            }
+        }
+    }
+
+    // both copies of the error correction level and mask
+    fn format(&mut self, mask: u32) {
+        let bits = bch((LEVEL_M << 3) | mask, 0x537, 10) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+
+        for i in 0..6 {
+            self.set(i, 8, bit(i));
+        }
+        self.set(7, 8, bit(6));
+        self.set(8, 8, bit(7));
+        self.set(8, 7, bit(8));
+        for i in 9..15 {
+            self.set(8, 14 - i, bit(i));
+        }
+        for i in 0..8 {
+            self.set(8, size - 1 - i, bit(i));
+        }
+        for i in 8..15 {
+            self.set(size - 15 + i, 8, bit(i));
+        }
+        // always dark
+        self.set(size - 8, 8, true);
+    }
+
+    // two columns at a time from the right, up and then down
+    fn place(&mut self, codewords: &[u8]) {
+        let mut i = 0;
+        let mut right = self.size - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..self.size {
+                for j in 0..2 {
+                    let col = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let row = if upward { self.size - 1 - vertical } else { vertical };
+                    if !self.reserved[row][col] && i < codewords.len() * 8 {
+                        self.modules[row][col] = (codewords[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for row in 0..self.size {
+            for col in 0..self.size {
+                if !self.reserved[row][col] && masked(mask, row, col) {
+                    self.modules[row][col] = !self.modules[row][col];
+                }
+            }
+        }
+    }
+
+    // how hard it is to scan, the lowest mask's is used
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let lines = (0..size)
+            .map(|row| self.modules[row].clone())
+            .chain((0..size).map(|col| (0..size).map(|row| self.modules[row][col]).collect()));
+
+        let mut penalty = 0;
+        let (finder, reversed) = ([true, false, true, true, true, false, true], [false; 4]);
+        for line in lines {
+            let mut run = 1;
+            for i in 1..=size {
+                match i < size && line[i] == line[i - 1] {
+                    true => run += 1,
+                    false => {
+                        if run >= 5 {
+                            penalty += run - 2;
+                        }
+                        run = 1;
+                    },
+                }
+            }
+            for window in line.windows(11) {
+                let dark_first = window[..7] == finder && window[7..] == reversed;
+                let light_first = window[..4] == reversed && window[4..] == finder;
+                if dark_first || light_first {
+                    penalty += 40;
+                }
+            }
+        }
+
+        for row in 0..size - 1 {
+            for col in 0..size - 1 {
+                let dark = self.modules[row][col];
+                let square = [(row, col + 1), (row + 1, col), (row + 1, col + 1)];
+                if square.iter().all(|(r, c)| self.modules[*r][*c] == dark) {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let dark = self.modules.iter().flatten().filter(|dark| **dark).count();
+        penalty + (dark * 100 / (size * size)).abs_diff(50) / 5 * 10
+    }
+}
+
+impl QrCode {
+    pub fn encode(data: &[u8]) -> Result<QrCode, TemplaterError> {
+        let version = (1..=VERSIONS)
+            .find(|version| 4 + count_bits(*version) + data.len() * 8 <= data_codewords(*version) * 8)
+            .ok_or_else(|| {
+                TemplaterError::from_str(&format!("{} bytes is too long for a qr code, the most is 213", data.len()))
+            })?;
+
+        let mut matrix = Matrix::new(version);
+        matrix.place(&codewords(data, version));
+        let penalty = |mask: u32| {
+            let mut masked = matrix.clone();
+            masked.apply_mask(mask);
+            masked.format(mask);
+            masked.penalty()
+        };
+        let mask = (0..8).min_by_key(|mask| penalty(*mask)).unwrap_or(0);
+        matrix.apply_mask(mask);
+        matrix.format(mask);
+
+        Ok(QrCode { version, modules: matrix.modules })
+    }
+
+    // `width` staff spaces wide, without its quiet zone around it
+    pub fn to_markup(&self, width: f64) -> String {
+        let module = width / self.modules.len() as f64;
+        let mut path = vec![];
+        for (row, modules) in self.modules.iter().enumerate() {
+            let mut col = 0;
+            while col < modules.len() {
+                let run = modules[col..].iter().take_while(|dark| **dark).count();
+                if run > 0 {
+                    let (x, y, w) = (col as f64 * module, row as f64 * module, run as f64 * module);
+                    path.push(format!(
+                        "(moveto {:.3} {:.3}) (rlineto {:.3} 0) (rlineto 0 {:.3}) (rlineto {:.3} 0) (closepath)",
+                        x,
+                        0.0 - y,
+                        w,
+                        -module,
+                        -w
+                    ));
+                }
+                col += run.max(1);
+            }
+        }
+
+        format!(
+            "\\pad-around #{:.3} \\override #'(filled . #t) \\path #0 #'({})",
+            QUIET_ZONE as f64 * module,
+            path.join(" ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_computes_error_correction_codewords() {
+        // "HELLO WORLD" in version 1, at level M
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        assert_eq!(error_correction(&data, 10), [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+        assert_eq!(bch(0b00101, 0x537, 10) ^ 0x5412, 0b100000011001110);
+        assert_eq!(bch(7, 0x1f25, 12), 0b000111110010010100);
+    }
+
+    #[test]
+    fn it_encodes_a_link_as_a_qr_code() {
+        let code = QrCode::encode(b"https://www.youtube.com/watch?v=cQ4K1hMtRFA&t=42s").unwrap();
+        assert_eq!(code.version, 4);
+        assert_eq!(code.modules.len(), 33);
+        // the finder patterns' corners, and the timing pattern
+        assert!(code.modules[0][0] && code.modules[0][32] && code.modules[32][0] && !code.modules[7][7]);
+        assert_eq!(code.modules[6][8..25].iter().filter(|dark| **dark).count(), 9);
+        let markup = code.to_markup(10.0);
+        assert!(markup.starts_with("\\pad-around #1.212 \\override #'(filled . #t) \\path #0 #'((moveto 0.000 0.000)"));
+        assert!(QrCode::encode(&[b'a'; 214]).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+// every transposed voice is checked against the range of the
+// instrument that reads the book, e.g. an alto sax for the Eb book,
+// and the notes outside of it are a warning with the measures
+// they're in. a range is the lowest and highest written note, and
+// `ranges` in `templater.toml` sets it by transposition name:
+//
+//     [ranges]
+//     eb = "bes f'''"
+//     alto-flute = "c' c''''"
+//
+// the built in ones are the saxophones' for `bb` and `eb`, the
+// horn's for `f`, and the trombone's for `bass`. concert pitch is
+// read by anything, so it has none unless it's set. `\relative`
+// music is read in absolute pitch, see `absolute.rs`. music with
+// `\fixed`, `\transpose`, or `<< >>` isn't checked, and a chord's
+// first note stands for it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::absolute::to_absolute;
+use crate::errors::TemplaterError;
+use crate::models::{Song, TransposeText};
+use crate::music::{events, Event, Fraction};
+use crate::transpose::{parse_lilypond_pitch, Pitch};
+
+// (transposition, range), written pitch
+const DEFAULT_RANGES: [(&str, &str); 4] = [("bb", "bes f'''"), ("eb", "bes f'''"), ("f", "fis c'''"), ("bass", "e, bes'")];
+
+// measures listed in a warning, before "and 3 more"
+const MAX_MEASURES: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Range {
+    pub low: Pitch,
+    pub high: Pitch,
+}
+
+impl Range {
+    // for `--transpose bb`, `eb`, `f`, and `bass`
+    pub fn default_for(transpose: &str) -> Option<Range> {
+        let transpose = transpose.trim().to_lowercase();
+        DEFAULT_RANGES.iter().find(|(name, _)| *name == transpose).and_then(|(_, range)| range.parse().ok())
+    }
+}
+
+// e.g. "bes f'''"
+impl FromStr for Range {
+    type Err = TemplaterError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            TemplaterError::from_str(&format!(
+                "'{}' is not a range. Expected the lowest and highest written note, e.g. \"bes f'''\".",
+                input
+            ))
+        };
+
+        let pitches: Vec<&str> = input.split_whitespace().collect();
+        let [low, high] = pitches[..] else {
+            return Err(err());
+        };
+        let (low, high) = (parse_note(low).ok_or_else(err)?, parse_note(high).ok_or_else(err)?);
+        match low.semitones() <= high.semitones() {
+            true => Ok(Range { low, high }),
+            false => Err(err()),
+        }
+    }
+}
+
+impl TryFrom<String> for Range {
+    type Error = TemplaterError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} to {}", self.low.lilypond_name(), self.high.lilypond_name())
+    }
+}
+
+// a note's pitch, e.g. "bes''" or "as,!". `as` and `es` are aes and ees
+pub(crate) fn parse_note(pitch: &str) -> Option<Pitch> {
+    let pitch = pitch.split('=').next()?.trim_end_matches(['!', '?']);
+    let pitch = match pitch.as_bytes() {
+        [b'a' | b'e', b's', ..] => format!("{}e{}", &pitch[..1], &pitch[1..]),
+        _ => pitch.to_string(),
+    };
+
+    parse_lilypond_pitch(&pitch).ok()
+}
+
+// e.g. "measure 3", "measures 3 and 7", "measures 3, 7, and 12"
+fn measure_list(measures: &[u64]) -> String {
+    let mut listed: Vec<String> = measures.iter().take(MAX_MEASURES).map(u64::to_string).collect();
+    if measures.len() > MAX_MEASURES {
+        listed.push(format!("{} more", measures.len() - MAX_MEASURES));
+    }
+
+    match &listed[..] {
+        [measure] => format!("measure {}", measure),
+        [first, second] => format!("measures {} and {}", first, second),
+        [rest @ .., last] => format!("measures {}, and {}", rest.join(", "), last),
+        [] => String::new(),
+    }
+}
+
+// the written pitch (as semitones from the unmarked `c`) and lilypond's
+// measure number of every note in `music`, a pickup is measure 0
+fn written_notes(music: &str, target: &Pitch) -> Vec<(Pitch, i32, u64)> {
+    let mut notes = vec![];
+    let mut measure = Fraction::new(4, 4);
+    let mut position = Fraction::ZERO;
+    let mut number = 1;
+    let mut started = false;
+
+    for event in events(music) {
+        match event {
+            Event::Time { beats, unit, .. } => measure = Fraction::new(beats, unit),
+            Event::Partial { duration, .. } => {
+                if !started {
+                    number = 0;
+                }
+                position = measure - duration;
+            },
+            Event::Note { pitch, duration, .. } => {
+                started |= duration > Fraction::ZERO;
+                if let Some(pitch) = pitch.as_deref().and_then(parse_note) {
+                    let semitones = pitch.semitones() + target.semitones();
+                    let spelled = pitch.transpose(target);
+                    let written = Pitch { octave: (semitones - spelled.semitones()).div_euclid(12), ..spelled };
+                    notes.push((written, semitones, number));
+                }
+                position = position + duration;
+                while measure.num > 0 && position >= measure {
+                    position = position - measure;
+                    number += 1;
+                }
+            },
+            Event::BarCheck { .. } | Event::Tie { .. } | Event::Slur { .. } | Event::Modifier { .. } => {},
+        }
+    }
+
+    notes
+}
+
+// warnings for every voice of `song` in the book for `book`, e.g.
+// "Eb book: the melody goes down to a in measures 3 and 7, below bes"
+pub fn verify_range(song: &Song, book: &TransposeText, range: &Range) -> Vec<String> {
+    let transposition = song.transposition(book);
+    let Some(target) = transposition.lilypond_text.split_whitespace().nth(1).and_then(parse_note) else {
+        return vec![];
+    };
+
+    let mut warnings = vec![];
+    for voice in &song.voices {
+        let name = voice.name.as_ref().map_or(String::from("the melody"), |name| format!("voice {}", name));
+        if ["<<", "\\fixed", "\\transpose"].iter().any(|command| voice.notes.contains(command)) {
+            continue;
+        }
+        let Ok(music) = to_absolute(&voice.notes) else { continue };
+
+        let notes = written_notes(&music, &target);
+        let outside = |too_far: &dyn Fn(i32) -> bool, lowest: bool| {
+            let notes: Vec<&(Pitch, i32, u64)> = notes.iter().filter(|(_, semitones, _)| too_far(*semitones)).collect();
+            let extreme = match lowest {
+                true => notes.iter().min_by_key(|(_, semitones, _)| *semitones),
+                false => notes.iter().max_by_key(|(_, semitones, _)| *semitones),
+            };
+            let mut measures: Vec<u64> = notes.iter().map(|(_, _, measure)| *measure).collect();
+            measures.dedup();
+            extreme.map(|(pitch, ..)| (pitch.lilypond_name(), measure_list(&measures)))
+        };
+
+        let (low, high) = (range.low.semitones(), range.high.semitones());
+        if let Some((lowest, measures)) = outside(&|semitones| semitones < low, true) {
+            warnings.push(format!(
+                "{} book: {} goes down to {} in {}, below {}",
+                book.display_text,
+                name,
+                lowest,
+                measures,
+                range.low.lilypond_name()
+            ));
+        }
+        if let Some((highest, measures)) = outside(&|semitones| semitones > high, false) {
+            warnings.push(format!(
+                "{} book: {} goes up to {} in {}, above {}",
+                book.display_text,
+                name,
+                highest,
+                measures,
+                range.high.lilypond_name()
+            ));
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontmatter::Defaults;
+    use crate::transpose::Presets;
+    use std::path::Path;
+
+    fn song(melody: &str) -> Song {
+        let input = format!("title: Test\n---\n{{ \\numericTimeSignature {} }}\n", melody);
+        Song::parse_with(Path::new("test.ly"), &input, false, &Defaults::default(), &Presets::default()).unwrap()
+    }
+
+    #[test]
+    fn it_reads_a_range() {
+        let range: Range = "bes f'''".parse().unwrap();
+        assert_eq!(range.to_string(), "bes to f'''");
+        assert_eq!(Range::default_for("Eb"), Some(range));
+        assert_eq!(Range::default_for("c"), None);
+        assert!("f''' bes".parse::<Range>().is_err());
+        assert!("bes".parse::<Range>().is_err());
+    }
+
+    #[test]
+    fn it_warns_about_notes_outside_the_range() {
+        let eb = crate::transpose::transpose_text("eb").unwrap();
+        let range = Range::default_for("eb").unwrap();
+
+        // c' is a' for an alto sax
+        assert!(verify_range(&song("c'1 | e' | g' | c''"), &eb, &range).is_empty());
+        assert_eq!(
+            verify_range(&song("\\partial 4 b,4 | c'1 | c | c''' | c"), &eb, &range),
+            [
+                "Eb book: the melody goes down to gis in measures 0, 2, and 4, below bes",
+                "Eb book: the melody goes up to a''' in measure 3, above f'''",
+            ]
+        );
+        let relative = song("\\relative c'' { c1 | g' | c' }");
+        let range = Range { high: parse_note("c'''").unwrap(), ..range };
+        assert_eq!(
+            verify_range(&relative, &eb, &range),
+            ["Eb book: the melody goes up to a'''' in measures 2 and 3, above c'''"]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+// `templater release v1.2`: the book in every transposition (or the
+// `--transpose` ones) as pdfs in `dist/v1.2/`, named with the version,
+// e.g. `openbook-Bb-v1.2.pdf`. the "Recent Additions" page and a
+// `CHANGES.md` (see `bookdiff.rs`) are since the songs dir's last tag
+// before the version, or `--changes-since`, and `SHA256SUMS` has the
+// checksum of every file, for `sha256sum -c`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::book::{Book, BookOutput};
+use crate::bookdiff::{diff_books, BookDiff, Side};
+use crate::changelog::{git, is_date};
+use crate::errors::TemplaterError;
+use crate::lilypond::run_lilypond;
+use crate::models::{Config, DEFAULT_OUTPUT};
+use crate::utils::{add_to_file_stem, is_editor_file};
+use crate::warnings::Warnings;
+
+pub const DIST_DIR: &str = "dist";
+// concert, Bb, Eb, and bass clef, like the justfile's
+pub const RELEASE_TRANSPOSITIONS: &str = "c,bb,eb,bass";
+const CHANGES_FILE: &str = "CHANGES.md";
+const CHECKSUMS_FILE: &str = "SHA256SUMS";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub version: String,
+    // where everything is written, e.g. `dist/v1.2`
+    pub dir: PathBuf,
+    // a git ref or date, none if it's the first release
+    pub since: Option<String>,
+}
+
+impl Release {
+    pub fn new(config: &Config, version: &str) -> Result<Self, TemplaterError> {
+        if version.is_empty() || version.contains(|c: char| c.is_whitespace() || "/\\".contains(c)) {
+            let message = format!("'{}' can't be in a file name. Expected a version, e.g. v1.2.", version);
+            return Err(TemplaterError::from_str(&message));
+        }
+        if config.writes_to_stdout() {
+            return Err(TemplaterError::from_str("A release can't be written to stdout."));
+        }
+
+        let since = match &config.changes_since {
+            Some(since) => Some(since.clone()),
+            None => last_release(&config.songs_dir, version),
+        };
+        Ok(Release { version: version.to_string(), dir: config.out_dir.join(DIST_DIR).join(version), since })
+    }
+
+    // `transposed` is whether `--transpose` was given
+    pub fn config(&self, mut config: Config, transposed: bool) -> Config {
+        if !transposed {
+            config.transpose = String::from(RELEASE_TRANSPOSITIONS);
+        }
+        let output = config.output.take().unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT));
+        config.output = Some(add_to_file_stem(&output, &format!("-{}", self.version)));
+        config.out_dir = self.dir.clone();
+        config.changes_since = self.since.clone();
+        // lilypond is run by `package`
+        config.pdf = false;
+
+        config
+    }
+
+    // runs lilypond on the written books, then writes the changes and
+    // the checksums. the warnings are the changes' songs that didn't
+    // parse at the last release
+    pub fn package(&self, book: &Book, outputs: &[BookOutput]) -> Result<Warnings, TemplaterError> {
+        for output in outputs {
+            if output.lilypond_output.is_some() {
+                run_lilypond(&book.config.lilypond, &book.config.lilypond_flags, &output.path, &output.source_map)?;
+            }
+        }
+
+        let mut warnings = Warnings::default();
+        let songs_dir = &book.config.songs_dir;
+        match self.since.as_deref().map(|since| since_revision(songs_dir, since)).transpose()?.flatten() {
+            Some(revision) => {
+                let songs_dirs = book.config.songs_dirs();
+                let new = Side::Dirs(songs_dirs.iter().map(PathBuf::from).collect());
+                let diff = diff_books(&Side::Revision(revision), &new, &songs_dirs, &book.presets, &mut warnings)?;
+                let since = self.since.clone().unwrap_or_default();
+                let changes = BookDiff { old: since, new: self.version.clone(), ..diff };
+                let path = self.dir.join(CHANGES_FILE);
+                fs::write(&path, changes.to_string()).map_err(|e| TemplaterError::file(&path, e))?;
+            },
+            None => {
+                log!("[info]: there's no release before {}, so there's no {}", self.version, CHANGES_FILE);
+                // e.g. from an earlier build of the same version
+                let path = self.dir.join(CHANGES_FILE);
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(TemplaterError::file(&path, e)),
+                    _ => {},
+                }
+            },
+        }
+
+        let path = self.dir.join(CHECKSUMS_FILE);
+        fs::write(&path, checksums(&self.dir)?).map_err(|e| TemplaterError::file(&path, e))?;
+        Ok(warnings)
+    }
+}
+
+// the songs dir's last tag that isn't `version`, none without one
+fn last_release(songs_dir: &Path, version: &str) -> Option<String> {
+    let args = ["describe", "--tags", "--abbrev=0", "--exclude", version, "HEAD"];
+    git(songs_dir, &args, "for the last release").ok().map(|tag| tag.trim().to_string())
+}
+
+// a date is the last commit before it, none if there's none
+fn since_revision(songs_dir: &Path, since: &str) -> Result<Option<String>, TemplaterError> {
+    if !is_date(since) {
+        return Ok(Some(since.to_string()));
+    }
+
+    let before = format!("--before={}", since);
+    let commit = git(songs_dir, &["rev-list", "-1", &before, "HEAD"], &format!("before {}", since))?;
+    Ok(Some(commit.trim().to_string()).filter(|commit| !commit.is_empty()))
+}
+
+// "<sha-256>  <path>" for every file below `dir`, like `sha256sum`
+fn checksums(dir: &Path) -> Result<String, TemplaterError> {
+    let mut paths = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(next) = dirs.pop() {
+        for entry in fs::read_dir(&next).map_err(|e| TemplaterError::file(&next, e))? {
+            let path = entry.map_err(|e| TemplaterError::file(&next, e))?.path();
+            if is_editor_file(&path) || path == dir.join(CHECKSUMS_FILE) {
+                continue;
+            }
+            match path.is_dir() {
+                true => dirs.push(path),
+                false => paths.push(path),
+            }
+        }
+    }
+    paths.sort();
+
+    let mut out = String::new();
+    for path in paths {
+        let bytes = fs::read(&path).map_err(|e| TemplaterError::file(&path, e))?;
+        let name = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        let hex: String = sha256(&bytes).iter().map(|byte| format!("{:02x}", byte)).collect();
+        out.push_str(&format!("{}  {}\n", hex, name));
+    }
+
+    Ok(out)
+}
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98,
+    0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8,
+    0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819,
+    0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+    0xc67178f2,
+];
+
+// there's no crate for it here, and it's short
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] =
+        [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+    // a 1 bit, zeros, and the length in bits, to a multiple of 64 bytes
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            words[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = words[i - 15].rotate_right(7) ^ words[i - 15].rotate_right(18) ^ (words[i - 15] >> 3);
+            let s1 = words[i - 2].rotate_right(17) ^ words[i - 2].rotate_right(19) ^ (words[i - 2] >> 10);
+            words[i] = words[i - 16].wrapping_add(s0).wrapping_add(words[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(ROUND_CONSTANTS[i]).wrapping_add(words[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (bytes, value) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(data: &[u8]) -> String {
+        sha256(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn it_hashes_like_sha256sum() {
+        assert_eq!(hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn it_names_a_releases_books() {
+        let release = Release { version: String::from("v1.2"), dir: PathBuf::from("dist/v1.2"), since: None };
+        let config = release.config(Config { pdf: true, ..Config::default() }, false);
+        assert_eq!(config.transpose, RELEASE_TRANSPOSITIONS);
+        assert_eq!(config.output.as_deref(), Some(Path::new("openbook-{key}-v1.2.ly")));
+        assert_eq!(config.out_dir, Path::new("dist/v1.2"));
+        assert!(!config.pdf);
+
+        let output = Some(PathBuf::from("book-{key}.ly"));
+        let config = release.config(Config { transpose: String::from("bb"), output, ..Config::default() }, true);
+        assert_eq!(config.transpose, "bb");
+        assert_eq!(config.output.as_deref(), Some(Path::new("book-{key}-v1.2.ly")));
+
+        assert!(Release::new(&Config::default(), "v1.2/rc").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+// every output format is a `Renderer`. the songs are parsed and
+// filtered once by `BookBuilder`, then each renderer turns them
+// into its files: the lilypond books, the midi book, a lyrics book
+// (`lyrics.rs`), a braille book (`braille.rs`), an html index
+// (`html.rs`), or the lead sheets. a new format only
+// needs a new `Renderer`, and a line in `Book::renderers`.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use minijinja::{context, Value};
+use rayon::prelude::*;
+
+use crate::book::Book;
+use crate::cache::BookpartCache;
+use crate::errors::TemplaterError;
+use crate::guitar::Voicings;
+use crate::lilypond::SourceMap;
+use crate::license::render_licenses;
+use crate::manuscript::render_blank_pages;
+use crate::models::{BookMode, Config, Song, TemplaterConfig};
+use crate::order::SortOrder;
+use crate::profiles::template_for;
+use crate::provenance::render_sources;
+use crate::toc::{render_index, render_toc};
+use crate::utils::this_year;
+use crate::volumes::{roman_numeral, volume_letters};
+use crate::capitalize_first_letter;
+
+// which files a renderer writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outputs {
+    // one for every transposition and volume
+    PerTransposition,
+    // one for every volume, in the first transposition
+    PerVolume,
+    // one for the whole book
+    Single,
+    // one for every song, in every transposition
+    PerSong,
+}
+
+// what running lilypond on an output makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LilypondOutput {
+    Pdf,
+    Midi,
+}
+
+pub trait Renderer {
+    fn outputs(&self) -> Outputs;
+
+    // `volume` is only given for a split book
+    fn output_path(
+        &self,
+        config: &Config,
+        conf: &TemplaterConfig,
+        volume: Option<usize>,
+    ) -> Result<PathBuf, TemplaterError>;
+
+    // for `Outputs::PerSong`, the song's file in `output_path`
+    fn song_path(&self, dir: PathBuf, song: &Song) -> PathBuf {
+        dir.join(format!("{}.ly", song.midi_name()))
+    }
+
+    // `songs` are the volume's songs, the one song, or all of them. returns which
+    // lines of the output came from which song.
+    fn render(
+        &self,
+        book: &Book,
+        out: &mut dyn Write,
+        conf: &TemplaterConfig,
+        songs: &[Song],
+        volume: Option<usize>,
+    ) -> Result<SourceMap, TemplaterError>;
+
+    // none for a file lilypond can't read
+    fn lilypond_output(&self) -> Option<LilypondOutput> {
+        None
+    }
+}
+
+pub(crate) fn count_lines(s: &str) -> usize {
+    s.matches('\n').count()
+}
+
+// `guitar` and `voicings`, for the headers of a guitar book, and
+// `layout_profile` for the `paper` of a book with `--layout`
+fn header_context(book: &Book, conf: &TemplaterConfig) -> Value {
+    context! {
+        guitar => book.voicings.is_some(),
+        voicings => book.voicings.as_ref().map(Voicings::context).unwrap_or_default(),
+        layout_profile => conf.layout.map(|layout| layout.context()),
+    }
+}
+
+// the lead sheets, or only the chord grids in a chords-only book, the
+// lead sheets with fret diagrams in a guitar book, or the roman numerals
+// in a numbers chart
+pub struct LilyPondBook;
+
+impl LilyPondBook {
+    // a song's bookpart, and whether it came from the cache
+    fn render_song(
+        &self,
+        book: &Book,
+        song: &Song,
+        conf: &TemplaterConfig,
+        templates_hash: u64,
+    ) -> Result<(String, bool), TemplaterError> {
+        let key = BookpartCache::key(song, templates_hash, conf, book.config.mode);
+        if let Some(cache) = &book.cache {
+            if let Some(bookpart) = cache.get(key)? {
+                return Ok((bookpart, true));
+            }
+        }
+
+        log!("Handling {}", song.title);
+        let bookpart = match book.config.mode {
+            BookMode::ChordsOnly => song.render_chord_sheet(&book.templates, conf),
+            BookMode::Guitar => song.render_guitar(&book.templates, conf),
+            BookMode::Numbers => song.render_numbers(&book.templates, conf),
+            _ => song.render(&book.templates, conf),
+        };
+        let bookpart = bookpart
+            .map_err(|e| TemplaterError::from_str(&format!("{}: {}", song.path.display(), e)))?;
+
+        if let Some(cache) = &book.cache {
+            cache.put(key, &bookpart)?;
+        }
+
+        Ok((bookpart, false))
+    }
+}
+
+impl Renderer for LilyPondBook {
+    fn outputs(&self) -> Outputs {
+        Outputs::PerTransposition
+    }
+
+    fn output_path(
+        &self,
+        config: &Config,
+        conf: &TemplaterConfig,
+        volume: Option<usize>,
+    ) -> Result<PathBuf, TemplaterError> {
+        Ok(config.output_path(&conf.transpose_text, volume))
+    }
+
+    fn render(
+        &self,
+        book: &Book,
+        out: &mut dyn Write,
+        conf: &TemplaterConfig,
+        songs: &[Song],
+        volume: Option<usize>,
+    ) -> Result<SourceMap, TemplaterError> {
+        let mut source_map = SourceMap::default();
+        let mut line = 1;
+
+        // a setlist's cover replaces the title page, ToC, and indices
+        if let Some(setlist) = &book.setlist {
+            let transpose_display = capitalize_first_letter(&conf.transpose_text.display_text);
+            let cover = setlist.render_cover(
+                &book.templates,
+                songs,
+                &transpose_display,
+                book.config.metadata.context(),
+                header_context(book, conf),
+            )?;
+            write!(out, "{}", cover)?;
+            line += count_lines(&cover);
+        } else {
+            let intro = book.templates.render(
+                &template_for(&book.templates, conf.layout, "intro"),
+                context! {
+                    transpose_display => capitalize_first_letter(&conf.transpose_text.display_text),
+                    num_tunes => songs.len(),
+                    volume => roman_numeral(volume.unwrap_or(1)),
+                    volume_letters => volume.map(|_| volume_letters(songs)).unwrap_or_default(),
+                    chords_only => book.config.mode == BookMode::ChordsOnly,
+                    numbers_chart => book.config.mode == BookMode::Numbers,
+                    metadata => book.config.metadata.context(),
+                    ..header_context(book, conf)
+                },
+            )?;
+            write!(out, "{}", intro)?;
+            line += count_lines(&intro);
+
+            // a setlist is in its own order
+            let sort = match book.setlist {
+                Some(_) => SortOrder::Title,
+                None => book.config.sort,
+            };
+            let toc = render_toc(&book.templates, songs, book.config.toc_form, sort, &conf.transpose_text)?;
+            write!(out, "{}", toc)?;
+            line += count_lines(&toc);
+
+            let changes = book.changes.as_ref().map(|changes| changes.render(&book.templates, songs)).transpose()?;
+            if let Some(changes) = changes.flatten() {
+                write!(out, "{}", changes)?;
+                line += count_lines(&changes);
+            }
+
+            for kind in &book.config.indexes {
+                if let Some(index) = render_index(&book.templates, *kind, songs, &conf.transpose_text)? {
+                    write!(out, "{}", index)?;
+                    line += count_lines(&index);
+                }
+            }
+        }
+
+        // songs are rendered in parallel, but written in order
+        let templates_hash = book.templates.hash();
+        let bookparts: Vec<(String, bool)> = songs
+            .par_iter()
+            .map(|song| self.render_song(book, song, conf, templates_hash))
+            .collect::<Result<_, _>>()?;
+
+        if book.cache.is_some() {
+            let cached = bookparts.iter().filter(|(_, cached)| *cached).count();
+            log!("[info]: {} of {} songs were already rendered", cached, bookparts.len());
+        }
+        let bookparts = bookparts.into_iter().map(|(bookpart, _)| bookpart);
+
+        let mut previous_letter = None;
+        for (song, bookpart) in songs.iter().zip(bookparts) {
+            let letter = song.letter();
+            let dividers = book.config.dividers
+                && matches!(book.config.mode, BookMode::Full | BookMode::Guitar)
+                && book.setlist.is_none()
+                && book.config.sort == SortOrder::Title;
+            if dividers && previous_letter.as_ref() != Some(&letter) {
+                let divider = book.templates.render("divider", context! { letter => letter.as_str() })?;
+                write!(out, "{}", divider)?;
+                line += count_lines(&divider);
+            }
+            previous_letter = Some(letter);
+
+            write!(out, "{}", bookpart)?;
+
+            let lines = count_lines(&bookpart);
+            source_map.push(line..line + lines, song.path.clone());
+            line += lines;
+        }
+
+        if book.setlist.is_none() {
+            if let Some(sources) = render_sources(&book.templates, songs)? {
+                write!(out, "{}", sources)?;
+            }
+            if let Some(licenses) = render_licenses(&book.templates, songs, this_year())? {
+                write!(out, "{}", licenses)?;
+            }
+        }
+        if let Some(pages) = render_blank_pages(&book.templates, &book.config)? {
+            write!(out, "{}", pages)?;
+        }
+
+        // }} escapes } apparently
+        writeln!(out, "}}")?;
+
+        Ok(source_map)
+    }
+
+    fn lilypond_output(&self) -> Option<LilypondOutput> {
+        Some(LilypondOutput::Pdf)
+    }
+}
+
+// every song as its own `\book`, so lilypond writes one midi
+// file per song. these aren't cached, they're quick to render.
+pub struct MidiBook;
+
+impl Renderer for MidiBook {
+    fn outputs(&self) -> Outputs {
+        Outputs::Single
+    }
+
+    fn output_path(
+        &self,
+        config: &Config,
+        _conf: &TemplaterConfig,
+        _volume: Option<usize>,
+    ) -> Result<PathBuf, TemplaterError> {
+        config.midi_path()
+    }
+
+    fn render(
+        &self,
+        book: &Book,
+        out: &mut dyn Write,
+        _conf: &TemplaterConfig,
+        songs: &[Song],
+        _volume: Option<usize>,
+    ) -> Result<SourceMap, TemplaterError> {
+        let mut source_map = SourceMap::default();
+        let mut line = 1;
+
+        let header = book.templates.render("midi-header", context! {})?;
+        write!(out, "{}", header)?;
+        line += count_lines(&header);
+
+        for song in songs {
+            let midi = song
+                .render_midi(&book.templates)
+                .map_err(|e| TemplaterError::from_str(&format!("{}: {}", song.path.display(), e)))?;
+            write!(out, "\n{}", midi)?;
+            line += 1;
+
+            let lines = count_lines(&midi);
+            source_map.push(line..line + lines, song.path.clone());
+            line += lines;
+        }
+
+        Ok(source_map)
+    }
+
+    fn lilypond_output(&self) -> Option<LilypondOutput> {
+        Some(LilypondOutput::Midi)
+    }
+}
+
+// every song as its own lead sheet in a folder next to the book,
+// e.g. `openbook-Bb-songs/042-misty.ly`, to compile and share one
+// song without the whole book. the bookparts are the book's, so
+// they're cached the same way.
+pub struct SongSheets;
+
+impl Renderer for SongSheets {
+    fn outputs(&self) -> Outputs {
+        Outputs::PerSong
+    }
+
+    fn output_path(
+        &self,
+        config: &Config,
+        conf: &TemplaterConfig,
+        _volume: Option<usize>,
+    ) -> Result<PathBuf, TemplaterError> {
+        Ok(config.sheets_dir(&conf.transpose_text))
+    }
+
+    fn render(
+        &self,
+        book: &Book,
+        out: &mut dyn Write,
+        conf: &TemplaterConfig,
+        songs: &[Song],
+        _volume: Option<usize>,
+    ) -> Result<SourceMap, TemplaterError> {
+        let mut source_map = SourceMap::default();
+        let mut line = 1;
+
+        let templates_hash = book.templates.hash();
+        for song in songs {
+            let header = book.templates.render(
+                "sheet-header",
+                context! {
+                    title => song.title.as_str(),
+                    transpose_display => capitalize_first_letter(&conf.transpose_text.display_text),
+                    ..header_context(book, conf)
+                },
+            )?;
+            write!(out, "{}", header)?;
+            line += count_lines(&header);
+
+            let (bookpart, _) = LilyPondBook.render_song(book, song, conf, templates_hash)?;
+            write!(out, "{}", bookpart)?;
+
+            let lines = count_lines(&bookpart);
+            source_map.push(line..line + lines, song.path.clone());
+            line += lines;
+
+            writeln!(out, "}}")?;
+        }
+
+        Ok(source_map)
+    }
+
+    fn lilypond_output(&self) -> Option<LilypondOutput> {
+        Some(LilypondOutput::Pdf)
+    }
+}
@@ -0,0 +1,416 @@
+// `\repeat volta` and its `\alternative` endings, and repeat bar
+// lines, in a song's chords and voices. lilypond renders a broken
+// repeat wrong, or not at all, so these are errors for the build and
+// `templater check`, with the measure they're in:
+//
+// - a `\repeat volta` whose music isn't closed
+// - an `\alternative` that isn't right after a `\repeat volta`'s
+//   music (or at the end of it), that has no endings, or that has
+//   more endings than the repeat is played
+//
+// and a warning, since lilypond prints it anyway:
+//
+// - a start repeat bar line, e.g. `\bar ".|:"`, that isn't ended by
+//   an end repeat, e.g. `\bar ":|."`, or the end of the `\repeat volta`
+//   it's in, before the next one or the end
+//
+// the jumps, e.g. `[D.S. al Coda]` or "D.C. al Fine" in a markup,
+// are what `--form-summary` adds to a song's form, see `form.rs`.
+
+use crate::models::Song;
+use crate::music::{events, tokenize, Event, Fraction, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repeat {
+    // how often it's played
+    pub times: u32,
+    pub line: usize,
+    // of its `\alternative`, 0 without one
+    pub endings: usize,
+}
+
+// what a `{` opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    Music,
+    // the music of `repeats[i]`
+    Repeat(usize),
+    // the endings of `repeats[i]`
+    Alternative(usize),
+}
+
+// the text of a string token, e.g. `:|.` for `":|."`
+pub(crate) fn literal(music: &str, end: usize) -> &str {
+    let quoted = music[..end].strip_suffix('"').unwrap_or_default();
+    quoted.rfind('"').map_or("", |start| &quoted[start + 1..])
+}
+
+// a repeat, an ending, or a bar line, for writing the music in another
+// format, see `abc.rs` and `ireal.rs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BarMark {
+    StartRepeat,
+    EndRepeat,
+    // e.g. `\bar ":..:"`
+    EndStartRepeat,
+    // the start of an `\alternative`'s ending, 1 for the first
+    Ending(usize),
+    Double,
+    Final,
+}
+
+// what a `{` opened, for `bar_marks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opened {
+    Music,
+    Repeat { alternative: bool },
+    // the endings so far
+    Alternative(usize),
+    Ending,
+}
+
+// what's wrong with a song's repeats
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepeatProblems {
+    // the `\repeat volta`s and `\alternative`s
+    pub errors: Vec<String>,
+    // the repeat bar lines
+    pub warnings: Vec<String>,
+}
+
+// every `\repeat volta` in `music`, and what's wrong with the repeats,
+// by the line it's on. (line, problem, whether it's an error)
+fn scan(music: &str) -> (Vec<Repeat>, Vec<(usize, String, bool)>) {
+    let tokens = tokenize(music);
+    let word = |i: usize| match tokens.get(i) {
+        Some((Token::Word(word), ..)) => Some(word.as_str()),
+        _ => None,
+    };
+
+    let mut repeats: Vec<Repeat> = vec![];
+    let mut problems = vec![];
+    let mut blocks: Vec<Block> = vec![];
+    // the repeat whose music the last token closed
+    let mut closed = None;
+    // where the last start repeat bar line is, until it's ended
+    let mut start_bar = None;
+    let mut i = 0;
+    while let Some((token, line, _)) = tokens.get(i) {
+        i += 1;
+        let just_closed = closed.take();
+        match token {
+            Token::Word(command) if command == "\\repeat" && word(i) == Some("volta") => {
+                let Some(times) = word(i + 1).and_then(|times| times.parse().ok()) else { continue };
+                i += 2;
+                if let Some((Token::Open, ..)) = tokens.get(i) {
+                    i += 1;
+                    blocks.push(Block::Repeat(repeats.len()));
+                }
+                repeats.push(Repeat { times, line: *line, endings: 0 });
+            },
+            // right after the repeat's music, or at the end of it
+            Token::Word(command) if command == "\\alternative" => {
+                let at_end = match blocks.last() {
+                    Some(Block::Repeat(repeat)) => Some(*repeat),
+                    _ => None,
+                };
+                let Some(repeat) = just_closed.or(at_end) else {
+                    problems.push((*line, String::from("the \\alternative isn't after a \\repeat volta"), true));
+                    continue;
+                };
+                match tokens.get(i) {
+                    Some((Token::Open, ..)) => {
+                        i += 1;
+                        blocks.push(Block::Alternative(repeat));
+                    },
+                    _ => problems.push((*line, String::from("the \\alternative has no endings"), true)),
+                }
+            },
+            Token::Word(command) if command == "\\bar" => {
+                let Some((Token::Literal, _, end)) = tokens.get(i) else { continue };
+                // e.g. ":|." ends a repeat, ".|:" starts one, and ":..:" is both
+                let bar = literal(music, *end);
+                if bar.starts_with(':') {
+                    start_bar = None;
+                }
+                if bar.ends_with(':') {
+                    if let Some(start) = start_bar.replace(*line) {
+                        problems.push((start, String::from("the start repeat isn't ended before the next one"), false));
+                    }
+                }
+            },
+            // every ending of an `\alternative` is music of its own
+            Token::Open => {
+                if let Some(Block::Alternative(repeat)) = blocks.last() {
+                    repeats[*repeat].endings += 1;
+                }
+                blocks.push(Block::Music);
+            },
+            Token::Close => match blocks.pop() {
+                Some(Block::Repeat(repeat)) => {
+                    // e.g. a `\bar "[|:"` at its start, the volta's end ends it
+                    if start_bar.is_some_and(|start| start >= repeats[repeat].line) {
+                        start_bar = None;
+                    }
+                    closed = Some(repeat);
+                },
+                Some(Block::Alternative(repeat)) => {
+                    let Repeat { times, line, endings } = repeats[repeat];
+                    if endings == 0 {
+                        problems.push((line, String::from("the \\alternative has no endings"), true));
+                    } else if endings > times as usize {
+                        let problem = format!("has {} endings, but it's played {} times", endings, times);
+                        problems.push((line, format!("the \\repeat volta {}", problem), true));
+                    }
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    for block in blocks {
+        if let Block::Repeat(repeat) = block {
+            problems.push((repeats[repeat].line, String::from("the \\repeat volta isn't closed"), true));
+        }
+    }
+    if let Some(start) = start_bar {
+        problems.push((start, String::from("the start repeat isn't ended"), false));
+    }
+
+    problems.sort_by_key(|(line, ..)| *line);
+    (repeats, problems)
+}
+
+// lilypond's measure number of the first note on every line, the
+// pickup is measure 0
+fn measures(music: &str) -> Vec<(usize, u64)> {
+    let mut measures = vec![];
+    let mut measure = Fraction::new(4, 4);
+    let mut position = Fraction::ZERO;
+    let mut number = 1;
+    let mut started = false;
+
+    for event in events(music) {
+        match event {
+            Event::Time { beats, unit, .. } => measure = Fraction::new(beats, unit),
+            Event::Partial { duration, .. } => {
+                if !started {
+                    number = 0;
+                }
+                position = measure - duration;
+            },
+            Event::Note { duration, line, .. } => {
+                started |= duration > Fraction::ZERO;
+                if measures.last().is_none_or(|(last, _)| *last < line) {
+                    measures.push((line, number));
+                }
+                position = position + duration;
+                while measure.num > 0 && position >= measure {
+                    position = position - measure;
+                    number += 1;
+                }
+            },
+            Event::BarCheck { .. } | Event::Tie { .. } | Event::Slur { .. } | Event::Modifier { .. } => {},
+        }
+    }
+
+    measures
+}
+
+// every repeat, ending, and repeat or double bar line in `music`, by
+// the byte it's read up to, like `music::spanned_events`
+pub(crate) fn bar_marks(music: &str) -> Vec<(usize, BarMark)> {
+    let tokens = tokenize(music);
+    let word = |i: usize| match tokens.get(i) {
+        Some((Token::Word(word), ..)) => Some(word.as_str()),
+        _ => None,
+    };
+    let is_open = |i: usize| matches!(tokens.get(i), Some((Token::Open, ..)));
+
+    let mut marks = vec![];
+    let mut opened: Vec<Opened> = vec![];
+    let mut i = 0;
+    while let Some((token, _, end)) = tokens.get(i) {
+        i += 1;
+        match token {
+            Token::Word(command) if command == "\\repeat" && word(i) == Some("volta") && is_open(i + 2) => {
+                i += 3;
+                opened.push(Opened::Repeat { alternative: false });
+                marks.push((tokens[i - 1].2, BarMark::StartRepeat));
+            },
+            Token::Word(command) if command == "\\alternative" && is_open(i) => {
+                if let Some(Opened::Repeat { alternative }) = opened.last_mut() {
+                    *alternative = true;
+                }
+                i += 1;
+                opened.push(Opened::Alternative(0));
+            },
+            Token::Word(command) if command == "\\bar" => {
+                let Some((Token::Literal, _, end)) = tokens.get(i) else { continue };
+                let mark = match literal(music, *end) {
+                    bar if bar.starts_with(':') && bar.ends_with(':') => BarMark::EndStartRepeat,
+                    bar if bar.starts_with(':') => BarMark::EndRepeat,
+                    bar if bar.ends_with(':') => BarMark::StartRepeat,
+                    "|." => BarMark::Final,
+                    "||" => BarMark::Double,
+                    _ => continue,
+                };
+                marks.push((*end, mark));
+            },
+            Token::Open => match opened.last_mut() {
+                Some(Opened::Alternative(endings)) => {
+                    *endings += 1;
+                    marks.push((*end, BarMark::Ending(*endings)));
+                    opened.push(Opened::Ending);
+                },
+                _ => opened.push(Opened::Music),
+            },
+            Token::Close => match opened.pop() {
+                // back to the start, unless its endings are next
+                Some(Opened::Repeat { alternative: false }) if word(i) != Some("\\alternative") => {
+                    marks.push((*end, BarMark::EndRepeat));
+                },
+                // every ending but the last
+                Some(Opened::Ending) if is_open(i) => marks.push((*end, BarMark::EndRepeat)),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    marks
+}
+
+// what's wrong with the repeats of `music`, e.g. "melody: the \\repeat
+// volta in measure 9 isn't closed"
+pub fn verify_music(name: &str, music: &str) -> RepeatProblems {
+    let (_, found) = scan(music);
+    let mut problems = RepeatProblems::default();
+    if found.is_empty() {
+        return problems;
+    }
+
+    let measures = measures(music);
+    for (line, problem, error) in found {
+        // the measure of the next note, since e.g. a `\repeat` is before its music
+        let measure = measures.iter().find(|(at, _)| *at >= line).or(measures.last());
+        let (subject, rest) = problem.split_at(problem.find(" isn't").or(problem.find(" has")).unwrap_or(0));
+        let problem = match measure {
+            Some((_, measure)) => format!("{}: {} in measure {}{}", name, subject, measure, rest),
+            None => format!("{}: {}", name, problem),
+        };
+        match error {
+            true => problems.errors.push(problem),
+            false => problems.warnings.push(problem),
+        }
+    }
+
+    problems
+}
+
+// the problems with every repeat of the song's chords, chord variants,
+// and voices
+pub fn verify_repeats(song: &Song) -> RepeatProblems {
+    let chords = [(String::from("chords"), song.chords.as_str())].into_iter();
+    let variants = song.variants.iter().map(|variant| (format!("chords {}", variant.name), variant.chords.as_str()));
+    let voices = song.voices.iter().map(|voice| {
+        (voice.name.as_ref().map_or(String::from("melody"), |name| format!("voice {}", name)), voice.notes.as_str())
+    });
+
+    let mut problems = RepeatProblems::default();
+    for (name, music) in chords.chain(variants).chain(voices) {
+        let RepeatProblems { errors, warnings } = verify_music(&name, music);
+        problems.errors.extend(errors);
+        problems.warnings.extend(warnings);
+    }
+    problems
+}
+
+// the jumps in `music`, e.g. "D.S. al Coda" or "D.C. al Fine", from
+// their marks
+pub fn jumps(music: &str) -> Vec<String> {
+    let tokens = tokenize(music);
+    let mut jumps = vec![];
+    for (i, (token, _, end)) in tokens.iter().enumerate() {
+        let Token::Literal = token else { continue };
+        let text = literal(music, *end).trim();
+        if !text.starts_with("D.S.") && !text.starts_with("D.C.") {
+            continue;
+        }
+
+        // e.g. `"D.S. al " \coda`
+        let mut jump = text.replace(" Al ", " al ");
+        if let Some(start) = jump.strip_suffix(" Al").or(jump.strip_suffix(" al")) {
+            let coda = match tokens.get(i + 1) {
+                Some((Token::Word(glyph), ..)) if glyph == "\\coda" => true,
+                Some((Token::Word(glyph), ..)) if glyph == "\\musicglyph" => {
+                    tokens.get(i + 2).is_some_and(|(_, _, end)| music[..*end].ends_with("coda\""))
+                },
+                _ => false,
+            };
+            if coda {
+                jump = format!("{} al Coda", start);
+            }
+        }
+        if !jumps.contains(&jump) {
+            jumps.push(jump);
+        }
+    }
+
+    jumps
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_accepts_repeats_with_their_endings() {
+        let music = "\\repeat volta 2 {\n  c1 | d |\n} \\alternative {\n  { e1 }\n  { f1 }\n}\n\
+                     \\repeat volta 3 { g1 \\alternative { \\volta 1,2 { a1 } \\volta 3 { b1 } } }\n\
+                     \\bar \".|:\" c1 | d \\bar \":..:\" e | f \\bar \":|.\"\n\
+                     \\repeat volta 2 { \\bar \"[|:\" c1 | d }";
+        assert_eq!(verify_music("melody", music), RepeatProblems::default());
+        assert_eq!(scan(music).0[0], Repeat { times: 2, line: 1, endings: 2 });
+    }
+
+    #[test]
+    fn it_finds_broken_repeats() {
+        let music = [
+            "\\partial 4 g4 |",
+            "\\repeat volta 2 {",
+            "  c1 | d |",
+            "} \\alternative {",
+            "  { e1 }",
+            "  { f1 }",
+            "  { g1 }",
+            "}",
+            "c1 \\alternative { d1 }",
+            "\\repeat volta 2 { e1 | f |",
+            "\\bar \"[|:\" c1 | d |",
+        ]
+        .join("\n");
+        let problems = verify_music("melody", &music);
+        assert_eq!(
+            problems.errors,
+            [
+                "melody: the \\repeat volta in measure 1 has 3 endings, but it's played 2 times",
+                "melody: the \\alternative in measure 6 isn't after a \\repeat volta",
+                "melody: the \\repeat volta in measure 8 isn't closed",
+            ]
+        );
+        assert_eq!(problems.warnings, ["melody: the start repeat in measure 10 isn't ended"]);
+        assert_eq!(
+            verify_music("chords", "\\repeat volta 2 { c1 } \\alternative { }").errors,
+            ["chords: the \\alternative in measure 1 has no endings"]
+        );
+    }
+
+    #[test]
+    fn it_finds_the_jumps() {
+        let music = "c1 \\mark \\markup { \\italic \"D.S. al Coda\" } | d1_\\markup { \"D.S. Al \" \\coda } |\
+                     e1^\\markup { \"D.C. al\" \\musicglyph #\"scripts.coda\" } f1^\"D.C. al Fine\"";
+        assert_eq!(jumps(music), ["D.S. al Coda", "D.C. al Coda", "D.C. al Fine"]);
+    }
+}
@@ -0,0 +1,188 @@
+// `templater new "Song Title"`: writes a new song file from
+// `templates/song`, with the frontmatter filled in, so a new song
+// doesn't start out as a copy of another one with its old composer.
+
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use minijinja::context;
+
+use crate::errors::TemplaterError;
+use crate::keys::Key;
+use crate::models::Config;
+use crate::templates::Templates;
+use crate::utils::slugify;
+
+// the fields that can be given as `field=value`, in prompt order
+pub const STUB_FIELDS: [&str; 4] = ["composer", "meter", "bpm", "key"];
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SongStub {
+    pub title: String,
+    pub composer: Option<String>,
+    pub meter: Option<String>,
+    pub bpm: Option<u32>,
+    // e.g. `ees \major`
+    pub key: Option<String>,
+}
+
+// e.g. `ees \minor`, see `Key::from_str` for what's accepted
+fn parse_key(input: &str) -> Result<String, TemplaterError> {
+    Ok(input.parse::<Key>()?.lilypond_name())
+}
+
+impl SongStub {
+    pub fn new<S: Into<String>>(title: S) -> Self {
+        SongStub { title: title.into(), ..Default::default() }
+    }
+
+    pub fn is_set(&self, field: &str) -> bool {
+        match field {
+            "composer" => self.composer.is_some(),
+            "meter" => self.meter.is_some(),
+            "bpm" => self.bpm.is_some(),
+            "key" => self.key.is_some(),
+            _ => false,
+        }
+    }
+
+    // an empty value leaves the field blank
+    pub fn set(&mut self, field: &str, value: &str) -> Result<(), TemplaterError> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(());
+        }
+
+        match field {
+            "composer" => self.composer = Some(value.to_string()),
+            "meter" => self.meter = Some(value.to_string()),
+            "bpm" => {
+                let bpm = value
+                    .parse()
+                    .map_err(|_| TemplaterError::from_str(&format!("bpm '{}' is not a number.", value)))?;
+                self.bpm = Some(bpm);
+            },
+            "key" => self.key = Some(parse_key(value)?),
+            other => {
+                return Err(TemplaterError::from_str(&format!(
+                    "Unknown field '{}'. Expected one of: {}.",
+                    other,
+                    STUB_FIELDS.join(", ")
+                )))
+            },
+        }
+
+        Ok(())
+    }
+
+    // `composer=Duke Ellington`
+    pub fn set_arg(&mut self, arg: &str) -> Result<(), TemplaterError> {
+        let (field, value) = arg
+            .split_once('=')
+            .ok_or_else(|| TemplaterError::from_str(&format!("Expected field=value, got '{}'.", arg)))?;
+        self.set(field.trim(), value)
+    }
+
+    // asks for every field that wasn't given on the command line
+    pub fn prompt<R: BufRead, W: Write>(&mut self, input: &mut R, out: &mut W) -> Result<(), TemplaterError> {
+        for field in STUB_FIELDS {
+            if self.is_set(field) {
+                continue;
+            }
+
+            // asked again until the value parses
+            loop {
+                write!(out, "{}: ", field)?;
+                out.flush()?;
+
+                let mut line = String::new();
+                if input.read_line(&mut line)? == 0 {
+                    return Ok(());
+                }
+                match self.set(field, &line) {
+                    Ok(()) => break,
+                    Err(e) => writeln!(out, "{}", e)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // e.g. `songs/take-the-a-train.ly`
+    pub fn path(&self, songs_dir: &Path) -> PathBuf {
+        songs_dir.join(format!("{}.ly", slugify(&self.title)))
+    }
+
+    pub fn render(&self, templates: &Templates) -> Result<String, TemplaterError> {
+        templates.render(
+            "song",
+            context! {
+                title => self.title.as_str(),
+                composer => self.composer.as_deref().unwrap_or_default(),
+                meter => self.meter.as_deref().unwrap_or_default(),
+                bpm => self.bpm.map(|bpm| bpm.to_string()).unwrap_or_default(),
+                key => self.key.as_deref().unwrap_or("c \\major"),
+            },
+        )
+    }
+}
+
+// never overwrites an existing song. returns the new file's path.
+pub fn new_song(config: &Config, stub: &SongStub) -> Result<PathBuf, TemplaterError> {
+    if slugify(&stub.title).is_empty() {
+        return Err(TemplaterError::from_str("usage: templater new \"Song Title\" [field=value ...]"));
+    }
+
+    let templates = Templates::load(config)?;
+    let song = stub.render(&templates)?;
+
+    let path = stub.path(&config.songs_dir);
+    create_song_file(&path, &song)?;
+
+    Ok(path)
+}
+
+// also used by `templater import`
+pub fn create_song_file(path: &Path, song: &str) -> Result<(), TemplaterError> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => {
+            TemplaterError::from_str(&format!("{} already exists.", path.display()))
+        },
+        _ => TemplaterError::file(path, e),
+    })?;
+    file.write_all(song.as_bytes()).map_err(|e| TemplaterError::file(path, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_keys() {
+        assert_eq!(parse_key("Eb").unwrap(), "ees \\major");
+        assert_eq!(parse_key("ebm").unwrap(), "ees \\minor");
+        assert_eq!(parse_key("F# minor").unwrap(), "fis \\minor");
+        assert_eq!(parse_key("\\key bes \\major").unwrap(), "bes \\major");
+        assert!(parse_key("h").is_err());
+        assert!(parse_key("c blues").is_err());
+    }
+
+    #[test]
+    fn it_prompts_for_missing_fields() {
+        let mut stub = SongStub::new("Take the A Train");
+        stub.set_arg("composer=Billy Strayhorn").unwrap();
+
+        let mut input = "Medium Swing\nfast\n160\n\n".as_bytes();
+        let mut out = vec![];
+        stub.prompt(&mut input, &mut out).unwrap();
+
+        assert_eq!(stub.composer.as_deref(), Some("Billy Strayhorn"));
+        assert_eq!(stub.meter.as_deref(), Some("Medium Swing"));
+        assert_eq!(stub.bpm, Some(160));
+        assert_eq!(stub.key, None);
+        assert_eq!(stub.path(Path::new("songs")), PathBuf::from("songs/take-the-a-train.ly"));
+        assert!(String::from_utf8(out).unwrap().contains("bpm 'fast' is not a number."));
+    }
+}
@@ -0,0 +1,66 @@
+// section markers in a song's chords and voices, on a line of
+// their own, so every song marks its form the same way:
+//
+//     [A]              \boxMark "A"
+//     [Intro]          \boxMark "Intro"
+//     [Coda]           \codaMark
+//     [Segno]          \segnoMark
+//     [To Coda]        \mark \markup { \small "To" \coda }
+//     [D.S. al Coda]   \mark \markup { \italic "D.S. al Coda" }
+//
+// `Fine`, `D.C.`, and `D.S.` are written out like the last one.
+// anything else in brackets is a boxed rehearsal mark.
+
+// a marker's name starts with a capital letter, so it can't be a
+// beam, e.g. `[c8 d]`
+fn marker(line: &str) -> Option<&str> {
+    let name = line.trim().strip_prefix('[')?.strip_suffix(']')?.trim();
+
+    let valid = name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_alphanumeric() || " .'-".contains(c));
+    valid.then_some(name)
+}
+
+fn mark(name: &str) -> String {
+    match name.to_lowercase().as_str() {
+        "coda" => String::from("\\codaMark"),
+        "segno" => String::from("\\segnoMark"),
+        "to coda" => String::from("\\mark \\markup { \\small \"To\" \\coda }"),
+        n if n == "fine" || n.starts_with("d.c.") || n.starts_with("d.s.") => {
+            format!("\\mark \\markup {{ \\italic \"{}\" }}", name)
+        },
+        _ => format!("\\boxMark \"{}\"", name),
+    }
+}
+
+// every marker replaced by its mark, at the same indentation. the
+// rest of the music is left as it is.
+pub fn expand_markers(music: &str) -> String {
+    music
+        .split_inclusive('\n')
+        .map(|line| match marker(line) {
+            Some(name) => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let newline = &line[line.trim_end().len()..];
+                format!("{}{}{}", indent, mark(name), newline)
+            },
+            None => line.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_expands_section_markers() {
+        let music = "\\chordmode {\n  [Intro]\n  c1 |\n  [A]\n  c8[ d] e4\n  [Coda]\n  [D.S. al Coda]\n}";
+
+        assert_eq!(
+            expand_markers(music),
+            "\\chordmode {\n  \\boxMark \"Intro\"\n  c1 |\n  \\boxMark \"A\"\n  c8[ d] e4\n  \\codaMark\n  \\mark \\markup { \\italic \"D.S. al Coda\" }\n}"
+        );
+        assert_eq!(expand_markers("[c8 d]\n[To Coda]\r\n"), "[c8 d]\n\\mark \\markup { \\small \"To\" \\coda }\r\n");
+    }
+}
@@ -0,0 +1,491 @@
+// `templater serve`: the templater as a long-running process, for
+// editor plugins and web frontends that would otherwise start it once
+// per request. the songs are read once, and it answers json-rpc 2.0
+// requests, one per line on stdin, with one response per line on
+// stdout:
+//
+//     {"jsonrpc": "2.0", "id": 1, "method": "render", "params": {"title": "Oleo"}}
+//     {"id":1,"jsonrpc":"2.0","result":"\\bookpart { ..."}
+//
+// with `--port 4000`, they're POSTed to http://127.0.0.1:4000 instead.
+// a browser's requests are refused, unless they're from the
+// `--allow-origin`, since any page that's open could send them.
+// the methods are:
+//
+// * `songs`: every song's metadata, like `templater export`
+// * `parse` {text, path?}: the metadata of every song in a file's
+//   contents, e.g. an editor's unsaved buffer
+// * `check` {path, text?}: what `templater check` says about a file,
+//   which has to be in a songs dir
+// * `render` {title | path | text, transposition?}: a song's bookpart
+//   in one of the book's transpositions, in `--transpose` order from 0
+// * `reload`: reads the songs again, after they've changed
+//
+// a song that's only text gets the defaults of the songs dir its path
+// is in, and the number of the book's song it's an edit of.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::book::BookBuilder;
+use crate::check::check_file;
+use crate::errors::TemplaterError;
+use crate::export::SongMetadata;
+use crate::frontmatter::Defaults;
+use crate::lint::Linter;
+use crate::log_to_stderr;
+use crate::models::Song;
+use crate::session::BookSession;
+use crate::warnings::Warnings;
+
+// json-rpc's own error codes
+pub(crate) const PARSE_ERROR: i64 = -32700;
+pub(crate) const INVALID_REQUEST: i64 = -32600;
+pub(crate) const METHOD_NOT_FOUND: i64 = -32601;
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+// the templater's errors, e.g. a song that doesn't parse
+const TEMPLATER_ERROR: i64 = -32000;
+
+// a POST bigger than this is refused, a song file is a few kb
+const MAX_BODY: usize = 1024 * 1024;
+// the request line and headers, more than any client sends
+const MAX_HEADERS: u64 = 64 * 1024;
+// a client that stops sending is dropped, so it can't keep every
+// other one waiting
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+impl From<TemplaterError> for RpcError {
+    fn from(e: TemplaterError) -> Self {
+        RpcError::new(TEMPLATER_ERROR, e.to_string())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    // none for a notification, which gets no response
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+// the song a request is about
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SongParams {
+    title: Option<String>,
+    path: Option<PathBuf>,
+    text: Option<String>,
+    transposition: usize,
+}
+
+pub struct Server {
+    builder: BookBuilder,
+    session: BookSession,
+    linter: Option<Linter>,
+}
+
+impl Server {
+    pub fn new(builder: BookBuilder) -> Result<Self, TemplaterError> {
+        let session = BookSession::new(builder.clone())?;
+        let config = &session.book().config;
+        let linter = match config.lint_ly {
+            // the book's build already warned about the includes' broken links
+            true => Some(Linter::new(&config.includes_dir, &mut Warnings::default())?),
+            false => None,
+        };
+
+        Ok(Server { builder, session, linter })
+    }
+
+    pub fn warnings(&self) -> &Warnings {
+        &self.session.book().warnings
+    }
+
+    // the response to one request, none for a notification
+    pub fn handle(&mut self, request: &str) -> Option<String> {
+        let error = |code: i64, e: serde_json::Error| {
+            Some(response(Value::Null, Err(RpcError::new(code, e.to_string()))).to_string())
+        };
+        let request: Value = match serde_json::from_str(request) {
+            Ok(request) => request,
+            Err(e) => return error(PARSE_ERROR, e),
+        };
+        let request: Request = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(e) => return error(INVALID_REQUEST, e),
+        };
+
+        let result = match request.jsonrpc.as_str() {
+            "2.0" => self.call(&request.method, request.params),
+            _ => Err(RpcError::new(INVALID_REQUEST, "Expected \"jsonrpc\": \"2.0\".")),
+        };
+        request.id.map(|id| response(id, result).to_string())
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "songs" => Ok(metadata(self.session.songs())),
+            "parse" => {
+                let params = song_params(params)?;
+                let text = params.text.as_deref().ok_or_else(|| invalid_params("parse needs the song's text"))?;
+                Ok(metadata(&self.parse(params.path.as_deref(), text)?))
+            },
+            "check" => {
+                let params = song_params(params)?;
+                let path = params.path.ok_or_else(|| invalid_params("check needs the song's path"))?;
+                let text = match params.text {
+                    Some(text) => text,
+                    None => {
+                        let file = self.song_file(&path)?;
+                        fs::read_to_string(&file).map_err(|e| TemplaterError::file(&path, e))?
+                    },
+                };
+
+                let presets = &self.session.book().presets;
+                let (issues, _) = check_file(&path, &text, &self.defaults(&path)?, presets, self.linter.as_ref());
+                let issues = issues
+                    .iter()
+                    .map(|issue| json!({ "severity": issue.severity.to_string(), "message": issue.message }))
+                    .collect();
+                Ok(Value::Array(issues))
+            },
+            "render" => {
+                let params = song_params(params)?;
+                let book = self.session.book();
+                let conf = book.confs.get(params.transposition).ok_or_else(|| {
+                    invalid_params(format!(
+                        "The book has {} transpositions, not {}.",
+                        book.confs.len(),
+                        params.transposition + 1
+                    ))
+                })?;
+
+                Ok(Value::String(self.song(&params)?.render(&book.templates, conf)?))
+            },
+            "reload" => {
+                self.session = BookSession::new(self.builder.clone())?;
+                Ok(json!(self.session.songs().len()))
+            },
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method '{}'. Expected songs, parse, check, render, or reload.", method),
+            )),
+        }
+    }
+
+    // only song files are read, not any file a client asks for
+    fn song_file(&self, path: &Path) -> Result<PathBuf, RpcError> {
+        let file = fs::canonicalize(path).map_err(|e| TemplaterError::file(path, e))?;
+        let songs_dirs = self.session.book().config.songs_dirs();
+        match songs_dirs.iter().filter_map(|dir| fs::canonicalize(dir).ok()).any(|dir| file.starts_with(dir)) {
+            true => Ok(file),
+            false => Err(invalid_params(format!("{} isn't in a songs dir.", path.display()))),
+        }
+    }
+
+    // the defaults of the songs dir `path` is in, or the first one's
+    fn defaults(&self, path: &Path) -> Result<Defaults, TemplaterError> {
+        let songs_dirs = self.session.book().config.songs_dirs();
+        match songs_dirs.iter().find(|dir| path.starts_with(dir)).or(songs_dirs.first()) {
+            Some(songs_dir) => Defaults::load(songs_dir),
+            None => Ok(Defaults::default()),
+        }
+    }
+
+    // every song in a file's contents
+    fn parse(&self, path: Option<&Path>, text: &str) -> Result<Vec<Song>, TemplaterError> {
+        let path = path.unwrap_or(Path::new("untitled.ly"));
+        let lyrics = self.session.book().config.lyrics;
+        let presets = &self.session.book().presets;
+        let mut songs = Song::parse_file(path, text, lyrics, &self.defaults(path)?, presets)?;
+
+        for song in &mut songs {
+            let edited = self.session.songs().iter().find(|other| other.path == song.path && other.title == song.title);
+            song.number = edited.map_or(0, |other| other.number);
+        }
+        Ok(songs)
+    }
+
+    // the song with the title, in the text or the book, or the first
+    // song of the text or the file
+    fn song(&self, params: &SongParams) -> Result<Song, RpcError> {
+        let is_title =
+            |song: &Song| params.title.as_ref().is_none_or(|title| song.title.eq_ignore_ascii_case(title.trim()));
+
+        let found = match &params.text {
+            Some(text) => self.parse(params.path.as_deref(), text)?.into_iter().find(is_title),
+            None if params.title.is_none() && params.path.is_none() => {
+                return Err(invalid_params("render needs the song's title, path, or text"))
+            },
+            None => {
+                let is_path = |song: &&Song| params.path.as_ref().is_none_or(|path| song.path == *path);
+                self.session.songs().iter().filter(is_path).find(|song| is_title(song)).cloned()
+            },
+        };
+        found.ok_or_else(|| invalid_params("No song has that title or path."))
+    }
+}
+
+fn invalid_params<S: Into<String>>(message: S) -> RpcError {
+    RpcError::new(INVALID_PARAMS, message)
+}
+
+fn song_params(params: Value) -> Result<SongParams, RpcError> {
+    match params {
+        Value::Null => Ok(SongParams::default()),
+        params => serde_json::from_value(params).map_err(|e| invalid_params(e.to_string())),
+    }
+}
+
+fn metadata(songs: &[Song]) -> Value {
+    json!(songs.iter().map(SongMetadata::new).collect::<Vec<_>>())
+}
+
+pub(crate) fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": e.code, "message": e.message } }),
+    }
+}
+
+// one request per line, until the input ends
+pub fn serve_lines<R: BufRead, W: Write>(server: &mut Server, input: R, output: &mut W) -> Result<(), TemplaterError> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle(&line) {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+// one request per connection, POSTed to any path. only this machine
+// can connect, and only `allow_origin` from a browser, e.g.
+// `http://localhost:5173`
+pub fn serve_http(server: &mut Server, port: u16, allow_origin: Option<&str>) -> Result<(), TemplaterError> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    log!("[info]: serving on http://127.0.0.1:{}", port);
+
+    for stream in listener.incoming() {
+        if let Err(e) = stream.and_then(|mut stream| handle_connection(server, &mut stream, allow_origin)) {
+            log!("[warn]: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn handle_connection(server: &mut Server, stream: &mut TcpStream, allow_origin: Option<&str>) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut headers = (&mut reader).take(MAX_HEADERS);
+    let mut request_line = String::new();
+    headers.read_line(&mut request_line)?;
+
+    let (mut length, mut origin) = (None, None);
+    loop {
+        let mut header = String::new();
+        if headers.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => length = value.trim().parse::<usize>().ok(),
+                "origin" => origin = Some(value.trim().to_string()),
+                _ => {},
+            }
+        }
+    }
+
+    // a page in a browser always says where it's from
+    let allowed = origin.as_deref().is_none_or(|origin| Some(origin) == allow_origin);
+    let (status, body) = match request_line.split_whitespace().next() {
+        _ if !allowed => ("403 Forbidden", String::new()),
+        Some("POST") => match length {
+            None => ("411 Length Required", String::new()),
+            Some(length) if length > MAX_BODY => ("413 Payload Too Large", String::new()),
+            Some(length) => {
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body)?;
+                match server.handle(&String::from_utf8_lossy(&body)) {
+                    Some(response) => ("200 OK", response),
+                    None => ("204 No Content", String::new()),
+                }
+            },
+        },
+        // a browser asks first, before a POST from another origin
+        Some("OPTIONS") => ("204 No Content", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let cors = match origin.filter(|_| allowed) {
+        Some(origin) => format!(
+            "Access-Control-Allow-Origin: {}\r\nAccess-Control-Allow-Methods: POST\r\n\
+             Access-Control-Allow-Headers: Content-Type\r\nVary: Origin\r\n",
+            origin
+        ),
+        None => String::new(),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        cors,
+        body
+    )?;
+    stream.flush()
+}
+
+// until stdin closes, or forever with a port. the responses are all
+// that's written to stdout
+pub fn serve(builder: BookBuilder, port: Option<u16>, allow_origin: Option<&str>) -> Result<Warnings, TemplaterError> {
+    if port.is_none() {
+        log_to_stderr();
+    }
+
+    let mut server = Server::new(builder)?;
+    match port {
+        Some(port) => serve_http(&mut server, port, allow_origin)?,
+        None => serve_lines(&mut server, io::stdin().lock(), &mut io::stdout().lock())?,
+    }
+    Ok(server.warnings().clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Config;
+
+    fn server() -> Server {
+        let config = Config {
+            songs_dir: "tests/fixtures/songs".into(),
+            templates_dir: "tests/fixtures/templates".into(),
+            song_ids: "tests/fixtures/songids.toml".into(),
+            composer_aliases: "tests/fixtures/composers.toml".into(),
+            cache: false,
+            ..Default::default()
+        };
+        Server::new(BookBuilder::from_config(config).dry_run(true)).unwrap()
+    }
+
+    fn call(server: &mut Server, request: &str) -> Value {
+        serde_json::from_str(&server.handle(request).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn it_answers_json_rpc_requests() {
+        let mut server = server();
+
+        let songs = call(&mut server, r#"{"jsonrpc": "2.0", "id": 1, "method": "songs"}"#);
+        assert_eq!(songs["id"], 1);
+        let titles = songs["result"].as_array().unwrap();
+        let title = titles[0]["title"].as_str().unwrap().to_string();
+
+        let request = json!({ "jsonrpc": "2.0", "id": "a", "method": "render", "params": { "title": title } });
+        let bookpart = call(&mut server, &request.to_string());
+        assert!(bookpart["result"].as_str().unwrap().contains("\\bookpart"));
+
+        let text = "title: Draft\ncomposer: Me\n---\n";
+        let request = json!({ "jsonrpc": "2.0", "id": 2, "method": "parse", "params": { "text": text } });
+        assert_eq!(call(&mut server, &request.to_string())["result"][0]["title"], "Draft");
+
+        let params = json!({ "path": "a.ly", "text": text });
+        let request = json!({ "jsonrpc": "2.0", "id": 3, "method": "check", "params": params });
+        let issues = call(&mut server, &request.to_string());
+        assert!(issues["result"].as_array().unwrap().iter().any(|issue| issue["message"] == "missing meter"));
+
+        assert_eq!(call(&mut server, r#"{"jsonrpc": "2.0", "id": 4, "method": "reload"}"#)["result"], titles.len());
+        assert_eq!(server.handle(r#"{"jsonrpc": "2.0", "method": "reload"}"#), None);
+    }
+
+    #[test]
+    fn it_only_reads_song_files() {
+        let mut server = server();
+        let check = |server: &mut Server, path: &str| {
+            let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "check", "params": { "path": path } });
+            call(server, &request.to_string())
+        };
+
+        assert!(check(&mut server, "tests/fixtures/songs/misty.ly")["result"].is_array());
+        assert_eq!(check(&mut server, "Cargo.toml")["error"]["code"], INVALID_PARAMS);
+        assert_eq!(check(&mut server, "tests/fixtures/songs/../../../Cargo.toml")["error"]["code"], INVALID_PARAMS);
+    }
+
+    // sends `request` to a server that answers one connection
+    fn post(allow_origin: Option<&str>, request: &str) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        handle_connection(&mut server(), &mut stream, allow_origin).unwrap();
+        // before the server's end closes, which could drop it
+        let mut response = vec![0; 64 * 1024];
+        let length = client.read(&mut response).unwrap();
+        String::from_utf8_lossy(&response[..length]).to_string()
+    }
+
+    #[test]
+    fn it_only_answers_the_allowed_origin() {
+        let body = r#"{"jsonrpc": "2.0", "id": 1, "method": "songs"}"#;
+        let request = |origin: &str| {
+            format!("POST / HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}", origin, body.len(), body)
+        };
+
+        let response = post(None, &request(""));
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+
+        let evil = request("Origin: https://example.com\r\n");
+        assert!(post(None, &evil).starts_with("HTTP/1.1 403 Forbidden"));
+        assert!(post(Some("http://localhost:5173"), &evil).starts_with("HTTP/1.1 403 Forbidden"));
+
+        let response = post(Some("http://localhost:5173"), &request("Origin: http://localhost:5173\r\n"));
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("Access-Control-Allow-Origin: http://localhost:5173\r\n"));
+
+        let large = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY + 1);
+        assert!(post(None, &large).starts_with("HTTP/1.1 413"));
+    }
+
+    #[test]
+    fn it_answers_bad_requests_with_an_error() {
+        let mut server = server();
+        let code = |server: &mut Server, request: &str| call(server, request)["error"]["code"].as_i64();
+
+        assert_eq!(code(&mut server, "{"), Some(PARSE_ERROR));
+        assert_eq!(code(&mut server, r#"{"id": 1, "method": "songs"}"#), Some(INVALID_REQUEST));
+        assert_eq!(code(&mut server, r#"{"jsonrpc": "2.0", "id": 1, "method": "play"}"#), Some(METHOD_NOT_FOUND));
+        let request = r#"{"jsonrpc": "2.0", "id": 1, "method": "render", "params": {"title": "Nope"}}"#;
+        assert_eq!(code(&mut server, request), Some(INVALID_PARAMS));
+        let request = r#"{"jsonrpc": "2.0", "id": 1, "method": "parse", "params": {"text": "title: [\n---\n"}}"#;
+        assert_eq!(code(&mut server, request), Some(TEMPLATER_ERROR));
+    }
+}
@@ -0,0 +1,78 @@
+// a book kept in memory for a live preview, e.g. an editor that
+// renders a song again as its template is typed. the songs are
+// read once, when the session starts, and only the templates
+// change:
+//
+//     let mut session = BookSession::new(BookBuilder::new().transpose("bb"))?;
+//     session.set_template("song-header", &source)?;
+//     let sheet = session.render_song(0, 0)?;
+//
+// every book has its own templates and presets, so a template set
+// here is only used by this session's book, not by other books in
+// the process.
+
+use crate::book::{Book, BookBuilder};
+use crate::errors::TemplaterError;
+use crate::models::{Song, TemplaterConfig};
+
+pub struct BookSession {
+    book: Book,
+}
+
+impl BookSession {
+    // builds the book, which loads the templates
+    pub fn new(builder: BookBuilder) -> Result<Self, TemplaterError> {
+        Ok(BookSession { book: builder.build()? })
+    }
+
+    pub fn book(&self) -> &Book {
+        &self.book
+    }
+
+    // in book order, the indices `render_song` takes
+    pub fn songs(&self) -> &[Song] {
+        &self.book.songs
+    }
+
+    pub fn find_song(&self, title: &str) -> Option<usize> {
+        self.book.songs.iter().position(|song| song.title.eq_ignore_ascii_case(title.trim()))
+    }
+
+    // the template as it's rendered now, e.g. to fill an editor
+    pub fn template(&self, name: &str) -> Option<String> {
+        self.book.templates.source(name)
+    }
+
+    // replaces one of the templates, e.g. "song-header". if it doesn't
+    // compile or uses a variable it doesn't get, the old one is kept
+    // and the error says why
+    pub fn set_template(&mut self, name: &str, source: &str) -> Result<(), TemplaterError> {
+        self.book.templates.set(name, source)
+    }
+
+    fn conf(&self, transposition: usize) -> Result<&TemplaterConfig, TemplaterError> {
+        self.book.confs.get(transposition).ok_or_else(|| {
+            let message = format!("The book has {} transpositions, not {}.", self.book.confs.len(), transposition + 1);
+            TemplaterError::from_str(&message)
+        })
+    }
+
+    // the whole book in one of its transpositions, numbered from 0
+    pub fn render_book(&self, transposition: usize) -> Result<String, TemplaterError> {
+        let mut output = vec![];
+        self.book.render(&mut output, self.conf(transposition)?)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    // one song's lead sheet, which lilypond can read on its own
+    pub fn render_song(&self, song: usize, transposition: usize) -> Result<String, TemplaterError> {
+        if song >= self.book.songs.len() {
+            let message = format!("The book has {} songs, not {}.", self.book.songs.len(), song + 1);
+            return Err(TemplaterError::from_str(&message));
+        }
+
+        let mut output = vec![];
+        self.book.render_sheet(&mut output, self.conf(transposition)?, song)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
@@ -0,0 +1,148 @@
+// `--setlist gig.txt`: a mini-book of only the songs on a gig's
+// setlist, in the order they're played, with the setlist as its
+// cover instead of the title page and ToC. every line is a title,
+// matched like duplicate titles are, or by how it starts, e.g.
+// "stella" for "Stella by Starlight". blank lines and lines
+// starting with `#` are skipped.
+
+use std::path::Path;
+
+use minijinja::{context, Value};
+
+use crate::duplicates::compare_titles;
+use crate::errors::TemplaterError;
+use crate::models::Song;
+use crate::utils::{capitalize_first_letter, read_file, slugify, title_sort_key};
+use crate::templates::Templates;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setlist {
+    // from the filename, e.g. "Friday Gig" for friday-gig.txt
+    pub name: String,
+    // (line, title)
+    pub entries: Vec<(usize, String)>,
+}
+
+impl Setlist {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TemplaterError> {
+        let path = path.as_ref();
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().replace(['-', '_'], " ")).unwrap_or_default();
+        let name: Vec<String> = stem.split(' ').map(capitalize_first_letter).collect();
+        let setlist = Setlist::parse(&name.join(" "), &read_file(path)?);
+
+        match setlist.entries.is_empty() {
+            true => Err(TemplaterError::from_str(&format!("{}: the setlist doesn't have any songs.", path.display()))),
+            false => Ok(setlist),
+        }
+    }
+
+    pub fn parse(name: &str, input: &str) -> Self {
+        let entries = input
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line, title)| (line, title.to_string()))
+            .collect();
+
+        Setlist { name: name.to_string(), entries }
+    }
+
+    // indices into `songs`, in the setlist's order. every line has
+    // to match exactly one song, and no song can be played twice.
+    pub fn select(&self, songs: &[Song]) -> Result<Vec<usize>, TemplaterError> {
+        let mut selected: Vec<usize> = vec![];
+        let mut errors = vec![];
+
+        for (line, title) in &self.entries {
+            let error = |message: String| TemplaterError::from_str(&format!("setlist line {}: {}", line, message));
+
+            let matches = find_song(title, songs);
+            match matches.as_slice() {
+                [] => errors.push(error(format!("no song matches '{}'", title))),
+                [i] if selected.contains(i) => {
+                    errors.push(error(format!("'{}' is already in the setlist", songs[*i].title)))
+                },
+                [i] => selected.push(*i),
+                _ => {
+                    let titles: Vec<&str> = matches.iter().map(|i| songs[*i].title.as_str()).collect();
+                    errors.push(error(format!("'{}' could be any of {}", title, titles.join(", "))));
+                },
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(selected),
+            false => Err(TemplaterError::collect(errors)),
+        }
+    }
+
+    // the cover, in place of the intro and ToC. `songs` are in
+    // setlist order, `headers` are the guitar book's and the layout's.
+    pub fn render_cover(
+        &self,
+        templates: &Templates,
+        songs: &[Song],
+        transpose_display: &str,
+        metadata: Value,
+        headers: Value,
+    ) -> Result<String, TemplaterError> {
+        let songs: Vec<Value> = songs
+            .iter()
+            .map(|song| {
+                context! {
+                    title => song.title.as_str(),
+                    composer => song.composer.as_str(),
+                    number => song.number,
+                    label => song.label(),
+                }
+            })
+            .collect();
+
+        templates.render(
+            "setlist",
+            context! { name => self.name.as_str(), transpose_display, metadata, songs, ..headers },
+        )
+    }
+}
+
+// the songs with the same title (ignoring case and punctuation),
+// or else a similar one, or else the ones it's the start of
+fn find_song(title: &str, songs: &[Song]) -> Vec<usize> {
+    let key = |title: &str| slugify(&title_sort_key(title)).replace('-', "");
+    let matching = |found: &dyn Fn(&Song) -> bool| -> Vec<usize> {
+        songs.iter().enumerate().filter(|(_, song)| found(song)).map(|(i, _)| i).collect()
+    };
+
+    let same = matching(&|song| compare_titles(title, &song.title).is_some());
+    if !same.is_empty() {
+        return same;
+    }
+
+    let start = key(title);
+    match start.is_empty() {
+        true => vec![],
+        false => matching(&|song| key(&song.title).starts_with(&start)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn song(title: &str) -> Song {
+        Song::parse(Path::new("test.ly"), &format!("title: {}", title), false).unwrap()
+    }
+
+    #[test]
+    fn it_matches_setlist_lines_to_songs() {
+        let songs = vec![song("All the Things You Are"), song("Misty"), song("Stella by Starlight"), song("Oleo")];
+        let setlist = Setlist::parse("Gig", "# first set\nstella\n\nThe Misty\nall the things you are\n");
+        assert_eq!(setlist.entries[0], (2, String::from("stella")));
+        assert_eq!(setlist.select(&songs).unwrap(), vec![2, 1, 0]);
+
+        let error = Setlist::parse("Gig", "Oleo\noleo\nBlue Bossa").select(&songs).unwrap_err().to_string();
+        assert!(error.contains("setlist line 2: 'Oleo' is already in the setlist"));
+        assert!(error.contains("setlist line 3: no song matches 'Blue Bossa'"));
+    }
+}
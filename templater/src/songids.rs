@@ -0,0 +1,115 @@
+// `songids.toml`: a stable number for every song, like the tune
+// numbers in a real book. songs are keyed by their title's slug.
+// a title without any latin letters keeps its own, and one without
+// any letters at all is keyed by its file's name.
+// numbers are never reused, so adding or removing songs doesn't
+// renumber the others.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::TemplaterError;
+use crate::models::Song;
+use crate::utils::{slugify, unicode_slugify};
+
+pub const DEFAULT_SONG_IDS_FILE: &str = "songids.toml";
+
+const HEADER: &str = "# generated by the templater. to keep a song's number after
+# renaming it, rename its key here too.
+";
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SongIds {
+    #[serde(default)]
+    songs: BTreeMap<String, u32>,
+}
+
+// latin titles keep the keys they always had, see `slugify`
+pub fn song_key(song: &Song) -> String {
+    let stem = song.path.file_stem().unwrap_or_default().to_string_lossy();
+    [slugify(&song.title), unicode_slugify(&song.title), unicode_slugify(&stem)]
+        .into_iter()
+        .find(|key| !key.is_empty())
+        .unwrap_or_default()
+}
+
+impl SongIds {
+    // a missing manifest is empty, every song gets a new number
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TemplaterError> {
+        let path = path.as_ref();
+
+        match fs::read_to_string(path) {
+            Ok(input) => toml::from_str(&input)
+                .map_err(|e| TemplaterError::from_str(&format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(SongIds::default()),
+            Err(e) => Err(TemplaterError::file(path, e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), TemplaterError> {
+        let path = path.as_ref();
+        let output = toml::to_string(self).map_err(|e| TemplaterError::from_str(&e.to_string()))?;
+
+        fs::write(path, format!("{}{}", HEADER, output)).map_err(|e| TemplaterError::file(path, e))
+    }
+
+    pub fn get(&self, key: &str) -> Option<u32> {
+        self.songs.get(key).copied()
+    }
+
+    // numbers every key that doesn't have one yet, in order.
+    // returns whether any were added.
+    pub fn assign<'a, I: IntoIterator<Item = &'a str>>(&mut self, keys: I) -> bool {
+        let mut next = self.songs.values().max().map_or(1, |max| max + 1);
+        let mut added = false;
+
+        for key in keys {
+            if !self.songs.contains_key(key) {
+                self.songs.insert(key.to_string(), next);
+                next += 1;
+                added = true;
+            }
+        }
+
+        added
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_keeps_existing_numbers() {
+        let mut ids: SongIds = toml::from_str("[songs]\nmisty = 1\noleo = 3\n").unwrap();
+
+        assert!(ids.assign(["airegin", "misty", "nardis", "oleo"]));
+        assert_eq!(ids.get("misty"), Some(1));
+        assert_eq!(ids.get("oleo"), Some(3));
+        assert_eq!(ids.get("airegin"), Some(4));
+        assert_eq!(ids.get("nardis"), Some(5));
+
+        assert!(!ids.assign(["misty"]));
+    }
+
+    #[test]
+    fn it_keys_titles_without_latin_letters() {
+        let song = |path: &str, title: &str| {
+            Song::parse(Path::new(path), &format!("title: {}\ncomposer: Trad.\n---\n", title), false).unwrap()
+        };
+        let (katyusha, kalinka) = (song("katyusha.ly", "Катюша"), song("kalinka.ly", "Калинка"));
+        assert_eq!(song_key(&katyusha), "катюша");
+        assert_eq!(song_key(&kalinka), "калинка");
+        assert_eq!(song_key(&song("misty.ly", "Misty")), "misty");
+        assert_eq!(song_key(&song("question.ly", "\"?\"")), "question");
+
+        let mut ids = SongIds::default();
+        ids.assign([song_key(&katyusha).as_str(), song_key(&kalinka).as_str()]);
+        assert_eq!((ids.get("катюша"), ids.get("калинка")), (Some(1), Some(2)));
+    }
+}
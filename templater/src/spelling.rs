@@ -0,0 +1,160 @@
+// how a transposed song's key is spelled. lilypond's `\transpose c d`
+// moves every note up a major 2nd by letter, so a song in B major
+// comes out in C# major for a Bb instrument, with 7 sharps. the song
+// is transposed by the same interval spelled a letter higher or lower
+// instead, e.g. `\transpose c eeses`, if its key has fewer accidentals
+// that way, so its key, chord symbols, and accidentals are all
+// spelled in Db. `spelling` is the policy for every transposition,
+// and `spellings` the one for a transposition by name:
+//
+//     spelling = "sharps"
+//
+//     [spellings]
+//     eb = "flats"
+//     bass = "as-written"
+//
+// `simplest`, the default, leaves a key with as many either way, F#
+// or Gb, the way lilypond spells it, and `sharps` or `flats` pick
+// one. `as-written` always leaves it to lilypond. a song without a
+// key or with its own `transpose_override` is never respelled, and
+// neither is concert pitch, or any other transposition by octaves.
+
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::keys::Key;
+use crate::models::TransposeText;
+use crate::transpose::{parse_lilypond_pitch, Pitch};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Spelling {
+    // lilypond's, by letter
+    AsWritten,
+    // the key with the fewest accidentals
+    #[default]
+    Simplest,
+    Sharps,
+    Flats,
+}
+
+impl FromStr for Spelling {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "as-written" | "lilypond" => Ok(Spelling::AsWritten),
+            "simplest" => Ok(Spelling::Simplest),
+            "sharps" => Ok(Spelling::Sharps),
+            "flats" => Ok(Spelling::Flats),
+            _ => Err(TemplaterError::from_str(&format!(
+                "Unknown spelling '{}'. Expected simplest, sharps, flats, or as-written.",
+                s
+            ))),
+        }
+    }
+}
+
+// the written pitch of a concert `c` for a song in `key`: `target`,
+// or the same pitch a letter higher or lower
+pub fn respell(key: &Key, target: &Pitch, spelling: Spelling) -> Pitch {
+    if spelling == Spelling::AsWritten || (target.step == 0 && target.alteration == 0) {
+        return *target;
+    }
+
+    let fifths = |target: &Pitch| Key { tonic: key.tonic.transpose(target), mode: key.mode }.fifths();
+    // only breaks a tie
+    let unwanted = |fifths: i32| match spelling {
+        Spelling::Sharps => fifths < 0,
+        Spelling::Flats => fifths > 0,
+        Spelling::AsWritten | Spelling::Simplest => false,
+    };
+
+    // the first of a tie is kept, so lilypond's wins one
+    [*target, target.enharmonic(1), target.enharmonic(-1)]
+        .into_iter()
+        .min_by_key(|target| {
+            let fifths = fifths(target);
+            (fifths.abs(), unwanted(fifths))
+        })
+        .unwrap_or(*target)
+}
+
+// `transpose_text` for a song in `key`
+pub fn spell_for<'a>(transpose_text: &'a TransposeText, key: &Key) -> Cow<'a, TransposeText> {
+    let target = transpose_text.lilypond_text.strip_prefix("c ").and_then(|target| parse_lilypond_pitch(target).ok());
+    let Some(target) = target else {
+        return Cow::Borrowed(transpose_text);
+    };
+
+    match respell(key, &target, transpose_text.spelling) {
+        respelled if respelled == target => Cow::Borrowed(transpose_text),
+        respelled => Cow::Owned(TransposeText {
+            lilypond_text: format!("c {}", respelled.lilypond_name()),
+            ..transpose_text.clone()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontmatter::Defaults;
+    use crate::models::Song;
+    use crate::transpose::{transpose_text, Presets};
+    use std::path::Path;
+
+    // the key a song in `key` is written in
+    fn written(key: &str, transposition: &str, spelling: Spelling) -> String {
+        let key: Key = key.parse().unwrap();
+        let transpose_text = TransposeText { spelling, ..transpose_text(transposition).unwrap() };
+        key.transposed(&spell_for(&transpose_text, &key)).to_string()
+    }
+
+    #[test]
+    fn it_spells_the_simpler_key() {
+        assert_eq!(written("B", "bb", Spelling::Simplest), "Db major");
+        assert_eq!(written("B", "bb", Spelling::AsWritten), "C# major");
+        assert_eq!(written("G#", "bb", Spelling::Simplest), "Bb major");
+        assert_eq!(written("E", "eb", Spelling::Simplest), "Db major");
+        assert_eq!(written("a# minor", "eb+8va", Spelling::Simplest), "G minor");
+        assert_eq!(written("Eb", "bb", Spelling::Simplest), "F major");
+    }
+
+    #[test]
+    fn it_breaks_a_tie_by_policy() {
+        assert_eq!(written("E", "bb", Spelling::Simplest), "F# major");
+        assert_eq!(written("E", "bb", Spelling::Flats), "Gb major");
+        assert_eq!(written("Ab", "bb", Spelling::Sharps), "Bb major");
+        assert_eq!(written("A", "eb", Spelling::Flats), "Gb major");
+        assert_eq!(written("A", "eb", Spelling::Sharps), "F# major");
+    }
+
+    #[test]
+    fn it_leaves_octaves_alone() {
+        assert_eq!(written("C#", "c", Spelling::Simplest), "C# major");
+        assert_eq!(written("C#", "bass", Spelling::Flats), "C# major");
+
+        let key: Key = "B".parse().unwrap();
+        let bb = transpose_text("bb+8va").unwrap();
+        assert_eq!(spell_for(&bb, &key).lilypond_text, "c eeses'");
+    }
+
+    #[test]
+    fn it_spells_a_song_for_its_key() {
+        let song = |front_matter: &str| {
+            let input = format!("title: Nica's Dream\ncomposer: Horace Silver\n{}\n---\n", front_matter);
+            let path = Path::new("nicas_dream.ly");
+            Song::parse_with(path, &input, false, &Defaults::default(), &Presets::default()).unwrap()
+        };
+        let eb = transpose_text("eb").unwrap();
+
+        assert_eq!(song("key: E").transposition(&eb).lilypond_text, "c beses");
+        assert_eq!(song("key: Eb").transposition(&eb).lilypond_text, "c a");
+        assert_eq!(song("").transposition(&eb).lilypond_text, "c a");
+        assert_eq!(song("key: E\ntranspose_override: bb").transposition(&eb).lilypond_text, "c d");
+    }
+}
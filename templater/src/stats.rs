@@ -0,0 +1,144 @@
+// `templater stats [stats.json]`: how many songs there are by
+// composer, decade, meter, key, and form, and how long they are on
+// average. printed as a table, and written as json if a file is
+// given.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::errors::TemplaterError;
+use crate::models::Song;
+use crate::form::song_form;
+
+// for songs without e.g. a meter
+const NONE: &str = "none";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Count {
+    pub name: String,
+    pub songs: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub songs: usize,
+    // most songs first
+    pub composers: Vec<Count>,
+    // oldest first, e.g. "1930s"
+    pub decades: Vec<Count>,
+    pub meters: Vec<Count>,
+    // in concert pitch, every song under the key it starts in
+    pub keys: Vec<Count>,
+    // how many songs change key, see `Song::key_changes`
+    pub modulating: usize,
+    // declared or guessed, see `form.rs`
+    pub forms: Vec<Count>,
+    // of the melodies, none if no song has one
+    pub average_measures: Option<f64>,
+}
+
+fn counts<I: IntoIterator<Item = String>>(names: I) -> Vec<Count> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for name in names {
+        *counts.entry(name).or_default() += 1;
+    }
+
+    let mut counts: Vec<Count> = counts.into_iter().map(|(name, songs)| Count { name, songs }).collect();
+    counts.sort_by(|a, b| b.songs.cmp(&a.songs).then_with(|| a.name.cmp(&b.name)));
+    counts
+}
+
+impl Stats {
+    pub fn new(songs: &[Song]) -> Self {
+        let or_none =
+            |name: Option<String>| name.filter(|name| !name.trim().is_empty()).unwrap_or_else(|| NONE.to_string());
+
+        let decade = |song: &Song| or_none(song.year.map(|year| format!("{}s", year - year % 10)));
+        let mut decades = counts(songs.iter().map(decade));
+        decades.sort_by(|a, b| (a.name == NONE).cmp(&(b.name == NONE)).then_with(|| a.name.cmp(&b.name)));
+
+        let measures: Vec<u64> = songs.iter().filter_map(|song| song.measures).collect();
+        let average_measures = match measures.is_empty() {
+            true => None,
+            false => Some(measures.iter().sum::<u64>() as f64 / measures.len() as f64),
+        };
+
+        Stats {
+            songs: songs.len(),
+            composers: counts(songs.iter().map(|song| song.composer.clone())),
+            decades,
+            meters: counts(songs.iter().map(|song| or_none(song.meter.clone()))),
+            keys: counts(songs.iter().map(|song| or_none(song.key.map(|key| key.to_string())))),
+            modulating: songs.iter().filter(|song| !song.key_changes.is_empty()).count(),
+            forms: counts(songs.iter().map(|song| or_none(song_form(song).map(|form| form.to_string())))),
+            average_measures,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, TemplaterError> {
+        serde_json::to_string_pretty(self).map_err(|e| TemplaterError::from_str(&e.to_string()))
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.average_measures {
+            Some(measures) => writeln!(f, "{} songs, {:.1} measures on average", self.songs, measures)?,
+            None => writeln!(f, "{} songs", self.songs)?,
+        }
+        if self.modulating > 0 {
+            writeln!(f, "{} of them change key", self.modulating)?;
+        }
+
+        let tables = [
+            ("composer", &self.composers),
+            ("decade", &self.decades),
+            ("meter", &self.meters),
+            ("key", &self.keys),
+            ("form", &self.forms),
+        ];
+        for (heading, counts) in tables {
+            let width = counts.iter().map(|count| count.name.chars().count()).chain([heading.len()]).max().unwrap_or(0);
+
+            writeln!(f, "\n{:width$}  songs", heading, width = width)?;
+            for count in counts {
+                writeln!(f, "{:width$}  {:>5}", count.name, count.songs, width = width)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn song(input: &str) -> Song {
+        Song::parse(Path::new("test.ly"), input, false).unwrap()
+    }
+
+    #[test]
+    fn it_counts_songs_by_field() {
+        let songs = vec![
+            song("title: Oleo\ncomposer: Sonny Rollins\nyear: 1954\nmeter: Fast\nkey: Bb\n---\n\\time 4/4\n\\numericTimeSignature\nc1 | c1 |"),
+            song("title: Airegin\ncomposer: Sonny Rollins\nsubsubtitle: 1954\nkey: f minor\n---\n\\numericTimeSignature\nc2 c | \\key bes \\minor c1 |"),
+            song("title: Misty\ncomposer: Erroll Garner\nsubsubtitle: From Pal Joey (1940)\n---\n"),
+        ];
+        let stats = Stats::new(&songs);
+
+        assert_eq!(stats.songs, 3);
+        assert_eq!(stats.composers[0], Count { name: String::from("Sonny Rollins"), songs: 2 });
+        assert_eq!(stats.decades.iter().map(|count| count.name.as_str()).collect::<Vec<_>>(), vec!["1940s", "1950s"]);
+        assert_eq!(stats.meters[0], Count { name: String::from(NONE), songs: 2 });
+        assert_eq!(stats.keys.len(), 3);
+        assert_eq!(stats.modulating, 1);
+        assert_eq!(stats.forms[0], Count { name: String::from(NONE), songs: 3 });
+        assert_eq!(stats.average_measures, Some(2.0));
+        assert!(stats.to_string().contains("Sonny Rollins      2"));
+        assert!(stats.to_string().contains("1 of them change key"));
+    }
+}
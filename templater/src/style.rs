@@ -0,0 +1,250 @@
+// style warnings for the front matter, e.g. a lowercase word in a
+// title or `ft.` instead of `feat.`, reported by `templater check`.
+// most of them are fixed in place by `templater check --autofix`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::TemplaterError;
+use crate::frontmatter;
+use crate::utils::{capitalize_first_letter, get_files_by_ext, read_file, today};
+use crate::warnings::Warnings;
+
+// before this, it's almost certainly a typo
+const FIRST_YEAR: u32 = 1850;
+
+// lowercase in a title, unless they're its first or last word
+const SMALL_WORDS: [&str; 37] = [
+    "a", "an", "the", "and", "but", "or", "nor", "for", "so", "yet", "as", "at", "by", "in", "of", "on", "to", "up",
+    "via", "per", "from", "into", "onto", "with", "de", "da", "do", "das", "dos", "del", "la", "le", "les", "el", "e",
+    "von", "van",
+];
+
+// the fields that credit people, or might, e.g. "Oleo (feat. Miles Davis)"
+const CREDIT_FIELDS: [&str; 5] = ["title", "subtitle", "composer", "poet", "arranger"];
+const YEAR_FIELDS: [&str; 3] = ["year", "copyright", "subsubtitle"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyleIssue {
+    // in the song file, starting at 1
+    pub line: usize,
+    pub message: String,
+    pub fixable: bool,
+}
+
+impl StyleIssue {
+    fn new<S: Into<String>>(line: usize, message: S, fixable: bool) -> Self {
+        StyleIssue { line, message: message.into(), fixable }
+    }
+}
+
+fn this_year() -> u32 {
+    today()[..4].parse().unwrap_or(FIRST_YEAR)
+}
+
+// e.g. "ft" -> "feat.", keeping any parentheses around it
+fn credit_word(word: &str) -> Option<String> {
+    let start = word.len() - word.trim_start_matches('(').len();
+    let end = word.trim_end_matches([')', ',']).len();
+    let (prefix, bare, suffix) = (&word[..start], &word[start..end], &word[end..]);
+
+    let fixed = match bare.to_lowercase().as_str() {
+        "ft" | "ft." | "feat" | "featuring" => "feat.",
+        "feat." if bare != "feat." => "feat.",
+        "arr" => "arr.",
+        "arr." if bare != "arr." => "arr.",
+        _ => return None,
+    };
+
+    Some(format!("{}{}{}", prefix, fixed, suffix))
+}
+
+// every word that should start with a capital letter, capitalized
+fn title_case(title: &str) -> String {
+    let words: Vec<&str> = title.split(' ').collect();
+    let last = words.iter().rposition(|word| !word.is_empty()).unwrap_or(0);
+
+    let fixed: Vec<String> = words
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            let bare = word.trim_start_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+            let small = SMALL_WORDS.contains(&bare.trim_end_matches(|c: char| !c.is_alphanumeric()));
+            let first = words[..i].iter().all(|word| word.is_empty()) || words[i - 1].ends_with(':');
+
+            match word.chars().find(|c| c.is_alphanumeric()) {
+                Some(c) if c.is_lowercase() && (first || i == last || !small) => capitalize_first_letter(word),
+                _ => word.to_string(),
+            }
+        })
+        .collect();
+
+    fixed.join(" ")
+}
+
+// four digit numbers that look like a year, e.g. "1955" in
+// "1955. Renewed 1983"
+fn years(value: &str) -> Vec<u32> {
+    value
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|digits| digits.len() == 4)
+        .filter_map(|digits| digits.parse().ok())
+        .filter(|year| (1000..3000).contains(year))
+        .collect()
+}
+
+// (issues, the line with every fixable one fixed)
+fn check_line(line: &str, line_number: usize, this_year: u32) -> (Vec<StyleIssue>, String) {
+    let mut issues = vec![];
+    let content = line.trim_end();
+
+    let Some((key, rest)) = content.split_once(':') else {
+        return (issues, line.to_string());
+    };
+    if key.is_empty() || key.starts_with(char::is_whitespace) || key.starts_with(['-', '#']) {
+        return (issues, line.to_string());
+    }
+
+    if content.len() != line.trim_end_matches(['\n', '\r']).len() {
+        issues.push(StyleIssue::new(line_number, format!("{} has trailing whitespace", key), true));
+    }
+
+    let value_start = key.len() + 1 + (rest.len() - rest.trim_start().len());
+    let value = &content[value_start..];
+    let quote = value.chars().next().filter(|c| "'\"".contains(*c) && value.len() > 1 && value.ends_with(*c));
+    let inner = match quote {
+        Some(_) => &value[1..value.len() - 1],
+        None => value,
+    };
+
+    let mut fixed = inner.to_string();
+    if inner != inner.trim() {
+        issues.push(StyleIssue::new(line_number, format!("{} '{}' has surrounding whitespace", key, inner), true));
+        fixed = inner.trim().to_string();
+    }
+
+    if CREDIT_FIELDS.contains(&key) {
+        let words: Vec<String> = fixed
+            .split(' ')
+            .map(|word| match credit_word(word) {
+                Some(credit) => {
+                    let message = format!("{}: '{}' should be '{}'", key, word, credit);
+                    issues.push(StyleIssue::new(line_number, message, true));
+                    credit
+                },
+                None => word.to_string(),
+            })
+            .collect();
+        fixed = words.join(" ");
+    }
+
+    if key == "title" {
+        let title = title_case(&fixed);
+        if title != fixed {
+            issues.push(StyleIssue::new(line_number, format!("title '{}' should be '{}'", fixed, title), true));
+            fixed = title;
+        }
+    }
+
+    if YEAR_FIELDS.contains(&key) {
+        for year in years(&fixed).into_iter().filter(|year| !(FIRST_YEAR..=this_year).contains(year)) {
+            let message = format!("{}: {} isn't between {} and {}", key, year, FIRST_YEAR, this_year);
+            issues.push(StyleIssue::new(line_number, message, false));
+        }
+    }
+
+    if issues.iter().all(|issue| !issue.fixable) {
+        return (issues, line.to_string());
+    }
+
+    let quote = quote.map(String::from).unwrap_or_default();
+    let newline = &line[line.trim_end_matches(['\n', '\r']).len()..];
+    (issues, format!("{}{}{}{}{}", &content[..value_start], quote, fixed, quote, newline))
+}
+
+// (issues, the input with every fixable one fixed) for every song
+// in a file
+pub fn check_style(input: &str) -> (Vec<StyleIssue>, String) {
+    let this_year = this_year();
+    let mut issues = vec![];
+    let mut fixed = String::with_capacity(input.len());
+
+    let songs = frontmatter::split_songs(input);
+    for (i, (first_line, song)) in songs.iter().enumerate() {
+        let mut in_front_matter = true;
+        for (n, line) in song.split_inclusive('\n').enumerate() {
+            if line.trim() == "---" {
+                in_front_matter = false;
+            }
+
+            match in_front_matter {
+                true => {
+                    let (line_issues, line) = check_line(line, first_line + n, this_year);
+                    issues.extend(line_issues);
+                    fixed.push_str(&line);
+                },
+                false => fixed.push_str(line),
+            }
+        }
+
+        // the separator isn't part of either song
+        if i + 1 < songs.len() {
+            let separator_line = songs[i + 1].0 - 2;
+            fixed.push_str(input.split_inclusive('\n').nth(separator_line).unwrap_or_default());
+        }
+    }
+
+    (issues, fixed)
+}
+
+// fixes every song file in place. (path, fixes) for every file
+// that changed.
+pub fn autofix_songs<P: AsRef<Path>>(
+    songs_dir: P,
+    warnings: &mut Warnings,
+) -> Result<Vec<(PathBuf, usize)>, TemplaterError> {
+    let mut paths = get_files_by_ext(&songs_dir, "ly", warnings)?;
+    paths.sort();
+
+    let mut fixed_files = vec![];
+    for path in paths {
+        let input = read_file(&path)?;
+        let (issues, fixed) = check_style(&input);
+        if fixed != input {
+            fs::write(&path, fixed).map_err(|e| TemplaterError::file(&path, e))?;
+            fixed_files.push((path, issues.iter().filter(|issue| issue.fixable).count()));
+        }
+    }
+
+    Ok(fixed_files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_fixes_the_front_matter_style() {
+        let input = "title: a night in tunisia \ncomposer: \"Dizzy Gillespie ft Frank Paparelli \"\ncopyright: 1742\n---\nc1 \n";
+        let (issues, fixed) = check_style(input);
+
+        assert_eq!(
+            fixed,
+            "title: A Night in Tunisia\ncomposer: \"Dizzy Gillespie feat. Frank Paparelli\"\ncopyright: 1742\n---\nc1 \n"
+        );
+        assert_eq!(issues.len(), 5);
+        assert_eq!(issues[4], StyleIssue::new(3, format!("copyright: 1742 isn't between 1850 and {}", this_year()), false));
+    }
+
+    #[test]
+    fn it_leaves_good_titles_alone() {
+        for title in ["The Days of Wine and Roses", "Chega de Saudade", "ESP", "(Meet) The Flintstones", "Bags' Groove"] {
+            assert_eq!(title_case(title), title);
+        }
+        assert_eq!(title_case("what is this thing called love?"), "What Is This Thing Called Love?");
+        assert_eq!(title_case("Zelda: a link to the past"), "Zelda: A Link to the Past");
+
+        let input = "title: Misty\n---\n===\ntitle: Body And soul\n---\n";
+        assert_eq!(check_style(input).1, "title: Misty\n---\n===\ntitle: Body And Soul\n---\n");
+    }
+}
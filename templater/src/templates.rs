@@ -0,0 +1,205 @@
+// the templates a book is rendered with: the stock ones, or a file
+// with the same name in `templates_dir`. every book compiles its own,
+// so books with different templates dirs (or a `BookSession` whose
+// template was edited) can be rendered in the same process.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+
+use minijinja::syntax::SyntaxConfig;
+use minijinja::{Environment, UndefinedBehavior, Value};
+
+use crate::errors::TemplaterError;
+use crate::models::Config;
+use crate::profiles::{LayoutProfile, VARIANT_TEMPLATES};
+use crate::utils::{escape_latex, escape_lilypond_string, normalize_line_endings, yaml_string};
+use crate::vars::check_templates;
+
+// the stock templates, so the binary works from any directory
+const DEFAULT_TEMPLATES: [(&str, &str); 33] = [
+    ("intro", include_str!("../../templates/intro")),
+    ("toc", include_str!("../../templates/toc")),
+    ("bookpart", include_str!("../../templates/bookpart")),
+    ("song-header", include_str!("../../templates/song-header")),
+    ("song-body", include_str!("../../templates/song-body")),
+    ("chords", include_str!("../../templates/chords")),
+    ("voice", include_str!("../../templates/voice")),
+    ("variant", include_str!("../../templates/variant")),
+    ("lyrics", include_str!("../../templates/lyrics")),
+    ("index", include_str!("../../templates/index")),
+    ("divider", include_str!("../../templates/divider")),
+    ("song", include_str!("../../templates/song")),
+    ("chord-sheet", include_str!("../../templates/chord-sheet")),
+    ("midi-header", include_str!("../../templates/midi-header")),
+    ("midi", include_str!("../../templates/midi")),
+    ("lyrics-text", include_str!("../../templates/lyrics-text")),
+    ("lyrics-markdown", include_str!("../../templates/lyrics-markdown")),
+    ("lyrics-latex", include_str!("../../templates/lyrics-latex")),
+    ("html-index", include_str!("../../templates/html-index")),
+    ("sheet-header", include_str!("../../templates/sheet-header")),
+    ("setlist", include_str!("../../templates/setlist")),
+    ("changes", include_str!("../../templates/changes")),
+    ("sources", include_str!("../../templates/sources")),
+    ("licenses", include_str!("../../templates/licenses")),
+    ("fretboards", include_str!("../../templates/fretboards")),
+    ("fretboards-header", include_str!("../../templates/fretboards-header")),
+    ("numbers-sheet", include_str!("../../templates/numbers-sheet")),
+    ("web-index", include_str!("../../templates/web-index")),
+    ("web-song", include_str!("../../templates/web-song")),
+    ("manuscript", include_str!("../../templates/manuscript")),
+    ("chord-grids", include_str!("../../templates/chord-grids")),
+    ("paper", include_str!("../../templates/paper")),
+    ("large-print", include_str!("../../templates/large-print")),
+];
+
+#[derive(Debug, Clone)]
+pub struct Templates {
+    env: Environment<'static>,
+}
+
+impl Templates {
+    // compiles every template, e.g. `intro-tablet` too if it's in the
+    // templates dir
+    pub fn load(config: &Config) -> Result<Self, TemplaterError> {
+        let mut env = Environment::new();
+        // included templates end on their own line, same as the files
+        let syntax = SyntaxConfig::builder()
+            .keep_trailing_newline(true)
+            .build()
+            .map_err(|e| TemplaterError::from_str(&e.to_string()))?;
+        env.set_syntax(syntax);
+        // a typo in a variable name shouldn't silently render nothing
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.add_filter("lilypond", |s: String| escape_lilypond_string(&s));
+        env.add_filter("yaml", |s: String| yaml_string(&s));
+        env.add_filter("latex", |s: String| escape_latex(&s));
+
+        for (name, default) in DEFAULT_TEMPLATES {
+            add_template(&mut env, name, read_template(config, name, default)?)?;
+        }
+        for layout in LayoutProfile::ALL {
+            for template in VARIANT_TEMPLATES {
+                let name = layout.variant_name(template);
+                let path = config.template_path(&name);
+                match fs::read_to_string(&path) {
+                    Ok(source) => add_template(&mut env, &name, normalize_line_endings(source))?,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                    Err(e) => return Err(TemplaterError::file(path, e)),
+                }
+            }
+        }
+        check_templates(&env)?;
+
+        Ok(Templates { env })
+    }
+
+    pub fn render(&self, name: &str, ctx: Value) -> Result<String, TemplaterError> {
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(ctx))
+            .map_err(|e| TemplaterError::Template { name: name.to_string(), message: template_message(&e) })
+    }
+
+    // changes whenever any template's source does
+    pub fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let mut templates: Vec<(&str, String)> =
+            self.env.templates().map(|(name, template)| (name, template.source().to_string())).collect();
+        templates.sort();
+        templates.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    // replaces one of the templates. a template that doesn't compile,
+    // or uses a variable it doesn't get, is an error and the old one
+    // is kept.
+    pub fn set(&mut self, name: &str, source: &str) -> Result<(), TemplaterError> {
+        if !DEFAULT_TEMPLATES.iter().any(|(template, _)| *template == name) {
+            let names: Vec<&str> = DEFAULT_TEMPLATES.iter().map(|(name, _)| *name).collect();
+            let message = format!("Unknown template '{}'. Expected one of {}.", name, names.join(", "));
+            return Err(TemplaterError::from_str(&message));
+        }
+
+        let mut env = self.env.clone();
+        add_template(&mut env, name, normalize_line_endings(source.to_string()))?;
+        check_templates(&env)?;
+        self.env = env;
+        Ok(())
+    }
+
+    pub fn source(&self, name: &str) -> Option<String> {
+        let template = self.env.get_template(name).ok()?;
+        Some(template.source().to_string())
+    }
+}
+
+fn read_template(config: &Config, name: &str, default: &str) -> Result<String, TemplaterError> {
+    let path = config.template_path(name);
+
+    match fs::read_to_string(&path) {
+        Ok(source) => Ok(normalize_line_endings(source)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(default.to_string()),
+        Err(e) => Err(TemplaterError::file(path, e)),
+    }
+}
+
+fn add_template(env: &mut Environment<'static>, name: &str, source: String) -> Result<(), TemplaterError> {
+    let template_error = |message: String| TemplaterError::Template { name: name.to_string(), message };
+
+    if let Some(placeholder) = find_old_placeholder(&source) {
+        return Err(template_error(format!(
+            "uses the old %%{}%% placeholder syntax. See the README for the template variables.",
+            placeholder
+        )));
+    }
+
+    env.add_template_owned(name.to_string(), source).map_err(|e| template_error(template_message(&e)))
+}
+
+// an error in an included template is wrapped by the including
+// template's error, e.g. "could not render include: ..."
+fn template_message(e: &minijinja::Error) -> String {
+    let mut message = e.to_string();
+
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+
+    message
+}
+
+// templates from before minijinja, e.g. `%%TITLE%%`
+fn find_old_placeholder(source: &str) -> Option<&str> {
+    source
+        .split("%%")
+        .skip(1)
+        .find(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_uppercase() || c == '_'))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn it_falls_back_to_the_default_templates() {
+        let config = Config { templates_dir: PathBuf::from("./test/missing"), ..Default::default() };
+
+        assert_eq!(read_template(&config, "voice", DEFAULT_TEMPLATES[6].1).unwrap(), DEFAULT_TEMPLATES[6].1);
+        assert!(DEFAULT_TEMPLATES.iter().all(|(_, source)| find_old_placeholder(source).is_none()));
+    }
+
+    #[test]
+    fn it_finds_old_placeholders() {
+        assert_eq!(find_old_placeholder("title = \"%%TITLE%%\""), Some("TITLE"));
+        assert_eq!(find_old_placeholder("%%instrument = \"{{ instrument }}\""), None);
+        assert_eq!(find_old_placeholder("%% TODO: make this better"), None);
+    }
+}
@@ -0,0 +1,129 @@
+// a song's `bpm` in the front matter: a number, e.g. `bpm: 240`, a
+// range, e.g. `bpm: 120-140`, or a term, e.g. `bpm: Medium Swing`,
+// which stands for the usual range of its tempo. the header shows
+// the term and a metronome mark, and the midi file and tempo index
+// use the middle of the range.
+
+use std::fmt;
+use std::str::FromStr;
+
+use minijinja::{context, Value};
+
+use crate::errors::TemplaterError;
+
+// (term, slowest, fastest), the way jazz lead sheets use them
+const TEMPO_TERMS: &[(&str, u32, u32)] = &[
+    ("ballad", 50, 80),
+    ("slow", 60, 90),
+    ("medium slow", 90, 110),
+    ("medium", 110, 150),
+    ("medium swing", 120, 160),
+    ("medium up", 160, 200),
+    ("medium fast", 160, 200),
+    ("up", 200, 260),
+    ("up tempo", 200, 260),
+    ("bright", 200, 260),
+    ("fast", 240, 300),
+    ("very fast", 300, 360),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tempo {
+    pub slowest: u32,
+    pub fastest: u32,
+    // e.g. "Medium Swing", if it was given as one
+    pub term: Option<String>,
+}
+
+// e.g. "Med. Up Swing" is "medium up", "Upswing" is "up"
+fn find_term(term: &str) -> Option<(u32, u32)> {
+    let term = term.to_lowercase().replace(['-', '.'], " ");
+    let words: Vec<&str> = term.split_whitespace().map(|word| if word == "med" { "medium" } else { word }).collect();
+    let term = words.join(" ");
+    let find = |term: &str| {
+        TEMPO_TERMS.iter().find(|(name, ..)| *name == term).map(|(_, slowest, fastest)| (*slowest, *fastest))
+    };
+
+    find(&term).or_else(|| find(term.strip_suffix("swing")?.trim()))
+}
+
+impl Tempo {
+    // what the midi file plays, and what the tempo index sorts by
+    pub fn bpm(&self) -> u32 {
+        (self.slowest + self.fastest) / 2
+    }
+
+    // e.g. "240" or "120–140"
+    pub fn mark(&self) -> String {
+        match self.slowest == self.fastest {
+            true => self.slowest.to_string(),
+            false => format!("{}–{}", self.slowest, self.fastest),
+        }
+    }
+
+    pub fn context(&self) -> Value {
+        context! { term => self.term.as_deref(), mark => self.mark(), bpm => self.bpm() }
+    }
+}
+
+impl From<u32> for Tempo {
+    fn from(bpm: u32) -> Self {
+        Tempo { slowest: bpm, fastest: bpm, term: None }
+    }
+}
+
+// e.g. "Medium Swing (120–160)" or "240"
+impl fmt::Display for Tempo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.term {
+            Some(term) => write!(f, "{} ({})", term, self.mark()),
+            None => write!(f, "{}", self.mark()),
+        }
+    }
+}
+
+impl FromStr for Tempo {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |message: String| TemplaterError::from_str(&message);
+        let s = s.trim();
+
+        if let Ok(bpm) = s.parse::<u32>() {
+            return Ok(Tempo::from(bpm));
+        }
+        if let Some((slowest, fastest)) = s.split_once(['-', '–']) {
+            if let (Ok(slowest), Ok(fastest)) = (slowest.trim().parse::<u32>(), fastest.trim().parse::<u32>()) {
+                return match slowest <= fastest {
+                    true => Ok(Tempo { slowest, fastest, term: None }),
+                    false => Err(error(format!("the tempo range '{}' is backwards, e.g. 120-140", s))),
+                };
+            }
+        }
+
+        match find_term(s) {
+            Some((slowest, fastest)) => Ok(Tempo { slowest, fastest, term: Some(s.to_string()) }),
+            None => Err(error(format!(
+                "unknown tempo '{}'. Expected a bpm, e.g. 132, a range, e.g. 120-140, or a tempo like Medium Swing.",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_every_kind_of_tempo() {
+        assert_eq!("240".parse::<Tempo>().unwrap(), Tempo::from(240));
+        assert_eq!("120–140".parse::<Tempo>().unwrap().mark(), "120–140");
+        assert_eq!("120 - 140".parse::<Tempo>().unwrap().bpm(), 130);
+        assert_eq!("Medium Swing".parse::<Tempo>().unwrap().to_string(), "Medium Swing (120–160)");
+        assert_eq!("Med. Up Swing".parse::<Tempo>().unwrap().mark(), "160–200");
+        assert_eq!("Up-Swing".parse::<Tempo>().unwrap().bpm(), 230);
+        assert!("140-120".parse::<Tempo>().is_err());
+        assert!("Bossa".parse::<Tempo>().is_err());
+    }
+}
@@ -0,0 +1,148 @@
+// how chords and pickups line up with measures, for the importers and
+// exporters. an import (`abc.rs`, `musicxml.rs`) has the chords of
+// each measure at their offsets, and writes them as a `\chordmode`
+// measure: every chord lasts until the next one, or the end of the
+// measure, and a measure starting without one continues the last
+// chord. a first measure shorter than the time signature is a pickup,
+// which is only as long as its notes. an export (`abc.rs`, `ireal.rs`)
+// goes the other way, counting its way through a song's measures from
+// its `\partial`.
+
+use std::ops::Sub;
+
+use crate::music::Fraction;
+
+// a measure's length, and whether it's a pickup, if it can be one
+// (e.g. the first). a pickup is only as long as its notes, and any
+// other measure is at least a full one
+pub fn measure_length<T: Copy + Ord + Default>(notes: T, full: T, can_be_pickup: bool) -> (T, bool) {
+    match can_be_pickup && notes > T::default() && notes < full {
+        true => (notes, true),
+        false => (full.max(notes), false),
+    }
+}
+
+// the chords of one imported measure after another
+#[derive(Debug, Default)]
+pub struct ChordLine {
+    // (root, suffix) of the chord that's still sounding
+    last_chord: Option<(String, String)>,
+}
+
+impl ChordLine {
+    // a measure `length` long of its (offset, root, suffix) chords,
+    // e.g. `c2:m7 f2:7 |`, with `duration` writing a length, e.g. `4.`.
+    // a pickup starts with its `\partial`
+    pub fn measure<T, D>(&mut self, chords: Vec<(T, String, String)>, length: T, pickup: bool, duration: D) -> String
+    where
+        T: Copy + Ord + Default + Sub<Output = T>,
+        D: Fn(T) -> String,
+    {
+        let mut chords = chords;
+        chords.sort_by_key(|(at, ..)| *at);
+        if chords.first().is_none_or(|(at, ..)| *at > T::default()) {
+            let (root, suffix) = self.last_chord.clone().unwrap_or((String::from("s"), String::new()));
+            chords.insert(0, (T::default(), root, suffix));
+        }
+
+        let mut tokens = vec![];
+        if pickup {
+            tokens.push(format!("\\partial {}", duration(length)));
+        }
+        for (i, (at, root, suffix)) in chords.iter().enumerate() {
+            let end = chords.get(i + 1).map_or(length, |(next, ..)| *next).min(length);
+            if end <= *at {
+                continue;
+            }
+
+            tokens.push(format!("{}{}{}", root, duration(end - *at), suffix));
+            if root != "s" {
+                self.last_chord = Some((root.clone(), suffix.clone()));
+            }
+        }
+
+        tokens.push(String::from("|"));
+        tokens.join(" ")
+    }
+}
+
+// where an export is in a song's measures
+#[derive(Debug, Clone)]
+pub struct Clock {
+    // since the start
+    pub time: Fraction,
+    // in the measure
+    pub position: Fraction,
+    pub meter: (u64, u64),
+    // whether the measure is the pickup
+    pub pickup: bool,
+}
+
+impl Clock {
+    pub fn new(meter: (u64, u64)) -> Self {
+        Clock { time: Fraction::ZERO, position: Fraction::ZERO, meter, pickup: false }
+    }
+
+    pub fn measure(&self) -> Fraction {
+        Fraction::new(self.meter.0, self.meter.1)
+    }
+
+    // a `\partial`, which only makes a pickup at the start
+    pub fn partial(&mut self, duration: Fraction) {
+        if self.time == Fraction::ZERO && duration < self.measure() {
+            self.position = self.measure() - duration;
+            self.pickup = true;
+        }
+    }
+
+    // `length` later, returning whether each measure that ended was
+    // the pickup
+    pub fn advance(&mut self, length: Fraction) -> Vec<bool> {
+        self.time = self.time + length;
+        self.position = self.position + length;
+
+        let mut ended = vec![];
+        while self.measure().num > 0 && self.position >= self.measure() {
+            self.position = self.position - self.measure();
+            ended.push(std::mem::take(&mut self.pickup));
+        }
+        ended
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chord(at: u32, root: &str, suffix: &str) -> (u32, String, String) {
+        (at, root.to_string(), suffix.to_string())
+    }
+
+    #[test]
+    fn it_writes_a_measure_of_chords() {
+        let mut line = ChordLine::default();
+        let duration = |length: u32| (4 / length).to_string();
+        let (length, pickup) = measure_length(1, 4, true);
+        assert_eq!(line.measure(vec![], length, pickup, duration), "\\partial 4 s4 |");
+
+        let (length, pickup) = measure_length(4, 4, false);
+        let chords = vec![chord(2, "g", ":7"), chord(0, "c", ":m7")];
+        assert_eq!(line.measure(chords, length, pickup, duration), "c2:m7 g2:7 |");
+        // the last chord goes on
+        assert_eq!(line.measure(vec![chord(2, "f", "")], length, pickup, duration), "g2:7 f2 |");
+    }
+
+    #[test]
+    fn it_counts_the_pickup() {
+        let mut clock = Clock::new((4, 4));
+        clock.partial(Fraction::new(1, 4));
+        assert_eq!(clock.advance(Fraction::new(1, 4)), vec![true]);
+        assert_eq!(clock.advance(Fraction::new(1, 2)), Vec::<bool>::new());
+        assert_eq!(clock.advance(Fraction::new(3, 2)), vec![false, false]);
+        assert_eq!(clock.position, Fraction::ZERO);
+
+        // a `\partial` later on isn't a pickup
+        clock.partial(Fraction::new(1, 4));
+        assert!(!clock.pickup);
+    }
+}
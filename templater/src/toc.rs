@@ -0,0 +1,329 @@
+// the alphabetical table of contents, and extra indices beyond
+// it. each index groups songs by a frontmatter field. both render
+// a `\markuplist` with page references to every song's label.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use minijinja::{context, Value};
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::form::song_form;
+use crate::models::{Song, TransposeText};
+use crate::order::SortOrder;
+use crate::capitalize_first_letter;
+use crate::templates::Templates;
+
+// bpm values are grouped into buckets of this size, e.g. 120-139
+const BPM_BUCKET_SIZE: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexKind {
+    Composer,
+    Meter,
+    Bpm,
+    Key,
+    Tag,
+    Time,
+}
+
+impl IndexKind {
+    // the default ones. `time` is only in a book that asks for it,
+    // since most books would list every song under 4/4
+    pub fn all() -> Vec<IndexKind> {
+        vec![IndexKind::Composer, IndexKind::Meter, IndexKind::Bpm, IndexKind::Key, IndexKind::Tag]
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            IndexKind::Composer => "Index by Composer",
+            IndexKind::Meter => "Index by Meter",
+            IndexKind::Bpm => "Index by Tempo (BPM)",
+            IndexKind::Key => "Index by Key",
+            IndexKind::Tag => "Index by Style",
+            IndexKind::Time => "Index by Time Signature",
+        }
+    }
+
+    // keys are built so that BTreeMap's ordering is also the
+    // display order. bpm buckets are zero padded for that reason.
+    // songs are indexed by the key the book's instrument reads, and
+    // under every one of their tags.
+    fn group_keys(&self, song: &Song, transpose_text: &TransposeText) -> Vec<(String, String)> {
+        let group = match self {
+            IndexKind::Composer => Some((song.composer_sort.clone(), song.composer.clone())),
+            IndexKind::Meter => song
+                .meter
+                .as_ref()
+                .filter(|m| !m.trim().is_empty())
+                .map(|m| (m.to_lowercase(), m.clone())),
+            IndexKind::Bpm => song.bpm().map(bpm_range),
+            // under every key it modulates to as well
+            IndexKind::Key => {
+                return song
+                    .keys()
+                    .iter()
+                    .map(|key| {
+                        let key = key.transposed(&song.transposition(transpose_text));
+                        (key.sort_key(), key.to_string())
+                    })
+                    .collect()
+            },
+            IndexKind::Tag => {
+                return song.tags.iter().map(|tag| (tag.to_lowercase(), capitalize_first_letter(tag))).collect()
+            },
+            // under every meter it changes to, e.g. 2/4 before 3/4 before 6/8
+            IndexKind::Time => {
+                return song
+                    .time_signatures
+                    .iter()
+                    .map(|time| (format!("{:04}/{:04}", time.unit, time.beats), time.to_string()))
+                    .collect()
+            },
+        };
+
+        group.into_iter().collect()
+    }
+}
+
+// (sort key, display name) of the bucket a bpm is in, e.g. "120-139 bpm"
+pub(crate) fn bpm_range(bpm: u32) -> (String, String) {
+    let low = bpm - bpm % BPM_BUCKET_SIZE;
+    let high = low + BPM_BUCKET_SIZE - 1;
+    (format!("{:04}", low), format!("{}-{} bpm", low, high))
+}
+
+impl fmt::Display for IndexKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexKind::Composer => write!(f, "composer"),
+            IndexKind::Meter => write!(f, "meter"),
+            IndexKind::Bpm => write!(f, "bpm"),
+            IndexKind::Key => write!(f, "key"),
+            IndexKind::Tag => write!(f, "tag"),
+            IndexKind::Time => write!(f, "time"),
+        }
+    }
+}
+
+impl FromStr for IndexKind {
+    type Err = TemplaterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "composer" => Ok(IndexKind::Composer),
+            "meter" => Ok(IndexKind::Meter),
+            "bpm" => Ok(IndexKind::Bpm),
+            "key" => Ok(IndexKind::Key),
+            "tag" | "tags" => Ok(IndexKind::Tag),
+            "time" | "time_signature" => Ok(IndexKind::Time),
+            _ => Err(TemplaterError::from_str(&format!(
+                "Unknown index '{}'. Expected composer, meter, bpm, key, tag, or time.",
+                s
+            ))),
+        }
+    }
+}
+
+// parses a comma delimited list, e.g. "composer,bpm". an empty
+// string disables all indices.
+pub fn parse_index_kinds(input: &str) -> Result<Vec<IndexKind>, TemplaterError> {
+    input
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(IndexKind::from_str)
+        .collect()
+}
+
+// (display name, songs) pairs, sorted by group and then by title.
+// songs without a value for the given field are left out.
+pub fn group_songs<'a>(
+    kind: IndexKind,
+    songs: &'a [Song],
+    transpose_text: &TransposeText,
+) -> Vec<(String, Vec<&'a Song>)> {
+    let mut groups: BTreeMap<String, (String, Vec<&Song>)> = BTreeMap::new();
+
+    for song in songs {
+        for (key, display) in kind.group_keys(song, transpose_text) {
+            groups
+                .entry(key)
+                .or_insert_with(|| (display, vec![]))
+                .1
+                .push(song);
+        }
+    }
+
+    groups
+        .into_values()
+        .map(|(display, mut songs)| {
+            songs.sort_by(|a, b| a.cmp_title(b));
+            (display, songs)
+        })
+        .collect()
+}
+
+// every song, in book order. entries link to the song's page.
+// `toc_form` adds their form, or else how many measures they are.
+// a book that isn't in title order has a heading before the first
+// song of every group, e.g. every composer.
+pub fn render_toc(
+    templates: &Templates,
+    songs: &[Song],
+    toc_form: bool,
+    sort: SortOrder,
+    transpose_text: &TransposeText,
+) -> Result<String, TemplaterError> {
+    let mut previous = None;
+    let songs: Vec<Value> = songs
+        .iter()
+        .map(|song| {
+            let heading = sort.heading(song, transpose_text);
+            let first = heading != previous;
+            previous = heading.clone();
+            context! {
+                heading => heading.filter(|_| first),
+                title => song.title.as_str(),
+                composer => song.composer.as_str(),
+                number => song.number,
+                label => song.label(),
+                form => song_form(song).map(|form| form.to_string()).unwrap_or_default(),
+                measures => song.measures,
+                collection => song.collection.as_deref(),
+            }
+        })
+        .collect();
+
+    templates.render("toc", context! { songs, toc_form })
+}
+
+pub fn render_index(
+    templates: &Templates,
+    kind: IndexKind,
+    songs: &[Song],
+    transpose_text: &TransposeText,
+) -> Result<Option<String>, TemplaterError> {
+    let groups = group_songs(kind, songs, transpose_text);
+    if groups.is_empty() {
+        return Ok(None);
+    }
+
+    let groups: Vec<Value> = groups
+        .into_iter()
+        .map(|(name, songs)| {
+            let songs: Vec<Value> = songs
+                .into_iter()
+                .map(|song| context! { title => song.title.as_str(), number => song.number, label => song.label() })
+                .collect();
+
+            context! { name, songs }
+        })
+        .collect();
+
+    let index = templates.render("index", context! { index_title => kind.title(), groups })?;
+
+    Ok(Some(index))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::transpose::transpose_text;
+    use std::path::Path;
+
+    fn song(front_matter: Vec<&str>) -> Song {
+        Song::parse(Path::new("test.ly"), &front_matter.join("\n"), false).unwrap()
+    }
+
+    fn titles(groups: Vec<(String, Vec<&Song>)>) -> Vec<(String, Vec<String>)> {
+        groups.into_iter().map(|(k, v)| (k, v.into_iter().map(|s| s.title.clone()).collect())).collect()
+    }
+
+    #[test]
+    fn it_groups_songs_by_bpm_bucket() {
+        let songs = vec![
+            song(vec!["title: Oleo", "composer: Sonny Rollins", "bpm: 240"]),
+            song(vec!["title: Airegin", "composer: Sonny Rollins", "bpm: 255"]),
+            song(vec!["title: Misty", "composer: Errol Garner", "bpm: 72"]),
+            song(vec!["title: Nardis", "composer: Miles Davis"]),
+        ];
+
+        let groups = titles(group_songs(IndexKind::Bpm, &songs, &transpose_text("c").unwrap()));
+
+        assert_eq!(
+            groups,
+            vec![
+                (String::from("60-79 bpm"), vec![String::from("Misty")]),
+                (String::from("240-259 bpm"), vec![String::from("Airegin"), String::from("Oleo")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_groups_songs_by_written_key() {
+        let songs = vec![
+            song(vec!["title: Misty", "key: Eb"]),
+            song(vec!["title: Oleo", "key: bb"]),
+            song(vec!["title: Nardis", "key: e minor"]),
+            song(vec!["title: Airegin", "key: f minor", "transpose_override: c"]),
+            song(vec!["title: Blue Monk"]),
+            song(vec!["title: Have You Met Miss Jones", "key: f", "---", "{ \\numericTimeSignature \\key bes \\major c1 }"]),
+        ];
+
+        assert_eq!(
+            titles(group_songs(IndexKind::Key, &songs, &transpose_text("bb").unwrap())),
+            vec![
+                (String::from("C major"), vec![String::from("Have You Met Miss Jones"), String::from("Oleo")]),
+                (String::from("F major"), vec![String::from("Misty")]),
+                (String::from("F minor"), vec![String::from("Airegin")]),
+                (String::from("F# minor"), vec![String::from("Nardis")]),
+                (String::from("G major"), vec![String::from("Have You Met Miss Jones")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_lists_songs_under_every_tag() {
+        let songs = vec![
+            song(vec!["title: Ceora", "tags: [bossa, ballad]"]),
+            song(vec!["title: Misty", "tags: Ballad"]),
+            song(vec!["title: Oleo"]),
+        ];
+
+        assert_eq!(
+            titles(group_songs(IndexKind::Tag, &songs, &transpose_text("c").unwrap())),
+            vec![
+                (String::from("Ballad"), vec![String::from("Ceora"), String::from("Misty")]),
+                (String::from("Bossa"), vec![String::from("Ceora")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_lists_songs_under_every_time_signature() {
+        let songs = vec![
+            song(vec!["title: Waltz", "---", "{ \\numericTimeSignature \\time 3/4 c2. | \\time 4/4 c1 | \\time 3/4 c2. }"]),
+            song(vec!["title: Oleo", "---", "{ \\numericTimeSignature c1 }"]),
+            song(vec!["title: Nardis"]),
+        ];
+
+        assert_eq!(
+            titles(group_songs(IndexKind::Time, &songs, &transpose_text("c").unwrap())),
+            vec![
+                (String::from("3/4"), vec![String::from("Waltz")]),
+                (String::from("4/4"), vec![String::from("Oleo"), String::from("Waltz")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_parses_index_kinds() {
+        assert_eq!(parse_index_kinds("composer, bpm").unwrap(), vec![IndexKind::Composer, IndexKind::Bpm]);
+        assert!(parse_index_kinds("").unwrap().is_empty());
+        assert!(parse_index_kinds("year").is_err());
+    }
+}
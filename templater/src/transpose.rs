@@ -0,0 +1,561 @@
+// computes lilypond's `\transpose` pair for any instrument key.
+//
+// an instrument in key K reads concert pitch transposed from K up
+// to c, e.g. a Bb instrument reads a major 2nd higher: `c d`.
+// the written pitch is always kept within the octave above `c`,
+// and the octave suffix (+8va, -8vb, ...) shifts it from there.
+//
+// `bass` is concert pitch in bass clef, an octave lower than the
+// treble clef books so the melody sits on the staff. a suffix
+// shifts it from there too, e.g. `bass+8va` keeps the melody at
+// concert pitch.
+//
+// `transpositions.toml` adds presets for anything else, by name:
+//
+//     # --transpose alto-flute
+//     [alto-flute]
+//     name = "Alto Flute in G"
+//     # lilypond's `\transpose` pair
+//     transpose = "c f"
+//     # optional
+//     clef = "treble"
+//     octave = 0
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::errors::TemplaterError;
+use crate::models::TransposeText;
+use crate::spelling::Spelling;
+
+pub const DEFAULT_TRANSPOSITIONS_FILE: &str = "transpositions.toml";
+
+const LETTERS: [char; 7] = ['c', 'd', 'e', 'f', 'g', 'a', 'b'];
+const SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+const BASS_OCTAVE_SHIFT: i32 = -1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pitch {
+    // index into LETTERS
+    pub step: usize,
+    // semitones, negative for flats
+    pub alteration: i32,
+    // relative to the octave of an unmarked lilypond `c`
+    pub octave: i32,
+}
+
+impl Pitch {
+    pub fn pitch_class(&self) -> i32 {
+        (SEMITONES[self.step] + self.alteration).rem_euclid(12)
+    }
+
+    // above the unmarked `c`, e.g. 14 for `d'`
+    pub fn semitones(&self) -> i32 {
+        self.octave * 12 + SEMITONES[self.step] + self.alteration
+    }
+
+    // moved up by the interval from `c` to `to`, and spelled the
+    // way lilypond's `\transpose c <to>` would. octaves are ignored.
+    pub fn transpose(&self, to: &Pitch) -> Pitch {
+        let step = (self.step + to.step) % LETTERS.len();
+        let mut alteration = (self.pitch_class() + to.pitch_class() - SEMITONES[step]).rem_euclid(12);
+        if alteration > 6 {
+            alteration -= 12;
+        }
+
+        Pitch { step, alteration, octave: 0 }
+    }
+
+    // the same pitch spelled `letters` letters higher, or lower if it's
+    // negative, e.g. `d` is `eeses` one up and `cisis` one down
+    pub fn enharmonic(&self, letters: i32) -> Pitch {
+        let steps = self.octave * LETTERS.len() as i32 + self.step as i32 + letters;
+        let step = steps.rem_euclid(LETTERS.len() as i32) as usize;
+        let octave = steps.div_euclid(LETTERS.len() as i32);
+        let semitones = self.octave * 12 + SEMITONES[self.step] + self.alteration;
+
+        Pitch { step, alteration: semitones - octave * 12 - SEMITONES[step], octave }
+    }
+
+    // e.g. "bes", "fis'", "c,"
+    pub fn lilypond_name(&self) -> String {
+        let mut s = String::from(LETTERS[self.step]);
+
+        let accidental = if self.alteration < 0 { "es" } else { "is" };
+        for _ in 0..self.alteration.abs() {
+            s.push_str(accidental);
+        }
+
+        let octave_mark = if self.octave < 0 { ',' } else { '\'' };
+        for _ in 0..self.octave.abs() {
+            s.push(octave_mark);
+        }
+
+        s
+    }
+
+    // e.g. "Bb", "F#"
+    pub fn display_name(&self) -> String {
+        let mut s = LETTERS[self.step].to_ascii_uppercase().to_string();
+        let accidental = if self.alteration < 0 { 'b' } else { '#' };
+        for _ in 0..self.alteration.abs() {
+            s.push(accidental);
+        }
+
+        s
+    }
+}
+
+impl FromStr for Pitch {
+    type Err = TemplaterError;
+
+    // accepts "bb", "eb", "f#", and lilypond style "bes", "fis"
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let err = || TemplaterError::Transposition(format!("'{}' is not a valid key.", input));
+
+        let mut chars = input.chars();
+        let step = chars
+            .next()
+            .and_then(|c| LETTERS.iter().position(|l| *l == c))
+            .ok_or_else(err)?;
+
+        let mut rest = chars.as_str();
+        let mut alteration = 0;
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix("es").or_else(|| rest.strip_prefix('b')) {
+                alteration -= 1;
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("is").or_else(|| rest.strip_prefix('#')) {
+                alteration += 1;
+                rest = r;
+            } else {
+                return Err(err());
+            }
+        }
+
+        Ok(Pitch { step, alteration, octave: 0 })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transposition {
+    pub key: Pitch,
+    pub octave_shift: i32,
+    // e.g. "bass", for every voice. `None` leaves the clef alone
+    pub clef: Option<&'static str>,
+}
+
+impl Transposition {
+    // the written pitch of a concert `c`
+    pub fn target(&self) -> Pitch {
+        let step = (LETTERS.len() - self.key.step) % LETTERS.len();
+        let semitones = (12 - self.key.pitch_class()) % 12;
+
+        let mut alteration = semitones - SEMITONES[step];
+        let mut octave = self.octave_shift;
+        if alteration > 6 {
+            alteration -= 12;
+            octave += 1;
+        } else if alteration < -6 {
+            alteration += 12;
+            octave -= 1;
+        }
+
+        Pitch { step, alteration, octave }
+    }
+
+    pub fn lilypond_text(&self) -> String {
+        format!("c {}", self.target().lilypond_name())
+    }
+
+    pub fn display_text(&self) -> String {
+        let (key, shift) = match (self.clef, self.key) {
+            (Some("bass"), _) => (String::from("Bass Clef"), self.octave_shift - BASS_OCTAVE_SHIFT),
+            (_, Pitch { step: 0, alteration: 0, .. }) => (String::from("Concert"), self.octave_shift),
+            (_, key) => (key.display_name(), self.octave_shift),
+        };
+
+        match shift {
+            0 => key,
+            shift => format!("{}{}", key, octave_suffix(shift)),
+        }
+    }
+
+    pub fn transpose_text(&self) -> TransposeText {
+        TransposeText {
+            display_text: self.display_text(),
+            lilypond_text: self.lilypond_text(),
+            clef: self.clef.map(String::from),
+            spelling: Spelling::default(),
+        }
+    }
+}
+
+fn octave_suffix(shift: i32) -> &'static str {
+    match shift {
+        2 => "+15ma",
+        1 => "+8va",
+        -1 => "-8vb",
+        -2 => "-15mb",
+        _ => "",
+    }
+}
+
+impl FromStr for Transposition {
+    type Err = TemplaterError;
+
+    // e.g. "bb", "f", "bb+8va", "eb-8vb", "bass"
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim().to_lowercase();
+        let (key, octave_shift) = match input.find(['+', '-']) {
+            Some(i) => {
+                let shift = match &input[i..] {
+                    "+15ma" => 2,
+                    "+8va" => 1,
+                    "-8vb" => -1,
+                    "-15mb" => -2,
+                    other => {
+                        return Err(TemplaterError::Transposition(format!(
+                            "Unknown octave shift '{}'. Expected +8va, -8vb, +15ma, or -15mb.",
+                            other
+                        )))
+                    },
+                };
+                (&input[..i], shift)
+            },
+            None => (input.as_str(), 0),
+        };
+
+        match key {
+            "bass" => Ok(Transposition {
+                key: Pitch { step: 0, alteration: 0, octave: 0 },
+                octave_shift: octave_shift + BASS_OCTAVE_SHIFT,
+                clef: Some("bass"),
+            }),
+            key => Ok(Transposition { key: key.parse()?, octave_shift, clef: None }),
+        }
+    }
+}
+
+// a written pitch, e.g. "bes" or "fis'"
+pub(crate) fn parse_lilypond_pitch(input: &str) -> Result<Pitch, TemplaterError> {
+    let name = input.trim_end_matches(['\'', ',']);
+    let marks = &input[name.len()..];
+    if marks.contains('\'') && marks.contains(',') {
+        return Err(TemplaterError::Transposition(format!("'{}' is not a valid pitch.", input)));
+    }
+
+    let octave = marks.len() as i32 * if marks.starts_with(',') { -1 } else { 1 };
+    Ok(Pitch { octave, ..name.parse()? })
+}
+
+// the written pitch of a concert `c` for `\transpose <from> <to>`,
+// so every pair is written as "c <to>" like the built in ones
+fn pair_target(from: &Pitch, to: &Pitch) -> Pitch {
+    let steps = |p: &Pitch| p.octave * LETTERS.len() as i32 + p.step as i32;
+    let semitones = |p: &Pitch| p.octave * 12 + SEMITONES[p.step] + p.alteration;
+
+    let distance = steps(to) - steps(from);
+    let step = distance.rem_euclid(LETTERS.len() as i32) as usize;
+    let octave = distance.div_euclid(LETTERS.len() as i32);
+    let alteration = semitones(to) - semitones(from) - octave * 12 - SEMITONES[step];
+
+    Pitch { step, alteration, octave }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    // e.g. "Alto Flute in G", on the cover and in the filename
+    pub name: String,
+    // e.g. "c f", read from the left pitch to the right one
+    pub transpose: String,
+    // for every voice, e.g. "bass"
+    #[serde(default)]
+    pub clef: Option<String>,
+    // octaves added to the written pitch
+    #[serde(default)]
+    pub octave: i32,
+}
+
+impl Preset {
+    pub fn transpose_text(&self) -> Result<TransposeText, TemplaterError> {
+        let pitches: Vec<&str> = self.transpose.split_whitespace().collect();
+        let [from, to] = pitches[..] else {
+            return Err(TemplaterError::Transposition(format!(
+                "'{}' is not a \\transpose pair, e.g. \"c f\".",
+                self.transpose
+            )));
+        };
+
+        let mut target = pair_target(&parse_lilypond_pitch(from)?, &parse_lilypond_pitch(to)?);
+        target.octave += self.octave;
+
+        Ok(TransposeText {
+            display_text: self.name.clone(),
+            lilypond_text: format!("c {}", target.lilypond_name()),
+            clef: self.clef.clone(),
+            spelling: Spelling::default(),
+        })
+    }
+}
+
+// the presets of one config's `transpositions.toml`, by lowercase
+// name. every book loads its own, like its templates.
+#[derive(Debug, Clone, Default)]
+pub struct Presets(BTreeMap<String, TransposeText>);
+
+impl Presets {
+    // a missing file has no presets. the names can't be ones
+    // `--transpose` already knows, e.g. `bb`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TemplaterError> {
+        let path = path.as_ref();
+        let error = |message: String| TemplaterError::Transposition(format!("{}: {}", path.display(), message));
+
+        let presets: BTreeMap<String, Preset> = match fs::read_to_string(path) {
+            Ok(input) => toml::from_str(&input).map_err(|e| error(e.to_string()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(TemplaterError::file(path, e)),
+        };
+
+        let mut transpose_texts = BTreeMap::new();
+        for (name, preset) in presets {
+            if name.parse::<Transposition>().is_ok() {
+                return Err(error(format!("'{}' is already a built in transposition", name)));
+            }
+            let transpose_text = preset.transpose_text().map_err(|e| error(format!("[{}]: {}", name, e)))?;
+            transpose_texts.insert(name.to_lowercase(), transpose_text);
+        }
+
+        Ok(Presets(transpose_texts))
+    }
+
+    // one of the presets, or a built in transposition
+    pub fn transpose_text(&self, input: &str) -> Result<TransposeText, TemplaterError> {
+        match self.0.get(&input.trim().to_lowercase()) {
+            Some(preset) => Ok(preset.clone()),
+            None => transpose_text(input),
+        }
+    }
+}
+
+// a built in transposition, see `Presets` for the others
+pub fn transpose_text(input: &str) -> Result<TransposeText, TemplaterError> {
+    let transposition: Transposition = input
+        .parse()
+        .map_err(|e: TemplaterError| TemplaterError::Transposition(format!("Transposing '{}' not supported. {}", input, e)))?;
+
+    Ok(transposition.transpose_text())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontmatter::split;
+    use crate::music::{events, Event};
+
+    // the fixture songs, whose pitches are transposed there and back
+    const SONGS: [(&str, &str); 5] = [
+        ("misty", include_str!("../tests/fixtures/songs/misty.ly")),
+        ("nardis", include_str!("../tests/fixtures/songs/nardis.ly")),
+        ("nows_the_time", include_str!("../tests/fixtures/songs/nows_the_time.ly")),
+        ("oleo", include_str!("../tests/fixtures/realbook/songs/oleo.ly")),
+        ("tiger_rag", include_str!("../tests/fixtures/realbook/songs/tiger_rag.ly")),
+    ];
+
+    fn lilypond_text(input: &str) -> String {
+        input.parse::<Transposition>().unwrap().lilypond_text()
+    }
+
+    #[test]
+    fn it_keeps_the_builtin_transpositions() {
+        assert_eq!("c c", lilypond_text("c"));
+        assert_eq!("c d", lilypond_text("bb"));
+        assert_eq!("c a", lilypond_text("eb"));
+    }
+
+    #[test]
+    fn it_transposes_arbitrary_keys() {
+        assert_eq!("c g", lilypond_text("f"));
+        assert_eq!("c ees", lilypond_text("a"));
+        assert_eq!("c f", lilypond_text("g"));
+        assert_eq!("c ces'", lilypond_text("c#"));
+        assert_eq!("c cis", lilypond_text("cb"));
+        assert_eq!("c bes", lilypond_text("d"));
+        assert_eq!("c b", lilypond_text("des"));
+    }
+
+    #[test]
+    fn it_transposes_pitches() {
+        let pitch = |s: &str| s.parse::<Pitch>().unwrap();
+
+        assert_eq!(pitch("eb").transpose(&pitch("d")).display_name(), "F");
+        assert_eq!(pitch("f").transpose(&pitch("a")).display_name(), "D");
+        assert_eq!(pitch("c").transpose(&pitch("ees")).display_name(), "Eb");
+        assert_eq!(pitch("b").transpose(&pitch("d")).display_name(), "C#");
+
+        assert_eq!(pitch("d").enharmonic(1).lilypond_name(), "eeses");
+        assert_eq!(pitch("d").enharmonic(-1).lilypond_name(), "cisis");
+        assert_eq!(pitch("c").enharmonic(-1).lilypond_name(), "bis,");
+        assert_eq!(pitch("b").enharmonic(1).lilypond_name(), "ces'");
+    }
+
+    #[test]
+    fn it_shifts_octaves() {
+        assert_eq!("c d'", lilypond_text("bb+8va"));
+        assert_eq!("c a'", lilypond_text("eb+8va"));
+        assert_eq!("c c,", lilypond_text("c-8vb"));
+        assert_eq!("c c,,", lilypond_text("c-15mb"));
+    }
+
+    #[test]
+    fn it_displays_keys() {
+        let display = |s: &str| s.parse::<Transposition>().unwrap().display_text();
+
+        assert_eq!("Concert", display("c"));
+        assert_eq!("Bb", display("bb"));
+        assert_eq!("F#", display("fis"));
+        assert_eq!("Bb+8va", display("Bb+8va"));
+    }
+
+    #[test]
+    fn it_transposes_for_bass_clef() {
+        let bass: Transposition = "bass".parse().unwrap();
+
+        assert_eq!("c c,", bass.lilypond_text());
+        assert_eq!("Bass Clef", bass.display_text());
+        assert_eq!(bass.transpose_text().clef.as_deref(), Some("bass"));
+        assert_eq!("c c", lilypond_text("bass+8va"));
+        assert_eq!("Bass Clef+8va", "bass+8va".parse::<Transposition>().unwrap().display_text());
+    }
+
+    #[test]
+    fn it_reads_presets() {
+        let preset = |transpose: &str, octave: i32| {
+            let preset = Preset { name: String::from("Alto Flute in G"), transpose: transpose.to_string(), clef: None, octave };
+            preset.transpose_text().map(|transpose_text| transpose_text.lilypond_text)
+        };
+
+        assert_eq!(preset("c f", 0).unwrap(), "c f");
+        assert_eq!(preset("c f", 1).unwrap(), "c f'");
+        assert_eq!(preset("g c'", 0).unwrap(), "c f");
+        assert_eq!(preset("bes c'", 0).unwrap(), "c d");
+        assert_eq!(preset("bes c", 0).unwrap(), "c d,");
+        assert_eq!(preset("c' bes", 0).unwrap(), "c bes,");
+        assert!(preset("c", 0).is_err());
+        assert!(preset("c h", 0).is_err());
+    }
+
+    #[test]
+    fn it_keeps_every_configs_presets() {
+        let dir = std::env::temp_dir().join(format!("templater-presets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let load = |name: &str, transpose: &str| {
+            let path = dir.join(name);
+            fs::write(&path, format!("[alto-flute]\nname = \"Alto Flute\"\ntranspose = \"{}\"\n", transpose)).unwrap();
+            Presets::load(path).unwrap()
+        };
+
+        let (g, f) = (load("g.toml", "c f"), load("f.toml", "c g"));
+        assert_eq!(g.transpose_text("Alto-Flute").unwrap().lilypond_text, "c f");
+        assert_eq!(f.transpose_text("alto-flute").unwrap().lilypond_text, "c g");
+        assert!(Presets::load(dir.join("none.toml")).unwrap().transpose_text("alto-flute").is_err());
+        assert!(transpose_text("alto-flute").is_err());
+        assert_eq!(g.transpose_text("bb").unwrap().lilypond_text, "c d");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_invalid_keys() {
+        assert!("h".parse::<Transposition>().is_err());
+        assert!("bx".parse::<Transposition>().is_err());
+        assert!("bb+2va".parse::<Transposition>().is_err());
+    }
+
+    // every key `--transpose` knows, and bass clef, in every octave
+    fn builtin_transpositions() -> Vec<String> {
+        let keys = LETTERS.iter().flat_map(|letter| ["es", "", "is"].map(|accidental| format!("{}{}", letter, accidental)));
+        let keys: Vec<String> = keys.chain([String::from("bass")]).collect();
+
+        ["", "+8va", "-8vb", "+15ma", "-15mb"]
+            .iter()
+            .flat_map(|shift| keys.iter().map(move |key| format!("{}{}", key, shift)))
+            .collect()
+    }
+
+    // every pitch of a song's chords and melody, as it's written. the
+    // octave marks are kept as they are, relative or not.
+    fn pitch_stream(song: &str) -> Vec<Pitch> {
+        let (_, music) = split(song);
+        events(music)
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Note { pitch: Some(pitch), .. } => Some(pitch),
+                _ => None,
+            })
+            .map(|pitch| {
+                // e.g. "bes!" or "c='", and dutch "as" and "es"
+                let mut name = pitch.split('=').next().unwrap().replace(['!', '?'], "");
+                if name[1..].starts_with('s') {
+                    name.insert(1, 'e');
+                }
+                parse_lilypond_pitch(&name).unwrap_or_else(|e| panic!("'{}': {}", pitch, e))
+            })
+            .collect()
+    }
+
+    // moved up by the interval from `c` to `interval`, octaves and all,
+    // e.g. "bes'" up "d" is "c''"
+    fn transposed(pitch: &Pitch, interval: &Pitch) -> Pitch {
+        let octave = pitch.octave + interval.octave + ((pitch.step + interval.step) / LETTERS.len()) as i32;
+        Pitch { octave, ..pitch.transpose(interval) }
+    }
+
+    // counted from an unmarked `c`
+    fn semitones(pitch: &Pitch) -> i32 {
+        pitch.octave * 12 + SEMITONES[pitch.step] + pitch.alteration
+    }
+
+    #[test]
+    fn it_transposes_every_song_there_and_back() {
+        let c = Pitch { step: 0, alteration: 0, octave: 0 };
+
+        for name in builtin_transpositions() {
+            let transposition: Transposition = name.parse().unwrap();
+            // what lilypond is given, e.g. "c d" for `bb`
+            let lilypond_text = transposition.lilypond_text();
+            let up = parse_lilypond_pitch(lilypond_text.strip_prefix("c ").unwrap()).unwrap();
+            assert_eq!(up, transposition.target(), "{}", name);
+            let down = pair_target(&up, &c);
+            assert_eq!(semitones(&up) + semitones(&down), 0, "{}: {}", name, down.lilypond_name());
+
+            for (song, input) in SONGS {
+                let pitches = pitch_stream(input);
+                assert!(!pitches.is_empty(), "{}", song);
+
+                let written: Vec<Pitch> = pitches.iter().map(|pitch| transposed(pitch, &up)).collect();
+                for (pitch, written) in pitches.iter().zip(&written) {
+                    assert_eq!(
+                        semitones(written) - semitones(pitch),
+                        semitones(&up),
+                        "{} in {}: {} is written {}",
+                        song,
+                        name,
+                        pitch.lilypond_name(),
+                        written.lilypond_name()
+                    );
+                }
+
+                let concert: Vec<Pitch> = written.iter().map(|pitch| transposed(pitch, &down)).collect();
+                assert_eq!(concert, pitches, "{} in {} and back", song, name);
+            }
+        }
+    }
+}
@@ -1,34 +1,382 @@
+use std::env;
+use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn capitalize_first_letter_ascii(s: &str) -> String {
-    let mut c = s.chars();
+use crate::errors::TemplaterError;
+use crate::warnings::{Warning, WarningKind, Warnings};
 
-    match c.next() {
-        None => String::new(),
-        Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
+// the first letter in title case, after any punctuation, e.g.
+// "¿qué pasa?" -> "¿Qué pasa?". digraphs like "ǆ" keep their
+// second letter lowercase, and "ß" becomes "Ss".
+pub fn capitalize_first_letter(s: &str) -> String {
+    let Some((i, first)) = s.char_indices().find(|(_, c)| c.is_alphanumeric()) else {
+        return s.to_string();
+    };
+
+    let title_case = match first {
+        'Ǆ' | 'ǅ' | 'ǆ' => String::from("ǅ"),
+        'Ǉ' | 'ǈ' | 'ǉ' => String::from("ǈ"),
+        'Ǌ' | 'ǋ' | 'ǌ' => String::from("ǋ"),
+        'Ǳ' | 'ǲ' | 'ǳ' => String::from("ǲ"),
+        'ß' => String::from("Ss"),
+        c => c.to_uppercase().collect(),
+    };
+
+    format!("{}{}{}", &s[..i], title_case, &s[i + first.len_utf8()..])
+}
+
+// a lowercase latin letter without its accent, e.g. 'é' -> "e"
+fn fold_letter(c: char) -> Option<&'static str> {
+    let base = match c {
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'ð' | 'ď' | 'đ' => "d",
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'ĥ' | 'ħ' => "h",
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'ĳ' => "ij",
+        'ĵ' => "j",
+        'ķ' | 'ĸ' => "k",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' | 'ŉ' | 'ŋ' => "n",
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'ß' => "ss",
+        'ś' | 'ŝ' | 'ş' | 'š' | 'ſ' => "s",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'þ' => "th",
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    };
+
+    Some(base)
+}
+
+// accented latin letters as their plain ones, e.g. "Água de
+// Beber" -> "Agua de Beber", so they sort (and are listed) with
+// them. anything else, e.g. cyrillic, is kept as it is.
+pub fn fold_accents(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c.to_lowercase().next().and_then(fold_letter) {
+            Some(base) if c.is_uppercase() => folded.push_str(&base.to_uppercase()),
+            Some(base) => folded.push_str(base),
+            None => folded.push(c),
+        }
+    }
+
+    folded
+}
+
+// for use inside of double quoted lilypond strings
+pub fn escape_lilypond_string(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// text for a LaTeX document, e.g. `Rock & Roll` becomes `Rock \& Roll`
+pub fn escape_latex(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            },
+            '\\' => out.push_str("\\textbackslash{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+// a frontmatter value, quoted if it has to be, e.g. `'Zelda: Majora''s Mask'`
+pub fn yaml_string(s: &str) -> String {
+    match s.is_empty() {
+        true => String::new(),
+        false => serde_yaml::to_string(s).map_or_else(|_| s.to_string(), |yaml| yaml.trim_end().to_string()),
+    }
+}
+
+// lowercase ascii alphanumerics separated by '-', usable
+// as a scheme symbol for `\label` and `\page-ref`.
+pub fn slugify(s: &str) -> String {
+    fold_accents(&s.to_lowercase())
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+// like `slugify`, but keeping every alphabet's letters and digits,
+// e.g. "катюша" for "Катюша!"
+pub fn unicode_slugify(s: &str) -> String {
+    s.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+// leading articles and punctuation are ignored when sorting, so
+// "The Girl from Ipanema" sorts under G and "(Meet) The
+// Flintstones" under M
+const TITLE_ARTICLES: [&str; 3] = ["the ", "a ", "an "];
+
+pub fn title_sort_key(title: &str) -> String {
+    let title = fold_accents(&title.to_lowercase());
+    let mut key = title.trim_start_matches(|c: char| !c.is_alphanumeric());
+
+    if let Some(rest) = TITLE_ARTICLES.iter().find_map(|article| key.strip_prefix(article)) {
+        key = rest.trim_start_matches(|c: char| !c.is_alphanumeric());
+    }
+
+    key.to_string()
+}
+
+// `*` matches any run of characters, `?` matches exactly one
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+
+    let (mut p, mut i) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, i));
+            p += 1;
+        } else if let Some((star_p, star_i)) = backtrack {
+            p = star_p + 1;
+            i = star_i + 1;
+            backtrack = Some((star_p, star_i + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// songs and templates written on windows would otherwise mix
+// line endings into the book
+pub fn normalize_line_endings(s: String) -> String {
+    match s.contains('\r') {
+        true => s.replace("\r\n", "\n"),
+        false => s,
+    }
+}
+
+// e.g. `2024-03-01`, in utc. `SOURCE_DATE_EPOCH` is used if it's
+// set, so a reproducible build gets the same filename.
+pub fn today() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+
+    date_from_epoch(secs)
+}
+
+// e.g. 2024, like `today`
+pub fn this_year() -> u32 {
+    today()[..4].parse().unwrap_or_default()
+}
+
+// seconds since 1970-01-01 to a date, from
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn date_from_epoch(secs: u64) -> String {
+    let days = secs / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// "book.ly" -> "book-vol2.ly"
+pub fn add_to_file_stem(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}{}", stem, suffix),
+    };
+
+    path.with_file_name(name)
+}
+
+// like `fs::read_to_string`, but the error names the file
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<String, TemplaterError> {
+    fs::read_to_string(&path).map_err(|e| TemplaterError::file(path.as_ref(), e))
+}
+
+// the folders `path` is in below `root`, as tags, e.g.
+// "songs/latin/bossa_nova/desafinado.ly" -> ["latin", "bossa nova"]
+pub fn folder_tags(root: &Path, path: &Path) -> Vec<String> {
+    let Some(dir) = path.strip_prefix(root).ok().and_then(Path::parent) else {
+        return vec![];
+    };
+
+    dir.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().replace(['_', '-'], " ")),
+            _ => None,
+        })
+        .collect()
+}
+
+pub const IGNORE_FILE: &str = ".templaterignore";
+
+// swap and backup files, e.g. `.misty.ly.swp`, `misty.ly~`, emacs'
+// `.#misty.ly` and `#misty.ly#`, or vim's `4913`. hidden files and
+// folders are skipped too.
+pub fn is_editor_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+
+    name.starts_with('.')
+        || name.ends_with('~')
+        || (name.starts_with('#') && name.ends_with('#'))
+        || name.ends_with(".swp")
+        || name == "4913"
+}
+
+// the patterns of a `.templaterignore`, one per line like a
+// `.gitignore`: `*` and `?` globs, `#` comments, a trailing `/`
+// for only folders, and a `/` anywhere else to match the path from
+// the top rather than any file or folder name
+#[derive(Debug, Default)]
+pub struct IgnoreFile {
+    patterns: Vec<(String, bool)>,
+}
+
+impl IgnoreFile {
+    // a missing file ignores nothing
+    pub fn load(dir: &Path) -> Result<Self, TemplaterError> {
+        let path = dir.join(IGNORE_FILE);
+        match fs::read_to_string(&path) {
+            Ok(input) => Ok(IgnoreFile::parse(&input)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(IgnoreFile::default()),
+            Err(e) => Err(TemplaterError::file(path, e)),
+        }
+    }
+
+    pub fn parse(input: &str) -> Self {
+        let patterns = input
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.strip_suffix('/') {
+                Some(dir) => (dir.to_string(), true),
+                None => (line.to_string(), false),
+            })
+            .collect();
+
+        IgnoreFile { patterns }
+    }
+
+    // `relative` is the path below the ignore file's folder
+    pub fn ignores(&self, relative: &Path, is_dir: bool) -> bool {
+        let path = relative.to_string_lossy().replace('\\', "/");
+        let name = relative.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+
+        self.patterns.iter().any(|(pattern, only_dirs)| {
+            let matches = match pattern.contains('/') {
+                true => glob_match(pattern.trim_start_matches('/'), &path),
+                false => glob_match(pattern, &name),
+            };
+            matches && (is_dir || !only_dirs)
+        })
     }
 }
 
-pub fn get_files_by_ext<P: AsRef<Path>>(path: &P, ext: &str) -> Vec<PathBuf> {
+// every file ending in `.ext` in `path` and its folders, however
+// deep. symlinks are followed, but a folder that was already read
+// (e.g. a link to its parent) and a file that was already found
+// aren't read again. editor files and anything in `path`'s
+// `.templaterignore` are skipped, and so are broken links, with a
+// warning.
+pub fn get_files_by_ext<P: AsRef<Path>>(
+    path: &P,
+    ext: &str,
+    warnings: &mut Warnings,
+) -> Result<Vec<PathBuf>, TemplaterError> {
+    let root = path.as_ref();
+    let ignore = IgnoreFile::load(root)?;
+    let mut seen = HashSet::new();
     let mut paths: Vec<PathBuf> = vec![];
 
-    for dir_entry in std::fs::read_dir(path).unwrap() {
-        let entry = dir_entry.unwrap();
+    find_files(root, root, ext, &ignore, &mut seen, &mut paths, warnings)?;
+    Ok(paths)
+}
+
+fn find_files(
+    root: &Path,
+    dir: &Path,
+    ext: &str,
+    ignore: &IgnoreFile,
+    seen: &mut HashSet<PathBuf>,
+    paths: &mut Vec<PathBuf>,
+    warnings: &mut Warnings,
+) -> Result<(), TemplaterError> {
+    // the real path, so a symlink cycle ends here
+    let real = fs::canonicalize(dir).map_err(|e| TemplaterError::file(dir, e))?;
+    if !seen.insert(real) {
+        return Ok(());
+    }
+
+    for dir_entry in fs::read_dir(dir).map_err(|e| TemplaterError::file(dir, e))? {
+        let entry = dir_entry.map_err(|e| TemplaterError::file(dir, e))?;
         let entry_path = entry.path();
-        let file_type = entry.file_type().unwrap();
-
-        if file_type.is_dir() {
-            let mut nested_paths = get_files_by_ext(&entry_path, ext);
-            paths.append(&mut nested_paths);
-        } else if let Some(cmp_ext) = entry_path.extension().and_then(OsStr::to_str) {
-            if ext == cmp_ext {
-                paths.push(entry_path.to_owned());
+        if is_editor_file(&entry_path) {
+            continue;
+        }
+
+        // follows symlinks
+        let metadata = match fs::metadata(&entry_path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warnings.push(Warning::song(WarningKind::BrokenLink, &entry_path, "broken symlink, skipped"));
+                continue;
+            },
+            Err(e) => return Err(TemplaterError::file(&entry_path, e)),
+        };
+        let relative = entry_path.strip_prefix(root).unwrap_or(&entry_path);
+        if ignore.ignores(relative, metadata.is_dir()) {
+            continue;
+        }
+
+        if metadata.is_dir() {
+            find_files(root, &entry_path, ext, ignore, seen, paths, warnings)?;
+        } else if entry_path.extension().and_then(OsStr::to_str) == Some(ext) {
+            let real = fs::canonicalize(&entry_path).map_err(|e| TemplaterError::file(&entry_path, e))?;
+            if seen.insert(real) {
+                paths.push(entry_path);
             }
         }
     }
 
-    paths
+    Ok(())
 }
 
 #[cfg(test)]
@@ -36,13 +384,120 @@ mod test {
     use super::*;
 
     #[test]
-    fn it_capitalizes_first_letter_ascii() {
-        assert_eq!("Cb", capitalize_first_letter_ascii("cb"));
+    fn it_ignores_what_templaterignore_lists() {
+        let ignore = IgnoreFile::parse("# old versions\n*-old.ly\nscratch/\n/jazz/wip-*\n");
+        assert!(ignore.ignores(Path::new("jazz/misty-old.ly"), false));
+        assert!(ignore.ignores(Path::new("scratch"), true));
+        assert!(!ignore.ignores(Path::new("scratch"), false));
+        assert!(ignore.ignores(Path::new("jazz/wip-nardis.ly"), false));
+        assert!(!ignore.ignores(Path::new("funk/wip-chameleon.ly"), false));
+        assert!(!ignore.ignores(Path::new("jazz/misty.ly"), false));
+
+        assert!(is_editor_file(Path::new("songs/.#misty.ly")));
+        assert!(is_editor_file(Path::new("songs/misty.ly~")));
+        assert!(!is_editor_file(Path::new("songs/misty.ly")));
+    }
+
+    #[test]
+    fn it_follows_symlinks_without_looping() {
+        let dir = std::env::temp_dir().join(format!("templater-files-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("jazz")).unwrap();
+        fs::write(dir.join("jazz/misty.ly"), "").unwrap();
+        fs::write(dir.join(".#misty.ly"), "").unwrap();
+        fs::write(dir.join("old.ly"), "").unwrap();
+        fs::write(dir.join(IGNORE_FILE), "old.ly\n").unwrap();
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&dir, dir.join("jazz/loop")).unwrap();
+            std::os::unix::fs::symlink(dir.join("missing"), dir.join("broken.ly")).unwrap();
+        }
+
+        let mut warnings = Warnings::default();
+        assert_eq!(get_files_by_ext(&dir, "ly", &mut warnings).unwrap(), vec![dir.join("jazz/misty.ly")]);
+        #[cfg(unix)]
+        assert_eq!(
+            warnings.iter().map(|warning| warning.to_string()).collect::<Vec<_>>(),
+            [format!("{}: broken symlink, skipped", dir.join("broken.ly").display())]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_tags_songs_with_their_folders() {
+        let root = Path::new("./songs");
+        assert_eq!(folder_tags(root, Path::new("./songs/latin/bossa_nova/desafinado.ly")), vec!["latin", "bossa nova"]);
+        assert!(folder_tags(root, Path::new("./songs/misty.ly")).is_empty());
+        assert!(folder_tags(root, Path::new("./staging/misty.ly")).is_empty());
+    }
+
+    #[test]
+    fn it_capitalizes_first_letter() {
+        assert_eq!("Cb", capitalize_first_letter("cb"));
+        assert_eq!("Água de beber", capitalize_first_letter("água de beber"));
+        assert_eq!("¿Qué pasa?", capitalize_first_letter("¿qué pasa?"));
+        assert_eq!("ǅungla", capitalize_first_letter("ǆungla"));
+    }
+
+    #[test]
+    fn it_slugifies_titles() {
+        assert_eq!("what-is-this-thing-called-love", slugify("What Is This Thing Called Love?"));
+        assert_eq!("bags-groove", slugify("Bags' Groove"));
+        assert_eq!("desafinado", slugify("Désafinado"));
+        assert_eq!("", slugify("Катюша"));
+        assert_eq!("подмосковные-вечера", unicode_slugify("Подмосковные вечера!"));
+        assert_eq!("água-de-beber", unicode_slugify("Água de Beber"));
+    }
+
+    #[test]
+    fn it_escapes_latex() {
+        assert_eq!("Rock \\& Roll 100\\%", escape_latex("Rock & Roll 100%"));
+        assert_eq!("a\\textasciitilde{}b", escape_latex("a~b"));
+    }
+
+    #[test]
+    fn it_ignores_articles_when_sorting() {
+        assert_eq!("girl from ipanema", title_sort_key("The Girl from Ipanema"));
+        assert_eq!("meet) the flintstones", title_sort_key("(Meet) The Flintstones"));
+        assert_eq!("night in tunisia", title_sort_key("A Night in Tunisia"));
+        assert_eq!("anthropology", title_sort_key("Anthropology"));
+        assert_eq!("2 am", title_sort_key("2 AM"));
+        assert_eq!("agua de beber", title_sort_key("Água de Beber"));
+        assert_eq!("strasse", title_sort_key("Straße"));
+    }
+
+    #[test]
+    fn it_matches_globs() {
+        assert!(glob_match("all the*", "all the things you are"));
+        assert!(glob_match("*blues*", "blues for alice"));
+        assert!(glob_match("f?ur", "four"));
+        assert!(!glob_match("f?ur", "fours"));
+        assert!(!glob_match("*moon", "moon river"));
+    }
+
+    #[test]
+    fn it_normalizes_line_endings() {
+        assert_eq!(normalize_line_endings("a\r\nb\r\n".into()), "a\nb\n");
+        assert_eq!(normalize_line_endings("a\nb\n".into()), "a\nb\n");
+    }
+
+    #[test]
+    fn it_formats_dates() {
+        assert_eq!(date_from_epoch(0), "1970-01-01");
+        assert_eq!(date_from_epoch(951782400), "2000-02-29");
+        assert_eq!(date_from_epoch(1709251199), "2024-02-29");
+    }
+
+    #[test]
+    fn it_names_missing_directories() {
+        let err = get_files_by_ext(&PathBuf::from("./test/missing"), "ly", &mut Warnings::default()).unwrap_err();
+        assert!(err.to_string().starts_with("./test/missing: "), "{}", err);
     }
 
     #[test]
     fn it_recursively_gets_ly_files() {
-        let paths = get_files_by_ext(&PathBuf::from("./test/"), "ly");
+        let paths = get_files_by_ext(&PathBuf::from("./test/"), "ly", &mut Warnings::default()).unwrap();
         let mut filenames: Vec<Option<&str>> = paths.iter().map(|p| p.to_str()).collect();
         filenames.sort_by(|a, b| a.unwrap().cmp(b.unwrap()));
 
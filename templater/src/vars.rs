@@ -0,0 +1,318 @@
+// the variables every template gets. a template that uses any
+// other variable is an error when the templates are loaded, not a
+// blank in the middle of the book. `templater vars` lists them.
+
+use std::collections::HashSet;
+
+use minijinja::Environment;
+
+use crate::errors::TemplaterError;
+use crate::profiles::variant_of;
+
+pub struct TemplateVars {
+    pub templates: &'static [&'static str],
+    pub vars: &'static [(&'static str, &'static str)],
+}
+
+// `bookpart` includes the other song templates, so they all share these
+const SONG_VARS: &[(&str, &str)] = &[
+    ("title", "the song's title"),
+    ("label", "what the ToC and indices link to, e.g. song-misty"),
+    ("song_number", "the song's number in the book"),
+    ("composer", "the composer, or Unknown"),
+    ("poet", "the lyricist, empty if not set"),
+    ("arranger", "empty if not set"),
+    ("dedication", "empty if not set"),
+    ("footer", "empty if not set"),
+    ("instrument", "empty if not set"),
+    ("meter", "e.g. Medium Swing, empty if not set"),
+    ("subtitle", "empty if not set"),
+    ("subsubtitle", "empty if not set"),
+    ("form_summary", "e.g. AABA with D.S. al Coda, empty without --form-summary"),
+    ("tagline", "empty if not set"),
+    ("bpm", "the tempo, the middle of its range, or none"),
+    ("tempo", "the tempo's term (may be empty), metronome mark, e.g. 120–140, and bpm, or none"),
+    ("source", "the book, page, transcriber, recording (artist, album, year), and citation, or none"),
+    ("backing_track", "its url, head (e.g. 1:05, may be empty), link, and qr (a markup, none if it's too long), or none"),
+    ("midi_name", "the midi file's name, e.g. 042-misty"),
+    ("transpose", "the lilypond pitches to transpose by, e.g. c d"),
+    ("transpose_display", "the instrument, e.g. Bb"),
+    ("clef", "the transposition's clef, or the song's clef, empty for treble"),
+    ("pianostaff", "whether the melody is on a piano staff"),
+    ("layout", "the page turn hints: pages, break_before, compress, and ragged"),
+    ("chords", "the \\chordmode music"),
+    ("fretboards", "whether to add a fret diagram above every chord, in a guitar book"),
+    ("large_print", "whether to add the large-print layout overrides, in a --large-print book"),
+    ("variants", "the alternate changes, each with a name and chords"),
+    ("voices", "the melody voices, each with a name (empty for the melody) and notes"),
+    ("lyrics", "the verses, each with a stanza (may be empty) and text"),
+    ("pre_staves", "lilypond code before the staves"),
+    ("pre_section", "lilypond code before the score"),
+    ("post_section", "lilypond code after the score"),
+];
+
+const METADATA_VAR: (&str, &str) = ("metadata", "title, subtitle, edition, date, editor, and copyright");
+
+pub const TEMPLATE_VARS: &[TemplateVars] = &[
+    TemplateVars {
+        templates: &[
+            "bookpart",
+            "song-header",
+            "song-body",
+            "chords",
+            "chord-sheet",
+            "midi",
+            "fretboards",
+            "numbers-sheet",
+            "large-print",
+        ],
+        vars: SONG_VARS,
+    },
+    TemplateVars { templates: &["voice"], vars: &[("voice", "one of voices, with a name and notes")] },
+    TemplateVars { templates: &["variant"], vars: &[("variant", "one of variants, with a name and chords")] },
+    TemplateVars { templates: &["lyrics"], vars: &[("verse", "one of lyrics, with a stanza and text")] },
+    TemplateVars {
+        templates: &["intro"],
+        vars: &[
+            ("transpose_display", "the instrument, e.g. Bb"),
+            ("num_tunes", "how many songs are in the book"),
+            ("volume", "e.g. II, I if the book isn't split"),
+            ("volume_letters", "e.g. M–Z, empty if the book isn't split"),
+            ("chords_only", "whether it's a chords-only book"),
+            ("numbers_chart", "whether it's a numbers chart"),
+            METADATA_VAR,
+        ],
+    },
+    TemplateVars {
+        templates: &["setlist"],
+        vars: &[
+            ("name", "the setlist's name, from its filename"),
+            ("transpose_display", "the instrument, e.g. Bb"),
+            METADATA_VAR,
+            ("songs", "in the order they're played, each with a number, title, composer, and label"),
+        ],
+    },
+    TemplateVars {
+        templates: &["toc"],
+        vars: &[
+            (
+                "songs",
+                "each with a number, title, composer, label, form (empty if not known), measures, collection, and \
+                 heading (the group it starts with --sort, e.g. a composer, if any)",
+            ),
+            ("toc_form", "whether to list the songs' forms, see --toc-form"),
+        ],
+    },
+    TemplateVars {
+        templates: &["sources"],
+        vars: &[("songs", "the songs with a source, each with a number, title, label, and source (like a song's)")],
+    },
+    TemplateVars {
+        templates: &["licenses"],
+        vars: &[
+            ("groups", "each license with a name, e.g. Public Domain, and its songs (number, title, label, and pd_year)"),
+            ("year", "the year the book is built, which decides what's in the public domain"),
+        ],
+    },
+    TemplateVars {
+        templates: &["manuscript", "chord-grids"],
+        vars: &[("pages", "how many pages of blank staves or chord grids, e.g. 4 with --manuscript-pages 4")],
+    },
+    TemplateVars {
+        templates: &["changes"],
+        vars: &[
+            ("since", "the git ref or date, e.g. v1.0"),
+            ("added", "the new songs, each with a number, title, composer, and label"),
+            ("modified", "the changed songs, the same way"),
+        ],
+    },
+    TemplateVars {
+        templates: &["index"],
+        vars: &[
+            ("index_title", "e.g. By Composer"),
+            ("groups", "each with a name and songs (number, title, and label)"),
+        ],
+    },
+    TemplateVars { templates: &["divider"], vars: &[("letter", "e.g. A, # for titles starting with a number")] },
+    TemplateVars { templates: &["midi-header"], vars: &[] },
+    TemplateVars {
+        templates: &["numbers-sheet"],
+        vars: &[
+            ("numbers_key", "the song's key, e.g. Eb major"),
+            ("numbers_pickup", "the pickup's chords, empty if there aren't any"),
+            ("numbers", "the lines of the chart, each a list of bars, e.g. IIm7 V7 or %"),
+            ("bars_per_line", "how many bars a full line has"),
+        ],
+    },
+    TemplateVars {
+        templates: &["intro", "setlist", "sheet-header", "fretboards-header"],
+        vars: &[
+            ("guitar", "whether it's a guitar book"),
+            ("voicings", "the fret diagrams from voicings.toml, each with a chord and frets"),
+        ],
+    },
+    TemplateVars {
+        templates: &["intro", "setlist", "sheet-header", "paper"],
+        vars: &[(
+            "layout_profile",
+            "the --layout, with a name, paper_width, paper_height, staff_size, and margin, none without one",
+        )],
+    },
+    TemplateVars {
+        templates: &["sheet-header"],
+        vars: &[("title", "the song's title"), ("transpose_display", "the instrument, e.g. Bb")],
+    },
+    TemplateVars {
+        templates: &["lyrics-text", "lyrics-markdown", "lyrics-latex"],
+        vars: &[
+            METADATA_VAR,
+            ("num_tunes", "how many songs are in the book"),
+            ("songs", "each with a number, title, composer, poet, label, and verses (stanza and lines)"),
+        ],
+    },
+    TemplateVars {
+        templates: &["html-index"],
+        vars: &[
+            METADATA_VAR,
+            ("num_tunes", "how many songs are in the book"),
+            ("books", "each with a key, a volume if the book is split, and the file to link to"),
+            ("songs", "each with a number, title, composer, poet, meter, bpm, time (every time signature), key, label, and tags"),
+        ],
+    },
+    TemplateVars {
+        templates: &["web-index"],
+        vars: &[
+            METADATA_VAR,
+            ("num_tunes", "how many songs are in the book"),
+            ("songs", "like a song page's song, in book order"),
+            ("tags", "every song's tags, sorted, without duplicates"),
+        ],
+    },
+    TemplateVars {
+        templates: &["web-song"],
+        vars: &[
+            METADATA_VAR,
+            ("song", "its number, title, subtitle, composer, poet, arranger, year, meter, bpm, tempo, time, key, form, \
+                      measures, tags, citation, and page (the link to it from the index)"),
+            ("snippet", "the file name of its lead sheet as an svg, with --snippets"),
+            ("index", "the link back to the index"),
+            ("previous", "the song before it, with a title and a page, none for the first song"),
+            ("next", "the song after it, none for the last song"),
+        ],
+    },
+    TemplateVars {
+        templates: &["song"],
+        vars: &[
+            ("title", "the new song's title"),
+            ("composer", "empty if not given"),
+            ("meter", "empty if not given"),
+            ("bpm", "empty if not given"),
+            ("key", "the melody's \\key, e.g. c \\major"),
+        ],
+    },
+];
+
+// included templates get their includer's variables too, and a
+// layout profile's intro or bookpart the stock one's
+pub fn template_vars(name: &str) -> Vec<&'static str> {
+    let name = variant_of(name).unwrap_or(name);
+    let includers: &[&str] = match name {
+        "voice" | "variant" | "lyrics" => &["song-body"],
+        _ => &[],
+    };
+
+    TEMPLATE_VARS
+        .iter()
+        .filter(|group| group.templates.contains(&name) || includers.iter().any(|t| group.templates.contains(t)))
+        .flat_map(|group| group.vars.iter().map(|(var, _)| *var))
+        .collect()
+}
+
+// every template's variables that the templater doesn't fill in
+pub fn check_templates(env: &Environment) -> Result<(), TemplaterError> {
+    let globals: HashSet<&str> = env.globals().map(|(name, _)| name).collect();
+
+    let mut errors = vec![];
+    for (name, template) in env.templates() {
+        let known = template_vars(name);
+        let mut unknown: Vec<String> = template
+            .undeclared_variables(false)
+            .into_iter()
+            .filter(|var| !known.contains(&var.as_str()) && !globals.contains(var.as_str()))
+            .collect();
+        unknown.sort();
+
+        if !unknown.is_empty() {
+            errors.push(TemplaterError::Template {
+                name: name.to_string(),
+                message: format!(
+                    "uses {}, which the templater doesn't fill in. `templater vars {}` lists the ones it does.",
+                    unknown.join(", "),
+                    name
+                ),
+            });
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(TemplaterError::collect(errors)),
+    }
+}
+
+// `templater vars [template]`
+pub fn describe_vars(template: Option<&str>) -> Result<String, TemplaterError> {
+    let groups: Vec<&TemplateVars> =
+        TEMPLATE_VARS.iter().filter(|group| template.is_none_or(|name| group.templates.contains(&name))).collect();
+    if groups.is_empty() {
+        let names: Vec<&str> = TEMPLATE_VARS.iter().flat_map(|group| group.templates.iter().copied()).collect();
+        return Err(TemplaterError::from_str(&format!(
+            "Unknown template '{}'. Expected one of {}.",
+            template.unwrap_or_default(),
+            names.join(", ")
+        )));
+    }
+
+    let mut out = String::new();
+    for group in groups {
+        out.push_str(&group.templates.join(", "));
+        if group.templates.iter().any(|name| ["voice", "variant", "lyrics"].contains(name)) {
+            out.push_str(" (and every song-body variable)");
+        }
+        out.push('\n');
+
+        let width = group.vars.iter().map(|(var, _)| var.len()).max().unwrap_or(0);
+        for (var, description) in group.vars {
+            out.push_str(&format!("    {:width$}  {}\n", var, description, width = width));
+        }
+        if group.vars.is_empty() {
+            out.push_str("    none\n");
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn env(templates: &[(&'static str, &'static str)]) -> Environment<'static> {
+        let mut env = Environment::new();
+        for (name, source) in templates {
+            env.add_template(name, source).unwrap();
+        }
+        env
+    }
+
+    #[test]
+    fn it_finds_variables_the_templater_doesnt_fill_in() {
+        assert!(check_templates(&env(&[("voice", "{{ voice.notes }} {{ transpose }} {{ range(2) }}")])).is_ok());
+        assert!(check_templates(&env(&[("intro-tablet", "{{ num_tunes }} {{ layout_profile.name }}")])).is_ok());
+
+        let error = check_templates(&env(&[("divider", "{{ letter }} {{ lettre }}{% set x = 1 %}{{ x }}")]));
+        assert!(error.unwrap_err().to_string().contains("uses lettre, which"));
+        assert!(describe_vars(Some("divider")).unwrap().contains("letter"));
+        assert!(describe_vars(Some("cover")).is_err());
+    }
+}
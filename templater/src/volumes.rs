@@ -0,0 +1,169 @@
+// a big book is easier to print (and bind) as a few smaller ones.
+// every volume is a whole book with its own intro, ToC, and
+// indices, covering a range of letters, e.g. A–L and M–Z. songs
+// keep their numbers, so a song is easy to find in either.
+
+use std::ops::Range;
+
+use crate::errors::TemplaterError;
+use crate::models::Song;
+use crate::utils::title_sort_key;
+
+// where one volume ends and the next starts, as indices into the
+// (sorted) songs. a book that isn't split is one volume.
+pub fn split_volumes(songs: &[Song], count: usize, split_at: &[String]) -> Result<Vec<Range<usize>>, TemplaterError> {
+    let cuts = match (count, split_at) {
+        (0 | 1, []) => vec![],
+        (0 | 1, letters) => cuts_at_letters(songs, letters)?,
+        (count, []) => cuts_by_size(songs, count)?,
+        _ => {
+            return Err(TemplaterError::from_str(
+                "Only one of --split-volumes and --split-at can be used.",
+            ))
+        },
+    };
+
+    let mut volumes = vec![];
+    let mut start = 0;
+    for end in cuts.into_iter().chain([songs.len()]) {
+        // e.g. `--split-at M` when no song starts with M-Z
+        if end > start {
+            volumes.push(start..end);
+        }
+        start = end;
+    }
+
+    Ok(volumes)
+}
+
+// songs are sorted by `sort_title`, so a volume starting at M starts
+// with the first song sorted after it
+fn cuts_at_letters(songs: &[Song], letters: &[String]) -> Result<Vec<usize>, TemplaterError> {
+    let mut letters = letters
+        .iter()
+        .map(|letter| match letter.trim() {
+            letter if letter.chars().count() == 1 && letter.chars().all(char::is_alphabetic) => {
+                Ok(title_sort_key(letter))
+            },
+            _ => Err(TemplaterError::from_str(&format!("--split-at expects letters, e.g. M or H,P. Got '{}'.", letter))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    letters.sort();
+    letters.dedup();
+
+    Ok(letters
+        .iter()
+        .map(|letter| songs.iter().position(|song| song.book_sort_title() >= letter.as_str()).unwrap_or(songs.len()))
+        .collect())
+}
+
+// about the same number of songs per volume, without splitting up
+// the songs of one letter
+fn cuts_by_size(songs: &[Song], count: usize) -> Result<Vec<usize>, TemplaterError> {
+    let boundaries: Vec<usize> = (1..songs.len()).filter(|&i| songs[i - 1].letter() != songs[i].letter()).collect();
+    if boundaries.len() + 1 < count {
+        return Err(TemplaterError::from_str(&format!(
+            "Can't split {} songs into {} volumes, they only start with {} different letters.",
+            songs.len(),
+            count,
+            boundaries.len() + 1
+        )));
+    }
+
+    // the boundary closest to every nth of the book that's after the
+    // previous cut, and leaves enough boundaries for the cuts after it
+    let mut cuts = vec![];
+    let mut next = 0;
+    for k in 1..count {
+        let target = songs.len() * k / count;
+        let last = boundaries.len() - (count - 1 - k);
+        let i = (next..last).min_by_key(|&i| boundaries[i].abs_diff(target)).unwrap_or(next);
+        cuts.push(boundaries[i]);
+        next = i + 1;
+    }
+
+    Ok(cuts)
+}
+
+// e.g. `A–L`, from the first and last song's letter
+pub fn volume_letters(songs: &[Song]) -> String {
+    match (songs.first(), songs.last()) {
+        (Some(first), Some(last)) if first.letter() != last.letter() => {
+            format!("{}–{}", first.letter(), last.letter())
+        },
+        (Some(first), _) => first.letter(),
+        _ => String::new(),
+    }
+}
+
+// volumes are numbered like the cover's "Volume I"
+pub fn roman_numeral(mut n: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+
+    let mut s = String::new();
+    for (value, numeral) in NUMERALS {
+        while n >= value {
+            s.push_str(numeral);
+            n -= value;
+        }
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn songs(titles: &[&str]) -> Vec<Song> {
+        let mut songs: Vec<Song> = titles
+            .iter()
+            .map(|title| Song::parse(Path::new("test.ly"), &format!("title: {}", title), false).unwrap())
+            .collect();
+        songs.sort_by(|a, b| a.cmp_title(b));
+        songs
+    }
+
+    #[test]
+    fn it_splits_at_letters() {
+        let songs = songs(&["Airegin", "Blue Monk", "Misty", "Nardis", "The Song Is You"]);
+        let volumes = split_volumes(&songs, 1, &[String::from("m")]).unwrap();
+
+        assert_eq!(volumes, vec![0..2, 2..5]);
+        assert_eq!(volume_letters(&songs[volumes[1].clone()]), "M–S");
+        assert_eq!(split_volumes(&songs, 1, &[String::from("X")]).unwrap(), vec![0..5]);
+        assert!(split_volumes(&songs, 1, &[String::from("Mi")]).is_err());
+    }
+
+    #[test]
+    fn it_splits_into_volumes_of_about_the_same_size() {
+        let songs = songs(&["Airegin", "All of Me", "Blue Monk", "Misty", "Nardis", "Naima", "Oleo"]);
+
+        assert_eq!(split_volumes(&songs, 2, &[]).unwrap(), vec![0..3, 3..7]);
+        assert_eq!(split_volumes(&songs, 5, &[]).unwrap(), vec![0..2, 2..3, 3..4, 4..6, 6..7]);
+        assert!(split_volumes(&songs, 6, &[]).is_err());
+        assert!(split_volumes(&songs, 2, &[String::from("M")]).is_err());
+    }
+
+    #[test]
+    fn it_writes_roman_numerals() {
+        assert_eq!(roman_numeral(1), "I");
+        assert_eq!(roman_numeral(4), "IV");
+        assert_eq!(roman_numeral(14), "XIV");
+    }
+}
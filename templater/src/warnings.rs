@@ -0,0 +1,171 @@
+// warnings found while building a book, e.g. an unknown frontmatter
+// key. they're collected and printed together at the end, with a
+// count of each kind. with `--strict`, any warning fails the build.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::errors::TemplaterError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningKind {
+    UnusedArgument,
+    UnknownKey,
+    MissingBpm,
+    // `--lint-ly` warnings, see `lint.rs`
+    Lint,
+    // chords that won't be in the melody's key, see `harmony.rs`
+    Harmony,
+    // syllables without a note, see `melisma.rs`
+    Lyrics,
+    // a melody that doesn't fit its form, see `form.rs`
+    Form,
+    // bar checks that fail because of the pickup, see `pickup.rs`
+    Pickup,
+    // a start repeat bar line that isn't ended, see `repeats.rs`
+    Repeat,
+    // notes the book's instrument can't play, see `range.rs`
+    Range,
+    // a `backing_track` link too long for a qr code, see `backing.rs`
+    BackingTrack,
+    SimilarTitle,
+    // a song `templater diff` can't read, e.g. at an old revision
+    Unparsable,
+    // a symlink to nothing, skipped when the songs are found
+    BrokenLink,
+}
+
+impl WarningKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningKind::UnusedArgument => "unused argument",
+            WarningKind::UnknownKey => "unknown frontmatter key",
+            WarningKind::MissingBpm => "missing bpm",
+            WarningKind::Lint => "lint",
+            WarningKind::Harmony => "harmony",
+            WarningKind::Lyrics => "lyrics",
+            WarningKind::Form => "form",
+            WarningKind::Pickup => "pickup",
+            WarningKind::Repeat => "repeat",
+            WarningKind::Range => "range",
+            WarningKind::BackingTrack => "backing track",
+            WarningKind::SimilarTitle => "similar title",
+            WarningKind::Unparsable => "unparsable song",
+            WarningKind::BrokenLink => "broken link",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    // the song it's about, if any
+    pub path: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new<S: Into<String>>(kind: WarningKind, message: S) -> Self {
+        Warning { kind, path: None, line: None, message: message.into() }
+    }
+
+    pub fn song<S: Into<String>>(kind: WarningKind, path: &Path, message: S) -> Self {
+        Warning { path: Some(path.to_path_buf()), ..Warning::new(kind, message) }
+    }
+}
+
+// e.g. `songs/misty.ly:12: unknown command \foo`
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.path, self.line) {
+            (Some(path), Some(line)) => write!(f, "{}:{}: {}", path.display(), line, self.message),
+            (Some(path), None) => write!(f, "{}: {}", path.display(), self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Warnings(Vec<Warning>);
+
+impl Warnings {
+    pub fn push(&mut self, warning: Warning) {
+        self.0.push(warning);
+    }
+
+    pub fn extend<I: IntoIterator<Item = Warning>>(&mut self, warnings: I) {
+        self.0.extend(warnings);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.0.iter()
+    }
+
+    // every warning as an error, if `strict`
+    pub fn check(self, strict: bool) -> Result<Self, TemplaterError> {
+        match strict && !self.is_empty() {
+            true => Err(TemplaterError::collect(self.0.into_iter().map(TemplaterError::Strict).collect())),
+            false => Ok(self),
+        }
+    }
+
+    // e.g. `3 warnings: 2 missing bpm, 1 lint`
+    pub fn summary(&self) -> String {
+        let mut counts: BTreeMap<WarningKind, usize> = BTreeMap::new();
+        for warning in &self.0 {
+            *counts.entry(warning.kind).or_default() += 1;
+        }
+
+        let counts: Vec<String> = counts.iter().map(|(kind, count)| format!("{} {}", count, kind.name())).collect();
+        match self.0.len() {
+            1 => format!("1 warning: {}", counts.join(", ")),
+            n => format!("{} warnings: {}", n, counts.join(", ")),
+        }
+    }
+
+    pub fn print(&self) {
+        if self.is_empty() {
+            return;
+        }
+
+        for warning in &self.0 {
+            log!("[warning]: {}", warning);
+        }
+        log!("[info]: {}. --strict turns them into errors.", self.summary());
+    }
+}
+
+impl IntoIterator for Warnings {
+    type Item = Warning;
+    type IntoIter = std::vec::IntoIter<Warning>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_counts_every_kind_of_warning() {
+        let mut warnings = Warnings::default();
+        warnings.push(Warning::song(WarningKind::MissingBpm, Path::new("songs/misty.ly"), "missing bpm"));
+        warnings.push(Warning::song(WarningKind::MissingBpm, Path::new("songs/oleo.ly"), "missing bpm"));
+        warnings.push(Warning { line: Some(3), ..Warning::song(WarningKind::Lint, Path::new("songs/oleo.ly"), "uh") });
+
+        assert_eq!(warnings.summary(), "3 warnings: 2 missing bpm, 1 lint");
+        assert_eq!(warnings.iter().nth(2).unwrap().to_string(), "songs/oleo.ly:3: uh");
+        assert!(warnings.clone().check(false).is_ok());
+
+        let error = warnings.check(true).unwrap_err();
+        assert_eq!(error.report().lines().next(), Some("[Error]: songs/misty.ly: missing bpm (--strict)"));
+    }
+}
@@ -0,0 +1,70 @@
+// `--watch`: rebuilds whenever a song or template changes
+
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::errors::TemplaterError;
+use crate::utils::is_editor_file;
+
+// editors tend to write a file in several steps (e.g. a swap
+// file, then a rename), so events are collected for a bit
+// before rebuilding.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+fn is_relevant(event: &Event) -> bool {
+    let is_change = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    );
+
+    is_change && event.paths.iter().any(|path| !is_editor_file(path))
+}
+
+// blocks forever, calling `rebuild` with the changed paths
+pub fn watch<F>(dirs: &[PathBuf], mut rebuild: F) -> Result<(), TemplaterError>
+where
+    F: FnMut(&[PathBuf]) -> Result<(), TemplaterError>,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+        log!("[info]: watching {}", dir.display());
+    }
+
+    loop {
+        let mut changed: Vec<PathBuf> = vec![];
+
+        let event = rx.recv().map_err(|e| TemplaterError::from_str(&e.to_string()))??;
+        if is_relevant(&event) {
+            changed.extend(event.paths);
+        }
+
+        while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+            let event = event?;
+            if is_relevant(&event) {
+                changed.extend(event.paths);
+            }
+        }
+
+        if changed.is_empty() {
+            continue;
+        }
+
+        changed.sort();
+        changed.dedup();
+
+        for path in &changed {
+            log!("[info]: changed: {}", path.display());
+        }
+
+        // a broken song shouldn't stop the watcher
+        if let Err(e) = rebuild(&changed) {
+            eprintln!("{}", e.report());
+        }
+    }
+}
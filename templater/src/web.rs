@@ -0,0 +1,123 @@
+// `templater web`: a static site for the book. `index.html` lists
+// every song and filters them as a title, composer, or tag is typed,
+// and every song gets a page of its own under `songs/`, optionally
+// with its lead sheet rendered by lilypond as an svg.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use minijinja::{context, Value};
+
+use crate::book::Book;
+use crate::errors::TemplaterError;
+use crate::export::SongMetadata;
+use crate::form::song_form;
+use crate::lilypond::run_lilypond;
+use crate::models::Song;
+use crate::provenance::Provenance;
+use crate::songids::song_key;
+
+// in `--out-dir`, if no dir is given
+pub const DEFAULT_WEB_DIR: &str = "web";
+const SONGS_DIR: &str = "songs";
+
+// relative to the index
+fn song_page(song: &Song) -> String {
+    format!("{}/{}.html", SONGS_DIR, song_key(song))
+}
+
+// relative to the song's page
+fn neighbor(song: Option<&Song>) -> Option<Value> {
+    let song = song?;
+    Some(context! { title => song.title.as_str(), page => format!("{}.html", song_key(song)) })
+}
+
+fn song_context(song: &Song) -> Value {
+    let song_metadata = SongMetadata::new(song);
+    context! {
+        number => song_metadata.number,
+        title => song_metadata.title,
+        subtitle => song.subtitle.as_deref(),
+        composer => song_metadata.composer,
+        poet => song_metadata.poet,
+        arranger => song.arranger.as_deref(),
+        year => song.year,
+        meter => song_metadata.meter,
+        bpm => song_metadata.bpm,
+        tempo => song.tempo.as_ref().map(|tempo| tempo.to_string()),
+        time => song_metadata.time,
+        key => song_metadata.key,
+        form => song_form(song).map(|form| form.to_string()),
+        measures => song.measures,
+        tags => song.tags.clone(),
+        citation => song.provenance.as_ref().map(Provenance::citation),
+        page => song_page(song),
+    }
+}
+
+// the lead sheet in the book's first transposition, next to the
+// song's page. returns the svg's file name.
+fn render_snippet(book: &Book, dir: &Path, index: usize) -> Result<String, TemplaterError> {
+    let song = &book.songs[index];
+    let conf = book.confs.first().ok_or_else(|| TemplaterError::from_str("The book has no transpositions."))?;
+    let path = dir.join(format!("{}.ly", song_key(song)));
+
+    let mut sheet = vec![];
+    let source_map = book.render_sheet(&mut sheet, conf, index)?;
+    fs::write(&path, sheet).map_err(|e| TemplaterError::file(&path, e))?;
+
+    let mut flags = book.config.lilypond_flags.clone();
+    flags.extend(["--svg".to_string(), "-dno-point-and-click".to_string()]);
+    run_lilypond(&book.config.lilypond, &flags, &path, &source_map)?;
+
+    // a sheet longer than a page is one svg per page, the first is shown
+    let stem = song_key(song);
+    [format!("{}.svg", stem), format!("{}-1.svg", stem)]
+        .into_iter()
+        .find(|svg| dir.join(svg).is_file())
+        .ok_or_else(|| TemplaterError::from_str(&format!("lilypond didn't write an svg for {}", path.display())))
+}
+
+// writes the site to `dir` and returns every page written,
+// the index first
+pub fn write_site(book: &Book, dir: &Path, snippets: bool) -> Result<Vec<PathBuf>, TemplaterError> {
+    let songs_dir = dir.join(SONGS_DIR);
+    fs::create_dir_all(&songs_dir).map_err(|e| TemplaterError::file(&songs_dir, e))?;
+    let metadata = book.config.metadata.context();
+
+    let mut tags: Vec<String> = book.songs.iter().flat_map(|song| song.tags.clone()).collect();
+    tags.sort_unstable();
+    tags.dedup();
+
+    let index_path = dir.join("index.html");
+    let songs: Vec<Value> = book.songs.iter().map(song_context).collect();
+    let index = book.templates.render("web-index", context! {
+        metadata => metadata.clone(),
+        num_tunes => songs.len(),
+        songs,
+        tags,
+    })?;
+    fs::write(&index_path, index).map_err(|e| TemplaterError::file(&index_path, e))?;
+
+    let mut pages = vec![index_path];
+    for (i, song) in book.songs.iter().enumerate() {
+        let snippet = match snippets {
+            true => Some(render_snippet(book, &songs_dir, i)?),
+            false => None,
+        };
+
+        let path = dir.join(song_page(song));
+        let page = book.templates.render("web-song", context! {
+            metadata => metadata.clone(),
+            song => song_context(song),
+            snippet,
+            index => "../index.html",
+            previous => neighbor(i.checked_sub(1).and_then(|i| book.songs.get(i))),
+            next => neighbor(book.songs.get(i + 1)),
+        })?;
+        fs::write(&path, page).map_err(|e| TemplaterError::file(&path, e))?;
+        pages.push(path);
+    }
+
+    Ok(pages)
+}
@@ -0,0 +1,111 @@
+// `.cache/written.toml`: a hash of every file the templater wrote,
+// so a book that was edited by hand afterwards isn't overwritten
+// without `--force`. it's FNV-1a, which unlike `DefaultHasher`
+// doesn't change between rust versions. a file that isn't listed,
+// e.g. after `rm -rf .cache`, is overwritten like before.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::TemplaterError;
+
+pub const WRITTEN_FILE: &str = "written.toml";
+
+const HEADER: &str = "# generated by the templater, see written.rs
+";
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WrittenFiles {
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+}
+
+pub fn content_hash(contents: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in contents {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    format!("{:016x}", hash)
+}
+
+fn key(path: &Path) -> String {
+    path.display().to_string()
+}
+
+impl WrittenFiles {
+    pub fn path<P: AsRef<Path>>(cache_dir: P) -> PathBuf {
+        cache_dir.as_ref().join(WRITTEN_FILE)
+    }
+
+    pub fn load<P: AsRef<Path>>(cache_dir: P) -> Result<Self, TemplaterError> {
+        let path = WrittenFiles::path(cache_dir);
+
+        match fs::read_to_string(&path) {
+            Ok(input) => toml::from_str(&input)
+                .map_err(|e| TemplaterError::from_str(&format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(WrittenFiles::default()),
+            Err(e) => Err(TemplaterError::file(path, e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, cache_dir: P) -> Result<(), TemplaterError> {
+        let cache_dir = cache_dir.as_ref();
+        fs::create_dir_all(cache_dir).map_err(|e| TemplaterError::file(cache_dir, e))?;
+
+        let path = WrittenFiles::path(cache_dir);
+        let output = toml::to_string(self).map_err(|e| TemplaterError::from_str(&e.to_string()))?;
+        fs::write(&path, format!("{}{}", HEADER, output)).map_err(|e| TemplaterError::file(path, e))
+    }
+
+    // whether `path` has changed since the templater wrote it. a
+    // missing file hasn't
+    pub fn edited(&self, path: &Path) -> Result<bool, TemplaterError> {
+        let Some(hash) = self.files.get(&key(path)) else {
+            return Ok(false);
+        };
+
+        match fs::read(path) {
+            Ok(contents) => Ok(content_hash(&contents) != *hash),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(TemplaterError::file(path, e)),
+        }
+    }
+
+    pub fn record(&mut self, path: &Path, contents: &[u8]) {
+        self.files.insert(key(path), content_hash(contents));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_notices_a_file_edited_after_it_was_written() {
+        let dir = std::env::temp_dir().join(format!("templater-written-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("openbook-Concert.ly");
+
+        let mut written = WrittenFiles::default();
+        assert!(!written.edited(&path).unwrap());
+
+        fs::write(&path, "\\version \"2.24.0\"\n").unwrap();
+        assert!(!written.edited(&path).unwrap());
+        written.record(&path, b"\\version \"2.24.0\"\n");
+        assert!(!written.edited(&path).unwrap());
+
+        fs::write(&path, "\\version \"2.24.0\"\n% fixed a typo\n").unwrap();
+        assert!(written.edited(&path).unwrap());
+
+        written.save(&dir).unwrap();
+        assert_eq!(WrittenFiles::load(&dir).unwrap(), written);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
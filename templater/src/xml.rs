@@ -0,0 +1,281 @@
+// just enough of an xml parser for `musicxml.rs`: elements,
+// attributes, text, and the standard entities. comments, the
+// prolog, and the doctype are skipped, namespaces are kept as
+// part of the name.
+
+use crate::errors::TemplaterError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.elements().filter(move |element| element.name == name)
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.elements().find(|element| element.name == name)
+    }
+
+    // e.g. `note/pitch/step`
+    pub fn find(&self, path: &str) -> Option<&Element> {
+        path.split('/').try_fold(self, |element, name| element.child(name))
+    }
+
+    pub fn has(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    // the text of the element at `path`, trimmed
+    pub fn text_at(&self, path: &str) -> Option<String> {
+        self.find(path).map(Element::text).filter(|text| !text.is_empty())
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for node in &self.children {
+            match node {
+                Node::Text(s) => text.push_str(s),
+                Node::Element(element) => text.push_str(&element.text()),
+            }
+        }
+
+        text.trim().to_string()
+    }
+}
+
+// the document's root element
+pub fn parse(input: &str) -> Result<Element, TemplaterError> {
+    let mut parser = Parser { input, pos: 0 };
+    parser.skip_misc()?;
+    let root = parser.element()?;
+    parser.skip_misc()?;
+
+    match parser.rest().is_empty() {
+        true => Ok(root),
+        false => Err(parser.error("content after the root element")),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> TemplaterError {
+        let line = self.input[..self.pos].matches('\n').count() + 1;
+        TemplaterError::from_str(&format!("invalid xml on line {}: {}", line, message))
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        match self.rest().starts_with(s) {
+            true => {
+                self.pos += s.len();
+                true
+            },
+            false => false,
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), TemplaterError> {
+        match self.eat(s) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected '{}'", s))),
+        }
+    }
+
+    // everything up to and including `end`
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, TemplaterError> {
+        let rest = self.rest();
+        let i = rest.find(end).ok_or_else(|| self.error(&format!("missing '{}'", end)))?;
+        self.pos += i + end.len();
+        Ok(&rest[..i])
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // whitespace, comments, `<?xml ...?>`, and `<!DOCTYPE ...>`
+    fn skip_misc(&mut self) -> Result<(), TemplaterError> {
+        loop {
+            self.skip_whitespace();
+            if self.eat("<!--") {
+                self.skip_past("-->")?;
+            } else if self.eat("<?") {
+                self.skip_past("?>")?;
+            } else if self.eat("<!DOCTYPE") {
+                // the internal subset can have its own `>`s
+                let rest = self.rest();
+                let end = match (rest.find('['), rest.find('>')) {
+                    (Some(open), Some(close)) if open < close => "]>",
+                    _ => ">",
+                };
+                self.skip_past(end)?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, TemplaterError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=' | '<'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+
+        self.pos += len;
+        Ok(rest[..len].to_string())
+    }
+
+    fn element(&mut self) -> Result<Element, TemplaterError> {
+        self.expect("<")?;
+        let mut element = Element { name: self.name()?, ..Default::default() };
+
+        loop {
+            self.skip_whitespace();
+            if self.eat("/>") {
+                return Ok(element);
+            }
+            if self.eat(">") {
+                break;
+            }
+
+            let key = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(self.error("expected a quoted attribute value")),
+            };
+            self.pos += 1;
+            let value = self.skip_past(&quote.to_string())?;
+            element.attributes.push((key, unescape(value)));
+        }
+
+        loop {
+            if self.eat("</") {
+                let name = self.name()?;
+                if name != element.name {
+                    return Err(self.error(&format!("expected </{}>, got </{}>", element.name, name)));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                return Ok(element);
+            }
+
+            if self.eat("<!--") {
+                self.skip_past("-->")?;
+            } else if self.eat("<![CDATA[") {
+                let text = self.skip_past("]]>")?;
+                element.children.push(Node::Text(text.to_string()));
+            } else if self.eat("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with('<') {
+                element.children.push(Node::Element(self.element()?));
+            } else if self.rest().is_empty() {
+                return Err(self.error(&format!("<{}> is never closed", element.name)));
+            } else {
+                let rest = self.rest();
+                let len = rest.find('<').unwrap_or(rest.len());
+                self.pos += len;
+                element.children.push(Node::Text(unescape(&rest[..len])));
+            }
+        }
+    }
+}
+
+// `&amp;`, `&#233;`, ... unknown entities are kept as they are
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let entity = rest.find(';').map(|end| &rest[1..end]);
+        let c = entity.and_then(|entity| match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let number = entity.strip_prefix('#')?;
+                let code = match number.strip_prefix('x') {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            },
+        });
+
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                out.push(c);
+                rest = &rest[entity.len() + 2..];
+            },
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_xml() {
+        let root = parse(
+            "<?xml version=\"1.0\"?>\n<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD\" \"x.dtd\">\n\
+             <score><!-- c --><work><work-title>Tom &amp; Jerry&#39;s</work-title></work>\
+             <note default-x='12'><chord/><pitch><step>C</step></pitch></note></score>",
+        )
+        .unwrap();
+
+        assert_eq!(root.name, "score");
+        assert_eq!(root.text_at("work/work-title").as_deref(), Some("Tom & Jerry's"));
+        assert_eq!(root.child("note").and_then(|note| note.attr("default-x")), Some("12"));
+        assert!(root.has("note/chord"));
+        assert!(parse("<a><b></a>").is_err());
+        assert!(parse("<a>").is_err());
+    }
+}
@@ -0,0 +1,751 @@
+// renders books from the songs in `tests/fixtures/songs` and compares
+// them to the ones in `tests/golden`, so a refactor can't silently
+// change the output. after an intended change, accept the new output
+// with `UPDATE_GOLDEN=1 cargo test --test golden`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use openbook_templater::anthology::DuplicateTitles;
+use openbook_templater::bookdiff::{book_songs, diff_books, diff_songs, Side};
+use openbook_templater::changelog::Changes;
+use openbook_templater::drafts::find_drafts;
+use openbook_templater::html::HtmlIndex;
+use openbook_templater::metadata::BookMetadata;
+use openbook_templater::models::{BookMode, ChordVariant, LayoutHints};
+use openbook_templater::order::SortOrder;
+use openbook_templater::profiles::LayoutProfile;
+use openbook_templater::renderer::Renderer;
+use openbook_templater::transpose::Presets;
+use openbook_templater::warnings::{WarningKind, Warnings};
+use openbook_templater::web::write_site;
+use openbook_templater::{Book, BookBuilder, Config};
+
+fn config() -> Config {
+    Config {
+        songs_dir: "tests/fixtures/songs".into(),
+        // none of these exist, so the book always uses the stock
+        // templates and numbers the songs in title order
+        templates_dir: "tests/fixtures/templates".into(),
+        song_ids: "tests/fixtures/songids.toml".into(),
+        composer_aliases: "tests/fixtures/composers.toml".into(),
+        cache: false,
+        ..Default::default()
+    }
+}
+
+fn render(transpose: &str, lyrics: bool) -> String {
+    let book = BookBuilder::from_config(config())
+        .transpose(transpose)
+        .lyrics(lyrics)
+        .dry_run(true)
+        .build()
+        .unwrap();
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+fn assert_golden(name: &str, rendered: &str) {
+    let path = Path::new("tests/golden").join(name);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, rendered).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(&path).unwrap_or_default();
+    if golden != rendered {
+        let diff = similar::TextDiff::from_lines(&golden, rendered)
+            .unified_diff()
+            .header(&path.display().to_string(), "rendered")
+            .to_string();
+        panic!("{} changed, rerun with UPDATE_GOLDEN=1 if that's intended:\n{}", path.display(), diff);
+    }
+}
+
+#[test]
+fn it_renders_the_concert_book() {
+    assert_golden("openbook-Concert.ly", &render("c", false));
+}
+
+#[test]
+fn it_renders_the_bb_book_with_lyrics() {
+    assert_golden("openbook-Bb.ly", &render("bb", true));
+}
+
+#[test]
+fn it_renders_the_same_book_every_time() {
+    assert_eq!(render("eb", true), render("eb", true));
+}
+
+#[test]
+fn it_renders_every_volume_with_its_own_toc() {
+    let book = BookBuilder::from_config(config()).split_at(vec![String::from("n")]).dry_run(true).build().unwrap();
+    assert_eq!(book.volumes, vec![0..1, 1..3]);
+
+    let mut output = vec![];
+    book.render_volume(&mut output, &book.confs[0], 2).unwrap();
+    let volume = String::from_utf8(output).unwrap();
+
+    assert!(volume.contains("\"Volume II\""));
+    assert!(volume.contains("This build contains 2 tunes."));
+    assert!(volume.contains("\\tocItem \\markup \"2. Nardis - Miles Davis\""));
+    assert!(!volume.contains("Misty"));
+}
+
+#[test]
+fn it_renders_one_midi_book_per_song() {
+    let book = BookBuilder::from_config(config()).transpose("bb").dry_run(true).build().unwrap();
+
+    let mut output = vec![];
+    book.render_midi(&mut output).unwrap();
+    let midi = String::from_utf8(output).unwrap();
+
+    assert_eq!(midi.matches("\\midi {").count(), 3);
+    assert!(midi.contains("\\bookOutputSuffix \"002-nardis\""));
+    // always concert pitch, and 120 bpm without a `bpm:`
+    assert!(midi.contains("\\transpose c c {"));
+    assert!(midi.contains("\\tempo 4 = 120"));
+    assert!(!midi.contains("\\layout"));
+}
+
+#[test]
+fn it_renders_a_chords_only_book() {
+    let book = BookBuilder::from_config(config()).mode(BookMode::ChordsOnly).dry_run(true).build().unwrap();
+    assert_eq!(book.output_path(&book.confs[0], None), Path::new("./openbook-Concert-chords.ly"));
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let chords = String::from_utf8(output).unwrap();
+
+    assert!(chords.contains("\"Chord Changes\""));
+    assert_eq!(chords.matches("\\new ChordGrid").count(), 3);
+    assert!(chords.contains("\\label #'song-misty"));
+    assert!(!chords.contains("\\numericTimeSignature"));
+}
+
+#[test]
+fn it_renders_a_numbers_chart() {
+    let book = BookBuilder::from_config(config()).mode(BookMode::Numbers).dry_run(true).build().unwrap();
+    assert_eq!(book.output_path(&book.confs[0], None), Path::new("./openbook-Concert-numbers.ly"));
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let numbers = String::from_utf8(output).unwrap();
+
+    assert!(numbers.contains("\"Numbers Chart\""));
+    assert!(numbers.contains("\\markup { \\italic \"in Eb major\" }"));
+    assert!(numbers.contains("\"| Imaj7\" \"| Vm7 I7\" \"| IVmaj7\" \"| IVm7 bVII7\" \"|\""));
+    assert_eq!(numbers.matches("\\markup { \\italic \"in ").count(), 3);
+    assert!(!numbers.contains("\\new ChordGrid") && !numbers.contains("\\new ChordNames"));
+}
+
+#[test]
+fn it_renders_a_guitar_book() {
+    let book = BookBuilder::from_config(config())
+        .mode(BookMode::Guitar)
+        .voicings("tests/fixtures/voicings.toml")
+        .dry_run(true)
+        .build()
+        .unwrap();
+    assert_eq!(book.output_path(&book.confs[0], None), Path::new("./openbook-Concert-guitar.ly"));
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let guitar = String::from_utf8(output).unwrap();
+
+    assert!(guitar.contains("\\include \"predefined-guitar-fretboards.ly\""));
+    let voicing = "\\storePredefinedDiagram #default-fret-table \\chordmode { ees:maj7 } #guitar-tuning \"x;6;5;7;8;x;\"";
+    assert!(guitar.contains(voicing));
+    assert_eq!(guitar.matches("\\new FretBoards").count(), 3);
+    assert_eq!(guitar.matches("\\new ChordNames=\"Chords\"").count(), 3);
+}
+
+#[test]
+fn it_renders_a_large_print_book() {
+    let book = BookBuilder::from_config(config()).layout(LayoutProfile::LargePrint).dry_run(true).build().unwrap();
+    assert_eq!(book.output_path(&book.confs[0], None), Path::new("./openbook-Concert-large-print.ly"));
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let large_print = String::from_utf8(output).unwrap();
+
+    assert!(large_print.contains("#(set-global-staff-size 26)"));
+    assert!(large_print.contains("paper-width = 8.5\\in"));
+    assert_eq!(large_print.matches("\\override ChordName.font-size = #3").count(), 3);
+    assert_eq!(large_print.matches("system-system-spacing.padding = #5").count(), 3);
+}
+
+#[test]
+fn it_renders_a_lyrics_book() {
+    let book = BookBuilder::from_config(config()).mode(BookMode::Lyrics).dry_run(true).build().unwrap();
+    assert_eq!(book.output_path(&book.confs[0], None), Path::new("./openbook-Concert-lyrics.md"));
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let lyrics = String::from_utf8(output).unwrap();
+
+    assert!(lyrics.starts_with("# The Openbook: Lyrics"));
+    assert!(lyrics.contains("Look at me, I'm as helpless as a kitten up a tree."));
+    assert!(!lyrics.contains("\\lyricmode"));
+    assert!(!lyrics.contains(" -- "));
+}
+
+#[test]
+fn it_renders_a_braille_book() {
+    let book =
+        BookBuilder::from_config(config()).mode(BookMode::Braille).transpose("Bb").dry_run(true).build().unwrap();
+    assert_eq!(book.output_path(&book.confs[0], None), Path::new("./openbook-Bb.brf"));
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let braille = String::from_utf8(output).unwrap();
+
+    assert_eq!(braille.matches('\x0c').count(), 2);
+    assert!(braille.starts_with(","));
+    assert!(braille.lines().all(|line| line.len() <= 40 && !line.chars().any(|c| c.is_ascii_lowercase())));
+    assert!(braille.contains(",MELODY3\n"));
+    assert!(braille.trim_end().ends_with("<K"));
+}
+
+#[test]
+fn it_renders_an_html_index() {
+    let config = Config { html_index: true, ..config() };
+    let book = BookBuilder::from_config(config).transpose("c,bb").dry_run(true).build().unwrap();
+    assert_eq!(book.renderers().len(), 2);
+
+    let mut output = vec![];
+    HtmlIndex.render(&book, &mut output, &book.confs[0], &book.songs, None).unwrap();
+    let index = String::from_utf8(output).unwrap();
+
+    assert!(index.contains("<a href=\"openbook-Concert.pdf\">Concert</a>"));
+    assert!(index.contains("<a href=\"openbook-Bb.pdf\">Bb</a>"));
+    assert!(index.contains("<tr id=\"song-misty\">"));
+    assert_eq!(index.matches("<tr id=").count(), 3);
+}
+
+#[test]
+fn it_merges_every_songs_dir_into_one_book() {
+    let merged = Config { more_songs_dirs: vec!["tests/fixtures/realbook/songs".into()], ..config() };
+    let err = BookBuilder::from_config(merged).dry_run(true).build().err().unwrap().to_string();
+    assert!(err.contains("duplicate title 'Misty'"), "{}", err);
+
+    let config = Config {
+        more_songs_dirs: vec!["tests/fixtures/realbook/songs".into()],
+        duplicate_titles: DuplicateTitles::Last,
+        ..config()
+    };
+    let book = BookBuilder::from_config(config).dry_run(true).build().unwrap();
+    let titles: Vec<_> = book.songs.iter().map(|song| (song.title.as_str(), song.collection.as_deref().unwrap())).collect();
+    assert_eq!(
+        titles,
+        [
+            ("Misty", "realbook"),
+            ("Nardis", "fixtures"),
+            ("Now's The Time", "fixtures"),
+            ("Oleo", "realbook"),
+            ("Tiger Rag", "realbook"),
+        ]
+    );
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+    assert!(rendered.contains("\"4. Oleo - Sonny Rollins [realbook]\""));
+    assert!(rendered.contains("\"1. Misty - Erroll Garner [realbook]\""));
+}
+
+#[test]
+fn it_lists_the_changes_in_every_songs_dir() {
+    let dir = env::temp_dir().join(format!("templater-changes-{}", std::process::id()));
+    let git = |args: &[&str]| {
+        let mut git = Command::new("git");
+        git.arg("-C").arg(&dir).args(["-c", "user.name=t", "-c", "user.email=t@t"]).args(args);
+        assert!(git.output().unwrap().status.success(), "git {:?}", args);
+    };
+    let copy = |from: &str, to: &str| {
+        fs::create_dir_all(dir.join(to)).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join(to).join(path.file_name().unwrap())).unwrap();
+        }
+    };
+    copy("tests/fixtures/songs", "fixtures/songs");
+    copy("tests/fixtures/realbook/songs", "realbook/songs");
+    fs::remove_file(dir.join("realbook/songs/tiger_rag.ly")).unwrap();
+    fs::write(dir.join("realbook/songs/broken.ly"), "---\ntitle: [\n---\n").unwrap();
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "v1"]);
+    git(&["tag", "v1"]);
+
+    // a new song in the second dir, and a changed one in each
+    fs::copy("tests/fixtures/realbook/songs/tiger_rag.ly", dir.join("realbook/songs/tiger_rag.ly")).unwrap();
+    fs::remove_file(dir.join("realbook/songs/broken.ly")).unwrap();
+    for song in ["fixtures/songs/nardis.ly", "realbook/songs/oleo.ly"] {
+        let input = fs::read_to_string(dir.join(song)).unwrap();
+        fs::write(dir.join(song), format!("{}% edited\n", input)).unwrap();
+    }
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "v2"]);
+
+    let config = Config {
+        songs_dir: dir.join("fixtures/songs"),
+        more_songs_dirs: vec![dir.join("realbook/songs")],
+        duplicate_titles: DuplicateTitles::Last,
+        changes_since: Some(String::from("v1")),
+        ..config()
+    };
+    let book = BookBuilder::from_config(config).dry_run(true).build().unwrap();
+    let (added, modified) = book.changes.as_ref().unwrap().songs(&book.songs);
+    assert_eq!(added.iter().map(|song| song.title.as_str()).collect::<Vec<_>>(), ["Tiger Rag"]);
+    assert_eq!(modified.iter().map(|song| song.title.as_str()).collect::<Vec<_>>(), ["Nardis", "Oleo"]);
+
+    // `templater diff v1` too, with the song that didn't parse at v1
+    // left out
+    let songs_dirs = book.config.songs_dirs();
+    let new = Side::Dirs(songs_dirs.iter().map(PathBuf::from).collect());
+    let mut warnings = Warnings::default();
+    let old = Side::Revision(String::from("v1"));
+    let diff = diff_books(&old, &new, &songs_dirs, &book.presets, &mut warnings).unwrap();
+    assert_eq!(diff.added.iter().map(|song| song.title.as_str()).collect::<Vec<_>>(), ["Tiger Rag"]);
+    assert!(diff.removed.is_empty());
+    assert_eq!(diff.changed, []);
+    let warnings: Vec<String> = warnings.iter().map(|warning| warning.to_string()).collect();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("v1:broken.ly: "), "{}", warnings[0]);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn it_cites_every_songs_source() {
+    let config = Config { songs_dir: "tests/fixtures/realbook/songs".into(), ..config() };
+    let book = BookBuilder::from_config(config).dry_run(true).build().unwrap();
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+    let citation = "The Real Book, Vol. 1, p. 326 · Recording: Miles Davis, Bags' Groove (1954)";
+    assert!(rendered.contains(&format!("\\fontsize #-2 \"{}\" }}", citation)));
+    assert!(rendered.contains("\\line{ \\abs-fontsize #20 \"Sources\" }"));
+    assert_eq!(rendered.matches(&format!("\\wordwrap-string \"{}\"", citation)).count(), 1);
+    assert_eq!(rendered.matches("\\wordwrap-string").count(), 1);
+}
+
+#[test]
+fn it_links_a_songs_backing_track() {
+    let config = Config { songs_dir: "tests/fixtures/realbook/songs".into(), ..config() };
+    let book = BookBuilder::from_config(config).dry_run(true).build().unwrap();
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+    assert_eq!(rendered.matches("instrument = \\markup \\center-column {").count(), 1);
+    assert_eq!(rendered.matches("%%instrument").count(), 2);
+    assert!(rendered.contains("\\override #'(filled . #t) \\path #0 #'((moveto 0.000 0.000)"));
+    assert!(rendered.contains("\\fontsize #-3 \"Head at 0:42\""));
+}
+
+#[test]
+fn it_lists_every_songs_license() {
+    let realbook = Config { songs_dir: "tests/fixtures/realbook/songs".into(), ..config() };
+    let book = BookBuilder::from_config(realbook).dry_run(true).build().unwrap();
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+    let licenses = &rendered[rendered.find("\"Licenses\"").unwrap()..];
+    let groups: Vec<_> = licenses.lines().filter(|line| line.contains("\\bold")).map(str::trim).collect();
+    assert_eq!(
+        groups,
+        [
+            "\\line { \\bold \"Public Domain\" }",
+            "\\line { \\bold \"All Rights Reserved\" }",
+            "\\line { \\bold \"Not Stated\" }"
+        ]
+    );
+    assert!(licenses.contains("\"1. Misty (public domain in 2051)\""));
+
+    let config = Config { songs_dir: "tests/fixtures/realbook/songs".into(), public_domain_only: true, ..config() };
+    let book = BookBuilder::from_config(config).dry_run(true).build().unwrap();
+    let titles: Vec<_> = book.songs.iter().map(|song| song.title.as_str()).collect();
+    assert_eq!(titles, ["Tiger Rag"]);
+}
+
+#[test]
+fn it_starts_a_song_in_its_own_clef() {
+    let realbook = Config { songs_dir: "tests/fixtures/realbook/songs".into(), ..config() };
+    let book = BookBuilder::from_config(realbook).transpose("bb").dry_run(true).build().unwrap();
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+    assert_eq!(rendered.matches("\\clef bass").count(), 1);
+    let tiger_rag = &rendered[rendered.find("title = \"Tiger Rag\"").unwrap()..];
+    assert!(tiger_rag.contains("\\clef bass"));
+}
+
+#[test]
+fn it_writes_a_static_site() {
+    let book = BookBuilder::from_config(config()).dry_run(true).build().unwrap();
+    let dir = env::temp_dir().join(format!("templater-web-{}", std::process::id()));
+    let pages = write_site(&book, &dir, false).unwrap();
+    assert_eq!(pages.len(), 4);
+
+    let index = fs::read_to_string(&pages[0]).unwrap();
+    assert!(index.contains("<a href=\"songs/misty.html\">Misty</a>"));
+    assert!(index.contains("data-search=\"misty errol garner johnny burke\""));
+    assert_eq!(index.matches("<tr data-search=").count(), 3);
+
+    let misty = fs::read_to_string(dir.join("songs/misty.html")).unwrap();
+    assert!(misty.contains("<dt>Words</dt><dd>Johnny Burke</dd>"));
+    assert!(misty.contains("<a href=\"../index.html\">"));
+    assert!(misty.contains("<a href=\"nardis.html\">Nardis →</a>"));
+    assert!(!misty.contains("<img"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn it_writes_every_song_as_its_own_lead_sheet() {
+    let config = Config { per_song: true, ..config() };
+    let book = BookBuilder::from_config(config).transpose("c,bb").dry_run(true).build().unwrap();
+
+    let renderers = book.renderers();
+    let sheets = renderers.last().unwrap().as_ref();
+    let paths: Vec<_> = book.outputs(sheets).iter().map(|output| book.output_file(sheets, output).unwrap()).collect();
+    assert_eq!(paths.len(), 6);
+    assert_eq!(paths[4], Path::new("./openbook-Bb-songs/002-nardis.ly"));
+
+    let mut output = vec![];
+    book.render_sheet(&mut output, &book.confs[1], 1).unwrap();
+    let sheet = String::from_utf8(output).unwrap();
+
+    assert!(sheet.starts_with("\\version \"2.24.0\""));
+    assert!(sheet.contains("\\include \"../includes/chords.ily\""));
+    assert_eq!(sheet.matches("\\bookpart {").count(), 1);
+    assert!(sheet.contains("\\transpose c d {"));
+    assert!(sheet.trim_end().ends_with('}'));
+    assert!(!sheet.contains("Table of Contents"));
+}
+
+#[test]
+fn it_renders_a_setlist_in_its_own_order() {
+    let builder = BookBuilder::from_config(config()).setlist("tests/fixtures/friday-gig.txt");
+    let book = builder.dry_run(true).build().unwrap();
+    assert_eq!(book.output_path(&book.confs[0], None), Path::new("./openbook-Concert-friday-gig.ly"));
+    let titles: Vec<&str> = book.songs.iter().map(|song| song.title.as_str()).collect();
+    assert_eq!(titles, vec!["Now's The Time", "Misty"]);
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let setlist = String::from_utf8(output).unwrap();
+
+    assert!(setlist.contains("\\bold \"Friday Gig\""));
+    assert!(setlist.contains("\"1. Now's The Time\""));
+    assert!(!setlist.contains("Table of Contents"));
+    assert!(setlist.find("\\label #'song-nows-the-time") < setlist.find("\\label #'song-misty"));
+}
+
+#[test]
+fn it_sorts_the_book_by_composer() {
+    let realbook = Config { songs_dir: "tests/fixtures/realbook/songs".into(), ..config() };
+    let book = BookBuilder::from_config(realbook).sort(SortOrder::Composer).dry_run(true).build().unwrap();
+    let titles: Vec<&str> = book.songs.iter().map(|song| song.title.as_str()).collect();
+    assert_eq!(titles, ["Misty", "Tiger Rag", "Oleo"]);
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+    let headings: Vec<usize> = ["Erroll Garner", "Nick LaRocca", "Sonny Rollins"]
+        .iter()
+        .map(|composer| rendered.find(&format!("\\bold \"{}\" }}", composer)).unwrap())
+        .collect();
+    assert!(headings.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(rendered.find("\\label #'song-tiger-rag") < rendered.find("\\label #'song-oleo"));
+    // the letters are out of order
+    assert!(!rendered.contains("\\abs-fontsize #96"));
+}
+
+#[test]
+fn it_adds_blank_pages_at_the_end() {
+    let builder = BookBuilder::from_config(config()).manuscript_pages(2).chord_grid_pages(1);
+    let book = builder.dry_run(true).build().unwrap();
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+    let manuscript = rendered.find("\\repeat unfold 20 { s1 \\break }").unwrap();
+    let chord_grids = rendered.find("\\repeat unfold 8 { \\repeat unfold 4 { s1 } \\break }").unwrap();
+    assert!(rendered.rfind("\\label #'song-") < Some(manuscript));
+    assert!(manuscript < chord_grids);
+}
+
+#[test]
+fn it_adds_a_recent_additions_page() {
+    let mut book = BookBuilder::from_config(config()).dry_run(true).build().unwrap();
+    book.changes = Some(Changes {
+        since: String::from("v1.0"),
+        added: vec![Path::new("tests/fixtures/songs/nardis.ly").to_path_buf()],
+        modified: vec![Path::new("tests/fixtures/songs/misty.ly").to_path_buf()],
+    });
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+
+    let page = &rendered[rendered.find("\"Recent Additions\"").unwrap()..];
+    assert!(page.contains("\"since v1.0\""));
+    assert!(page.find("Nardis") < page.find("\\bold \"Changed\""));
+    assert!(page.find("\\bold \"Changed\"") < page.find("Misty"));
+    assert!(rendered.find("Table of Contents") < rendered.find("Recent Additions"));
+}
+
+#[test]
+fn it_adds_every_songs_form_to_the_toc() {
+    let mut config = config();
+    config.toc_form = true;
+    let mut book = BookBuilder::from_config(config).dry_run(true).build().unwrap();
+    book.songs[1].form = Some("AABA".parse().unwrap());
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+
+    assert!(rendered.contains("\"1. Misty - Errol Garner (26 bars)\""));
+    assert!(rendered.contains("\"2. Nardis - Miles Davis (AABA)\""));
+}
+
+#[test]
+fn it_writes_only_one_book_to_stdout() {
+    let midi = Config { output: Some("-".into()), midi: true, ..config() };
+    let error = BookBuilder::from_config(midi).dry_run(true).build().err().unwrap();
+    assert_eq!(error.to_string(), "--output - writes one book to stdout, so it can't be used with --midi.");
+
+    let stdout = Config { output: Some("-".into()), ..config() };
+    let error = BookBuilder::from_config(stdout).transpose("c,bb").dry_run(true).build().err().unwrap();
+    assert!(error.to_string().contains("can't be used with more than one transposition"));
+}
+
+#[test]
+fn it_labels_songs_without_latin_letters() {
+    let dir = env::temp_dir().join(format!("templater-labels-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let misty = fs::read_to_string("tests/fixtures/songs/misty.ly").unwrap();
+    let write = |file: &str, title: &str| fs::write(dir.join(file), misty.replace("title: Misty", title)).unwrap();
+    write("katyusha.ly", "title: Катюша");
+    write("kalinka.ly", "title: Калинка");
+
+    let book = BookBuilder::from_config(Config { songs_dir: dir.clone(), ..config() }).dry_run(true).build().unwrap();
+    let labels: Vec<String> = book.songs.iter().map(|song| song.label()).collect();
+    assert_eq!(labels, ["song-u43au430u43bu438u43du43au430", "song-u43au430u442u44eu448u430"]);
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let rendered = String::from_utf8(output).unwrap();
+    for label in &labels {
+        assert_eq!(rendered.matches(&format!("\\label #'{}", label)).count(), 1);
+    }
+    assert!(!rendered.contains("#'song- "));
+
+    // not the same title, but the same label
+    write("kalinka.ly", "title: Café");
+    write("katyusha.ly", "title: Cafe");
+    let book = BookBuilder::from_config(Config { songs_dir: dir.clone(), ..config() }).dry_run(true).build();
+    let error = book.err().unwrap();
+    assert!(error.to_string().contains("'Cafe' would have the same label as 'Café'"), "{}", error);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn it_names_the_pages_of_songs_without_latin_letters() {
+    let dir = env::temp_dir().join(format!("templater-web-pages-{}", std::process::id()));
+    let songs = dir.join("songs-dir");
+    fs::create_dir_all(&songs).unwrap();
+    let misty = fs::read_to_string("tests/fixtures/songs/misty.ly").unwrap();
+    fs::write(songs.join("katyusha.ly"), misty.replace("title: Misty", "title: Катюша")).unwrap();
+    fs::write(songs.join("kalinka.ly"), misty.replace("title: Misty", "title: Калинка")).unwrap();
+
+    let book = BookBuilder::from_config(Config { songs_dir: songs, ..config() }).dry_run(true).build().unwrap();
+    let site = dir.join("site");
+    let pages = write_site(&book, &site, false).unwrap();
+    assert_eq!(pages.len(), 3);
+    assert!(site.join("songs/катюша.html").is_file());
+    assert!(site.join("songs/калинка.html").is_file());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn it_fails_a_strict_build_on_warnings() {
+    // none of the fixtures set a bpm
+    let book = BookBuilder::from_config(config()).dry_run(true).build().unwrap();
+    assert_eq!(book.warnings.iter().count(), 3);
+
+    let config = Config { strict: true, ..config() };
+    let error = BookBuilder::from_config(config).dry_run(true).build().err().unwrap();
+    assert!(error.report().contains("[Error]: tests/fixtures/songs/misty.ly: missing bpm (--strict)"));
+}
+
+#[test]
+fn it_only_checks_the_range_of_songs_in_the_book() {
+    let ranges = BTreeMap::from([(String::from("c"), "c' d'".parse().unwrap())]);
+    let out_of_range = |builder: BookBuilder| {
+        let book = builder.transpose("c").dry_run(true).build().unwrap();
+        let warnings = book.warnings.iter().filter(|warning| warning.kind == WarningKind::Range);
+        let mut paths: Vec<PathBuf> = warnings.filter_map(|warning| warning.path.clone()).collect();
+        paths.dedup();
+        paths
+    };
+
+    let misty = PathBuf::from("tests/fixtures/songs/misty.ly");
+    let all = out_of_range(BookBuilder::from_config(Config { ranges: ranges.clone(), ..config() }));
+    assert_eq!(all, [misty.clone(), PathBuf::from("tests/fixtures/songs/nows_the_time.ly")]);
+    let book = BookBuilder::from_config(Config { ranges, ..config() }).song_names(vec![String::from("Misty")]);
+    assert_eq!(out_of_range(book), [misty]);
+
+    // nor any other warnings of the songs that are left out
+    let dir = env::temp_dir().join(format!("templater-left-out-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::copy("tests/fixtures/songs/misty.ly", dir.join("misty.ly")).unwrap();
+    let nardis = fs::read_to_string("tests/fixtures/songs/nardis.ly").unwrap();
+    fs::write(dir.join("nardis.ly"), format!("tempo_marking: Fast\n{}", nardis)).unwrap();
+    let config = || Config { songs_dir: dir.clone(), ..config() };
+    let kinds = |book: Book| {
+        book.warnings.iter().map(|warning| (warning.kind, warning.path.clone())).collect::<Vec<_>>()
+    };
+
+    let all = kinds(BookBuilder::from_config(config()).dry_run(true).build().unwrap());
+    assert!(all.contains(&(WarningKind::UnknownKey, Some(dir.join("nardis.ly")))));
+    assert!(all.contains(&(WarningKind::MissingBpm, Some(dir.join("nardis.ly")))));
+    let book = BookBuilder::from_config(config()).song_names(vec![String::from("Misty")]).dry_run(true);
+    assert_eq!(kinds(book.build().unwrap()), [(WarningKind::MissingBpm, Some(dir.join("misty.ly")))]);
+    let strict = BookBuilder::from_config(Config { strict: true, ..config() });
+    let error = strict.song_names(vec![String::from("Nardis")]).dry_run(true).build().err().unwrap();
+    assert!(!error.report().contains("misty.ly"), "{}", error.report());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn it_warns_about_a_backing_track_too_long_for_a_qr_code() {
+    let dir = env::temp_dir().join(format!("templater-backing-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let misty = fs::read_to_string("tests/fixtures/songs/misty.ly").unwrap();
+    let url = format!("https://example.com/{}.mp3", "misty".repeat(40));
+    fs::write(dir.join("misty.ly"), format!("backing_track:\n  url: {}\n{}", url, misty)).unwrap();
+
+    let book = BookBuilder::from_config(Config { songs_dir: dir.clone(), ..config() }).dry_run(true).build().unwrap();
+    assert_eq!(book.warnings.iter().filter(|warning| warning.kind == WarningKind::BackingTrack).count(), 1);
+
+    let config = Config { songs_dir: dir.clone(), strict: true, ..config() };
+    let error = BookBuilder::from_config(config).dry_run(true).build().err().unwrap();
+    let report = error.report();
+    assert!(report.contains("misty.ly: backing_track has no qr code: 224 bytes is too long"), "{}", report);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn it_fills_in_the_title_page() {
+    let metadata = BookMetadata {
+        title: String::from("The \"Gig\" Book"),
+        editor: Some(String::from("Jane Doe")),
+        copyright: Some(String::from("Arrangements (c) 2024 Jane Doe")),
+        ..Default::default()
+    };
+    let book = BookBuilder::from_config(config()).metadata(metadata).dry_run(true).build().unwrap();
+
+    let mut output = vec![];
+    book.render(&mut output, &book.confs[0]).unwrap();
+    let intro = String::from_utf8(output).unwrap();
+
+    assert!(intro.contains("\\bold \"The \\\"Gig\\\" Book\""));
+    assert!(intro.contains("\"An open-source, libre songbook.\""));
+    assert!(intro.contains("\"Edited by Jane Doe\""));
+    assert!(intro.contains("\\line{ \"Arrangements (c) 2024 Jane Doe\" }"));
+    assert!(!intro.contains("To-do: Help is needed"));
+}
+
+#[test]
+fn it_adds_layout_hints_to_the_bookpart() {
+    let book = BookBuilder::from_config(config()).dry_run(true).build().unwrap();
+    let plain = book.songs[0].render(&book.templates, &book.confs[0]).unwrap();
+    assert!(!plain.contains("\\paper"));
+
+    let mut song = book.songs[0].clone();
+    song.layout = LayoutHints { pages: Some(2), break_before: true, ragged: true, ..Default::default() };
+    let bookpart = song.render(&book.templates, &book.confs[0]).unwrap();
+
+    assert!(bookpart.starts_with("\\bookpart {\n  \\markup \\null\n}\n\\bookpart {\n"));
+    assert!(bookpart.contains("  \\paper {\n    page-count = 2\n    ragged-bottom = ##t\n"));
+    assert!(!bookpart.contains("system-system-spacing"));
+    assert_eq!(bookpart.matches("\\bookpart").count(), 2);
+}
+
+#[test]
+fn it_adds_a_chord_staff_for_every_variant() {
+    let book = BookBuilder::from_config(config()).transpose("bb").dry_run(true).build().unwrap();
+    let mut song = book.songs[0].clone();
+    song.variants = vec![ChordVariant { name: String::from("Coltrane changes"), chords: String::from("c1:maj7") }];
+    let bookpart = song.render(&book.templates, &book.confs[0]).unwrap();
+
+    let variant = bookpart.find("\\new ChordNames=\"Coltrane changes\"").unwrap();
+    assert!(variant < bookpart.find("\\new ChordNames=\"Chords\"").unwrap());
+    assert!(bookpart.contains("instrumentName = \\markup \\small \"Coltrane changes\""));
+    assert_eq!(bookpart.matches("\\transpose c d {").count(), 2 + song.voices.len());
+}
+
+#[test]
+fn it_leaves_out_drafts() {
+    let book = BookBuilder::from_config(config()).dry_run(true).build().unwrap();
+    assert_eq!(book.songs.len(), 3);
+
+    let book = BookBuilder::from_config(config()).include_drafts(true).dry_run(true).build().unwrap();
+    assert!(book.songs.iter().any(|song| song.title == "Work in Progress" && song.draft));
+
+    let report = find_drafts("tests/fixtures/songs", &mut Warnings::default()).unwrap();
+    assert_eq!(report.drafts.iter().map(|draft| draft.title.as_str()).collect::<Vec<_>>(), vec!["Work in Progress"]);
+    assert!(report.to_string().ends_with("1 of 4 songs are drafts"));
+}
+
+#[test]
+fn it_diffs_a_book_against_its_songs() {
+    let side = Side::Dirs(vec!["tests/fixtures/songs".into()]);
+    let songs = side.songs(&[Path::new(".")], &Presets::default(), &mut Warnings::default()).unwrap();
+    assert!(diff_songs(&book_songs(&render("bb", true)), &songs).is_empty());
+
+    let mut book = book_songs(&render("c", false));
+    book[0].melody = book[0].melody.replacen("bes8 c", "bes8 d", 1);
+    book.remove(1);
+    let diff = diff_songs(&book, &songs);
+    assert_eq!(diff.changed, [(String::from("Misty"), vec!["melody"])]);
+    assert_eq!(diff.added.iter().map(|song| song.title.as_str()).collect::<Vec<_>>(), ["Nardis"]);
+}
+
+#[test]
+fn it_keeps_every_books_presets() {
+    let dir = env::temp_dir().join(format!("templater-presets-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let book = |transpose: &str| {
+        let transpositions = dir.join(format!("{}.toml", transpose.replace(' ', "-")));
+        let preset = format!("[alto-flute]\nname = \"Alto Flute\"\ntranspose = \"{}\"\n", transpose);
+        fs::write(&transpositions, preset).unwrap();
+        let config = Config { transpositions, ..config() };
+        BookBuilder::from_config(config).transpose("alto-flute").dry_run(true).build().unwrap()
+    };
+
+    // built one after the other, each with its own file
+    let (g, f) = (book("c f"), book("c g"));
+    assert_eq!(g.confs[0].transpose_text.lilypond_text, "c f");
+    assert_eq!(f.confs[0].transpose_text.lilypond_text, "c g");
+    assert_eq!(f.presets.transpose_text("alto-flute").unwrap().lilypond_text, "c g");
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,50 @@
+// a `BookSession` keeps its own templates, so replacing one doesn't
+// change any other book's, see `it_keeps_its_own_templates`.
+
+use openbook_templater::{BookBuilder, BookSession, Config};
+
+fn session() -> BookSession {
+    let config = Config {
+        songs_dir: "tests/fixtures/songs".into(),
+        templates_dir: "tests/fixtures/templates".into(),
+        song_ids: "tests/fixtures/songids.toml".into(),
+        composer_aliases: "tests/fixtures/composers.toml".into(),
+        cache: false,
+        ..Default::default()
+    };
+
+    BookSession::new(BookBuilder::from_config(config).transpose("c,bb").dry_run(true)).unwrap()
+}
+
+#[test]
+fn it_renders_again_with_a_new_template() {
+    let mut session = session();
+    let misty = session.find_song("misty").unwrap();
+    assert!(session.render_song(misty, 1).unwrap().contains("title = \"Misty\""));
+
+    let header = session.template("song-header").unwrap();
+    let header = header.replace("title = \"{{ title }}\"", "title = \"{{ title|upper }}\"");
+    session.set_template("song-header", &header).unwrap();
+    assert_eq!(session.template("song-header").unwrap(), header);
+    assert!(session.render_song(misty, 1).unwrap().contains("title = \"MISTY\""));
+    assert!(session.render_book(0).unwrap().contains("title = \"MISTY\""));
+
+    // a broken template keeps the one before it
+    let err = session.set_template("song-header", "{{ titel }}").unwrap_err().to_string();
+    assert!(err.contains("titel"), "{}", err);
+    assert!(session.set_template("song-footer", "").is_err());
+    assert!(session.render_song(misty, 1).unwrap().contains("title = \"MISTY\""));
+    assert!(session.render_song(misty, 2).is_err());
+}
+
+#[test]
+fn it_keeps_its_own_templates() {
+    let (mut edited, other) = (session(), session());
+    let misty = edited.find_song("misty").unwrap();
+
+    let header = edited.template("song-header").unwrap();
+    edited.set_template("song-header", &header.replace("{{ title }}", "{{ title|upper }}")).unwrap();
+    assert!(edited.render_song(misty, 1).unwrap().contains("title = \"MISTY\""));
+    assert_eq!(other.template("song-header").unwrap(), header);
+    assert!(other.render_song(misty, 1).unwrap().contains("title = \"Misty\""));
+}